@@ -0,0 +1,363 @@
+//! Python bindings for PiQL
+//!
+//! Exposes `piql.run(query, {name: df})` for one-off queries and `piql.QueryEngine`
+//! for tick-based workflows. DataFrames cross the boundary as Arrow IPC: Python
+//! polars writes them with `write_ipc` and reads results back with `read_ipc`, so
+//! the Python and Rust polars versions don't have to match.
+//!
+//! Built with maturin (see `pyproject.toml`); queries run with the GIL released.
+
+use std::io::Cursor;
+
+use piql::{
+    EmitMode, EvalContext, ParamValue, Params, RemoveMode, Retention, TickDtype, TimeSeriesConfig,
+};
+use polars::prelude::*;
+use pyo3::exceptions::{PyException, PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBool, PyBytes, PyDict, PyFloat, PyInt, PyList, PyString};
+
+pyo3::create_exception!(
+    piql,
+    PiqlError,
+    PyException,
+    "A query failed to parse or evaluate."
+);
+
+fn piql_err(e: impl std::fmt::Display) -> PyErr {
+    PiqlError::new_err(e.to_string())
+}
+
+/// Python polars DataFrame (or LazyFrame, collected first) → Rust DataFrame
+fn from_py(obj: &Bound<'_, PyAny>) -> PyResult<DataFrame> {
+    let py = obj.py();
+    let polars = py.import("polars")?;
+    let df = if obj.is_instance(&polars.getattr("LazyFrame")?)? {
+        obj.call_method0("collect")?
+    } else if obj.is_instance(&polars.getattr("DataFrame")?)? {
+        obj.clone()
+    } else {
+        return Err(PyTypeError::new_err(format!(
+            "expected a polars DataFrame or LazyFrame, got {}",
+            obj.get_type().name()?
+        )));
+    };
+    // The oldest format is readable by any Rust polars version
+    let kwargs = PyDict::new(py);
+    kwargs.set_item(
+        "compat_level",
+        polars.getattr("CompatLevel")?.call_method0("oldest")?,
+    )?;
+    let ipc: Vec<u8> = df
+        .call_method("write_ipc", (py.None(),), Some(&kwargs))?
+        .call_method0("getvalue")?
+        .extract()?;
+    py.detach(|| IpcReader::new(Cursor::new(ipc)).finish())
+        .map_err(piql_err)
+}
+
+/// Rust DataFrame → Python polars DataFrame
+fn to_py(py: Python<'_>, mut df: DataFrame) -> PyResult<Bound<'_, PyAny>> {
+    let ipc = py
+        .detach(move || {
+            let mut buf = Vec::new();
+            IpcWriter::new(&mut buf).finish(&mut df)?;
+            PolarsResult::Ok(buf)
+        })
+        .map_err(piql_err)?;
+    let reader = py
+        .import("io")?
+        .call_method1("BytesIO", (PyBytes::new(py, &ipc),))?;
+    py.import("polars")?.call_method1("read_ipc", (reader,))
+}
+
+/// Collect a query result, which must be a DataFrame
+fn collect(value: piql::Value) -> Result<DataFrame, String> {
+    match value {
+        piql::Value::DataFrame(lf, _) => lf.collect().map_err(|e| e.to_string()),
+        piql::Value::GroupBy(..) => Err("query returned a group_by without .agg(...)".into()),
+        _ => Err("query did not return a DataFrame".into()),
+    }
+}
+
+fn param_value(obj: &Bound<'_, PyAny>) -> PyResult<ParamValue> {
+    // `bool` is a subclass of `int`, so check it first
+    if obj.is_none() {
+        Ok(ParamValue::Null)
+    } else if obj.is_instance_of::<PyBool>() {
+        Ok(ParamValue::Bool(obj.extract()?))
+    } else if obj.is_instance_of::<PyInt>() {
+        Ok(ParamValue::Int(obj.extract()?))
+    } else if obj.is_instance_of::<PyFloat>() {
+        Ok(ParamValue::Float(obj.extract()?))
+    } else if obj.is_instance_of::<PyString>() {
+        Ok(ParamValue::String(obj.extract()?))
+    } else if let Ok(list) = obj.downcast::<PyList>() {
+        list.iter()
+            .map(|item| param_value(&item))
+            .collect::<PyResult<_>>()
+            .map(ParamValue::List)
+    } else {
+        Err(PyTypeError::new_err(format!(
+            "unsupported parameter type {} (expected str, int, float, bool, None or list)",
+            obj.get_type().name()?
+        )))
+    }
+}
+
+fn params(params: Option<&Bound<'_, PyDict>>) -> PyResult<Params> {
+    let Some(params) = params else {
+        return Ok(Params::new());
+    };
+    params
+        .iter()
+        .map(|(name, value)| -> PyResult<(String, ParamValue)> {
+            Ok((name.extract()?, param_value(&value)?))
+        })
+        .collect()
+}
+
+fn tick_dtype(name: &str) -> PyResult<TickDtype> {
+    match name {
+        "int" => Ok(TickDtype::Int),
+        "datetime" => Ok(TickDtype::Datetime),
+        other => Err(PyValueError::new_err(format!(
+            "unknown tick_dtype '{other}' (expected 'int' or 'datetime')"
+        ))),
+    }
+}
+
+fn time_series(
+    tick_column: String,
+    partition_key: String,
+    dtype: &str,
+) -> PyResult<TimeSeriesConfig> {
+    Ok(TimeSeriesConfig::new(tick_column, partition_key).with_tick_dtype(tick_dtype(dtype)?))
+}
+
+/// Run a one-off query against `tables` (name → polars DataFrame or LazyFrame)
+///
+/// `:name` placeholders in the query are bound from `params`.
+#[pyfunction]
+#[pyo3(signature = (query, tables, params = None))]
+fn run<'py>(
+    py: Python<'py>,
+    query: &str,
+    tables: &Bound<'py, PyDict>,
+    params: Option<&Bound<'py, PyDict>>,
+) -> PyResult<Bound<'py, PyAny>> {
+    let mut ctx = EvalContext::new();
+    for (name, df) in tables.iter() {
+        ctx = ctx.with_df(name.extract::<String>()?, from_py(&df)?.lazy());
+    }
+    let params = self::params(params)?;
+    let df = py
+        .detach(|| {
+            let value = piql::run_with_params(query, &params, &ctx).map_err(|e| e.to_string())?;
+            collect(value)
+        })
+        .map_err(PiqlError::new_err)?;
+    to_py(py, df)
+}
+
+/// Tick-based query engine: base tables grow each tick, and materialized tables
+/// and subscriptions are re-evaluated by `on_tick`
+#[pyclass(name = "QueryEngine", module = "piql")]
+struct PyQueryEngine {
+    inner: piql::QueryEngine,
+}
+
+#[pymethods]
+impl PyQueryEngine {
+    #[new]
+    fn new() -> Self {
+        Self {
+            inner: piql::QueryEngine::new(),
+        }
+    }
+
+    /// Register a plain table
+    fn add_df(&mut self, name: String, df: &Bound<'_, PyAny>) -> PyResult<()> {
+        self.inner.add_base_df(name, from_py(df)?.lazy());
+        Ok(())
+    }
+
+    /// Register a table holding every tick, for scope methods like `.window()`
+    #[pyo3(signature = (name, df, tick_column, partition_key, tick_dtype = "int"))]
+    fn add_time_series_df(
+        &mut self,
+        name: String,
+        df: &Bound<'_, PyAny>,
+        tick_column: String,
+        partition_key: String,
+        tick_dtype: &str,
+    ) -> PyResult<()> {
+        let config = time_series(tick_column, partition_key, tick_dtype)?;
+        self.inner
+            .add_time_series_df(name, from_py(df)?.lazy(), config);
+        Ok(())
+    }
+
+    /// Register a base table that grows with `append_tick`
+    ///
+    /// Queries see the latest tick by default and the full history with `.all()`.
+    /// With `keep_last_ticks`, older ticks are dropped as new ones arrive.
+    #[pyo3(signature = (name, tick_column, partition_key, tick_dtype = "int", keep_last_ticks = None))]
+    fn register_base(
+        &mut self,
+        name: String,
+        tick_column: String,
+        partition_key: String,
+        tick_dtype: &str,
+        keep_last_ticks: Option<i64>,
+    ) -> PyResult<()> {
+        let config = time_series(tick_column, partition_key, tick_dtype)?;
+        let retention = keep_last_ticks.map_or(Retention::KeepAll, Retention::LastTicks);
+        self.inner
+            .register_base_with_retention(name, config, retention);
+        Ok(())
+    }
+
+    /// Append the rows of a new tick to a base table
+    fn append_tick(&mut self, py: Python<'_>, name: &str, rows: &Bound<'_, PyAny>) -> PyResult<()> {
+        let rows = from_py(rows)?.lazy();
+        py.detach(|| self.inner.append_tick(name, rows))
+            .map_err(piql_err)
+    }
+
+    /// Replace the data of a registered table
+    fn update_df(&mut self, name: &str, df: &Bound<'_, PyAny>) -> PyResult<()> {
+        self.inner.update_df(name, from_py(df)?.lazy());
+        Ok(())
+    }
+
+    /// Unregister a table, returning the materialized tables and subscriptions
+    /// removed with it; fails if anything reads it unless `cascade` is set
+    #[pyo3(signature = (name, cascade = false))]
+    fn remove_df(&mut self, name: &str, cascade: bool) -> PyResult<Vec<String>> {
+        let mode = if cascade {
+            RemoveMode::Cascade
+        } else {
+            RemoveMode::Fail
+        };
+        self.inner.remove_df(name, mode).map_err(piql_err)
+    }
+
+    /// Rename a table; fails if a materialized table or subscription reads it
+    fn rename_df(&mut self, old: &str, new: String) -> PyResult<()> {
+        self.inner.rename_df(old, new).map_err(piql_err)
+    }
+
+    /// Save a query snippet referenced as `!name` in later queries
+    fn define_alias(&mut self, name: String, query: String) -> PyResult<()> {
+        self.inner.define_alias(name, query).map_err(piql_err)
+    }
+
+    /// Store the result of `query` as table `name`, re-evaluated on every tick
+    fn materialize(&mut self, py: Python<'_>, name: String, query: String) -> PyResult<()> {
+        py.detach(|| self.inner.materialize(name, query))
+            .map_err(piql_err)
+    }
+
+    /// Evaluate `query` on every tick; `emit` is `always`, `on_change` or `diff`
+    #[pyo3(signature = (name, query, emit = "always"))]
+    fn subscribe(&mut self, name: String, query: String, emit: &str) -> PyResult<()> {
+        let mode = match emit {
+            "always" => EmitMode::Always,
+            "on_change" => EmitMode::OnChange,
+            "diff" => EmitMode::Diff,
+            other => {
+                return Err(PyValueError::new_err(format!(
+                    "unknown emit mode '{other}' (expected 'always', 'on_change' or 'diff')"
+                )));
+            }
+        };
+        self.inner.subscribe_with_mode(name, query, mode);
+        Ok(())
+    }
+
+    fn unsubscribe(&mut self, name: &str) {
+        self.inner.unsubscribe(name);
+    }
+
+    /// Evaluate subscriptions reading only the latest tick against the appended rows
+    fn set_incremental(&mut self, enabled: bool) {
+        self.inner.set_incremental(enabled);
+    }
+
+    /// Most threads used to evaluate subscriptions in `on_tick`
+    fn set_parallelism(&mut self, threads: usize) {
+        self.inner.set_parallelism(threads);
+    }
+
+    /// Advance to `tick`, returning subscription results by name
+    fn on_tick<'py>(&mut self, py: Python<'py>, tick: i64) -> PyResult<Bound<'py, PyDict>> {
+        let results = py.detach(|| self.inner.on_tick(tick)).map_err(piql_err)?;
+        let dict = PyDict::new(py);
+        for (name, df) in results {
+            dict.set_item(name, to_py(py, df)?)?;
+        }
+        Ok(dict)
+    }
+
+    /// Run a one-off query against the current tables and tick
+    #[pyo3(signature = (query, params = None))]
+    fn query<'py>(
+        &self,
+        py: Python<'py>,
+        query: &str,
+        params: Option<&Bound<'py, PyDict>>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let params = self::params(params)?;
+        let df = py
+            .detach(|| {
+                let prepared = self.inner.prepare(query).map_err(|e| e.to_string())?;
+                let value = self
+                    .inner
+                    .run_prepared(&prepared, &params)
+                    .map_err(|e| e.to_string())?;
+                collect(value)
+            })
+            .map_err(PiqlError::new_err)?;
+        to_py(py, df)
+    }
+
+    /// Current tick (`None` before the first `on_tick`)
+    #[getter]
+    fn tick(&self) -> Option<i64> {
+        self.inner.tick()
+    }
+
+    /// Set the tick seen by `query` outside of `on_tick`
+    fn set_tick(&mut self, tick: i64) {
+        self.inner.set_tick(tick);
+    }
+
+    /// Names of all registered tables, sorted
+    fn dataframe_names(&self) -> Vec<String> {
+        let mut names = self.inner.dataframe_names();
+        names.sort();
+        names
+    }
+
+    /// Persist tables, aliases, materializations and subscriptions to a directory
+    fn save_snapshot(&self, py: Python<'_>, path: std::path::PathBuf) -> PyResult<()> {
+        py.detach(|| self.inner.save_snapshot(path))
+            .map_err(piql_err)
+    }
+
+    /// Restore state written by `save_snapshot`
+    fn load_snapshot(&mut self, py: Python<'_>, path: std::path::PathBuf) -> PyResult<()> {
+        py.detach(|| self.inner.load_snapshot(path))
+            .map_err(piql_err)
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "piql")]
+fn piql_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(run, m)?)?;
+    m.add_class::<PyQueryEngine>()?;
+    m.add("PiqlError", m.py().get_type::<PiqlError>())?;
+    Ok(())
+}
@@ -0,0 +1,47 @@
+import polars as pl
+import pytest
+
+import piql
+
+
+def entities() -> pl.DataFrame:
+    return pl.DataFrame({"name": ["a", "b", "c"], "gold": [50, 500, 5000]})
+
+
+def test_run_returns_polars_dataframe():
+    result = piql.run("entities.filter($gold > 100)", {"entities": entities()})
+    assert isinstance(result, pl.DataFrame)
+    assert result["name"].to_list() == ["b", "c"]
+
+
+def test_run_accepts_lazy_frames_and_params():
+    result = piql.run(
+        "entities.filter($gold > :min)",
+        {"entities": entities().lazy()},
+        params={"min": 1000},
+    )
+    assert result["name"].to_list() == ["c"]
+
+
+def test_errors_raise_piql_error():
+    with pytest.raises(piql.PiqlError):
+        piql.run("entities.filter(", {"entities": entities()})
+    with pytest.raises(TypeError):
+        piql.run("t", {"t": [1, 2, 3]})
+
+
+def test_query_engine_ticks():
+    engine = piql.QueryEngine()
+    engine.register_base("entities", tick_column="tick", partition_key="name")
+    engine.subscribe("rich", 'entities.filter($gold > 100).sort("name")')
+
+    for tick in (1, 2):
+        rows = entities().with_columns(
+            pl.lit(tick, dtype=pl.Int64).alias("tick"), pl.col("gold") * tick
+        )
+        engine.append_tick("entities", rows)
+        results = engine.on_tick(tick)
+
+    assert engine.tick == 2
+    assert results["rich"]["gold"].to_list() == [1000, 10000]
+    assert engine.query("entities.all()").height == 6
@@ -0,0 +1,539 @@
+//! Webhook alerts on query results
+//!
+//! An alert re-evaluates its query whenever a table changes, like an SSE
+//! subscription, and checks a condition over the result: a PiQL expression such as
+//! `pl.len() > 0` or `$gold.max() > 1e6`, which holds if any of its values is true.
+//! When it holds, a JSON [`AlertPayload`] is POSTed to the alert's webhook, retried
+//! with exponential backoff on failure, and the alert stays muted for its mute
+//! window.
+//!
+//! This module is feature-gated behind the `webhooks` feature. Alerts come from a
+//! JSON file (`--alerts`) or `POST /alerts`.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::updates::RecvError;
+use axum::Json;
+use axum::extract::{Path as UrlPath, State};
+use axum::http::StatusCode;
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use piql::{EvalContext, Value};
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::task::JoinHandle;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::annotate::Annotations;
+use crate::core::ServerCore;
+use crate::error::AppError;
+use crate::json::dataframe_to_json_rows;
+use crate::schedule::{timestamp, valid_name};
+use crate::state::{ErrorResponse, QueryRequest};
+
+/// OpenAPI documentation for alert endpoints
+#[derive(OpenApi)]
+#[openapi(
+    paths(list, create, delete),
+    components(schemas(AlertSpec, AlertInfo, AlertsResponse, AlertPayload))
+)]
+pub struct AlertApiDoc;
+
+/// Result rows included in a webhook payload
+pub const PREVIEW_ROWS: usize = 10;
+/// Delay before the first retry of a failed delivery; doubles on every retry
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+/// Longest delay between retries
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Most retries an alert may ask for
+pub const MAX_RETRIES: u32 = 10;
+/// Time allowed for one webhook request
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A condition on a query's result and the webhook notified when it holds
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AlertSpec {
+    /// Letters, digits, `_` and `-`
+    pub name: String,
+    #[serde(flatten)]
+    pub query: QueryRequest,
+    /// PiQL expression over the result's columns, e.g. `pl.len() > 0`
+    pub condition: String,
+    /// `http://` or `https://` URL receiving the payload as a JSON POST
+    pub webhook: String,
+    /// Seconds after a notification during which the alert doesn't fire again
+    #[serde(default = "default_mute_secs")]
+    pub mute_secs: u64,
+    /// Attempts after a failed delivery before giving up (at most [`MAX_RETRIES`])
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+}
+
+fn default_mute_secs() -> u64 {
+    300
+}
+
+fn default_retries() -> u32 {
+    3
+}
+
+/// JSON body POSTed to the webhook
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct AlertPayload {
+    pub alert: String,
+    pub query: String,
+    pub condition: String,
+    /// RFC 3339, UTC
+    pub fired_at: String,
+    pub rows: usize,
+    pub columns: Vec<String>,
+    /// First [`PREVIEW_ROWS`] rows of the result
+    #[schema(value_type = Vec<Object>)]
+    pub preview: Vec<serde_json::Map<String, serde_json::Value>>,
+}
+
+/// A registered alert and its latest notification
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct AlertInfo {
+    #[serde(flatten)]
+    pub spec: AlertSpec,
+    /// Last time the condition held outside the mute window, RFC 3339 UTC
+    pub last_fired: Option<String>,
+    /// Why the last evaluation or delivery failed
+    pub last_error: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AlertsResponse {
+    pub alerts: Vec<AlertInfo>,
+}
+
+#[derive(Debug, Error)]
+pub enum AlertError {
+    #[error("invalid alert name '{0}' (use letters, digits, '_' and '-')")]
+    InvalidName(String),
+    #[error("invalid webhook URL '{0}' (must start with http:// or https://)")]
+    InvalidWebhook(String),
+    #[error("{0}")]
+    InvalidParams(String),
+    #[error("retries must be at most {MAX_RETRIES}, got {0}")]
+    TooManyRetries(u32),
+    #[error("invalid condition '{condition}': {message}")]
+    InvalidCondition { condition: String, message: String },
+    #[error("Unknown alert: {0}")]
+    Unknown(String),
+    #[error(transparent)]
+    Query(#[from] piql::PiqlError),
+}
+
+#[derive(Default)]
+struct Status {
+    last_fired: Option<DateTime<Utc>>,
+    last_error: Option<String>,
+}
+
+struct Registered {
+    spec: AlertSpec,
+    status: Status,
+    task: JoinHandle<()>,
+}
+
+/// Registered alerts and the client delivering their webhooks
+#[derive(Default)]
+pub struct Alerts {
+    alerts: Mutex<BTreeMap<String, Registered>>,
+    client: reqwest::Client,
+}
+
+impl Alerts {
+    /// Registered alerts by name
+    pub fn list(&self) -> Vec<AlertInfo> {
+        let alerts = self.alerts.lock().unwrap();
+        alerts
+            .values()
+            .map(|registered| AlertInfo {
+                spec: registered.spec.clone(),
+                last_fired: registered.status.last_fired.as_ref().map(timestamp),
+                last_error: registered.status.last_error.clone(),
+            })
+            .collect()
+    }
+
+    /// Stop and unregister `name`; returns whether it existed
+    pub fn remove(&self, name: &str) -> bool {
+        match self.alerts.lock().unwrap().remove(name) {
+            Some(registered) => {
+                registered.task.abort();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn update(&self, name: &str, f: impl FnOnce(&mut Status)) {
+        if let Some(registered) = self.alerts.lock().unwrap().get_mut(name) {
+            f(&mut registered.status);
+        }
+    }
+}
+
+/// Validate and register `spec`, replacing any alert of the same name, and start
+/// watching it. Must be called within a tokio runtime.
+pub async fn add(core: &ServerCore, spec: AlertSpec) -> Result<AlertInfo, AlertError> {
+    if !valid_name(&spec.name) {
+        return Err(AlertError::InvalidName(spec.name));
+    }
+    if !(spec.webhook.starts_with("http://") || spec.webhook.starts_with("https://")) {
+        return Err(AlertError::InvalidWebhook(spec.webhook));
+    }
+    if spec.retries > MAX_RETRIES {
+        return Err(AlertError::TooManyRetries(spec.retries));
+    }
+    // Evaluate once so a bad query or condition is reported now, not on every update
+    let params = spec
+        .query
+        .piql_params()
+        .map_err(AlertError::InvalidParams)?;
+    let (df, _) = core
+        .execute_query_with_params(&spec.query.query, &params, Annotations::default())
+        .await?;
+    condition_met(&spec.condition, df).map_err(|message| AlertError::InvalidCondition {
+        condition: spec.condition.clone(),
+        message,
+    })?;
+
+    let name = spec.name.clone();
+    let replaced = {
+        let mut alerts = core.alerts().alerts.lock().unwrap();
+        let task = tokio::spawn(watch(core.clone(), spec.clone()));
+        alerts.insert(
+            name.clone(),
+            Registered {
+                spec: spec.clone(),
+                status: Status::default(),
+                task,
+            },
+        )
+    };
+    if let Some(replaced) = replaced {
+        replaced.task.abort();
+    }
+    info!(
+        "Alert '{name}' on {}: {} -> {}",
+        spec.query.query, spec.condition, spec.webhook
+    );
+
+    let info = core.alerts().list();
+    Ok(info.into_iter().find(|i| i.spec.name == name).unwrap())
+}
+
+/// Whether any value of `condition`, evaluated over `df`, is true
+pub fn condition_met(condition: &str, df: DataFrame) -> Result<bool, String> {
+    let ctx = EvalContext::new().with_materialized_df("result", df);
+    let query = format!("result.select(({condition}).alias(\"_condition\"))");
+    let lf = match piql::run(&query, &ctx).map_err(|e| e.to_string())? {
+        Value::DataFrame(lf, _) => lf,
+        _ => return Err("expected an expression".to_string()),
+    };
+    let out = lf.collect().map_err(|e| e.to_string())?;
+    let values = out
+        .column("_condition")
+        .and_then(|c| c.bool())
+        .map_err(|_| "the condition must be a boolean expression".to_string())?;
+    Ok(values.any())
+}
+
+/// Re-evaluate the alert on every table update until it is removed or the server
+/// shuts down, notifying the webhook when the condition holds outside the mute window
+async fn watch(core: ServerCore, spec: AlertSpec) {
+    let shutdown = core.shutdown().clone();
+    let mut updates = core.subscribe_updates();
+    let mute = i64::try_from(spec.mute_secs)
+        .ok()
+        .and_then(chrono::TimeDelta::try_seconds)
+        .unwrap_or(chrono::TimeDelta::MAX);
+    let mut muted_until: Option<DateTime<Utc>> = None;
+    loop {
+        let now = Utc::now();
+        if muted_until.is_none_or(|until| now >= until) {
+            match evaluate(&core, &spec).await {
+                Ok(Some(df)) => {
+                    muted_until = Some(
+                        now.checked_add_signed(mute)
+                            .unwrap_or(DateTime::<Utc>::MAX_UTC),
+                    );
+                    let payload = payload(&spec, &df, &now);
+                    let delivered = deliver(&core.alerts().client, &spec, &payload).await;
+                    if let Err(e) = &delivered {
+                        warn!("Alert '{}' webhook failed: {e}", spec.name);
+                    } else {
+                        info!("Alert '{}' fired ({} rows)", spec.name, df.height());
+                    }
+                    core.alerts().update(&spec.name, |status| {
+                        status.last_fired = Some(now);
+                        status.last_error = delivered.err();
+                    });
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    warn!("Alert '{}' evaluation failed: {e}", spec.name);
+                    core.alerts()
+                        .update(&spec.name, |status| status.last_error = Some(e));
+                }
+            }
+        }
+        tokio::select! {
+            received = updates.recv() => match received {
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Disconnected | RecvError::Closed) => return,
+            },
+            _ = shutdown.closing() => return,
+        }
+    }
+}
+
+/// Run the alert's query, returning the result if the condition holds
+async fn evaluate(core: &ServerCore, spec: &AlertSpec) -> Result<Option<DataFrame>, String> {
+    let params = spec.query.piql_params()?;
+    let (df, _) = core
+        .execute_query_with_params(&spec.query.query, &params, Annotations::default())
+        .await
+        .map_err(|e| e.to_string())?;
+    let condition = spec.condition.clone();
+    let checked = df.clone();
+    let met = tokio::task::spawn_blocking(move || condition_met(&condition, checked))
+        .await
+        .map_err(|e| e.to_string())??;
+    Ok(met.then_some(df))
+}
+
+fn payload(spec: &AlertSpec, df: &DataFrame, fired_at: &DateTime<Utc>) -> AlertPayload {
+    AlertPayload {
+        alert: spec.name.clone(),
+        query: spec.query.query.clone(),
+        condition: spec.condition.clone(),
+        fired_at: timestamp(fired_at),
+        rows: df.height(),
+        columns: df
+            .get_column_names()
+            .iter()
+            .map(|name| name.to_string())
+            .collect(),
+        preview: dataframe_to_json_rows(&df.head(Some(PREVIEW_ROWS))),
+    }
+}
+
+/// POST `payload` to the webhook, retrying failures with exponential backoff
+async fn deliver(
+    client: &reqwest::Client,
+    spec: &AlertSpec,
+    payload: &AlertPayload,
+) -> Result<(), String> {
+    let mut backoff = INITIAL_BACKOFF;
+    let mut attempt = 0;
+    loop {
+        let result = client
+            .post(&spec.webhook)
+            .timeout(DELIVERY_TIMEOUT)
+            .json(payload)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        match result {
+            Ok(_) => return Ok(()),
+            Err(e) if attempt >= spec.retries => {
+                return Err(format!("{e} (after {} attempts)", attempt + 1));
+            }
+            Err(e) => {
+                warn!(
+                    "Alert '{}' webhook attempt {} failed: {e}; retrying in {backoff:?}",
+                    spec.name,
+                    attempt + 1
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// Load alerts from a JSON file holding an array of alert specs
+pub fn load_specs(path: &Path) -> Result<Vec<AlertSpec>, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+    serde_json::from_str(&text).map_err(|e| format!("invalid alerts file: {e}"))
+}
+
+// ============ HTTP ============
+
+/// List alerts
+#[utoipa::path(
+    get,
+    path = "/alerts",
+    responses((status = 200, description = "Alerts with their last notification", body = AlertsResponse))
+)]
+pub async fn list(State(core): State<Arc<ServerCore>>) -> Json<AlertsResponse> {
+    Json(AlertsResponse {
+        alerts: core.alerts().list(),
+    })
+}
+
+/// Create or replace an alert
+///
+/// The query is re-evaluated whenever a table changes; when `condition` holds, an
+/// `AlertPayload` is POSTed to `webhook` and the alert is muted for `mute_secs`.
+#[utoipa::path(
+    post,
+    path = "/alerts",
+    request_body = AlertSpec,
+    responses(
+        (status = 200, description = "The registered alert", body = AlertInfo),
+        (status = 400, description = "Invalid name, webhook, retries, query or condition", body = ErrorResponse)
+    )
+)]
+pub async fn create(
+    State(core): State<Arc<ServerCore>>,
+    Json(spec): Json<AlertSpec>,
+) -> Result<Json<AlertInfo>, AppError> {
+    info!("POST /alerts: {} ({})", spec.name, spec.condition);
+    Ok(Json(add(&core, spec).await?))
+}
+
+/// Remove an alert
+#[utoipa::path(
+    delete,
+    path = "/alerts/{name}",
+    params(("name" = String, Path, description = "Alert name")),
+    responses(
+        (status = 204, description = "Alert removed"),
+        (status = 400, description = "Unknown alert", body = ErrorResponse)
+    )
+)]
+pub async fn delete(
+    State(core): State<Arc<ServerCore>>,
+    UrlPath(name): UrlPath<String>,
+) -> Result<StatusCode, AppError> {
+    info!("DELETE /alerts/{name}");
+    if !core.alerts().remove(&name) {
+        return Err(AlertError::Unknown(name).into());
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::post;
+
+    fn spec(webhook: &str, condition: &str) -> AlertSpec {
+        serde_json::from_value(serde_json::json!({
+            "name": "rich",
+            "query": "t.filter($gold > :min)",
+            "params": {"min": 10},
+            "condition": condition,
+            "webhook": webhook,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn conditions_hold_if_any_value_is_true() {
+        let df = df! { "gold" => &[5.0, 2e6] }.unwrap();
+        assert!(condition_met("pl.len() > 0", df.clone()).unwrap());
+        assert!(condition_met("$gold.max() > 1000000", df.clone()).unwrap());
+        assert!(condition_met("$gold < 10", df.clone()).unwrap());
+        assert!(!condition_met("$gold.sum() < 0", df.clone()).unwrap());
+        assert!(!condition_met("pl.len() > 0", df.clear()).unwrap());
+        assert!(condition_met("$gold + 1", df.clone()).is_err());
+        assert!(condition_met("$missing > 1", df).is_err());
+    }
+
+    #[tokio::test]
+    async fn webhook_receives_summary_once_per_mute_window() {
+        let received = Arc::new(Mutex::new(Vec::<serde_json::Value>::new()));
+        let sink = received.clone();
+        let app = axum::Router::new().route(
+            "/hook",
+            post(move |Json(body): Json<serde_json::Value>| async move {
+                sink.lock().unwrap().push(body);
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let core = ServerCore::new();
+        core.insert_df("t", df! { "gold" => &[5, 20] }.unwrap())
+            .await;
+        let info = add(&core, spec(&format!("http://{addr}/hook"), "pl.len() > 1"))
+            .await
+            .unwrap();
+        assert_eq!(info.spec.mute_secs, 300);
+
+        core.insert_df("t", df! { "gold" => &[5, 20, 30] }.unwrap())
+            .await;
+        for _ in 0..100 {
+            if !received.lock().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        // Muted: a second match doesn't notify again
+        core.insert_df("t", df! { "gold" => &[20, 30, 40] }.unwrap())
+            .await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let received = received.lock().unwrap().clone();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0]["alert"], "rich");
+        assert_eq!(received[0]["rows"], 2);
+        assert_eq!(received[0]["preview"][1]["gold"], 30);
+        let listed = core.alerts().list();
+        assert!(listed[0].last_fired.is_some());
+        assert!(listed[0].last_error.is_none());
+
+        assert!(core.alerts().remove("rich"));
+        assert!(core.alerts().list().is_empty());
+    }
+
+    #[tokio::test]
+    async fn invalid_alerts_are_rejected() {
+        let core = ServerCore::new();
+        core.insert_df("t", df! { "gold" => &[5, 20] }.unwrap())
+            .await;
+        let hook = "http://localhost:1/hook";
+
+        let mut bad_name = spec(hook, "pl.len() > 0");
+        bad_name.name = "a/b".into();
+        assert!(matches!(
+            add(&core, bad_name).await,
+            Err(AlertError::InvalidName(_))
+        ));
+        assert!(matches!(
+            add(&core, spec("ftp://host", "pl.len() > 0")).await,
+            Err(AlertError::InvalidWebhook(_))
+        ));
+        let mut persistent = spec(hook, "pl.len() > 0");
+        persistent.retries = MAX_RETRIES + 1;
+        assert!(matches!(
+            add(&core, persistent).await,
+            Err(AlertError::TooManyRetries(_))
+        ));
+        assert!(matches!(
+            add(&core, spec(hook, "$gold +")).await,
+            Err(AlertError::InvalidCondition { .. })
+        ));
+        let mut bad_query = spec(hook, "pl.len() > 0");
+        bad_query.query.query = "missing".into();
+        assert!(matches!(
+            add(&core, bad_query).await,
+            Err(AlertError::Query(_))
+        ));
+        assert!(core.alerts().list().is_empty());
+    }
+}
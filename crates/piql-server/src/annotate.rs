@@ -0,0 +1,198 @@
+//! Provenance columns injected into query results
+//!
+//! Consumers that store pushed results often need to know where a frame came from.
+//! Instead of tracking that client-side, endpoints can ask for any of:
+//! - `_tick` → EvalContext tick the query was evaluated at (null if unset)
+//! - `_run` → current run name (null outside multi-run mode)
+//! - `_generated_at` → server timestamp (UTC, milliseconds)
+//! - `_query_hash` → stable hash of the query text
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use polars::prelude::*;
+
+pub const TICK_COLUMN: &str = "_tick";
+pub const RUN_COLUMN: &str = "_run";
+pub const GENERATED_AT_COLUMN: &str = "_generated_at";
+pub const QUERY_HASH_COLUMN: &str = "_query_hash";
+
+/// Which provenance columns to inject
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Annotations {
+    pub tick: bool,
+    pub run: bool,
+    pub generated_at: bool,
+    pub query_hash: bool,
+}
+
+impl Annotations {
+    pub fn all() -> Self {
+        Self {
+            tick: true,
+            run: true,
+            generated_at: true,
+            query_hash: true,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Parse a comma-separated list, e.g. `tick,run` or `all`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut annotations = Self::default();
+        for part in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            match part {
+                "all" => annotations = Self::all(),
+                "tick" => annotations.tick = true,
+                "run" => annotations.run = true,
+                "generated_at" => annotations.generated_at = true,
+                "query_hash" => annotations.query_hash = true,
+                other => {
+                    return Err(format!(
+                        "unknown annotation '{other}' (expected tick, run, generated_at, query_hash or all)"
+                    ));
+                }
+            }
+        }
+        Ok(annotations)
+    }
+}
+
+/// Values for the provenance columns of a single evaluation
+pub struct Provenance<'a> {
+    pub tick: Option<i64>,
+    pub run: Option<&'a str>,
+    pub query: &'a str,
+}
+
+/// Append the requested provenance columns to a collected result.
+///
+/// Columns already present in the result (e.g. `_run` on `_all::` tables) are left untouched.
+pub fn annotate(
+    df: DataFrame,
+    annotations: Annotations,
+    provenance: &Provenance<'_>,
+) -> PolarsResult<DataFrame> {
+    if annotations.is_empty() {
+        return Ok(df);
+    }
+
+    let schema = df.schema();
+    let mut exprs = Vec::new();
+    let mut push = |name: &str, e: Expr| {
+        if !schema.contains(name) {
+            exprs.push(e.alias(name));
+        }
+    };
+
+    if annotations.tick {
+        let tick = match provenance.tick {
+            Some(t) => lit(t),
+            None => lit(NULL),
+        };
+        push(TICK_COLUMN, tick.cast(DataType::Int64));
+    }
+    if annotations.run {
+        let run = match provenance.run {
+            Some(r) => lit(r),
+            None => lit(NULL).cast(DataType::String),
+        };
+        push(RUN_COLUMN, run);
+    }
+    if annotations.generated_at {
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as i64)
+            .unwrap_or_default();
+        push(
+            GENERATED_AT_COLUMN,
+            lit(now_ms).cast(DataType::Datetime(TimeUnit::Milliseconds, None)),
+        );
+    }
+    if annotations.query_hash {
+        push(QUERY_HASH_COLUMN, lit(query_hash(provenance.query)));
+    }
+
+    if exprs.is_empty() {
+        return Ok(df);
+    }
+    df.lazy().with_columns(exprs).collect()
+}
+
+/// Stable (FNV-1a, 64-bit) hex hash of the query text.
+///
+/// Unlike `DefaultHasher`, the output does not change across Rust releases, so it
+/// can be stored alongside results and compared later.
+pub fn query_hash(query: &str) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in query.trim().bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    format!("{hash:016x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_annotation_list() {
+        let a = Annotations::parse("tick, query_hash").unwrap();
+        assert!(a.tick && a.query_hash && !a.run && !a.generated_at);
+        assert_eq!(Annotations::parse("all").unwrap(), Annotations::all());
+        assert!(Annotations::parse("").unwrap().is_empty());
+        assert!(Annotations::parse("tick,bogus").is_err());
+    }
+
+    #[test]
+    fn annotate_adds_requested_columns() {
+        let df = df! { "a" => &[1, 2] }.unwrap();
+        let provenance = Provenance {
+            tick: Some(7),
+            run: None,
+            query: "t",
+        };
+        let out = annotate(df, Annotations::all(), &provenance).unwrap();
+
+        assert_eq!(
+            out.get_column_names(),
+            &["a", "_tick", "_run", "_generated_at", "_query_hash"]
+        );
+        assert_eq!(out.column("_tick").unwrap().i64().unwrap().get(1), Some(7));
+        assert_eq!(out.column("_run").unwrap().null_count(), 2);
+        assert_eq!(
+            out.column("_query_hash").unwrap().str().unwrap().get(0),
+            Some(query_hash("t").as_str())
+        );
+    }
+
+    #[test]
+    fn annotate_keeps_existing_columns() {
+        let df = df! { "_run" => &["r1", "r2"] }.unwrap();
+        let provenance = Provenance {
+            tick: None,
+            run: Some("latest"),
+            query: "t",
+        };
+        let annotations = Annotations {
+            run: true,
+            ..Default::default()
+        };
+        let out = annotate(df, annotations, &provenance).unwrap();
+        assert_eq!(out.width(), 1);
+        assert_eq!(
+            out.column("_run").unwrap().str().unwrap().get(0),
+            Some("r1")
+        );
+    }
+
+    #[test]
+    fn query_hash_is_stable() {
+        assert_eq!(query_hash("entities"), query_hash("  entities\n"));
+        assert_ne!(query_hash("entities"), query_hash("trades"));
+        assert_eq!(query_hash("").len(), 16);
+    }
+}
@@ -0,0 +1,369 @@
+//! Optional API-key / bearer-token authentication
+//!
+//! Credentials are looked up in two tables:
+//! - `X-Api-Key: <key>` → `api_keys`
+//! - `Authorization: Bearer <token>` → `bearer_tokens`
+//!
+//! Each credential has a scope. `read` may query and inspect; `write` may also
+//! modify server state (upload/delete tables, materialize). Missing or unknown
+//! credentials get 401, insufficient scope gets 403.
+//!
+//! The middleware attaches the credential's scope to the request, for endpoints that
+//! only write for some request bodies (`POST /export` with a `path`), and the
+//! credential itself, which `/ask` counts usage against.
+//!
+//! A credential may also have a [`ColumnMask`]; the middleware attaches it to the
+//! request for the query endpoints to enforce. Masked credentials can't use `/ask`
+//! or `/materialize`, which would expose the hidden columns, or rename tables.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use axum::Json;
+use axum::extract::{Request, State};
+use axum::http::{HeaderMap, Method, StatusCode, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use serde::Deserialize;
+
+use crate::mask::ColumnMask;
+use crate::state::ErrorResponse;
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// The configured credential a request was authenticated with, attached by
+/// [`require_auth`] for endpoints that account usage per client
+#[derive(Debug, Clone)]
+pub struct Credential(pub String);
+
+/// Access level granted to a credential
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Queries, subscriptions and introspection
+    Read,
+    /// Everything, including endpoints that modify tables
+    Write,
+}
+
+impl Scope {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "read" => Ok(Self::Read),
+            "write" => Ok(Self::Write),
+            other => Err(format!("unknown scope '{other}' (expected read or write)")),
+        }
+    }
+}
+
+/// Accepted credentials and their scopes
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AuthConfig {
+    #[serde(default)]
+    pub api_keys: HashMap<String, Scope>,
+    #[serde(default)]
+    pub bearer_tokens: HashMap<String, Scope>,
+    /// Columns hidden from a credential (API key or bearer token)
+    #[serde(default)]
+    pub column_masks: HashMap<String, ColumnMask>,
+}
+
+/// Why a request was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthError {
+    /// No credential, or one that isn't configured (401)
+    Unauthorized,
+    /// Valid credential without the required scope (403)
+    Forbidden,
+    /// Masked credential on an endpoint that can't enforce its mask (403)
+    Masked,
+}
+
+impl AuthConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accept `key` in the `X-Api-Key` header
+    pub fn with_api_key(mut self, key: impl Into<String>, scope: Scope) -> Self {
+        self.api_keys.insert(key.into(), scope);
+        self
+    }
+
+    /// Accept `token` in an `Authorization: Bearer` header
+    pub fn with_bearer_token(mut self, token: impl Into<String>, scope: Scope) -> Self {
+        self.bearer_tokens.insert(token.into(), scope);
+        self
+    }
+
+    /// Hide columns from `credential` (an API key or bearer token)
+    pub fn with_column_mask(mut self, credential: impl Into<String>, mask: ColumnMask) -> Self {
+        self.column_masks.insert(credential.into(), mask);
+        self
+    }
+
+    /// Load from a JSON file: `{"api_keys": {"<key>": "read"}, "bearer_tokens": {"<token>": "write"}}`
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+        serde_json::from_str(&text).map_err(|e| format!("invalid auth config: {e}"))
+    }
+
+    /// The credential presented in `headers` and its scope, if configured
+    fn credential<'a>(&self, headers: &'a HeaderMap) -> Option<(&'a str, Scope)> {
+        if let Some(key) = headers.get(API_KEY_HEADER).and_then(|v| v.to_str().ok())
+            && let Some(scope) = self.api_keys.get(key)
+        {
+            return Some((key, *scope));
+        }
+        let token = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))?
+            .trim();
+        self.bearer_tokens.get(token).map(|scope| (token, *scope))
+    }
+
+    /// Column mask of the credential presented in `headers`, if it has one
+    pub fn column_mask(&self, headers: &HeaderMap) -> Option<&ColumnMask> {
+        let (credential, _) = self.credential(headers)?;
+        self.column_masks.get(credential)
+    }
+
+    /// Check a request against the configured credentials
+    pub fn authorize(
+        &self,
+        method: &Method,
+        path: &str,
+        headers: &HeaderMap,
+    ) -> Result<(), AuthError> {
+        let (credential, scope) = self.credential(headers).ok_or(AuthError::Unauthorized)?;
+        if scope < required_scope(method, path) {
+            return Err(AuthError::Forbidden);
+        }
+        // Masks are keyed by table name, so a renamed table would escape its mask
+        let renames = *method == Method::PATCH && path.starts_with("/dataframes/");
+        let unmasked = renames
+            || path == "/materialize"
+            || path == "/ask"
+            || path == "/diff"
+            || path.starts_with("/schedules")
+            || path.starts_with("/alerts");
+        if self.column_masks.contains_key(credential) && unmasked {
+            return Err(AuthError::Masked);
+        }
+        Ok(())
+    }
+}
+
+/// Scope needed for an endpoint: anything that modifies tables, runs, schedules,
+/// alerts, templates, saved queries or the result cache requires `write` (running
+/// a template or saved query only needs `read`)
+pub fn required_scope(method: &Method, path: &str) -> Scope {
+    let modifies_tables = *method == Method::DELETE
+        || *method == Method::PATCH
+        || path == "/materialize"
+        || (*method == Method::POST && path.starts_with("/dataframes/"))
+        || (*method == Method::POST && path.starts_with("/runs/"))
+        || (*method == Method::POST && path.starts_with("/schedules"))
+        || (*method == Method::POST && path.starts_with("/alerts"))
+        || *method == Method::PUT
+        || (*method == Method::POST && path == "/templates")
+        || (*method == Method::POST && path == "/queries/saved")
+        || (*method == Method::POST && path == "/cache/clear");
+    if modifies_tables {
+        Scope::Write
+    } else {
+        Scope::Read
+    }
+}
+
+/// Middleware rejecting requests without a sufficiently scoped credential
+pub async fn require_auth(
+    State(config): State<Arc<AuthConfig>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    match config.authorize(request.method(), request.uri().path(), request.headers()) {
+        Ok(()) => {
+            if let Some(mask) = config.column_mask(request.headers()).cloned() {
+                request.extensions_mut().insert(mask);
+            }
+            // For handlers whose body decides whether they write (`/export` with `path`)
+            if let Some((credential, scope)) = config.credential(request.headers()) {
+                let credential = Credential(credential.to_string());
+                request.extensions_mut().insert(scope);
+                request.extensions_mut().insert(credential);
+            }
+            next.run(request).await
+        }
+        Err(err) => {
+            let (status, error) = match err {
+                AuthError::Unauthorized => (
+                    StatusCode::UNAUTHORIZED,
+                    "missing or invalid credentials (use X-Api-Key or Authorization: Bearer)",
+                ),
+                AuthError::Forbidden => (
+                    StatusCode::FORBIDDEN,
+                    "credential does not have write scope",
+                ),
+                AuthError::Masked => (
+                    StatusCode::FORBIDDEN,
+                    "credential has column masks, which this endpoint can't enforce",
+                ),
+            };
+            log::warn!(
+                "Rejected {} {}: {error}",
+                request.method(),
+                request.uri().path()
+            );
+            (
+                status,
+                Json(ErrorResponse {
+                    error: error.to_string(),
+                }),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn headers(name: &'static str, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn scopes_gate_write_endpoints() {
+        let config = AuthConfig::new()
+            .with_api_key("reader", Scope::Read)
+            .with_bearer_token("writer", Scope::Write);
+        let reader = headers(API_KEY_HEADER, "reader");
+        let writer = headers("authorization", "Bearer writer");
+
+        assert_eq!(config.authorize(&Method::POST, "/query", &reader), Ok(()));
+        assert_eq!(
+            config.authorize(&Method::POST, "/dataframes/t", &reader),
+            Err(AuthError::Forbidden)
+        );
+        assert_eq!(
+            config.authorize(&Method::DELETE, "/dataframes/t", &reader),
+            Err(AuthError::Forbidden)
+        );
+        assert_eq!(
+            config.authorize(&Method::PATCH, "/dataframes/t", &reader),
+            Err(AuthError::Forbidden)
+        );
+        assert_eq!(
+            config.authorize(&Method::POST, "/materialize", &writer),
+            Ok(())
+        );
+        assert_eq!(
+            config.authorize(&Method::GET, "/dataframes/t/schema", &reader),
+            Ok(())
+        );
+        assert_eq!(
+            config.authorize(&Method::POST, "/schedules/hourly/run", &reader),
+            Err(AuthError::Forbidden)
+        );
+        assert_eq!(
+            config.authorize(&Method::GET, "/schedules", &reader),
+            Ok(())
+        );
+        assert_eq!(
+            config.authorize(&Method::POST, "/runs/r1/promote", &reader),
+            Err(AuthError::Forbidden)
+        );
+        assert_eq!(
+            config.authorize(&Method::POST, "/cache/clear", &reader),
+            Err(AuthError::Forbidden)
+        );
+        assert_eq!(
+            config.authorize(&Method::POST, "/cache/clear", &writer),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn unknown_or_missing_credentials_are_unauthorized() {
+        let config = AuthConfig::new().with_api_key("reader", Scope::Read);
+        assert_eq!(
+            config.authorize(&Method::POST, "/query", &HeaderMap::new()),
+            Err(AuthError::Unauthorized)
+        );
+        assert_eq!(
+            config.authorize(&Method::POST, "/query", &headers(API_KEY_HEADER, "nope")),
+            Err(AuthError::Unauthorized)
+        );
+        // API keys are not accepted as bearer tokens
+        assert_eq!(
+            config.authorize(
+                &Method::POST,
+                "/query",
+                &headers("authorization", "Bearer reader")
+            ),
+            Err(AuthError::Unauthorized)
+        );
+    }
+
+    #[test]
+    fn masked_credentials_skip_unenforceable_endpoints() {
+        let config = AuthConfig::new()
+            .with_api_key("analyst", Scope::Write)
+            .with_column_mask(
+                "analyst",
+                ColumnMask::default().with_dropped("users", "email"),
+            );
+        let analyst = headers(API_KEY_HEADER, "analyst");
+
+        assert_eq!(config.authorize(&Method::POST, "/query", &analyst), Ok(()));
+        assert!(config.column_mask(&analyst).is_some());
+        assert_eq!(
+            config.authorize(&Method::POST, "/materialize", &analyst),
+            Err(AuthError::Masked)
+        );
+        assert_eq!(
+            config.authorize(&Method::POST, "/ask", &analyst),
+            Err(AuthError::Masked)
+        );
+        assert_eq!(
+            config.authorize(&Method::POST, "/schedules", &analyst),
+            Err(AuthError::Masked)
+        );
+        assert_eq!(
+            config.authorize(&Method::GET, "/alerts", &analyst),
+            Err(AuthError::Masked)
+        );
+        assert_eq!(
+            config.authorize(&Method::PATCH, "/dataframes/users", &analyst),
+            Err(AuthError::Masked)
+        );
+    }
+
+    #[test]
+    fn config_file_format() {
+        let config: AuthConfig =
+            serde_json::from_str(r#"{"api_keys": {"k": "write"}, "bearer_tokens": {"t": "read"}}"#)
+                .unwrap();
+        assert_eq!(config.api_keys["k"], Scope::Write);
+        assert_eq!(config.bearer_tokens["t"], Scope::Read);
+
+        let config: AuthConfig = serde_json::from_str(
+            r#"{"api_keys": {"k": "read"}, "column_masks": {"k": {"drop": {"users": ["email"]}, "redact": {"users": ["phone"]}}}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.column_masks["k"],
+            ColumnMask::default()
+                .with_dropped("users", "email")
+                .with_redacted("users", "phone")
+        );
+    }
+}
@@ -0,0 +1,331 @@
+//! Batch query endpoint: several named queries answered in one round trip
+//!
+//! `POST /query/batch` evaluates every query against one snapshot of the tables, so
+//! a dashboard's panels agree on the tick they show, and locks the shared context
+//! once rather than per query. Each query succeeds or fails on its own; the response
+//! carries a status per query.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Instant;
+
+use axum::Json;
+use axum::extract::{Extension, State};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use log::info;
+use polars::prelude::DataFrame;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::core::ServerCore;
+use crate::error::AppError;
+use crate::ipc::dataframe_to_ipc_bytes;
+use crate::json::dataframe_to_compact_json;
+use crate::mask::ColumnMask;
+use crate::schedule::valid_name;
+use crate::state::{ErrorResponse, QueryRequest};
+
+/// One query of a batch
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct BatchQuery {
+    /// Identifies the result in the response: letters, digits, `_` and `-`
+    pub name: String,
+    #[serde(flatten)]
+    pub query: QueryRequest,
+}
+
+/// JSON body of `POST /query/batch`
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct BatchRequest {
+    pub queries: Vec<BatchQuery>,
+    /// Evaluate the queries concurrently instead of one after another
+    #[serde(default)]
+    pub parallel: bool,
+}
+
+/// Outcome of one query, as returned with `Accept: application/json`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BatchResult {
+    pub name: String,
+    /// HTTP status the query would have had on its own
+    pub status: u16,
+    /// Compact JSON result, as returned by `/query`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub result: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Results of a batch, in request order
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BatchResponse {
+    pub results: Vec<BatchResult>,
+}
+
+/// A query's name and its masked result or error
+struct Outcome {
+    name: String,
+    result: Result<DataFrame, AppError>,
+}
+
+/// Validate `request` and run its queries against one snapshot of the tables, as
+/// restricted by `mask`. A query whose params are invalid or that mentions a column
+/// masked by `mask` fails alone; the others still run.
+async fn run_batch(
+    core: &ServerCore,
+    mask: Option<&ColumnMask>,
+    request: BatchRequest,
+) -> Result<Vec<Outcome>, AppError> {
+    if request.queries.is_empty() {
+        return Err(AppError::bad_request("batch has no queries"));
+    }
+    let mut names = HashSet::new();
+    for query in &request.queries {
+        if !valid_name(&query.name) {
+            return Err(AppError::bad_request(format!(
+                "invalid query name {:?}: use letters, digits, `_` and `-`",
+                query.name
+            )));
+        }
+        if !names.insert(query.name.as_str()) {
+            return Err(AppError::bad_request(format!(
+                "duplicate query name: {}",
+                query.name
+            )));
+        }
+    }
+
+    // Whether each query can run, or why not
+    let mut planned = Vec::with_capacity(request.queries.len());
+    let mut runnable = Vec::new();
+    for query in &request.queries {
+        match plan(core, mask, &query.query).await {
+            Ok(bindings) => {
+                runnable.push((query.query.query.clone(), bindings));
+                planned.push(Ok(()));
+            }
+            Err(e) => planned.push(Err(e)),
+        }
+    }
+
+    let mut executed = core
+        .execute_batch(&runnable, request.parallel, mask)
+        .await
+        .into_iter();
+    let outcomes = request
+        .queries
+        .into_iter()
+        .zip(planned)
+        .map(|(query, planned)| {
+            let result = planned
+                .and_then(|()| Ok(executed.next().expect("one result per runnable query")?));
+            Outcome {
+                name: query.name,
+                result,
+            }
+        })
+        .collect();
+    Ok(outcomes)
+}
+
+/// Bind `query`'s params and check it against `mask`, returning the bindings
+async fn plan(
+    core: &ServerCore,
+    mask: Option<&ColumnMask>,
+    query: &QueryRequest,
+) -> Result<piql::Params, AppError> {
+    let bindings = query.piql_params().map_err(AppError::bad_request)?;
+    if let Some(mask) = mask {
+        let compiled = core.compile_query(&query.query, &bindings).await?;
+        mask.check(&compiled).map_err(AppError::forbidden)?;
+    }
+    Ok(bindings)
+}
+
+/// Encode `outcomes` as a `multipart/mixed` body: one part per query, in request
+/// order, holding an Arrow IPC stream or a JSON error. Returns the boundary and body.
+async fn multipart(outcomes: Vec<Outcome>) -> (String, Vec<u8>) {
+    let mut parts = Vec::with_capacity(outcomes.len());
+    for outcome in outcomes {
+        let encoded = match outcome.result {
+            Ok(df) => dataframe_to_ipc_bytes(df).await.map_err(AppError::from),
+            Err(e) => Err(e),
+        };
+        let (status, content_type, body) = match encoded {
+            Ok(bytes) => (StatusCode::OK, "application/vnd.apache.arrow.stream", bytes),
+            Err(e) => {
+                let error = ErrorResponse { error: e.message };
+                let body = serde_json::to_vec(&error).unwrap_or_default();
+                (e.status, "application/json", body)
+            }
+        };
+        parts.push((outcome.name, status, content_type, body));
+    }
+
+    // The boundary must not occur in any part; Arrow payloads are arbitrary bytes
+    let boundary = (0..)
+        .map(|n| format!("piql-batch-{n}"))
+        .find(|b| {
+            parts
+                .iter()
+                .all(|(.., body)| !body.windows(b.len()).any(|w| w == b.as_bytes()))
+        })
+        .expect("some boundary is unused");
+
+    let mut out = Vec::new();
+    for (name, status, content_type, body) in parts {
+        out.extend_from_slice(
+            format!(
+                "--{boundary}\r\nContent-Type: {content_type}\r\nX-Piql-Query: {name}\r\nX-Piql-Status: {}\r\n\r\n",
+                status.as_u16()
+            )
+            .as_bytes(),
+        );
+        out.extend_from_slice(&body);
+        out.extend_from_slice(b"\r\n");
+    }
+    out.extend_from_slice(format!("--{boundary}--\r\n").as_bytes());
+    (boundary, out)
+}
+
+// ============ HTTP ============
+
+/// Execute several queries in one request
+///
+/// All queries see the same snapshot of the tables. With `parallel: true` they are
+/// evaluated concurrently on the compute pool. The response is `multipart/mixed`
+/// with one part per query, in request order: each part has `X-Piql-Query` (its
+/// name) and `X-Piql-Status` headers and holds an Arrow IPC stream, or a JSON
+/// `{error}` when the query failed. With `Accept: application/json` the response is
+/// a [`BatchResponse`] with compact JSON results instead.
+/// Results bypass the result cache. Columns masked for the caller's credential are
+/// dropped or redacted, and queries mentioning them fail with status 403.
+#[utoipa::path(
+    post,
+    path = "/query/batch",
+    request_body = BatchRequest,
+    responses(
+        (status = 200, description = "One part per query, or JSON with `Accept: application/json`",
+            content_type = "multipart/mixed", body = BatchResponse),
+        (status = 400, description = "No queries, or an invalid or duplicate name", body = ErrorResponse)
+    )
+)]
+pub async fn batch(
+    State(core): State<Arc<ServerCore>>,
+    mask: Option<Extension<ColumnMask>>,
+    headers: HeaderMap,
+    Json(request): Json<BatchRequest>,
+) -> Result<Response, AppError> {
+    info!(
+        "POST /query/batch: {} queries{}",
+        request.queries.len(),
+        if request.parallel { " (parallel)" } else { "" }
+    );
+    let start = Instant::now();
+    let outcomes = run_batch(&core, mask.as_ref().map(|Extension(mask)| mask), request).await?;
+    let failed = outcomes.iter().filter(|o| o.result.is_err()).count();
+    info!(
+        "Batch finished in {:.2?}, {} of {} queries failed",
+        start.elapsed(),
+        failed,
+        outcomes.len()
+    );
+
+    let wants_json = headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("application/json"));
+    if wants_json {
+        let results = outcomes
+            .into_iter()
+            .map(|outcome| match outcome.result {
+                Ok(df) => BatchResult {
+                    name: outcome.name,
+                    status: StatusCode::OK.as_u16(),
+                    result: Some(dataframe_to_compact_json(&df)),
+                    error: None,
+                },
+                Err(e) => BatchResult {
+                    name: outcome.name,
+                    status: e.status.as_u16(),
+                    result: None,
+                    error: Some(e.message),
+                },
+            })
+            .collect();
+        return Ok(Json(BatchResponse { results }).into_response());
+    }
+
+    let (boundary, body) = multipart(outcomes).await;
+    Ok((
+        [(
+            header::CONTENT_TYPE,
+            format!("multipart/mixed; boundary={boundary}"),
+        )],
+        body,
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::prelude::*;
+
+    fn request(queries: &[(&str, &str)], parallel: bool) -> BatchRequest {
+        serde_json::from_value(serde_json::json!({
+            "queries": queries
+                .iter()
+                .map(|(name, query)| serde_json::json!({
+                    "name": name,
+                    "query": query,
+                    "params": {"min": 2},
+                }))
+                .collect::<Vec<_>>(),
+            "parallel": parallel,
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn batch_queries_succeed_or_fail_independently() {
+        let core = ServerCore::new();
+        core.insert_df("t", df! { "x" => &[1, 2, 3] }.unwrap())
+            .await;
+
+        for parallel in [false, true] {
+            let queries = [
+                ("big", "t.filter($x >= :min)"),
+                ("missing", "nope"),
+                ("all", "t"),
+            ];
+            let Ok(outcomes) = run_batch(&core, None, request(&queries, parallel)).await else {
+                panic!("expected the batch to run");
+            };
+            let names: Vec<_> = outcomes.iter().map(|o| o.name.as_str()).collect();
+            assert_eq!(names, ["big", "missing", "all"]);
+            assert_eq!(outcomes[0].result.as_ref().ok().unwrap().height(), 2);
+            let Err(e) = &outcomes[1].result else {
+                panic!("expected an unknown table error");
+            };
+            assert_eq!(e.status, StatusCode::BAD_REQUEST);
+            assert_eq!(outcomes[2].result.as_ref().ok().unwrap().height(), 3);
+
+            let (boundary, body) = multipart(outcomes).await;
+            let body = String::from_utf8_lossy(&body);
+            assert_eq!(body.matches(&format!("--{boundary}\r\n")).count(), 3);
+            assert!(body.contains("X-Piql-Query: missing\r\nX-Piql-Status: 400\r\n"));
+            assert!(body.ends_with(&format!("--{boundary}--\r\n")));
+        }
+
+        let invalid: [&[(&str, &str)]; 3] = [&[], &[("a", "t"), ("a", "t")], &[("a b", "t")]];
+        for queries in invalid {
+            let Err(e) = run_batch(&core, None, request(queries, false)).await else {
+                panic!("expected the batch to be rejected");
+            };
+            assert_eq!(e.status, StatusCode::BAD_REQUEST);
+        }
+    }
+}
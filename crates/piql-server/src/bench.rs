@@ -0,0 +1,232 @@
+//! Timing a query over repeated runs
+//!
+//! `POST /bench` evaluates a query `warmup` times untimed, then `iterations` times,
+//! bypassing the result cache so every run does the full work. It reports latency
+//! percentiles and the estimated size of the largest result, to compare
+//! formulations of the same query. Polars doesn't report the memory a query uses
+//! while it runs, so the result size is the only memory figure.
+//!
+//! A credential with a column mask benches against its restricted tables, like
+//! `POST /query`.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::extract::State;
+use axum::{Extension, Json};
+use log::info;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::core::ServerCore;
+use crate::error::AppError;
+use crate::mask::ColumnMask;
+use crate::state::{ErrorResponse, QueryRequest};
+
+/// Timed runs when the request doesn't say
+pub const DEFAULT_ITERATIONS: u32 = 10;
+/// Most timed runs one request may ask for
+pub const MAX_ITERATIONS: u32 = 1000;
+
+/// JSON body of `POST /bench`
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct BenchRequest {
+    #[serde(flatten)]
+    pub query: QueryRequest,
+    /// Timed runs (default: 10, at most 1000)
+    pub iterations: Option<u32>,
+    /// Untimed runs before the timed ones (default: 1)
+    pub warmup: Option<u32>,
+}
+
+/// Result of `POST /bench`
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct BenchResponse {
+    pub iterations: u32,
+    pub warmup: u32,
+    pub min_ms: f64,
+    pub median_ms: f64,
+    pub p95_ms: f64,
+    pub max_ms: f64,
+    pub mean_ms: f64,
+    /// Rows in the result
+    pub rows: usize,
+    /// Estimated in-memory size of the largest result, in bytes (not the memory
+    /// used while evaluating)
+    pub max_result_bytes: usize,
+}
+
+/// Latency summary of `timings` (at least one)
+fn summarize(mut timings: Vec<Duration>) -> [f64; 5] {
+    timings.sort();
+    let ms = |d: Duration| d.as_secs_f64() * 1e3;
+    let n = timings.len();
+    let percentile = |p: f64| ms(timings[((p * n as f64).ceil() as usize).clamp(1, n) - 1]);
+    let mean = timings.iter().copied().map(ms).sum::<f64>() / n as f64;
+    [
+        ms(timings[0]),
+        percentile(0.5),
+        percentile(0.95),
+        ms(timings[n - 1]),
+        mean,
+    ]
+}
+
+/// Time repeated runs of a query
+///
+/// Runs the query `warmup` times, then `iterations` times, bypassing the result
+/// cache, and reports min/median/p95/max/mean latency in milliseconds plus the
+/// estimated size of the largest result.
+#[utoipa::path(
+    post,
+    path = "/bench",
+    request_body = BenchRequest,
+    responses(
+        (status = 200, description = "Latency summary", body = BenchResponse),
+        (status = 400, description = "Query error or too many iterations", body = ErrorResponse),
+        (status = 403, description = "Query mentions a column masked for this credential", body = ErrorResponse),
+        (status = 422, description = "Query exceeded a resource limit", body = ErrorResponse)
+    )
+)]
+pub async fn bench(
+    State(core): State<Arc<ServerCore>>,
+    mask: Option<Extension<ColumnMask>>,
+    Json(request): Json<BenchRequest>,
+) -> Result<Json<BenchResponse>, AppError> {
+    let query = &request.query.query;
+    let iterations = request.iterations.unwrap_or(DEFAULT_ITERATIONS);
+    let warmup = request.warmup.unwrap_or(1);
+    if iterations == 0 || iterations > MAX_ITERATIONS {
+        return Err(AppError::bad_request(format!(
+            "iterations must be between 1 and {MAX_ITERATIONS}"
+        )));
+    }
+    if warmup > MAX_ITERATIONS {
+        return Err(AppError::bad_request(format!(
+            "warmup must be at most {MAX_ITERATIONS}"
+        )));
+    }
+    info!(
+        "POST /bench ({iterations} iterations): {}",
+        query.lines().next().unwrap_or(query)
+    );
+    let params = request.query.piql_params().map_err(AppError::bad_request)?;
+    let mask = mask.as_ref().map(|Extension(mask)| mask);
+    if let Some(mask) = mask {
+        let compiled = core.compile_query(query, &params).await?;
+        mask.check(&compiled).map_err(AppError::forbidden)?;
+    }
+
+    for _ in 0..warmup {
+        core.execute_query_uncached(query, &params, mask).await?;
+    }
+    let mut timings = Vec::with_capacity(iterations as usize);
+    let mut rows = 0;
+    let mut max_result_bytes = 0;
+    for _ in 0..iterations {
+        let start = Instant::now();
+        let df = core.execute_query_uncached(query, &params, mask).await?;
+        timings.push(start.elapsed());
+        rows = df.height();
+        max_result_bytes = max_result_bytes.max(df.estimated_size());
+    }
+
+    let [min_ms, median_ms, p95_ms, max_ms, mean_ms] = summarize(timings);
+    Ok(Json(BenchResponse {
+        iterations,
+        warmup,
+        min_ms,
+        median_ms,
+        p95_ms,
+        max_ms,
+        mean_ms,
+        rows,
+        max_result_bytes,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::prelude::df;
+
+    #[test]
+    fn percentiles_pick_ranked_timings() {
+        let timings = (1..=20).map(Duration::from_millis).collect();
+        let [min, median, p95, max, mean] = summarize(timings);
+        assert_eq!((min, median, p95, max), (1.0, 10.0, 19.0, 20.0));
+        assert!((mean - 10.5).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn bench_reports_latency_and_result_size() {
+        let core = Arc::new(ServerCore::new());
+        core.insert_df("t", df! { "a" => &[1, 2, 3] }.unwrap())
+            .await;
+        let request = |iterations| {
+            serde_json::from_value::<BenchRequest>(serde_json::json!({
+                "query": "t.filter($a > 1)",
+                "iterations": iterations,
+            }))
+            .unwrap()
+        };
+
+        let Ok(Json(response)) = bench(State(core.clone()), None, Json(request(5))).await else {
+            panic!("bench failed");
+        };
+        assert_eq!(response.iterations, 5);
+        assert_eq!(response.rows, 2);
+        assert!(response.max_result_bytes > 0);
+        assert!(response.min_ms <= response.median_ms && response.median_ms <= response.max_ms);
+
+        assert!(bench(State(core), None, Json(request(0))).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn bench_enforces_column_masks() {
+        let core = Arc::new(ServerCore::new());
+        let users = df! {
+            "name" => &["a", "b"],
+            "email" => &["ceo@corp", "b@x"],
+        }
+        .unwrap();
+        core.insert_df("users", users).await;
+        let mask = Extension(ColumnMask::default().with_dropped("users", "email"));
+        let request = |query: &str| {
+            serde_json::from_value::<BenchRequest>(serde_json::json!({
+                "query": query,
+                "iterations": 1,
+            }))
+            .unwrap()
+        };
+
+        let Err(err) = bench(
+            State(core.clone()),
+            Some(mask.clone()),
+            Json(request(r#"users.filter($email == "ceo@corp")"#)),
+        )
+        .await
+        else {
+            panic!("a masked column must not be usable");
+        };
+        assert_eq!(err.status, axum::http::StatusCode::FORBIDDEN);
+
+        // Selectors run against the restricted table, so the result is smaller
+        let Ok(Json(masked)) = bench(
+            State(core.clone()),
+            Some(mask),
+            Json(request("users.select(pl.all())")),
+        )
+        .await
+        else {
+            panic!("bench failed");
+        };
+        let Ok(Json(full)) =
+            bench(State(core), None, Json(request("users.select(pl.all())"))).await
+        else {
+            panic!("bench failed");
+        };
+        assert_eq!(masked.rows, 2);
+        assert!(masked.max_result_bytes < full.max_result_bytes);
+    }
+}
@@ -7,7 +7,8 @@ use std::sync::Arc;
 
 use anyhow::Context;
 use clap::Parser;
-use piql::TimeSeriesConfig;
+use piql::{TickDtype, TimeSeriesConfig};
+use piql_server::TableOrigin;
 
 #[derive(Parser)]
 #[command(name = "piql-server")]
@@ -32,10 +33,29 @@ EXAMPLES:
 
     # Each subdir with a _ready sentinel is a run:
     #   data/0206_1430_basic/fill.parquet  → fill, _0206_1430_basic::fill, _all::fill
+
+    # Normalize a table on every (re)load
+    piql-server ./data/ --reload-hook 'events=events.filter($value.is_not_null())'
+
+    # Tables in S3, re-read every 5 minutes (built with --features cloud)
+    piql-server s3://sims/latest/ticks.parquet s3://sims/latest/trades/ --remote-poll-secs 300
+
+    # Boot with synthetic demo data (entities, trades, locations)
+    piql-server --demo
+
+    # Require an API key (read-only key for dashboards, write key for uploads)
+    piql-server ./data/ --api-key dashboard-key --api-key admin-key:write
+
+    # Keep uploaded tables and materialized views across restarts
+    piql-server ./data/ --state-dir ./piql-state/ --state-save-interval 300
+
+    # Only allow a local dashboard to call the API; accept 256 MiB uploads
+    piql-server ./data/ --cors-origin http://localhost:5173 --max-body-mb 256
 ")]
 struct Args {
-    /// Paths to parquet/csv/ipc files or directories
-    #[arg(required = true)]
+    /// Paths to parquet/csv/ipc files or directories, or object store / HTTP URLs
+    /// (`s3://bucket/ticks.parquet`, `gs://bucket/trades/`; needs the `cloud` feature)
+    #[arg(required_unless_present_any = ["demo", "state_dir"])]
     paths: Vec<PathBuf>,
 
     /// Port to listen on
@@ -57,6 +77,13 @@ struct Args {
     #[arg(long, conflicts_with = "concat")]
     runs: bool,
 
+    /// In --runs mode, also treat every subdirectory holding data files as a run
+    /// (runs/<run>/<table>.parquet) and (re)load it once its files have been
+    /// unchanged for this many milliseconds, without waiting for a _ready sentinel.
+    /// Deleting a run directory unregisters the run.
+    #[arg(long, value_name = "MS", requires = "runs")]
+    runs_settle_ms: Option<u64>,
+
     /// In --runs mode, automatically drop an existing _run column before labeling _all:: tables.
     /// By default, loading fails if a source table already contains _run.
     #[arg(long, requires = "runs")]
@@ -66,43 +93,394 @@ struct Args {
     #[arg(long, default_value = "100000")]
     max_rows: u32,
 
-    /// Register table time-series metadata as TABLE:TICK_COLUMN:PARTITION_KEY.
+    /// Pre-load a synthetic simulation dataset (entities, trades, locations) instead of files.
+    #[arg(long, conflicts_with_all = ["concat", "runs"])]
+    demo: bool,
+
+    /// Number of entities to generate in --demo mode
+    #[arg(long, default_value = "100", requires = "demo")]
+    demo_entities: usize,
+
+    /// Number of ticks to generate in --demo mode
+    #[arg(long, default_value = "50", requires = "demo")]
+    demo_ticks: i64,
+
+    /// RNG seed for --demo mode (same seed → same data)
+    #[arg(long, default_value = "0", requires = "demo")]
+    demo_seed: u64,
+
+    /// Attempts per file reload before giving up (1 = no retries)
+    #[arg(long, default_value = "3")]
+    reload_attempts: u32,
+
+    /// Delay before the first reload retry in milliseconds (doubles per retry, capped at 1s)
+    #[arg(long, default_value = "50")]
+    reload_backoff_ms: u64,
+
+    /// Remove a table when all reload attempts fail, instead of keeping stale data
+    #[arg(long)]
+    reload_remove_on_failure: bool,
+
+    /// Seconds between re-reads of remote (URL) sources (0 = load once)
+    #[arg(long, default_value = "60")]
+    remote_poll_secs: u64,
+
+    /// Object store setting for remote sources as KEY=VALUE, e.g. aws_region=us-east-1.
+    /// Credentials are also read from the provider's usual environment variables.
+    /// Repeatable.
+    #[arg(long = "storage-option", value_name = "KEY=VALUE")]
+    storage_options: Vec<String>,
+
+    /// Quiet period in milliseconds after the last file change before reloading
+    #[arg(long, default_value = "100")]
+    reload_debounce_ms: u64,
+
+    /// Ignore changes to files whose name matches PATTERN (`*` is a wildcard), in
+    /// addition to hidden files and *.tmp/*.part/*.partial/*~. Repeatable.
+    #[arg(long = "reload-ignore", value_name = "PATTERN")]
+    reload_ignore: Vec<String>,
+
+    /// Transform a table on every load/reload with a PiQL query, as TABLE=QUERY.
+    /// The query sees the freshly loaded data under the table's own name.
+    /// Repeat this flag to configure multiple tables.
+    #[arg(long = "reload-hook", value_name = "TABLE=QUERY")]
+    reload_hooks: Vec<String>,
+
+    /// Register table time-series metadata as TABLE:TICK_COLUMN:PARTITION_KEY[:datetime].
+    /// Append `:datetime` when the tick column holds timestamps.
     /// Repeat this flag to configure multiple tables.
-    #[arg(long = "time-series", value_name = "TABLE:TICK:PARTITION")]
+    #[arg(long = "time-series", value_name = "TABLE:TICK:PARTITION[:datetime]")]
     time_series: Vec<String>,
+
+    /// Snapshot directory: restored on startup (if present) and written on shutdown.
+    /// Holds tables as Parquet plus materialized view definitions.
+    #[arg(long, value_name = "DIR")]
+    state_dir: Option<PathBuf>,
+
+    /// Rows of CSV/JSON/NDJSON files used to infer column types (0 = all rows)
+    #[arg(long, value_name = "N")]
+    infer_schema_rows: Option<usize>,
+
+    /// Read a column of loaded files as this type instead of inferring it, as
+    /// COLUMN=TYPE (TYPE as in `cast`, e.g. str, i32, datetime[ms]).
+    /// Repeat this flag to override multiple columns.
+    #[arg(long = "dtype", value_name = "COLUMN=TYPE")]
+    dtypes: Vec<String>,
+
+    /// Read string columns whose name matches PATTERN (`*` wildcard, e.g. `*_id`) as
+    /// categorical. Repeat this flag for multiple patterns.
+    #[arg(long = "categorical", value_name = "PATTERN")]
+    categorical: Vec<String>,
+
+    /// Keep the string cache behind categorical columns for the life of the process,
+    /// so repeated casts reuse it and category codes stay stable across reloads
+    #[arg(long)]
+    string_cache: bool,
+
+    /// CSV field delimiter (one character, or \t). A piql.toml next to the files can
+    /// set this and the other CSV options per directory ([csv]) and per file
+    /// ([files."name.csv"]).
+    #[arg(long, value_name = "CHAR")]
+    csv_delimiter: Option<String>,
+
+    /// CSV files have no header row (columns are named column_1, column_2, ...)
+    #[arg(long)]
+    csv_no_header: bool,
+
+    /// Lines to skip at the start of CSV files, before the header
+    #[arg(long, value_name = "N")]
+    csv_skip_rows: Option<usize>,
+
+    /// Name the column of a hive partition key (directories like `tick=5/`) as
+    /// KEY=COLUMN instead of KEY. Repeat this flag to rename multiple keys.
+    #[arg(long = "partition-column", value_name = "KEY=COLUMN")]
+    partition_columns: Vec<String>,
+
+    /// Register files as scans every query re-reads instead of loading them into
+    /// memory, so queries read only the columns and row groups they need. Suits
+    /// Parquet too large to hold; compressed and JSON files are still loaded.
+    #[arg(long)]
+    lazy: bool,
+
+    /// Require credentials, loaded from a JSON file:
+    /// {"api_keys": {"<key>": "read"|"write"}, "bearer_tokens": {"<token>": "read"|"write"}}
+    #[arg(long, value_name = "FILE")]
+    auth_config: Option<PathBuf>,
+
+    /// Accept an X-Api-Key value as KEY[:read|write] (default scope: read).
+    /// Repeat this flag to add multiple keys; combines with --auth-config.
+    #[arg(long = "api-key", value_name = "KEY[:SCOPE]")]
+    api_keys: Vec<String>,
+
+    /// Also write the snapshot every N seconds (requires --state-dir)
+    #[arg(long, value_name = "SECS", requires = "state_dir")]
+    state_save_interval: Option<u64>,
+
+    /// Allow cross-origin requests only from this origin (repeatable; default: any origin)
+    #[arg(
+        long = "cors-origin",
+        value_name = "ORIGIN",
+        conflicts_with = "no_cors"
+    )]
+    cors_origins: Vec<String>,
+
+    /// Send no CORS headers (browsers will block cross-origin requests)
+    #[arg(long)]
+    no_cors: bool,
+
+    /// Disable gzip/zstd response compression
+    #[arg(long)]
+    no_compression: bool,
+
+    /// Maximum request body size in MiB (uploads)
+    #[arg(long, value_name = "MB", default_value = "64")]
+    max_body_mb: usize,
+
+    /// Fail queries that take longer than this to collect (422)
+    #[arg(long, value_name = "SECS")]
+    query_timeout: Option<u64>,
+
+    /// Fail queries whose result has more rows than this, instead of truncating (422)
+    #[arg(long, value_name = "N")]
+    max_result_rows: Option<usize>,
+
+    /// Fail queries whose result would take more than this many MiB in memory (422)
+    #[arg(long, value_name = "MB")]
+    max_result_mb: Option<usize>,
+
+    /// Threads evaluating queries. Default: one per CPU.
+    #[arg(long, value_name = "N")]
+    compute_threads: Option<usize>,
+
+    /// Queries that may wait for a compute thread before new ones get 503
+    #[arg(long, value_name = "N", default_value_t = piql_server::compute::DEFAULT_QUEUE_DEPTH)]
+    compute_queue: usize,
+
+    /// Reject requests that upload, delete or materialize tables
+    #[arg(long)]
+    read_only: bool,
+
+    /// Reject queries calling this method, e.g. `join` or `all` (repeatable)
+    #[arg(long = "deny-method", value_name = "NAME")]
+    denied_methods: Vec<String>,
+
+    /// Only allow queries calling these methods (repeatable; `pl.*` is always allowed)
+    #[arg(long = "allow-method", value_name = "NAME")]
+    allowed_methods: Vec<String>,
+
+    /// Reject cross joins
+    #[arg(long)]
+    deny_cross_joins: bool,
+
+    /// Reject `.all()`, `.window()`, `.since()`, `.latest()` and `.last_ticks()` on tables with
+    /// more rows than this
+    #[arg(long, value_name = "N")]
+    max_history_rows: Option<usize>,
+
+    /// Lower `head(n)`, `tail(n)` and `top(n, ...)` to at most N rows
+    #[arg(long, value_name = "N")]
+    max_head: Option<i64>,
+
+    /// Evaluate queries as written, without moving filters before sorts and new
+    /// columns (for debugging)
+    #[arg(long)]
+    no_optimize: bool,
+
+    /// Maximum number of cached query results (0 disables the result cache)
+    #[arg(long, value_name = "N", default_value = "256")]
+    cache_size: usize,
+
+    /// Queries kept in the built-in `_queries` table (0 disables the query log)
+    #[arg(long, value_name = "N", default_value = "1000")]
+    query_log_size: usize,
+
+    /// Rows per Arrow record batch when streaming results (/query and Flight)
+    #[arg(long, value_name = "ROWS", default_value = "65536")]
+    batch_size: usize,
+
+    /// Seconds a shutdown (Ctrl-C or SIGTERM) waits for in-flight requests to finish
+    #[arg(long, value_name = "SECS", default_value = "30")]
+    drain_timeout: u64,
+
+    /// Directory POST /export may write files to (without it, exports are downloads only)
+    #[arg(long, value_name = "DIR")]
+    export_dir: Option<PathBuf>,
+
+    /// Seconds between heartbeat comments on idle SSE streams
+    #[arg(long, value_name = "SECS", default_value = "15")]
+    sse_heartbeat: u64,
+
+    /// Events each SSE subscription keeps for clients resuming with Last-Event-ID
+    /// (0 disables resuming)
+    #[arg(long, value_name = "N", default_value = "16")]
+    sse_replay: usize,
+
+    /// Update notices queued per SSE subscriber before --subscriber-lag applies
+    #[arg(long, value_name = "N", default_value = "16")]
+    subscriber_queue: usize,
+
+    /// What a full subscriber queue does: drop_oldest, coalesce (re-evaluate once
+    /// for everything missed) or disconnect
+    #[arg(long, value_name = "POLICY", default_value = "coalesce")]
+    subscriber_lag: piql_server::LagPolicy,
+
+    /// JSON file of scheduled queries (`[{"name", "query", "cron", "format"}]`) whose
+    /// results are written under --export-dir
+    #[arg(long, value_name = "FILE", requires = "export_dir")]
+    schedules: Option<PathBuf>,
+
+    /// JSON file holding the saved-query library served under /queries/saved,
+    /// created on the first save (default: kept in memory)
+    #[arg(long, value_name = "FILE")]
+    saved_queries: Option<PathBuf>,
+
+    /// JSON file of webhook alerts (`[{"name", "query", "condition", "webhook"}]`)
+    #[cfg(feature = "webhooks")]
+    #[arg(long, value_name = "FILE")]
+    alerts: Option<PathBuf>,
+
+    /// Maximum /ask questions per client per minute (clients are told to retry with 429)
+    #[cfg(feature = "llm")]
+    #[arg(long, value_name = "N")]
+    ask_rate_limit: Option<u32>,
+
+    /// Maximum estimated LLM tokens per client per UTC day for /ask
+    #[cfg(feature = "llm")]
+    #[arg(long, value_name = "TOKENS")]
+    ask_daily_tokens: Option<u64>,
+
+    /// LLM price per million tokens, for the cost estimate in GET /ask/usage
+    #[cfg(feature = "llm")]
+    #[arg(long, value_name = "USD", default_value = "0")]
+    ask_usd_per_mtok: f64,
+
+    /// Also serve query results over Arrow Flight (DoGet, ticket = PiQL query) on this port
+    #[cfg(feature = "flight")]
+    #[arg(long, value_name = "PORT")]
+    flight_port: Option<u16>,
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
-    let args = Args::parse();
+    let mut args = Args::parse();
 
     let max_rows = if args.max_rows == 0 {
         None
     } else {
         Some(args.max_rows)
     };
-    let core = Arc::new(piql_server::ServerCore::with_max_rows(max_rows));
+    let mut core = piql_server::ServerCore::with_max_rows(max_rows);
+    if let Some(auth) = load_auth_config(args.auth_config.as_deref(), &args.api_keys)? {
+        log::info!(
+            "Authentication enabled: {} API keys, {} bearer tokens",
+            auth.api_keys.len(),
+            auth.bearer_tokens.len()
+        );
+        core = core.with_auth(auth);
+    }
+    core = core.with_config(server_config(&args)?);
+    if let Some(path) = &args.saved_queries {
+        let library = piql_server::saved_queries::SavedQueries::open(path)?;
+        log::info!(
+            "Saved queries: {} in {}",
+            library.search(None, None).len(),
+            path.display()
+        );
+        core = core.with_saved_queries(library);
+    }
+    let default_compute = piql_server::ComputeConfig::default();
+    let compute = piql_server::ComputeConfig {
+        threads: args.compute_threads.unwrap_or(default_compute.threads),
+        queue_depth: args.compute_queue,
+    };
+    log::info!(
+        "Compute pool: {} threads, queue depth {}",
+        compute.threads,
+        compute.queue_depth
+    );
+    core = core.with_compute(compute);
+    core = core.with_policy(piql_server::QueryPolicy {
+        read_only: args.read_only,
+        allowed_methods: (!args.allowed_methods.is_empty())
+            .then(|| args.allowed_methods.iter().cloned().collect()),
+        denied_methods: args.denied_methods.iter().cloned().collect(),
+        deny_cross_joins: args.deny_cross_joins,
+        max_history_rows: args.max_history_rows,
+        max_head: args.max_head,
+    });
+    #[cfg(feature = "llm")]
+    {
+        core = core.with_ask_limits(piql_server::llm::AskLimits {
+            requests_per_minute: args.ask_rate_limit,
+            daily_tokens: args.ask_daily_tokens,
+            usd_per_million_tokens: args.ask_usd_per_mtok,
+        });
+    }
+    let core = Arc::new(core);
     log::info!(
         "Max rows per query: {}",
         max_rows.map_or("unlimited".to_string(), |n| n.to_string())
     );
+    core.set_cache_capacity(args.cache_size).await;
+    core.set_optimizer(!args.no_optimize).await;
+    if args.string_cache {
+        core.enable_string_cache().await;
+    }
+    core.set_query_log_capacity(args.query_log_size).await;
+    core.set_resource_limits(piql::ResourceLimits {
+        timeout: args.query_timeout.map(std::time::Duration::from_secs),
+        max_rows: args.max_result_rows,
+        max_bytes: args.max_result_mb.map(|mb| mb * 1024 * 1024),
+    })
+    .await;
+
+    for spec in &args.reload_hooks {
+        let (table, query) = spec
+            .split_once('=')
+            .filter(|(table, query)| !table.is_empty() && !query.is_empty())
+            .ok_or_else(|| anyhow::anyhow!("invalid --reload-hook spec '{spec}'"))?;
+        piql::compile(query, &piql::EvalContext::new())
+            .with_context(|| format!("invalid --reload-hook query for '{table}'"))?;
+        core.set_reload_hook(table, piql_server::ReloadHook::query(query))
+            .await;
+        log::info!("Registered reload hook for table: {table}");
+    }
 
-    if args.runs {
+    // Watchers stop watching when dropped, so keep them for the life of the server
+    #[cfg(feature = "file-watcher")]
+    let mut _run_watcher = None;
+    #[cfg(feature = "file-watcher")]
+    let mut _file_watcher = None;
+    let mut _remote_poller = None;
+
+    if args.demo {
+        log::info!(
+            "Loading demo dataset: {} entities, {} ticks, seed {}",
+            args.demo_entities,
+            args.demo_ticks,
+            args.demo_seed
+        );
+        piql_server::demo::load_demo(&core, args.demo_entities, args.demo_ticks, args.demo_seed)
+            .await
+            .context("failed to load demo dataset")?;
+    } else if args.runs {
         // Run-aware mode: watch parent dir for run subdirectories
         #[cfg(feature = "file-watcher")]
         {
             let parent = &args.paths[0];
             log::info!("Starting in run-aware mode, watching: {}", parent.display());
-            let _watcher = piql_server::watcher::load_and_watch_runs(
+            let watcher = piql_server::watcher::load_and_watch_runs(
                 core.clone(),
                 parent.clone(),
                 piql_server::watcher::RunModeOptions {
                     drop_existing_run_label_column: args.runs_drop_existing_run_col,
+                    settle: args.runs_settle_ms.map(std::time::Duration::from_millis),
                 },
             )
             .await?;
+            _run_watcher = Some(watcher);
         }
 
         #[cfg(not(feature = "file-watcher"))]
@@ -116,7 +494,8 @@ async fn main() -> anyhow::Result<()> {
                 Ok(dfs) => {
                     for (name, df) in dfs {
                         log::info!("Loaded concatenated df: {}", name);
-                        core.insert_df(name, df).await;
+                        core.insert_df(name.clone(), df).await;
+                        core.set_table_origin(&name, TableOrigin::file(path)).await;
                     }
                 }
                 Err(e) => {
@@ -126,84 +505,359 @@ async fn main() -> anyhow::Result<()> {
         }
     } else {
         // Normal mode: load files and optionally start watching
+        let load_options = load_options(&args)?;
+        let (urls, paths): (Vec<_>, Vec<_>) = std::mem::take(&mut args.paths)
+            .into_iter()
+            .partition(|path| path.to_str().is_some_and(piql_server::loader::is_remote));
+        args.paths = paths;
+        if !urls.is_empty() {
+            let urls = urls
+                .iter()
+                .map(|url| url.to_string_lossy().into_owned())
+                .collect();
+            let interval = (args.remote_poll_secs > 0)
+                .then(|| std::time::Duration::from_secs(args.remote_poll_secs));
+            _remote_poller = piql_server::remote::load_and_poll(
+                core.clone(),
+                urls,
+                load_options.clone(),
+                interval,
+            )
+            .await;
+        }
         #[cfg(feature = "file-watcher")]
-        let _watcher = piql_server::watcher::load_and_watch(core.clone(), args.paths).await?;
+        {
+            use piql_server::loader::{OnReloadFailure, ReloadPolicy, RetryPolicy};
+            let mut ignore = ReloadPolicy::default().ignore;
+            ignore.extend(args.reload_ignore.iter().cloned());
+            let policy = ReloadPolicy {
+                debounce: std::time::Duration::from_millis(args.reload_debounce_ms),
+                ignore,
+                retry: RetryPolicy {
+                    max_attempts: args.reload_attempts,
+                    backoff: std::time::Duration::from_millis(args.reload_backoff_ms),
+                    ..Default::default()
+                },
+                on_failure: if args.reload_remove_on_failure {
+                    OnReloadFailure::Remove
+                } else {
+                    OnReloadFailure::KeepStale
+                },
+                load: load_options,
+                ..Default::default()
+            };
+            let watcher =
+                piql_server::watcher::load_and_watch_with_policy(core.clone(), args.paths, policy)
+                    .await?;
+            _file_watcher = Some(watcher);
+        }
 
         #[cfg(not(feature = "file-watcher"))]
         {
             // Just load files once without watching
-            let files = piql_server::loader::collect_files(&args.paths);
+            let mut files = piql_server::loader::collect_files(&args.paths);
+            files.extend(piql_server::loader::collect_datasets(&args.paths));
             for path in files {
-                if let Ok(df) = piql_server::loader::load_file(&path).await {
-                    let name = piql_server::loader::df_name_from_path(&path);
-                    core.insert_df(name, df).await;
+                let name = piql_server::loader::df_name_from_path(&path);
+                if load_options.lazy {
+                    match piql_server::loader::scan_file_with_options(&path, &load_options).await {
+                        Ok(scan) => core.insert_scan(name.clone(), scan).await,
+                        Err(e) => {
+                            log::error!("Failed to scan {}: {e}", path.display());
+                            continue;
+                        }
+                    }
+                } else if let Ok(df) =
+                    piql_server::loader::load_file_with_options(&path, &load_options).await
+                {
+                    core.insert_df(name.clone(), df).await;
+                } else {
+                    continue;
                 }
+                core.set_table_origin(&name, TableOrigin::file(&path)).await;
             }
         }
     }
 
+    if let Some(dir) = &args.state_dir
+        && dir.join(piql_server::snapshot::MANIFEST_FILE).exists()
+    {
+        let n = core
+            .load_state(dir)
+            .await
+            .with_context(|| format!("failed to restore state from {}", dir.display()))?;
+        log::info!("Restored {n} tables from {}", dir.display());
+    }
+
     apply_time_series_configs(&core, &args.time_series).await?;
 
-    let router = piql_server::build_router_with_docs(core);
+    if let Some(path) = &args.schedules {
+        for spec in piql_server::schedule::load_specs(path).map_err(anyhow::Error::msg)? {
+            let name = spec.name.clone();
+            core.add_schedule(spec)
+                .await
+                .with_context(|| format!("invalid schedule '{name}'"))?;
+        }
+    }
+
+    #[cfg(feature = "webhooks")]
+    if let Some(path) = &args.alerts {
+        for spec in piql_server::alert::load_specs(path).map_err(anyhow::Error::msg)? {
+            let name = spec.name.clone();
+            core.add_alert(spec)
+                .await
+                .with_context(|| format!("invalid alert '{name}'"))?;
+        }
+    }
+
+    // Stops once shutdown begins; the final save below waits for it
+    let periodic_save = match (args.state_dir.clone(), args.state_save_interval) {
+        (Some(dir), Some(secs)) => {
+            let core = core.clone();
+            Some(tokio::spawn(async move {
+                let shutdown = core.shutdown().clone();
+                let mut interval =
+                    tokio::time::interval(std::time::Duration::from_secs(secs.max(1)));
+                interval.tick().await;
+                loop {
+                    tokio::select! {
+                        _ = interval.tick() => {}
+                        _ = shutdown.closing() => break,
+                    }
+                    if let Err(e) = core.save_state(&dir).await {
+                        log::error!("Periodic state save failed: {e}");
+                    }
+                }
+            }))
+        }
+        _ => None,
+    };
 
     let addr = format!("{}:{}", args.host, args.port);
     println!("Starting server on {}", addr);
     println!("  POST /query - Execute PiQL query");
+    println!("  POST /query/batch - Execute named queries against one snapshot");
+    println!("  POST /explain - Show query plan and desugared AST");
+    println!("  POST /complete - Editor completions at a cursor");
+    println!("  POST /format - Canonical formatting of a query");
     println!("  GET  /dataframes - List available DataFrames");
+    println!("  GET  /dataframes/{{name}}/schema - Column dtypes and null counts");
+    println!("  POST /dataframes/{{name}} - Upload Arrow IPC, Parquet or CSV");
+    println!("  DELETE /dataframes/{{name}} - Unregister a DataFrame (?cascade=true)");
+    println!("  PATCH /dataframes/{{name}} - Rename a DataFrame");
+    println!("  POST /materialize - Define a materialized view");
+    println!("  GET  /capabilities - List supported PiQL methods");
+    println!("  GET  /metrics - Prometheus metrics");
+    println!("  POST /cache/clear - Drop cached query results");
+    println!("  POST /diff - Compare a query across two runs");
+    #[cfg(feature = "file-watcher")]
+    println!("  GET  /runs - Loaded runs (POST /runs/{{name}}/load, /promote; DELETE to unload)");
+    println!("  GET  /schedules - Scheduled queries (POST to add one)");
+    println!("  GET  /queries/saved?tag=&q= - Saved queries (POST to add, /{{name}}/run)");
+    println!("  GET  /templates - Query templates (POST to add one, /templates/{{name}}/run)");
+    #[cfg(feature = "webhooks")]
+    println!("  GET  /alerts - Webhook alerts (POST to add one)");
     println!("  GET  /subscribe?query=<query> - SSE subscription");
     #[cfg(feature = "llm")]
     println!("  POST /ask - Natural language query");
+    #[cfg(feature = "llm")]
+    println!("  GET  /ask/usage - Per-client /ask usage");
     println!("  GET  /swagger-ui - API documentation");
 
+    #[cfg(feature = "flight")]
+    if let Some(port) = args.flight_port {
+        let flight_addr: std::net::SocketAddr = format!("{}:{}", args.host, port)
+            .parse()
+            .context("invalid Flight address")?;
+        println!("  Arrow Flight DoGet on {flight_addr}");
+        let core = core.clone();
+        tokio::spawn(async move {
+            let shutdown = core.shutdown().clone();
+            let shutdown = async move { shutdown.closing().await };
+            if let Err(e) = piql_server::flight::serve(core, flight_addr, shutdown).await {
+                log::error!("Flight server failed: {e}");
+            }
+        });
+    }
+
     let listener = tokio::net::TcpListener::bind(&addr).await?;
-    axum::serve(listener, router).await?;
+    piql_server::serve_with_graceful_shutdown(core.clone(), listener).await?;
+
+    if let Some(task) = periodic_save {
+        core.shutdown().begin();
+        if let Err(e) = task.await {
+            log::error!("Periodic state save task failed: {e}");
+        }
+    }
+    if let Some(dir) = &args.state_dir {
+        core.save_state(dir)
+            .await
+            .with_context(|| format!("failed to save state to {}", dir.display()))?;
+        log::info!("Saved state to {}", dir.display());
+    }
 
     Ok(())
 }
 
+fn server_config(args: &Args) -> anyhow::Result<piql_server::ServerConfig> {
+    let cors = if args.no_cors {
+        piql_server::CorsOrigins::Disabled
+    } else if args.cors_origins.is_empty() {
+        piql_server::CorsOrigins::Any
+    } else {
+        piql_server::CorsOrigins::list(args.cors_origins.iter().map(String::as_str))
+            .map_err(anyhow::Error::msg)?
+    };
+    let compression = if args.no_compression {
+        piql_server::Compression::disabled()
+    } else {
+        piql_server::Compression::default()
+    };
+    Ok(piql_server::ServerConfig {
+        cors,
+        compression,
+        max_body_bytes: args.max_body_mb * 1024 * 1024,
+        batch_size: args.batch_size.max(1),
+        drain_timeout: std::time::Duration::from_secs(args.drain_timeout),
+        export_dir: args.export_dir.clone(),
+        sse_heartbeat: std::time::Duration::from_secs(args.sse_heartbeat.max(1)),
+        sse_replay_events: args.sse_replay,
+        subscriber_queue: args.subscriber_queue.max(1),
+        subscriber_lag: args.subscriber_lag,
+    })
+}
+
+fn load_options(args: &Args) -> anyhow::Result<piql_server::loader::LoadOptions> {
+    let delimiter = args
+        .csv_delimiter
+        .as_deref()
+        .map(piql_server::loader::parse_delimiter)
+        .transpose()
+        .map_err(anyhow::Error::msg)?;
+    let mut options = piql_server::loader::LoadOptions {
+        infer_schema_length: args.infer_schema_rows,
+        csv: piql_server::loader::CsvOptions {
+            delimiter,
+            has_header: args.csv_no_header.then_some(false),
+            skip_rows: args.csv_skip_rows,
+            ..Default::default()
+        },
+        categorical: args.categorical.clone(),
+        lazy: args.lazy,
+        ..Default::default()
+    };
+    for spec in &args.dtypes {
+        let (column, dtype) = spec
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("invalid --dtype '{spec}' (expected COLUMN=TYPE)"))?;
+        let dtype = piql::advanced::parse_dtype(dtype.trim())
+            .with_context(|| format!("invalid --dtype '{spec}'"))?;
+        options.dtypes.with_column(column.trim().into(), dtype);
+    }
+    for spec in &args.storage_options {
+        let (key, value) = spec
+            .split_once('=')
+            .filter(|(key, _)| !key.is_empty())
+            .ok_or_else(|| {
+                anyhow::anyhow!("invalid --storage-option '{spec}' (expected KEY=VALUE)")
+            })?;
+        options.storage.insert(key.to_string(), value.to_string());
+    }
+    for spec in &args.partition_columns {
+        let (key, column) = spec
+            .split_once('=')
+            .filter(|(key, column)| !key.is_empty() && !column.is_empty())
+            .ok_or_else(|| {
+                anyhow::anyhow!("invalid --partition-column '{spec}' (expected KEY=COLUMN)")
+            })?;
+        options
+            .partition_columns
+            .insert(key.to_string(), column.to_string());
+    }
+    Ok(options)
+}
+
+fn load_auth_config(
+    file: Option<&std::path::Path>,
+    api_keys: &[String],
+) -> anyhow::Result<Option<piql_server::AuthConfig>> {
+    if file.is_none() && api_keys.is_empty() {
+        return Ok(None);
+    }
+    let mut config = match file {
+        Some(path) => piql_server::AuthConfig::from_file(path).map_err(anyhow::Error::msg)?,
+        None => piql_server::AuthConfig::new(),
+    };
+    for spec in api_keys {
+        let (key, scope) = match spec.rsplit_once(':') {
+            Some((key, scope)) => (
+                key,
+                piql_server::Scope::parse(scope)
+                    .map_err(|e| anyhow::anyhow!("invalid --api-key spec: {e}"))?,
+            ),
+            None => (spec.as_str(), piql_server::Scope::Read),
+        };
+        if key.is_empty() {
+            anyhow::bail!("invalid --api-key spec: empty key");
+        }
+        config = config.with_api_key(key, scope);
+    }
+    Ok(Some(config))
+}
+
 async fn apply_time_series_configs(
     core: &Arc<piql_server::ServerCore>,
     specs: &[String],
 ) -> anyhow::Result<()> {
     for spec in specs {
-        let (table, tick_column, partition_key) = parse_time_series_spec(spec)?;
-        core.set_time_series_config(
-            &table,
-            TimeSeriesConfig {
-                tick_column,
-                partition_key,
-            },
-        )
-        .await
-        .with_context(|| format!("failed to register time-series config for table '{table}'"))?;
+        let (table, config) = parse_time_series_spec(spec)?;
+        core.set_time_series_config(&table, config)
+            .await
+            .with_context(|| {
+                format!("failed to register time-series config for table '{table}'")
+            })?;
         log::info!("Registered time-series config for table: {table}");
     }
     Ok(())
 }
 
-fn parse_time_series_spec(spec: &str) -> anyhow::Result<(String, String, String)> {
+fn parse_time_series_spec(spec: &str) -> anyhow::Result<(String, TimeSeriesConfig)> {
     let invalid = || anyhow::anyhow!("invalid --time-series spec '{spec}'");
-    let mut parts = spec.splitn(3, ':');
+    let mut parts = spec.splitn(4, ':');
     let table = parts.next().filter(|s| !s.is_empty()).ok_or_else(invalid)?;
     let tick_column = parts.next().filter(|s| !s.is_empty()).ok_or_else(invalid)?;
     let partition_key = parts.next().filter(|s| !s.is_empty()).ok_or_else(invalid)?;
+    let tick_dtype = match parts.next() {
+        None | Some("int") => TickDtype::Int,
+        Some("datetime") => TickDtype::Datetime,
+        Some(_) => return Err(invalid()),
+    };
     Ok((
         table.to_string(),
-        tick_column.to_string(),
-        partition_key.to_string(),
+        TimeSeriesConfig::new(tick_column, partition_key).with_tick_dtype(tick_dtype),
     ))
 }
 
 #[cfg(test)]
 mod tests {
     use super::parse_time_series_spec;
+    use piql::TickDtype;
 
     #[test]
     fn parse_time_series_spec_valid() {
-        let (table, tick, partition) = parse_time_series_spec("events:step:id").unwrap();
+        let (table, config) = parse_time_series_spec("events:step:id").unwrap();
         assert_eq!(table, "events");
-        assert_eq!(tick, "step");
-        assert_eq!(partition, "id");
+        assert_eq!(config.tick_column, "step");
+        assert_eq!(config.partition_key, "id");
+        assert_eq!(config.tick_dtype, TickDtype::Int);
+    }
+
+    #[test]
+    fn parse_time_series_spec_datetime() {
+        let (_, config) = parse_time_series_spec("logs:ts:host:datetime").unwrap();
+        assert_eq!(config.tick_column, "ts");
+        assert_eq!(config.tick_dtype, TickDtype::Datetime);
+        assert!(parse_time_series_spec("logs:ts:host:hourly").is_err());
     }
 
     #[test]
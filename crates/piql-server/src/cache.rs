@@ -0,0 +1,245 @@
+//! Query result cache
+//!
+//! Entries are keyed by the normalized query text and bound parameters, and remember
+//! the version of every table the query read, plus the tick it ran at. Table versions
+//! are bumped on every `DfUpdate` and time-series config change, so an entry is only
+//! served while all of its inputs are unchanged. When full, the least recently used
+//! entry is evicted.
+
+use std::collections::HashMap;
+
+use polars::prelude::DataFrame;
+
+pub const DEFAULT_CAPACITY: usize = 256;
+
+/// Whether a result came from the cache, reported in the `Cache-Status` header (RFC 9211)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheStatus {
+    Hit,
+    Miss,
+    /// Caching is disabled
+    Bypass,
+}
+
+impl CacheStatus {
+    pub fn header_value(self) -> &'static str {
+        match self {
+            Self::Hit => "piql; hit",
+            Self::Miss => "piql; fwd=miss",
+            Self::Bypass => "piql; fwd=bypass",
+        }
+    }
+}
+
+struct Entry {
+    tick: Option<i64>,
+    /// Tables the query read, with their versions at evaluation time
+    inputs: Vec<(String, u64)>,
+    df: DataFrame,
+    last_used: u64,
+}
+
+/// LRU cache of collected query results
+pub struct ResultCache {
+    capacity: usize,
+    entries: HashMap<String, Entry>,
+    /// Logical clock for LRU ordering
+    clock: u64,
+}
+
+impl ResultCache {
+    /// A cache holding up to `capacity` results (0 disables caching)
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            clock: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.entries.len() > capacity {
+            self.evict_lru();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Cached result for `key`, if it was computed at `tick` from the current
+    /// `versions` of its input tables. Stale entries are dropped.
+    pub fn get(
+        &mut self,
+        key: &str,
+        tick: Option<i64>,
+        versions: &HashMap<String, u64>,
+    ) -> Option<DataFrame> {
+        let entry = self.entries.get_mut(key)?;
+        let fresh = entry.tick == tick
+            && entry
+                .inputs
+                .iter()
+                .all(|(table, v)| versions.get(table).copied().unwrap_or(0) == *v);
+        if !fresh {
+            self.entries.remove(key);
+            return None;
+        }
+        self.clock += 1;
+        entry.last_used = self.clock;
+        Some(entry.df.clone())
+    }
+
+    pub fn insert(
+        &mut self,
+        key: String,
+        tick: Option<i64>,
+        inputs: Vec<(String, u64)>,
+        df: DataFrame,
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+        if !self.entries.contains_key(&key) && self.entries.len() >= self.capacity {
+            self.evict_lru();
+        }
+        self.clock += 1;
+        self.entries.insert(
+            key,
+            Entry {
+                tick,
+                inputs,
+                df,
+                last_used: self.clock,
+            },
+        );
+    }
+
+    /// Drop every entry, returning how many were removed
+    pub fn clear(&mut self) -> usize {
+        let n = self.entries.len();
+        self.entries.clear();
+        n
+    }
+
+    fn evict_lru(&mut self) {
+        if let Some(key) = self
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(key, _)| key.clone())
+        {
+            self.entries.remove(&key);
+        }
+    }
+}
+
+/// Cache key for a query and its bound parameters
+pub fn cache_key(query: &str, params: &piql::Params) -> String {
+    let mut key = normalize_query(query);
+    let mut names: Vec<&String> = params.keys().collect();
+    names.sort();
+    for name in names {
+        key.push_str(&format!("\0{name}={:?}", params[name]));
+    }
+    key
+}
+
+/// Query text trimmed, with whitespace runs outside string literals collapsed to a
+/// single space.
+pub fn normalize_query(query: &str) -> String {
+    let mut out = String::with_capacity(query.len());
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    let mut pending_space = false;
+    for c in query.trim().chars() {
+        if let Some(q) = quote {
+            out.push(c);
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == q {
+                quote = None;
+            }
+            continue;
+        }
+        if c.is_whitespace() {
+            pending_space = true;
+            continue;
+        }
+        if pending_space {
+            out.push(' ');
+            pending_space = false;
+        }
+        if c == '"' || c == '\'' {
+            quote = Some(c);
+        }
+        out.push(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::prelude::*;
+
+    fn versions(pairs: &[(&str, u64)]) -> HashMap<String, u64> {
+        pairs.iter().map(|(t, v)| (t.to_string(), *v)).collect()
+    }
+
+    #[test]
+    fn entries_expire_when_inputs_change() {
+        let df = df! { "a" => &[1] }.unwrap();
+        let mut cache = ResultCache::new(4);
+        cache.insert("q".into(), Some(1), vec![("t".into(), 3)], df);
+
+        assert!(cache.get("q", Some(1), &versions(&[("t", 3)])).is_some());
+        // Unrelated table changes don't matter
+        assert!(
+            cache
+                .get("q", Some(1), &versions(&[("t", 3), ("u", 9)]))
+                .is_some()
+        );
+        assert!(cache.get("q", Some(2), &versions(&[("t", 3)])).is_none());
+        // The stale entry was dropped
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let df = df! { "a" => &[1] }.unwrap();
+        let none = HashMap::new();
+        let mut cache = ResultCache::new(2);
+        cache.insert("a".into(), None, vec![], df.clone());
+        cache.insert("b".into(), None, vec![], df.clone());
+        cache.get("a", None, &none);
+        cache.insert("c".into(), None, vec![], df);
+
+        assert!(cache.get("b", None, &none).is_none());
+        assert!(cache.get("a", None, &none).is_some());
+        assert!(cache.get("c", None, &none).is_some());
+    }
+
+    #[test]
+    fn normalization_preserves_string_literals() {
+        assert_eq!(
+            normalize_query("  t.filter($a\n   == 'x  y')  "),
+            "t.filter($a == 'x  y')"
+        );
+        assert_ne!(
+            normalize_query(r#"t.filter($a == "x  y")"#),
+            normalize_query(r#"t.filter($a == "x y")"#)
+        );
+    }
+}
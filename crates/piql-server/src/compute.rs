@@ -0,0 +1,213 @@
+//! Dedicated compute thread pool for query evaluation
+//!
+//! Query and materialization collects run on a fixed set of OS threads rather than
+//! tokio's blocking pool, so a burst of heavy queries can't starve file loads or
+//! exports. Jobs wait in a bounded queue; when it is full new jobs are rejected
+//! (surfaced as HTTP 503) instead of piling up. Time spent queued is recorded in
+//! the `piql_compute_queue_wait_seconds` histogram.
+
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use thiserror::Error;
+use tokio::sync::oneshot;
+
+use crate::metrics::Metrics;
+
+/// Jobs that may wait for a free thread when none is given
+pub const DEFAULT_QUEUE_DEPTH: usize = 256;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Size of the compute pool
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ComputeConfig {
+    /// Threads evaluating queries (at least one is started)
+    pub threads: usize,
+    /// Jobs that may wait for a free thread before new ones are rejected
+    pub queue_depth: usize,
+}
+
+impl Default for ComputeConfig {
+    fn default() -> Self {
+        Self {
+            threads: thread::available_parallelism().map_or(4, |n| n.get()),
+            queue_depth: DEFAULT_QUEUE_DEPTH,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ComputeError {
+    #[error("compute queue is full ({0} jobs waiting)")]
+    Saturated(usize),
+    #[error("compute job panicked")]
+    Panicked,
+}
+
+impl From<ComputeError> for piql::PiqlError {
+    fn from(e: ComputeError) -> Self {
+        match e {
+            ComputeError::Saturated(depth) => {
+                piql::EvalError::ResourceLimit(piql::LimitExceeded::Saturated(depth)).into()
+            }
+            ComputeError::Panicked => piql::EvalError::Other(e.to_string()).into(),
+        }
+    }
+}
+
+/// Fixed-size thread pool fed by a bounded queue
+pub struct ComputePool {
+    config: ComputeConfig,
+    /// Dropped with the pool, which lets the workers' `recv` fail and the threads exit
+    jobs: SyncSender<Job>,
+    metrics: Arc<Metrics>,
+}
+
+impl ComputePool {
+    pub fn new(config: ComputeConfig, metrics: Arc<Metrics>) -> Self {
+        let (jobs, rx) = mpsc::sync_channel::<Job>(config.queue_depth);
+        let rx = Arc::new(Mutex::new(rx));
+        for i in 0..config.threads.max(1) {
+            let rx = rx.clone();
+            thread::Builder::new()
+                .name(format!("piql-compute-{i}"))
+                .spawn(move || worker(&rx))
+                .expect("spawn compute thread");
+        }
+        Self {
+            config,
+            jobs,
+            metrics,
+        }
+    }
+
+    pub fn config(&self) -> ComputeConfig {
+        self.config
+    }
+
+    /// Run `job` on a pool thread, failing at once with [`ComputeError::Saturated`]
+    /// when the queue is full
+    pub async fn run<T, F>(&self, job: F) -> Result<T, ComputeError>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        self.run_cancellable(|_| job()).await
+    }
+
+    /// Like [`Self::run`], but `job` is passed a check of whether the caller has
+    /// stopped waiting for its result (e.g. the request was dropped), so a long
+    /// collect can be cancelled rather than hold the thread
+    pub async fn run_cancellable<T, F>(&self, job: F) -> Result<T, ComputeError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&dyn Fn() -> bool) -> T + Send + 'static,
+    {
+        // The sender is dropped without a value only when the job panicked
+        self.submit(job)?.await.map_err(|_| ComputeError::Panicked)
+    }
+
+    /// Queue `job`, returning the receiver of its result
+    fn submit<T, F>(&self, job: F) -> Result<oneshot::Receiver<T>, ComputeError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&dyn Fn() -> bool) -> T + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let metrics = self.metrics.clone();
+        let enqueued = Instant::now();
+        let job: Job = Box::new(move || {
+            metrics.record_compute_wait(enqueued.elapsed());
+            let result = job(&|| tx.is_closed());
+            let _ = tx.send(result);
+        });
+        match self.jobs.try_send(job) {
+            Ok(()) => Ok(rx),
+            Err(TrySendError::Full(_)) => {
+                self.metrics.record_compute_rejected();
+                Err(ComputeError::Saturated(self.config.queue_depth))
+            }
+            Err(TrySendError::Disconnected(_)) => Err(ComputeError::Panicked),
+        }
+    }
+}
+
+fn worker(rx: &Mutex<Receiver<Job>>) {
+    loop {
+        let job = match rx.lock() {
+            Ok(rx) => rx.recv(),
+            Err(_) => return,
+        };
+        let Ok(job) = job else { return };
+        // A panicking query fails its own request, not the thread
+        let _ = catch_unwind(AssertUnwindSafe(job));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn full_queue_rejects_jobs_and_records_wait() {
+        let metrics = Arc::new(Metrics::new());
+        let pool = ComputePool::new(
+            ComputeConfig {
+                threads: 1,
+                queue_depth: 1,
+            },
+            metrics.clone(),
+        );
+
+        // Occupy the only thread until released, then fill the queue behind it
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+        let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+        let busy = pool
+            .submit(move |_| {
+                started_tx.send(()).unwrap();
+                release_rx.recv().unwrap();
+                1
+            })
+            .unwrap();
+        started_rx.recv().unwrap();
+        let queued = pool.submit(|_| 2).unwrap();
+
+        let Err(ComputeError::Saturated(1)) = pool.run(|| 3).await else {
+            panic!("expected a saturated pool");
+        };
+        let err = piql::PiqlError::from(ComputeError::Saturated(1));
+        assert_eq!(
+            crate::AppError::from(err).status,
+            axum::http::StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        release_tx.send(()).unwrap();
+        assert_eq!(busy.await.unwrap(), 1);
+        assert_eq!(queued.await.unwrap(), 2);
+
+        let text = metrics.render();
+        assert!(text.contains("piql_compute_queue_wait_seconds_count 2\n"));
+        assert!(text.contains("piql_compute_rejected_total 1\n"));
+    }
+
+    #[tokio::test]
+    async fn jobs_see_when_their_caller_stops_waiting() {
+        let pool = ComputePool::new(ComputeConfig::default(), Arc::new(Metrics::new()));
+        let (done_tx, done_rx) = std::sync::mpsc::channel();
+        let run = pool.run_cancellable(move |cancelled| {
+            while !cancelled() {
+                thread::sleep(std::time::Duration::from_millis(1));
+            }
+            done_tx.send(()).unwrap();
+        });
+        // Dropping the future abandons the job's result
+        let _ = tokio::time::timeout(std::time::Duration::from_millis(20), run).await;
+        done_rx
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .expect("job noticed it was cancelled");
+    }
+}
@@ -0,0 +1,159 @@
+//! HTTP-level server configuration (CORS, compression, body size, shutdown drain,
+//! export directory)
+//!
+//! Defaults are aimed at the browser dashboard use case: any origin may call the
+//! API, responses are gzip/zstd-compressed when the client accepts it, and request
+//! bodies (uploads) may be up to 64 MiB.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use axum::Router;
+use axum::extract::DefaultBodyLimit;
+use axum::http::HeaderValue;
+use tower_http::compression::CompressionLayer;
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+
+use crate::updates::LagPolicy;
+
+pub const DEFAULT_MAX_BODY_BYTES: usize = 64 * 1024 * 1024;
+pub const DEFAULT_BATCH_SIZE: usize = 64 * 1024;
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_SSE_HEARTBEAT: Duration = Duration::from_secs(15);
+pub const DEFAULT_SSE_REPLAY_EVENTS: usize = 16;
+
+/// Which origins may make cross-origin requests
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum CorsOrigins {
+    /// Any origin (`Access-Control-Allow-Origin: *`)
+    #[default]
+    Any,
+    /// Only these origins, e.g. `http://localhost:5173`
+    List(Vec<HeaderValue>),
+    /// No CORS headers; browsers block cross-origin requests
+    Disabled,
+}
+
+impl CorsOrigins {
+    /// Allow only the given origins
+    pub fn list<'a>(origins: impl IntoIterator<Item = &'a str>) -> Result<Self, String> {
+        origins
+            .into_iter()
+            .map(|o| HeaderValue::from_str(o).map_err(|_| format!("invalid CORS origin '{o}'")))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Self::List)
+    }
+}
+
+/// Response compression algorithms, negotiated via `Accept-Encoding`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compression {
+    pub gzip: bool,
+    pub zstd: bool,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self {
+            gzip: true,
+            zstd: true,
+        }
+    }
+}
+
+impl Compression {
+    pub fn disabled() -> Self {
+        Self {
+            gzip: false,
+            zstd: false,
+        }
+    }
+}
+
+/// Layers applied by `build_router`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerConfig {
+    pub cors: CorsOrigins,
+    pub compression: Compression,
+    /// Maximum request body size in bytes
+    pub max_body_bytes: usize,
+    /// Rows per record batch when streaming query results
+    pub batch_size: usize,
+    /// How long a graceful shutdown waits for in-flight requests
+    pub drain_timeout: Duration,
+    /// Directory `/export` may write files to; `None` only allows downloads
+    pub export_dir: Option<PathBuf>,
+    /// Interval of heartbeat comments on idle SSE streams, which keep proxies from
+    /// dropping them
+    pub sse_heartbeat: Duration,
+    /// Events each SSE subscription keeps for clients resuming with `Last-Event-ID`
+    /// (0 disables resuming)
+    pub sse_replay_events: usize,
+    /// Update notices queued per SSE subscriber before `subscriber_lag` applies
+    pub subscriber_queue: usize,
+    /// What a full SSE subscriber queue does with the next notice, unless the
+    /// subscription sets `lag`
+    pub subscriber_lag: LagPolicy,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            cors: CorsOrigins::default(),
+            compression: Compression::default(),
+            max_body_bytes: DEFAULT_MAX_BODY_BYTES,
+            batch_size: DEFAULT_BATCH_SIZE,
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            export_dir: None,
+            sse_heartbeat: DEFAULT_SSE_HEARTBEAT,
+            sse_replay_events: DEFAULT_SSE_REPLAY_EVENTS,
+            subscriber_queue: crate::updates::DEFAULT_QUEUE_CAPACITY,
+            subscriber_lag: LagPolicy::default(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Wrap `router` in the configured layers.
+    ///
+    /// CORS is outermost so preflight requests are answered before auth runs.
+    pub fn apply<S>(&self, router: Router<S>) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let mut router = router.layer(DefaultBodyLimit::max(self.max_body_bytes));
+
+        let Compression { gzip, zstd } = self.compression;
+        if gzip || zstd {
+            router = router.layer(CompressionLayer::new().gzip(gzip).zstd(zstd));
+        }
+
+        let cors = match &self.cors {
+            CorsOrigins::Any => Some(AllowOrigin::any()),
+            CorsOrigins::List(origins) => Some(AllowOrigin::list(origins.clone())),
+            CorsOrigins::Disabled => None,
+        };
+        if let Some(allow_origin) = cors {
+            router = router.layer(
+                CorsLayer::new()
+                    .allow_origin(allow_origin)
+                    .allow_methods(Any)
+                    .allow_headers(Any),
+            );
+        }
+
+        router
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cors_origin_list_validates_values() {
+        let cors = CorsOrigins::list(["http://localhost:5173", "https://dash.example"]).unwrap();
+        assert!(matches!(cors, CorsOrigins::List(ref o) if o.len() == 2));
+        assert!(CorsOrigins::list(["bad\norigin"]).is_err());
+    }
+}
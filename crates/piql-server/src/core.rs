@@ -1,36 +1,266 @@
 //! ServerCore - main public API for piql-server
 
+use std::collections::HashMap;
+use std::path::Path;
 use std::sync::Arc;
 
-use piql::TimeSeriesConfig;
+use piql::{RemoveMode, TimeSeriesConfig};
 use polars::prelude::*;
-use tokio::sync::broadcast;
 
-use crate::state::{DfUpdate, SharedState};
+use crate::annotate::Annotations;
+use crate::auth::AuthConfig;
+use crate::cache::CacheStatus;
+use crate::compute::ComputeConfig;
+use crate::config::ServerConfig;
+use crate::hooks::ReloadHook;
+use crate::mask::ColumnMask;
+use crate::materialize::Materialization;
+use crate::metrics::Metrics;
+use crate::policy::QueryPolicy;
+use crate::saved_queries::SavedQueries;
+use crate::schedule::{self, ScheduleError, ScheduleInfo, ScheduleSpec, Scheduler};
+use crate::shutdown::Shutdown;
+use crate::snapshot::{self, SnapshotError};
+use crate::state::{DfUpdate, ExplainResponse, SharedState, TableSchema};
+use crate::table_stats::{TableOrigin, TableStats};
+use crate::templates::Templates;
+use crate::updates::{LagPolicy, SubscriberStats, UpdateReceiver};
 
 /// Main server core providing DataFrame management and query execution
 #[derive(Clone)]
 pub struct ServerCore {
     state: Arc<SharedState>,
+    /// Credentials required by the router (None = open access)
+    auth: Option<Arc<AuthConfig>>,
+    /// CORS, compression and body-size layers applied by the router
+    config: ServerConfig,
+    /// Whether the server is shutting down, and its in-flight requests
+    shutdown: Arc<Shutdown>,
+    /// Scheduled queries and their run history
+    scheduler: Arc<Scheduler>,
+    /// Query templates run with `{{var}}` values
+    templates: Arc<Templates>,
+    /// Named queries analysts share under `/queries/saved`
+    saved_queries: Arc<SavedQueries>,
+    /// SSE subscriptions clients can resume with `Last-Event-ID`
+    subscriptions: Arc<crate::sse::Subscriptions>,
+    /// Held while saving state, so a later save can't be overtaken by an earlier one
+    saving: Arc<tokio::sync::Mutex<()>>,
+    /// Webhook alerts on query results
+    #[cfg(feature = "webhooks")]
+    alerts: Arc<crate::alert::Alerts>,
+    /// Loaded simulation runs behind `run::table`, `_all::table` and bare names
+    #[cfg(feature = "file-watcher")]
+    runs: Arc<tokio::sync::Mutex<crate::runs::RunRegistry>>,
+    /// Conversations of the `/ask` endpoint
+    #[cfg(feature = "llm")]
+    ask_sessions: Arc<crate::llm::AskSessions>,
+    /// Model answering `/ask`
+    #[cfg(feature = "llm")]
+    llm_provider: Arc<dyn crate::llm::LlmProvider>,
+    /// `/ask` usage per client and its limits
+    #[cfg(feature = "llm")]
+    ask_usage: Arc<crate::llm::AskUsage>,
+    /// Table summaries shown to the model by `/ask`
+    #[cfg(feature = "llm")]
+    ask_summaries: Arc<crate::llm::TableSummaries>,
 }
 
 impl ServerCore {
     /// Create a new ServerCore
     pub fn new() -> Self {
         let (state, _) = SharedState::new();
-        Self { state }
+        Self::from_state(state)
     }
 
     /// Create a new ServerCore with max rows limit
     pub fn with_max_rows(max_rows: Option<u32>) -> Self {
         let (state, _) = SharedState::with_max_rows(max_rows);
-        Self { state }
+        Self::from_state(state)
     }
 
     /// Create a new ServerCore and return an update receiver
-    pub fn with_update_receiver() -> (Self, broadcast::Receiver<()>) {
+    pub fn with_update_receiver() -> (Self, UpdateReceiver) {
         let (state, rx) = SharedState::new();
-        (Self { state }, rx)
+        (Self::from_state(state), rx)
+    }
+
+    fn from_state(state: Arc<SharedState>) -> Self {
+        Self {
+            state,
+            auth: None,
+            config: ServerConfig::default(),
+            shutdown: Arc::new(Shutdown::new()),
+            scheduler: Arc::new(Scheduler::default()),
+            templates: Arc::new(Templates::default()),
+            saved_queries: Arc::new(SavedQueries::default()),
+            subscriptions: Arc::new(crate::sse::Subscriptions::default()),
+            saving: Arc::new(tokio::sync::Mutex::new(())),
+            #[cfg(feature = "webhooks")]
+            alerts: Arc::new(crate::alert::Alerts::default()),
+            #[cfg(feature = "file-watcher")]
+            runs: Arc::new(tokio::sync::Mutex::new(crate::runs::RunRegistry::new())),
+            #[cfg(feature = "llm")]
+            ask_sessions: Arc::new(crate::llm::AskSessions::default()),
+            #[cfg(feature = "llm")]
+            llm_provider: crate::llm::provider_from_env().unwrap_or_else(|e| {
+                log::warn!("LLM provider config: {e}; falling back to the claude CLI");
+                Arc::new(crate::llm::ClaudeCli)
+            }),
+            #[cfg(feature = "llm")]
+            ask_usage: Arc::new(crate::llm::AskUsage::default()),
+            #[cfg(feature = "llm")]
+            ask_summaries: Arc::new(crate::llm::TableSummaries::default()),
+        }
+    }
+
+    /// Require credentials from `auth` on every endpoint of routers built from this core
+    pub fn with_auth(mut self, auth: AuthConfig) -> Self {
+        self.auth = Some(Arc::new(auth));
+        self
+    }
+
+    /// Configure the CORS, compression and body-size layers of routers built from this core
+    pub fn with_config(mut self, config: ServerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Check every query against `policy` before evaluating it; in read-only mode,
+    /// routers built from this core also reject requests that modify tables
+    pub fn with_policy(self, policy: QueryPolicy) -> Self {
+        self.state.set_policy(policy);
+        self
+    }
+
+    /// Run query and materialization collects on a pool of `config.threads` threads,
+    /// answering 503 once `config.queue_depth` collects are waiting
+    pub fn with_compute(self, config: ComputeConfig) -> Self {
+        self.state.set_compute(config);
+        self
+    }
+
+    /// Serve `library` under `/queries/saved` instead of an empty in-memory one
+    pub fn with_saved_queries(mut self, library: SavedQueries) -> Self {
+        self.saved_queries = Arc::new(library);
+        self
+    }
+
+    /// Drop `/ask` conversations after `ttl` without a question (default 30 minutes)
+    #[cfg(feature = "llm")]
+    pub fn with_ask_session_ttl(mut self, ttl: std::time::Duration) -> Self {
+        self.ask_sessions = Arc::new(crate::llm::AskSessions::new(ttl));
+        self
+    }
+
+    /// Answer `/ask` with `provider` instead of the one configured by environment
+    #[cfg(feature = "llm")]
+    pub fn with_llm_provider(mut self, provider: Arc<dyn crate::llm::LlmProvider>) -> Self {
+        self.llm_provider = provider;
+        self
+    }
+
+    /// Limit `/ask` requests and estimated tokens per client (default: unlimited)
+    #[cfg(feature = "llm")]
+    pub fn with_ask_limits(mut self, limits: crate::llm::AskLimits) -> Self {
+        self.ask_usage = Arc::new(crate::llm::AskUsage::new(limits));
+        self
+    }
+
+    /// Model answering `/ask`
+    #[cfg(feature = "llm")]
+    pub fn llm_provider(&self) -> &Arc<dyn crate::llm::LlmProvider> {
+        &self.llm_provider
+    }
+
+    /// Conversations of the `/ask` endpoint
+    #[cfg(feature = "llm")]
+    pub fn ask_sessions(&self) -> &Arc<crate::llm::AskSessions> {
+        &self.ask_sessions
+    }
+
+    /// `/ask` usage per client
+    #[cfg(feature = "llm")]
+    pub fn ask_usage(&self) -> &Arc<crate::llm::AskUsage> {
+        &self.ask_usage
+    }
+
+    /// Table summaries shown to the model by `/ask`
+    #[cfg(feature = "llm")]
+    pub fn ask_summaries(&self) -> &Arc<crate::llm::TableSummaries> {
+        &self.ask_summaries
+    }
+
+    /// HTTP layer configuration
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    /// Constructs queries may use
+    pub fn policy(&self) -> Arc<QueryPolicy> {
+        self.state.policy()
+    }
+
+    /// Graceful shutdown state; call [`Shutdown::begin`] to start shutting down
+    pub fn shutdown(&self) -> &Arc<Shutdown> {
+        &self.shutdown
+    }
+
+    /// Scheduled queries; register them with [`ServerCore::add_schedule`]
+    pub fn scheduler(&self) -> &Arc<Scheduler> {
+        &self.scheduler
+    }
+
+    /// Query templates; register them with [`templates::add`]
+    ///
+    /// [`templates::add`]: crate::templates::add
+    pub fn templates(&self) -> &Arc<Templates> {
+        &self.templates
+    }
+
+    /// The saved-query library
+    pub fn saved_queries(&self) -> &Arc<SavedQueries> {
+        &self.saved_queries
+    }
+
+    /// SSE subscriptions kept for resuming
+    pub(crate) fn subscriptions(&self) -> &Arc<crate::sse::Subscriptions> {
+        &self.subscriptions
+    }
+
+    /// Run `spec`'s query on its cron schedule, writing each result under
+    /// [`ServerConfig::export_dir`]; replaces any schedule of the same name.
+    /// Must be called within a tokio runtime.
+    pub async fn add_schedule(&self, spec: ScheduleSpec) -> Result<ScheduleInfo, ScheduleError> {
+        schedule::add(self, spec).await
+    }
+
+    /// Webhook alerts; register them with [`ServerCore::add_alert`]
+    #[cfg(feature = "webhooks")]
+    pub fn alerts(&self) -> &Arc<crate::alert::Alerts> {
+        &self.alerts
+    }
+
+    /// Re-evaluate `spec`'s query on every table update and notify its webhook when
+    /// the condition holds; replaces any alert of the same name. Must be called
+    /// within a tokio runtime.
+    #[cfg(feature = "webhooks")]
+    pub async fn add_alert(
+        &self,
+        spec: crate::alert::AlertSpec,
+    ) -> Result<crate::alert::AlertInfo, crate::alert::AlertError> {
+        crate::alert::add(self, spec).await
+    }
+
+    /// Run registry of run-aware mode, shared by the run watcher and `/runs`
+    #[cfg(feature = "file-watcher")]
+    pub fn runs(&self) -> &Arc<tokio::sync::Mutex<crate::runs::RunRegistry>> {
+        &self.runs
+    }
+
+    /// Authentication config, if enabled
+    pub fn auth(&self) -> Option<&Arc<AuthConfig>> {
+        self.auth.as_ref()
     }
 
     /// Get the underlying shared state
@@ -38,19 +268,69 @@ impl ServerCore {
         self.state.clone()
     }
 
+    /// Server metrics
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        self.state.metrics()
+    }
+
     /// Get a receiver for update notifications
-    pub fn subscribe_updates(&self) -> broadcast::Receiver<()> {
+    pub fn subscribe_updates(&self) -> UpdateReceiver {
         self.state.subscribe_updates()
     }
 
+    /// Get a receiver queueing up to `capacity` notices, handling overflow per `policy`
+    pub fn subscribe_updates_with(&self, policy: LagPolicy, capacity: usize) -> UpdateReceiver {
+        self.state.subscribe_updates_with(policy, capacity)
+    }
+
+    /// Queue depth and lag counters of every update subscriber
+    pub fn subscriber_stats(&self) -> Vec<SubscriberStats> {
+        self.state.update_bus().stats()
+    }
+
     /// Insert a DataFrame
     pub async fn insert_df(&self, name: impl Into<String>, df: DataFrame) {
         self.state.insert_df(name, df).await;
     }
 
-    /// Remove a DataFrame
-    pub async fn remove_df(&self, name: &str) {
-        self.state.remove_df(name).await;
+    /// Register a table read from `scan` by every query instead of held in memory
+    pub async fn insert_scan(&self, name: impl Into<String>, scan: LazyFrame) {
+        self.state.insert_scan(name, scan).await;
+    }
+
+    /// Read scan-backed table `name` into memory, so queries stop re-reading its
+    /// source (no-op for tables already in memory)
+    pub async fn pin_df(&self, name: &str) -> Result<(), piql::PiqlError> {
+        self.state.pin_df(name).await
+    }
+
+    /// Append rows (typically one tick's worth) to a table, creating it if needed;
+    /// subscribers are told which rows were added
+    pub async fn append_tick(&self, name: impl Into<String>, new_rows: DataFrame) {
+        self.state.append_tick(name, new_rows).await;
+    }
+
+    /// The update replacing `name` with `df`: an append of the trailing rows when
+    /// `df` extends the current table, else a reload
+    pub async fn reload_update(&self, name: String, df: DataFrame) -> DfUpdate {
+        self.state.reload_update(name, df).await
+    }
+
+    /// Remove a table, and with [`RemoveMode::Cascade`] the materialized views
+    /// downstream of it, which are returned. With [`RemoveMode::Fail`] a table that
+    /// views read is kept and an error returned.
+    pub async fn remove_df(
+        &self,
+        name: &str,
+        mode: RemoveMode,
+    ) -> Result<Vec<String>, piql::PiqlError> {
+        self.state.remove_df(name, mode).await
+    }
+
+    /// Rename a table, keeping its data and time-series config; fails if `new`
+    /// exists or a materialized view reads `old`
+    pub async fn rename_df(&self, old: &str, new: &str) -> Result<(), piql::PiqlError> {
+        self.state.rename_df(old, new).await
     }
 
     /// Apply a DfUpdate
@@ -58,6 +338,47 @@ impl ServerCore {
         self.state.apply_update(update).await;
     }
 
+    /// Register a hook that transforms `name` on every insert/reload, before queries see it.
+    ///
+    /// Only affects future loads; call before loading files.
+    pub async fn set_reload_hook(&self, name: impl Into<String>, hook: ReloadHook) {
+        self.state.set_reload_hook(name, hook).await;
+    }
+
+    /// Remove the reload hook for `name`, if any
+    pub async fn remove_reload_hook(&self, name: &str) -> Option<ReloadHook> {
+        self.state.remove_reload_hook(name).await
+    }
+
+    /// Store the result of `query` as table `name` and re-evaluate it whenever a table
+    /// it reads receives an update.
+    pub async fn materialize(&self, name: &str, query: &str) -> Result<(), piql::PiqlError> {
+        self.state.materialize(name, query).await
+    }
+
+    /// Save all tables, materialized view definitions and resumable SSE subscriptions
+    /// to a snapshot directory. Saves run one at a time.
+    pub async fn save_state(&self, dir: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        let _saving = self.saving.lock().await;
+        snapshot::save(&self.state, &self.subscriptions, dir.as_ref()).await
+    }
+
+    /// Restore a snapshot written by [`save_state`](Self::save_state); returns the number of tables restored
+    pub async fn load_state(&self, dir: impl AsRef<Path>) -> Result<usize, SnapshotError> {
+        snapshot::load(
+            &self.state,
+            &self.subscriptions,
+            self.config.sse_replay_events,
+            dir.as_ref(),
+        )
+        .await
+    }
+
+    /// Registered materialized views by name
+    pub async fn materializations(&self) -> HashMap<String, Materialization> {
+        self.state.materializations().await
+    }
+
     /// Register per-table time-series metadata for scope/sugar behavior.
     pub async fn set_time_series_config(
         &self,
@@ -67,15 +388,246 @@ impl ServerCore {
         self.state.set_time_series_config(name, config).await
     }
 
+    /// Set the current simulation tick (used by scope methods and `_tick` annotations)
+    pub async fn set_tick(&self, tick: Option<i64>) {
+        self.state.set_tick(tick).await;
+    }
+
+    /// Set the current run name reported in `_run` annotations
+    pub async fn set_current_run(&self, run: Option<String>) {
+        self.state.set_current_run(run).await;
+    }
+
+    /// Set the `_all::` run label column that `.by_run` groups over
+    pub async fn set_run_label_column(&self, column: impl Into<String>) {
+        self.state.set_run_label_column(column).await;
+    }
+
     /// List all DataFrame names
     pub async fn list_dataframes(&self) -> Vec<String> {
         self.state.list_dataframes().await
     }
 
+    /// Names of the tables a credential with column mask `mask` can query
+    pub async fn list_dataframes_as(&self, mask: Option<&ColumnMask>) -> Vec<String> {
+        self.state.list_dataframes_as(mask).await
+    }
+
     /// Execute a query and return collected DataFrame
     pub async fn execute_query(&self, query: &str) -> Result<DataFrame, piql::PiqlError> {
         self.state.execute_query(query).await
     }
+
+    /// Partition key of the table `query` reads from, used to key result diffs
+    pub async fn partition_key(&self, query: &str) -> Result<Option<String>, piql::PiqlError> {
+        self.state.partition_key(query).await
+    }
+
+    /// Compile `query` against the current tables without evaluating it
+    pub async fn compile_query(
+        &self,
+        query: &str,
+        params: &piql::Params,
+    ) -> Result<piql::CompiledQuery, piql::PiqlError> {
+        self.state.compile_query(query, params).await
+    }
+
+    /// Names of `query`'s `:name` placeholders, sorted
+    pub async fn query_params(&self, query: &str) -> Result<Vec<String>, piql::PiqlError> {
+        self.state.query_params(query).await
+    }
+
+    /// Source tables, scoped tick range and `schema` of `query`'s result
+    pub async fn result_meta(
+        &self,
+        query: &str,
+        params: &piql::Params,
+        schema: &Schema,
+    ) -> Result<piql::QueryResultMeta, piql::PiqlError> {
+        self.state.result_meta(query, params, schema).await
+    }
+
+    /// Completion suggestions for the query text before `cursor` (a byte offset).
+    /// With `mask` only the tables and columns the credential can see are suggested.
+    pub async fn complete(
+        &self,
+        query: &str,
+        cursor: usize,
+        mask: Option<&ColumnMask>,
+    ) -> Vec<piql::Completion> {
+        self.state.complete(query, cursor, mask).await
+    }
+
+    /// Column names, dtypes and null counts of a table (cached until the table changes)
+    pub async fn table_schema(&self, name: &str) -> Option<TableSchema> {
+        self.state.table_schema(name).await
+    }
+
+    /// Schemas of the tables in `names` as a credential with column mask `mask` sees
+    /// them (see [`ColumnMask::restrict`]), skipping tables it can't see. Without a
+    /// mask these are the cached [`Self::table_schema`]s.
+    pub async fn table_schemas_as(
+        &self,
+        names: &[String],
+        mask: Option<&ColumnMask>,
+    ) -> Vec<TableSchema> {
+        self.state.table_schemas_as(names, mask).await
+    }
+
+    /// Size, update time and origin of a table. With `mask` the table is measured as
+    /// the credential sees it.
+    pub async fn table_stats(&self, name: &str, mask: Option<&ColumnMask>) -> Option<TableStats> {
+        self.state.table_stats(name, mask).await
+    }
+
+    /// Record where table `name` was loaded from, reported by its stats and in `_tables`
+    pub async fn set_table_origin(&self, name: &str, origin: TableOrigin) {
+        self.state.set_table_origin(name, origin).await;
+    }
+
+    /// Return the optimized plan and desugared core AST of a query without collecting
+    /// it. With `mask` the query is planned against the tables as by
+    /// [`Self::execute_query_as`].
+    pub async fn explain_query(
+        &self,
+        query: &str,
+        mask: Option<&ColumnMask>,
+    ) -> Result<ExplainResponse, piql::PiqlError> {
+        self.state.explain_query(query, mask).await
+    }
+
+    /// Execute a query and append the requested provenance columns
+    pub async fn execute_query_annotated(
+        &self,
+        query: &str,
+        annotations: Annotations,
+    ) -> Result<DataFrame, piql::PiqlError> {
+        self.state.execute_query_annotated(query, annotations).await
+    }
+
+    /// Execute a query through the result cache, reporting whether it was a hit
+    pub async fn execute_query_cached(
+        &self,
+        query: &str,
+        annotations: Annotations,
+    ) -> Result<(DataFrame, CacheStatus), piql::PiqlError> {
+        self.state.execute_query_cached(query, annotations).await
+    }
+
+    /// Execute a query with `:name` placeholders bound to `params`, through the result cache
+    pub async fn execute_query_with_params(
+        &self,
+        query: &str,
+        params: &piql::Params,
+        annotations: Annotations,
+    ) -> Result<(DataFrame, CacheStatus), piql::PiqlError> {
+        self.state
+            .execute_query_with_params(query, params, annotations)
+            .await
+    }
+
+    /// Execute a query as a credential with column mask `mask` sees the tables (see
+    /// [`ColumnMask::restrict`]). Without a mask this is
+    /// [`Self::execute_query_with_params`]; masked queries bypass the result cache.
+    pub async fn execute_query_as(
+        &self,
+        query: &str,
+        params: &piql::Params,
+        annotations: Annotations,
+        mask: Option<&ColumnMask>,
+    ) -> Result<(DataFrame, CacheStatus), piql::PiqlError> {
+        match mask {
+            Some(mask) => self
+                .state
+                .execute_query_masked(query, params, annotations, mask)
+                .await
+                .map(|df| (df, CacheStatus::Bypass)),
+            None => {
+                self.execute_query_with_params(query, params, annotations)
+                    .await
+            }
+        }
+    }
+
+    /// Execute a query with bare table names bound to run `run`'s `{run}::table` tables
+    pub async fn execute_query_in_run(
+        &self,
+        query: &str,
+        params: &piql::Params,
+        run: &str,
+    ) -> Result<DataFrame, piql::PiqlError> {
+        self.state.execute_query_in_run(query, params, run).await
+    }
+
+    /// Execute a query bypassing the result cache, query log and metrics.
+    /// With `mask` the tables are restricted as by [`Self::execute_query_as`].
+    pub async fn execute_query_uncached(
+        &self,
+        query: &str,
+        params: &piql::Params,
+        mask: Option<&ColumnMask>,
+    ) -> Result<DataFrame, piql::PiqlError> {
+        self.state.execute_query_uncached(query, params, mask).await
+    }
+
+    /// Execute several queries against one snapshot of the tables, bypassing the cache.
+    /// With `mask` the tables are restricted as by [`Self::execute_query_as`].
+    pub async fn execute_batch(
+        &self,
+        queries: &[(String, piql::Params)],
+        parallel: bool,
+        mask: Option<&ColumnMask>,
+    ) -> Vec<Result<DataFrame, piql::PiqlError>> {
+        self.state.execute_batch(queries, parallel, mask).await
+    }
+
+    /// Execute a query with table `name` bound to only `rows`, bypassing the cache.
+    /// With `mask` the tables are restricted as by [`Self::execute_query_as`].
+    pub async fn execute_query_on_rows(
+        &self,
+        query: &str,
+        annotations: Annotations,
+        name: &str,
+        rows: DataFrame,
+        mask: Option<&ColumnMask>,
+    ) -> Result<DataFrame, piql::PiqlError> {
+        self.state
+            .execute_query_on_rows(query, annotations, name, rows, mask)
+            .await
+    }
+
+    /// Fail queries that run longer than `limits.timeout` or whose results exceed its
+    /// row or memory ceilings, with a `ResourceLimit` error (422 over HTTP)
+    pub async fn set_resource_limits(&self, limits: piql::ResourceLimits) {
+        self.state.set_resource_limits(limits).await;
+    }
+
+    /// Turn the plan-level query optimizer on (the default) or off, to debug
+    /// queries as written
+    pub async fn set_optimizer(&self, enabled: bool) {
+        self.state.set_optimizer(enabled).await;
+    }
+
+    /// Keep the string cache behind categorical columns for the rest of the process,
+    /// so casts reuse it and category codes stay stable across reloads
+    pub async fn enable_string_cache(&self) {
+        self.state.enable_string_cache().await;
+    }
+
+    /// Maximum number of cached query results (0 disables the cache)
+    pub async fn set_cache_capacity(&self, capacity: usize) {
+        self.state.set_cache_capacity(capacity).await;
+    }
+
+    /// Maximum number of entries kept in the `_queries` table (0 disables the log)
+    pub async fn set_query_log_capacity(&self, capacity: usize) {
+        self.state.set_query_log_capacity(capacity).await;
+    }
+
+    /// Drop all cached query results, returning how many were removed
+    pub async fn clear_cache(&self) -> usize {
+        self.state.clear_cache().await
+    }
 }
 
 impl Default for ServerCore {
@@ -87,9 +639,33 @@ impl Default for ServerCore {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::AppError;
+    use crate::state::UpdateNotice;
     use piql::TimeSeriesConfig;
     use polars::df;
 
+    #[tokio::test]
+    async fn prepared_queries_are_dropped_when_configs_change() {
+        let core = ServerCore::new();
+        let df = df! {
+            "id" => &[1, 1, 2, 2],
+            "step" => &[1, 2, 1, 2],
+        }
+        .unwrap();
+        core.insert_df("events", df).await;
+        core.set_cache_capacity(0).await;
+
+        // Without a tick column `.at` can't resolve; the failed compile must not stick
+        assert!(core.execute_query("events.at(2)").await.is_err());
+        core.set_time_series_config("events", TimeSeriesConfig::new("step", "id"))
+            .await
+            .unwrap();
+        assert_eq!(
+            core.execute_query("events.at(2)").await.unwrap().height(),
+            2
+        );
+    }
+
     #[tokio::test]
     async fn per_table_time_series_config_enables_scope_queries() {
         let core = ServerCore::new();
@@ -101,17 +677,456 @@ mod tests {
         .unwrap();
         core.insert_df("events", df).await;
 
-        core.set_time_series_config(
-            "events",
-            TimeSeriesConfig {
-                tick_column: "step".into(),
-                partition_key: "id".into(),
-            },
-        )
-        .await
-        .unwrap();
+        core.set_time_series_config("events", TimeSeriesConfig::new("step", "id"))
+            .await
+            .unwrap();
 
         let result = core.execute_query("events.at(2)").await.unwrap();
         assert_eq!(result.height(), 2);
     }
+
+    #[tokio::test]
+    async fn annotated_query_reports_tick_and_run() {
+        let core = ServerCore::new();
+        core.insert_df("t", df! { "a" => &[1, 2, 3] }.unwrap())
+            .await;
+        core.set_tick(Some(42)).await;
+        core.set_current_run(Some("run_a".into())).await;
+
+        let annotations = Annotations {
+            tick: true,
+            run: true,
+            ..Default::default()
+        };
+        let result = core
+            .execute_query_annotated("t", annotations)
+            .await
+            .unwrap();
+        assert_eq!(
+            result.column("_tick").unwrap().i64().unwrap().get(0),
+            Some(42)
+        );
+        assert_eq!(
+            result.column("_run").unwrap().str().unwrap().get(2),
+            Some("run_a")
+        );
+
+        let plain = core.execute_query("t").await.unwrap();
+        assert_eq!(plain.width(), 1);
+    }
+
+    #[tokio::test]
+    async fn table_schema_is_invalidated_on_update() {
+        let core = ServerCore::new();
+        core.insert_df("t", df! { "a" => &[Some(1), None, Some(3)] }.unwrap())
+            .await;
+
+        let schema = core.table_schema("t").await.unwrap();
+        assert_eq!(schema.row_count, 3);
+        assert_eq!(schema.columns[0].dtype, "i32");
+        assert_eq!(schema.columns[0].null_count, 1);
+
+        core.apply_update(DfUpdate::Reload {
+            name: "t".into(),
+            df: df! { "a" => &["x"], "b" => &[1.5] }.unwrap(),
+        })
+        .await;
+        let schema = core.table_schema("t").await.unwrap();
+        assert_eq!(schema.row_count, 1);
+        assert_eq!(schema.columns.len(), 2);
+        assert_eq!(schema.columns[0].dtype, "str");
+
+        core.remove_df("t", RemoveMode::Fail).await.unwrap();
+        assert!(core.table_schema("t").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn appended_rows_extend_tables_and_notify_subscribers() {
+        let (core, mut updates) = ServerCore::with_update_receiver();
+        core.insert_df("t", df! { "a" => &[1, 2] }.unwrap()).await;
+        assert!(matches!(updates.recv().await, Ok(UpdateNotice::Changed)));
+
+        core.append_tick("t", df! { "a" => &[3] }.unwrap()).await;
+        match updates.recv().await.unwrap() {
+            UpdateNotice::Append { name, new_rows } => {
+                assert_eq!(name, "t");
+                assert_eq!(new_rows.height(), 1);
+            }
+            UpdateNotice::Changed => panic!("expected an append notice"),
+        }
+        assert_eq!(core.execute_query("t").await.unwrap().height(), 3);
+
+        // Rows with another schema are dropped
+        core.append_tick("t", df! { "b" => &["x"] }.unwrap()).await;
+        assert_eq!(core.execute_query("t").await.unwrap().height(), 3);
+
+        let grown = df! { "a" => &[1, 2, 3, 4, 5] }.unwrap();
+        match core.reload_update("t".into(), grown).await {
+            DfUpdate::Append { new_rows, .. } => assert_eq!(new_rows.height(), 2),
+            _ => panic!("expected an append"),
+        }
+        let rewritten = df! { "a" => &[9, 2, 3, 4] }.unwrap();
+        assert!(matches!(
+            core.reload_update("t".into(), rewritten).await,
+            DfUpdate::Reload { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn cached_results_follow_table_versions() {
+        let core = ServerCore::new();
+        core.insert_df("t", df! { "a" => &[1, 2] }.unwrap()).await;
+        core.insert_df("u", df! { "b" => &[1] }.unwrap()).await;
+        let run = |q: &'static str| {
+            let core = &core;
+            async move {
+                core.execute_query_cached(q, Annotations::default())
+                    .await
+                    .unwrap()
+            }
+        };
+
+        assert_eq!(run("t").await.1, CacheStatus::Miss);
+        let (df, status) = run("  t\n").await;
+        assert_eq!((df.height(), status), (2, CacheStatus::Hit));
+
+        // Updating an unrelated table keeps the entry
+        core.insert_df("u", df! { "b" => &[2] }.unwrap()).await;
+        assert_eq!(run("t").await.1, CacheStatus::Hit);
+
+        core.insert_df("t", df! { "a" => &[1, 2, 3] }.unwrap())
+            .await;
+        let (df, status) = run("t").await;
+        assert_eq!((df.height(), status), (3, CacheStatus::Miss));
+
+        core.set_tick(Some(5)).await;
+        assert_eq!(run("t").await.1, CacheStatus::Miss);
+
+        assert_eq!(core.clear_cache().await, 1);
+        core.set_cache_capacity(0).await;
+        assert_eq!(run("t").await.1, CacheStatus::Bypass);
+    }
+
+    #[tokio::test]
+    async fn executed_queries_are_logged_to_queries_table() {
+        let core = ServerCore::new();
+        core.insert_df("t", df! { "a" => &[1, 2, 3] }.unwrap())
+            .await;
+        assert_eq!(core.execute_query("_queries").await.unwrap().height(), 0);
+
+        core.execute_query("t.filter($a > 1)").await.unwrap();
+        assert!(core.execute_query("t.missing").await.is_err());
+        let log = core
+            .execute_query(r#"_queries.select($query, $rows, $error)"#)
+            .await
+            .unwrap();
+        // The log query itself is appended only after it ran
+        assert_eq!(log.height(), 3);
+        let queries: Vec<_> = log.column("query").unwrap().str().unwrap().iter().collect();
+        assert_eq!(
+            queries,
+            [
+                Some("_queries"),
+                Some("t.filter($a > 1)"),
+                Some("t.missing")
+            ]
+        );
+        let rows: Vec<_> = log.column("rows").unwrap().u64().unwrap().iter().collect();
+        assert_eq!(rows, [Some(0), Some(2), None]);
+        assert!(log.column("error").unwrap().str().unwrap().get(2).is_some());
+
+        // The name is reserved
+        core.insert_df("_queries", df! { "a" => &[1] }.unwrap())
+            .await;
+        assert!(core.materialize("_queries", "t").await.is_err());
+        assert_eq!(core.execute_query("_queries").await.unwrap().width(), 5);
+
+        core.set_query_log_capacity(1).await;
+        assert_eq!(core.execute_query("_queries").await.unwrap().height(), 1);
+    }
+
+    #[tokio::test]
+    async fn masked_credentials_cannot_read_the_query_log() {
+        let core = ServerCore::new();
+        let users = df! { "name" => &["a"], "email" => &["ceo@corp"] }.unwrap();
+        core.insert_df("users", users).await;
+        core.execute_query(r#"users.filter($email == "ceo@corp")"#)
+            .await
+            .unwrap();
+        core.materialize("recent", "_queries.tail(10)")
+            .await
+            .unwrap();
+        let mask = ColumnMask::default().with_dropped("users", "email");
+        let run_as = |query: &'static str, mask: Option<ColumnMask>| {
+            let core = &core;
+            async move {
+                core.execute_query_as(
+                    query,
+                    &piql::Params::new(),
+                    Annotations::default(),
+                    mask.as_ref(),
+                )
+                .await
+            }
+        };
+
+        assert!(run_as("_queries", Some(mask.clone())).await.is_err());
+        assert!(run_as("recent", Some(mask.clone())).await.is_err());
+        assert!(run_as("users", Some(mask)).await.is_ok());
+        // Unmasked credentials still see it
+        assert!(run_as("_queries", None).await.unwrap().0.height() > 0);
+    }
+
+    #[tokio::test]
+    async fn masked_credentials_see_restricted_metadata() {
+        let core = ServerCore::new();
+        let users = df! { "name" => &["a"], "email" => &["a@x"] }.unwrap();
+        core.insert_df("users", users.clone()).await;
+        core.insert_df("r1::users", users).await;
+        core.materialize("contacts", "users").await.unwrap();
+        let mask = ColumnMask::default().with_dropped("users", "email");
+        let mask = Some(&mask);
+
+        let names = core.list_dataframes_as(mask).await;
+        assert!(names.contains(&"contacts".to_string()));
+        assert!(!names.contains(&"_queries".to_string()));
+        // Run versions of a table and views over it are masked too
+        let schemas = core.table_schemas_as(&names, mask).await;
+        for table in ["users", "r1::users", "contacts"] {
+            let schema = schemas.iter().find(|s| s.name == table).unwrap();
+            let columns: Vec<&str> = schema.columns.iter().map(|c| c.name.as_str()).collect();
+            assert_eq!(columns, ["name"], "{table}");
+        }
+        assert_eq!(core.table_stats("contacts", mask).await.unwrap().columns, 1);
+        assert_eq!(core.table_stats("contacts", None).await.unwrap().columns, 2);
+
+        let plan = core.explain_query("users", mask).await.unwrap().plan;
+        assert!(!plan.contains("email"), "{plan}");
+        let completions = core.complete("users.filter($", 14, mask).await;
+        assert!(completions.iter().any(|c| c.label == "name"));
+        assert!(!completions.iter().any(|c| c.label == "email"));
+    }
+
+    #[tokio::test]
+    async fn materialized_view_follows_upstream_updates() {
+        let core = ServerCore::new();
+        core.insert_df("t", df! { "a" => &[1, 2, 3] }.unwrap())
+            .await;
+        core.materialize("big", "t.filter($a > 1)").await.unwrap();
+        core.materialize("big_count", "big.count()").await.unwrap();
+        assert_eq!(core.execute_query("big").await.unwrap().height(), 2);
+
+        core.apply_update(DfUpdate::Reload {
+            name: "t".into(),
+            df: df! { "a" => &[5, 6, 7, 8] }.unwrap(),
+        })
+        .await;
+        assert_eq!(core.execute_query("big").await.unwrap().height(), 4);
+        let count = core.execute_query("big_count").await.unwrap();
+        assert_eq!(count.column("a").unwrap().u32().unwrap().get(0), Some(4));
+
+        let views = core.materializations().await;
+        assert_eq!(views["big_count"].dependencies, ["big".to_string()].into());
+
+        assert!(core.remove_df("big", RemoveMode::Fail).await.is_err());
+        let removed = core.remove_df("big", RemoveMode::Cascade).await.unwrap();
+        assert_eq!(removed, ["big_count"]);
+        assert!(core.materializations().await.is_empty());
+        assert!(
+            !core
+                .list_dataframes()
+                .await
+                .contains(&"big_count".to_string())
+        );
+        assert!(core.materialize("loop", "loop.head(1)").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn renamed_tables_keep_data_and_view_definitions() {
+        let core = ServerCore::new();
+        core.insert_df("t", df! { "a" => &[1, 2, 3] }.unwrap())
+            .await;
+        core.materialize("big", "t.filter($a > 1)").await.unwrap();
+
+        // `big`'s query names `t`
+        assert!(matches!(
+            core.rename_df("t", "u").await,
+            Err(piql::PiqlError::TableInUse { .. })
+        ));
+        assert!(matches!(
+            core.rename_df("big", "t").await,
+            Err(piql::PiqlError::TableExists(_))
+        ));
+        assert!(core.rename_df("_queries", "log").await.is_err());
+
+        core.rename_df("big", "large").await.unwrap();
+        assert!(core.execute_query("big").await.is_err());
+        assert!(core.table_schema("big").await.is_none());
+        assert!(core.materializations().await.contains_key("large"));
+        core.apply_update(DfUpdate::Reload {
+            name: "t".into(),
+            df: df! { "a" => &[5, 6, 7, 8] }.unwrap(),
+        })
+        .await;
+        assert_eq!(core.execute_query("large").await.unwrap().height(), 4);
+    }
+
+    #[tokio::test]
+    async fn materialize_rejects_dependency_cycles() {
+        let core = ServerCore::new();
+        core.insert_df("t", df! { "a" => &[1, 2, 3] }.unwrap())
+            .await;
+        core.materialize("x", "t.filter($a > 1)").await.unwrap();
+        core.materialize("y", "x.head(1)").await.unwrap();
+
+        let err = core.materialize("x", "y.head(1)").await.unwrap_err();
+        assert!(matches!(err, piql::PiqlError::DependencyCycle(ref path) if path == "x -> y -> x"));
+        assert_eq!(core.materializations().await["x"].query, "t.filter($a > 1)");
+
+        // Replacing a view with a query over its upstream table is fine
+        core.materialize("x", "t.filter($a > 2)").await.unwrap();
+        assert_eq!(core.execute_query("y").await.unwrap().height(), 1);
+    }
+
+    #[tokio::test]
+    async fn resource_limits_fail_queries_with_422() {
+        let core = ServerCore::new();
+        let ids: Vec<i64> = (0..500).collect();
+        let keys = vec![0i64; ids.len()];
+        core.insert_df("t", df! { "k" => &keys, "a" => &ids }.unwrap())
+            .await;
+        core.set_resource_limits(piql::ResourceLimits {
+            max_rows: Some(1000),
+            ..Default::default()
+        })
+        .await;
+
+        let err = core
+            .execute_query(r#"t.join(t, on="k")"#)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            crate::error::resource_limit(&err),
+            Some(&piql::LimitExceeded::Rows(1000))
+        );
+        assert_eq!(
+            AppError::from(err).status,
+            axum::http::StatusCode::UNPROCESSABLE_ENTITY
+        );
+        assert_eq!(core.execute_query("t").await.unwrap().height(), 500);
+    }
+
+    #[tokio::test]
+    async fn policy_applies_to_queries() {
+        let core = ServerCore::new().with_policy(QueryPolicy {
+            read_only: true,
+            denied_methods: ["sort".to_string()].into(),
+            max_head: Some(1),
+            ..Default::default()
+        });
+        core.insert_df("t", df! { "a" => &[1, 2, 3] }.unwrap())
+            .await;
+
+        assert!(core.policy().read_only);
+        let err = core.execute_query(r#"t.sort("a")"#).await.unwrap_err();
+        assert!(err.to_string().contains("`.sort()` is disabled"));
+        assert_eq!(core.execute_query("t.head(3)").await.unwrap().height(), 1);
+    }
+
+    #[tokio::test]
+    async fn explain_returns_plan_and_core_ast() {
+        let core = ServerCore::new();
+        core.insert_df("t", df! { "a" => &[1, 2, 3] }.unwrap())
+            .await;
+
+        let explained = core.explain_query("t.filter($a > 1)", None).await.unwrap();
+        assert!(explained.plan.contains("FILTER") || explained.plan.contains("SELECTION"));
+        assert_eq!(explained.core_ast["callee"]["name"], "filter");
+
+        assert!(core.explain_query("t.filter(", None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn complete_uses_registered_tables() {
+        let core = ServerCore::new();
+        core.insert_df("trades", df! { "price" => &[1.0] }.unwrap())
+            .await;
+
+        let tables = core.complete("tr", 2, None).await;
+        assert_eq!(tables[0].label, "trades");
+        let columns = core.complete("trades.filter($p", 16, None).await;
+        assert_eq!(columns[0].label, "price");
+        assert_eq!(columns[0].detail.as_deref(), Some("f64"));
+    }
+
+    #[tokio::test]
+    async fn reload_hook_runs_on_insert_and_reload() {
+        let core = ServerCore::new();
+        core.set_reload_hook("t", ReloadHook::query("t.filter($a > 1)"))
+            .await;
+
+        core.insert_df("t", df! { "a" => &[1, 2, 3] }.unwrap())
+            .await;
+        assert_eq!(core.execute_query("t").await.unwrap().height(), 2);
+
+        core.apply_update(DfUpdate::Reload {
+            name: "t".into(),
+            df: df! { "a" => &[0, 5] }.unwrap(),
+        })
+        .await;
+        assert_eq!(core.execute_query("t").await.unwrap().height(), 1);
+    }
+
+    #[tokio::test]
+    async fn failing_reload_hook_keeps_previous_data() {
+        let core = ServerCore::new();
+        core.insert_df("t", df! { "a" => &[1, 2, 3] }.unwrap())
+            .await;
+        core.set_reload_hook("t", ReloadHook::callback(|df| df.drop("missing")))
+            .await;
+
+        core.apply_update(DfUpdate::Reload {
+            name: "t".into(),
+            df: df! { "a" => &[9] }.unwrap(),
+        })
+        .await;
+        assert_eq!(core.execute_query("t").await.unwrap().height(), 3);
+    }
+
+    #[tokio::test]
+    async fn scan_backed_tables_take_appends_until_pinned() {
+        let path =
+            std::env::temp_dir().join(format!("piql-core-scan-{}.parquet", std::process::id()));
+        let mut df = df! { "a" => &[1i64, 2, 3] }.unwrap();
+        ParquetWriter::new(std::fs::File::create(&path).unwrap())
+            .finish(&mut df)
+            .unwrap();
+        let options = crate::loader::LoadOptions::default();
+        let scan = crate::loader::scan_file_with_options(&path, &options)
+            .await
+            .unwrap();
+
+        let core = ServerCore::new();
+        core.insert_scan("t", scan).await;
+        let stats = core.table_stats("t", None).await.unwrap();
+        assert!(stats.scan);
+        assert_eq!(stats.rows, 3);
+        assert_eq!(core.table_schema("t").await.unwrap().row_count, 3);
+
+        // Appended rows are held after the scan
+        core.append_tick("t", df! { "a" => &[4i64] }.unwrap()).await;
+        assert_eq!(core.execute_query("t").await.unwrap().height(), 4);
+
+        // Once pinned, the file is no longer read
+        core.pin_df("t").await.unwrap();
+        assert!(!core.table_stats("t", None).await.unwrap().scan);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            core.execute_query("t.filter($a > 2)")
+                .await
+                .unwrap()
+                .height(),
+            2
+        );
+    }
 }
@@ -0,0 +1,216 @@
+//! Synthetic simulation dataset for demos and tests
+//!
+//! Produces a small economy with three tables:
+//! - `locations` → static lookup table (`id`, `name`, `region`)
+//! - `entities` → one row per entity per tick (`tick`, `entity_id`, `gold`, ...)
+//! - `trades` → trades between entities (`tick`, `trade_id`, `buyer_id`, ...)
+//!
+//! Output is fully determined by the seed, so examples and tests are reproducible.
+
+use std::collections::HashMap;
+
+use piql::TimeSeriesConfig;
+use polars::prelude::*;
+
+use crate::core::ServerCore;
+
+const LOCATIONS: &[(&str, &str)] = &[
+    ("harbor", "coast"),
+    ("market", "city"),
+    ("mill", "river"),
+    ("mine", "mountain"),
+    ("farm", "plains"),
+];
+
+const ENTITY_TYPES: &[&str] = &["merchant", "producer", "consumer"];
+
+const ITEMS: &[&str] = &["grain", "ore", "timber", "cloth", "fish"];
+
+/// Small deterministic PRNG (SplitMix64) so output is stable across platforms and releases.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform integer in `[0, n)`
+    fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n.max(1)
+    }
+
+    /// Uniform float in `[0, 1)`
+    fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Generate the demo tables for `entities` entities over ticks `1..=ticks`.
+pub fn generate(
+    entities: usize,
+    ticks: i64,
+    seed: u64,
+) -> PolarsResult<HashMap<String, DataFrame>> {
+    let mut rng = SplitMix64(seed);
+
+    let locations = df! {
+        "id" => (0..LOCATIONS.len() as i64).collect::<Vec<_>>(),
+        "name" => LOCATIONS.iter().map(|(name, _)| *name).collect::<Vec<_>>(),
+        "region" => LOCATIONS.iter().map(|(_, region)| *region).collect::<Vec<_>>(),
+    }?;
+
+    // Static per-entity attributes
+    let names: Vec<String> = (0..entities).map(|i| format!("entity_{i}")).collect();
+    let types: Vec<&str> = (0..entities)
+        .map(|_| ENTITY_TYPES[rng.below(ENTITY_TYPES.len() as u64) as usize])
+        .collect();
+    let mut gold: Vec<i64> = (0..entities).map(|_| 50 + rng.below(200) as i64).collect();
+    let mut location: Vec<i64> = (0..entities)
+        .map(|_| rng.below(LOCATIONS.len() as u64) as i64)
+        .collect();
+
+    let rows = entities * ticks.max(0) as usize;
+    let mut e_tick = Vec::with_capacity(rows);
+    let mut e_id = Vec::with_capacity(rows);
+    let mut e_name = Vec::with_capacity(rows);
+    let mut e_type = Vec::with_capacity(rows);
+    let mut e_location = Vec::with_capacity(rows);
+    let mut e_gold = Vec::with_capacity(rows);
+
+    let mut t_tick = Vec::new();
+    let mut t_id = Vec::new();
+    let mut t_buyer = Vec::new();
+    let mut t_seller = Vec::new();
+    let mut t_item = Vec::new();
+    let mut t_quantity = Vec::new();
+    let mut t_price = Vec::new();
+
+    for tick in 1..=ticks {
+        // Trades move gold between entities before the tick snapshot is taken
+        if entities >= 2 {
+            let n_trades = rng.below(entities as u64 / 2 + 1);
+            for _ in 0..n_trades {
+                let buyer = rng.below(entities as u64) as usize;
+                let seller = (buyer + 1 + rng.below(entities as u64 - 1) as usize) % entities;
+                let quantity = 1 + rng.below(10) as i64;
+                let price = ((1.0 + rng.unit() * 9.0) * 100.0).round() / 100.0;
+                let cost = (quantity as f64 * price).round() as i64;
+
+                gold[buyer] -= cost;
+                gold[seller] += cost;
+
+                t_tick.push(tick);
+                t_id.push(t_id.len() as i64);
+                t_buyer.push(buyer as i64);
+                t_seller.push(seller as i64);
+                t_item.push(ITEMS[rng.below(ITEMS.len() as u64) as usize]);
+                t_quantity.push(quantity);
+                t_price.push(price);
+            }
+        }
+
+        for i in 0..entities {
+            // Producers earn a little each tick; everyone occasionally relocates
+            if types[i] == "producer" {
+                gold[i] += rng.below(5) as i64;
+            }
+            if rng.below(20) == 0 {
+                location[i] = rng.below(LOCATIONS.len() as u64) as i64;
+            }
+
+            e_tick.push(tick);
+            e_id.push(i as i64);
+            e_name.push(names[i].as_str());
+            e_type.push(types[i]);
+            e_location.push(location[i]);
+            e_gold.push(gold[i]);
+        }
+    }
+
+    let entities_df = df! {
+        "tick" => e_tick,
+        "entity_id" => e_id,
+        "name" => e_name,
+        "type" => e_type,
+        "location_id" => e_location,
+        "gold" => e_gold,
+    }?;
+
+    let trades_df = df! {
+        "tick" => t_tick,
+        "trade_id" => t_id,
+        "buyer_id" => t_buyer,
+        "seller_id" => t_seller,
+        "item" => t_item,
+        "quantity" => t_quantity,
+        "price" => t_price,
+    }?;
+
+    let mut tables = HashMap::new();
+    tables.insert("locations".to_string(), locations);
+    tables.insert("entities".to_string(), entities_df);
+    tables.insert("trades".to_string(), trades_df);
+    Ok(tables)
+}
+
+/// Time-series metadata for the generated tables.
+pub fn time_series_configs() -> Vec<(&'static str, TimeSeriesConfig)> {
+    vec![
+        ("entities", TimeSeriesConfig::new("tick", "entity_id")),
+        ("trades", TimeSeriesConfig::new("tick", "buyer_id")),
+    ]
+}
+
+/// Generate the demo tables and register them (with time-series configs) on a ServerCore.
+pub async fn load_demo(
+    core: &ServerCore,
+    entities: usize,
+    ticks: i64,
+    seed: u64,
+) -> Result<(), piql::PiqlError> {
+    let tables = generate(entities, ticks, seed).map_err(piql::EvalError::from)?;
+    for (name, df) in tables {
+        core.insert_df(name, df).await;
+    }
+    for (name, config) in time_series_configs() {
+        core.set_time_series_config(name, config).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate_is_deterministic_per_seed() {
+        let a = generate(10, 5, 42).unwrap();
+        let b = generate(10, 5, 42).unwrap();
+        for name in ["locations", "entities", "trades"] {
+            assert!(a[name].equals(&b[name]), "{name} differs for same seed");
+        }
+
+        let c = generate(10, 5, 7).unwrap();
+        assert!(!a["entities"].equals(&c["entities"]));
+    }
+
+    #[test]
+    fn generate_produces_one_entity_row_per_tick() {
+        let tables = generate(8, 4, 1).unwrap();
+        assert_eq!(tables["entities"].height(), 32);
+        assert_eq!(tables["locations"].height(), LOCATIONS.len());
+    }
+
+    #[tokio::test]
+    async fn load_demo_enables_scope_queries() {
+        let core = ServerCore::new();
+        load_demo(&core, 5, 3, 0).await.unwrap();
+
+        let result = core.execute_query("entities.at(2)").await.unwrap();
+        assert_eq!(result.height(), 5);
+    }
+}
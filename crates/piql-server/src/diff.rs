@@ -0,0 +1,321 @@
+//! Comparing a query's result across two runs
+//!
+//! `POST /diff` evaluates one query twice, with bare table names bound to each run's
+//! tables (`run_a::table`, then `run_b::table`), and full-joins the two results on
+//! key columns: the `on` columns if given, else the partition key of the table the
+//! query reads, else row position ([`ROW_COLUMN`]). Every other column appears as
+//! `{col}_a` and `{col}_b`, plus `{col}_delta` (`b - a`) when it is numeric.
+//!
+//! Schema mismatches don't fail the comparison: columns only one run has are kept
+//! with that side's suffix, and columns whose dtypes differ are compared as `f64`
+//! when both are numeric, or as strings otherwise.
+
+use std::sync::Arc;
+
+use axum::Json;
+use axum::extract::State;
+use log::info;
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use utoipa::ToSchema;
+
+use crate::core::ServerCore;
+use crate::error::AppError;
+use crate::json::dataframe_to_json_rows;
+use crate::state::{ErrorResponse, QueryRequest};
+
+/// Key column numbering rows when the results have no key
+pub const ROW_COLUMN: &str = "_row";
+
+/// JSON body of `POST /diff`
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct DiffRequest {
+    #[serde(flatten)]
+    pub query: QueryRequest,
+    pub run_a: String,
+    pub run_b: String,
+    /// Key columns matching rows of the two results (default: the partition key)
+    pub on: Option<Vec<String>>,
+}
+
+/// Result of `POST /diff`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DiffResponse {
+    pub run_a: String,
+    pub run_b: String,
+    /// Key columns the results were joined on
+    pub on: Vec<String>,
+    /// Columns only in run A's result
+    pub only_in_a: Vec<String>,
+    /// Columns only in run B's result
+    pub only_in_b: Vec<String>,
+    /// Columns whose dtypes differ between the runs
+    pub retyped: Vec<String>,
+    /// Joined rows, sorted by key; a row missing from one run has nulls on that side
+    #[schema(value_type = Vec<Object>)]
+    pub rows: Vec<serde_json::Map<String, serde_json::Value>>,
+}
+
+#[derive(Debug, Error)]
+pub enum DiffError {
+    #[error("key column '{column}' is missing from the result of run '{run}'")]
+    MissingKey { column: String, run: String },
+    #[error(transparent)]
+    Polars(#[from] PolarsError),
+}
+
+/// Two results joined on their key columns
+#[derive(Debug)]
+pub struct Comparison {
+    pub df: DataFrame,
+    pub on: Vec<String>,
+    pub only_in_a: Vec<String>,
+    pub only_in_b: Vec<String>,
+    pub retyped: Vec<String>,
+}
+
+/// Full-join `a` and `b` (the results of runs `runs[0]` and `runs[1]`) on `on`, or
+/// on row position if `on` is empty
+pub fn compare(
+    a: DataFrame,
+    b: DataFrame,
+    on: &[String],
+    runs: [&str; 2],
+) -> Result<Comparison, DiffError> {
+    let (a, b, on) = if on.is_empty() {
+        (
+            a.with_row_index(ROW_COLUMN.into(), None)?,
+            b.with_row_index(ROW_COLUMN.into(), None)?,
+            vec![ROW_COLUMN.to_string()],
+        )
+    } else {
+        (a, b, on.to_vec())
+    };
+    for column in &on {
+        for (df, run) in [(&a, runs[0]), (&b, runs[1])] {
+            if df.get_column_index(column).is_none() {
+                return Err(DiffError::MissingKey {
+                    column: column.clone(),
+                    run: run.to_string(),
+                });
+            }
+        }
+    }
+
+    let schema_a = a.schema().clone();
+    let schema_b = b.schema().clone();
+    let mut casts = Vec::new();
+    let mut retyped = Vec::new();
+    for (name, dtype_a) in schema_a.iter() {
+        let Some(dtype_b) = schema_b.get(name) else {
+            continue;
+        };
+        if dtype_a != dtype_b {
+            let common = if dtype_a.is_primitive_numeric() && dtype_b.is_primitive_numeric() {
+                DataType::Float64
+            } else {
+                DataType::String
+            };
+            casts.push(col(name.clone()).cast(common));
+            retyped.push(name.to_string());
+        }
+    }
+
+    let is_key = |name: &PlSmallStr| on.iter().any(|key| key == name.as_str());
+    let suffixed = |schema: &Schema, suffix: &str| -> Vec<Expr> {
+        let mut columns: Vec<Expr> = on.iter().map(col).collect();
+        columns.extend(
+            schema
+                .iter_names()
+                .filter(|name| !is_key(name))
+                .map(|name| col(name.clone()).alias(format!("{name}_{suffix}"))),
+        );
+        columns
+    };
+    let lazy_a = a
+        .lazy()
+        .with_columns(casts.clone())
+        .select(suffixed(&schema_a, "a"));
+    let lazy_b = b
+        .lazy()
+        .with_columns(casts)
+        .select(suffixed(&schema_b, "b"));
+    let keys: Vec<Expr> = on.iter().map(col).collect();
+    let joined = lazy_a.join(
+        lazy_b,
+        keys.clone(),
+        keys.clone(),
+        JoinArgs::new(JoinType::Full).with_coalesce(JoinCoalesce::CoalesceColumns),
+    );
+
+    let mut columns = keys.clone();
+    let mut only_in_a = Vec::new();
+    for (name, dtype_a) in schema_a.iter().filter(|(name, _)| !is_key(name)) {
+        let (a, b) = (format!("{name}_a"), format!("{name}_b"));
+        match schema_b.get(name) {
+            Some(dtype_b) => {
+                columns.push(col(&a));
+                columns.push(col(&b));
+                if dtype_a.is_primitive_numeric() && dtype_b.is_primitive_numeric() {
+                    columns.push((col(&b) - col(&a)).alias(format!("{name}_delta")));
+                }
+            }
+            None => {
+                columns.push(col(&a));
+                only_in_a.push(name.to_string());
+            }
+        }
+    }
+    let mut only_in_b = Vec::new();
+    for name in schema_b.iter_names() {
+        if !is_key(name) && !schema_a.contains(name) {
+            columns.push(col(format!("{name}_b")));
+            only_in_b.push(name.to_string());
+        }
+    }
+
+    let df = joined
+        .select(columns)
+        .sort_by_exprs(keys, SortMultipleOptions::default().with_nulls_last(true))
+        .collect()?;
+    Ok(Comparison {
+        df,
+        on,
+        only_in_a,
+        only_in_b,
+        retyped,
+    })
+}
+
+/// Compare a query's result across two runs
+///
+/// Evaluates `query` against the tables of `run_a` and of `run_b` and returns the
+/// two results joined on `on` (default: the partition key, else row position), with
+/// `{col}_a`, `{col}_b` and numeric `{col}_delta` columns.
+#[utoipa::path(
+    post,
+    path = "/diff",
+    request_body = DiffRequest,
+    responses(
+        (status = 200, description = "Joined comparison of the two results", body = DiffResponse),
+        (status = 400, description = "Unknown run, query error or missing key column", body = ErrorResponse),
+        (status = 422, description = "Query exceeded a resource limit", body = ErrorResponse)
+    )
+)]
+pub async fn diff(
+    State(core): State<Arc<ServerCore>>,
+    Json(request): Json<DiffRequest>,
+) -> Result<Json<DiffResponse>, AppError> {
+    let query = &request.query.query;
+    info!(
+        "POST /diff ({} vs {}): {}",
+        request.run_a,
+        request.run_b,
+        query.lines().next().unwrap_or(query)
+    );
+    let params = request.query.piql_params().map_err(AppError::bad_request)?;
+    let a = core
+        .execute_query_in_run(query, &params, &request.run_a)
+        .await?;
+    let b = core
+        .execute_query_in_run(query, &params, &request.run_b)
+        .await?;
+    let on = match request.on {
+        Some(on) => on,
+        None => core
+            .partition_key(query)
+            .await?
+            .filter(|key| a.schema().contains(key) && b.schema().contains(key))
+            .into_iter()
+            .collect(),
+    };
+
+    let runs = [request.run_a.as_str(), request.run_b.as_str()];
+    let comparison = compare(a, b, &on, runs).map_err(|e| AppError::bad_request(e.to_string()))?;
+    Ok(Json(DiffResponse {
+        rows: dataframe_to_json_rows(&comparison.df),
+        run_a: request.run_a,
+        run_b: request.run_b,
+        on: comparison.on,
+        only_in_a: comparison.only_in_a,
+        only_in_b: comparison.only_in_b,
+        retyped: comparison.retyped,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn query_results_are_compared_across_runs() {
+        let core = ServerCore::new();
+        core.insert_df(
+            "r1::entities",
+            df! { "id" => &[1, 2], "gold" => &[10, 20], "name" => &["a", "b"] }.unwrap(),
+        )
+        .await;
+        core.insert_df(
+            "r2::entities",
+            df! { "id" => &[2, 3], "gold" => &[25.5, 30.0], "rank" => &[1, 2] }.unwrap(),
+        )
+        .await;
+
+        let params = piql::Params::new();
+        let a = core
+            .execute_query_in_run("entities", &params, "r1")
+            .await
+            .unwrap();
+        let b = core
+            .execute_query_in_run("entities", &params, "r2")
+            .await
+            .unwrap();
+        assert!(
+            core.execute_query_in_run("entities", &params, "r3")
+                .await
+                .is_err()
+        );
+
+        let comparison = compare(a, b, &["id".to_string()], ["r1", "r2"]).unwrap();
+        assert_eq!(comparison.only_in_a, ["name"]);
+        assert_eq!(comparison.only_in_b, ["rank"]);
+        assert_eq!(comparison.retyped, ["gold"]);
+        let df = comparison.df;
+        assert_eq!(
+            df.get_column_names_str(),
+            ["id", "gold_a", "gold_b", "gold_delta", "name_a", "rank_b"]
+        );
+        assert_eq!(df.height(), 3);
+        let delta: Vec<_> = df
+            .column("gold_delta")
+            .unwrap()
+            .f64()
+            .unwrap()
+            .iter()
+            .collect();
+        assert_eq!(delta, [None, Some(5.5), None]);
+    }
+
+    #[test]
+    fn results_without_keys_are_compared_by_position() {
+        let a = df! { "x" => &[1, 2] }.unwrap();
+        let b = df! { "x" => &[1, 5, 7] }.unwrap();
+        let comparison = compare(a.clone(), b.clone(), &[], ["a", "b"]).unwrap();
+        assert_eq!(comparison.on, [ROW_COLUMN]);
+        let delta: Vec<_> = comparison
+            .df
+            .column("x_delta")
+            .unwrap()
+            .i32()
+            .unwrap()
+            .iter()
+            .collect();
+        assert_eq!(delta, [Some(0), Some(3), None]);
+
+        assert!(matches!(
+            compare(a, b, &["id".to_string()], ["a", "b"]),
+            Err(DiffError::MissingKey { .. })
+        ));
+    }
+}
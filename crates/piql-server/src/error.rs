@@ -5,17 +5,42 @@ use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use polars::prelude::PolarsError;
 
+use crate::export::ExportError;
 use crate::ipc::IpcEncodeError;
+use crate::schedule::ScheduleError;
 use crate::state::ErrorResponse;
 
 /// Application error type surfaced by handlers.
-pub struct AppError(pub String);
+pub struct AppError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl AppError {
+    /// A 400 response: the request or its query is invalid
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        AppError {
+            status: StatusCode::BAD_REQUEST,
+            message: message.into(),
+        }
+    }
+
+    /// A 403 response: the credential may not run this request
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        AppError {
+            status: StatusCode::FORBIDDEN,
+            message: message.into(),
+        }
+    }
+}
 
 impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
         (
-            StatusCode::BAD_REQUEST,
-            Json(ErrorResponse { error: self.0 }),
+            self.status,
+            Json(ErrorResponse {
+                error: self.message,
+            }),
         )
             .into_response()
     }
@@ -23,18 +48,74 @@ impl IntoResponse for AppError {
 
 impl From<piql::PiqlError> for AppError {
     fn from(e: piql::PiqlError) -> Self {
-        AppError(e.to_string())
+        let status = match resource_limit(&e) {
+            // Nothing wrong with the query; the server has no room to run it now
+            Some(piql::LimitExceeded::Saturated(_)) => StatusCode::SERVICE_UNAVAILABLE,
+            // A valid query that ran into a resource limit
+            Some(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            None => StatusCode::BAD_REQUEST,
+        };
+        AppError {
+            status,
+            message: e.to_string(),
+        }
+    }
+}
+
+/// The resource limit a query exceeded, if that is why it failed
+pub fn resource_limit(e: &piql::PiqlError) -> Option<&piql::LimitExceeded> {
+    match e {
+        piql::PiqlError::Eval(piql::EvalError::ResourceLimit(limit))
+        | piql::PiqlError::EvalWithQuery {
+            source: piql::EvalError::ResourceLimit(limit),
+            ..
+        } => Some(limit),
+        _ => None,
     }
 }
 
 impl From<PolarsError> for AppError {
     fn from(e: PolarsError) -> Self {
-        AppError(e.to_string())
+        AppError::bad_request(e.to_string())
     }
 }
 
 impl From<IpcEncodeError> for AppError {
     fn from(e: IpcEncodeError) -> Self {
-        AppError(e.to_string())
+        AppError::bad_request(e.to_string())
+    }
+}
+
+impl From<ExportError> for AppError {
+    fn from(e: ExportError) -> Self {
+        match e {
+            ExportError::Polars(_) | ExportError::Disabled(_) | ExportError::InvalidPath(_) => {
+                AppError::bad_request(e.to_string())
+            }
+            _ => AppError {
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+                message: e.to_string(),
+            },
+        }
+    }
+}
+
+impl From<ScheduleError> for AppError {
+    fn from(e: ScheduleError) -> Self {
+        AppError::bad_request(e.to_string())
+    }
+}
+
+#[cfg(feature = "file-watcher")]
+impl From<crate::runs::RunRegistryError> for AppError {
+    fn from(e: crate::runs::RunRegistryError) -> Self {
+        AppError::bad_request(e.to_string())
+    }
+}
+
+#[cfg(feature = "webhooks")]
+impl From<crate::alert::AlertError> for AppError {
+    fn from(e: crate::alert::AlertError) -> Self {
+        AppError::bad_request(e.to_string())
     }
 }
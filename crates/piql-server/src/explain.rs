@@ -0,0 +1,101 @@
+//! JSON rendering of the desugared core AST for `POST /explain`
+//!
+//! Every node is an object tagged by `type`:
+//! - `ident` → `{name}`
+//! - `literal` → `{value}`
+//! - `list` → `{items}`
+//! - `attr` → `{base, name}`
+//! - `call` → `{callee, args}`, each arg `{value}` or `{name, value}` for kwargs
+//! - `binary` → `{op, lhs, rhs}`; `unary` → `{op, operand}`
+//! - `when` → `{branches: [{condition, value}], otherwise}`
+//! - `invalid` → `{message}`
+
+use piql::advanced::{Arg, CoreArg, CoreExpr, Literal};
+use serde_json::{Value as Json, json};
+
+/// Render a core expression as a JSON tree
+pub fn core_ast_json(expr: &CoreExpr) -> Json {
+    match expr {
+        CoreExpr::Ident(name) => json!({ "type": "ident", "name": name }),
+        CoreExpr::Literal(lit) => json!({ "type": "literal", "value": literal_json(lit) }),
+        CoreExpr::List(items) => json!({
+            "type": "list",
+            "items": items.iter().map(core_ast_json).collect::<Vec<_>>(),
+        }),
+        CoreExpr::Attr(base, name) => json!({
+            "type": "attr",
+            "base": core_ast_json(base),
+            "name": name,
+        }),
+        CoreExpr::Call(callee, args) => json!({
+            "type": "call",
+            "callee": core_ast_json(callee),
+            "args": args.iter().map(arg_json).collect::<Vec<_>>(),
+        }),
+        CoreExpr::BinaryOp(lhs, op, rhs) => json!({
+            "type": "binary",
+            "op": op.to_string(),
+            "lhs": core_ast_json(lhs),
+            "rhs": core_ast_json(rhs),
+        }),
+        CoreExpr::UnaryOp(op, operand) => json!({
+            "type": "unary",
+            "op": op.to_string(),
+            "operand": core_ast_json(operand),
+        }),
+        CoreExpr::WhenThenOtherwise {
+            branches,
+            otherwise,
+        } => json!({
+            "type": "when",
+            "branches": branches
+                .iter()
+                .map(|(condition, value)| json!({
+                    "condition": core_ast_json(condition),
+                    "value": core_ast_json(value),
+                }))
+                .collect::<Vec<_>>(),
+            "otherwise": core_ast_json(otherwise),
+        }),
+        CoreExpr::Invalid(message) => json!({ "type": "invalid", "message": message }),
+    }
+}
+
+fn arg_json(arg: &CoreArg) -> Json {
+    match arg {
+        Arg::Positional(value) => json!({ "value": core_ast_json(value) }),
+        Arg::Keyword(name, value) => json!({ "name": name, "value": core_ast_json(value) }),
+    }
+}
+
+fn literal_json(lit: &Literal) -> Json {
+    match lit {
+        Literal::String(s) => json!(s),
+        Literal::Int(n) => json!(n),
+        Literal::Float(f) => json!(f),
+        Literal::Bool(b) => json!(b),
+        Literal::Null => Json::Null,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use piql::EvalContext;
+
+    #[test]
+    fn sugar_is_desugared_in_json() {
+        let compiled = piql::compile("t.filter($a > 1)", &EvalContext::new()).unwrap();
+        let ast = core_ast_json(compiled.core());
+
+        assert_eq!(ast["type"], "call");
+        assert_eq!(ast["callee"]["name"], "filter");
+        let predicate = &ast["args"][0]["value"];
+        assert_eq!(predicate["type"], "binary");
+        assert_eq!(predicate["op"], ">");
+        // `$a` becomes `pl.col("a")`
+        assert_eq!(predicate["lhs"]["callee"]["name"], "col");
+        assert_eq!(predicate["lhs"]["args"][0]["value"]["value"], "a");
+        assert_eq!(predicate["rhs"]["value"], 1);
+    }
+}
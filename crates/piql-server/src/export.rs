@@ -0,0 +1,527 @@
+//! Export query results as files: `POST /export`
+//!
+//! The result of a query is written as Parquet, CSV, SQLite or DuckDB, either to a
+//! file under [`ServerConfig::export_dir`] or streamed back as a download. SQLite
+//! and DuckDB files hold one table, [`EXPORT_TABLE`].
+//!
+//! SQLite needs the `sqlite` feature (part of `full`) and DuckDB the opt-in `duckdb`
+//! feature. Columns SQLite has no type for (dates, lists, structs, ...) are stored
+//! as text.
+//!
+//! [`ServerConfig::export_dir`]: crate::ServerConfig::export_dir
+
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use axum::Json;
+use axum::extract::{Extension, State};
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use log::info;
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use utoipa::ToSchema;
+
+use crate::annotate::Annotations;
+use crate::auth::Scope;
+use crate::core::ServerCore;
+use crate::error::AppError;
+use crate::mask::ColumnMask;
+use crate::state::{ErrorResponse, QueryRequest};
+
+/// Table holding the result in SQLite and DuckDB exports
+pub const EXPORT_TABLE: &str = "result";
+
+/// File format of an export
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Duckdb,
+    Sqlite,
+    Parquet,
+    Csv,
+}
+
+impl ExportFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Duckdb => "duckdb",
+            ExportFormat::Sqlite => "sqlite",
+            ExportFormat::Parquet => "parquet",
+            ExportFormat::Csv => "csv",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Duckdb => "application/octet-stream",
+            ExportFormat::Sqlite => "application/vnd.sqlite3",
+            ExportFormat::Parquet => "application/vnd.apache.parquet",
+            ExportFormat::Csv => "text/csv",
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ExportError {
+    #[error("{0}")]
+    Polars(#[from] PolarsError),
+    #[error("can't write export file: {0}")]
+    Io(#[from] std::io::Error),
+    #[cfg(feature = "sqlite")]
+    #[error("SQLite export failed: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[cfg(feature = "duckdb")]
+    #[error("DuckDB export failed: {0}")]
+    Duckdb(#[from] duckdb::Error),
+    #[error("format={0} requires piql-server built with the `{0}` feature")]
+    Disabled(&'static str),
+    #[error("{0}")]
+    InvalidPath(String),
+}
+
+/// JSON body of `POST /export`
+#[derive(Deserialize, ToSchema)]
+pub struct ExportRequest {
+    #[serde(flatten)]
+    pub query: QueryRequest,
+    pub format: ExportFormat,
+    /// File to write, relative to the server's export directory. Without it the file
+    /// is returned as a download.
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+/// Response of a server-side export
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ExportResponse {
+    /// File written, as given in the request
+    pub path: String,
+    pub rows: usize,
+}
+
+/// Export a query result as a file
+///
+/// Runs the query (with optional `:name` placeholder `params`) and writes the result
+/// as `format`. With `path`, the file is written under the server's export directory
+/// (`--export-dir`) and its row count returned, which needs a `write` credential and
+/// a writable server; otherwise the file is the response.
+/// SQLite and DuckDB files hold the result as a table named `result`.
+#[utoipa::path(
+    post,
+    path = "/export",
+    request_body = ExportRequest,
+    responses(
+        (status = 200, description = "Exported file (without `path`) or where it was written", content(
+            (ExportResponse = "application/json"),
+            (Vec<u8> = "application/octet-stream")
+        )),
+        (status = 400, description = "Query error, bad path, or format not built in", body = ErrorResponse),
+        (status = 403, description = "Query reads a masked column, or server-side export is disabled or not allowed", body = ErrorResponse),
+        (status = 422, description = "Query exceeded a resource limit", body = ErrorResponse)
+    )
+)]
+pub async fn export(
+    State(core): State<Arc<ServerCore>>,
+    mask: Option<Extension<ColumnMask>>,
+    scope: Option<Extension<Scope>>,
+    Json(request): Json<ExportRequest>,
+) -> Result<Response, AppError> {
+    let ExportRequest {
+        query,
+        format,
+        path,
+    } = request;
+    let bindings = query.piql_params().map_err(AppError::bad_request)?;
+    let query = query.query;
+    info!(
+        "POST /export ({}): {}",
+        format.extension(),
+        query.lines().next().unwrap_or(&query)
+    );
+
+    let target = match &path {
+        Some(path) => {
+            // Writing a file is a write, which the path-based scope check can't see
+            if core.policy().read_only {
+                return Err(AppError::forbidden("this server is read-only"));
+            }
+            if scope.is_some_and(|Extension(scope)| scope < Scope::Write) {
+                return Err(AppError::forbidden(
+                    "writing export files requires a credential with write scope",
+                ));
+            }
+            let Some(dir) = core.config().export_dir.as_deref() else {
+                return Err(AppError::forbidden(
+                    "server-side export is disabled (start the server with --export-dir)",
+                ));
+            };
+            Some(resolve_path(dir, path)?)
+        }
+        None => None,
+    };
+
+    let mask = mask.as_ref().map(|Extension(mask)| mask);
+    if let Some(mask) = mask {
+        let compiled = core.compile_query(&query, &bindings).await?;
+        mask.check(&compiled).map_err(AppError::forbidden)?;
+    }
+    let (mut df, _) = core
+        .execute_query_as(&query, &bindings, Annotations::default(), mask)
+        .await?;
+    let rows = df.height();
+
+    match (target, path) {
+        (Some(target), Some(path)) => {
+            tokio::task::spawn_blocking(move || write_file(&mut df, format, &target))
+                .await
+                .map_err(|e| AppError::bad_request(format!("export task failed: {e}")))??;
+            info!("Exported {rows} rows to {path}");
+            Ok(Json(ExportResponse { path, rows }).into_response())
+        }
+        _ => {
+            let bytes = tokio::task::spawn_blocking(move || to_bytes(&mut df, format))
+                .await
+                .map_err(|e| AppError::bad_request(format!("export task failed: {e}")))??;
+            let disposition = format!("attachment; filename=\"result.{}\"", format.extension());
+            Ok((
+                [
+                    (header::CONTENT_TYPE, format.content_type().to_string()),
+                    (header::CONTENT_DISPOSITION, disposition),
+                ],
+                bytes,
+            )
+                .into_response())
+        }
+    }
+}
+
+/// `path` under `dir`, rejecting absolute paths and `..`
+pub fn resolve_path(dir: &Path, path: &str) -> Result<PathBuf, ExportError> {
+    let relative = Path::new(path);
+    let plain = relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if !plain || relative.file_name().is_none() {
+        return Err(ExportError::InvalidPath(format!(
+            "export path '{path}' must be relative to the export directory, without '..'"
+        )));
+    }
+    Ok(dir.join(relative))
+}
+
+/// Write `df` to `path` as `format`, replacing any existing file
+pub fn write_file(
+    df: &mut DataFrame,
+    format: ExportFormat,
+    path: &Path,
+) -> Result<(), ExportError> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    match format {
+        ExportFormat::Parquet => {
+            ParquetWriter::new(std::fs::File::create(path)?).finish(df)?;
+        }
+        ExportFormat::Csv => {
+            CsvWriter::new(std::fs::File::create(path)?).finish(df)?;
+        }
+        ExportFormat::Sqlite | ExportFormat::Duckdb => {
+            // Both would add a table to an existing database
+            if path.exists() {
+                std::fs::remove_file(path)?;
+            }
+            if format == ExportFormat::Sqlite {
+                write_sqlite(df, path)?;
+            } else {
+                write_duckdb(df, path)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `df` encoded as `format`
+pub fn to_bytes(df: &mut DataFrame, format: ExportFormat) -> Result<Vec<u8>, ExportError> {
+    let mut buf = Vec::new();
+    match format {
+        ExportFormat::Parquet => {
+            ParquetWriter::new(&mut buf).finish(df)?;
+        }
+        ExportFormat::Csv => {
+            CsvWriter::new(&mut buf).finish(df)?;
+        }
+        // Database files are written through their libraries, so go via a temp file
+        ExportFormat::Sqlite | ExportFormat::Duckdb => {
+            let path = temp_path(format.extension());
+            let written = write_file(df, format, &path)
+                .and_then(|()| std::fs::read(&path).map_err(ExportError::from));
+            let _ = std::fs::remove_file(&path);
+            buf = written?;
+        }
+    }
+    Ok(buf)
+}
+
+fn temp_path(extension: &str) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    std::env::temp_dir().join(format!(
+        "piql-export-{}-{}.{extension}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+/// Quote an SQL identifier or string literal
+#[cfg(any(feature = "sqlite", feature = "duckdb"))]
+fn quote(s: &str, quote: char) -> String {
+    let escaped = s.replace(quote, &format!("{quote}{quote}"));
+    format!("{quote}{escaped}{quote}")
+}
+
+#[cfg(feature = "sqlite")]
+fn write_sqlite(df: &DataFrame, path: &Path) -> Result<(), ExportError> {
+    if df.width() == 0 {
+        return Err(PolarsError::NoData("result has no columns to export".into()).into());
+    }
+    let columns = df
+        .get_columns()
+        .iter()
+        .map(sqlite_column)
+        .collect::<PolarsResult<Vec<_>>>()?;
+    let definitions: Vec<String> = columns
+        .iter()
+        .map(|(name, sql_type, _)| format!("{} {sql_type}", quote(name, '"')))
+        .collect();
+    let placeholders = vec!["?"; columns.len()].join(", ");
+    let table = quote(EXPORT_TABLE, '"');
+
+    let mut conn = rusqlite::Connection::open(path)?;
+    let tx = conn.transaction()?;
+    tx.execute(
+        &format!("CREATE TABLE {table} ({})", definitions.join(", ")),
+        [],
+    )?;
+    {
+        let mut insert = tx.prepare(&format!("INSERT INTO {table} VALUES ({placeholders})"))?;
+        for row in 0..df.height() {
+            insert.execute(rusqlite::params_from_iter(
+                columns.iter().map(|(_, _, values)| &values[row]),
+            ))?;
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Column name, SQLite type and values of `column`
+#[cfg(feature = "sqlite")]
+fn sqlite_column(
+    column: &Column,
+) -> PolarsResult<(String, &'static str, Vec<rusqlite::types::Value>)> {
+    use rusqlite::types::Value;
+
+    let name = column.name().to_string();
+    let dtype = column.dtype();
+    Ok(if dtype.is_bool() {
+        let values = column.bool()?.iter();
+        let values = values.map(|v| v.map_or(Value::Null, |b| Value::Integer(b as i64)));
+        (name, "INTEGER", values.collect())
+    } else if dtype.is_integer() {
+        let column = column.cast(&DataType::Int64)?;
+        let values = column.i64()?.iter();
+        (
+            name,
+            "INTEGER",
+            values
+                .map(|v| v.map_or(Value::Null, Value::Integer))
+                .collect(),
+        )
+    } else if dtype.is_float() {
+        let column = column.cast(&DataType::Float64)?;
+        let values = column.f64()?.iter();
+        (
+            name,
+            "REAL",
+            values.map(|v| v.map_or(Value::Null, Value::Real)).collect(),
+        )
+    } else if dtype.is_string() {
+        let values = column.str()?.iter();
+        let values = values.map(|v| v.map_or(Value::Null, |s| Value::Text(s.to_string())));
+        (name, "TEXT", values.collect())
+    } else if matches!(dtype, DataType::Binary) {
+        let values = column.binary()?.iter();
+        let values = values.map(|v| v.map_or(Value::Null, |b| Value::Blob(b.to_vec())));
+        (name, "BLOB", values.collect())
+    } else {
+        let values = (0..column.len()).map(|i| {
+            column.get(i).map(|value| match value {
+                AnyValue::Null => Value::Null,
+                value => Value::Text(value.to_string()),
+            })
+        });
+        (name, "TEXT", values.collect::<PolarsResult<_>>()?)
+    })
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn write_sqlite(_df: &DataFrame, _path: &Path) -> Result<(), ExportError> {
+    Err(ExportError::Disabled("sqlite"))
+}
+
+/// Written via a Parquet file DuckDB reads back, which keeps every column type
+#[cfg(feature = "duckdb")]
+fn write_duckdb(df: &mut DataFrame, path: &Path) -> Result<(), ExportError> {
+    let parquet = temp_path("parquet");
+    let result = write_file(df, ExportFormat::Parquet, &parquet).and_then(|()| {
+        let conn = duckdb::Connection::open(path)?;
+        conn.execute_batch(&format!(
+            "CREATE TABLE {} AS SELECT * FROM read_parquet({})",
+            quote(EXPORT_TABLE, '"'),
+            quote(&parquet.to_string_lossy(), '\''),
+        ))?;
+        Ok(())
+    });
+    let _ = std::fs::remove_file(&parquet);
+    result
+}
+
+#[cfg(not(feature = "duckdb"))]
+fn write_duckdb(_df: &mut DataFrame, _path: &Path) -> Result<(), ExportError> {
+    Err(ExportError::Disabled("duckdb"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn df() -> DataFrame {
+        df! {
+            "name" => &["a", "b"],
+            "gold" => &[Some(1i64), None],
+            "rate" => &[0.5, 1.5],
+            "active" => &[true, false],
+        }
+        .unwrap()
+    }
+
+    #[test]
+    fn export_paths_stay_in_the_export_directory() {
+        let dir = Path::new("/exports");
+        assert_eq!(
+            resolve_path(dir, "team/result.csv").unwrap(),
+            Path::new("/exports/team/result.csv")
+        );
+        for bad in ["", ".", "/etc/passwd", "../secret.csv", "a/../../b.csv"] {
+            assert!(resolve_path(dir, bad).is_err(), "{bad}");
+        }
+    }
+
+    #[tokio::test]
+    async fn writing_files_needs_write_scope_and_a_writable_server() {
+        let dir = std::env::temp_dir().join(format!("piql-export-scope-{}", std::process::id()));
+        let config = crate::ServerConfig {
+            export_dir: Some(dir.clone()),
+            ..Default::default()
+        };
+        let core = ServerCore::new().with_config(config);
+        core.insert_df("t", df()).await;
+        let core = Arc::new(core);
+        let request = |path: Option<&str>| {
+            serde_json::from_value::<ExportRequest>(serde_json::json!({
+                "query": "t",
+                "format": "csv",
+                "path": path,
+            }))
+            .unwrap()
+        };
+        let run = |core: Arc<ServerCore>, scope: Scope, path: Option<&'static str>| async move {
+            export(
+                State(core),
+                None,
+                Some(Extension(scope)),
+                Json(request(path)),
+            )
+            .await
+            .map_err(|e| e.status)
+        };
+
+        let denied = run(core.clone(), Scope::Read, Some("t.csv")).await;
+        assert_eq!(denied.err(), Some(axum::http::StatusCode::FORBIDDEN));
+        assert!(!dir.join("t.csv").exists());
+        assert!(run(core.clone(), Scope::Read, None).await.is_ok());
+        assert!(run(core.clone(), Scope::Write, Some("t.csv")).await.is_ok());
+        assert!(dir.join("t.csv").exists());
+
+        let read_only = ServerCore::clone(&core).with_policy(crate::QueryPolicy {
+            read_only: true,
+            ..Default::default()
+        });
+        let denied = run(Arc::new(read_only), Scope::Write, Some("u.csv")).await;
+        assert_eq!(denied.err(), Some(axum::http::StatusCode::FORBIDDEN));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parquet_and_csv_round_trip() {
+        let bytes = to_bytes(&mut df(), ExportFormat::Parquet).unwrap();
+        let read = ParquetReader::new(std::io::Cursor::new(bytes))
+            .finish()
+            .unwrap();
+        assert!(read.equals_missing(&df()));
+
+        let csv = String::from_utf8(to_bytes(&mut df(), ExportFormat::Csv).unwrap()).unwrap();
+        assert_eq!(csv.lines().next(), Some("name,gold,rate,active"));
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn sqlite_export_holds_result_table() {
+        let path = temp_path("sqlite");
+        // Replaces an existing file rather than adding to it
+        std::fs::write(&path, b"stale").unwrap();
+        let mut df = df();
+        df.with_column(
+            Column::new("day".into(), &[19723i32, 19724])
+                .cast(&DataType::Date)
+                .unwrap(),
+        )
+        .unwrap();
+        write_file(&mut df, ExportFormat::Sqlite, &path).unwrap();
+
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        let rows: Vec<(String, Option<i64>, f64, bool, String)> = conn
+            .prepare("SELECT name, gold, rate, active, day FROM result ORDER BY name")
+            .unwrap()
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            })
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        drop(conn);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            rows,
+            [
+                (
+                    "a".to_string(),
+                    Some(1),
+                    0.5,
+                    true,
+                    "2024-01-01".to_string()
+                ),
+                ("b".to_string(), None, 1.5, false, "2024-01-02".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,230 @@
+//! Arrow Flight endpoint (feature `flight`)
+//!
+//! `DoGet` takes a PiQL query (UTF-8) as the ticket and streams the result as
+//! record batches of at most `batch_size` rows, encoding each batch only when the
+//! client pulls it. All other Flight RPCs return `Unimplemented`.
+//!
+//! When the core has authentication enabled, the same credentials as the HTTP API
+//! are accepted as gRPC metadata (`x-api-key` or `authorization: Bearer ...`).
+
+use std::future::Future;
+use std::io::Cursor;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use arrow_flight::encode::FlightDataEncoderBuilder;
+use arrow_flight::error::FlightError;
+use arrow_flight::flight_service_server::{FlightService, FlightServiceServer};
+use arrow_flight::{
+    Action, ActionType, Criteria, Empty, FlightData, FlightDescriptor, FlightInfo,
+    HandshakeRequest, HandshakeResponse, PollInfo, PutResult, SchemaResult, Ticket,
+};
+use arrow_ipc::reader::StreamReader;
+use axum::http::Method;
+use futures::stream::{self, BoxStream};
+use futures::{StreamExt, TryStreamExt};
+use polars::prelude::DataFrame;
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::annotate::Annotations;
+use crate::auth::AuthError;
+use crate::core::ServerCore;
+use crate::ipc::{dataframe_to_ipc_bytes, split_batches};
+use crate::mask::ColumnMask;
+
+/// Flight service answering `DoGet` with PiQL query results
+pub struct PiqlFlightService {
+    core: Arc<ServerCore>,
+    batch_size: usize,
+}
+
+impl PiqlFlightService {
+    pub fn new(core: Arc<ServerCore>) -> Self {
+        let batch_size = core.config().batch_size;
+        Self { core, batch_size }
+    }
+
+    /// Rows per record batch (defaults to the core's `ServerConfig::batch_size`)
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    pub fn into_server(self) -> FlightServiceServer<Self> {
+        FlightServiceServer::new(self)
+    }
+
+    fn authorize<T>(&self, request: &Request<T>) -> Result<Option<ColumnMask>, Status> {
+        let Some(auth) = self.core.auth() else {
+            return Ok(None);
+        };
+        let headers = request.metadata().clone().into_headers();
+        auth.authorize(&Method::GET, "/flight", &headers)
+            .map_err(|e| match e {
+                AuthError::Unauthorized => Status::unauthenticated(
+                    "missing or invalid credentials (use x-api-key or authorization: Bearer)",
+                ),
+                AuthError::Forbidden => Status::permission_denied("insufficient scope"),
+                AuthError::Masked => Status::permission_denied("column masks can't be enforced"),
+            })?;
+        Ok(auth.column_mask(&headers).cloned())
+    }
+}
+
+/// Serve Flight on `addr` until `shutdown` resolves
+pub async fn serve(
+    core: Arc<ServerCore>,
+    addr: SocketAddr,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), tonic::transport::Error> {
+    let service = PiqlFlightService::new(core);
+    tonic::transport::Server::builder()
+        .add_service(service.into_server())
+        .serve_with_shutdown(addr, shutdown)
+        .await
+}
+
+/// Re-decode a Polars IPC slice as arrow-rs record batches
+async fn to_record_batches(df: DataFrame) -> Result<Vec<arrow_array::RecordBatch>, FlightError> {
+    let bytes = dataframe_to_ipc_bytes(df)
+        .await
+        .map_err(|e| FlightError::ExternalError(Box::new(e)))?;
+    let reader = StreamReader::try_new(Cursor::new(bytes), None)?;
+    Ok(reader.collect::<Result<Vec<_>, _>>()?)
+}
+
+fn unimplemented<T>(rpc: &str) -> Result<T, Status> {
+    Err(Status::unimplemented(format!(
+        "{rpc} is not supported; use DoGet with a PiQL query ticket"
+    )))
+}
+
+#[tonic::async_trait]
+impl FlightService for PiqlFlightService {
+    type HandshakeStream = BoxStream<'static, Result<HandshakeResponse, Status>>;
+    type ListFlightsStream = BoxStream<'static, Result<FlightInfo, Status>>;
+    type DoGetStream = BoxStream<'static, Result<FlightData, Status>>;
+    type DoPutStream = BoxStream<'static, Result<PutResult, Status>>;
+    type DoActionStream = BoxStream<'static, Result<arrow_flight::Result, Status>>;
+    type ListActionsStream = BoxStream<'static, Result<ActionType, Status>>;
+    type DoExchangeStream = BoxStream<'static, Result<FlightData, Status>>;
+
+    async fn do_get(
+        &self,
+        request: Request<Ticket>,
+    ) -> Result<Response<Self::DoGetStream>, Status> {
+        let mask = self.authorize(&request)?;
+        let query = String::from_utf8(request.into_inner().ticket.to_vec())
+            .map_err(|_| Status::invalid_argument("ticket must be a UTF-8 PiQL query"))?;
+        log::info!("Flight DoGet: {}", query.lines().next().unwrap_or(&query));
+
+        let params = piql::Params::new();
+        if let Some(mask) = &mask {
+            let compiled = self
+                .core
+                .compile_query(&query, &params)
+                .await
+                .map_err(|e| Status::invalid_argument(e.to_string()))?;
+            mask.check(&compiled).map_err(Status::permission_denied)?;
+        }
+
+        let (df, _) = self
+            .core
+            .execute_query_as(&query, &params, Annotations::default(), mask.as_ref())
+            .await
+            .map_err(|e| match crate::error::resource_limit(&e) {
+                Some(piql::LimitExceeded::Saturated(_)) => Status::unavailable(e.to_string()),
+                Some(_) => Status::resource_exhausted(e.to_string()),
+                None => Status::invalid_argument(e.to_string()),
+            })?;
+
+        let batches = stream::iter(split_batches(&df, self.batch_size))
+            .then(to_record_batches)
+            .map_ok(|batches| stream::iter(batches.into_iter().map(Ok)))
+            .try_flatten();
+        let flight_data = FlightDataEncoderBuilder::new()
+            .build(batches)
+            .map_err(Status::from);
+        Ok(Response::new(flight_data.boxed()))
+    }
+
+    async fn handshake(
+        &self,
+        _request: Request<Streaming<HandshakeRequest>>,
+    ) -> Result<Response<Self::HandshakeStream>, Status> {
+        unimplemented("Handshake")
+    }
+
+    async fn list_flights(
+        &self,
+        _request: Request<Criteria>,
+    ) -> Result<Response<Self::ListFlightsStream>, Status> {
+        unimplemented("ListFlights")
+    }
+
+    async fn get_flight_info(
+        &self,
+        _request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+        unimplemented("GetFlightInfo")
+    }
+
+    async fn poll_flight_info(
+        &self,
+        _request: Request<FlightDescriptor>,
+    ) -> Result<Response<PollInfo>, Status> {
+        unimplemented("PollFlightInfo")
+    }
+
+    async fn get_schema(
+        &self,
+        _request: Request<FlightDescriptor>,
+    ) -> Result<Response<SchemaResult>, Status> {
+        unimplemented("GetSchema")
+    }
+
+    async fn do_put(
+        &self,
+        _request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoPutStream>, Status> {
+        unimplemented("DoPut")
+    }
+
+    async fn do_action(
+        &self,
+        _request: Request<Action>,
+    ) -> Result<Response<Self::DoActionStream>, Status> {
+        unimplemented("DoAction")
+    }
+
+    async fn list_actions(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<Self::ListActionsStream>, Status> {
+        unimplemented("ListActions")
+    }
+
+    async fn do_exchange(
+        &self,
+        _request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoExchangeStream>, Status> {
+        unimplemented("DoExchange")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn slices_decode_as_record_batches() {
+        let df = polars::prelude::df! { "a" => &[1i64, 2, 3], "b" => &["x", "y", "z"] }.unwrap();
+        let mut rows = 0;
+        for slice in split_batches(&df, 2) {
+            let batches = to_record_batches(slice).await.unwrap();
+            rows += batches.iter().map(|b| b.num_rows()).sum::<usize>();
+            assert_eq!(batches[0].num_columns(), 2);
+        }
+        assert_eq!(rows, 3);
+    }
+}
@@ -0,0 +1,86 @@
+//! Per-table reload hooks
+//!
+//! A hook post-processes a table every time it is inserted or reloaded, before it
+//! becomes visible to queries. Use it to keep normalization (casts, derived tick
+//! columns, dropping corrupt rows) on the server instead of in every client query.
+
+use std::fmt;
+use std::sync::Arc;
+
+use piql::{EvalContext, PiqlError, Value};
+use polars::prelude::*;
+
+type HookFn = dyn Fn(DataFrame) -> PolarsResult<DataFrame> + Send + Sync;
+
+/// Transform applied to a table on every load/reload
+#[derive(Clone)]
+pub enum ReloadHook {
+    /// Rust callback receiving the freshly loaded frame
+    Callback(Arc<HookFn>),
+    /// PiQL query evaluated with the freshly loaded frame bound to the table's name,
+    /// e.g. `events.filter($value.is_not_null())` for table `events`
+    Query(String),
+}
+
+impl ReloadHook {
+    /// Hook from a Rust callback
+    pub fn callback(
+        f: impl Fn(DataFrame) -> PolarsResult<DataFrame> + Send + Sync + 'static,
+    ) -> Self {
+        Self::Callback(Arc::new(f))
+    }
+
+    /// Hook from a PiQL transform query
+    pub fn query(query: impl Into<String>) -> Self {
+        Self::Query(query.into())
+    }
+
+    /// Apply the hook to a freshly loaded `df` registered as `name`.
+    pub fn apply(&self, name: &str, df: DataFrame) -> Result<DataFrame, PiqlError> {
+        match self {
+            Self::Callback(f) => f(df).map_err(|e| piql::EvalError::from(e).into()),
+            Self::Query(query) => {
+                let ctx = EvalContext::new().with_materialized_df(name, df);
+                match piql::run(query, &ctx)? {
+                    Value::DataFrame(lf, _) => {
+                        lf.collect().map_err(|e| piql::EvalError::from(e).into())
+                    }
+                    _ => Err(piql::EvalError::TypeError {
+                        expected: "DataFrame".to_string(),
+                        got: "other value".to_string(),
+                    }
+                    .into()),
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Debug for ReloadHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Callback(_) => f.write_str("Callback(..)"),
+            Self::Query(q) => f.debug_tuple("Query").field(q).finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_hook_sees_table_by_name() {
+        let df = df! { "a" => &[1, 2, 3] }.unwrap();
+        let hook = ReloadHook::query("events.filter($a > 1)");
+        let out = hook.apply("events", df).unwrap();
+        assert_eq!(out.height(), 2);
+    }
+
+    #[test]
+    fn callback_hook_errors_propagate() {
+        let df = df! { "a" => &[1] }.unwrap();
+        let hook = ReloadHook::callback(|df| df.drop("missing"));
+        assert!(hook.apply("t", df).is_err());
+    }
+}
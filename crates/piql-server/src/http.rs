@@ -4,66 +4,627 @@ use std::sync::Arc;
 use std::time::Instant;
 
 use axum::Json;
-use axum::extract::State;
-use axum::http::header;
+use axum::body::{Body, Bytes};
+use axum::extract::{Extension, Path, Query, State};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode, header};
 use axum::response::IntoResponse;
 use log::{debug, info, warn};
+use piql::RemoveMode;
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use crate::annotate::Annotations;
 
 use crate::core::ServerCore;
 use crate::error::AppError;
-use crate::ipc::dataframe_to_ipc_bytes;
-use crate::state::{DataframesResponse, ErrorResponse};
+use crate::ipc::dataframe_to_ipc_stream;
+use crate::json::dataframe_to_compact_json;
+use crate::loader::{self, DataFormat};
+use crate::mask::ColumnMask;
+use crate::materialize;
+use crate::state::{
+    CacheClearResponse, CapabilitiesResponse, CompleteRequest, CompleteResponse,
+    DataframesResponse, ErrorResponse, ExplainResponse, FormatRequest, FormatResponse,
+    MaterializationNode, MaterializationsResponse, MaterializeRequest, QueryRequest, RenameRequest,
+    TableSchema,
+};
+use crate::table_stats::{self, TableStats};
+
+const CACHE_STATUS: HeaderName = HeaderName::from_static("cache-status");
+const LINEAGE: HeaderName = HeaderName::from_static("x-piql-lineage");
+
+#[derive(Deserialize, IntoParams)]
+pub struct QueryParams {
+    /// Provenance columns to append: comma-separated `tick`, `run`, `generated_at`, `query_hash`, or `all`
+    pub annotate: Option<String>,
+    /// Rows per streamed record batch (default: server `--batch-size`)
+    pub batch_size: Option<usize>,
+    /// Query language of the body (default: `piql`)
+    #[serde(default)]
+    pub dialect: Dialect,
+}
+
+/// Query language accepted by `/query`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Dialect {
+    #[default]
+    Piql,
+    /// `SELECT ... FROM ...`, translated to PiQL (requires the `sql` feature)
+    Sql,
+}
+
+impl Dialect {
+    /// The query as PiQL text
+    fn to_piql(self, query: String) -> Result<String, AppError> {
+        match self {
+            Dialect::Piql => Ok(query),
+            #[cfg(feature = "sql")]
+            Dialect::Sql => Ok(piql::sql_to_piql(&query)?),
+            #[cfg(not(feature = "sql"))]
+            Dialect::Sql => Err(AppError::bad_request(
+                "dialect=sql requires piql-server built with the `sql` feature".to_string(),
+            )),
+        }
+    }
+}
 
 /// Execute a piql query
+///
+/// The body is either the query text, or (with `Content-Type: application/json`) a
+/// `{query, params}` object binding `:name` placeholders in the query.
+/// With `dialect=sql` the query is SQL (`SELECT ... FROM table ...`) instead of PiQL.
+/// The result is streamed as a chunked Arrow IPC stream, one record batch at a time.
+/// With `Accept: application/json` the result is compact JSON instead: `{"value": v}`
+/// for a single cell, `{"name", "values"}` for a single column, else `{"rows"}`.
+/// `X-Piql-Lineage` holds JSON metadata: the tables the query reads, the tick range
+/// its scope methods select, and the output schema.
+/// Columns masked for the caller's credential are dropped or redacted, and queries
+/// mentioning them are rejected.
 #[utoipa::path(
     post,
     path = "/query",
-    request_body(content = String, content_type = "text/plain", description = "PiQL query string"),
+    params(QueryParams),
+    request_body(
+        content(
+            (String = "text/plain"),
+            (QueryRequest = "application/json")
+        ),
+        description = "PiQL query string, or query plus placeholder values"
+    ),
     responses(
-        (status = 200, description = "Arrow IPC stream", content_type = "application/vnd.apache.arrow.stream"),
-        (status = 400, description = "Query error", body = ErrorResponse)
+        (status = 200, description = "Arrow IPC stream, or compact JSON with `Accept: application/json`",
+            content_type = "application/vnd.apache.arrow.stream",
+            headers(
+                ("Cache-Status" = String, description = "`piql; hit`, `piql; fwd=miss` or `piql; fwd=bypass`"),
+                ("X-Piql-Lineage" = String, description = "JSON `{sources, ticks: {from, to}, schema}` describing the result")
+            )),
+        (status = 400, description = "Query error", body = ErrorResponse),
+        (status = 403, description = "Query reads a column masked for this credential", body = ErrorResponse),
+        (status = 422, description = "Query exceeded a resource limit", body = ErrorResponse)
     )
 )]
 pub async fn query(
     State(core): State<Arc<ServerCore>>,
+    mask: Option<Extension<ColumnMask>>,
+    Query(params): Query<QueryParams>,
+    headers: HeaderMap,
     body: String,
 ) -> Result<impl IntoResponse, AppError> {
+    let is_json = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+    let (query, bindings) = if is_json {
+        let request: QueryRequest = serde_json::from_str(&body)
+            .map_err(|e| AppError::bad_request(format!("invalid query request: {e}")))?;
+        let bindings = request.piql_params().map_err(AppError::bad_request)?;
+        (request.query, bindings)
+    } else {
+        (body, piql::Params::new())
+    };
+    let query = params.dialect.to_piql(query)?;
+    info!("POST /query: {}", query.lines().next().unwrap_or(&query));
+    debug!("Full query: {} (params: {:?})", query, bindings);
+
+    let annotations = match params.annotate.as_deref() {
+        Some(spec) => Annotations::parse(spec).map_err(AppError::bad_request)?,
+        None => Annotations::default(),
+    };
+
+    let batch_size = params.batch_size.unwrap_or(core.config().batch_size);
+    respond_with_query(
+        &core,
+        mask.as_ref().map(|Extension(mask)| mask),
+        &query,
+        &bindings,
+        annotations,
+        &headers,
+        batch_size,
+    )
+    .await
+}
+
+/// Run `query` and answer with its result as `/query` does: an Arrow IPC stream of
+/// `batch_size`-row batches, or compact JSON with `Accept: application/json`, with
+/// `Cache-Status` and `X-Piql-Lineage` headers. The query sees the tables with the
+/// columns masked by `mask` dropped or redacted, and is rejected if it mentions them.
+pub(crate) async fn respond_with_query(
+    core: &ServerCore,
+    mask: Option<&ColumnMask>,
+    query: &str,
+    bindings: &piql::Params,
+    annotations: Annotations,
+    headers: &HeaderMap,
+    batch_size: usize,
+) -> Result<axum::response::Response, AppError> {
     let start = Instant::now();
-    info!("POST /query: {}", body.lines().next().unwrap_or(&body));
-    debug!("Full query: {}", body);
+    if let Some(mask) = mask {
+        let compiled = core.compile_query(query, bindings).await?;
+        mask.check(&compiled).map_err(AppError::forbidden)?;
+    }
 
-    let df = match core.execute_query(&body).await {
-        Ok(df) => df,
+    let (df, cache_status) = match core
+        .execute_query_as(query, bindings, annotations, mask)
+        .await
+    {
+        Ok(result) => result,
         Err(e) => {
             warn!("Query failed in {:.2?}: {}", start.elapsed(), e);
             return Err(e.into());
         }
     };
 
-    let buf = dataframe_to_ipc_bytes(df).await?;
-
     info!(
-        "Query succeeded in {:.2?}, {} bytes",
+        "Query succeeded in {:.2?}, {} rows ({:?})",
         start.elapsed(),
-        buf.len()
+        df.height(),
+        cache_status
     );
+    // Metadata is best-effort; the result is sent without it if it can't be computed
+    let lineage = core
+        .result_meta(query, bindings, df.schema())
+        .await
+        .ok()
+        .and_then(|meta| serde_json::to_string(&meta).ok())
+        .and_then(|json| HeaderValue::from_str(&ascii_json(&json)).ok())
+        .map(|value| [(LINEAGE, value)]);
+    let wants_json = headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("application/json"));
+    if wants_json {
+        return Ok((
+            [(CACHE_STATUS, cache_status.header_value())],
+            lineage,
+            Json(dataframe_to_compact_json(&df)),
+        )
+            .into_response());
+    }
+    let batches = dataframe_to_ipc_stream(df, batch_size).await?;
     Ok((
-        [(header::CONTENT_TYPE, "application/vnd.apache.arrow.stream")],
-        buf,
-    ))
+        [
+            (header::CONTENT_TYPE, "application/vnd.apache.arrow.stream"),
+            (CACHE_STATUS, cache_status.header_value()),
+        ],
+        lineage,
+        Body::from_stream(batches),
+    )
+        .into_response())
+}
+
+/// Escape non-ASCII characters in JSON text as `\uXXXX`, so it fits in a header
+fn ascii_json(json: &str) -> String {
+    let mut out = String::with_capacity(json.len());
+    for c in json.chars() {
+        if c.is_ascii() {
+            out.push(c);
+        } else {
+            for unit in c.encode_utf16(&mut [0; 2]) {
+                out.push_str(&format!("\\u{unit:04x}"));
+            }
+        }
+    }
+    out
+}
+
+/// Explain a piql query without executing it
+///
+/// Returns the optimized Polars plan and the desugared core AST. Columns masked for
+/// the caller's credential are left out of the plan, as from query results.
+#[utoipa::path(
+    post,
+    path = "/explain",
+    request_body(content = String, content_type = "text/plain", description = "PiQL query string"),
+    responses(
+        (status = 200, description = "Query plan and core AST", body = ExplainResponse),
+        (status = 400, description = "Query error", body = ErrorResponse),
+        (status = 403, description = "Query mentions a column masked for this credential", body = ErrorResponse)
+    )
+)]
+pub async fn explain(
+    State(core): State<Arc<ServerCore>>,
+    mask: Option<Extension<ColumnMask>>,
+    body: String,
+) -> Result<Json<ExplainResponse>, AppError> {
+    info!("POST /explain: {}", body.lines().next().unwrap_or(&body));
+    let mask = mask.as_ref().map(|Extension(mask)| mask);
+    if let Some(mask) = mask {
+        let compiled = core.compile_query(&body, &piql::Params::new()).await?;
+        mask.check(&compiled).map_err(AppError::forbidden)?;
+    }
+    Ok(Json(core.explain_query(&body, mask).await?))
+}
+
+/// Format a query in canonical form
+///
+/// Invalid queries return every parse error found, with positions, instead of a
+/// formatted query.
+#[utoipa::path(
+    post,
+    path = "/format",
+    request_body = FormatRequest,
+    responses(
+        (status = 200, description = "Formatted query or parse errors", body = FormatResponse)
+    )
+)]
+pub async fn format(Json(request): Json<FormatRequest>) -> Json<FormatResponse> {
+    debug!("POST /format: {}", request.query);
+    let width = request.width.unwrap_or(80);
+    let response = match piql::format(&request.query, width) {
+        Ok(formatted) => FormatResponse {
+            formatted: Some(formatted),
+            errors: Vec::new(),
+        },
+        Err(_) => FormatResponse {
+            formatted: None,
+            errors: piql::advanced::parse_recovering(&request.query)
+                .diagnostics
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+        },
+    };
+    Json(response)
+}
+
+/// Completion suggestions for a query editor
+///
+/// Table names at the start of a query, columns after `$` or inside `pl.col("`,
+/// receiver methods after `.`, directives after `@`. Columns masked for the caller's
+/// credential aren't suggested.
+#[utoipa::path(
+    post,
+    path = "/complete",
+    request_body = CompleteRequest,
+    responses(
+        (status = 200, description = "Ranked suggestions", body = CompleteResponse)
+    )
+)]
+pub async fn complete(
+    State(core): State<Arc<ServerCore>>,
+    mask: Option<Extension<ColumnMask>>,
+    Json(request): Json<CompleteRequest>,
+) -> Json<CompleteResponse> {
+    let cursor = request.cursor.unwrap_or(request.query.len());
+    debug!("POST /complete at {cursor}: {}", request.query);
+    let mask = mask.as_ref().map(|Extension(mask)| mask);
+    let completions = core
+        .complete(&request.query, cursor, mask)
+        .await
+        .into_iter()
+        .map(Into::into)
+        .collect();
+    Json(CompleteResponse { completions })
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct DataframesParams {
+    /// Embed each table's schema in the response
+    #[serde(default)]
+    pub schemas: bool,
 }
 
 /// List available DataFrames
 #[utoipa::path(
     get,
     path = "/dataframes",
+    params(DataframesParams),
     responses(
         (status = 200, description = "List of available dataframe names", body = DataframesResponse)
     )
 )]
-pub async fn list_dataframes(State(core): State<Arc<ServerCore>>) -> Json<DataframesResponse> {
+pub async fn list_dataframes(
+    State(core): State<Arc<ServerCore>>,
+    mask: Option<Extension<ColumnMask>>,
+    Query(params): Query<DataframesParams>,
+) -> Json<DataframesResponse> {
     info!("GET /dataframes");
-    let names = core.list_dataframes().await;
+    let mask = mask.as_ref().map(|Extension(mask)| mask);
+    let names = core.list_dataframes_as(mask).await;
     debug!("Available dataframes: {:?}", names);
-    Json(DataframesResponse { names })
+    let schemas = if params.schemas {
+        // Tables removed since listing are skipped
+        Some(core.table_schemas_as(&names, mask).await)
+    } else {
+        None
+    };
+    Json(DataframesResponse { names, schemas })
+}
+
+/// Get column names, dtypes, null counts and row count of a DataFrame
+#[utoipa::path(
+    get,
+    path = "/dataframes/{name}/schema",
+    params(("name" = String, Path, description = "DataFrame name")),
+    responses(
+        (status = 200, description = "Table schema", body = TableSchema),
+        (status = 400, description = "Unknown DataFrame", body = ErrorResponse)
+    )
+)]
+pub async fn dataframe_schema(
+    State(core): State<Arc<ServerCore>>,
+    mask: Option<Extension<ColumnMask>>,
+    Path(name): Path<String>,
+) -> Result<Json<TableSchema>, AppError> {
+    info!("GET /dataframes/{name}/schema");
+    let mask = mask.as_ref().map(|Extension(mask)| mask);
+    core.table_schemas_as(std::slice::from_ref(&name), mask)
+        .await
+        .pop()
+        .map(Json)
+        .ok_or_else(|| AppError::bad_request(format!("Unknown DataFrame: {name}")))
+}
+
+/// Get the size and provenance of a DataFrame
+///
+/// Reports rows, columns, estimated in-memory bytes, when the table last changed
+/// and the file or run it was loaded from. The `_tables` table holds the same for
+/// every table.
+#[utoipa::path(
+    get,
+    path = "/dataframes/{name}/stats",
+    params(("name" = String, Path, description = "DataFrame name")),
+    responses(
+        (status = 200, description = "Table statistics", body = TableStats),
+        (status = 400, description = "Unknown DataFrame", body = ErrorResponse)
+    )
+)]
+pub async fn dataframe_stats(
+    State(core): State<Arc<ServerCore>>,
+    mask: Option<Extension<ColumnMask>>,
+    Path(name): Path<String>,
+) -> Result<Json<TableStats>, AppError> {
+    info!("GET /dataframes/{name}/stats");
+    let mask = mask.as_ref().map(|Extension(mask)| mask);
+    core.table_stats(&name, mask)
+        .await
+        .map(Json)
+        .ok_or_else(|| AppError::bad_request(format!("Unknown DataFrame: {name}")))
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct UploadParams {
+    /// `ipc_stream`, `ipc`, `parquet`, `csv`, `json` or `ndjson` (detected from the body when omitted)
+    pub format: Option<String>,
+}
+
+/// Upload a DataFrame, replacing any existing table with the same name
+#[utoipa::path(
+    post,
+    path = "/dataframes/{name}",
+    params(("name" = String, Path, description = "DataFrame name"), UploadParams),
+    request_body(content = Vec<u8>, content_type = "application/octet-stream", description = "Arrow IPC, Parquet, CSV, JSON or NDJSON bytes"),
+    responses(
+        (status = 200, description = "Schema of the registered table", body = TableSchema),
+        (status = 400, description = "Invalid body or format, or reserved name", body = ErrorResponse)
+    )
+)]
+pub async fn upload_dataframe(
+    State(core): State<Arc<ServerCore>>,
+    Path(name): Path<String>,
+    Query(params): Query<UploadParams>,
+    body: Bytes,
+) -> Result<Json<TableSchema>, AppError> {
+    reject_reserved(&name)?;
+    let format = match params.format.as_deref() {
+        Some(f) => DataFormat::parse(f).map_err(AppError::bad_request)?,
+        None => DataFormat::sniff(&body),
+    };
+    info!(
+        "POST /dataframes/{name}: {} bytes as {format:?}",
+        body.len()
+    );
+
+    let df = loader::load_bytes(body.to_vec(), format).await?;
+    core.insert_df(name.clone(), df).await;
+    core.table_schema(&name).await.map(Json).ok_or_else(|| {
+        AppError::bad_request(format!("Upload of {name} was rejected by its reload hook"))
+    })
+}
+
+/// Fail requests that would replace or remove the built-in `_queries` or `_tables` table
+fn reject_reserved(name: &str) -> Result<(), AppError> {
+    if let Some(purpose) = table_stats::builtin(name) {
+        return Err(AppError::bad_request(format!(
+            "`{name}` is reserved for {purpose}"
+        )));
+    }
+    Ok(())
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct DeleteParams {
+    /// Also remove the materialized views reading the table; without it, deleting a
+    /// table that views read fails
+    #[serde(default)]
+    pub cascade: bool,
+}
+
+/// Unregister a DataFrame
+#[utoipa::path(
+    delete,
+    path = "/dataframes/{name}",
+    params(("name" = String, Path, description = "DataFrame name"), DeleteParams),
+    responses(
+        (status = 204, description = "DataFrame removed"),
+        (status = 400, description = "Unknown or reserved DataFrame, or read by materialized views", body = ErrorResponse)
+    )
+)]
+pub async fn delete_dataframe(
+    State(core): State<Arc<ServerCore>>,
+    Path(name): Path<String>,
+    Query(params): Query<DeleteParams>,
+) -> Result<StatusCode, AppError> {
+    info!("DELETE /dataframes/{name}");
+    reject_reserved(&name)?;
+    let mode = if params.cascade {
+        RemoveMode::Cascade
+    } else {
+        RemoveMode::Fail
+    };
+    let views = core.remove_df(&name, mode).await?;
+    if !views.is_empty() {
+        info!(
+            "Removed materialized views reading {name}: {}",
+            views.join(", ")
+        );
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Rename a DataFrame
+///
+/// Keeps the table's data and, for a materialized view, its query. Fails if a
+/// materialized view reads the table, since its query names it.
+#[utoipa::path(
+    patch,
+    path = "/dataframes/{name}",
+    params(("name" = String, Path, description = "Current DataFrame name")),
+    request_body = RenameRequest,
+    responses(
+        (status = 200, description = "Schema of the renamed table", body = TableSchema),
+        (status = 400, description = "Unknown, reserved or taken name, or read by materialized views", body = ErrorResponse)
+    )
+)]
+pub async fn rename_dataframe(
+    State(core): State<Arc<ServerCore>>,
+    Path(name): Path<String>,
+    Json(request): Json<RenameRequest>,
+) -> Result<Json<TableSchema>, AppError> {
+    info!("PATCH /dataframes/{name}: rename to {}", request.name);
+    reject_reserved(&name)?;
+    reject_reserved(&request.name)?;
+    core.rename_df(&name, &request.name).await?;
+    core.table_schema(&request.name)
+        .await
+        .map(Json)
+        .ok_or_else(|| AppError::bad_request(format!("Unknown DataFrame: {}", request.name)))
+}
+
+/// Create or replace a materialized view
+///
+/// The query result is stored as a named DataFrame and re-evaluated whenever a table
+/// it reads is updated.
+#[utoipa::path(
+    post,
+    path = "/materialize",
+    request_body = MaterializeRequest,
+    responses(
+        (status = 200, description = "Schema of the materialized table", body = TableSchema),
+        (status = 400, description = "Query error", body = ErrorResponse),
+        (status = 422, description = "Query exceeded a resource limit", body = ErrorResponse)
+    )
+)]
+pub async fn materialize(
+    State(core): State<Arc<ServerCore>>,
+    Json(request): Json<MaterializeRequest>,
+) -> Result<Json<TableSchema>, AppError> {
+    info!("POST /materialize {}: {}", request.name, request.query);
+    core.materialize(&request.name, &request.query).await?;
+    core.table_schema(&request.name)
+        .await
+        .map(Json)
+        .ok_or_else(|| {
+            AppError::bad_request(format!(
+                "Materialization of {} was rejected by its reload hook",
+                request.name
+            ))
+        })
+}
+
+/// List materialized views and their dependencies
+///
+/// Views are listed in refresh order: each comes after the views it reads.
+#[utoipa::path(
+    get,
+    path = "/materializations",
+    responses(
+        (status = 200, description = "Materialized views", body = MaterializationsResponse)
+    )
+)]
+pub async fn materializations(
+    State(core): State<Arc<ServerCore>>,
+) -> Json<MaterializationsResponse> {
+    let views = core.materializations().await;
+    let graph = materialize::dependency_graph(&views);
+    let views = graph
+        .topological_order()
+        .into_iter()
+        .map(|name| MaterializationNode {
+            query: views[&name].query.clone(),
+            dependencies: views[&name].dependencies.iter().cloned().collect(),
+            dependents: graph.dependents(&name),
+            name,
+        })
+        .collect();
+    Json(MaterializationsResponse { views })
+}
+
+/// List supported PiQL methods and functions
+///
+/// Generated from the evaluator's method tables, so it always matches what queries can use.
+#[utoipa::path(
+    get,
+    path = "/capabilities",
+    responses(
+        (status = 200, description = "Supported methods per receiver", body = CapabilitiesResponse)
+    )
+)]
+pub async fn capabilities() -> Json<CapabilitiesResponse> {
+    info!("GET /capabilities");
+    Json(CapabilitiesResponse::current())
+}
+
+/// Prometheus metrics
+///
+/// Query counts, parse/eval errors, latency histogram, rows returned, active SSE
+/// subscribers, DataFrame update events and each update subscriber's queue depth
+/// and lag, in the Prometheus text format.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    responses(
+        (status = 200, description = "Prometheus text exposition", body = String, content_type = "text/plain")
+    )
+)]
+pub async fn metrics(State(core): State<Arc<ServerCore>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        core.metrics().render() + &core.state().update_bus().render_metrics(),
+    )
+}
+
+/// Drop all cached query results
+#[utoipa::path(
+    post,
+    path = "/cache/clear",
+    responses(
+        (status = 200, description = "Number of entries removed", body = CacheClearResponse)
+    )
+)]
+pub async fn clear_cache(State(core): State<Arc<ServerCore>>) -> Json<CacheClearResponse> {
+    let cleared = core.clear_cache().await;
+    info!("POST /cache/clear: {cleared} entries");
+    Json(CacheClearResponse { cleared })
 }
@@ -1,7 +1,14 @@
 //! Arrow IPC serialization helpers
 
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+use axum::body::Bytes;
 use base64::Engine;
+use futures::stream::{self, Stream, StreamExt};
 use polars::prelude::*;
+use polars_arrow::io::ipc::write::{StreamWriter, WriteOptions};
 
 /// Error while encoding a DataFrame as Arrow IPC.
 #[derive(Debug)]
@@ -41,12 +48,106 @@ pub async fn dataframe_to_ipc_bytes(mut df: DataFrame) -> Result<Vec<u8>, IpcEnc
     Ok(bytes)
 }
 
+/// Serialize a DataFrame as an Arrow IPC stream, yielding one chunk per record batch
+/// of at most `batch_size` rows.
+///
+/// One writer encodes every batch on the blocking pool as the stream is polled, so
+/// dictionaries (categoricals, enums) stay consistent across batches and the full
+/// IPC buffer is never held in memory. The first chunk (schema and first batch) is
+/// encoded before this returns, so a result that can't be encoded fails as an error
+/// response; a later failure ends the stream with an error, aborting the body
+/// rather than closing it as a complete stream.
+pub async fn dataframe_to_ipc_stream(
+    df: DataFrame,
+    batch_size: usize,
+) -> Result<impl Stream<Item = Result<Bytes, IpcEncodeError>>, IpcEncodeError> {
+    let (tx, rx) = tokio::sync::mpsc::channel(2);
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = write_ipc_stream(&df, batch_size, &tx) {
+            let _ = tx.blocking_send(Err(IpcEncodeError::Polars(e)));
+        }
+    });
+    let mut chunks = stream::unfold(rx, |mut rx| async move {
+        let chunk = rx.recv().await?;
+        Some((chunk, rx))
+    })
+    .boxed();
+    let first = match chunks.next().await {
+        Some(chunk) => chunk?,
+        None => {
+            return Err(IpcEncodeError::Polars(
+                polars_err!(ComputeError: "IPC encoding stopped"),
+            ));
+        }
+    };
+    Ok(stream::once(async { Ok(first) }).chain(chunks))
+}
+
+/// Write `df` as one IPC stream to `tx`, a chunk per batch; stops early if the
+/// receiver is gone
+fn write_ipc_stream(
+    df: &DataFrame,
+    batch_size: usize,
+    tx: &tokio::sync::mpsc::Sender<Result<Bytes, IpcEncodeError>>,
+) -> PolarsResult<()> {
+    let pending = Pending::default();
+    let mut writer = StreamWriter::new(pending.clone(), WriteOptions { compression: None });
+    writer.start(&df.schema().to_arrow(CompatLevel::oldest()), None)?;
+    for mut batch in split_batches(df, batch_size) {
+        batch.as_single_chunk_par();
+        for chunk in batch.iter_chunks(CompatLevel::oldest(), true) {
+            writer.write(&chunk, None)?;
+        }
+        if tx.blocking_send(Ok(pending.take())).is_err() {
+            return Ok(());
+        }
+    }
+    writer.finish()?;
+    let _ = tx.blocking_send(Ok(pending.take()));
+    Ok(())
+}
+
+/// Bytes the IPC writer has produced that haven't been sent yet
+#[derive(Clone, Default)]
+struct Pending(Rc<RefCell<Vec<u8>>>);
+
+impl Pending {
+    fn take(&self) -> Bytes {
+        Bytes::from(std::mem::take(&mut *self.0.borrow_mut()))
+    }
+}
+
+impl Write for Pending {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
 /// Serialize a DataFrame as base64-encoded Arrow IPC stream.
 pub async fn dataframe_to_base64_ipc(df: DataFrame) -> Result<String, IpcEncodeError> {
     let buf = dataframe_to_ipc_bytes(df).await?;
     Ok(base64::engine::general_purpose::STANDARD.encode(&buf))
 }
 
+/// Split a DataFrame into zero-copy slices of at most `batch_size` rows.
+///
+/// Always yields at least one (possibly empty) frame so the schema can be sent.
+pub fn split_batches(df: &DataFrame, batch_size: usize) -> Vec<DataFrame> {
+    let batch_size = batch_size.max(1);
+    if df.height() == 0 {
+        return vec![df.clone()];
+    }
+    (0..df.height())
+        .step_by(batch_size)
+        .map(|offset| df.slice(offset as i64, batch_size))
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::Cursor;
@@ -67,4 +168,73 @@ mod tests {
         assert_eq!(decoded.width(), 2);
         assert_eq!(decoded.column("a").unwrap().i32().unwrap().get(1), Some(2));
     }
+
+    #[tokio::test]
+    async fn batched_stream_is_a_single_ipc_stream() {
+        let df = df! {
+            "a" => &[1i32, 2, 3, 4, 5],
+            "b" => &["v", "w", "x", "y", "z"],
+        }
+        .unwrap();
+
+        let chunks: Vec<Bytes> = dataframe_to_ipc_stream(df.clone(), 2)
+            .await
+            .unwrap()
+            .map(|chunk| chunk.unwrap())
+            .collect()
+            .await;
+        // 3 batches + EOS
+        assert_eq!(chunks.len(), 4);
+        let buf = chunks.concat();
+        let decoded = IpcStreamReader::new(Cursor::new(buf)).finish().unwrap();
+        assert!(decoded.equals(&df));
+
+        let empty: Vec<Bytes> = dataframe_to_ipc_stream(df.head(Some(0)), 2)
+            .await
+            .unwrap()
+            .map(|chunk| chunk.unwrap())
+            .collect()
+            .await;
+        let decoded = IpcStreamReader::new(Cursor::new(empty.concat()))
+            .finish()
+            .unwrap();
+        assert_eq!(decoded.height(), 0);
+        assert_eq!(decoded.width(), 2);
+    }
+
+    #[tokio::test]
+    async fn batched_stream_keeps_dictionaries_consistent() {
+        // Each batch sees different categories, so the writer must send them all
+        let df = df! { "kind" => &["a", "b", "c", "a", "d"] }
+            .unwrap()
+            .lazy()
+            .with_column(col("kind").cast(DataType::from_categories(Categories::global())))
+            .collect()
+            .unwrap();
+
+        let chunks: Vec<Bytes> = dataframe_to_ipc_stream(df.clone(), 2)
+            .await
+            .unwrap()
+            .map(|chunk| chunk.unwrap())
+            .collect()
+            .await;
+        let decoded = IpcStreamReader::new(Cursor::new(chunks.concat()))
+            .finish()
+            .unwrap();
+        let kinds = decoded
+            .column("kind")
+            .unwrap()
+            .cast(&DataType::String)
+            .unwrap();
+        let kinds: Vec<_> = kinds.str().unwrap().into_iter().flatten().collect();
+        assert_eq!(kinds, ["a", "b", "c", "a", "d"]);
+    }
+
+    #[test]
+    fn split_batches_covers_all_rows() {
+        let df = df! { "a" => &[1i32, 2, 3, 4, 5] }.unwrap();
+        let heights: Vec<usize> = split_batches(&df, 2).iter().map(|b| b.height()).collect();
+        assert_eq!(heights, vec![2, 2, 1]);
+        assert_eq!(split_batches(&df.head(Some(0)), 2).len(), 1);
+    }
 }
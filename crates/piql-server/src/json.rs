@@ -0,0 +1,99 @@
+//! Query results as JSON
+
+use polars::prelude::*;
+
+/// Result rows as JSON objects keyed by column name
+pub fn dataframe_to_json_rows(df: &DataFrame) -> Vec<serde_json::Map<String, serde_json::Value>> {
+    (0..df.height())
+        .map(|row| {
+            df.get_columns()
+                .iter()
+                .map(|column| {
+                    let value = column.get(row).map_or(serde_json::Value::Null, any_to_json);
+                    (column.name().to_string(), value)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// A result as compact JSON, collapsed like `piql::collapse_result`: `{"value": v}`
+/// for a single cell, `{"name": ..., "values": [...]}` for a single column, and
+/// otherwise `{"rows": [...]}`
+pub fn dataframe_to_compact_json(df: &DataFrame) -> serde_json::Value {
+    let cell = |column: &Column, row| column.get(row).map_or(serde_json::Value::Null, any_to_json);
+    match df.get_columns() {
+        [column] if column.len() == 1 => serde_json::json!({ "value": cell(column, 0) }),
+        [column] => serde_json::json!({
+            "name": column.name().as_str(),
+            "values": (0..column.len()).map(|row| cell(column, row)).collect::<Vec<_>>(),
+        }),
+        _ => serde_json::json!({ "rows": dataframe_to_json_rows(df) }),
+    }
+}
+
+fn any_to_json(value: AnyValue<'_>) -> serde_json::Value {
+    use serde_json::Value as Json;
+    match value {
+        AnyValue::Null => Json::Null,
+        AnyValue::Boolean(b) => Json::Bool(b),
+        AnyValue::String(s) => Json::String(s.to_string()),
+        AnyValue::StringOwned(s) => Json::String(s.to_string()),
+        v if v.is_signed_integer() => v.extract::<i64>().map_or(Json::Null, Json::from),
+        v if v.is_unsigned_integer() => v.extract::<u64>().map_or(Json::Null, Json::from),
+        v if v.is_float() => v
+            .extract::<f64>()
+            .and_then(serde_json::Number::from_f64)
+            .map_or(Json::Null, Json::Number),
+        // Dates, durations, lists, structs: their Polars display form
+        v => Json::String(v.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_rows_keep_scalar_types() {
+        let df = df! {
+            "name" => &[Some("a"), None],
+            "gold" => &[1i64, 2],
+            "ratio" => &[0.5, 1.5],
+            "ok" => &[true, false],
+        }
+        .unwrap();
+        let rows = serde_json::Value::from(
+            dataframe_to_json_rows(&df)
+                .into_iter()
+                .map(serde_json::Value::Object)
+                .collect::<Vec<_>>(),
+        );
+        assert_eq!(
+            rows,
+            serde_json::json!([
+                {"name": "a", "gold": 1, "ratio": 0.5, "ok": true},
+                {"name": null, "gold": 2, "ratio": 1.5, "ok": false},
+            ])
+        );
+    }
+
+    #[test]
+    fn compact_json_collapses_single_columns_and_cells() {
+        let cell = df! { "total" => &[400i64] }.unwrap();
+        assert_eq!(
+            dataframe_to_compact_json(&cell),
+            serde_json::json!({"value": 400})
+        );
+        let column = df! { "name" => &["a", "b"] }.unwrap();
+        assert_eq!(
+            dataframe_to_compact_json(&column),
+            serde_json::json!({"name": "name", "values": ["a", "b"]})
+        );
+        let wide = df! { "name" => &["a"], "gold" => &[1i64] }.unwrap();
+        assert_eq!(
+            dataframe_to_compact_json(&wide),
+            serde_json::json!({"rows": [{"name": "a", "gold": 1}]})
+        );
+    }
+}
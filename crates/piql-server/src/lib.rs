@@ -6,8 +6,36 @@
 //! # Features
 //!
 //! - `llm` - Natural language to PiQL query generation
-//! - `file-watcher` - Automatic DataFrame reloading on file changes
-//! - `full` - All features enabled
+//! - `webhooks` - Alerts POSTing to a webhook when a query result meets a condition
+//!   (see [`alert`])
+//! - `file-watcher` - Automatic DataFrame reloading on file changes, and run-aware
+//!   mode with its `/runs` endpoints (see [`runs`])
+//! - `sql` - Accept SQL on `/query` (`?dialect=sql`), translated to PiQL
+//! - `sqlite` - SQLite files from `/export` (see [`export`])
+//! - `full` - All features above enabled
+//! - `duckdb` - DuckDB files from `/export` (opt-in; builds the DuckDB library)
+//! - `flight` - Arrow Flight `DoGet` server for large results (opt-in; pulls in tonic)
+//! - `cloud` - Load tables from `s3://`, `gs://`, `az://` and `http(s)://` URLs
+//!   (opt-in; see [`remote`])
+//!
+//! Authentication is opt-in: `ServerCore::with_auth` makes every endpoint built by
+//! `build_router` require an `X-Api-Key` or bearer token (see [`auth`]).
+//!
+//! CORS, response compression and the request body limit are configured with
+//! `ServerCore::with_config` (see [`ServerConfig`]); by default any origin is
+//! allowed and gzip/zstd responses are negotiated.
+//!
+//! Queries evaluate on a dedicated thread pool whose size and queue depth are set
+//! with `ServerCore::with_compute`; a full queue answers 503 (see [`compute`]).
+//!
+//! Queries can run on a cron schedule, writing each result to a timestamped file
+//! (see [`schedule`]), or be registered as templates run with `{{var}}` values
+//! (see [`templates`]). A library of saved queries with descriptions and tags can be
+//! searched and run under `/queries/saved` (see [`saved_queries`]). `/query/batch`
+//! runs several queries against one snapshot of the tables (see [`batch`]).
+//!
+//! [`serve_with_graceful_shutdown`] runs the router until SIGINT/SIGTERM, draining
+//! in-flight queries and closing SSE streams before returning (see [`shutdown`]).
 //!
 //! # Example
 //!
@@ -30,26 +58,71 @@
 //! }
 //! ```
 
+pub mod annotate;
+pub mod auth;
+pub mod batch;
+pub mod bench;
+pub mod cache;
+pub mod compute;
+pub mod config;
 pub mod core;
+pub mod demo;
+pub mod diff;
 pub mod error;
+pub mod explain;
+pub mod export;
+pub mod hooks;
 pub mod http;
 pub mod ipc;
+pub mod json;
 pub mod loader;
+pub mod mask;
+pub mod materialize;
+pub mod metrics;
+pub mod policy;
+pub mod query_log;
+pub mod remote;
+pub mod saved_queries;
+pub mod schedule;
+pub mod shutdown;
+pub mod snapshot;
 pub mod sse;
 pub mod state;
+pub mod table_stats;
+pub mod templates;
+pub mod updates;
 
 #[cfg(feature = "llm")]
 pub mod llm;
 
+#[cfg(feature = "webhooks")]
+pub mod alert;
+
+#[cfg(feature = "flight")]
+pub mod flight;
+
 #[cfg(feature = "file-watcher")]
 pub mod runs;
 #[cfg(feature = "file-watcher")]
 pub mod watcher;
 
 // Re-exports for convenience
+pub use annotate::Annotations;
+pub use auth::{AuthConfig, Scope};
+pub use compute::ComputeConfig;
+pub use config::{Compression, CorsOrigins, ServerConfig};
 pub use core::ServerCore;
 pub use error::AppError;
-pub use state::{DfUpdate, SharedState};
+pub use export::ExportFormat;
+pub use hooks::ReloadHook;
+pub use mask::ColumnMask;
+pub use policy::QueryPolicy;
+pub use query_log::QUERY_LOG_TABLE;
+pub use schedule::ScheduleSpec;
+pub use shutdown::{Shutdown, serve_with_graceful_shutdown};
+pub use state::{DfUpdate, SharedState, UpdateNotice};
+pub use table_stats::{TABLES_TABLE, TableOrigin};
+pub use updates::{LagPolicy, UpdateReceiver};
 
 use std::sync::Arc;
 
@@ -60,8 +133,90 @@ use utoipa::OpenApi;
 /// OpenAPI documentation (base endpoints)
 #[derive(OpenApi)]
 #[openapi(
-    paths(http::query, http::list_dataframes, sse::subscribe,),
-    components(schemas(state::DataframesResponse, state::ErrorResponse,))
+    paths(
+        http::query,
+        batch::batch,
+        http::explain,
+        http::complete,
+        http::format,
+        http::list_dataframes,
+        http::dataframe_schema,
+        http::dataframe_stats,
+        http::upload_dataframe,
+        http::delete_dataframe,
+        http::rename_dataframe,
+        http::materialize,
+        http::materializations,
+        http::capabilities,
+        http::metrics,
+        http::clear_cache,
+        export::export,
+        diff::diff,
+        bench::bench,
+        schedule::list,
+        schedule::create,
+        schedule::delete,
+        schedule::run,
+        schedule::runs,
+        templates::list,
+        templates::create,
+        templates::delete,
+        templates::run,
+        saved_queries::list,
+        saved_queries::save_new,
+        saved_queries::get,
+        saved_queries::replace,
+        saved_queries::delete,
+        saved_queries::run,
+        sse::subscribe,
+    ),
+    components(schemas(
+        state::CacheClearResponse,
+        state::DataframesResponse,
+        state::TableSchema,
+        table_stats::TableStats,
+        state::ColumnSchema,
+        state::ErrorResponse,
+        state::ExplainResponse,
+        state::MaterializeRequest,
+        state::RenameRequest,
+        state::MaterializationsResponse,
+        state::MaterializationNode,
+        state::QueryRequest,
+        http::Dialect,
+        state::FormatRequest,
+        state::FormatResponse,
+        state::ParseDiagnostic,
+        state::CompleteRequest,
+        state::CompleteResponse,
+        state::CompletionItem,
+        state::CapabilitiesResponse,
+        state::NamespaceCapabilities,
+        state::MethodCapability,
+        export::ExportRequest,
+        export::ExportResponse,
+        export::ExportFormat,
+        diff::DiffRequest,
+        diff::DiffResponse,
+        bench::BenchRequest,
+        bench::BenchResponse,
+        schedule::ScheduleSpec,
+        schedule::ScheduleInfo,
+        schedule::ScheduleRun,
+        schedule::SchedulesResponse,
+        schedule::ScheduleRunsResponse,
+        templates::TemplateSpec,
+        templates::TemplateInfo,
+        templates::TemplatesResponse,
+        templates::TemplateRunRequest,
+        saved_queries::SavedQueryRequest,
+        saved_queries::SavedQuery,
+        saved_queries::SavedQueriesResponse,
+        batch::BatchQuery,
+        batch::BatchRequest,
+        batch::BatchResult,
+        batch::BatchResponse,
+    ))
 )]
 struct ApiDocBase;
 
@@ -75,21 +230,111 @@ pub fn openapi_spec() -> utoipa::openapi::OpenApi {
         let llm_doc = llm::LlmApiDoc::openapi();
         doc.paths.paths.extend(llm_doc.paths.paths);
     }
+    #[cfg(feature = "webhooks")]
+    {
+        use utoipa::OpenApi;
+        doc.merge(alert::AlertApiDoc::openapi());
+    }
+    #[cfg(feature = "file-watcher")]
+    {
+        use utoipa::OpenApi;
+        doc.merge(runs::RunsApiDoc::openapi());
+    }
     doc
 }
 
 /// Build the axum router with all endpoints
 pub fn build_router(core: Arc<ServerCore>) -> Router {
-    #[allow(unused_mut)]
     let mut router = Router::new()
         .route("/query", post(http::query))
+        .route("/query/batch", post(batch::batch))
+        .route("/explain", post(http::explain))
+        .route("/complete", post(http::complete))
+        .route("/format", post(http::format))
         .route("/dataframes", get(http::list_dataframes))
+        .route(
+            "/dataframes/{name}",
+            post(http::upload_dataframe)
+                .delete(http::delete_dataframe)
+                .patch(http::rename_dataframe),
+        )
+        .route("/dataframes/{name}/schema", get(http::dataframe_schema))
+        .route("/dataframes/{name}/stats", get(http::dataframe_stats))
+        .route("/materialize", post(http::materialize))
+        .route("/materializations", get(http::materializations))
+        .route("/capabilities", get(http::capabilities))
+        .route("/metrics", get(http::metrics))
+        .route("/cache/clear", post(http::clear_cache))
+        .route("/export", post(export::export))
+        .route("/diff", post(diff::diff))
+        .route("/bench", post(bench::bench))
+        .route("/schedules", get(schedule::list).post(schedule::create))
+        .route("/schedules/{name}", axum::routing::delete(schedule::delete))
+        .route("/schedules/{name}/run", post(schedule::run))
+        .route("/schedules/{name}/runs", get(schedule::runs))
+        .route("/templates", get(templates::list).post(templates::create))
+        .route(
+            "/templates/{name}",
+            axum::routing::delete(templates::delete),
+        )
+        .route("/templates/{name}/run", post(templates::run))
+        .route(
+            "/queries/saved",
+            get(saved_queries::list).post(saved_queries::save_new),
+        )
+        .route(
+            "/queries/saved/{name}",
+            get(saved_queries::get)
+                .put(saved_queries::replace)
+                .delete(saved_queries::delete),
+        )
+        .route("/queries/saved/{name}/run", post(saved_queries::run))
         .route("/subscribe", get(sse::subscribe));
 
     #[cfg(feature = "llm")]
     {
-        router = router.route("/ask", post(llm::ask));
+        router = router
+            .route("/ask", post(llm::ask))
+            .route("/ask/usage", get(llm::usage))
+            .route(
+                "/ask/sessions/{id}",
+                axum::routing::delete(llm::delete_session),
+            );
+    }
+
+    #[cfg(feature = "webhooks")]
+    {
+        router = router
+            .route("/alerts", get(alert::list).post(alert::create))
+            .route("/alerts/{name}", axum::routing::delete(alert::delete));
+    }
+
+    #[cfg(feature = "file-watcher")]
+    {
+        router = router
+            .route("/runs", get(runs::list))
+            .route("/runs/{name}", axum::routing::delete(runs::delete))
+            .route("/runs/{name}/load", post(runs::load))
+            .route("/runs/{name}/promote", post(runs::promote));
+    }
+
+    if core.policy().read_only {
+        router = router.layer(axum::middleware::from_fn_with_state(
+            core.clone(),
+            policy::reject_writes,
+        ));
+    }
+    if let Some(auth) = core.auth().cloned() {
+        router = router.layer(axum::middleware::from_fn_with_state(
+            auth,
+            auth::require_auth,
+        ));
     }
+    router = router.layer(axum::middleware::from_fn_with_state(
+        core.clone(),
+        shutdown::reject_when_closing,
+    ));
+    router = core.config().apply(router);
 
     router.with_state(core)
 }
@@ -1,49 +1,52 @@
 //! Natural language to PiQL query generation using LLMs
 //!
-//! This module is feature-gated behind the `llm` feature.
+//! This module is feature-gated behind the `llm` feature. The model behind `/ask` is
+//! an [`LlmProvider`], chosen from the environment by [`provider_from_env`] unless
+//! set with `ServerCore::with_llm_provider`.
 
+use std::collections::{HashMap, VecDeque};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use axum::extract::{Query, State};
-use axum::http::{HeaderName, HeaderValue, header};
-use axum::response::IntoResponse;
+use axum::Extension;
+use axum::Json;
+use axum::extract::{ConnectInfo, Path, Query, State};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use futures::future::BoxFuture;
 use log::{debug, info, warn};
 use piql::EvalContext;
+use piql::advanced::SurfaceExpr;
 use polars::prelude::*;
-use serde::Deserialize;
-use utoipa::{IntoParams, OpenApi};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use utoipa::{IntoParams, OpenApi, ToSchema};
 
+use crate::auth::{Credential, Scope};
 use crate::core::ServerCore;
 use crate::error::AppError;
 use crate::ipc::dataframe_to_ipc_bytes;
+use crate::json::dataframe_to_json_rows;
+use crate::state::{ErrorResponse, ParseDiagnostic, TableSchema};
 
 /// OpenAPI documentation for LLM endpoints
 #[derive(OpenApi)]
-#[openapi(paths(ask))]
+#[openapi(
+    paths(ask, delete_session),
+    components(schemas(AskResponse, AskAttempt))
+)]
 pub struct LlmApiDoc;
 
-// ============ Natural Language to PiQL ============
-
-pub const PIQL_DOCS: &str = r#"PiQL is a text query language for Polars dataframes. Write queries that look like Python Polars.
-
-## Supported Features
-
-**DataFrame methods**
-`filter`, `select`, `with_columns`, `head`, `tail`, `sort`, `drop`, `explode`, `group_by`, `join`, `rename`, `drop_nulls`, `reverse`, `top`
+const SESSION_HEADER: HeaderName = HeaderName::from_static("x-piql-session");
 
-**Expr methods**
-`alias`, `over`, `is_between`, `diff`, `shift`, `sum`, `mean`, `min`, `max`, `count`, `first`, `last`, `cast`, `fill_null`, `is_null`, `is_not_null`, `unique`, `abs`, `round`, `len`, `n_unique`, `cum_sum`, `cum_max`, `cum_min`, `rank`, `clip`, `reverse`
-
-**pl functions**
-`col`, `lit`, `when`/`then`/`otherwise`
-
-**str namespace**
-`starts_with`, `ends_with`, `to_lowercase`, `to_uppercase`, `len_chars`, `contains`, `replace`, `slice`
+// ============ Natural Language to PiQL ============
 
-**dt namespace**
-`year`, `month`, `day`, `hour`, `minute`, `second`
+const PIQL_DOCS_INTRO: &str = "PiQL is a text query language for Polars dataframes. Write queries that look like Python Polars.";
 
-**Operators**
+const PIQL_DOCS_SYNTAX: &str = r#"**Operators**
 `+`, `-`, `*`, `/`, `%`, `==`, `!=`, `<`, `<=`, `>`, `>=`, `&`, `|`, `~`
 
 **Sugar**
@@ -55,6 +58,40 @@ When aliasing arithmetic expressions, you MUST wrap the expression in parenthese
 - WRONG: `pl.col("a") - pl.col("b").alias("diff")` (aliases "b", not the difference)
 "#;
 
+/// PiQL language description for the system prompt.
+///
+/// Method lists come from `piql::capabilities()`, so the prompt never advertises
+/// methods the evaluator doesn't support (or misses new ones).
+pub fn piql_docs() -> String {
+    let mut docs = format!("{PIQL_DOCS_INTRO}\n\n## Supported Features\n\n");
+    for (ns, methods) in piql::capabilities() {
+        let heading = match ns {
+            piql::Namespace::Pl => "pl functions".to_string(),
+            piql::Namespace::DataFrame => "DataFrame methods".to_string(),
+            piql::Namespace::GroupBy => "GroupBy methods".to_string(),
+            piql::Namespace::Expr => "Expr methods".to_string(),
+            other => format!("{} namespace", other.as_str()),
+        };
+        let names: Vec<String> = methods
+            .iter()
+            .map(|m| {
+                let name = if m.name == "when" {
+                    "`when`/`then`/`otherwise`".to_string()
+                } else {
+                    format!("`{}`", m.name)
+                };
+                match m.kwargs {
+                    [] | ["*"] => name,
+                    kwargs => format!("{name} ({}=)", kwargs.join("=, ")),
+                }
+            })
+            .collect();
+        docs.push_str(&format!("**{heading}**\n{}\n\n", names.join(", ")));
+    }
+    docs.push_str(PIQL_DOCS_SYNTAX);
+    docs
+}
+
 /// Example query templates - {table}, {str_col}, {num_col}, {cat_col} are placeholders
 const EXAMPLE_TEMPLATES: &[(&str, &str)] = &[
     (
@@ -92,6 +129,101 @@ const EXAMPLE_TEMPLATES: &[(&str, &str)] = &[
     ),
 ];
 
+/// Rows scanned per table when summarizing columns
+const SUMMARY_MAX_ROWS: IdxSize = 100_000;
+/// Columns summarized per table; the rest are only counted
+const SUMMARY_MAX_COLUMNS: usize = 30;
+/// Most common values listed for string columns
+const SUMMARY_TOP_VALUES: IdxSize = 5;
+/// Longer values are cut off in the summary
+const SUMMARY_MAX_VALUE_CHARS: usize = 40;
+
+/// One line per column with its dtype, null share, min/max for numeric and
+/// temporal columns, and the most common values of string columns.
+///
+/// Only the first `SUMMARY_MAX_ROWS` rows and `SUMMARY_MAX_COLUMNS` columns are
+/// looked at, so the prompt stays small for wide or long tables.
+pub fn column_summary(lf: LazyFrame) -> PolarsResult<String> {
+    let mut lf = lf.slice(0, SUMMARY_MAX_ROWS);
+    let schema = lf.collect_schema()?;
+    let columns: Vec<(&PlSmallStr, &DataType)> = schema.iter().take(SUMMARY_MAX_COLUMNS).collect();
+
+    let mut stats = vec![len().alias("__rows")];
+    for (i, (name, dtype)) in columns.iter().enumerate() {
+        let c = col(name.as_str());
+        stats.push(c.clone().null_count().alias(format!("__null_{i}")));
+        if dtype.is_primitive_numeric() || dtype.is_temporal() {
+            stats.push(c.clone().min().alias(format!("__min_{i}")));
+            stats.push(c.max().alias(format!("__max_{i}")));
+        }
+    }
+    let stats = lf.clone().select(stats).collect()?;
+    let stat = |name: &str| stats.column(name).ok().and_then(|c| c.get(0).ok());
+    let rows = stat("__rows")
+        .and_then(|v| v.extract::<usize>())
+        .unwrap_or(0);
+
+    let mut out = String::new();
+    for (i, (name, dtype)) in columns.iter().enumerate() {
+        out.push_str(&format!("- {name}: {dtype}"));
+        if rows > 0 {
+            let nulls = stat(&format!("__null_{i}"))
+                .and_then(|v| v.extract::<usize>())
+                .unwrap_or(0);
+            out.push_str(&format!(
+                ", {:.0}% null",
+                nulls as f64 * 100.0 / rows as f64
+            ));
+        }
+        if let (Some(min), Some(max)) = (stat(&format!("__min_{i}")), stat(&format!("__max_{i}"))) {
+            out.push_str(&format!(", min {min}, max {max}"));
+        }
+        if matches!(dtype, DataType::String) {
+            let top = top_values(&lf, name)?;
+            if !top.is_empty() {
+                out.push_str(&format!(", top values {}", top.join(", ")));
+            }
+        }
+        out.push('\n');
+    }
+    if schema.len() > columns.len() {
+        out.push_str(&format!(
+            "- ... and {} more columns\n",
+            schema.len() - columns.len()
+        ));
+    }
+    Ok(out)
+}
+
+/// Most common non-null values of a string column, quoted, most frequent first
+fn top_values(lf: &LazyFrame, name: &str) -> PolarsResult<Vec<String>> {
+    let counts = lf
+        .clone()
+        .select([col(name)])
+        .filter(col(name).is_not_null())
+        .group_by([col(name)])
+        .agg([len().alias("__count")])
+        .sort_by_exprs(
+            [col("__count"), col(name)],
+            SortMultipleOptions::default().with_order_descending_multi([true, false]),
+        )
+        .limit(SUMMARY_TOP_VALUES)
+        .collect()?;
+    Ok(counts
+        .column(name)?
+        .str()?
+        .into_no_null_iter()
+        .map(|value| {
+            if value.chars().count() > SUMMARY_MAX_VALUE_CHARS {
+                let cut: String = value.chars().take(SUMMARY_MAX_VALUE_CHARS).collect();
+                format!("{cut:?}...")
+            } else {
+                format!("{value:?}")
+            }
+        })
+        .collect())
+}
+
 /// Column info extracted from a dataframe
 pub struct ColumnInfo {
     pub str_cols: Vec<String>,
@@ -102,91 +234,162 @@ pub struct ColumnInfo {
     pub sample_cat: Option<String>,
 }
 
-/// Get schema info, sample data, and generate examples from actual data
-pub async fn get_schema_and_examples(ctx: &EvalContext) -> (String, String) {
-    let dfs: Vec<(String, LazyFrame)> = ctx
-        .dataframes
-        .iter()
-        .map(|(name, entry)| (name.clone(), entry.df.clone().lazy()))
-        .collect();
+/// Tables summarized in the `/ask` prompt; the rest are only counted
+const PROMPT_MAX_TABLES: usize = 20;
+/// Bytes of table summaries in the `/ask` prompt; tables past it are only counted
+const PROMPT_MAX_SCHEMA_BYTES: usize = 32 * 1024;
 
-    let results: Vec<(String, String, ColumnInfo)> = tokio::task::spawn_blocking(move || {
-        dfs.into_iter()
-            .filter_map(|(name, mut lf)| {
-                let df = lf.clone().slice(0, 5).collect().ok()?;
-                let sample_str = format!("{}", df);
-
-                let schema = lf.collect_schema().ok()?;
-                let mut str_cols = Vec::new();
-                let mut num_cols = Vec::new();
-
-                for (col_name, dtype) in schema.iter() {
-                    match dtype {
-                        DataType::String => str_cols.push(col_name.to_string()),
-                        DataType::Int64 | DataType::Int32 | DataType::Float64 => {
-                            num_cols.push(col_name.to_string())
-                        }
-                        _ => {}
-                    }
-                }
+/// A table's column summary and sample rows, as shown in the prompt
+pub struct TableSummary {
+    pub sample: String,
+    pub info: ColumnInfo,
+}
 
-                let mut cat_col = None;
-                let mut sample_cat = None;
-                let mut sample_str_val = None;
-                let mut sample_num = None;
-
-                if df.height() > 0 {
-                    for col in &str_cols {
-                        if let Ok(series) = df.column(col)
-                            && let Ok(val) = series.str()
-                            && let Some(s) = val.get(0)
-                        {
-                            if sample_str_val.is_none() {
-                                sample_str_val = Some(s.to_string());
-                            }
-                            if s.len() < 20 && cat_col.is_none() {
-                                cat_col = Some(col.clone());
-                                sample_cat = Some(s.to_string());
-                            }
-                        }
-                    }
-                    for col in &num_cols {
-                        if let Ok(series) = df.column(col)
-                            && let Ok(val) = series.i64()
-                        {
-                            sample_num = val.get(0);
-                            break;
-                        }
-                    }
+/// Table summaries for the `/ask` prompt, kept until the table's version changes
+#[derive(Default)]
+pub struct TableSummaries {
+    by_table: Mutex<HashMap<String, (u64, Arc<TableSummary>)>>,
+}
+
+/// Summarize a table's columns and first rows, and pick columns for examples
+fn summarize_table(mut lf: LazyFrame) -> Option<TableSummary> {
+    let df = lf.clone().slice(0, 5).collect().ok()?;
+    let summary = column_summary(lf.clone()).unwrap_or_default();
+    let sample = format!("Columns:\n{summary}\nSample rows:\n{df}");
+
+    let schema = lf.collect_schema().ok()?;
+    let mut str_cols = Vec::new();
+    let mut num_cols = Vec::new();
+
+    for (col_name, dtype) in schema.iter() {
+        match dtype {
+            DataType::String => str_cols.push(col_name.to_string()),
+            DataType::Int64 | DataType::Int32 | DataType::Float64 => {
+                num_cols.push(col_name.to_string())
+            }
+            _ => {}
+        }
+    }
+
+    let mut cat_col = None;
+    let mut sample_cat = None;
+    let mut sample_str_val = None;
+    let mut sample_num = None;
+
+    if df.height() > 0 {
+        for col in &str_cols {
+            if let Ok(series) = df.column(col)
+                && let Ok(val) = series.str()
+                && let Some(s) = val.get(0)
+            {
+                if sample_str_val.is_none() {
+                    sample_str_val = Some(s.to_string());
                 }
+                if s.len() < 20 && cat_col.is_none() {
+                    cat_col = Some(col.clone());
+                    sample_cat = Some(s.to_string());
+                }
+            }
+        }
+        for col in &num_cols {
+            if let Ok(series) = df.column(col)
+                && let Ok(val) = series.i64()
+            {
+                sample_num = val.get(0);
+                break;
+            }
+        }
+    }
 
-                Some((
-                    name,
-                    sample_str,
-                    ColumnInfo {
-                        str_cols,
-                        num_cols,
-                        cat_col,
-                        sample_str: sample_str_val,
-                        sample_num,
-                        sample_cat,
-                    },
-                ))
-            })
-            .collect()
+    Some(TableSummary {
+        sample,
+        info: ColumnInfo {
+            str_cols,
+            num_cols,
+            cat_col,
+            sample_str: sample_str_val,
+            sample_num,
+            sample_cat,
+        },
+    })
+}
+
+/// Get schema info, sample data, and generate examples from actual data
+///
+/// Tables are summarized in name order, up to `PROMPT_MAX_TABLES` tables and
+/// `PROMPT_MAX_SCHEMA_BYTES` bytes; a note counts the ones left out. Summaries are
+/// reused from `summaries` while the table's entry in `versions` is unchanged.
+pub async fn get_schema_and_examples(
+    ctx: &EvalContext,
+    versions: &HashMap<String, u64>,
+    summaries: &TableSummaries,
+) -> (String, String) {
+    let mut names: Vec<&String> = ctx.dataframes.keys().collect();
+    names.sort();
+    let (shown, unshown) = names.split_at(names.len().min(PROMPT_MAX_TABLES));
+    let version = |name: &str| versions.get(name).copied().unwrap_or(0);
+
+    let mut cache = summaries.by_table.lock().await;
+    cache.retain(|name, _| ctx.dataframes.contains_key(name));
+    let stale: Vec<(String, u64, LazyFrame)> = shown
+        .iter()
+        .filter(|name| {
+            cache
+                .get(name.as_str())
+                .is_none_or(|(cached, _)| *cached != version(name))
+        })
+        .map(|name| {
+            (
+                name.to_string(),
+                version(name),
+                ctx.dataframes[*name].lazy(),
+            )
+        })
+        .collect();
+    let computed = tokio::task::spawn_blocking(move || {
+        stale
+            .into_iter()
+            .filter_map(|(name, version, lf)| Some((name, version, summarize_table(lf)?)))
+            .collect::<Vec<_>>()
     })
     .await
     .unwrap_or_default();
+    for (name, version, summary) in computed {
+        cache.insert(name, (version, Arc::new(summary)));
+    }
+    let results: Vec<(&str, Arc<TableSummary>)> = shown
+        .iter()
+        .filter_map(|name| {
+            let (cached, summary) = cache.get(name.as_str())?;
+            (*cached == version(name)).then(|| (name.as_str(), summary.clone()))
+        })
+        .collect();
+    drop(cache);
 
     let mut schema_info = String::new();
-    for (name, sample, _) in &results {
-        schema_info.push_str(&format!("## {}\n{}\n\n", name, sample));
+    let mut left_out = unshown.len();
+    for (name, summary) in &results {
+        let section = format!("## {}\n{}\n\n", name, summary.sample);
+        if left_out > unshown.len()
+            || (!schema_info.is_empty()
+                && schema_info.len() + section.len() > PROMPT_MAX_SCHEMA_BYTES)
+        {
+            left_out += 1;
+            continue;
+        }
+        schema_info.push_str(&section);
+    }
+    if left_out > 0 {
+        schema_info.push_str(&format!(
+            "## ... and {left_out} more tables, not summarized to keep this prompt short\n"
+        ));
     }
 
     let mut examples = String::new();
-    if let Some((table, _, info)) = results
+    if let Some((table, TableSummary { info, .. })) = results
         .iter()
-        .find(|(_, _, i)| !i.str_cols.is_empty() && !i.num_cols.is_empty())
+        .map(|(name, summary)| (name, &**summary))
+        .find(|(_, s)| !s.info.str_cols.is_empty() && !s.info.num_cols.is_empty())
     {
         let str_col = info.str_cols.first().map(|s| s.as_str()).unwrap_or("name");
         let num_col = info.num_cols.first().map(|s| s.as_str()).unwrap_or("id");
@@ -233,101 +436,831 @@ pub fn build_system_prompt(schema_info: &str, examples: &str) -> String {
 </available_dataframes>
 
 IMPORTANT:
-- Respond with ONLY the PiQL query string
-- Do NOT include any explanation, markdown formatting, or code blocks
+- Respond with ONLY the PiQL query string, optionally preceded by one `# comment` line saying what it does
+- Do NOT include any other explanation, markdown formatting, or code blocks
 - Do NOT wrap the query in quotes or backticks
 - Just output the raw query that can be executed directly
 - CRITICAL: When aliasing arithmetic, ALWAYS use parentheses: `(a - b).alias("x")` NOT `a - b.alias("x")`"#,
-        PIQL_DOCS, examples, schema_info
+        piql_docs(),
+        examples,
+        schema_info
     )
 }
 
-/// Call LLM to generate query
-pub async fn generate_query(prompt: &str, system: &str) -> Result<String, AppError> {
-    if let Ok(api_key) = std::env::var("OPENROUTER_API_KEY") {
-        call_openrouter(&api_key, prompt, system).await
-    } else {
-        call_claude_cli(prompt, system).await
-    }
-}
-
-async fn call_openrouter(api_key: &str, prompt: &str, system: &str) -> Result<String, AppError> {
-    let client = reqwest::Client::new();
-    let resp = client
-        .post("https://openrouter.ai/api/v1/chat/completions")
-        .header("Authorization", format!("Bearer {}", api_key))
-        .json(&serde_json::json!({
-            "model": "anthropic/claude-sonnet-4",
-            "messages": [
-                {"role": "system", "content": system},
-                {"role": "user", "content": prompt}
-            ]
-        }))
+// ============ Providers ============
+
+/// Default model for OpenRouter, the OpenAI-compatible default endpoint
+const DEFAULT_OPENROUTER_MODEL: &str = "anthropic/claude-sonnet-4";
+const DEFAULT_OPENAI_MODEL: &str = "gpt-4o";
+const DEFAULT_ANTHROPIC_MODEL: &str = "claude-sonnet-4-20250514";
+const DEFAULT_LOCAL_URL: &str = "http://localhost:11434/api/generate";
+const DEFAULT_LOCAL_MODEL: &str = "llama3.1";
+
+/// A model that answers a user prompt under a system prompt
+pub trait LlmProvider: Send + Sync {
+    fn generate<'a>(
+        &'a self,
+        system: &'a str,
+        prompt: &'a str,
+    ) -> BoxFuture<'a, Result<String, AppError>>;
+}
+
+/// Chat-completions API of OpenAI, OpenRouter, vLLM, llama.cpp server, ...
+pub struct OpenAiCompatible {
+    /// Base URL up to `/chat/completions`, e.g. `https://api.openai.com/v1`
+    pub base_url: String,
+    pub api_key: Option<String>,
+    pub model: String,
+}
+
+impl LlmProvider for OpenAiCompatible {
+    fn generate<'a>(
+        &'a self,
+        system: &'a str,
+        prompt: &'a str,
+    ) -> BoxFuture<'a, Result<String, AppError>> {
+        Box::pin(async move {
+            let url = format!("{}/chat/completions", self.base_url.trim_end_matches('/'));
+            let mut request = reqwest::Client::new().post(url);
+            if let Some(api_key) = &self.api_key {
+                request = request.bearer_auth(api_key);
+            }
+            let json = send_json(
+                request.json(&serde_json::json!({
+                    "model": self.model,
+                    "messages": [
+                        {"role": "system", "content": system},
+                        {"role": "user", "content": prompt}
+                    ]
+                })),
+                "OpenAI-compatible",
+            )
+            .await?;
+            response_text(&json["choices"][0]["message"]["content"])
+        })
+    }
+}
+
+/// Anthropic Messages API
+pub struct Anthropic {
+    pub api_key: String,
+    pub model: String,
+    /// Base URL up to `/v1/messages` (default `https://api.anthropic.com`)
+    pub base_url: String,
+}
+
+impl LlmProvider for Anthropic {
+    fn generate<'a>(
+        &'a self,
+        system: &'a str,
+        prompt: &'a str,
+    ) -> BoxFuture<'a, Result<String, AppError>> {
+        Box::pin(async move {
+            let url = format!("{}/v1/messages", self.base_url.trim_end_matches('/'));
+            let request = reqwest::Client::new()
+                .post(url)
+                .header("x-api-key", &self.api_key)
+                .header("anthropic-version", "2023-06-01")
+                .json(&serde_json::json!({
+                    "model": self.model,
+                    "max_tokens": 1024,
+                    "system": system,
+                    "messages": [{"role": "user", "content": prompt}]
+                }));
+            let json = send_json(request, "Anthropic").await?;
+            response_text(&json["content"][0]["text"])
+        })
+    }
+}
+
+/// Self-hosted model behind an Ollama-style `/api/generate` endpoint: the request is
+/// `{model, system, prompt, stream: false}` and the answer is in `response`
+pub struct LocalHttp {
+    pub url: String,
+    pub model: String,
+}
+
+impl LlmProvider for LocalHttp {
+    fn generate<'a>(
+        &'a self,
+        system: &'a str,
+        prompt: &'a str,
+    ) -> BoxFuture<'a, Result<String, AppError>> {
+        Box::pin(async move {
+            let request = reqwest::Client::new()
+                .post(&self.url)
+                .json(&serde_json::json!({
+                    "model": self.model,
+                    "system": system,
+                    "prompt": prompt,
+                    "stream": false
+                }));
+            let json = send_json(request, "local LLM").await?;
+            response_text(&json["response"])
+        })
+    }
+}
+
+/// The `claude` command-line tool, for local development without API keys
+pub struct ClaudeCli;
+
+impl LlmProvider for ClaudeCli {
+    fn generate<'a>(
+        &'a self,
+        system: &'a str,
+        prompt: &'a str,
+    ) -> BoxFuture<'a, Result<String, AppError>> {
+        Box::pin(async move {
+            let full_prompt = format!("{}\n\nUser question: {}", system, prompt);
+            let output = tokio::process::Command::new("claude")
+                .args(["-p", &full_prompt])
+                .output()
+                .await
+                .map_err(|e| AppError::bad_request(format!("Failed to run claude CLI: {}", e)))?;
+
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                return Err(AppError::bad_request(format!(
+                    "claude CLI failed: {}",
+                    stderr
+                )));
+            }
+
+            Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        })
+    }
+}
+
+async fn send_json(
+    request: reqwest::RequestBuilder,
+    provider: &str,
+) -> Result<serde_json::Value, AppError> {
+    let resp = request
         .send()
         .await
-        .map_err(|e| AppError(format!("OpenRouter request failed: {}", e)))?;
-
-    let json: serde_json::Value = resp
-        .json()
+        .map_err(|e| AppError::bad_request(format!("{provider} request failed: {e}")))?;
+    resp.json()
         .await
-        .map_err(|e| AppError(format!("Failed to parse OpenRouter response: {}", e)))?;
+        .map_err(|e| AppError::bad_request(format!("Failed to parse {provider} response: {e}")))
+}
 
-    let query = json["choices"][0]["message"]["content"]
+fn response_text(content: &serde_json::Value) -> Result<String, AppError> {
+    content
         .as_str()
-        .ok_or_else(|| AppError("No response content from LLM".into()))?
-        .trim()
-        .to_string();
+        .map(|s| s.trim().to_string())
+        .ok_or_else(|| AppError::bad_request("No response content from LLM"))
+}
 
-    Ok(query)
+/// Provider selected by environment variables.
+///
+/// `PIQL_LLM_PROVIDER` is one of `openai`, `openrouter`, `anthropic`, `local` or
+/// `claude-cli`; `PIQL_LLM_MODEL` and `PIQL_LLM_URL` override the model and endpoint.
+/// Keys come from `OPENAI_API_KEY`, `OPENROUTER_API_KEY` and `ANTHROPIC_API_KEY`.
+/// Without `PIQL_LLM_PROVIDER`, OpenRouter is used when its key is set and the
+/// claude CLI otherwise.
+pub fn provider_from_env() -> Result<Arc<dyn LlmProvider>, String> {
+    provider_from_vars(|name| std::env::var(name).ok())
 }
 
-async fn call_claude_cli(prompt: &str, system: &str) -> Result<String, AppError> {
-    let full_prompt = format!("{}\n\nUser question: {}", system, prompt);
-    let output = tokio::process::Command::new("claude")
-        .args(["-p", &full_prompt])
-        .output()
-        .await
-        .map_err(|e| AppError(format!("Failed to run claude CLI: {}", e)))?;
+fn provider_from_vars(
+    var: impl Fn(&str) -> Option<String>,
+) -> Result<Arc<dyn LlmProvider>, String> {
+    let model = |default: &str| var("PIQL_LLM_MODEL").unwrap_or_else(|| default.to_string());
+    let url = |default: &str| var("PIQL_LLM_URL").unwrap_or_else(|| default.to_string());
+    let key = |name: &str| var(name).ok_or_else(|| format!("{name} is not set"));
+
+    let name = match var("PIQL_LLM_PROVIDER") {
+        Some(name) => name,
+        None if var("OPENROUTER_API_KEY").is_some() => "openrouter".to_string(),
+        None => "claude-cli".to_string(),
+    };
+    Ok(match name.as_str() {
+        "openai" => Arc::new(OpenAiCompatible {
+            base_url: url("https://api.openai.com/v1"),
+            api_key: var("OPENAI_API_KEY"),
+            model: model(DEFAULT_OPENAI_MODEL),
+        }),
+        "openrouter" => Arc::new(OpenAiCompatible {
+            base_url: url("https://openrouter.ai/api/v1"),
+            api_key: Some(key("OPENROUTER_API_KEY")?),
+            model: model(DEFAULT_OPENROUTER_MODEL),
+        }),
+        "anthropic" => Arc::new(Anthropic {
+            api_key: key("ANTHROPIC_API_KEY")?,
+            model: model(DEFAULT_ANTHROPIC_MODEL),
+            base_url: url("https://api.anthropic.com"),
+        }),
+        "local" => Arc::new(LocalHttp {
+            url: url(DEFAULT_LOCAL_URL),
+            model: model(DEFAULT_LOCAL_MODEL),
+        }),
+        "claude-cli" => Arc::new(ClaudeCli),
+        other => return Err(format!("unknown PIQL_LLM_PROVIDER '{other}'")),
+    })
+}
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(AppError(format!("claude CLI failed: {}", stderr)));
+// ============ Query Validation ============
+
+/// A generated query, or the model's last attempt along with its parse errors
+struct GeneratedQuery {
+    /// Pretty-printed query, or the raw model output when it doesn't parse
+    query: String,
+    /// The model's leading `# comment`, if any
+    explanation: Option<String>,
+    /// Parse errors, empty when the query is valid
+    errors: Vec<ParseDiagnostic>,
+    /// Every answer the model gave, in order
+    attempts: Vec<AskAttempt>,
+}
+
+/// Parse the model output, pretty-printing it when valid
+fn check_query(raw: String) -> GeneratedQuery {
+    match piql::advanced::parse_with_comments(&raw) {
+        Ok(expr) => {
+            let explanation = match &expr {
+                SurfaceExpr::Commented { leading, .. } if !leading.is_empty() => Some(
+                    leading
+                        .iter()
+                        .map(|line| line.trim())
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                ),
+                _ => None,
+            };
+            GeneratedQuery {
+                query: piql::advanced::pretty(&expr.strip_comments(), 80),
+                explanation,
+                errors: Vec::new(),
+                attempts: Vec::new(),
+            }
+        }
+        Err(_) => {
+            let errors = piql::advanced::parse_recovering(&raw)
+                .diagnostics
+                .into_iter()
+                .map(Into::into)
+                .collect();
+            GeneratedQuery {
+                query: raw,
+                explanation: None,
+                errors,
+                attempts: Vec::new(),
+            }
+        }
     }
+}
+
+/// Most error-feedback rounds a single `/ask` may request
+pub const MAX_REPAIRS: usize = 5;
 
-    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+/// One model answer and why it was rejected
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct AskAttempt {
+    /// The query as generated (pretty-printed when it parses)
+    pub query: String,
+    /// Parse or evaluation error; absent for the accepted attempt
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
 }
 
-// ============ Query Validation ============
+/// Ask the model to fix a query, showing it what went wrong
+fn repair_prompt(prompt: &str, query: &str, error: &str) -> String {
+    format!(
+        "{prompt}\n\nYour previous query:\n{query}\n\nfailed with:\n{error}\n\n\
+         Reply with a corrected query."
+    )
+}
 
-/// Generate a query, validate it parses, and return pretty-printed. Retries once on failure.
-async fn generate_valid_query(prompt: &str, system: &str) -> Result<String, AppError> {
+/// Generate a query and check it parses.
+///
+/// An unparseable answer is sent back to the model with its error, once or up to
+/// `repairs` times. With `repairs > 0` queries that parse are also planned against
+/// `core`, so unknown columns and type errors are fed back too. The last attempt
+/// is returned (with its parse errors, if any) along with the trace of all of them.
+async fn generate_checked_query(
+    provider: &dyn LlmProvider,
+    core: &ServerCore,
+    prompt: &str,
+    system: &str,
+    repairs: usize,
+) -> Result<GeneratedQuery, AppError> {
     debug!("Generating query for prompt: {}", prompt);
-    let query = generate_query(prompt, system).await?;
-    debug!("LLM returned: {}", query);
+    let max_attempts = 1 + repairs.max(1);
+    let mut attempts = Vec::new();
+    let mut user_prompt = prompt.to_string();
+    loop {
+        let raw = provider.generate(system, &user_prompt).await?;
+        debug!("LLM returned: {}", raw);
+
+        let mut generated = check_query(raw);
+        let error = match generated.errors.first() {
+            Some(error) => Some(format!("parse error: {}", error.message)),
+            None if repairs > 0 => core
+                .explain_query(&generated.query, None)
+                .await
+                .err()
+                .map(|e| e.to_string()),
+            None => None,
+        };
+        attempts.push(AskAttempt {
+            query: generated.query.clone(),
+            error: error.clone(),
+        });
+
+        let Some(error) = error else {
+            info!(
+                "Generated valid query on attempt {} ({} chars)",
+                attempts.len(),
+                generated.query.len()
+            );
+            debug!("Query:\n{}", generated.query);
+            generated.attempts = attempts;
+            return Ok(generated);
+        };
+        if attempts.len() >= max_attempts {
+            warn!("Giving up after {} attempts: {}", attempts.len(), error);
+            generated.attempts = attempts;
+            return Ok(generated);
+        }
+        warn!(
+            "Attempt {} rejected, asking for a fix: {}",
+            attempts.len(),
+            error
+        );
+        user_prompt = repair_prompt(prompt, &generated.query, &error);
+    }
+}
+
+// ============ Chart Suggestions ============
+
+/// Most value columns a suggested chart plots
+const CHART_MAX_SERIES: usize = 4;
 
-    // Try to parse - if it succeeds, return pretty-printed
-    if let Ok(expr) = piql::advanced::parse(&query) {
-        let pretty = piql::advanced::pretty(&expr, 80);
-        info!("Generated valid query ({} chars)", pretty.len());
-        debug!("Query:\n{}", pretty);
-        return Ok(pretty);
+/// How to draw a chart
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ChartKind {
+    /// Values over time or ticks
+    Line,
+    /// Values per category
+    Bar,
+    /// One number against another
+    Scatter,
+    /// Distribution of a single number
+    Histogram,
+}
+
+/// A suggested visualization of a query result
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct ChartSpec {
+    pub kind: ChartKind,
+    /// Column on the horizontal axis
+    pub x: String,
+    /// Columns plotted as values; empty for histograms
+    pub y: Vec<String>,
+    /// Column whose values split the data into series
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+}
+
+/// Tick columns configured in the context, which make good line-chart x axes
+fn time_columns(ctx: &EvalContext) -> Vec<String> {
+    let mut columns: Vec<String> = ctx
+        .dataframes
+        .values()
+        .filter_map(|entry| entry.time_series.as_ref())
+        .map(|ts| ts.tick_column.clone())
+        .chain(
+            ctx.base_tables
+                .values()
+                .map(|t| t.config.tick_column.clone()),
+        )
+        .chain(ctx.default_tick_column.clone())
+        .collect();
+    columns.sort();
+    columns.dedup();
+    columns
+}
+
+/// Pick a chart for a result from its column types.
+///
+/// A temporal or tick column gives a line chart, a text column a bar chart, two
+/// numbers a scatter plot and a lone number a histogram. The first remaining text
+/// column (if any) groups line and bar charts into series.
+pub fn suggest_chart(schema: &Schema, time_columns: &[String]) -> Option<ChartSpec> {
+    let is_time = |name: &str, dtype: &DataType| {
+        dtype.is_temporal() || (dtype.is_integer() && time_columns.iter().any(|c| c == name))
+    };
+    let time = schema
+        .iter()
+        .find(|(name, dtype)| is_time(name, dtype))
+        .map(|(name, _)| name.to_string());
+    let numeric: Vec<String> = schema
+        .iter()
+        .filter(|(name, dtype)| dtype.is_primitive_numeric() && !is_time(name, dtype))
+        .map(|(name, _)| name.to_string())
+        .collect();
+    let categorical: Vec<String> = schema
+        .iter()
+        .filter(|(_, dtype)| {
+            matches!(
+                dtype,
+                DataType::String
+                    | DataType::Boolean
+                    | DataType::Categorical(..)
+                    | DataType::Enum(..)
+            )
+        })
+        .map(|(name, _)| name.to_string())
+        .collect();
+    let series = || numeric.iter().take(CHART_MAX_SERIES).cloned().collect();
+
+    if let Some(x) = time
+        && !numeric.is_empty()
+    {
+        return Some(ChartSpec {
+            kind: ChartKind::Line,
+            x,
+            y: series(),
+            group: categorical.first().cloned(),
+        });
+    }
+    if let Some(x) = categorical.first()
+        && !numeric.is_empty()
+    {
+        return Some(ChartSpec {
+            kind: ChartKind::Bar,
+            x: x.clone(),
+            y: series(),
+            group: categorical.get(1).cloned(),
+        });
+    }
+    match numeric.as_slice() {
+        [x, y, ..] => Some(ChartSpec {
+            kind: ChartKind::Scatter,
+            x: x.clone(),
+            y: vec![y.clone()],
+            group: None,
+        }),
+        [x] => Some(ChartSpec {
+            kind: ChartKind::Histogram,
+            x: x.clone(),
+            y: Vec::new(),
+            group: None,
+        }),
+        [] => None,
     }
+}
+
+// ============ Sessions ============
+
+/// Sessions unused for this long are dropped
+pub const DEFAULT_SESSION_TTL: Duration = Duration::from_secs(30 * 60);
+/// Most recent turns kept per session (and sent with each follow-up)
+const SESSION_MAX_TURNS: usize = 10;
+/// Sessions kept at once; starting another drops the least recently used
+const MAX_SESSIONS: usize = 10_000;
+
+/// A question and the query generated for it
+#[derive(Debug, Clone, PartialEq)]
+pub struct AskTurn {
+    pub question: String,
+    pub query: String,
+}
 
-    // Parse failed - retry once
-    warn!("First query attempt failed to parse, retrying...");
-    let query = generate_query(prompt, system).await?;
-    debug!("LLM retry returned: {}", query);
+struct AskSession {
+    turns: Vec<AskTurn>,
+    last_used: Instant,
+}
+
+/// Prior turns of `/ask` conversations by client and session ID, so follow-up
+/// questions ("now only merchants") see what they follow up on
+///
+/// Keying by client (see [`client_id`]) keeps one caller from reading or deleting
+/// another's conversation by sending the same session ID.
+pub struct AskSessions {
+    ttl: Duration,
+    sessions: Mutex<HashMap<(String, String), AskSession>>,
+}
 
-    // Validate the retry and pretty-print
-    let expr = piql::advanced::parse(&query).map_err(|e| {
-        warn!("Retry also failed: {}", e);
-        AppError(format!("Generated invalid PiQL after retry: {}", e))
-    })?;
+impl AskSessions {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
 
-    let pretty = piql::advanced::pretty(&expr, 80);
-    info!("Generated valid query on retry ({} chars)", pretty.len());
-    debug!("Query:\n{}", pretty);
-    Ok(pretty)
+    /// Turns `client` recorded in session `id` so far (empty for new or expired sessions)
+    pub async fn history(&self, client: &str, id: &str) -> Vec<AskTurn> {
+        let mut sessions = self.sessions.lock().await;
+        self.evict_expired(&mut sessions);
+        sessions
+            .get(&session_key(client, id))
+            .map(|session| session.turns.clone())
+            .unwrap_or_default()
+    }
+
+    /// Append a turn to `client`'s session `id`, starting the session if needed
+    pub async fn record(&self, client: &str, id: &str, turn: AskTurn) {
+        let key = session_key(client, id);
+        let mut sessions = self.sessions.lock().await;
+        self.evict_expired(&mut sessions);
+        if !sessions.contains_key(&key)
+            && sessions.len() >= MAX_SESSIONS
+            && let Some(oldest) = sessions
+                .iter()
+                .min_by_key(|(_, session)| session.last_used)
+                .map(|(key, _)| key.clone())
+        {
+            sessions.remove(&oldest);
+        }
+        let session = sessions.entry(key).or_insert_with(|| AskSession {
+            turns: Vec::new(),
+            last_used: Instant::now(),
+        });
+        session.turns.push(turn);
+        if session.turns.len() > SESSION_MAX_TURNS {
+            session.turns.remove(0);
+        }
+        session.last_used = Instant::now();
+    }
+
+    /// Forget `client`'s session `id`; false if it didn't exist
+    pub async fn remove(&self, client: &str, id: &str) -> bool {
+        let mut sessions = self.sessions.lock().await;
+        self.evict_expired(&mut sessions);
+        sessions.remove(&session_key(client, id)).is_some()
+    }
+
+    fn evict_expired(&self, sessions: &mut HashMap<(String, String), AskSession>) {
+        sessions.retain(|_, session| session.last_used.elapsed() < self.ttl);
+    }
+}
+
+fn session_key(client: &str, id: &str) -> (String, String) {
+    (client.to_string(), id.to_string())
+}
+
+impl Default for AskSessions {
+    fn default() -> Self {
+        Self::new(DEFAULT_SESSION_TTL)
+    }
+}
+
+/// The user prompt: the question, preceded by the earlier turns of its session
+fn prompt_with_history(question: &str, history: &[AskTurn]) -> String {
+    if history.is_empty() {
+        return question.to_string();
+    }
+    let mut prompt = String::from(
+        "Earlier questions in this conversation and the queries that answered them:\n\n",
+    );
+    for turn in history {
+        prompt.push_str(&format!(
+            "Question: {}\nQuery: {}\n\n",
+            turn.question, turn.query
+        ));
+    }
+    prompt.push_str(&format!(
+        "New question (it may refine or refer to the earlier ones): {question}"
+    ));
+    prompt
+}
+
+// ============ Usage Limits ============
+
+const MINUTE: Duration = Duration::from_secs(60);
+const DAY_SECS: u64 = 86_400;
+/// Clients tracked at once; a new one first drops those with nothing left to
+/// limit, then the least recently seen
+const MAX_CLIENTS: usize = 10_000;
+
+/// Tokens in a text, estimated at four characters per token
+fn estimate_tokens(text: &str) -> u64 {
+    (text.chars().count() as u64).div_ceil(4)
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Caps on `/ask` usage, applied to each client separately
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AskLimits {
+    /// Questions per rolling minute
+    pub requests_per_minute: Option<u32>,
+    /// Estimated LLM tokens per UTC day
+    pub daily_tokens: Option<u64>,
+    /// Price used for the cost estimate reported by `/ask/usage`
+    pub usd_per_million_tokens: f64,
+}
+
+/// Counters for one client
+#[derive(Default)]
+struct ClientUsage {
+    /// When the questions of the last minute were admitted
+    recent: VecDeque<Instant>,
+    /// When the client last asked or was charged tokens
+    last_seen: Option<Instant>,
+    /// UTC day (since the epoch) the `day_*` counters belong to
+    day: u64,
+    day_requests: u64,
+    day_tokens: u64,
+    total_requests: u64,
+    total_tokens: u64,
+}
+
+impl ClientUsage {
+    /// Forget questions older than a minute and reset the daily counters on a new day
+    fn roll(&mut self, now: Instant, unix: u64) {
+        while self
+            .recent
+            .front()
+            .is_some_and(|t| now.duration_since(*t) >= MINUTE)
+        {
+            self.recent.pop_front();
+        }
+        let day = unix / DAY_SECS;
+        if day != self.day {
+            self.day = day;
+            self.day_requests = 0;
+            self.day_tokens = 0;
+        }
+    }
+}
+
+/// One client's `/ask` usage
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ClientUsageReport {
+    /// `key:<hash of the credential>`, `ip:<address>` or `anonymous`
+    pub client: String,
+    pub requests_last_minute: usize,
+    pub requests_today: u64,
+    /// Estimated LLM tokens since UTC midnight
+    pub tokens_today: u64,
+    pub total_requests: u64,
+    pub total_tokens: u64,
+    /// `total_tokens` at the configured price
+    pub estimated_cost_usd: f64,
+}
+
+/// `/ask` requests and estimated tokens per client, enforcing [`AskLimits`]
+pub struct AskUsage {
+    limits: AskLimits,
+    clients: Mutex<HashMap<String, ClientUsage>>,
+}
+
+impl AskUsage {
+    pub fn new(limits: AskLimits) -> Self {
+        Self {
+            limits,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn limits(&self) -> AskLimits {
+        self.limits
+    }
+
+    /// Count a question from `client`, or return how long until it may ask again
+    pub async fn admit(&self, client: &str) -> Result<(), Duration> {
+        self.admit_at(client, Instant::now(), unix_secs()).await
+    }
+
+    async fn admit_at(&self, client: &str, now: Instant, unix: u64) -> Result<(), Duration> {
+        let mut clients = self.clients.lock().await;
+        let usage = Self::usage(&mut clients, client, now, unix);
+        if let Some(budget) = self.limits.daily_tokens
+            && usage.day_tokens >= budget
+        {
+            return Err(Duration::from_secs(DAY_SECS - unix % DAY_SECS));
+        }
+        if let Some(rpm) = self.limits.requests_per_minute
+            && usage.recent.len() >= rpm as usize
+            && let Some(oldest) = usage.recent.front()
+        {
+            return Err(MINUTE.saturating_sub(now.duration_since(*oldest)));
+        }
+        usage.recent.push_back(now);
+        usage.day_requests += 1;
+        usage.total_requests += 1;
+        Ok(())
+    }
+
+    /// Add the estimated tokens spent answering `client`
+    pub async fn record(&self, client: &str, tokens: u64) {
+        self.record_at(client, tokens, Instant::now(), unix_secs())
+            .await;
+    }
+
+    async fn record_at(&self, client: &str, tokens: u64, now: Instant, unix: u64) {
+        let mut clients = self.clients.lock().await;
+        let usage = Self::usage(&mut clients, client, now, unix);
+        usage.day_tokens += tokens;
+        usage.total_tokens += tokens;
+    }
+
+    /// Counters of `client`, rolled to `now` and making room for it if it is new
+    fn usage<'a>(
+        clients: &'a mut HashMap<String, ClientUsage>,
+        client: &str,
+        now: Instant,
+        unix: u64,
+    ) -> &'a mut ClientUsage {
+        if !clients.contains_key(client) && clients.len() >= MAX_CLIENTS {
+            clients.retain(|_, usage| {
+                usage.roll(now, unix);
+                !usage.recent.is_empty() || usage.day_tokens > 0
+            });
+            if clients.len() >= MAX_CLIENTS
+                && let Some(oldest) = clients
+                    .iter()
+                    .min_by_key(|(_, usage)| usage.last_seen)
+                    .map(|(client, _)| client.clone())
+            {
+                clients.remove(&oldest);
+            }
+        }
+        let usage = clients.entry(client.to_string()).or_default();
+        usage.roll(now, unix);
+        usage.last_seen = Some(now);
+        usage
+    }
+
+    /// Usage of `client` (if seen), or with `None` of every client seen so far,
+    /// sorted by client
+    pub async fn report(&self, client: Option<&str>) -> Vec<ClientUsageReport> {
+        let now = Instant::now();
+        let unix = unix_secs();
+        let mut clients = self.clients.lock().await;
+        let mut report: Vec<_> = clients
+            .iter_mut()
+            .filter(|(name, _)| client.is_none_or(|client| client == name.as_str()))
+            .map(|(client, usage)| {
+                usage.roll(now, unix);
+                ClientUsageReport {
+                    client: client.clone(),
+                    requests_last_minute: usage.recent.len(),
+                    requests_today: usage.day_requests,
+                    tokens_today: usage.day_tokens,
+                    total_requests: usage.total_requests,
+                    total_tokens: usage.total_tokens,
+                    estimated_cost_usd: usage.total_tokens as f64 / 1e6
+                        * self.limits.usd_per_million_tokens,
+                }
+            })
+            .collect();
+        report.sort_by(|a, b| a.client.cmp(&b.client));
+        report
+    }
+}
+
+impl Default for AskUsage {
+    fn default() -> Self {
+        Self::new(AskLimits::default())
+    }
+}
+
+/// Who is asking: a hash of the credential the request was authenticated with,
+/// else the peer address
+///
+/// Only credentials checked by [`crate::auth::require_auth`] count: without auth
+/// configured, any header a client sends would give it a fresh set of limits.
+fn client_id(credential: Option<&str>, peer: Option<SocketAddr>) -> String {
+    if let Some(credential) = credential {
+        let mut hasher = DefaultHasher::new();
+        credential.hash(&mut hasher);
+        return format!("key:{:016x}", hasher.finish());
+    }
+    peer.map_or_else(
+        || "anonymous".to_string(),
+        |addr| format!("ip:{}", addr.ip()),
+    )
+}
+
+/// Wraps a provider, adding up the estimated tokens of its calls
+struct Metered<'a> {
+    inner: &'a dyn LlmProvider,
+    tokens: AtomicU64,
+}
+
+impl LlmProvider for Metered<'_> {
+    fn generate<'a>(
+        &'a self,
+        system: &'a str,
+        prompt: &'a str,
+    ) -> BoxFuture<'a, Result<String, AppError>> {
+        Box::pin(async move {
+            let reply = self.inner.generate(system, prompt).await?;
+            let tokens =
+                estimate_tokens(system) + estimate_tokens(prompt) + estimate_tokens(&reply);
+            self.tokens.fetch_add(tokens, Ordering::Relaxed);
+            Ok(reply)
+        })
+    }
 }
 
 // ============ HTTP Handler ============
@@ -337,43 +1270,205 @@ pub struct AskParams {
     /// Execute the generated query and return results
     #[serde(default)]
     pub execute: bool,
+    /// Check generated queries against the data and send failures back to the
+    /// model for up to this many fixes (capped at 5)
+    #[serde(default)]
+    pub repairs: usize,
+    /// Suggest how to chart the result (JSON responses with `?execute=true`)
+    #[serde(default)]
+    pub chart: bool,
+}
+
+/// JSON body of `POST /ask` (with `Accept: application/json`)
+#[derive(Serialize, ToSchema)]
+pub struct AskResponse {
+    /// Generated query; the model's raw output when it doesn't parse
+    pub query: String,
+    /// What the model says the query does
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub explanation: Option<String>,
+    /// Result rows (only with `?execute=true`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Vec<Object>>)]
+    pub rows: Option<Vec<serde_json::Map<String, serde_json::Value>>>,
+    /// Result columns and row count (only with `?execute=true`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema: Option<TableSchema>,
+    /// Parse errors of the generated query, empty when it is valid
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<ParseDiagnostic>,
+    /// Why executing the query failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Suggested visualization of the result (only with `?execute=true&chart=true`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chart: Option<ChartSpec>,
+    /// Every query the model produced and why the rejected ones failed
+    pub attempts: Vec<AskAttempt>,
 }
 
 /// Natural language to PiQL query
+///
+/// Requests from one client with the same `X-Piql-Session` header form a
+/// conversation: earlier questions and their queries are sent along, so follow-ups
+/// can refine them.
+///
+/// With `Accept: application/json` the response is an [`AskResponse`]; a generated
+/// query that fails to parse or execute comes back with status 400 and its
+/// diagnostics. Otherwise the query is in the `X-Piql-Query` header and the body is
+/// the Arrow IPC result (empty unless `?execute=true`).
+///
+/// `?repairs=N` plans each generated query against the loaded data and sends
+/// failures back to the model for up to N fixes; the JSON `attempts` field lists
+/// every query tried and its error.
+///
+/// `?chart=true` (JSON, with `?execute=true`) adds a chart suggestion picked from
+/// the result's column types.
+///
+/// Clients (by authenticated credential, else address) over the configured
+/// [`AskLimits`] get status 429 with a `Retry-After` header.
 #[utoipa::path(
     post,
     path = "/ask",
     request_body(content = String, content_type = "text/plain", description = "Natural language question"),
-    params(AskParams),
+    params(
+        AskParams,
+        ("X-Piql-Session" = Option<String>, Header, description = "Conversation ID; earlier turns are included in the prompt")
+    ),
     responses(
-        (status = 200, description = "Generated query (in X-Piql-Query header) and optionally results"),
-        (status = 400, description = "Error")
+        (status = 200, description = "Generated query and optionally results", content(
+            (AskResponse = "application/json"),
+            (Vec<u8> = "application/vnd.apache.arrow.stream")
+        )),
+        (status = 400, description = "Error, or (JSON) the generated query with diagnostics", body = AskResponse),
+        (status = 429, description = "Rate limit or daily token budget exceeded; see `Retry-After`", body = ErrorResponse)
     )
 )]
 pub async fn ask(
     State(core): State<Arc<ServerCore>>,
     Query(params): Query<AskParams>,
+    peer: Option<Extension<ConnectInfo<SocketAddr>>>,
+    credential: Option<Extension<Credential>>,
+    headers: HeaderMap,
     body: String,
-) -> Result<impl IntoResponse, AppError> {
+) -> Result<Response, AppError> {
     info!("POST /ask: {}", body);
+    let client = client_id(
+        credential
+            .as_ref()
+            .map(|Extension(Credential(c))| c.as_str()),
+        peer.map(|Extension(ConnectInfo(addr))| addr),
+    );
+    if let Err(retry) = core.ask_usage().admit(&client).await {
+        let secs = retry.as_secs_f64().ceil().max(1.0) as u64;
+        warn!("/ask limit reached for {client}, retry in {secs}s");
+        return Ok((
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, secs.to_string())],
+            Json(ErrorResponse {
+                error: format!("/ask limit reached; retry in {secs}s"),
+            }),
+        )
+            .into_response());
+    }
+    let wants_json = headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("application/json"));
+    let session = headers
+        .get(SESSION_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    let history = match &session {
+        Some(id) => core.ask_sessions().history(&client, id).await,
+        None => Vec::new(),
+    };
 
     // Get schema info and samples for the prompt
     let state = core.state();
     let ctx = state.ctx.read().await;
-    let (schema_info, examples) = get_schema_and_examples(&ctx).await;
+    let versions = state.table_versions().await;
+    let (schema_info, examples) =
+        get_schema_and_examples(&ctx, &versions, core.ask_summaries()).await;
+    let time_columns = time_columns(&ctx);
     drop(ctx);
 
     let system_prompt = build_system_prompt(&schema_info, &examples);
     info!("Full system prompt:\n{}", system_prompt);
 
-    // Generate query with retry on parse failure
-    let query = generate_valid_query(&body, &system_prompt).await?;
+    // Generate query, feeding errors back to the model
+    let prompt = prompt_with_history(&body, &history);
+    let provider = core.llm_provider().clone();
+    let repairs = params.repairs.min(MAX_REPAIRS);
+    let metered = Metered {
+        inner: provider.as_ref(),
+        tokens: AtomicU64::new(0),
+    };
+    let generated = generate_checked_query(&metered, &core, &prompt, &system_prompt, repairs).await;
+    let tokens = metered.tokens.load(Ordering::Relaxed);
+    core.ask_usage().record(&client, tokens).await;
+    let generated = generated?;
+    let rejected = generated
+        .attempts
+        .last()
+        .and_then(|attempt| attempt.error.clone());
+    if let Some(id) = &session
+        && rejected.is_none()
+    {
+        let turn = AskTurn {
+            question: body.clone(),
+            query: generated.query.clone(),
+        };
+        core.ask_sessions().record(&client, id, turn).await;
+    }
+
+    if wants_json {
+        let mut response = AskResponse {
+            query: generated.query,
+            explanation: generated.explanation,
+            rows: None,
+            schema: None,
+            errors: generated.errors,
+            error: None,
+            chart: None,
+            attempts: generated.attempts,
+        };
+        if response.errors.is_empty() && rejected.is_some() {
+            response.error = rejected;
+        } else if response.errors.is_empty() && params.execute {
+            match core.execute_query(&response.query).await {
+                Ok(df) => {
+                    response.rows = Some(dataframe_to_json_rows(&df));
+                    response.schema = Some(TableSchema::from_df("result", &df));
+                    if params.chart {
+                        response.chart = suggest_chart(df.schema(), &time_columns);
+                    }
+                }
+                Err(e) => response.error = Some(e.to_string()),
+            }
+        }
+        let status = if response.errors.is_empty() && response.error.is_none() {
+            StatusCode::OK
+        } else {
+            StatusCode::BAD_REQUEST
+        };
+        return Ok((status, Json(response)).into_response());
+    }
+
+    if let Some(error) = generated.errors.first() {
+        return Err(AppError::bad_request(format!(
+            "Generated invalid PiQL after {} attempts: {}",
+            generated.attempts.len(),
+            error.message
+        )));
+    }
+    let query = generated.query;
 
     let response_body = if params.execute {
         let df = core.execute_query(&query).await?;
         dataframe_to_ipc_bytes(df)
             .await
-            .map_err(|e| AppError(e.to_string()))?
+            .map_err(|e| AppError::bad_request(e.to_string()))?
     } else {
         Vec::new()
     };
@@ -391,5 +1486,393 @@ pub async fn ask(
             ),
         ],
         response_body,
-    ))
+    )
+        .into_response())
+}
+
+/// Forget one of the caller's `/ask` conversations
+#[utoipa::path(
+    delete,
+    path = "/ask/sessions/{id}",
+    params(("id" = String, Path, description = "Session ID sent as `X-Piql-Session`")),
+    responses(
+        (status = 204, description = "Session removed"),
+        (status = 400, description = "Unknown or expired session", body = ErrorResponse)
+    )
+)]
+pub async fn delete_session(
+    State(core): State<Arc<ServerCore>>,
+    Path(id): Path<String>,
+    peer: Option<Extension<ConnectInfo<SocketAddr>>>,
+    credential: Option<Extension<Credential>>,
+) -> Result<StatusCode, AppError> {
+    info!("DELETE /ask/sessions/{id}");
+    let client = client_id(
+        credential
+            .as_ref()
+            .map(|Extension(Credential(c))| c.as_str()),
+        peer.map(|Extension(ConnectInfo(addr))| addr),
+    );
+    if !core.ask_sessions().remove(&client, &id).await {
+        return Err(AppError::bad_request(format!("Unknown session: {id}")));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Body of `GET /ask/usage`
+#[derive(Serialize, ToSchema)]
+pub struct AskUsageResponse {
+    /// Questions allowed per client per minute (absent = unlimited)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub requests_per_minute: Option<u32>,
+    /// Estimated tokens allowed per client per UTC day (absent = unlimited)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub daily_tokens: Option<u64>,
+    pub clients: Vec<ClientUsageReport>,
+}
+
+/// `/ask` usage and limits per client
+///
+/// Token counts are estimates (four characters per token) of everything sent to and
+/// received from the model. A credential with only read scope sees its own usage;
+/// the other clients' are listed for write scope (or with authentication off).
+#[utoipa::path(
+    get,
+    path = "/ask/usage",
+    responses((status = 200, description = "Usage per client", body = AskUsageResponse))
+)]
+pub async fn usage(
+    State(core): State<Arc<ServerCore>>,
+    peer: Option<Extension<ConnectInfo<SocketAddr>>>,
+    credential: Option<Extension<Credential>>,
+    scope: Option<Extension<Scope>>,
+) -> Json<AskUsageResponse> {
+    let limits = core.ask_usage().limits();
+    // Other clients' keys and addresses are only for credentials that may write
+    let client = scope
+        .is_some_and(|Extension(scope)| scope < Scope::Write)
+        .then(|| {
+            client_id(
+                credential
+                    .as_ref()
+                    .map(|Extension(Credential(c))| c.as_str()),
+                peer.map(|Extension(ConnectInfo(addr))| addr),
+            )
+        });
+    Json(AskUsageResponse {
+        requests_per_minute: limits.requests_per_minute,
+        daily_tokens: limits.daily_tokens,
+        clients: core.ask_usage().report(client.as_deref()).await,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn column_summary_lists_dtypes_ranges_and_top_values() {
+        let df = df! {
+            "name" => &[Some("a"), Some("b"), Some("b"), None],
+            "gold" => &[10i64, 250, 50, 40],
+        }
+        .unwrap();
+        let summary = column_summary(df.lazy()).unwrap();
+        assert_eq!(
+            summary,
+            "- name: str, 25% null, top values \"b\", \"a\"\n- gold: i64, 0% null, min 10, max 250\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn schema_summaries_are_capped_and_cached_per_version() {
+        let mut ctx = EvalContext::new();
+        for i in 0..PROMPT_MAX_TABLES + 3 {
+            let df = df! { "gold" => &[i as i64] }.unwrap();
+            ctx = ctx.with_df(format!("t{i:02}"), df.lazy());
+        }
+        let mut versions = HashMap::new();
+        let summaries = TableSummaries::default();
+        let (schema_info, _) = get_schema_and_examples(&ctx, &versions, &summaries).await;
+        assert!(schema_info.contains("## t00\n"));
+        assert!(!schema_info.contains(&format!("## t{PROMPT_MAX_TABLES}\n")));
+        assert!(schema_info.contains("... and 3 more tables"));
+        assert!(schema_info.len() <= PROMPT_MAX_SCHEMA_BYTES + 100);
+
+        // Summaries are reused until the table's version changes
+        let ctx = ctx.with_df("t00", df! { "coins" => &[1i64] }.unwrap().lazy());
+        let (schema_info, _) = get_schema_and_examples(&ctx, &versions, &summaries).await;
+        assert!(!schema_info.contains("coins"));
+        versions.insert("t00".to_string(), 1);
+        let (schema_info, _) = get_schema_and_examples(&ctx, &versions, &summaries).await;
+        assert!(schema_info.contains("coins"));
+    }
+
+    #[test]
+    fn check_query_pretty_prints_and_keeps_explanation() {
+        let generated =
+            check_query("# richest first\nentities.sort(\"gold\", descending=True)".into());
+        assert!(generated.errors.is_empty());
+        assert_eq!(generated.query, "entities.sort(\"gold\", descending=True)");
+        assert_eq!(generated.explanation.as_deref(), Some("richest first"));
+
+        let generated = check_query("entities.filter($gold >".into());
+        assert_eq!(generated.query, "entities.filter($gold >");
+        assert!(!generated.errors.is_empty());
+    }
+
+    #[tokio::test]
+    async fn sessions_keep_recent_turns_and_expire() {
+        let sessions = AskSessions::default();
+        for i in 0..SESSION_MAX_TURNS + 2 {
+            let turn = AskTurn {
+                question: format!("q{i}"),
+                query: format!("t.head({i})"),
+            };
+            sessions.record("ip:1", "s", turn).await;
+        }
+        let history = sessions.history("ip:1", "s").await;
+        assert_eq!(history.len(), SESSION_MAX_TURNS);
+        assert_eq!(history[0].question, "q2");
+        assert!(sessions.history("ip:1", "other").await.is_empty());
+        // Another client sending the same session ID neither sees nor deletes it
+        assert!(sessions.history("ip:2", "s").await.is_empty());
+        assert!(!sessions.remove("ip:2", "s").await);
+        assert!(sessions.remove("ip:1", "s").await);
+        assert!(!sessions.remove("ip:1", "s").await);
+
+        let sessions = AskSessions::new(Duration::ZERO);
+        let turn = AskTurn {
+            question: "q".into(),
+            query: "t".into(),
+        };
+        sessions.record("ip:1", "s", turn).await;
+        assert!(sessions.history("ip:1", "s").await.is_empty());
+    }
+
+    /// Replies with canned answers in order, recording the prompts it was sent
+    struct Scripted {
+        replies: std::sync::Mutex<Vec<&'static str>>,
+        prompts: std::sync::Mutex<Vec<String>>,
+    }
+
+    impl LlmProvider for Scripted {
+        fn generate<'a>(
+            &'a self,
+            _system: &'a str,
+            prompt: &'a str,
+        ) -> BoxFuture<'a, Result<String, AppError>> {
+            self.prompts.lock().unwrap().push(prompt.to_string());
+            let reply = self.replies.lock().unwrap().remove(0);
+            Box::pin(async move { Ok(reply.to_string()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn eval_errors_are_fed_back_for_repair() {
+        let core = ServerCore::new();
+        let df = df! { "gold" => &[1i64, 2] }.unwrap();
+        core.insert_df("entities", df).await;
+        let provider = Scripted {
+            replies: std::sync::Mutex::new(vec![
+                "entities.filter($gold >",
+                "entities.select($coins)",
+                "entities.select($gold)",
+            ]),
+            prompts: std::sync::Mutex::new(Vec::new()),
+        };
+
+        let Ok(generated) = generate_checked_query(&provider, &core, "gold?", "", 2).await else {
+            panic!("generation failed");
+        };
+        assert_eq!(generated.query, "entities.select($gold)");
+        let errors: Vec<_> = generated
+            .attempts
+            .iter()
+            .map(|a| a.error.is_some())
+            .collect();
+        assert_eq!(errors, [true, true, false]);
+        let prompts = provider.prompts.lock().unwrap();
+        assert!(prompts[2].contains("entities.select($coins)"));
+        assert!(prompts[2].contains("coins"));
+    }
+
+    #[test]
+    fn chart_suggestions_follow_column_types() {
+        let df = df! {
+            "tick" => &[1i64, 2],
+            "name" => &["a", "b"],
+            "gold" => &[10i64, 20],
+        }
+        .unwrap();
+        let ticks = ["tick".to_string()];
+        let chart = suggest_chart(df.schema(), &ticks).unwrap();
+        assert_eq!(chart.kind, ChartKind::Line);
+        assert_eq!(
+            (chart.x.as_str(), chart.y.as_slice()),
+            ("tick", &["gold".to_string()][..])
+        );
+        assert_eq!(chart.group.as_deref(), Some("name"));
+
+        let chart = suggest_chart(df.schema(), &[]).unwrap();
+        assert_eq!(chart.kind, ChartKind::Bar);
+        assert_eq!(chart.x, "name");
+        assert_eq!(chart.y, ["tick", "gold"]);
+
+        let gold = df.select(["gold"]).unwrap();
+        assert_eq!(
+            suggest_chart(gold.schema(), &[]).map(|c| c.kind),
+            Some(ChartKind::Histogram)
+        );
+        let names = df.select(["name"]).unwrap();
+        assert_eq!(suggest_chart(names.schema(), &[]), None);
+    }
+
+    #[tokio::test]
+    async fn usage_limits_requests_and_daily_tokens() {
+        let usage = AskUsage::new(AskLimits {
+            requests_per_minute: Some(2),
+            daily_tokens: Some(100),
+            usd_per_million_tokens: 10.0,
+        });
+        let start = Instant::now();
+        let noon = 20_000 * DAY_SECS + DAY_SECS / 2;
+
+        assert_eq!(usage.admit_at("a", start, noon).await, Ok(()));
+        assert_eq!(usage.admit_at("a", start, noon).await, Ok(()));
+        let retry = usage
+            .admit_at("a", start + Duration::from_secs(15), noon)
+            .await;
+        assert_eq!(retry, Err(Duration::from_secs(45)));
+        // Other clients have their own allowance
+        assert_eq!(usage.admit_at("b", start, noon).await, Ok(()));
+        // The window rolls after a minute
+        let later = start + MINUTE;
+        assert_eq!(usage.admit_at("a", later, noon).await, Ok(()));
+
+        usage.record_at("a", 100, later, noon).await;
+        let retry = usage.admit_at("a", later + MINUTE, noon).await;
+        assert_eq!(retry, Err(Duration::from_secs(DAY_SECS / 2)));
+
+        let report = usage.report(None).await;
+        assert_eq!(report[0].client, "a");
+        assert_eq!(report[0].total_requests, 3);
+        assert!((report[0].estimated_cost_usd - 0.001).abs() < 1e-12);
+        assert_eq!(report.len(), 2);
+        let report = usage.report(Some("b")).await;
+        assert_eq!(report.len(), 1);
+        assert_eq!(report[0].client, "b");
+        assert!(usage.report(Some("c")).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn read_scope_sees_only_its_own_usage() {
+        let core = Arc::new(ServerCore::new());
+        let reader = client_id(Some("reader"), None);
+        for client in [reader.as_str(), "ip:10.0.0.1"] {
+            core.ask_usage().admit(client).await.unwrap();
+        }
+        let credential = || Some(Extension(Credential("reader".into())));
+
+        let Json(report) = usage(
+            State(core.clone()),
+            None,
+            credential(),
+            Some(Extension(Scope::Read)),
+        )
+        .await;
+        let clients: Vec<_> = report.clients.iter().map(|r| r.client.as_str()).collect();
+        assert_eq!(clients, [reader.as_str()]);
+
+        let Json(report) = usage(
+            State(core),
+            None,
+            credential(),
+            Some(Extension(Scope::Write)),
+        )
+        .await;
+        assert_eq!(report.clients.len(), 2);
+    }
+
+    #[test]
+    fn clients_are_keyed_by_credential_then_address() {
+        let peer: SocketAddr = "10.0.0.1:5000".parse().unwrap();
+        assert_eq!(client_id(None, Some(peer)), "ip:10.0.0.1");
+        assert_eq!(client_id(None, None), "anonymous");
+        let id = client_id(Some("secret"), Some(peer));
+        assert!(id.starts_with("key:") && !id.contains("secret"));
+    }
+
+    #[tokio::test]
+    async fn tracked_clients_and_sessions_are_capped() {
+        let usage = AskUsage::default();
+        let now = Instant::now();
+        for i in 0..=MAX_CLIENTS {
+            usage.admit_at(&format!("ip:{i}"), now, 0).await.unwrap();
+        }
+        let report = usage.report(None).await;
+        assert_eq!(report.len(), MAX_CLIENTS);
+        assert!(
+            report
+                .iter()
+                .any(|r| r.client == format!("ip:{MAX_CLIENTS}"))
+        );
+
+        let sessions = AskSessions::default();
+        let turn = AskTurn {
+            question: "richest?".into(),
+            query: "entities".into(),
+        };
+        for i in 0..=MAX_SESSIONS {
+            sessions.record("ip:1", &i.to_string(), turn.clone()).await;
+        }
+        assert_eq!(sessions.sessions.lock().await.len(), MAX_SESSIONS);
+        let newest = MAX_SESSIONS.to_string();
+        assert_eq!(sessions.history("ip:1", &newest).await.len(), 1);
+    }
+
+    #[test]
+    fn prompt_includes_earlier_turns() {
+        assert_eq!(prompt_with_history("richest?", &[]), "richest?");
+        let history = [AskTurn {
+            question: "richest?".into(),
+            query: "entities.top(5, \"gold\")".into(),
+        }];
+        let prompt = prompt_with_history("now only merchants", &history);
+        assert!(prompt.contains("Question: richest?\nQuery: entities.top(5, \"gold\")"));
+        assert!(prompt.ends_with("now only merchants"));
+    }
+
+    #[test]
+    fn provider_selection_from_env() {
+        fn vars(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+            let pairs: HashMap<String, String> = pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            move |name| pairs.get(name).cloned()
+        }
+        assert!(provider_from_vars(vars(&[])).is_ok());
+        assert!(provider_from_vars(vars(&[("OPENROUTER_API_KEY", "k")])).is_ok());
+        assert!(provider_from_vars(vars(&[("PIQL_LLM_PROVIDER", "local")])).is_ok());
+        assert!(provider_from_vars(vars(&[("PIQL_LLM_PROVIDER", "openai")])).is_ok());
+
+        let err = provider_from_vars(vars(&[("PIQL_LLM_PROVIDER", "anthropic")])).err();
+        assert_eq!(err.as_deref(), Some("ANTHROPIC_API_KEY is not set"));
+        let err = provider_from_vars(vars(&[("PIQL_LLM_PROVIDER", "gpt")])).err();
+        assert_eq!(err.as_deref(), Some("unknown PIQL_LLM_PROVIDER 'gpt'"));
+    }
+
+    #[test]
+    fn docs_list_methods_from_capabilities() {
+        let docs = piql_docs();
+        assert!(docs.contains("**str namespace**"));
+        assert!(docs.contains("`sort` (descending=)"));
+        assert!(docs.contains("`when`/`then`/`otherwise`"));
+        for (_, methods) in piql::capabilities() {
+            for m in methods {
+                assert!(docs.contains(&format!("`{}`", m.name)), "{}", m.name);
+            }
+        }
+    }
 }
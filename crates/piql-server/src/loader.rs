@@ -1,44 +1,894 @@
 //! File loading utilities
 
 use std::collections::HashMap;
+use std::io::Read;
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
+use polars::io::cloud::CloudOptions;
 use polars::prelude::*;
 
+/// Compression around a data file, detected from its last extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// `.gz`
+    Gzip,
+    /// `.zst`
+    Zstd,
+}
+
+impl Compression {
+    /// Decompress a whole file
+    pub fn decompress(self, bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Self::Gzip => {
+                let mut out = Vec::new();
+                flate2::read::MultiGzDecoder::new(bytes).read_to_end(&mut out)?;
+                Ok(out)
+            }
+            Self::Zstd => zstd::stream::decode_all(bytes),
+        }
+    }
+}
+
+/// Format and compression of a file from its extensions, e.g. `ticks.csv.gz`
+pub fn file_format(path: &Path) -> Option<(DataFormat, Option<Compression>)> {
+    let name = path.file_name()?.to_str()?;
+    let (rest, compression) = match name.rsplit_once('.')? {
+        (rest, "gz") => (rest, Some(Compression::Gzip)),
+        (rest, "zst") => (rest, Some(Compression::Zstd)),
+        _ => (name, None),
+    };
+    let format = match rest.rsplit_once('.')?.1 {
+        "parquet" => DataFormat::Parquet,
+        "csv" => DataFormat::Csv,
+        "ipc" | "arrow" => DataFormat::IpcFile,
+        "json" => DataFormat::Json,
+        "ndjson" | "jsonl" => DataFormat::NdJson,
+        _ => return None,
+    };
+    Some((format, compression))
+}
+
+/// Sidecar holding per-directory loader settings, next to the data files
+pub const SIDECAR_FILE: &str = "piql.toml";
+
+/// How to parse CSV files. Unset fields fall back to the less specific settings
+/// (file section of `piql.toml` → its `[csv]` section → CLI → Polars defaults).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CsvOptions {
+    /// Field separator
+    pub delimiter: Option<u8>,
+    /// Whether the first row names the columns (default true)
+    pub has_header: Option<bool>,
+    /// Lines to skip before the header
+    pub skip_rows: Option<usize>,
+    /// Column types to parse instead of inferring them
+    pub dtypes: Schema,
+}
+
+impl CsvOptions {
+    /// `self` with the fields set in `over` replaced
+    fn merged(&self, over: &CsvOptions) -> CsvOptions {
+        let mut dtypes = self.dtypes.clone();
+        for (name, dtype) in over.dtypes.iter() {
+            dtypes.with_column(name.clone(), dtype.clone());
+        }
+        CsvOptions {
+            delimiter: over.delimiter.or(self.delimiter),
+            has_header: over.has_header.or(self.has_header),
+            skip_rows: over.skip_rows.or(self.skip_rows),
+            dtypes,
+        }
+    }
+
+    /// Read a `piql.toml` section:
+    /// `{ delimiter = ";", has_header = false, skip_rows = 2, dtypes = { id = "str" } }`
+    fn from_toml(item: &toml_edit::Item) -> Result<Self, String> {
+        let table = item
+            .as_table_like()
+            .ok_or_else(|| "expected a table".to_string())?;
+        let mut options = CsvOptions::default();
+        for (key, value) in table.iter() {
+            match key {
+                "delimiter" => {
+                    let delimiter = value.as_str().ok_or("delimiter must be a string")?;
+                    options.delimiter = Some(parse_delimiter(delimiter)?);
+                }
+                "has_header" => {
+                    options.has_header =
+                        Some(value.as_bool().ok_or("has_header must be true or false")?);
+                }
+                "skip_rows" => {
+                    let rows = value.as_integer().ok_or("skip_rows must be an integer")?;
+                    options.skip_rows =
+                        Some(usize::try_from(rows).map_err(|_| "skip_rows must be >= 0")?);
+                }
+                "dtypes" => {
+                    let dtypes = value
+                        .as_table_like()
+                        .ok_or("dtypes must be a table of column = \"type\"")?;
+                    for (column, dtype) in dtypes.iter() {
+                        let name = dtype
+                            .as_str()
+                            .ok_or_else(|| format!("dtype of '{column}' must be a string"))?;
+                        let dtype = piql::advanced::parse_dtype(name).map_err(|e| e.to_string())?;
+                        options.dtypes.with_column(column.into(), dtype);
+                    }
+                }
+                other => return Err(format!("unknown CSV option '{other}'")),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// A one-character delimiter, or `\t` for tab
+pub fn parse_delimiter(s: &str) -> Result<u8, String> {
+    match s.as_bytes() {
+        [byte] if byte.is_ascii() => Ok(*byte),
+        b"\\t" => Ok(b'\t'),
+        _ => Err(format!(
+            "invalid delimiter '{s}' (expected one ASCII character or \\t)"
+        )),
+    }
+}
+
+/// CSV settings for `path` from the `piql.toml` in its directory: the `[csv]`
+/// section, overridden by the file's own `[files."<name>"]` section
+pub fn sidecar_csv_options(path: &Path) -> Result<CsvOptions, String> {
+    let Some(sidecar) = path.parent().map(|dir| dir.join(SIDECAR_FILE)) else {
+        return Ok(CsvOptions::default());
+    };
+    let Ok(text) = std::fs::read_to_string(&sidecar) else {
+        return Ok(CsvOptions::default());
+    };
+    let invalid = |e: String| format!("{}: {e}", sidecar.display());
+    let doc: toml_edit::DocumentMut = text.parse().map_err(|e| invalid(format!("{e}")))?;
+
+    let mut options = match doc.get("csv") {
+        Some(section) => {
+            CsvOptions::from_toml(section).map_err(|e| invalid(format!("[csv]: {e}")))?
+        }
+        None => CsvOptions::default(),
+    };
+    let file_name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+    if let Some(section) = doc.get("files").and_then(|files| files.get(file_name)) {
+        let file_options = CsvOptions::from_toml(section)
+            .map_err(|e| invalid(format!("[files.\"{file_name}\"]: {e}")))?;
+        options = options.merged(&file_options);
+    }
+    Ok(options)
+}
+
+/// Schema inference settings for loaded files
+#[derive(Debug, Clone, Default)]
+pub struct LoadOptions {
+    /// Rows of CSV/JSON/NDJSON used to infer column types (None = format default,
+    /// 0 = all rows)
+    pub infer_schema_length: Option<usize>,
+    /// Column types to use instead of the inferred or stored ones. CSV columns are
+    /// parsed as these types; other formats are cast after loading. Columns a file
+    /// doesn't have are ignored.
+    pub dtypes: Schema,
+    /// Name patterns (`*` matches any run of characters, e.g. `*_id`) of string
+    /// columns to read as categorical, so joins and group-bys on IDs compare codes
+    /// instead of strings. `dtypes` wins for columns it names.
+    pub categorical: Vec<String>,
+    /// Column names for hive partition keys (`tick` → `step`); other keys keep
+    /// their name
+    pub partition_columns: HashMap<String, String>,
+    /// CSV parsing for every file; `piql.toml` sidecars override it per directory
+    /// and per file
+    pub csv: CsvOptions,
+    /// Object store settings for remote sources (e.g. `aws_region`,
+    /// `aws_endpoint_url`), on top of the provider's standard environment variables
+    pub storage: HashMap<String, String>,
+    /// Register local files and datasets as scans every query re-reads instead of
+    /// loading them into memory (see [`scan_file_with_options_sync`])
+    pub lazy: bool,
+}
+
+impl LoadOptions {
+    fn csv_infer_length(&self) -> Option<Option<usize>> {
+        self.infer_schema_length.map(|n| (n > 0).then_some(n))
+    }
+
+    fn json_infer_length(&self) -> Option<Option<NonZeroUsize>> {
+        self.infer_schema_length.map(NonZeroUsize::new)
+    }
+
+    /// These options with the CSV settings of `path`'s `piql.toml` applied
+    fn for_file(&self, path: &Path) -> Result<LoadOptions, PolarsError> {
+        let sidecar = sidecar_csv_options(path).map_err(|e| PolarsError::ComputeError(e.into()))?;
+        Ok(LoadOptions {
+            csv: self.csv.merged(&sidecar),
+            ..self.clone()
+        })
+    }
+
+    /// Column types CSV columns are parsed as: `dtypes`, then the CSV-specific ones
+    fn csv_dtypes(&self) -> Schema {
+        let mut dtypes = self.dtypes.clone();
+        for (name, dtype) in self.csv.dtypes.iter() {
+            dtypes.with_column(name.clone(), dtype.clone());
+        }
+        dtypes
+    }
+
+    /// Read options for CSV held in memory
+    fn csv_read_options(&self) -> CsvReadOptions {
+        let mut read_options = CsvReadOptions::default();
+        if let Some(length) = self.csv_infer_length() {
+            read_options = read_options.with_infer_schema_length(length);
+        }
+        if let Some(delimiter) = self.csv.delimiter {
+            read_options = read_options.map_parse_options(|p| p.with_separator(delimiter));
+        }
+        if let Some(has_header) = self.csv.has_header {
+            read_options = read_options.with_has_header(has_header);
+        }
+        if let Some(skip_rows) = self.csv.skip_rows {
+            read_options = read_options.with_skip_rows(skip_rows);
+        }
+        read_options
+    }
+
+    /// Cast columns whose type differs from the one in `dtypes`, and string columns
+    /// matching a `categorical` pattern to categorical
+    fn apply(&self, lf: LazyFrame) -> PolarsResult<LazyFrame> {
+        if self.dtypes.is_empty() && self.categorical.is_empty() {
+            return Ok(lf);
+        }
+        let mut lf = lf;
+        let schema = lf.collect_schema()?;
+        let categorical = DataType::from_categories(Categories::global());
+        let hinted = schema.iter().filter(|(name, dtype)| {
+            **dtype == DataType::String
+                && !self.dtypes.contains(name)
+                && self
+                    .categorical
+                    .iter()
+                    .any(|pattern| glob_match(pattern, name))
+        });
+        let casts: Vec<Expr> = self
+            .dtypes
+            .iter()
+            .filter(|(name, dtype)| schema.get(name).is_some_and(|current| current != *dtype))
+            .chain(hinted.map(|(name, _)| (name, &categorical)))
+            .map(|(name, dtype)| col(name.clone()).cast(dtype.clone()))
+            .collect();
+        Ok(if casts.is_empty() {
+            lf
+        } else {
+            lf.with_columns(casts)
+        })
+    }
+}
+
+/// `dtypes` restricted to the columns of a file, for readers that add missing ones
+fn overrides_for(dtypes: &Schema, schema: &Schema) -> Option<SchemaRef> {
+    let overrides: Schema = dtypes
+        .iter()
+        .filter(|(name, _)| schema.contains(name))
+        .map(|(name, dtype)| Field::new(name.clone(), dtype.clone()))
+        .collect();
+    (!overrides.is_empty()).then(|| Arc::new(overrides))
+}
+
 /// Load a DataFrame from a file path (sync, collects immediately)
 pub fn load_file_sync(path: &Path) -> Result<DataFrame, PolarsError> {
+    load_file_with_options_sync(path, &LoadOptions::default())
+}
+
+/// Load a DataFrame from a file path with schema inference settings (sync)
+///
+/// Compressed files (`.gz`, `.zst`) are decompressed into memory first; a
+/// directory is loaded as a hive-partitioned dataset.
+pub fn load_file_with_options_sync(
+    path: &Path,
+    options: &LoadOptions,
+) -> Result<DataFrame, PolarsError> {
+    scan_file_with_options_sync(path, options)?.collect()
+}
+
+/// A scan of a file path with schema inference settings, reading the file only
+/// when collected
+///
+/// Parquet, IPC, CSV and NDJSON files and hive-partitioned directories are
+/// scanned in place, so a query collecting the scan reads only the columns and row
+/// groups it needs. Compressed and plain JSON files are read into memory now.
+pub fn scan_file_with_options_sync(
+    path: &Path,
+    options: &LoadOptions,
+) -> Result<LazyFrame, PolarsError> {
+    if path.is_dir() {
+        return options.apply(scan_hive(PlPath::Local(Arc::from(path)), options, None)?);
+    }
+    let Some((format, compression)) = file_format(path) else {
+        return Err(PolarsError::ComputeError(
+            format!("unsupported file type: {}", path.display()).into(),
+        ));
+    };
+    let options = &options.for_file(path)?;
+    if let Some(compression) = compression {
+        let bytes = compression.decompress(&std::fs::read(path)?)?;
+        return Ok(load_bytes_with_options_sync(bytes, format, options)?.lazy());
+    }
+
     let pl_path = PlPath::Local(Arc::from(path));
-    let lf = match path.extension().and_then(|e| e.to_str()) {
-        Some("parquet") => LazyFrame::scan_parquet(pl_path, Default::default())?,
-        Some("csv") => LazyCsvReader::new(pl_path).finish()?,
-        Some("ipc" | "arrow") => {
+    let lf = match format {
+        DataFormat::Parquet => scan_parquet(pl_path, None)?,
+        DataFormat::Csv => scan_csv(pl_path, options, None)?,
+        DataFormat::IpcFile | DataFormat::IpcStream => {
             LazyFrame::scan_ipc(pl_path, Default::default(), Default::default())?
         }
-        Some(ext) => {
-            return Err(PolarsError::ComputeError(
-                format!("unsupported file extension: {ext}").into(),
-            ));
-        }
-        None => {
-            return Err(PolarsError::ComputeError(
-                "file has no extension".to_string().into(),
-            ));
+        DataFormat::Json => {
+            let mut reader = JsonReader::new(std::fs::File::open(path)?);
+            if let Some(length) = options.json_infer_length() {
+                reader = reader.infer_schema_len(length);
+            }
+            reader.finish()?.lazy()
         }
+        DataFormat::NdJson => scan_ndjson(pl_path, options, None)?,
+    };
+    options.apply(lf)
+}
+
+fn scan_parquet(pl_path: PlPath, cloud_options: Option<CloudOptions>) -> PolarsResult<LazyFrame> {
+    let args = ScanArgsParquet {
+        cloud_options,
+        ..Default::default()
     };
-    lf.collect()
+    LazyFrame::scan_parquet(pl_path, args)
+}
+
+fn scan_csv(
+    pl_path: PlPath,
+    options: &LoadOptions,
+    cloud_options: Option<CloudOptions>,
+) -> PolarsResult<LazyFrame> {
+    let mut reader = LazyCsvReader::new(pl_path).with_cloud_options(cloud_options);
+    if let Some(length) = options.csv_infer_length() {
+        reader = reader.with_infer_schema_length(length);
+    }
+    if let Some(delimiter) = options.csv.delimiter {
+        reader = reader.with_separator(delimiter);
+    }
+    if let Some(has_header) = options.csv.has_header {
+        reader = reader.with_has_header(has_header);
+    }
+    if let Some(skip_rows) = options.csv.skip_rows {
+        reader = reader.with_skip_rows(skip_rows);
+    }
+    let dtypes = options.csv_dtypes();
+    if !dtypes.is_empty() {
+        let schema = reader.clone().finish()?.collect_schema()?;
+        reader = reader.with_dtype_overwrite(overrides_for(&dtypes, &schema));
+    }
+    reader.finish()
+}
+
+fn scan_ndjson(
+    pl_path: PlPath,
+    options: &LoadOptions,
+    cloud_options: Option<CloudOptions>,
+) -> PolarsResult<LazyFrame> {
+    let mut reader = LazyJsonLineReader::new(pl_path).with_cloud_options(cloud_options);
+    if let Some(length) = options.json_infer_length() {
+        reader = reader.with_infer_schema_length(length);
+    }
+    reader.finish()
+}
+
+/// A scan of a file path with schema inference settings (async, runs on blocking
+/// thread pool); see [`scan_file_with_options_sync`]
+pub async fn scan_file_with_options(
+    path: &Path,
+    options: &LoadOptions,
+) -> Result<LazyFrame, PolarsError> {
+    let path = path.to_path_buf();
+    let options = options.clone();
+    tokio::task::spawn_blocking(move || scan_file_with_options_sync(&path, &options))
+        .await
+        .map_err(|e| PolarsError::ComputeError(format!("blocking task failed: {e}").into()))?
 }
 
 /// Load a DataFrame from a file path (async, runs on blocking thread pool)
 pub async fn load_file(path: &Path) -> Result<DataFrame, PolarsError> {
+    load_file_with_options(path, &LoadOptions::default()).await
+}
+
+/// Load a DataFrame from a file path with schema inference settings (async)
+pub async fn load_file_with_options(
+    path: &Path,
+    options: &LoadOptions,
+) -> Result<DataFrame, PolarsError> {
     let path = path.to_path_buf();
-    tokio::task::spawn_blocking(move || load_file_sync(&path))
+    let options = options.clone();
+    tokio::task::spawn_blocking(move || load_file_with_options_sync(&path, &options))
+        .await
+        .map_err(|e| PolarsError::ComputeError(format!("blocking task failed: {e}").into()))?
+}
+
+// ============ Hive-partitioned datasets ============
+
+/// The key of a hive partition directory name (`tick=5` → `tick`)
+fn partition_key(dir_name: &str) -> Option<&str> {
+    dir_name
+        .split_once('=')
+        .map(|(key, _)| key)
+        .filter(|key| !key.is_empty())
+}
+
+/// Whether `dir` is a hive-partitioned dataset: it has `key=value` subdirectories
+pub fn is_hive_dataset(dir: &Path) -> bool {
+    std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .any(|entry| {
+            entry.path().is_dir() && entry.file_name().to_str().and_then(partition_key).is_some()
+        })
+}
+
+/// Load all parquet files under a hive-partitioned directory as one table, with a
+/// column per partition key (values parsed as numbers or dates where possible)
+pub fn load_hive_dataset_sync(dir: &Path, options: &LoadOptions) -> Result<DataFrame, PolarsError> {
+    let lf = scan_hive(PlPath::Local(Arc::from(dir)), options, None)?;
+    options.apply(lf)?.collect()
+}
+
+fn scan_hive(
+    pl_path: PlPath,
+    options: &LoadOptions,
+    cloud_options: Option<CloudOptions>,
+) -> PolarsResult<LazyFrame> {
+    let args = ScanArgsParquet {
+        hive_options: polars::io::HiveOptions::new_enabled(),
+        cloud_options,
+        ..Default::default()
+    };
+    let lf = LazyFrame::scan_parquet(pl_path, args)?;
+    let (existing, new): (Vec<_>, Vec<_>) = options.partition_columns.iter().unzip();
+    Ok(lf.rename(existing, new, false))
+}
+
+/// Hive-partitioned datasets among `paths`: each path that is one, and the
+/// datasets directly inside the others
+pub fn collect_datasets(paths: &[PathBuf]) -> Vec<PathBuf> {
+    let mut datasets = Vec::new();
+    for path in paths.iter().filter(|p| p.is_dir()) {
+        if is_hive_dataset(path) {
+            datasets.push(path.clone());
+        } else if let Ok(entries) = std::fs::read_dir(path) {
+            datasets.extend(
+                entries
+                    .flatten()
+                    .map(|entry| entry.path())
+                    .filter(|p| p.is_dir() && is_hive_dataset(p)),
+            );
+        }
+    }
+    datasets
+}
+
+// ============ Remote sources ============
+
+/// URL schemes loaded through an object store instead of the local filesystem
+const REMOTE_SCHEMES: &[&str] = &[
+    "s3", "s3a", "gs", "gcs", "az", "azure", "abfs", "abfss", "adl", "http", "https",
+];
+
+/// Whether `source` is an object store or HTTP(S) URL (`s3://bucket/ticks.parquet`)
+pub fn is_remote(source: &str) -> bool {
+    source
+        .split_once("://")
+        .is_some_and(|(scheme, _)| REMOTE_SCHEMES.contains(&scheme))
+}
+
+/// The object key of a URL without scheme, query or fragment
+fn url_key(url: &str) -> &str {
+    let key = url.split_once("://").map_or(url, |(_, rest)| rest);
+    key.split(['?', '#']).next().unwrap_or(key)
+}
+
+/// Table name for a remote source, derived like [`df_name_from_path`] from the
+/// last segment of the key. A prefix (`s3://b/trades/`) or glob
+/// (`s3://b/trades/*.parquet`) is named after its directory.
+pub fn remote_df_name(url: &str) -> String {
+    let mut segments = url_key(url).split('/').rev();
+    let last = segments.next().unwrap_or_default();
+    if last.is_empty() || last.contains(['*', '?', '[']) {
+        return segments.next().unwrap_or("unknown").to_string();
+    }
+    df_name_from_path(Path::new(last))
+}
+
+fn cloud_options(url: &str, options: &LoadOptions) -> PolarsResult<Option<CloudOptions>> {
+    if options.storage.is_empty() {
+        return Ok(None);
+    }
+    CloudOptions::from_untyped_config(CloudScheme::from_uri(url).as_ref(), &options.storage)
+        .map(Some)
+}
+
+/// Load a DataFrame from an object store or HTTP(S) URL (sync)
+///
+/// Parquet, CSV and NDJSON objects are scanned in place; a URL ending in `/` is
+/// read as a hive-partitioned parquet dataset. Globs (`*.parquet`) concatenate
+/// every matching object. Requires the `cloud` feature.
+pub fn load_url_sync(url: &str, options: &LoadOptions) -> Result<DataFrame, PolarsError> {
+    if !cfg!(feature = "cloud") {
+        return Err(PolarsError::ComputeError(
+            format!("{url}: remote sources need piql-server built with the `cloud` feature").into(),
+        ));
+    }
+    let cloud_options = cloud_options(url, options)?;
+    let pl_path = PlPath::new(url);
+    let key = url_key(url);
+    if key.ends_with('/') {
+        let lf = scan_hive(pl_path, options, cloud_options)?;
+        return options.apply(lf)?.collect();
+    }
+    let lf = match file_format(Path::new(key)) {
+        Some((DataFormat::Parquet, None)) => scan_parquet(pl_path, cloud_options)?,
+        Some((DataFormat::Csv, None)) => scan_csv(pl_path, options, cloud_options)?,
+        Some((DataFormat::NdJson, None)) => scan_ndjson(pl_path, options, cloud_options)?,
+        _ => {
+            return Err(PolarsError::ComputeError(
+                format!("{url}: remote sources must be uncompressed parquet, csv or ndjson").into(),
+            ));
+        }
+    };
+    options.apply(lf)?.collect()
+}
+
+/// Load a DataFrame from an object store or HTTP(S) URL (async, runs on blocking
+/// thread pool)
+pub async fn load_url(url: &str, options: &LoadOptions) -> Result<DataFrame, PolarsError> {
+    let url = url.to_string();
+    let options = options.clone();
+    tokio::task::spawn_blocking(move || load_url_sync(&url, &options))
         .await
         .map_err(|e| PolarsError::ComputeError(format!("blocking task failed: {e}").into()))?
 }
 
-/// Extract DataFrame name from path (file stem)
+// ============ In-memory uploads ============
+
+/// Serialization format of an uploaded table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataFormat {
+    /// Arrow IPC stream (what `/query` returns)
+    IpcStream,
+    /// Arrow IPC file (`.arrow` / `.ipc`)
+    IpcFile,
+    Parquet,
+    /// CSV with a header row
+    Csv,
+    /// JSON array of row objects
+    Json,
+    /// One JSON row object per line
+    NdJson,
+}
+
+impl DataFormat {
+    /// Parse a format name: `ipc_stream`, `ipc` / `arrow`, `parquet`, `csv`, `json`
+    /// or `ndjson` / `jsonl`.
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "ipc_stream" | "arrow_stream" => Ok(Self::IpcStream),
+            "ipc" | "arrow" => Ok(Self::IpcFile),
+            "parquet" => Ok(Self::Parquet),
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            "ndjson" | "jsonl" => Ok(Self::NdJson),
+            other => Err(format!(
+                "unknown format '{other}' (expected ipc_stream, ipc, parquet, csv, json or ndjson)"
+            )),
+        }
+    }
+
+    /// Detect the format from magic bytes (or a leading `[` / `{` for JSON),
+    /// falling back to CSV
+    pub fn sniff(bytes: &[u8]) -> Self {
+        if bytes.starts_with(b"PAR1") {
+            Self::Parquet
+        } else if bytes.starts_with(b"ARROW1") {
+            Self::IpcFile
+        } else if bytes.starts_with(&[0xFF, 0xFF, 0xFF, 0xFF]) {
+            Self::IpcStream
+        } else {
+            match bytes.iter().find(|b| !b.is_ascii_whitespace()) {
+                Some(b'[') => Self::Json,
+                Some(b'{') => Self::NdJson,
+                _ => Self::Csv,
+            }
+        }
+    }
+}
+
+/// Decode a DataFrame from an in-memory buffer (sync)
+pub fn load_bytes_sync(bytes: Vec<u8>, format: DataFormat) -> Result<DataFrame, PolarsError> {
+    load_bytes_with_options_sync(bytes, format, &LoadOptions::default())
+}
+
+/// Decode a DataFrame from an in-memory buffer with schema inference settings (sync)
+pub fn load_bytes_with_options_sync(
+    bytes: Vec<u8>,
+    format: DataFormat,
+    options: &LoadOptions,
+) -> Result<DataFrame, PolarsError> {
+    let cursor = std::io::Cursor::new(bytes);
+    let df = match format {
+        DataFormat::IpcStream => IpcStreamReader::new(cursor).finish()?,
+        DataFormat::IpcFile => IpcReader::new(cursor).finish()?,
+        DataFormat::Parquet => ParquetReader::new(cursor).finish()?,
+        DataFormat::Csv => {
+            let mut read_options = options.csv_read_options();
+            let dtypes = options.csv_dtypes();
+            if !dtypes.is_empty() {
+                let header = read_options
+                    .clone()
+                    .with_n_rows(Some(0))
+                    .into_reader_with_file_handle(cursor.clone())
+                    .finish()?;
+                read_options =
+                    read_options.with_schema_overwrite(overrides_for(&dtypes, header.schema()));
+            }
+            read_options.into_reader_with_file_handle(cursor).finish()?
+        }
+        DataFormat::Json | DataFormat::NdJson => {
+            let json_format = if format == DataFormat::Json {
+                JsonFormat::Json
+            } else {
+                JsonFormat::JsonLines
+            };
+            let mut reader = JsonReader::new(cursor).with_json_format(json_format);
+            if let Some(length) = options.json_infer_length() {
+                reader = reader.infer_schema_len(length);
+            }
+            reader.finish()?
+        }
+    };
+    options.apply(df.lazy())?.collect()
+}
+
+/// Decode a DataFrame from an in-memory buffer (async, runs on blocking thread pool)
+pub async fn load_bytes(bytes: Vec<u8>, format: DataFormat) -> Result<DataFrame, PolarsError> {
+    tokio::task::spawn_blocking(move || load_bytes_sync(bytes, format))
+        .await
+        .map_err(|e| PolarsError::ComputeError(format!("blocking task failed: {e}").into()))?
+}
+
+// ============ Reload retries ============
+
+/// What to do with a table once every reload attempt has failed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnReloadFailure {
+    /// Keep serving the previously loaded data
+    #[default]
+    KeepStale,
+    /// Remove the table so queries fail loudly instead of returning stale data
+    Remove,
+}
+
+/// Retry schedule for a single file load
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Total attempts including the first one (1 = no retries)
+    pub max_attempts: u32,
+    /// Delay before the first retry; doubled after every failed retry
+    pub backoff: Duration,
+    /// Upper bound on the delay between attempts
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Single attempt, no retries
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Delay before attempt `attempt + 1` (attempts are 1-based)
+    fn delay_after(&self, attempt: u32) -> Duration {
+        let factor = 1u32
+            .checked_shl(attempt.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        self.backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+}
+
+/// File names writers commonly use while a file is incomplete: hidden files,
+/// `.tmp`/`.part` suffixes and editor backups
+pub const DEFAULT_IGNORE_PATTERNS: &[&str] = &[".*", "*.tmp", "*.part", "*.partial", "*~"];
+
+/// Reload behavior for watched files
+#[derive(Debug, Clone)]
+pub struct ReloadPolicy {
+    /// Quiet period after the last change before reloading
+    pub debounce: Duration,
+    /// File name patterns (`*` matches anything) whose changes are ignored
+    pub ignore: Vec<String>,
+    /// Retry schedule used unless an extension override applies
+    pub retry: RetryPolicy,
+    /// Per-extension overrides, keyed without the dot (e.g. `"csv"`)
+    pub per_extension: HashMap<String, RetryPolicy>,
+    /// Decision once all attempts are exhausted
+    pub on_failure: OnReloadFailure,
+    /// Schema inference settings for every load
+    pub load: LoadOptions,
+}
+
+impl Default for ReloadPolicy {
+    fn default() -> Self {
+        Self {
+            debounce: Duration::from_millis(100),
+            ignore: DEFAULT_IGNORE_PATTERNS
+                .iter()
+                .map(|p| p.to_string())
+                .collect(),
+            retry: RetryPolicy::default(),
+            per_extension: HashMap::new(),
+            on_failure: OnReloadFailure::default(),
+            load: LoadOptions::default(),
+        }
+    }
+}
+
+impl ReloadPolicy {
+    /// Whether changes to `path` should be ignored (temporary or hidden file)
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            return false;
+        };
+        self.ignore.iter().any(|pattern| glob_match(pattern, name))
+    }
+
+    /// Retry schedule for a given file
+    pub fn retry_for(&self, path: &Path) -> &RetryPolicy {
+        path.extension()
+            .and_then(|e| e.to_str())
+            .and_then(|ext| self.per_extension.get(ext))
+            .unwrap_or(&self.retry)
+    }
+}
+
+/// Match `name` against a pattern where `*` stands for any run of characters
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(idx) => rest = &rest[idx + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// Size and modification time, to notice a file changing while it is read
+fn file_version(path: &Path) -> Option<(u64, std::time::SystemTime)> {
+    let meta = std::fs::metadata(path).ok()?;
+    Some((meta.len(), meta.modified().ok()?))
+}
+
+/// Accept a loaded table only if its file didn't change during the load and the
+/// result has columns; otherwise the writer was mid-write
+fn verify_loaded(
+    path: &Path,
+    before: Option<(u64, std::time::SystemTime)>,
+    df: DataFrame,
+) -> Result<DataFrame, PolarsError> {
+    if file_version(path) != before {
+        return Err(PolarsError::ComputeError(
+            format!("{} changed while loading", path.display()).into(),
+        ));
+    }
+    if df.width() == 0 {
+        return Err(PolarsError::NoData(
+            format!("{} has no columns", path.display()).into(),
+        ));
+    }
+    Ok(df)
+}
+
+/// Errors that can be caused by reading a file while it is still being written.
+fn is_transient(err: &PolarsError) -> bool {
+    match err {
+        PolarsError::Context { error, .. } => is_transient(error),
+        PolarsError::IO { .. } | PolarsError::ComputeError(_) | PolarsError::NoData(_) => true,
+        _ => false,
+    }
+}
+
+/// Load a file, retrying transient failures according to `policy`.
+///
+/// An attempt fails (transiently) if the file changes while it is read or loads
+/// without columns, so a half-written file never replaces good data. Gives up
+/// early if the file disappears between attempts.
+pub async fn load_file_with_retry(
+    path: &Path,
+    policy: &RetryPolicy,
+    options: &LoadOptions,
+) -> Result<DataFrame, PolarsError> {
+    let max_attempts = policy.max_attempts.max(1);
+    let mut attempt = 1;
+    loop {
+        let before = file_version(path);
+        let loaded = load_file_with_options(path, options)
+            .await
+            .and_then(|df| verify_loaded(path, before, df));
+        match loaded {
+            Ok(df) => {
+                if attempt > 1 {
+                    log::info!(
+                        "Loaded {} on attempt {}/{}",
+                        path.display(),
+                        attempt,
+                        max_attempts
+                    );
+                }
+                return Ok(df);
+            }
+            Err(e) if attempt < max_attempts && is_transient(&e) && path.exists() => {
+                let delay = policy.delay_after(attempt);
+                log::warn!(
+                    "Load attempt {}/{} for {} failed: {}; retrying in {:?}",
+                    attempt,
+                    max_attempts,
+                    path.display(),
+                    e,
+                    delay
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => {
+                log::error!(
+                    "Load attempt {}/{} for {} failed: {}; giving up",
+                    attempt,
+                    max_attempts,
+                    path.display(),
+                    e
+                );
+                return Err(e);
+            }
+        }
+    }
+}
+
+/// Extract DataFrame name from path (file stem, ignoring a `.gz` / `.zst` suffix)
 pub fn df_name_from_path(path: &Path) -> String {
+    let path = match path.extension().and_then(|e| e.to_str()) {
+        Some("gz" | "zst") => path.with_extension(""),
+        _ => path.to_path_buf(),
+    };
     path.file_stem()
         .and_then(|s| s.to_str())
         .unwrap_or("unknown")
@@ -47,10 +897,7 @@ pub fn df_name_from_path(path: &Path) -> String {
 
 /// Check if a file has a supported extension
 pub fn is_supported_file(path: &Path) -> bool {
-    matches!(
-        path.extension().and_then(|e| e.to_str()),
-        Some("parquet" | "csv" | "ipc" | "arrow")
-    )
+    file_format(path).is_some()
 }
 
 /// Collect all supported files from paths (files or directories)
@@ -73,14 +920,17 @@ pub fn collect_files(paths: &[PathBuf]) -> Vec<PathBuf> {
     files
 }
 
-/// Recursively collect all supported files from a directory tree
+/// Recursively collect all supported files from a directory tree. Hive-partitioned
+/// datasets are collected as a whole (their directory) rather than file by file.
 fn collect_files_recursive(dir: &Path) -> Vec<PathBuf> {
     let mut files = Vec::new();
 
     if let Ok(entries) = std::fs::read_dir(dir) {
         for entry in entries.flatten() {
             let path = entry.path();
-            if path.is_dir() {
+            if path.is_dir() && is_hive_dataset(&path) {
+                files.push(path);
+            } else if path.is_dir() {
                 files.extend(collect_files_recursive(&path));
             } else if path.is_file() && is_supported_file(&path) {
                 files.push(path);
@@ -93,7 +943,11 @@ fn collect_files_recursive(dir: &Path) -> Vec<PathBuf> {
 
 /// Load a directory tree, concatenating all files with the same name (sync).
 fn load_concat_dir_sync(dir: &Path) -> Result<HashMap<String, DataFrame>, PolarsError> {
-    let files = collect_files_recursive(dir);
+    let files = if is_hive_dataset(dir) {
+        vec![dir.to_path_buf()]
+    } else {
+        collect_files_recursive(dir)
+    };
 
     // Group files by name (stem)
     let mut by_name: HashMap<String, Vec<PathBuf>> = HashMap::new();
@@ -163,3 +1017,301 @@ pub async fn load_concat_dir(dir: &Path) -> Result<HashMap<String, DataFrame>, P
         .await
         .map_err(|e| PolarsError::ComputeError(format!("blocking task failed: {e}").into()))?
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_bytes_sniffs_format() {
+        let mut df = df! { "a" => &[1i64, 2], "b" => &["x", "y"] }.unwrap();
+
+        let mut parquet = Vec::new();
+        ParquetWriter::new(&mut parquet).finish(&mut df).unwrap();
+        let mut ipc_stream = Vec::new();
+        IpcStreamWriter::new(&mut ipc_stream)
+            .finish(&mut df)
+            .unwrap();
+        let mut ipc_file = Vec::new();
+        IpcWriter::new(&mut ipc_file).finish(&mut df).unwrap();
+        let csv = b"a,b\n1,x\n2,y\n".to_vec();
+
+        for (bytes, format) in [
+            (parquet, DataFormat::Parquet),
+            (ipc_stream, DataFormat::IpcStream),
+            (ipc_file, DataFormat::IpcFile),
+            (csv, DataFormat::Csv),
+        ] {
+            assert_eq!(DataFormat::sniff(&bytes), format);
+            let loaded = load_bytes_sync(bytes, format).unwrap();
+            assert!(loaded.equals(&df), "{format:?} round trip differs");
+        }
+    }
+
+    #[test]
+    fn file_format_from_extensions() {
+        assert_eq!(
+            file_format(Path::new("a/ticks.csv.gz")),
+            Some((DataFormat::Csv, Some(Compression::Gzip)))
+        );
+        assert_eq!(
+            file_format(Path::new("events.jsonl.zst")),
+            Some((DataFormat::NdJson, Some(Compression::Zstd)))
+        );
+        assert_eq!(
+            file_format(Path::new("rows.json")),
+            Some((DataFormat::Json, None))
+        );
+        assert_eq!(file_format(Path::new("notes.txt.gz")), None);
+        assert_eq!(file_format(Path::new("csv")), None);
+        assert_eq!(df_name_from_path(Path::new("a/ticks.csv.gz")), "ticks");
+        assert_eq!(df_name_from_path(Path::new("a/ticks.csv")), "ticks");
+    }
+
+    #[test]
+    fn loads_json_and_compressed_files() {
+        use std::io::Write;
+
+        let dir = std::env::temp_dir().join(format!("piql-formats-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let expected = df! { "id" => &["007", "008"], "gold" => &[1i64, 2] }.unwrap();
+        let csv = b"id,gold\n007,1\n008,2\n";
+        let ndjson = b"{\"id\":\"007\",\"gold\":1}\n{\"id\":\"008\",\"gold\":2}\n";
+
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        gz.write_all(csv).unwrap();
+        let files = [
+            (
+                "t.json",
+                br#"[{"id":"007","gold":1},{"id":"008","gold":2}]"#.to_vec(),
+            ),
+            ("t.ndjson", ndjson.to_vec()),
+            (
+                "t.jsonl.zst",
+                zstd::stream::encode_all(&ndjson[..], 0).unwrap(),
+            ),
+            ("t.csv.gz", gz.finish().unwrap()),
+            ("t.csv", csv.to_vec()),
+        ];
+        let options = LoadOptions {
+            dtypes: Schema::from_iter([Field::new("id".into(), DataType::String)]),
+            ..Default::default()
+        };
+        for (name, bytes) in files {
+            let path = dir.join(name);
+            std::fs::write(&path, bytes).unwrap();
+            assert!(is_supported_file(&path));
+            let df = load_file_with_options_sync(&path, &options).unwrap();
+            assert!(df.equals(&expected), "{name} loaded as {df}");
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn categorical_hints_cast_matching_string_columns() {
+        let dir = std::env::temp_dir().join(format!("piql-categorical-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("trades.csv");
+        std::fs::write(
+            &path,
+            "trader_id,desk_id,order_id,note\nt1,d1,1,x\nt2,d1,2,y\n",
+        )
+        .unwrap();
+        let options = LoadOptions {
+            categorical: vec!["*_id".to_string()],
+            dtypes: Schema::from_iter([Field::new("desk_id".into(), DataType::String)]),
+            ..Default::default()
+        };
+
+        let df = load_file_with_options_sync(&path, &options).unwrap();
+        assert!(matches!(
+            df.column("trader_id").unwrap().dtype(),
+            DataType::Categorical(..)
+        ));
+        // Explicit dtypes win, and only string columns are hinted
+        assert_eq!(df.column("desk_id").unwrap().dtype(), &DataType::String);
+        assert_eq!(df.column("order_id").unwrap().dtype(), &DataType::Int64);
+        assert_eq!(df.column("note").unwrap().dtype(), &DataType::String);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn sidecar_sets_csv_options_per_directory_and_file() {
+        let dir = std::env::temp_dir().join(format!("piql-sidecar-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join(SIDECAR_FILE),
+            r#"
+[csv]
+delimiter = ";"
+dtypes = { id = "str" }
+
+[files."raw.csv"]
+has_header = false
+skip_rows = 1
+"#,
+        )
+        .unwrap();
+        std::fs::write(dir.join("ids.csv"), "id;gold\n007;1\n").unwrap();
+        std::fs::write(dir.join("raw.csv"), "exported today\n5;6\n").unwrap();
+
+        let ids = load_file_sync(&dir.join("ids.csv")).unwrap();
+        let expected = df! { "id" => &["007"], "gold" => &[1i64] }.unwrap();
+        assert!(ids.equals(&expected), "{ids}");
+
+        let raw = load_file_sync(&dir.join("raw.csv")).unwrap();
+        let expected = df! { "column_1" => &[5i64], "column_2" => &[6i64] }.unwrap();
+        assert!(raw.equals(&expected), "{raw}");
+
+        std::fs::write(dir.join(SIDECAR_FILE), "[csv]\nquote = true\n").unwrap();
+        let err = load_file_sync(&dir.join("ids.csv")).err().unwrap();
+        assert!(
+            err.to_string().contains("unknown CSV option 'quote'"),
+            "{err}"
+        );
+
+        assert_eq!(parse_delimiter("\\t"), Ok(b'\t'));
+        assert!(parse_delimiter(";;").is_err());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn hive_partitions_load_as_one_table() {
+        let root = std::env::temp_dir().join(format!("piql-hive-{}", std::process::id()));
+        let dataset = root.join("trades");
+        for tick in [1, 2] {
+            let dir = dataset.join(format!("tick={tick}"));
+            std::fs::create_dir_all(&dir).unwrap();
+            let mut df = df! { "price" => &[10i64 * tick, 11 * tick] }.unwrap();
+            let mut file = std::fs::File::create(dir.join("part-0.parquet")).unwrap();
+            ParquetWriter::new(&mut file).finish(&mut df).unwrap();
+        }
+
+        assert!(is_hive_dataset(&dataset));
+        assert_eq!(
+            collect_datasets(std::slice::from_ref(&root)),
+            [dataset.as_path()]
+        );
+        assert!(collect_files(std::slice::from_ref(&root)).is_empty());
+
+        let options = LoadOptions {
+            partition_columns: HashMap::from([("tick".to_string(), "step".to_string())]),
+            ..Default::default()
+        };
+        let df = load_file_with_options_sync(&dataset, &options).unwrap();
+        assert_eq!(df.height(), 4);
+        let steps = df.column("step").unwrap().cast(&DataType::Int64).unwrap();
+        assert_eq!(steps.i64().unwrap().sum(), Some(6));
+
+        let tables = load_concat_dir_sync(&root).unwrap();
+        assert_eq!(tables.keys().collect::<Vec<_>>(), ["trades"]);
+        assert_eq!(tables["trades"].height(), 4);
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_cap() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(350),
+        };
+        assert_eq!(policy.delay_after(1), Duration::from_millis(100));
+        assert_eq!(policy.delay_after(2), Duration::from_millis(200));
+        assert_eq!(policy.delay_after(3), Duration::from_millis(350));
+        assert_eq!(policy.delay_after(40), Duration::from_millis(350));
+    }
+
+    #[test]
+    fn remote_sources_are_named_like_files() {
+        assert!(is_remote("s3://sims/out/ticks.parquet"));
+        assert!(is_remote("https://example.com/ticks.csv"));
+        assert!(!is_remote("data/ticks.parquet"));
+        assert!(!is_remote("file:///data/ticks.parquet"));
+
+        assert_eq!(remote_df_name("s3://sims/out/ticks.parquet"), "ticks");
+        assert_eq!(remote_df_name("gs://sims/out/events.ndjson"), "events");
+        assert_eq!(remote_df_name("https://host/ticks.csv?token=abc"), "ticks");
+        assert_eq!(remote_df_name("s3://sims/trades/"), "trades");
+        assert_eq!(remote_df_name("s3://sims/trades/*.parquet"), "trades");
+    }
+
+    #[test]
+    fn temporary_files_are_ignored() {
+        let policy = ReloadPolicy::default();
+        assert!(policy.is_ignored(Path::new("data/.ticks.parquet")));
+        assert!(policy.is_ignored(Path::new("data/ticks.parquet.tmp")));
+        assert!(policy.is_ignored(Path::new("data/ticks.csv~")));
+        assert!(!policy.is_ignored(Path::new("data/ticks.parquet")));
+
+        assert!(glob_match("tmp_*.parquet", "tmp_1.parquet"));
+        assert!(glob_match("*_*_done", "a_b_done"));
+        assert!(!glob_match("tmp_*.parquet", "tmp_1.csv"));
+        assert!(!glob_match("ticks", "ticks.parquet"));
+    }
+
+    #[test]
+    fn per_extension_override() {
+        let mut policy = ReloadPolicy::default();
+        policy
+            .per_extension
+            .insert("csv".to_string(), RetryPolicy::none());
+        assert_eq!(policy.retry_for(Path::new("a.csv")).max_attempts, 1);
+        assert_eq!(policy.retry_for(Path::new("a.parquet")).max_attempts, 3);
+    }
+
+    #[tokio::test]
+    async fn retry_recovers_once_file_is_complete() {
+        let dir = std::env::temp_dir().join(format!("piql-retry-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("partial.ipc");
+        // Truncated file, as seen while a writer is mid-flush
+        std::fs::write(&path, b"ARROW1").unwrap();
+
+        let writer_path = path.clone();
+        let writer = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(30)).await;
+            let mut df = df! { "a" => &[1, 2, 3] }.unwrap();
+            let mut file = std::fs::File::create(&writer_path).unwrap();
+            IpcWriter::new(&mut file).finish(&mut df).unwrap();
+        });
+
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            backoff: Duration::from_millis(20),
+            max_backoff: Duration::from_millis(100),
+        };
+        let df = load_file_with_retry(&path, &policy, &LoadOptions::default())
+            .await
+            .unwrap();
+        writer.await.unwrap();
+        assert_eq!(df.height(), 3);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn retry_gives_up_after_max_attempts() {
+        let dir = std::env::temp_dir().join(format!("piql-retry-fail-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("broken.ipc");
+        std::fs::write(&path, b"not arrow").unwrap();
+
+        let policy = RetryPolicy {
+            max_attempts: 2,
+            backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(1),
+        };
+        assert!(
+            load_file_with_retry(&path, &policy, &LoadOptions::default())
+                .await
+                .is_err()
+        );
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,368 @@
+//! Column masks: hide sensitive columns from some credentials
+//!
+//! A mask lists, per table, columns to drop from results and columns to redact
+//! (values replaced by nulls). Masks are configured per credential in
+//! [`AuthConfig::column_masks`](crate::auth::AuthConfig::column_masks).
+//!
+//! Queries of a masked credential are evaluated against [`ColumnMask::restrict`]ed
+//! tables, in which the masked columns are already dropped or redacted, so no
+//! selector, rename or filter can reach their values. Masks also apply to the
+//! `{run}::` and `_all::` versions of a table, and materialized views reading a
+//! masked table are re-derived from the restricted tables. The `_queries` log is
+//! hidden from masked credentials altogether: it holds every credential's query
+//! text, literals included.
+//!
+//! Schemas, table listings and stats, plans from `/explain` and completions are
+//! read from the same restricted tables, so they don't reveal masked columns either.
+//!
+//! A query reading a masked table is also rejected up front if it mentions a masked
+//! column by name (`$email`, `pl.col("email")`, `sort("email")`, or any string equal
+//! to the name), so it fails with a clear error instead of a missing column.
+
+use std::collections::{BTreeSet, HashMap};
+
+use piql::advanced::{Arg, CoreExpr, Literal};
+use piql::{BaseTableEntry, CompiledQuery, DataFrameEntry, EvalContext};
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::materialize::{self, Materialization};
+use crate::query_log::QUERY_LOG_TABLE;
+
+/// Columns hidden from a credential
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ColumnMask {
+    /// Table → columns removed from results and schemas
+    #[serde(default)]
+    pub drop: HashMap<String, BTreeSet<String>>,
+    /// Table → columns whose values are replaced by nulls
+    #[serde(default)]
+    pub redact: HashMap<String, BTreeSet<String>>,
+}
+
+impl ColumnMask {
+    /// Remove `column` of `table` from results
+    pub fn with_dropped(mut self, table: impl Into<String>, column: impl Into<String>) -> Self {
+        self.drop
+            .entry(table.into())
+            .or_default()
+            .insert(column.into());
+        self
+    }
+
+    /// Replace the values of `column` of `table` with nulls
+    pub fn with_redacted(mut self, table: impl Into<String>, column: impl Into<String>) -> Self {
+        self.redact
+            .entry(table.into())
+            .or_default()
+            .insert(column.into());
+        self
+    }
+
+    /// Reject `compiled` if it mentions a masked column of a table it reads
+    pub fn check(&self, compiled: &CompiledQuery) -> Result<(), String> {
+        let tables = compiled.referenced_tables();
+        let masked: Vec<(&String, &String)> = tables
+            .iter()
+            .flat_map(|table| {
+                let dropped = self.drop.get(base_name(table)).into_iter().flatten();
+                let redacted = self.redact.get(base_name(table)).into_iter().flatten();
+                dropped.chain(redacted).map(move |column| (table, column))
+            })
+            .collect();
+        if masked.is_empty() {
+            return Ok(());
+        }
+
+        let mut strings = BTreeSet::new();
+        collect_strings(compiled.core(), &mut strings);
+        match masked
+            .into_iter()
+            .find(|(_, column)| strings.contains(column.as_str()))
+        {
+            Some((table, column)) => Err(format!(
+                "column `{table}.{column}` is not available to this credential"
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// `ctx` with the masked columns of every table dropped or redacted, and the
+    /// materialized views in `views` that read a masked table re-derived from the
+    /// restricted tables. Tables that can't be restricted (e.g. a view whose query
+    /// reads a dropped column) are removed, as is the query log (and views over it).
+    pub fn restrict(
+        &self,
+        mut ctx: EvalContext,
+        views: &HashMap<String, Materialization>,
+    ) -> EvalContext {
+        let masked: Vec<String> = ctx
+            .dataframes
+            .keys()
+            .filter(|name| self.masks(name))
+            .cloned()
+            .collect();
+        for name in &masked {
+            let entry = ctx.dataframes.remove(name).expect("listed above");
+            match self.restrict_entry(name, entry) {
+                Ok(entry) => {
+                    ctx.dataframes.insert(name.clone(), entry);
+                }
+                Err(e) => log::warn!("Hiding table {name} from a masked credential: {e}"),
+            }
+        }
+        let mut hidden = Vec::new();
+        for (name, base) in &mut ctx.base_tables {
+            if !self.masks(name) {
+                continue;
+            }
+            if let Err(e) = self.restrict_base(name, base) {
+                log::warn!("Hiding table {name} from a masked credential: {e}");
+                hidden.push(name.clone());
+            }
+        }
+        for name in hidden {
+            ctx.base_tables.remove(&name);
+            ctx.dataframes.remove(&name);
+        }
+        // Other credentials' queries, with the literals they filtered on
+        ctx.dataframes.remove(QUERY_LOG_TABLE);
+
+        let changed = masked.iter().map(String::as_str).chain([QUERY_LOG_TABLE]);
+        for name in materialize::downstream_order(changed, views) {
+            let time_series = ctx
+                .dataframes
+                .get(&name)
+                .and_then(|entry| entry.time_series.clone());
+            let derived = match piql::run(&views[&name].query, &ctx) {
+                Ok(piql::Value::DataFrame(lf, _)) => self
+                    .restrict_lazy(&name, lf)
+                    .and_then(|lf| DataFrameEntry::from_scan(lf, time_series))
+                    .map_err(|e| e.to_string()),
+                Ok(_) => Err("query doesn't produce a table".to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            match derived {
+                Ok(entry) => {
+                    ctx.dataframes.insert(name, entry);
+                }
+                Err(e) => {
+                    log::debug!("Hiding view {name} from a masked credential: {e}");
+                    ctx.dataframes.remove(&name);
+                }
+            }
+        }
+        ctx
+    }
+
+    /// Whether the mask hides columns of `name`, or of the table `{run}::name` or
+    /// `_all::name` is a version of
+    fn masks(&self, name: &str) -> bool {
+        let table = base_name(name);
+        self.drop.contains_key(table) || self.redact.contains_key(table)
+    }
+
+    fn restrict_entry(
+        &self,
+        name: &str,
+        mut entry: DataFrameEntry,
+    ) -> PolarsResult<DataFrameEntry> {
+        entry.df = self.restrict_df(name, entry.df)?;
+        entry.scan = entry
+            .scan
+            .map(|scan| self.restrict_lazy(name, scan))
+            .transpose()?;
+        Ok(entry)
+    }
+
+    fn restrict_base(&self, name: &str, base: &mut BaseTableEntry) -> PolarsResult<()> {
+        base.all = base
+            .all
+            .take()
+            .map(|lf| self.restrict_lazy(name, lf))
+            .transpose()?;
+        base.now = base
+            .now
+            .take()
+            .map(|lf| self.restrict_lazy(name, lf))
+            .transpose()?;
+        Ok(())
+    }
+
+    /// Drop and redact the masked columns of table `name` in `df`
+    fn restrict_df(&self, name: &str, mut df: DataFrame) -> PolarsResult<DataFrame> {
+        let table = base_name(name);
+        for column in self.drop.get(table).into_iter().flatten() {
+            if df.schema().contains(column) {
+                df = df.drop(column)?;
+            }
+        }
+        for column in self.redact.get(table).into_iter().flatten() {
+            if let Some(dtype) = df.schema().get(column).cloned() {
+                let nulls = Column::full_null(column.as_str().into(), df.height(), &dtype);
+                df.with_column(nulls)?;
+            }
+        }
+        Ok(df)
+    }
+
+    /// Drop and redact the masked columns of table `name` in `lf`'s output
+    fn restrict_lazy(&self, name: &str, mut lf: LazyFrame) -> PolarsResult<LazyFrame> {
+        let table = base_name(name);
+        let schema = lf.collect_schema()?;
+        let redacted: Vec<Expr> = self
+            .redact
+            .get(table)
+            .into_iter()
+            .flatten()
+            .filter_map(|column| {
+                let dtype = schema.get(column)?;
+                Some(lit(NULL).cast(dtype.clone()).alias(column.as_str()))
+            })
+            .collect();
+        let dropped: Vec<&str> = self
+            .drop
+            .get(table)
+            .into_iter()
+            .flatten()
+            .filter(|column| schema.contains(column))
+            .map(String::as_str)
+            .collect();
+        if !redacted.is_empty() {
+            lf = lf.with_columns(redacted);
+        }
+        if !dropped.is_empty() {
+            lf = lf.drop(by_name(dropped, true));
+        }
+        Ok(lf)
+    }
+}
+
+/// The table `name` is a version of: `t` for `t`, `{run}::t` and `_all::t`
+fn base_name(name: &str) -> &str {
+    name.rsplit_once("::").map_or(name, |(_, table)| table)
+}
+
+/// Every string literal in `expr`, including column names of `pl.col("...")`
+fn collect_strings<'a>(expr: &'a CoreExpr, strings: &mut BTreeSet<&'a str>) {
+    match expr {
+        CoreExpr::Literal(Literal::String(s)) => {
+            strings.insert(s);
+        }
+        CoreExpr::Literal(_) | CoreExpr::Ident(_) | CoreExpr::Invalid(_) => {}
+        CoreExpr::List(items) => items.iter().for_each(|e| collect_strings(e, strings)),
+        CoreExpr::Attr(base, _) | CoreExpr::UnaryOp(_, base) => collect_strings(base, strings),
+        CoreExpr::Call(callee, args) => {
+            collect_strings(callee, strings);
+            for arg in args {
+                match arg {
+                    Arg::Positional(e) | Arg::Keyword(_, e) => collect_strings(e, strings),
+                }
+            }
+        }
+        CoreExpr::BinaryOp(lhs, _, rhs) => {
+            collect_strings(lhs, strings);
+            collect_strings(rhs, strings);
+        }
+        CoreExpr::WhenThenOtherwise {
+            branches,
+            otherwise,
+        } => {
+            for (condition, value) in branches {
+                collect_strings(condition, strings);
+                collect_strings(value, strings);
+            }
+            collect_strings(otherwise, strings);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mask() -> ColumnMask {
+        ColumnMask::default()
+            .with_dropped("users", "email")
+            .with_redacted("users", "phone")
+    }
+
+    fn ctx() -> piql::EvalContext {
+        let users = df! {
+            "name" => &["a", "b"],
+            "email" => &["a@x", "b@x"],
+            "phone" => &["1", "2"],
+        }
+        .unwrap();
+        let other = df! { "email" => &["c@x"] }.unwrap();
+        piql::EvalContext::new()
+            .with_df("users", users.lazy())
+            .with_df("other", other.lazy())
+    }
+
+    fn check(query: &str) -> Result<(), String> {
+        mask().check(&piql::compile(query, &ctx()).unwrap())
+    }
+
+    #[test]
+    fn rejects_queries_mentioning_masked_columns() {
+        assert_eq!(
+            check("users.select($email)").unwrap_err(),
+            "column `users.email` is not available to this credential"
+        );
+        assert!(check(r#"users.sort("phone")"#).is_err());
+        assert!(check(r#"users.filter(pl.col("email") == "a@x")"#).is_err());
+        assert!(check("users.select($name)").is_ok());
+        // Masks are per table
+        assert!(check("other.select($email)").is_ok());
+    }
+
+    fn run(ctx: &piql::EvalContext, query: &str) -> DataFrame {
+        match piql::run(query, ctx).unwrap() {
+            piql::Value::DataFrame(lf, _) => lf.collect().unwrap(),
+            _ => panic!("expected a table"),
+        }
+    }
+
+    #[test]
+    fn restricted_tables_hide_masked_columns_from_any_query() {
+        let views = HashMap::from([
+            (
+                "contacts".to_string(),
+                Materialization {
+                    query: "users".to_string(),
+                    dependencies: BTreeSet::from(["users".to_string()]),
+                },
+            ),
+            (
+                "emails".to_string(),
+                Materialization {
+                    query: "users.select($email)".to_string(),
+                    dependencies: BTreeSet::from(["users".to_string()]),
+                },
+            ),
+        ]);
+        let mut ctx = ctx();
+        for (name, query) in [("contacts", "users"), ("emails", "users.select($email)")] {
+            let df = run(&ctx, query);
+            ctx = ctx.with_df(name, df.lazy());
+        }
+        let ctx = mask().restrict(ctx, &views);
+
+        for query in [
+            "users",
+            r#"users.select(pl.col("^.*$"))"#,
+            "users.select(pl.all())",
+            "contacts",
+        ] {
+            let df = run(&ctx, query);
+            assert_eq!(df.get_column_names(), ["name", "phone"], "{query}");
+            assert_eq!(df.column("phone").unwrap().null_count(), 2, "{query}");
+        }
+        // Redacted values can't be probed with filters either
+        assert_eq!(run(&ctx, r#"users.filter($phone == "1")"#).height(), 0);
+        // A view of dropped columns is hidden; masks are per table
+        assert!(!ctx.dataframes.contains_key("emails"));
+        assert_eq!(run(&ctx, "other").get_column_names(), ["email"]);
+    }
+}
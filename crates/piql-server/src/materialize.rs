@@ -0,0 +1,132 @@
+//! Materialized views: derived tables kept in sync with their upstream tables
+//!
+//! A view stores its source query and the tables that query reads. Whenever one of
+//! those tables receives a `DfUpdate`, every affected view is re-evaluated in
+//! dependency order (a view over another view is refreshed after it).
+
+use std::collections::{BTreeSet, HashMap};
+
+use piql::DependencyGraph;
+
+/// A derived table and the query that produces it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Materialization {
+    pub query: String,
+    /// Tables (including other views) the query reads
+    pub dependencies: BTreeSet<String>,
+}
+
+/// Dependency graph of `views`
+///
+/// Views that would close a dependency cycle are left out.
+pub fn dependency_graph(views: &HashMap<String, Materialization>) -> DependencyGraph {
+    let mut names: Vec<&String> = views.keys().collect();
+    names.sort();
+    let mut graph = DependencyGraph::new();
+    for name in names {
+        if let Err(e) = graph.insert(name.clone(), views[name].dependencies.iter().cloned()) {
+            log::warn!("Skipping materialized view {name}: {e}");
+        }
+    }
+    graph
+}
+
+/// Views affected by a change to `changed`, ordered so each view comes after the
+/// views it depends on.
+///
+/// Views caught in a dependency cycle are left out.
+pub fn refresh_order(changed: &str, views: &HashMap<String, Materialization>) -> Vec<String> {
+    downstream_order([changed], views)
+}
+
+/// Views downstream of any of `changed`, ordered as by [`refresh_order`]
+pub fn downstream_order<'a>(
+    changed: impl IntoIterator<Item = &'a str>,
+    views: &HashMap<String, Materialization>,
+) -> Vec<String> {
+    // Transitive closure of views downstream of `changed`
+    let mut affected = BTreeSet::new();
+    let mut frontier: Vec<String> = changed.into_iter().map(str::to_string).collect();
+    while let Some(table) = frontier.pop() {
+        for (name, view) in views {
+            if view.dependencies.contains(&table) && affected.insert(name.clone()) {
+                frontier.push(name.clone());
+            }
+        }
+    }
+
+    // Topological order within the affected set
+    let mut order = Vec::with_capacity(affected.len());
+    while !affected.is_empty() {
+        let ready: Vec<String> = affected
+            .iter()
+            .filter(|name| {
+                views[*name]
+                    .dependencies
+                    .iter()
+                    .all(|dep| !affected.contains(dep))
+            })
+            .cloned()
+            .collect();
+        if ready.is_empty() {
+            log::warn!("Materialized views form a dependency cycle: {affected:?}");
+            break;
+        }
+        for name in ready {
+            affected.remove(&name);
+            order.push(name);
+        }
+    }
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn view(deps: &[&str]) -> Materialization {
+        Materialization {
+            query: String::new(),
+            dependencies: deps.iter().map(|d| d.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn refresh_order_follows_dependencies() {
+        // a -> b, a -> c, (b, c) -> d
+        let views = HashMap::from([
+            ("b".to_string(), view(&["a"])),
+            ("c".to_string(), view(&["a"])),
+            ("d".to_string(), view(&["b", "c"])),
+            ("unrelated".to_string(), view(&["z"])),
+        ]);
+
+        let order = refresh_order("a", &views);
+        assert_eq!(order, vec!["b", "c", "d"]);
+        assert_eq!(refresh_order("c", &views), vec!["d"]);
+        assert!(refresh_order("d", &views).is_empty());
+        assert_eq!(downstream_order(["b", "c"], &views), vec!["d"]);
+        assert_eq!(downstream_order(["z", "b"], &views), vec!["d", "unrelated"]);
+    }
+
+    #[test]
+    fn refresh_order_skips_cycles() {
+        let views = HashMap::from([
+            ("x".to_string(), view(&["src", "y"])),
+            ("y".to_string(), view(&["x"])),
+        ]);
+        assert!(refresh_order("src", &views).is_empty());
+    }
+
+    #[test]
+    fn dependency_graph_orders_views() {
+        let views = HashMap::from([
+            ("d".to_string(), view(&["b", "c"])),
+            ("b".to_string(), view(&["a"])),
+            ("c".to_string(), view(&["a"])),
+        ]);
+        let graph = dependency_graph(&views);
+        assert_eq!(graph.topological_order(), vec!["b", "c", "d"]);
+        assert_eq!(graph.dependents("b"), vec!["d"]);
+    }
+}
@@ -0,0 +1,286 @@
+//! Prometheus metrics
+//!
+//! Counters are plain atomics updated on the query and update paths; `render`
+//! produces the text exposition format served by `GET /metrics`.
+
+use std::fmt::Write;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Upper bounds (seconds) of the query latency histogram buckets
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// How a query ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryOutcome {
+    Ok,
+    ParseError,
+    EvalError,
+}
+
+impl QueryOutcome {
+    pub fn of<T>(result: &Result<T, piql::PiqlError>) -> Self {
+        match result {
+            Ok(_) => Self::Ok,
+            Err(piql::PiqlError::Parse(_)) => Self::ParseError,
+            Err(_) => Self::EvalError,
+        }
+    }
+}
+
+/// Kind of DataFrame update applied to the server state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateKind {
+    Insert,
+    Remove,
+    Reload,
+    Append,
+}
+
+#[derive(Default)]
+struct Histogram {
+    /// Non-cumulative counts per bucket; the last slot is `+Inf`
+    buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    sum_micros: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        let i = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| secs <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Server-wide metrics
+#[derive(Default)]
+pub struct Metrics {
+    queries: AtomicU64,
+    parse_errors: AtomicU64,
+    eval_errors: AtomicU64,
+    rows_returned: AtomicU64,
+    latency: Histogram,
+    subscribers: AtomicU64,
+    inserts: AtomicU64,
+    removes: AtomicU64,
+    reloads: AtomicU64,
+    appends: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    /// Time collects spent queued for a compute thread
+    compute_wait: Histogram,
+    compute_rejected: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a finished query
+    pub fn record_query(&self, outcome: QueryOutcome, elapsed: Duration, rows: usize) {
+        self.queries.fetch_add(1, Ordering::Relaxed);
+        match outcome {
+            QueryOutcome::Ok => {
+                self.rows_returned.fetch_add(rows as u64, Ordering::Relaxed);
+            }
+            QueryOutcome::ParseError => {
+                self.parse_errors.fetch_add(1, Ordering::Relaxed);
+            }
+            QueryOutcome::EvalError => {
+                self.eval_errors.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.latency.observe(elapsed);
+    }
+
+    pub fn record_update(&self, kind: UpdateKind) {
+        let counter = match kind {
+            UpdateKind::Insert => &self.inserts,
+            UpdateKind::Remove => &self.removes,
+            UpdateKind::Reload => &self.reloads,
+            UpdateKind::Append => &self.appends,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a result cache lookup
+    pub fn record_cache(&self, hit: bool) {
+        let counter = if hit {
+            &self.cache_hits
+        } else {
+            &self.cache_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record how long a job waited for a compute thread
+    pub fn record_compute_wait(&self, waited: Duration) {
+        self.compute_wait.observe(waited);
+    }
+
+    /// Count a job turned away because the compute queue was full
+    pub fn record_compute_rejected(&self) {
+        self.compute_rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count an SSE subscriber for as long as the returned guard is alive
+    pub fn track_subscriber(self: &Arc<Self>) -> SubscriberGuard {
+        self.subscribers.fetch_add(1, Ordering::Relaxed);
+        SubscriberGuard(self.clone())
+    }
+
+    pub fn active_subscribers(&self) -> u64 {
+        self.subscribers.load(Ordering::Relaxed)
+    }
+
+    /// Render in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let load = |c: &AtomicU64| c.load(Ordering::Relaxed);
+        let mut out = String::new();
+
+        counter(
+            &mut out,
+            "piql_queries_total",
+            "Queries executed",
+            load(&self.queries),
+        );
+        counter(
+            &mut out,
+            "piql_query_parse_errors_total",
+            "Queries that failed to parse",
+            load(&self.parse_errors),
+        );
+        counter(
+            &mut out,
+            "piql_query_eval_errors_total",
+            "Queries that failed during evaluation",
+            load(&self.eval_errors),
+        );
+        counter(
+            &mut out,
+            "piql_rows_returned_total",
+            "Rows returned by successful queries",
+            load(&self.rows_returned),
+        );
+
+        counter(
+            &mut out,
+            "piql_cache_hits_total",
+            "Queries served from the result cache",
+            load(&self.cache_hits),
+        );
+        counter(
+            &mut out,
+            "piql_cache_misses_total",
+            "Cacheable queries that had to be evaluated",
+            load(&self.cache_misses),
+        );
+
+        histogram(
+            &mut out,
+            "piql_query_duration_seconds",
+            "Query latency",
+            &self.latency,
+        );
+        histogram(
+            &mut out,
+            "piql_compute_queue_wait_seconds",
+            "Time collects waited for a compute thread",
+            &self.compute_wait,
+        );
+        counter(
+            &mut out,
+            "piql_compute_rejected_total",
+            "Queries rejected because the compute queue was full",
+            load(&self.compute_rejected),
+        );
+
+        let name = "piql_sse_subscribers";
+        let _ = writeln!(out, "# HELP {name} Active SSE subscriptions");
+        let _ = writeln!(out, "# TYPE {name} gauge");
+        let _ = writeln!(out, "{name} {}", load(&self.subscribers));
+
+        let name = "piql_dataframe_updates_total";
+        let _ = writeln!(out, "# HELP {name} DataFrame updates applied, by kind");
+        let _ = writeln!(out, "# TYPE {name} counter");
+        for (kind, c) in [
+            ("insert", &self.inserts),
+            ("remove", &self.removes),
+            ("reload", &self.reloads),
+            ("append", &self.appends),
+        ] {
+            let _ = writeln!(out, "{name}{{kind=\"{kind}\"}} {}", load(c));
+        }
+
+        out
+    }
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} counter");
+    let _ = writeln!(out, "{name} {value}");
+}
+
+fn histogram(out: &mut String, name: &str, help: &str, histogram: &Histogram) {
+    let load = |c: &AtomicU64| c.load(Ordering::Relaxed);
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} histogram");
+    let mut cumulative = 0;
+    for (bound, bucket) in LATENCY_BUCKETS.iter().zip(&histogram.buckets) {
+        cumulative += load(bucket);
+        let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {cumulative}");
+    }
+    cumulative += load(&histogram.buckets[LATENCY_BUCKETS.len()]);
+    let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {cumulative}");
+    let sum = load(&histogram.sum_micros) as f64 / 1e6;
+    let _ = writeln!(out, "{name}_sum {sum}");
+    let _ = writeln!(out, "{name}_count {}", load(&histogram.count));
+}
+
+/// Decrements the subscriber gauge on drop
+pub struct SubscriberGuard(Arc<Metrics>);
+
+impl Drop for SubscriberGuard {
+    fn drop(&mut self) {
+        self.0.subscribers.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_reports_counters_and_histogram() {
+        let metrics = Arc::new(Metrics::new());
+        metrics.record_query(QueryOutcome::Ok, Duration::from_millis(3), 10);
+        metrics.record_query(QueryOutcome::ParseError, Duration::from_millis(20), 0);
+        metrics.record_update(UpdateKind::Reload);
+        let guard = metrics.track_subscriber();
+
+        let text = metrics.render();
+        assert!(text.contains("piql_queries_total 2\n"));
+        assert!(text.contains("piql_query_parse_errors_total 1\n"));
+        assert!(text.contains("piql_rows_returned_total 10\n"));
+        assert!(text.contains("piql_query_duration_seconds_bucket{le=\"0.001\"} 0\n"));
+        assert!(text.contains("piql_query_duration_seconds_bucket{le=\"0.005\"} 1\n"));
+        assert!(text.contains("piql_query_duration_seconds_bucket{le=\"+Inf\"} 2\n"));
+        assert!(text.contains("piql_sse_subscribers 1\n"));
+        assert!(text.contains("piql_dataframe_updates_total{kind=\"reload\"} 1\n"));
+
+        drop(guard);
+        assert_eq!(metrics.active_subscribers(), 0);
+    }
+}
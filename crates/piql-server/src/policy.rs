@@ -0,0 +1,295 @@
+//! Query policy: restrict what queries may do, e.g. for a public demo
+//!
+//! The policy inspects each query's core AST before it is evaluated:
+//! - blocked constructs (denied methods, cross joins, full history of large tables)
+//!   reject the query with an error naming the construct
+//! - `head`/`tail`/`top` row counts above the cap are lowered to it
+//!
+//! In read-only mode, the router also rejects every request that modifies tables
+//! (the same endpoints that need `write` scope, see [`crate::auth::required_scope`]).
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use axum::Json;
+use axum::extract::{Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use piql::advanced::{Arg, CoreArg, CoreExpr, Literal};
+use piql::{CompiledQuery, EvalContext};
+
+use crate::auth::{Scope, required_scope};
+use crate::core::ServerCore;
+use crate::state::ErrorResponse;
+
+/// Methods reading a table's full history
+const HISTORY_METHODS: [&str; 6] = ["all", "window", "since", "latest", "last_ticks", "resample"];
+
+/// Row-count methods capped by `max_head`, with the count's default
+const ROW_COUNT_METHODS: [(&str, Option<i64>); 3] =
+    [("head", Some(10)), ("tail", Some(10)), ("top", None)];
+
+/// What queries may do (the default allows everything)
+#[derive(Debug, Clone, Default)]
+pub struct QueryPolicy {
+    /// Reject HTTP requests that upload, delete or materialize tables
+    pub read_only: bool,
+    /// If set, only these methods may be called (`pl.*` functions are always allowed)
+    pub allowed_methods: Option<BTreeSet<String>>,
+    /// Methods that may not be called, e.g. `join` or `all`
+    pub denied_methods: BTreeSet<String>,
+    /// Reject `join(..., how="cross")`
+    pub deny_cross_joins: bool,
+    /// Reject `.all()`, `.window()`, `.since()`, `.latest()`, `.last_ticks()` and `.resample()`
+    /// on tables with more rows than this
+    pub max_history_rows: Option<usize>,
+    /// Lower `head(n)`, `tail(n)` and `top(n, ...)` to at most this many rows, rejecting
+    /// counts that aren't positive integer literals
+    pub max_head: Option<i64>,
+}
+
+impl QueryPolicy {
+    /// Whether queries are evaluated unchanged
+    pub fn allows_all_queries(&self) -> bool {
+        self.allowed_methods.is_none()
+            && self.denied_methods.is_empty()
+            && !self.deny_cross_joins
+            && self.max_history_rows.is_none()
+            && self.max_head.is_none()
+    }
+
+    /// Check `compiled` against the policy, rewriting capped row counts
+    ///
+    /// The error names the blocked construct.
+    pub fn apply(
+        &self,
+        compiled: CompiledQuery,
+        ctx: &EvalContext,
+    ) -> Result<CompiledQuery, String> {
+        if self.allows_all_queries() {
+            return Ok(compiled);
+        }
+        let mut core = compiled.core().clone();
+        self.visit(&mut core, ctx)?;
+        Ok(compiled.with_core(core))
+    }
+
+    fn visit(&self, expr: &mut CoreExpr, ctx: &EvalContext) -> Result<(), String> {
+        match expr {
+            CoreExpr::Call(callee, args) => {
+                if let CoreExpr::Attr(receiver, method) = callee.as_ref() {
+                    self.check_call(receiver, method, args, ctx)?;
+                    self.cap_rows(method, args)?;
+                }
+                self.visit(callee, ctx)?;
+                args.iter_mut().try_for_each(|arg| match arg {
+                    Arg::Positional(e) | Arg::Keyword(_, e) => self.visit(e, ctx),
+                })
+            }
+            CoreExpr::Attr(base, _) | CoreExpr::UnaryOp(_, base) => self.visit(base, ctx),
+            CoreExpr::BinaryOp(lhs, _, rhs) => {
+                self.visit(lhs, ctx)?;
+                self.visit(rhs, ctx)
+            }
+            CoreExpr::List(items) => items.iter_mut().try_for_each(|e| self.visit(e, ctx)),
+            CoreExpr::WhenThenOtherwise {
+                branches,
+                otherwise,
+            } => {
+                for (condition, value) in branches {
+                    self.visit(condition, ctx)?;
+                    self.visit(value, ctx)?;
+                }
+                self.visit(otherwise, ctx)
+            }
+            CoreExpr::Ident(_) | CoreExpr::Literal(_) | CoreExpr::Invalid(_) => Ok(()),
+        }
+    }
+
+    fn check_call(
+        &self,
+        receiver: &CoreExpr,
+        method: &str,
+        args: &[CoreArg],
+        ctx: &EvalContext,
+    ) -> Result<(), String> {
+        let namespaced = matches!(receiver, CoreExpr::Ident(name) if name == "pl");
+        if !namespaced {
+            if self.denied_methods.contains(method) {
+                return Err(format!("`.{method}()` is disabled on this server"));
+            }
+            if let Some(allowed) = &self.allowed_methods
+                && !allowed.contains(method)
+            {
+                return Err(format!(
+                    "`.{method}()` is not among the methods allowed on this server"
+                ));
+            }
+        }
+
+        if self.deny_cross_joins
+            && method == "join"
+            && args.iter().any(|arg| {
+                matches!(arg, Arg::Keyword(name, CoreExpr::Literal(Literal::String(how)))
+                    if name == "how" && how == "cross")
+            })
+        {
+            return Err(
+                "cross joins (`join(..., how=\"cross\")`) are disabled on this server".into(),
+            );
+        }
+
+        if let Some(max) = self.max_history_rows
+            && HISTORY_METHODS.contains(&method)
+            && let CoreExpr::Ident(table) = receiver
+            && let Some(entry) = ctx.dataframes.get(table)
+            && let Ok(rows) = entry.height()
+            && rows > max
+        {
+            return Err(format!(
+                "`{table}.{method}()` would read {rows} rows; this server allows at most {max}"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Lower row counts above `max_head`; a count that isn't a positive integer literal is
+    /// rejected, since eval would otherwise read it as "every row"
+    fn cap_rows(&self, method: &str, args: &mut Vec<CoreArg>) -> Result<(), String> {
+        let Some(max) = self.max_head else {
+            return Ok(());
+        };
+        let Some((_, default)) = ROW_COUNT_METHODS.iter().find(|(name, _)| *name == method) else {
+            return Ok(());
+        };
+        match args
+            .iter_mut()
+            .find(|arg| matches!(arg, Arg::Positional(_)))
+        {
+            Some(Arg::Positional(CoreExpr::Literal(Literal::Int(n)))) if *n >= 1 => {
+                *n = (*n).min(max);
+            }
+            Some(_) => {
+                return Err(format!(
+                    "`.{method}()` row count must be a positive integer on this server"
+                ));
+            }
+            None if default.is_some_and(|n| n > max) => {
+                args.insert(0, Arg::Positional(CoreExpr::Literal(Literal::Int(max))));
+            }
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+/// Middleware rejecting requests that modify tables when the policy is read-only
+pub async fn reject_writes(
+    State(core): State<Arc<ServerCore>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if core.policy().read_only
+        && required_scope(request.method(), request.uri().path()) == Scope::Write
+    {
+        log::warn!(
+            "Rejected {} {}: server is read-only",
+            request.method(),
+            request.uri().path()
+        );
+        return (
+            StatusCode::FORBIDDEN,
+            Json(ErrorResponse {
+                error: "this server is read-only".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::prelude::*;
+
+    fn ctx() -> EvalContext {
+        let df = df! { "a" => &[1, 2, 3], "k" => &[0, 0, 0] }.unwrap();
+        EvalContext::new().with_df("t", df.lazy())
+    }
+
+    /// Rows returned by `query` under `policy`
+    fn run(policy: &QueryPolicy, query: &str) -> Result<usize, String> {
+        let ctx = ctx();
+        let compiled = policy.apply(piql::compile(query, &ctx).unwrap(), &ctx)?;
+        match piql::run_compiled(&compiled, &ctx).unwrap() {
+            piql::Value::DataFrame(lf, _) => Ok(lf.collect().unwrap().height()),
+            _ => panic!("expected DataFrame"),
+        }
+    }
+
+    #[test]
+    fn blocks_denied_and_unlisted_methods() {
+        let denied = QueryPolicy {
+            denied_methods: ["sort".to_string()].into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            run(&denied, r#"t.sort("a")"#).unwrap_err(),
+            "`.sort()` is disabled on this server"
+        );
+        assert_eq!(run(&denied, "t.filter($a > 1)"), Ok(2));
+
+        let allowed = QueryPolicy {
+            allowed_methods: Some(["filter".to_string()].into()),
+            ..Default::default()
+        };
+        // `$a` is `pl.col("a")`, which is always allowed
+        assert_eq!(run(&allowed, "t.filter($a > 1)"), Ok(2));
+        assert!(
+            run(&allowed, "t.head(1)")
+                .unwrap_err()
+                .contains("`.head()`")
+        );
+    }
+
+    #[test]
+    fn blocks_cross_joins_and_large_history_reads() {
+        let policy = QueryPolicy {
+            deny_cross_joins: true,
+            max_history_rows: Some(2),
+            ..Default::default()
+        };
+        assert!(
+            run(&policy, r#"t.join(t, on="k", how="cross")"#)
+                .unwrap_err()
+                .contains("cross joins")
+        );
+        assert_eq!(run(&policy, r#"t.join(t, on="k")"#), Ok(9));
+        assert_eq!(
+            run(&policy, "t.all()").unwrap_err(),
+            "`t.all()` would read 3 rows; this server allows at most 2"
+        );
+    }
+
+    #[test]
+    fn caps_row_counts() {
+        let policy = QueryPolicy {
+            max_head: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(run(&policy, "t.head(100)"), Ok(2));
+        assert_eq!(run(&policy, "t.head()"), Ok(2));
+        assert_eq!(run(&policy, "t.head(1)"), Ok(1));
+        assert_eq!(run(&policy, r#"t.top(5, "a")"#), Ok(2));
+        for query in ["t.head(-1)", "t.tail(-1)", r#"t.top(-1, "a")"#, "t.head(0)"] {
+            assert!(
+                run(&policy, query)
+                    .unwrap_err()
+                    .contains("positive integer")
+            );
+        }
+        assert!(QueryPolicy::default().allows_all_queries());
+    }
+}
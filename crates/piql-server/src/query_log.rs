@@ -0,0 +1,153 @@
+//! Built-in `_queries` table: the server's own query log
+//!
+//! Every query executed through the server (`/query`, `/subscribe`, Flight) is
+//! appended with its text, start time, duration, row count and error, so the log can
+//! be queried like any table: `_queries.top(10, "duration_ms")`. Only the most recent
+//! entries are kept.
+//!
+//! Appends bump the table's version, so cached results over `_queries` go stale, but
+//! don't notify subscribers: a subscription re-evaluated on its own log entry would
+//! never settle.
+
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use polars::prelude::*;
+
+/// Reserved table name of the query log
+pub const QUERY_LOG_TABLE: &str = "_queries";
+
+/// Entries kept by default
+pub const DEFAULT_CAPACITY: usize = 1000;
+
+/// One executed query
+#[derive(Debug, Clone)]
+pub struct QueryLogEntry {
+    pub query: String,
+    pub started_at: SystemTime,
+    pub duration: Duration,
+    /// Result rows (`None` if the query failed)
+    pub rows: Option<usize>,
+    pub error: Option<String>,
+}
+
+/// Ring buffer of the most recently executed queries
+pub struct QueryLog {
+    capacity: usize,
+    entries: VecDeque<QueryLogEntry>,
+}
+
+impl QueryLog {
+    /// A log keeping up to `capacity` entries (0 disables logging)
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: VecDeque::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.entries.len() > capacity {
+            self.entries.pop_front();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Append `entry`, evicting the oldest one when full
+    pub fn record(&mut self, entry: QueryLogEntry) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// The log as a DataFrame, oldest entry first
+    ///
+    /// Columns: `started_at` (UTC, milliseconds), `query`, `duration_ms`, `rows`, `error`.
+    pub fn to_df(&self) -> PolarsResult<DataFrame> {
+        let started_at: Vec<i64> = self
+            .entries
+            .iter()
+            .map(|e| {
+                e.started_at
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_millis() as i64)
+            })
+            .collect();
+        let query: Vec<&str> = self.entries.iter().map(|e| e.query.as_str()).collect();
+        let duration_ms: Vec<f64> = self
+            .entries
+            .iter()
+            .map(|e| e.duration.as_secs_f64() * 1000.0)
+            .collect();
+        let rows: Vec<Option<u64>> = self
+            .entries
+            .iter()
+            .map(|e| e.rows.map(|n| n as u64))
+            .collect();
+        let error: Vec<Option<&str>> = self.entries.iter().map(|e| e.error.as_deref()).collect();
+
+        DataFrame::new(vec![
+            Column::new("started_at".into(), started_at)
+                .cast(&DataType::Datetime(TimeUnit::Milliseconds, None))?,
+            Column::new("query".into(), query),
+            Column::new("duration_ms".into(), duration_ms),
+            Column::new("rows".into(), rows),
+            Column::new("error".into(), error),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(query: &str, rows: Option<usize>) -> QueryLogEntry {
+        QueryLogEntry {
+            query: query.to_string(),
+            started_at: SystemTime::now(),
+            duration: Duration::from_millis(5),
+            rows,
+            error: rows.is_none().then(|| "boom".to_string()),
+        }
+    }
+
+    #[test]
+    fn keeps_most_recent_entries() {
+        let mut log = QueryLog::new(2);
+        log.record(entry("a", Some(1)));
+        log.record(entry("b", None));
+        log.record(entry("c", Some(3)));
+        assert_eq!(log.len(), 2);
+
+        let df = log.to_df().unwrap();
+        let queries: Vec<_> = df.column("query").unwrap().str().unwrap().iter().collect();
+        assert_eq!(queries, [Some("b"), Some("c")]);
+        assert_eq!(df.column("rows").unwrap().null_count(), 1);
+        assert_eq!(df.column("error").unwrap().null_count(), 1);
+        assert_eq!(
+            df.column("duration_ms").unwrap().f64().unwrap().get(0),
+            Some(5.0)
+        );
+
+        log.set_capacity(0);
+        assert!(log.is_empty());
+        log.record(entry("d", Some(1)));
+        assert!(log.is_empty());
+    }
+}
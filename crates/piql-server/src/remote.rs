@@ -0,0 +1,90 @@
+//! Remote (object store / HTTP) table sources
+//!
+//! Object stores have no change notifications, so remote tables are re-read on an
+//! interval and replaced only when their contents changed.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use polars::prelude::DataFrame;
+
+use crate::core::ServerCore;
+use crate::loader::{LoadOptions, load_url, remote_df_name};
+use crate::state::DfUpdate;
+use crate::table_stats::TableOrigin;
+
+/// Periodically re-reads remote tables; polling stops when dropped
+pub struct RemotePoller {
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl Drop for RemotePoller {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Load remote tables, then re-read them every `interval` (if set)
+///
+/// Failed loads are logged and retried on the next poll; a table whose read fails
+/// keeps its last good data.
+pub async fn load_and_poll(
+    core: Arc<ServerCore>,
+    urls: Vec<String>,
+    options: LoadOptions,
+    interval: Option<Duration>,
+) -> Option<RemotePoller> {
+    let mut last: HashMap<String, DataFrame> = HashMap::new();
+    refresh(&core, &urls, &options, &mut last).await;
+
+    let interval = interval?;
+    let task = tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            refresh(&core, &urls, &options, &mut last).await;
+        }
+    });
+    Some(RemotePoller { task })
+}
+
+/// Read every URL and publish the tables that are new or changed since `last`
+async fn refresh(
+    core: &ServerCore,
+    urls: &[String],
+    options: &LoadOptions,
+    last: &mut HashMap<String, DataFrame>,
+) {
+    for url in urls {
+        let df = match load_url(url, options).await {
+            Ok(df) => df,
+            Err(e) => {
+                log::warn!("Failed to load {url}: {e}");
+                continue;
+            }
+        };
+        if last.get(url).is_some_and(|prev| prev.equals_missing(&df)) {
+            continue;
+        }
+        let name = remote_df_name(url);
+        let update = if last.contains_key(url) {
+            log::info!("Reloaded {name} from {url}");
+            core.reload_update(name.clone(), df.clone()).await
+        } else {
+            log::info!("Loaded {name} from {url}");
+            DfUpdate::Insert {
+                name: name.clone(),
+                df: df.clone(),
+            }
+        };
+        core.apply_update(update).await;
+        let origin = TableOrigin {
+            path: Some(url.clone()),
+            run: None,
+        };
+        core.set_table_origin(&name, origin).await;
+        last.insert(url.clone(), df);
+    }
+}
@@ -4,21 +4,45 @@
 //! - `table` → latest run's version
 //! - `run_name::table` → specific run's version
 //! - `_all::table` → all runs concatenated with a run-label column (`_run` by default)
+//!
+//! The registry lives on [`ServerCore::runs`]; the run watcher and the `/runs`
+//! endpoints (list, load, unload, promote) both go through it.
 
 use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
 
+use axum::Json;
+use axum::extract::{Path as UrlPath, State};
+use axum::http::StatusCode;
+use log::info;
 use polars::prelude::*;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use utoipa::{OpenApi, ToSchema};
 
 use crate::core::ServerCore;
-use crate::state::DfUpdate;
+use crate::error::AppError;
+use crate::loader::{collect_files, df_name_from_path, load_file_sync};
+use crate::state::{DfUpdate, ErrorResponse};
+use crate::table_stats::TableOrigin;
+
+/// OpenAPI documentation for run endpoints
+#[derive(OpenApi)]
+#[openapi(
+    paths(list, load, delete, promote),
+    components(schemas(RunSummary, RunsResponse, LoadRunRequest))
+)]
+pub struct RunsApiDoc;
 
-pub const DEFAULT_RUN_LABEL_COLUMN: &str = "_run";
+pub use piql::DEFAULT_RUN_LABEL_COLUMN;
 
 #[derive(Debug, Clone)]
 pub struct RunRegistryOptions {
     pub run_label_column: String,
     pub drop_existing_run_label_column: bool,
+    /// Parent of the run directories; `POST /runs/{name}/load` reads paths under it
+    pub dir: Option<PathBuf>,
 }
 
 impl Default for RunRegistryOptions {
@@ -26,6 +50,7 @@ impl Default for RunRegistryOptions {
         Self {
             run_label_column: DEFAULT_RUN_LABEL_COLUMN.to_string(),
             drop_existing_run_label_column: false,
+            dir: None,
         }
     }
 }
@@ -49,6 +74,46 @@ pub enum RunRegistryError {
         column: String,
         source: PolarsError,
     },
+    #[error("unknown run '{0}'")]
+    Unknown(String),
+    #[error("run '{0}' is already loaded")]
+    AlreadyLoaded(String),
+    #[error("'_all' is reserved and can't name a run")]
+    ReservedName,
+    #[error("run '{0}' has no loadable tables")]
+    NoTables(String),
+    #[error("loading runs over HTTP requires a runs directory (--runs)")]
+    NoRunsDir,
+    #[error("run path '{0}' must be relative to the runs directory, without '..'")]
+    InvalidPath(String),
+}
+
+/// A loaded run, as listed by `GET /runs`
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct RunSummary {
+    pub name: String,
+    /// Table names, sorted
+    pub tables: Vec<String>,
+    /// Rows across all tables
+    pub rows: usize,
+    /// Whether bare table names resolve to this run
+    pub latest: bool,
+}
+
+/// Response of `GET /runs`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RunsResponse {
+    /// Loaded runs, oldest first
+    pub runs: Vec<RunSummary>,
+    /// Run behind the bare table names
+    pub latest: Option<String>,
+}
+
+/// JSON body of `POST /runs/{name}/load`
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct LoadRunRequest {
+    /// Directory of table files, relative to the runs directory
+    pub path: String,
 }
 
 pub struct RunRegistry {
@@ -81,6 +146,38 @@ impl RunRegistry {
         }
     }
 
+    /// Replace the options; they apply to runs loaded afterwards
+    pub fn set_options(&mut self, options: RunRegistryOptions) {
+        self.options = options;
+    }
+
+    /// Whether a run with this name is loaded
+    pub fn has_run(&self, run_name: &str) -> bool {
+        self.runs.iter().any(|r| r.name == run_name)
+    }
+
+    /// Name of the run behind the bare table names
+    pub fn latest(&self) -> Option<&str> {
+        self.latest.as_deref()
+    }
+
+    /// Loaded runs, oldest first
+    pub fn summaries(&self) -> Vec<RunSummary> {
+        self.runs
+            .iter()
+            .map(|run| {
+                let mut tables: Vec<String> = run.tables.keys().cloned().collect();
+                tables.sort();
+                RunSummary {
+                    rows: run.tables.values().map(DataFrame::height).sum(),
+                    latest: self.latest.as_deref() == Some(run.name.as_str()),
+                    name: run.name.clone(),
+                    tables,
+                }
+            })
+            .collect()
+    }
+
     /// Load a new run, registering all three naming tiers.
     pub async fn load_run(
         &mut self,
@@ -93,7 +190,7 @@ impl RunRegistry {
             return Ok(());
         }
 
-        if self.runs.iter().any(|r| r.name == run_name) {
+        if self.has_run(run_name) {
             log::debug!("Run '{}' already loaded, skipping", run_name);
             return Ok(());
         }
@@ -112,6 +209,8 @@ impl RunRegistry {
             // 1. Register run-specific: run_name::table
             let specific_name = format!("{run_name}::{table}");
             core.insert_df(&specific_name, df.clone()).await;
+            core.set_table_origin(&specific_name, TableOrigin::run(run_name))
+                .await;
 
             // 2. Update latest bare name
             core.apply_update(DfUpdate::Reload {
@@ -119,6 +218,8 @@ impl RunRegistry {
                 df: df.clone(),
             })
             .await;
+            core.set_table_origin(table, TableOrigin::run(run_name))
+                .await;
 
             // 3. Build _run-annotated version for _all:: concat
             let with_run = df
@@ -142,6 +243,9 @@ impl RunRegistry {
             tables: annotated,
         });
         self.latest = Some(run_name.to_string());
+        core.set_current_run(self.latest.clone()).await;
+        core.set_run_label_column(&self.options.run_label_column)
+            .await;
 
         // 4. Incrementally update _all:: for each table in this run
         let table_names: Vec<String> = normalized_tables.keys().cloned().collect();
@@ -161,11 +265,31 @@ impl RunRegistry {
         Ok(())
     }
 
-    /// Remove a run and clean up all three tiers.
-    pub async fn remove_run(&mut self, run_name: &str, core: &ServerCore) {
+    /// Make a loaded run the one bare table names resolve to.
+    pub async fn promote(
+        &mut self,
+        run_name: &str,
+        core: &ServerCore,
+    ) -> Result<(), RunRegistryError> {
+        if !self.has_run(run_name) {
+            return Err(RunRegistryError::Unknown(run_name.to_string()));
+        }
+        self.latest = Some(run_name.to_string());
+        core.set_current_run(self.latest.clone()).await;
+        self.rebuild_latest_bare_names(core, self.all_table_names())
+            .await;
+        log::info!("Promoted run '{run_name}' to latest");
+        Ok(())
+    }
+
+    /// Remove a run and clean up all three tiers. Returns whether it was loaded.
+    ///
+    /// If it was the latest run, the most recently loaded remaining run takes over
+    /// the bare table names.
+    pub async fn remove_run(&mut self, run_name: &str, core: &ServerCore) -> bool {
         let Some(idx) = self.runs.iter().position(|r| r.name == run_name) else {
             log::debug!("Run '{}' not loaded, nothing to remove", run_name);
-            return;
+            return false;
         };
 
         let removed = self.runs.remove(idx);
@@ -181,7 +305,10 @@ impl RunRegistry {
         }
 
         let known_tables_before = self.all_table_names_with(&removed.tables);
-        self.latest = self.runs.last().map(|r| r.name.clone());
+        if self.latest.as_deref() == Some(run_name) {
+            self.latest = self.runs.last().map(|r| r.name.clone());
+            core.set_current_run(self.latest.clone()).await;
+        }
 
         // Rebuild _all:: and bare names for affected tables
         for table in &table_names {
@@ -196,6 +323,7 @@ impl RunRegistry {
             table_names.len(),
             self.runs.len()
         );
+        true
     }
 
     /// Incrementally append one run's annotated table into `_all::{table}`.
@@ -330,8 +458,14 @@ impl RunRegistry {
         for table in known_tables {
             match self.latest_df_for(&table) {
                 Some(df) => {
-                    core.apply_update(DfUpdate::Reload { name: table, df })
-                        .await;
+                    core.apply_update(DfUpdate::Reload {
+                        name: table.clone(),
+                        df,
+                    })
+                    .await;
+                    if let Some(run) = &self.latest {
+                        core.set_table_origin(&table, TableOrigin::run(run)).await;
+                    }
                 }
                 None => {
                     core.apply_update(DfUpdate::Remove { name: table }).await;
@@ -347,10 +481,169 @@ impl Default for RunRegistry {
     }
 }
 
+/// Read every loadable table in a run directory, keyed by table name. Files that
+/// fail to load are logged and skipped.
+pub async fn read_run_dir(run_dir: &Path) -> HashMap<String, DataFrame> {
+    let run_dir = run_dir.to_path_buf();
+    let read = tokio::task::spawn_blocking(move || {
+        let mut tables = HashMap::new();
+        for path in collect_files(&[run_dir]) {
+            match load_file_sync(&path) {
+                Ok(df) => {
+                    tables.insert(df_name_from_path(&path), df);
+                }
+                Err(e) => log::warn!("Failed to load {}: {}", path.display(), e),
+            }
+        }
+        tables
+    });
+    read.await.unwrap_or_else(|e| {
+        log::error!("Failed to read run directory: {e}");
+        HashMap::new()
+    })
+}
+
+/// `path` under the runs directory `dir`, rejecting absolute paths and `..`
+fn resolve_run_dir(dir: &Path, path: &str) -> Result<PathBuf, RunRegistryError> {
+    let relative = Path::new(path);
+    let plain = relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if !plain {
+        return Err(RunRegistryError::InvalidPath(path.to_string()));
+    }
+    Ok(dir.join(relative))
+}
+
+/// Load the tables under `path` (relative to the runs directory) as run `run_name`
+pub async fn load_from_dir(
+    core: &ServerCore,
+    run_name: &str,
+    path: &str,
+) -> Result<RunSummary, RunRegistryError> {
+    if run_name == "_all" {
+        return Err(RunRegistryError::ReservedName);
+    }
+    let run_dir = {
+        let registry = core.runs().lock().await;
+        if registry.has_run(run_name) {
+            return Err(RunRegistryError::AlreadyLoaded(run_name.to_string()));
+        }
+        let dir = registry
+            .options
+            .dir
+            .as_deref()
+            .ok_or(RunRegistryError::NoRunsDir)?;
+        resolve_run_dir(dir, path)?
+    };
+
+    let tables = read_run_dir(&run_dir).await;
+    if tables.is_empty() {
+        return Err(RunRegistryError::NoTables(run_name.to_string()));
+    }
+    let mut registry = core.runs().lock().await;
+    // Re-check: the watcher may have loaded it while the files were read
+    if registry.has_run(run_name) {
+        return Err(RunRegistryError::AlreadyLoaded(run_name.to_string()));
+    }
+    registry.load_run(run_name, tables, core).await?;
+    Ok(registry
+        .summaries()
+        .into_iter()
+        .find(|run| run.name == run_name)
+        .expect("run was just loaded"))
+}
+
+// ============ HTTP ============
+
+/// List loaded runs
+#[utoipa::path(
+    get,
+    path = "/runs",
+    responses((status = 200, description = "Loaded runs, oldest first", body = RunsResponse))
+)]
+pub async fn list(State(core): State<Arc<ServerCore>>) -> Json<RunsResponse> {
+    let registry = core.runs().lock().await;
+    Json(RunsResponse {
+        runs: registry.summaries(),
+        latest: registry.latest().map(str::to_string),
+    })
+}
+
+/// Load a run from a directory
+///
+/// Registers every table file under `path` (relative to the runs directory) as
+/// `{name}::table`, adds it to `_all::table`, and makes it the latest run.
+#[utoipa::path(
+    post,
+    path = "/runs/{name}/load",
+    params(("name" = String, Path, description = "Run name")),
+    request_body = LoadRunRequest,
+    responses(
+        (status = 200, description = "The loaded run", body = RunSummary),
+        (status = 400, description = "Reserved or already loaded name, invalid path or no tables", body = ErrorResponse)
+    )
+)]
+pub async fn load(
+    State(core): State<Arc<ServerCore>>,
+    UrlPath(name): UrlPath<String>,
+    Json(request): Json<LoadRunRequest>,
+) -> Result<Json<RunSummary>, AppError> {
+    info!("POST /runs/{name}/load: {}", request.path);
+    Ok(Json(load_from_dir(&core, &name, &request.path).await?))
+}
+
+/// Unload a run
+///
+/// Removes `{name}::table` and its rows of `_all::table`; if it was the latest run,
+/// bare names move to the most recently loaded remaining run.
+#[utoipa::path(
+    delete,
+    path = "/runs/{name}",
+    params(("name" = String, Path, description = "Run name")),
+    responses(
+        (status = 204, description = "Run unloaded"),
+        (status = 400, description = "Unknown run", body = ErrorResponse)
+    )
+)]
+pub async fn delete(
+    State(core): State<Arc<ServerCore>>,
+    UrlPath(name): UrlPath<String>,
+) -> Result<StatusCode, AppError> {
+    info!("DELETE /runs/{name}");
+    if !core.runs().lock().await.remove_run(&name, &core).await {
+        return Err(RunRegistryError::Unknown(name).into());
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Make a run the latest
+///
+/// Bare table names resolve to this run's tables until another run is loaded or
+/// promoted.
+#[utoipa::path(
+    post,
+    path = "/runs/{name}/promote",
+    params(("name" = String, Path, description = "Run name")),
+    responses(
+        (status = 204, description = "Run promoted"),
+        (status = 400, description = "Unknown run", body = ErrorResponse)
+    )
+)]
+pub async fn promote(
+    State(core): State<Arc<ServerCore>>,
+    UrlPath(name): UrlPath<String>,
+) -> Result<StatusCode, AppError> {
+    info!("POST /runs/{name}/promote");
+    core.runs().lock().await.promote(&name, &core).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use polars::df;
+    use polars::prelude::ParquetWriter;
     use std::collections::HashSet;
 
     #[tokio::test]
@@ -406,7 +699,8 @@ mod tests {
             result,
             Err(RunRegistryError::RunLabelColumnConflict { .. })
         ));
-        assert!(core.list_dataframes().await.is_empty());
+        // Only the built-in query log is registered
+        assert_eq!(core.list_dataframes().await, ["_queries", "_tables"]);
     }
 
     #[tokio::test]
@@ -432,6 +726,29 @@ mod tests {
         assert_eq!(run_col.get(0), Some("r1"));
     }
 
+    #[tokio::test]
+    async fn by_run_groups_over_configured_label_column() {
+        let core = ServerCore::new();
+        let mut registry = RunRegistry::with_options(RunRegistryOptions {
+            run_label_column: "run".to_string(),
+            ..Default::default()
+        });
+
+        let mut r1 = HashMap::new();
+        r1.insert("a".to_string(), df! { "x" => &[1.0, 3.0] }.unwrap());
+        registry.load_run("r1", r1, &core).await.unwrap();
+        let mut r2 = HashMap::new();
+        r2.insert("a".to_string(), df! { "x" => &[10.0] }.unwrap());
+        registry.load_run("r2", r2, &core).await.unwrap();
+
+        let df = core
+            .execute_query("_all::a.with_columns($x.mean.by_run.alias(\"m\"))")
+            .await
+            .unwrap();
+        let mean: Vec<_> = df.column("m").unwrap().f64().unwrap().iter().collect();
+        assert_eq!(mean, [Some(2.0), Some(2.0), Some(10.0)]);
+    }
+
     #[tokio::test]
     async fn incremental_all_cache_is_updated_on_each_load() {
         let core = ServerCore::new();
@@ -450,4 +767,77 @@ mod tests {
 
         assert_eq!(registry.all_tables["a"].height(), 2);
     }
+
+    #[tokio::test]
+    async fn promoted_run_keeps_bare_names_until_it_is_removed() {
+        let core = ServerCore::new();
+        let mut registry = RunRegistry::new();
+        for (name, x) in [("r1", 1), ("r2", 2), ("r3", 3)] {
+            let mut run = HashMap::new();
+            run.insert("a".to_string(), df! { "x" => &[x] }.unwrap());
+            registry.load_run(name, run, &core).await.unwrap();
+        }
+        let bare_x = |df: DataFrame| df.column("x").unwrap().i32().unwrap().get(0);
+
+        registry.promote("r1", &core).await.unwrap();
+        assert_eq!(registry.latest(), Some("r1"));
+        assert_eq!(bare_x(core.execute_query("a").await.unwrap()), Some(1));
+        assert!(matches!(
+            registry.promote("r9", &core).await,
+            Err(RunRegistryError::Unknown(_))
+        ));
+
+        // Removing another run leaves the promoted one in place
+        assert!(registry.remove_run("r3", &core).await);
+        assert_eq!(bare_x(core.execute_query("a").await.unwrap()), Some(1));
+        assert_eq!(core.execute_query("_all::a").await.unwrap().height(), 2);
+
+        assert!(registry.remove_run("r1", &core).await);
+        assert!(!registry.remove_run("r1", &core).await);
+        assert_eq!(registry.latest(), Some("r2"));
+        assert_eq!(bare_x(core.execute_query("a").await.unwrap()), Some(2));
+    }
+
+    #[tokio::test]
+    async fn runs_load_from_paths_under_the_runs_directory() {
+        let dir = std::env::temp_dir().join(format!("piql-run-load-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("r1")).unwrap();
+        let mut df = df! { "x" => &[1, 2] }.unwrap();
+        let mut file = std::fs::File::create(dir.join("r1/ticks.parquet")).unwrap();
+        ParquetWriter::new(&mut file).finish(&mut df).unwrap();
+
+        let core = ServerCore::new();
+        assert!(matches!(
+            load_from_dir(&core, "r1", "r1").await,
+            Err(RunRegistryError::NoRunsDir)
+        ));
+        core.runs().lock().await.set_options(RunRegistryOptions {
+            dir: Some(dir.clone()),
+            ..Default::default()
+        });
+        assert!(matches!(
+            load_from_dir(&core, "r1", "../r1").await,
+            Err(RunRegistryError::InvalidPath(_))
+        ));
+        assert!(matches!(
+            load_from_dir(&core, "_all", "r1").await,
+            Err(RunRegistryError::ReservedName)
+        ));
+
+        let summary = load_from_dir(&core, "first", "r1").await.unwrap();
+        assert_eq!(summary.tables, ["ticks"]);
+        assert_eq!(summary.rows, 2);
+        assert!(summary.latest);
+        assert_eq!(
+            core.execute_query("first::ticks").await.unwrap().height(),
+            2
+        );
+        assert!(matches!(
+            load_from_dir(&core, "first", "r1").await,
+            Err(RunRegistryError::AlreadyLoaded(_))
+        ));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
 }
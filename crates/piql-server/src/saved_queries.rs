@@ -0,0 +1,512 @@
+//! Saved-query library: named queries with descriptions and tags shared by analysts
+//!
+//! Saved queries are listed, searched and run under `/queries/saved`. With
+//! [`SavedQueries::open`] the library is kept in a JSON file, rewritten after every
+//! change; otherwise it lives in memory until the server stops.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use axum::Json;
+use axum::extract::{Extension, Path as UrlPath, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::Response;
+use chrono::Utc;
+use log::info;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::annotate::Annotations;
+use crate::core::ServerCore;
+use crate::error::AppError;
+use crate::mask::ColumnMask;
+use crate::schedule::{timestamp, valid_name};
+use crate::state::{ErrorResponse, QueryRequest};
+
+/// A query to save, or the new version of a saved one
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct SavedQueryRequest {
+    /// Letters, digits, `_` and `-`
+    pub name: String,
+    #[serde(flatten)]
+    pub query: QueryRequest,
+    #[serde(default)]
+    pub description: String,
+    /// Who saved the query (kept from the first version when an update omits it)
+    pub author: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// A query in the library
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SavedQuery {
+    pub name: String,
+    #[serde(flatten)]
+    pub query: QueryRequest,
+    #[serde(default)]
+    pub description: String,
+    pub author: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// RFC 3339, UTC
+    pub created_at: String,
+    /// RFC 3339, UTC
+    pub updated_at: String,
+}
+
+impl SavedQuery {
+    /// Whether the query has `tag` (if given) and every word of `text` (if given)
+    /// appears, ignoring case, in its name, description, query or tags
+    fn matches(&self, tag: Option<&str>, text: Option<&str>) -> bool {
+        if let Some(tag) = tag
+            && !self.tags.iter().any(|t| t.eq_ignore_ascii_case(tag))
+        {
+            return false;
+        }
+        let Some(text) = text else {
+            return true;
+        };
+        let haystack = format!(
+            "{} {} {} {}",
+            self.name,
+            self.description,
+            self.query.query,
+            self.tags.join(" ")
+        )
+        .to_lowercase();
+        text.split_whitespace()
+            .all(|word| haystack.contains(&word.to_lowercase()))
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SavedQueriesResponse {
+    /// Matching queries, sorted by name
+    pub queries: Vec<SavedQuery>,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+pub struct SearchParams {
+    /// Only queries with this tag (ignoring case)
+    pub tag: Option<String>,
+    /// Only queries whose name, description, query or tags contain every word
+    pub q: Option<String>,
+}
+
+#[derive(Debug, Error)]
+pub enum SavedQueryError {
+    #[error("invalid saved query name '{0}' (use letters, digits, '_' and '-')")]
+    InvalidName(String),
+    #[error("saved query '{0}' already exists (PUT /queries/saved/{0} to update it)")]
+    Exists(String),
+    #[error("saved query name '{body}' doesn't match the URL ('{url}')")]
+    NameMismatch { url: String, body: String },
+    #[error("{0}")]
+    InvalidParams(String),
+    #[error("Unknown saved query: {0}")]
+    Unknown(String),
+    #[error("saved queries file {path}: {message}")]
+    Io { path: PathBuf, message: String },
+    #[error(transparent)]
+    Query(#[from] piql::PiqlError),
+}
+
+impl From<SavedQueryError> for AppError {
+    fn from(e: SavedQueryError) -> Self {
+        match e {
+            SavedQueryError::Query(e) => e.into(),
+            SavedQueryError::Io { .. } => AppError {
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+                message: e.to_string(),
+            },
+            e => AppError::bad_request(e.to_string()),
+        }
+    }
+}
+
+/// The saved-query library, optionally backed by a JSON file
+#[derive(Default)]
+pub struct SavedQueries {
+    queries: Mutex<BTreeMap<String, SavedQuery>>,
+    /// File rewritten after every change (None = memory only)
+    path: Option<PathBuf>,
+}
+
+impl SavedQueries {
+    /// A library kept in `path`, starting from the queries already in it (if the
+    /// file exists)
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, SavedQueryError> {
+        let path = path.into();
+        let io_error = |message: String| SavedQueryError::Io {
+            path: path.clone(),
+            message,
+        };
+        let queries = match std::fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str::<Vec<SavedQuery>>(&text)
+                .map_err(|e| io_error(e.to_string()))?
+                .into_iter()
+                .map(|saved| (saved.name.clone(), saved))
+                .collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(io_error(e.to_string())),
+        };
+        Ok(Self {
+            queries: Mutex::new(queries),
+            path: Some(path),
+        })
+    }
+
+    /// Saved queries with `tag` and the words of `text` (see [`SearchParams`]),
+    /// sorted by name
+    pub fn search(&self, tag: Option<&str>, text: Option<&str>) -> Vec<SavedQuery> {
+        let queries = self.queries.lock().unwrap();
+        queries
+            .values()
+            .filter(|saved| saved.matches(tag, text))
+            .cloned()
+            .collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<SavedQuery> {
+        self.queries.lock().unwrap().get(name).cloned()
+    }
+
+    /// Remove `name` and rewrite the file; returns whether it existed
+    pub fn remove(&self, name: &str) -> Result<bool, SavedQueryError> {
+        let mut queries = self.queries.lock().unwrap();
+        let Some(removed) = queries.remove(name) else {
+            return Ok(false);
+        };
+        if let Err(e) = self.persist(&queries) {
+            queries.insert(removed.name.clone(), removed);
+            return Err(e);
+        }
+        Ok(true)
+    }
+
+    /// Store `saved` under its name and rewrite the file. A failed write leaves
+    /// the library as it was.
+    fn put(&self, saved: SavedQuery) -> Result<(), SavedQueryError> {
+        let mut queries = self.queries.lock().unwrap();
+        let previous = queries.insert(saved.name.clone(), saved.clone());
+        if let Err(e) = self.persist(&queries) {
+            match previous {
+                Some(previous) => queries.insert(saved.name, previous),
+                None => queries.remove(&saved.name),
+            };
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Write `queries` to the file, through a temporary file so a crash never leaves
+    /// it half-written
+    fn persist(&self, queries: &BTreeMap<String, SavedQuery>) -> Result<(), SavedQueryError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let all: Vec<&SavedQuery> = queries.values().collect();
+        let json = serde_json::to_vec_pretty(&all).expect("saved queries serialize");
+        let tmp = path.with_extension("json.tmp");
+        write_then_rename(&tmp, path, &json).map_err(|e| SavedQueryError::Io {
+            path: path.clone(),
+            message: e.to_string(),
+        })
+    }
+}
+
+fn write_then_rename(tmp: &Path, path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    std::fs::write(tmp, bytes)?;
+    std::fs::rename(tmp, path)
+}
+
+/// Save a new query; fails if one of the same name exists
+pub async fn create(
+    core: &ServerCore,
+    request: SavedQueryRequest,
+) -> Result<SavedQuery, SavedQueryError> {
+    if core.saved_queries().get(&request.name).is_some() {
+        return Err(SavedQueryError::Exists(request.name));
+    }
+    save(core, request, None).await
+}
+
+/// Replace saved query `name`, keeping its creation time (and author, unless the
+/// request names one)
+pub async fn update(
+    core: &ServerCore,
+    name: &str,
+    request: SavedQueryRequest,
+) -> Result<SavedQuery, SavedQueryError> {
+    if request.name != name {
+        return Err(SavedQueryError::NameMismatch {
+            url: name.to_string(),
+            body: request.name,
+        });
+    }
+    let previous = core
+        .saved_queries()
+        .get(name)
+        .ok_or_else(|| SavedQueryError::Unknown(name.to_string()))?;
+    save(core, request, Some(previous)).await
+}
+
+async fn save(
+    core: &ServerCore,
+    request: SavedQueryRequest,
+    previous: Option<SavedQuery>,
+) -> Result<SavedQuery, SavedQueryError> {
+    if !valid_name(&request.name) {
+        return Err(SavedQueryError::InvalidName(request.name));
+    }
+    request
+        .query
+        .piql_params()
+        .map_err(SavedQueryError::InvalidParams)?;
+    // Tables may be loaded later, so only check that the query parses
+    core.query_params(&request.query.query).await?;
+
+    let now = timestamp(&Utc::now());
+    let saved = SavedQuery {
+        name: request.name,
+        query: request.query,
+        description: request.description,
+        author: request
+            .author
+            .or_else(|| previous.as_ref().and_then(|p| p.author.clone())),
+        tags: request.tags,
+        created_at: previous.map_or_else(|| now.clone(), |p| p.created_at),
+        updated_at: now,
+    };
+    core.saved_queries().put(saved.clone())?;
+    info!("Saved query '{}': {}", saved.name, saved.query.query);
+    Ok(saved)
+}
+
+// ============ HTTP ============
+
+/// List or search saved queries
+#[utoipa::path(
+    get,
+    path = "/queries/saved",
+    params(SearchParams),
+    responses((status = 200, description = "Matching saved queries", body = SavedQueriesResponse))
+)]
+pub async fn list(
+    State(core): State<Arc<ServerCore>>,
+    Query(params): Query<SearchParams>,
+) -> Json<SavedQueriesResponse> {
+    Json(SavedQueriesResponse {
+        queries: core
+            .saved_queries()
+            .search(params.tag.as_deref(), params.q.as_deref()),
+    })
+}
+
+/// Save a query to the library
+#[utoipa::path(
+    post,
+    path = "/queries/saved",
+    request_body = SavedQueryRequest,
+    responses(
+        (status = 201, description = "The saved query", body = SavedQuery),
+        (status = 400, description = "Invalid or taken name, invalid params or query", body = ErrorResponse),
+        (status = 500, description = "The library file couldn't be written", body = ErrorResponse)
+    )
+)]
+pub async fn save_new(
+    State(core): State<Arc<ServerCore>>,
+    Json(request): Json<SavedQueryRequest>,
+) -> Result<(StatusCode, Json<SavedQuery>), AppError> {
+    info!("POST /queries/saved: {}", request.name);
+    Ok((StatusCode::CREATED, Json(create(&core, request).await?)))
+}
+
+/// Get a saved query
+#[utoipa::path(
+    get,
+    path = "/queries/saved/{name}",
+    params(("name" = String, Path, description = "Saved query name")),
+    responses(
+        (status = 200, description = "The saved query", body = SavedQuery),
+        (status = 400, description = "Unknown saved query", body = ErrorResponse)
+    )
+)]
+pub async fn get(
+    State(core): State<Arc<ServerCore>>,
+    UrlPath(name): UrlPath<String>,
+) -> Result<Json<SavedQuery>, AppError> {
+    let saved = core
+        .saved_queries()
+        .get(&name)
+        .ok_or(SavedQueryError::Unknown(name))?;
+    Ok(Json(saved))
+}
+
+/// Replace a saved query
+#[utoipa::path(
+    put,
+    path = "/queries/saved/{name}",
+    params(("name" = String, Path, description = "Saved query name")),
+    request_body = SavedQueryRequest,
+    responses(
+        (status = 200, description = "The updated query", body = SavedQuery),
+        (status = 400, description = "Unknown saved query, or invalid params or query", body = ErrorResponse),
+        (status = 500, description = "The library file couldn't be written", body = ErrorResponse)
+    )
+)]
+pub async fn replace(
+    State(core): State<Arc<ServerCore>>,
+    UrlPath(name): UrlPath<String>,
+    Json(request): Json<SavedQueryRequest>,
+) -> Result<Json<SavedQuery>, AppError> {
+    info!("PUT /queries/saved/{name}");
+    Ok(Json(update(&core, &name, request).await?))
+}
+
+/// Delete a saved query
+#[utoipa::path(
+    delete,
+    path = "/queries/saved/{name}",
+    params(("name" = String, Path, description = "Saved query name")),
+    responses(
+        (status = 204, description = "Saved query removed"),
+        (status = 400, description = "Unknown saved query", body = ErrorResponse),
+        (status = 500, description = "The library file couldn't be written", body = ErrorResponse)
+    )
+)]
+pub async fn delete(
+    State(core): State<Arc<ServerCore>>,
+    UrlPath(name): UrlPath<String>,
+) -> Result<StatusCode, AppError> {
+    info!("DELETE /queries/saved/{name}");
+    if !core.saved_queries().remove(&name)? {
+        return Err(SavedQueryError::Unknown(name).into());
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Run a saved query with its stored params
+///
+/// The result is returned as by `/query`: an Arrow IPC stream, or compact JSON with
+/// `Accept: application/json`.
+#[utoipa::path(
+    post,
+    path = "/queries/saved/{name}/run",
+    params(("name" = String, Path, description = "Saved query name")),
+    responses(
+        (status = 200, description = "Arrow IPC stream, or compact JSON with `Accept: application/json`",
+            content_type = "application/vnd.apache.arrow.stream"),
+        (status = 400, description = "Unknown saved query, or query error", body = ErrorResponse),
+        (status = 403, description = "Query reads a column masked for this credential", body = ErrorResponse),
+        (status = 422, description = "Query exceeded a resource limit", body = ErrorResponse)
+    )
+)]
+pub async fn run(
+    State(core): State<Arc<ServerCore>>,
+    mask: Option<Extension<ColumnMask>>,
+    UrlPath(name): UrlPath<String>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    info!("POST /queries/saved/{name}/run");
+    let saved = core
+        .saved_queries()
+        .get(&name)
+        .ok_or(SavedQueryError::Unknown(name))?;
+    let params = saved.query.piql_params().map_err(AppError::bad_request)?;
+    crate::http::respond_with_query(
+        &core,
+        mask.as_ref().map(|Extension(mask)| mask),
+        &saved.query.query,
+        &params,
+        Annotations::default(),
+        &headers,
+        core.config().batch_size,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(name: &str, tags: &[&str]) -> SavedQueryRequest {
+        serde_json::from_value(serde_json::json!({
+            "name": name,
+            "query": "trades.filter($qty > :min)",
+            "params": {"min": 10},
+            "description": "Large trades by desk",
+            "author": "ana",
+            "tags": tags,
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn saved_queries_persist_and_search() {
+        let path = std::env::temp_dir().join(format!("piql-saved-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let core = ServerCore::new().with_saved_queries(SavedQueries::open(&path).unwrap());
+
+        let saved = create(&core, request("big_trades", &["risk"]))
+            .await
+            .unwrap();
+        assert_eq!(saved.created_at, saved.updated_at);
+        create(&core, request("desk_pnl", &["pnl"])).await.unwrap();
+        assert!(matches!(
+            create(&core, request("desk_pnl", &[])).await,
+            Err(SavedQueryError::Exists(_))
+        ));
+
+        let mut changed = request("big_trades", &["risk", "daily"]);
+        changed.author = None;
+        let updated = update(&core, "big_trades", changed).await.unwrap();
+        assert_eq!(updated.author.as_deref(), Some("ana"));
+        assert_eq!(updated.created_at, saved.created_at);
+
+        // Reopening the file restores the library
+        let library = SavedQueries::open(&path).unwrap();
+        let names = |found: Vec<SavedQuery>| -> Vec<String> {
+            found.into_iter().map(|saved| saved.name).collect()
+        };
+        assert_eq!(
+            names(library.search(None, None)),
+            ["big_trades", "desk_pnl"]
+        );
+        assert_eq!(names(library.search(Some("DAILY"), None)), ["big_trades"]);
+        assert_eq!(names(library.search(None, Some("desk pnl"))), ["desk_pnl"]);
+        assert_eq!(names(library.search(None, Some("large qty"))).len(), 2);
+        assert!(library.search(Some("risk"), Some("missing")).is_empty());
+
+        assert!(library.remove("desk_pnl").unwrap());
+        assert!(!library.remove("desk_pnl").unwrap());
+        assert_eq!(
+            SavedQueries::open(&path).unwrap().search(None, None).len(),
+            1
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn invalid_saved_queries_are_rejected() {
+        let core = ServerCore::new();
+        assert!(matches!(
+            create(&core, request("../up", &[])).await,
+            Err(SavedQueryError::InvalidName(_))
+        ));
+        let mut bad = request("bad", &[]);
+        bad.query.query = "trades.filter(".to_string();
+        assert!(matches!(
+            create(&core, bad).await,
+            Err(SavedQueryError::Query(_))
+        ));
+        assert!(matches!(
+            update(&core, "other", request("bad", &[])).await,
+            Err(SavedQueryError::NameMismatch { .. })
+        ));
+        assert!(core.saved_queries().search(None, None).is_empty());
+    }
+}
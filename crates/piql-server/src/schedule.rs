@@ -0,0 +1,497 @@
+//! Scheduled queries: results written to disk on a cron schedule
+//!
+//! Each schedule runs a query whenever its cron pattern (UTC, e.g. `0 * * * *` for
+//! hourly) matches, and writes the result under [`ServerConfig::export_dir`] as
+//! `{name}/{name}-{YYYYmmddTHHMMSSZ}.{ext}` (Parquet unless another export format is
+//! given). Schedules come from a JSON file (`--schedules`) or `POST /schedules`; the
+//! last [`HISTORY_CAPACITY`] runs are kept in memory.
+//!
+//! Runs in progress count as in-flight requests for a graceful shutdown, and no new
+//! run starts once shutdown has begun.
+//!
+//! [`ServerConfig::export_dir`]: crate::ServerConfig::export_dir
+
+use std::collections::{BTreeMap, VecDeque};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use axum::Json;
+use axum::extract::{Path as UrlPath, State};
+use axum::http::StatusCode;
+use chrono::{DateTime, SecondsFormat, Utc};
+use croner::Cron;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::task::JoinHandle;
+use utoipa::ToSchema;
+
+use crate::annotate::Annotations;
+use crate::core::ServerCore;
+use crate::error::AppError;
+use crate::export::{self, ExportFormat};
+use crate::state::{ErrorResponse, QueryRequest};
+
+/// Runs kept in the history
+pub const HISTORY_CAPACITY: usize = 1000;
+
+/// A query run on a schedule
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ScheduleSpec {
+    /// Letters, digits, `_` and `-`; also the output subdirectory
+    pub name: String,
+    #[serde(flatten)]
+    pub query: QueryRequest,
+    /// Cron pattern in UTC: `minute hour day month weekday`, or `@hourly`, `@daily`, ...
+    pub cron: String,
+    /// Output format (default `parquet`)
+    #[serde(default = "default_format")]
+    pub format: ExportFormat,
+}
+
+fn default_format() -> ExportFormat {
+    ExportFormat::Parquet
+}
+
+/// One execution of a schedule
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ScheduleRun {
+    pub schedule: String,
+    /// RFC 3339, UTC
+    pub started_at: String,
+    pub duration_ms: f64,
+    /// Output file relative to the export directory (absent if the run failed)
+    pub path: Option<String>,
+    pub rows: Option<usize>,
+    pub error: Option<String>,
+}
+
+/// A registered schedule and when it runs
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ScheduleInfo {
+    #[serde(flatten)]
+    pub spec: ScheduleSpec,
+    /// Next run, RFC 3339 UTC
+    pub next_run: Option<String>,
+    pub last_run: Option<ScheduleRun>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SchedulesResponse {
+    pub schedules: Vec<ScheduleInfo>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ScheduleRunsResponse {
+    /// Oldest first
+    pub runs: Vec<ScheduleRun>,
+}
+
+#[derive(Debug, Error)]
+pub enum ScheduleError {
+    #[error("invalid schedule name '{0}' (use letters, digits, '_' and '-')")]
+    InvalidName(String),
+    #[error("invalid cron pattern '{pattern}': {message}")]
+    InvalidCron { pattern: String, message: String },
+    #[error("{0}")]
+    InvalidParams(String),
+    #[error("scheduled queries need an export directory (start the server with --export-dir)")]
+    NoExportDir,
+    #[error("Unknown schedule: {0}")]
+    Unknown(String),
+    #[error(transparent)]
+    Query(#[from] piql::PiqlError),
+}
+
+struct Registered {
+    spec: ScheduleSpec,
+    cron: Cron,
+    task: JoinHandle<()>,
+}
+
+/// Registered schedules and their run history
+#[derive(Default)]
+pub struct Scheduler {
+    schedules: Mutex<BTreeMap<String, Registered>>,
+    history: Mutex<VecDeque<ScheduleRun>>,
+}
+
+impl Scheduler {
+    /// Registered schedules by name, with their next and last runs
+    pub fn list(&self) -> Vec<ScheduleInfo> {
+        let schedules = self.schedules.lock().unwrap();
+        let history = self.history.lock().unwrap();
+        let now = Utc::now();
+        schedules
+            .values()
+            .map(|registered| ScheduleInfo {
+                spec: registered.spec.clone(),
+                next_run: registered
+                    .cron
+                    .find_next_occurrence(&now, false)
+                    .ok()
+                    .map(|t| timestamp(&t)),
+                last_run: history
+                    .iter()
+                    .rev()
+                    .find(|run| run.schedule == registered.spec.name)
+                    .cloned(),
+            })
+            .collect()
+    }
+
+    /// Stop and unregister `name`; returns whether it existed
+    pub fn remove(&self, name: &str) -> bool {
+        match self.schedules.lock().unwrap().remove(name) {
+            Some(registered) => {
+                registered.task.abort();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Recorded runs of `name`, oldest first
+    pub fn runs(&self, name: &str) -> Vec<ScheduleRun> {
+        let history = self.history.lock().unwrap();
+        history
+            .iter()
+            .filter(|run| run.schedule == name)
+            .cloned()
+            .collect()
+    }
+
+    fn spec(&self, name: &str) -> Option<ScheduleSpec> {
+        let schedules = self.schedules.lock().unwrap();
+        schedules
+            .get(name)
+            .map(|registered| registered.spec.clone())
+    }
+
+    fn record(&self, run: ScheduleRun) {
+        let mut history = self.history.lock().unwrap();
+        if history.len() == HISTORY_CAPACITY {
+            history.pop_front();
+        }
+        history.push_back(run);
+    }
+}
+
+/// Validate and register `spec`, replacing any schedule of the same name, and start
+/// running it. Must be called within a tokio runtime.
+pub async fn add(core: &ServerCore, spec: ScheduleSpec) -> Result<ScheduleInfo, ScheduleError> {
+    if !valid_name(&spec.name) {
+        return Err(ScheduleError::InvalidName(spec.name));
+    }
+    let cron = Cron::from_str(&spec.cron).map_err(|e| ScheduleError::InvalidCron {
+        pattern: spec.cron.clone(),
+        message: e.to_string(),
+    })?;
+    if core.config().export_dir.is_none() {
+        return Err(ScheduleError::NoExportDir);
+    }
+    let params = spec
+        .query
+        .piql_params()
+        .map_err(ScheduleError::InvalidParams)?;
+    core.compile_query(&spec.query.query, &params).await?;
+
+    let name = spec.name.clone();
+    let task = tokio::spawn(run_on_schedule(core.clone(), name.clone(), cron.clone()));
+    let replaced = core.scheduler().schedules.lock().unwrap().insert(
+        name.clone(),
+        Registered {
+            spec: spec.clone(),
+            cron,
+            task,
+        },
+    );
+    if let Some(replaced) = replaced {
+        replaced.task.abort();
+    }
+    info!("Scheduled '{name}' ({}): {}", spec.cron, spec.query.query);
+
+    let info = core.scheduler().list();
+    Ok(info.into_iter().find(|i| i.spec.name == name).unwrap())
+}
+
+/// Run schedule `name` once now, recording the run in its history
+pub async fn run_now(core: &ServerCore, name: &str) -> Result<ScheduleRun, ScheduleError> {
+    let spec = core
+        .scheduler()
+        .spec(name)
+        .ok_or_else(|| ScheduleError::Unknown(name.to_string()))?;
+    let _in_flight = core.shutdown().track();
+
+    let started_at = Utc::now();
+    let start = Instant::now();
+    let result = execute(core, &spec, &started_at).await;
+    let run = ScheduleRun {
+        schedule: spec.name.clone(),
+        started_at: timestamp(&started_at),
+        duration_ms: start.elapsed().as_secs_f64() * 1000.0,
+        path: result.as_ref().ok().map(|(path, _)| path.clone()),
+        rows: result.as_ref().ok().map(|(_, rows)| *rows),
+        error: result.err(),
+    };
+    match &run.error {
+        Some(error) => warn!("Scheduled query '{name}' failed: {error}"),
+        None => info!(
+            "Scheduled query '{name}' wrote {} rows to {}",
+            run.rows.unwrap_or(0),
+            run.path.as_deref().unwrap_or_default()
+        ),
+    }
+    core.scheduler().record(run.clone());
+    Ok(run)
+}
+
+/// Run the query and write its result; returns the output path and row count
+async fn execute(
+    core: &ServerCore,
+    spec: &ScheduleSpec,
+    started_at: &DateTime<Utc>,
+) -> Result<(String, usize), String> {
+    let dir = core
+        .config()
+        .export_dir
+        .clone()
+        .ok_or_else(|| ScheduleError::NoExportDir.to_string())?;
+    let params = spec.query.piql_params()?;
+    let (mut df, _) = core
+        .execute_query_with_params(&spec.query.query, &params, Annotations::default())
+        .await
+        .map_err(|e| e.to_string())?;
+    let rows = df.height();
+
+    let path = format!(
+        "{name}/{name}-{}.{}",
+        started_at.format("%Y%m%dT%H%M%SZ"),
+        spec.format.extension(),
+        name = spec.name,
+    );
+    let target = Path::new(&dir).join(&path);
+    let format = spec.format;
+    tokio::task::spawn_blocking(move || export::write_file(&mut df, format, &target))
+        .await
+        .map_err(|e| format!("write task failed: {e}"))?
+        .map_err(|e| e.to_string())?;
+    Ok((path, rows))
+}
+
+/// Sleep until each occurrence of `cron` and run the schedule, until it is removed
+/// or the server shuts down
+async fn run_on_schedule(core: ServerCore, name: String, cron: Cron) {
+    let shutdown = core.shutdown().clone();
+    loop {
+        let now = Utc::now();
+        let next = match cron.find_next_occurrence(&now, false) {
+            Ok(next) => next,
+            Err(e) => {
+                warn!("Schedule '{name}' has no next run: {e}");
+                return;
+            }
+        };
+        let wait = (next - now).to_std().unwrap_or_default();
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = shutdown.closing() => return,
+        }
+        if run_now(&core, &name).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Whether `name` is non-empty and only letters, digits, `_` and `-`
+pub(crate) fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+pub(crate) fn timestamp(time: &DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Load schedules from a JSON file holding an array of schedule specs
+pub fn load_specs(path: &Path) -> Result<Vec<ScheduleSpec>, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+    serde_json::from_str(&text).map_err(|e| format!("invalid schedules file: {e}"))
+}
+
+// ============ HTTP ============
+
+/// List scheduled queries
+#[utoipa::path(
+    get,
+    path = "/schedules",
+    responses((status = 200, description = "Schedules with their next and last runs", body = SchedulesResponse))
+)]
+pub async fn list(State(core): State<Arc<ServerCore>>) -> Json<SchedulesResponse> {
+    Json(SchedulesResponse {
+        schedules: core.scheduler().list(),
+    })
+}
+
+/// Create or replace a scheduled query
+///
+/// The query runs whenever `cron` matches (UTC) and its result is written under the
+/// server's export directory as `{name}/{name}-{timestamp}.{format}`.
+#[utoipa::path(
+    post,
+    path = "/schedules",
+    request_body = ScheduleSpec,
+    responses(
+        (status = 200, description = "The registered schedule", body = ScheduleInfo),
+        (status = 400, description = "Invalid name, cron pattern or query, or no export directory", body = ErrorResponse)
+    )
+)]
+pub async fn create(
+    State(core): State<Arc<ServerCore>>,
+    Json(spec): Json<ScheduleSpec>,
+) -> Result<Json<ScheduleInfo>, AppError> {
+    info!("POST /schedules: {} ({})", spec.name, spec.cron);
+    Ok(Json(add(&core, spec).await?))
+}
+
+/// Remove a scheduled query
+#[utoipa::path(
+    delete,
+    path = "/schedules/{name}",
+    params(("name" = String, Path, description = "Schedule name")),
+    responses(
+        (status = 204, description = "Schedule removed"),
+        (status = 400, description = "Unknown schedule", body = ErrorResponse)
+    )
+)]
+pub async fn delete(
+    State(core): State<Arc<ServerCore>>,
+    UrlPath(name): UrlPath<String>,
+) -> Result<StatusCode, AppError> {
+    info!("DELETE /schedules/{name}");
+    if !core.scheduler().remove(&name) {
+        return Err(ScheduleError::Unknown(name).into());
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Run a scheduled query now
+#[utoipa::path(
+    post,
+    path = "/schedules/{name}/run",
+    params(("name" = String, Path, description = "Schedule name")),
+    responses(
+        (status = 200, description = "The run, with its output path or error", body = ScheduleRun),
+        (status = 400, description = "Unknown schedule", body = ErrorResponse)
+    )
+)]
+pub async fn run(
+    State(core): State<Arc<ServerCore>>,
+    UrlPath(name): UrlPath<String>,
+) -> Result<Json<ScheduleRun>, AppError> {
+    info!("POST /schedules/{name}/run");
+    Ok(Json(run_now(&core, &name).await?))
+}
+
+/// Run history of a scheduled query
+#[utoipa::path(
+    get,
+    path = "/schedules/{name}/runs",
+    params(("name" = String, Path, description = "Schedule name")),
+    responses((status = 200, description = "Recorded runs, oldest first", body = ScheduleRunsResponse))
+)]
+pub async fn runs(
+    State(core): State<Arc<ServerCore>>,
+    UrlPath(name): UrlPath<String>,
+) -> Json<ScheduleRunsResponse> {
+    Json(ScheduleRunsResponse {
+        runs: core.scheduler().runs(&name),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ServerConfig;
+    use polars::prelude::*;
+
+    fn spec(name: &str, cron: &str) -> ScheduleSpec {
+        serde_json::from_value(serde_json::json!({
+            "name": name,
+            "query": "t.filter($a > :min)",
+            "params": {"min": 1},
+            "cron": cron,
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn schedules_write_timestamped_outputs_and_record_runs() {
+        let dir = std::env::temp_dir().join(format!("piql-schedule-{}", std::process::id()));
+        let core = ServerCore::new().with_config(ServerConfig {
+            export_dir: Some(dir.clone()),
+            ..Default::default()
+        });
+        core.insert_df("t", df! { "a" => &[1, 2, 3] }.unwrap())
+            .await;
+
+        let info = add(&core, spec("hourly", "0 * * * *")).await.unwrap();
+        assert_eq!(info.spec.format, ExportFormat::Parquet);
+        assert!(info.next_run.unwrap().ends_with(":00:00Z"));
+
+        let run = run_now(&core, "hourly").await.unwrap();
+        assert_eq!(run.rows, Some(2), "{run:?}");
+        let path = run.path.unwrap();
+        assert!(path.starts_with("hourly/hourly-") && path.ends_with("Z.parquet"));
+        let written = ParquetReader::new(std::fs::File::open(dir.join(&path)).unwrap())
+            .finish()
+            .unwrap();
+        assert_eq!(written.height(), 2);
+
+        let listed = core.scheduler().list();
+        assert_eq!(listed.len(), 1);
+        assert!(listed[0].last_run.is_some());
+        assert_eq!(core.scheduler().runs("hourly").len(), 1);
+
+        assert!(core.scheduler().remove("hourly"));
+        assert!(matches!(
+            run_now(&core, "hourly").await,
+            Err(ScheduleError::Unknown(_))
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn invalid_schedules_are_rejected() {
+        let core = ServerCore::new();
+        assert!(matches!(
+            add(&core, spec("hourly", "0 * * * *")).await,
+            Err(ScheduleError::NoExportDir)
+        ));
+
+        let core = core.with_config(ServerConfig {
+            export_dir: Some(std::env::temp_dir()),
+            ..Default::default()
+        });
+        assert!(matches!(
+            add(&core, spec("../up", "0 * * * *")).await,
+            Err(ScheduleError::InvalidName(_))
+        ));
+        assert!(matches!(
+            add(&core, spec("hourly", "61 * * * *")).await,
+            Err(ScheduleError::InvalidCron { .. })
+        ));
+        let mut bad_query = spec("hourly", "@daily");
+        bad_query.query.query = "t.filter(".to_string();
+        assert!(matches!(
+            add(&core, bad_query).await,
+            Err(ScheduleError::Query(_))
+        ));
+        assert!(core.scheduler().list().is_empty());
+    }
+}
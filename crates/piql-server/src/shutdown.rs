@@ -0,0 +1,293 @@
+//! Graceful shutdown
+//!
+//! [`serve_with_graceful_shutdown`] serves the router until SIGINT or SIGTERM, then:
+//! 1. answers new requests with 503 (see [`reject_when_closing`])
+//! 2. sends every SSE subscriber a final `server-closing` event and ends its stream
+//! 3. lets in-flight requests finish, up to [`ServerConfig::drain_timeout`]
+//! 4. closes the listener and returns
+//!
+//! Connections still open a few seconds past the drain timeout are dropped. Embedders
+//! can start the same sequence with [`Shutdown::begin`].
+//!
+//! [`ServerConfig::drain_timeout`]: crate::ServerConfig::drain_timeout
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use axum::Json;
+use axum::extract::{Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use tokio::net::TcpListener;
+use tokio::sync::{Notify, watch};
+
+use crate::core::ServerCore;
+use crate::state::ErrorResponse;
+
+/// SSE event sent to subscribers before their stream is closed
+pub const CLOSING_EVENT: &str = "server-closing";
+
+/// Extra time past the drain timeout before open connections are dropped
+const CLOSE_GRACE: Duration = Duration::from_secs(5);
+
+/// Shutdown state shared by the router, SSE streams and the serve loop
+#[derive(Debug)]
+pub struct Shutdown {
+    closing: watch::Sender<bool>,
+    in_flight: AtomicUsize,
+    idle: Notify,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self {
+            closing: watch::Sender::new(false),
+            in_flight: AtomicUsize::new(0),
+            idle: Notify::new(),
+        }
+    }
+
+    /// Start shutting down; later calls have no effect
+    pub fn begin(&self) {
+        self.closing.send_replace(true);
+    }
+
+    pub fn is_closing(&self) -> bool {
+        *self.closing.borrow()
+    }
+
+    /// Resolves once shutdown has begun
+    pub async fn closing(&self) {
+        let mut rx = self.closing.subscribe();
+        // The sender lives as long as `self`, so this only returns once closing
+        let _ = rx.wait_for(|closing| *closing).await;
+    }
+
+    /// Count a request as in flight until the guard is dropped
+    pub fn track(self: &Arc<Self>) -> InFlight {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlight(self.clone())
+    }
+
+    /// Requests currently in flight
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Wait until no request is in flight, giving up after `timeout`.
+    /// Returns whether every request finished.
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        let drained = async {
+            loop {
+                let idle = self.idle.notified();
+                tokio::pin!(idle);
+                // Register before checking, so a guard dropped in between still wakes us
+                idle.as_mut().enable();
+                if self.in_flight() == 0 {
+                    return;
+                }
+                idle.await;
+            }
+        };
+        tokio::time::timeout(timeout, drained).await.is_ok()
+    }
+}
+
+/// Guard returned by [`Shutdown::track`]
+#[derive(Debug)]
+pub struct InFlight(Arc<Shutdown>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if self.0.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+/// Middleware answering 503 once shutdown has begun, and tracking in-flight requests
+pub async fn reject_when_closing(
+    State(core): State<Arc<ServerCore>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let shutdown = core.shutdown();
+    if shutdown.is_closing() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ErrorResponse {
+                error: "server is shutting down".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    let _in_flight = shutdown.track();
+    next.run(request).await
+}
+
+/// Resolves on SIGINT (Ctrl-C) or, on Unix, SIGTERM
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            log::warn!("Can't listen for Ctrl-C: {e}");
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                log::warn!("Can't listen for SIGTERM: {e}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+/// Serve the router with API docs on `listener`, shutting down gracefully on
+/// SIGINT/SIGTERM or [`Shutdown::begin`]
+pub async fn serve_with_graceful_shutdown(
+    core: Arc<ServerCore>,
+    listener: TcpListener,
+) -> std::io::Result<()> {
+    let shutdown = core.shutdown().clone();
+    let drain_timeout = core.config().drain_timeout;
+
+    let signal = {
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            shutdown_signal().await;
+            log::info!("Shutdown signal received");
+            shutdown.begin();
+        })
+    };
+
+    let router = crate::build_router_with_docs(core);
+    let drained = {
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown.closing().await;
+            log::info!(
+                "Draining {} in-flight requests (up to {drain_timeout:?})",
+                shutdown.in_flight()
+            );
+            if !shutdown.drain(drain_timeout).await {
+                log::warn!(
+                    "{} requests still running after {drain_timeout:?}, closing anyway",
+                    shutdown.in_flight()
+                );
+            }
+        }
+    };
+    let server = axum::serve(
+        listener,
+        router.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(drained);
+    // Graceful shutdown waits for every connection to close; don't let a stuck one
+    // keep the process alive
+    let deadline = async {
+        shutdown.closing().await;
+        tokio::time::sleep(drain_timeout + CLOSE_GRACE).await;
+    };
+
+    let result = tokio::select! {
+        result = server => result,
+        _ = deadline => {
+            log::warn!("Connections still open after the drain timeout, dropping them");
+            Ok(())
+        }
+    };
+    signal.abort();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::df;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    /// Send a raw HTTP/1.1 GET and return the connection
+    async fn get(addr: SocketAddr, path: &str) -> TcpStream {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!("GET {path} HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+        stream
+    }
+
+    async fn read_until(stream: &mut TcpStream, needle: &str) -> String {
+        let mut text = String::new();
+        let mut buf = [0u8; 4096];
+        while !text.contains(needle) {
+            let n = stream.read(&mut buf).await.unwrap();
+            assert!(n > 0, "connection closed before {needle:?}: {text}");
+            text.push_str(&String::from_utf8_lossy(&buf[..n]));
+        }
+        text
+    }
+
+    #[tokio::test]
+    async fn drain_waits_for_in_flight_requests() {
+        let shutdown = Arc::new(Shutdown::new());
+        assert!(shutdown.drain(Duration::ZERO).await);
+
+        let guard = shutdown.track();
+        assert!(!shutdown.drain(Duration::from_millis(10)).await);
+        let drained = tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move { shutdown.drain(Duration::from_secs(5)).await }
+        });
+        drop(guard);
+        assert!(drained.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn shutdown_closes_subscriptions_and_rejects_new_requests() {
+        let core = Arc::new(ServerCore::new());
+        core.insert_df("t", df! { "a" => &[1, 2] }.unwrap()).await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve_with_graceful_shutdown(core.clone(), listener));
+
+        let mut sse = get(addr, "/subscribe?query=t").await;
+        read_until(&mut sse, "event: result").await;
+
+        // A request still running keeps the server draining
+        let in_flight = core.shutdown().track();
+        core.shutdown().begin();
+        read_until(&mut sse, &format!("event: {CLOSING_EVENT}")).await;
+
+        let mut rejected = get(addr, "/dataframes").await;
+        assert!(read_until(&mut rejected, "\r\n").await.contains("503"));
+        assert!(!server.is_finished());
+
+        drop(in_flight);
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("server did not shut down")
+            .unwrap()
+            .unwrap();
+    }
+}
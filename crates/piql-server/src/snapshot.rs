@@ -0,0 +1,302 @@
+//! Persist and restore server state across restarts
+//!
+//! A snapshot directory holds:
+//! - `manifest.json` → registered tables (with time-series metadata), materialized
+//!   view queries, and SSE subscriptions clients can resume
+//! - `tables/<save>-<n>.parquet` → data of every table that is not a materialized
+//!   view or the `_queries` log
+//!
+//! Files are written with [`piql::write_snapshot`], so a save never overwrites the
+//! files of the snapshot it replaces, and removes them once the new manifest is in
+//! place.
+//!
+//! Materialized views are not dumped; they are re-evaluated from the restored tables.
+//! Resumable SSE subscriptions are saved with the events kept for replay, so a
+//! client reconnecting with `Last-Event-ID` after a restart continues where it was.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use piql::{TickDtype, TimeSeriesConfig};
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::sse::{SubscriptionEntry, Subscriptions};
+use crate::state::SharedState;
+use crate::table_stats;
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+const MANIFEST_VERSION: u32 = 1;
+
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("snapshot I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid snapshot manifest: {0}")]
+    Manifest(#[from] serde_json::Error),
+    #[error("unsupported snapshot version {0}")]
+    Version(u32),
+    #[error(transparent)]
+    Polars(#[from] PolarsError),
+    #[error("blocking task failed: {0}")]
+    Join(#[from] tokio::task::JoinError),
+    #[error(transparent)]
+    Write(#[from] piql::SnapshotError),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub tables: Vec<TableEntry>,
+    pub materializations: Vec<MaterializationEntry>,
+    #[serde(default)]
+    pub subscriptions: Vec<SubscriptionEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TableEntry {
+    pub name: String,
+    /// Parquet file, relative to the snapshot directory
+    pub file: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_series: Option<TimeSeriesEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TimeSeriesEntry {
+    pub tick_column: String,
+    pub partition_key: String,
+    /// `int` or `datetime`
+    #[serde(default)]
+    pub tick_dtype: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MaterializationEntry {
+    pub name: String,
+    pub query: String,
+}
+
+impl From<&TimeSeriesConfig> for TimeSeriesEntry {
+    fn from(config: &TimeSeriesConfig) -> Self {
+        Self {
+            tick_column: config.tick_column.clone(),
+            partition_key: config.partition_key.clone(),
+            tick_dtype: match config.tick_dtype {
+                TickDtype::Int => "int",
+                TickDtype::Datetime => "datetime",
+            }
+            .to_string(),
+        }
+    }
+}
+
+impl From<TimeSeriesEntry> for TimeSeriesConfig {
+    fn from(entry: TimeSeriesEntry) -> Self {
+        let tick_dtype = match entry.tick_dtype.as_str() {
+            "datetime" => TickDtype::Datetime,
+            _ => TickDtype::Int,
+        };
+        Self::new(entry.tick_column, entry.partition_key).with_tick_dtype(tick_dtype)
+    }
+}
+
+/// Write all tables, materialized view definitions and resumable subscriptions to
+/// `dir`.
+///
+/// The manifest is replaced last (via rename) and the previous save's files are
+/// removed only after, so an interrupted save leaves the previous snapshot intact.
+pub(crate) async fn save(
+    state: &SharedState,
+    subscriptions: &Subscriptions,
+    dir: &Path,
+) -> Result<(), SnapshotError> {
+    let subscriptions = subscriptions.save().await;
+    let views = state.materializations().await;
+    let ctx = state.ctx.read().await.clone();
+
+    let mut materializations: Vec<MaterializationEntry> = views
+        .iter()
+        .map(|(name, view)| MaterializationEntry {
+            name: name.clone(),
+            query: view.query.clone(),
+        })
+        .collect();
+    materializations.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let dir = dir.to_path_buf();
+    // Collecting scan-backed tables reads files, so it happens off the runtime too
+    tokio::task::spawn_blocking(move || -> Result<(), SnapshotError> {
+        let mut names: Vec<&String> = ctx
+            .dataframes
+            .keys()
+            .filter(|name| !views.contains_key(*name) && table_stats::builtin(name).is_none())
+            .collect();
+        names.sort();
+
+        let mut dumps = piql::SnapshotDumps::new();
+        let mut tables = Vec::with_capacity(names.len());
+        for name in names {
+            let entry = &ctx.dataframes[name];
+            // Scan-backed tables are saved with their current rows
+            tables.push(TableEntry {
+                name: name.clone(),
+                file: dumps.add(entry.collect()?),
+                time_series: entry.time_series.as_ref().map(TimeSeriesEntry::from),
+            });
+        }
+        let manifest = Manifest {
+            version: MANIFEST_VERSION,
+            tables,
+            materializations,
+            subscriptions,
+        };
+        piql::write_snapshot(&dir, &manifest, dumps)?;
+        Ok(())
+    })
+    .await??;
+    Ok(())
+}
+
+/// Restore tables, materialized views and subscriptions saved by [`save`], keeping
+/// `replay_capacity` events of each subscription.
+///
+/// Tables replace any existing table with the same name. Views that fail to
+/// re-evaluate are logged and skipped. Returns the number of tables restored.
+pub(crate) async fn load(
+    state: &SharedState,
+    subscriptions: &Subscriptions,
+    replay_capacity: usize,
+    dir: &Path,
+) -> Result<usize, SnapshotError> {
+    let manifest: Manifest = serde_json::from_slice(&std::fs::read(dir.join(MANIFEST_FILE))?)?;
+    if manifest.version != MANIFEST_VERSION {
+        return Err(SnapshotError::Version(manifest.version));
+    }
+
+    let n_tables = manifest.tables.len();
+    for table in manifest.tables {
+        let path = dir.join(&table.file);
+        let df = tokio::task::spawn_blocking(move || {
+            ParquetReader::new(std::fs::File::open(path)?).finish()
+        })
+        .await??;
+        state.insert_df(table.name.clone(), df).await;
+        if let Some(time_series) = table.time_series
+            && let Err(e) = state
+                .set_time_series_config(&table.name, time_series.into())
+                .await
+        {
+            log::warn!(
+                "Failed to restore time-series config for {}: {e}",
+                table.name
+            );
+        }
+    }
+
+    // Views may read other views: keep retrying until no more can be restored
+    let mut pending = manifest.materializations;
+    loop {
+        let available: HashSet<String> = state.list_dataframes().await.into_iter().collect();
+        let (ready, blocked): (Vec<_>, Vec<_>) = pending.into_iter().partition(|view| {
+            piql::compile(&view.query, &piql::EvalContext::new()).is_ok_and(|compiled| {
+                compiled
+                    .referenced_tables()
+                    .iter()
+                    .all(|d| available.contains(d))
+            })
+        });
+        if ready.is_empty() {
+            for view in blocked {
+                log::warn!(
+                    "Skipping materialized view {}: missing upstream tables",
+                    view.name
+                );
+            }
+            break;
+        }
+        for view in ready {
+            if let Err(e) = state.materialize(&view.name, &view.query).await {
+                log::warn!("Failed to restore materialized view {}: {e}", view.name);
+            }
+        }
+        pending = blocked;
+    }
+
+    subscriptions.restore(manifest.subscriptions, replay_capacity);
+    Ok(n_tables)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::ServerCore;
+
+    #[tokio::test]
+    async fn save_and_load_round_trip() {
+        let dir = std::env::temp_dir().join(format!("piql-snapshot-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let core = ServerCore::new();
+        core.insert_df(
+            "t",
+            df! { "tick" => &[1, 2, 2], "id" => &[1, 1, 2] }.unwrap(),
+        )
+        .await;
+        core.set_time_series_config("t", TimeSeriesConfig::new("tick", "id"))
+            .await
+            .unwrap();
+        core.materialize("latest", "t.at(2)").await.unwrap();
+        core.materialize("latest_count", "latest.height()")
+            .await
+            .unwrap();
+        core.save_state(&dir).await.unwrap();
+
+        // Views are recomputed rather than dumped, and a second save replaces the
+        // first one's files
+        assert_eq!(std::fs::read_dir(dir.join("tables")).unwrap().count(), 1);
+        core.save_state(&dir).await.unwrap();
+        assert_eq!(std::fs::read_dir(dir.join("tables")).unwrap().count(), 1);
+
+        let restored = ServerCore::new();
+        assert_eq!(restored.load_state(&dir).await.unwrap(), 1);
+        assert_eq!(
+            restored.list_dataframes().await,
+            vec!["_queries", "_tables", "latest", "latest_count", "t"]
+        );
+        assert_eq!(restored.execute_query("t.at(1)").await.unwrap().height(), 1);
+        assert_eq!(restored.materializations().await.len(), 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn overlapping_saves_leave_one_loadable_snapshot() {
+        let dir = std::env::temp_dir().join(format!("piql-snapshot-race-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        // Left behind by a save that died before renaming its manifest
+        std::fs::write(dir.join("manifest.json.7.tmp"), "{").unwrap();
+
+        let core = ServerCore::new();
+        core.insert_df("t", df! { "id" => &[1, 2, 3] }.unwrap())
+            .await;
+        let (a, b, c) = tokio::join!(
+            core.save_state(&dir),
+            core.save_state(&dir),
+            core.save_state(&dir)
+        );
+        a.unwrap();
+        b.unwrap();
+        c.unwrap();
+
+        assert_eq!(std::fs::read_dir(dir.join("tables")).unwrap().count(), 1);
+        assert!(!dir.join("manifest.json.7.tmp").exists());
+        let restored = ServerCore::new();
+        assert_eq!(restored.load_state(&dir).await.unwrap(), 1);
+        assert_eq!(restored.execute_query("t").await.unwrap().height(), 3);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,80 +1,925 @@
 //! SSE subscription handler
+//!
+//! Every event carries an id `{subscription}-{seq}`. A subscription keeps its last
+//! few events ([`ServerConfig::sse_replay_events`](crate::ServerConfig)), so a client
+//! reconnecting with `Last-Event-ID` gets the events it missed and continues where
+//! it left off, with `on_change`/`diff` state intact, instead of starting over.
+//! Resumable subscriptions are saved with server snapshots, so they survive a
+//! restart (a `diff` subscription's next event after one is a full diff).
 
+use std::collections::{HashMap, VecDeque};
 use std::convert::Infallible;
+use std::hash::{DefaultHasher, Hash, Hasher};
 use std::sync::Arc;
-use std::time::Duration;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
 
-use axum::extract::{Query, State};
+use axum::extract::{Extension, Query, RawQuery, State};
+use axum::http::HeaderMap;
 use axum::response::sse::{Event, KeepAlive, Sse};
+use futures::future;
 use futures::stream::{self, Stream, StreamExt};
 use log::{debug, info, warn};
-use serde::Deserialize;
-use tokio_stream::wrappers::BroadcastStream;
+use piql::EmitMode;
+use polars::prelude::DataFrame;
+use serde::{Deserialize, Serialize};
 use utoipa::IntoParams;
 
+use crate::annotate::Annotations;
 use crate::core::ServerCore;
+use crate::error::AppError;
 use crate::ipc::dataframe_to_base64_ipc;
+use crate::json::dataframe_to_json_rows;
+use crate::mask::ColumnMask;
+use crate::shutdown::CLOSING_EVENT;
+use crate::state::{ErrorResponse, UpdateNotice};
+use crate::updates::{LagPolicy, RecvError};
 
 #[derive(Deserialize, IntoParams)]
 pub struct SubscribeParams {
     /// PiQL query to subscribe to
     pub query: String,
+    /// Provenance columns to append: comma-separated `tick`, `run`, `generated_at`, `query_hash`, or `all`
+    pub annotate: Option<String>,
+    /// `always` (default), `on_change` to skip results identical to the last one sent,
+    /// or `diff` to send only added/changed/removed rows keyed by the partition key.
+    /// Per-evaluation annotations such as `generated_at` defeat change detection.
+    #[param(value_type = Option<String>)]
+    pub emit: Option<EmitMode>,
+    /// `ipc` (default) for base64 Arrow IPC, or `json` for
+    /// `{"total": n, "offset": o, "rows": [...]}` with rows as JSON objects
+    #[param(value_type = Option<String>)]
+    pub format: Option<EventFormat>,
+    /// Comma-separated columns to send (default: all)
+    pub columns: Option<String>,
+    /// First row sent in `format=json` events (default: 0)
+    pub offset: Option<usize>,
+    /// Rows sent in `format=json` events (default: all); `total` still counts every row
+    pub limit: Option<usize>,
+    /// With `emit=always`, answer rows appended to a table the query reads with an
+    /// `append` event: the query evaluated over only the new rows. Suits row-wise
+    /// queries (filters, projections), not aggregations.
+    pub appends: Option<bool>,
+    /// Appends answered with `append` events before a full `result` is sent again
+    /// (default: 50)
+    pub resync: Option<u32>,
+    /// When updates arrive faster than results are sent: `coalesce` to re-evaluate
+    /// once for all of them, `drop_oldest`, or `disconnect` to end the stream with a
+    /// `lagged` event (default: the server's `--subscriber-lag`)
+    #[param(value_type = Option<String>)]
+    pub lag: Option<LagPolicy>,
+}
+
+/// Final event of a stream cut off for falling behind (`lag=disconnect`)
+pub const LAGGED_EVENT: &str = "lagged";
+
+/// Default `append` events between full results
+pub const DEFAULT_RESYNC: u32 = 50;
+
+/// Subscriptions kept for resuming; the least recently active is dropped first
+const MAX_RESUMABLE: usize = 1024;
+
+/// An event kept for replay
+#[derive(Debug, Clone)]
+struct SentEvent {
+    seq: u64,
+    event: &'static str,
+    data: String,
+}
+
+impl SentEvent {
+    fn to_event(&self, subscription: u64) -> Event {
+        Event::default()
+            .id(format!("{subscription}-{}", self.seq))
+            .event(self.event)
+            .data(&self.data)
+    }
+}
+
+/// A resumable subscription as saved in a server snapshot
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SubscriptionEntry {
+    pub id: u64,
+    /// Query string of the subscribing request
+    pub params: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mask: Option<ColumnMask>,
+    /// Sequence number of the last event sent
+    pub seq: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_hash: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub appended: Option<u32>,
+    /// Events kept for replay, oldest first
+    #[serde(default)]
+    pub events: Vec<SavedEvent>,
+}
+
+/// An event kept for replay, as saved in a server snapshot
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedEvent {
+    pub seq: u64,
+    pub event: String,
+    pub data: String,
+}
+
+/// Name of an event a subscription sends, as kept for replay
+fn event_name(event: &str) -> Option<&'static str> {
+    ["result", "append", "diff", "error"]
+        .into_iter()
+        .find(|name| *name == event)
+}
+
+/// Subscription and sequence number of an event id
+fn parse_event_id(id: &str) -> Option<(u64, u64)> {
+    let (subscription, seq) = id.split_once('-')?;
+    Some((subscription.parse().ok()?, seq.parse().ok()?))
+}
+
+/// Subscriptions clients can resume with `Last-Event-ID`, by id
+#[derive(Default)]
+pub(crate) struct Subscriptions {
+    next_id: AtomicU64,
+    entries: std::sync::Mutex<HashMap<u64, Resumable>>,
+}
+
+struct Resumable {
+    /// Query string of the subscribing request, which a resuming one must repeat
+    params: String,
+    emitter: Arc<tokio::sync::Mutex<Emitter>>,
+    last_active: Instant,
+}
+
+impl Subscriptions {
+    fn next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Keep subscription `id` for resuming
+    fn keep(&self, id: u64, params: String, emitter: Arc<tokio::sync::Mutex<Emitter>>) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_RESUMABLE
+            && let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_active)
+                .map(|(id, _)| *id)
+        {
+            entries.remove(&oldest);
+        }
+        entries.insert(
+            id,
+            Resumable {
+                params,
+                emitter,
+                last_active: Instant::now(),
+            },
+        );
+    }
+
+    /// The resumable subscriptions, for a snapshot
+    pub(crate) async fn save(&self) -> Vec<SubscriptionEntry> {
+        let kept: Vec<_> = {
+            let entries = self.entries.lock().unwrap();
+            entries
+                .iter()
+                .map(|(id, entry)| (*id, entry.params.clone(), entry.emitter.clone()))
+                .collect()
+        };
+        let mut saved = Vec::with_capacity(kept.len());
+        for (id, params, emitter) in kept {
+            let emitter = emitter.lock().await;
+            saved.push(SubscriptionEntry {
+                id,
+                params,
+                mask: emitter.mask.clone(),
+                seq: emitter.seq,
+                last_hash: emitter.last_hash,
+                appended: emitter.appended,
+                events: emitter
+                    .sent
+                    .iter()
+                    .map(|sent| SavedEvent {
+                        seq: sent.seq,
+                        event: sent.event.to_string(),
+                        data: sent.data.clone(),
+                    })
+                    .collect(),
+            });
+        }
+        saved.sort_by_key(|entry| entry.id);
+        saved
+    }
+
+    /// Make subscriptions saved by [`Self::save`] resumable again, keeping the last
+    /// `replay_capacity` events of each
+    pub(crate) fn restore(&self, saved: Vec<SubscriptionEntry>, replay_capacity: usize) {
+        for entry in saved {
+            let uri = format!("/subscribe?{}", entry.params);
+            let emitter = uri
+                .parse()
+                .map_err(|e: axum::http::uri::InvalidUri| e.to_string())
+                .and_then(|uri| {
+                    Query::<SubscribeParams>::try_from_uri(&uri).map_err(|e| e.body_text())
+                })
+                .and_then(|Query(params)| Emitter::for_params(&params));
+            let mut emitter = match emitter {
+                Ok(emitter) => emitter,
+                Err(e) => {
+                    warn!("Not restoring SSE subscription {}: {e}", entry.id);
+                    continue;
+                }
+            };
+            emitter.mask = entry.mask;
+            emitter.seq = entry.seq;
+            emitter.last_hash = entry.last_hash;
+            emitter.appended = entry.appended;
+            emitter.replay_capacity = replay_capacity;
+            let skip = entry.events.len().saturating_sub(replay_capacity);
+            emitter.sent = entry
+                .events
+                .into_iter()
+                .skip(skip)
+                .filter_map(|saved| {
+                    Some(SentEvent {
+                        seq: saved.seq,
+                        event: event_name(&saved.event)?,
+                        data: saved.data,
+                    })
+                })
+                .collect();
+            self.next_id.fetch_max(entry.id + 1, Ordering::Relaxed);
+            self.keep(
+                entry.id,
+                entry.params,
+                Arc::new(tokio::sync::Mutex::new(emitter)),
+            );
+        }
+    }
+
+    fn touch(&self, id: u64) {
+        if let Some(entry) = self.entries.lock().unwrap().get_mut(&id) {
+            entry.last_active = Instant::now();
+        }
+    }
+
+    /// The emitter of subscription `id` and the events it sent after `after`, if
+    /// the request matches it and every missed event is still kept
+    async fn resume(
+        &self,
+        id: u64,
+        after: u64,
+        params: &str,
+        mask: Option<&ColumnMask>,
+    ) -> Option<(Arc<tokio::sync::Mutex<Emitter>>, Vec<SentEvent>)> {
+        let emitter = {
+            let entries = self.entries.lock().unwrap();
+            let entry = entries.get(&id).filter(|entry| entry.params == params)?;
+            entry.emitter.clone()
+        };
+        let missed = {
+            let emitter = emitter.lock().await;
+            // The replayed events were masked for the credential that subscribed
+            if emitter.mask.as_ref() != mask {
+                return None;
+            }
+            emitter.sent_after(after)?
+        };
+        self.touch(id);
+        Some((emitter, missed))
+    }
+}
+
+/// Encoding of SSE event data
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventFormat {
+    #[default]
+    Ipc,
+    Json,
+}
+
+/// What part of each result a subscription sends, and how
+#[derive(Debug, Clone, Default)]
+struct EventView {
+    format: EventFormat,
+    columns: Option<Vec<String>>,
+    offset: usize,
+    limit: Option<usize>,
+}
+
+impl EventView {
+    fn from_params(params: &SubscribeParams) -> Self {
+        Self {
+            format: params.format.unwrap_or_default(),
+            columns: params.columns.as_deref().map(|columns| {
+                columns
+                    .split(',')
+                    .map(|c| c.trim().to_string())
+                    .filter(|c| !c.is_empty())
+                    .collect()
+            }),
+            offset: params.offset.unwrap_or(0),
+            limit: params.limit,
+        }
+    }
+
+    /// The selected columns of `df`
+    fn select(&self, df: DataFrame) -> Result<DataFrame, String> {
+        match &self.columns {
+            Some(columns) => df
+                .select(columns.iter().cloned())
+                .map_err(|e| e.to_string()),
+            None => Ok(df),
+        }
+    }
+
+    async fn encode(&self, df: DataFrame) -> Result<String, String> {
+        match self.format {
+            EventFormat::Ipc => dataframe_to_base64_ipc(df).await.map_err(|e| e.to_string()),
+            EventFormat::Json => {
+                let total = df.height();
+                // Offsets past `i64::MAX` are past every row; a wrapped negative offset
+                // would slice from the end instead
+                let offset = i64::try_from(self.offset).unwrap_or(i64::MAX);
+                let page = df.slice(offset, self.limit.unwrap_or(total));
+                Ok(serde_json::json!({
+                    "total": total,
+                    "offset": self.offset,
+                    "rows": dataframe_to_json_rows(&page),
+                })
+                .to_string())
+            }
+        }
+    }
 }
 
 /// Subscribe to query results via SSE
 ///
-/// Returns a stream of events. Each event contains base64-encoded Arrow IPC data.
+/// Returns a stream of events. Each event contains base64-encoded Arrow IPC data, or
+/// with `format=json` one page of rows (`offset`, `limit`) plus the total row count.
+/// `columns` restricts the columns sent in either format.
 /// Events are emitted:
 /// - Immediately with initial results
-/// - Whenever any DataFrame is updated
+/// - Whenever any DataFrame is updated (with `emit=on_change`/`diff`, only if the
+///   result changed)
+///
+/// When the server shuts down, a final `server-closing` event is sent and the
+/// stream ends. If results fall behind updates, `lag` decides whether the missed
+/// updates are coalesced into one re-evaluation, the oldest dropped, or the stream
+/// ended with a `lagged` event. Idle streams get a heartbeat comment every `sse_heartbeat`.
+///
+/// Events have ids `{subscription}-{seq}`. Reconnecting with the same query string
+/// and a `Last-Event-ID` header replays the events sent after it, then resumes the
+/// subscription; if they are no longer kept, a new subscription starts.
+///
+/// With `emit=diff`, events are named `diff` and carry a `_change` column
+/// (`added`, `changed`, `removed`, or `reset` when the columns changed); the first
+/// one holds every row as `added`.
+///
+/// With `appends=true`, rows appended to a table the query reads (see
+/// [`ServerCore::append_tick`]) produce `append` events holding only the query's
+/// result over the new rows, with a full `result` every `resync` appends and after
+/// any other update. Appends to tables the query doesn't read send nothing.
+///
+/// Column masks of the caller's credential apply as for `/query`.
 #[utoipa::path(
     get,
     path = "/subscribe",
     params(SubscribeParams),
     responses(
         (status = 200, description = "SSE stream of query results"),
-        (status = 400, description = "Error")
+        (status = 400, description = "Invalid annotation list, or `appends` without `emit=always`", body = ErrorResponse),
+        (status = 403, description = "Query reads a column masked for this credential", body = ErrorResponse)
     )
 )]
 pub async fn subscribe(
     State(core): State<Arc<ServerCore>>,
+    mask: Option<Extension<ColumnMask>>,
+    headers: HeaderMap,
+    RawQuery(raw_params): RawQuery,
     Query(params): Query<SubscribeParams>,
-) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, AppError> {
+    let configured = Emitter::for_params(&params).map_err(AppError::bad_request)?;
     let query = params.query;
     info!("GET /subscribe: {}", query);
-    let update_rx = core.subscribe_updates();
+    let annotations = match params.annotate.as_deref() {
+        Some(spec) => Annotations::parse(spec).map_err(AppError::bad_request)?,
+        None => Annotations::default(),
+    };
+    let mask = mask.map(|Extension(mask)| mask);
+    if let Some(mask) = &mask {
+        let compiled = core.compile_query(&query, &piql::Params::new()).await?;
+        mask.check(&compiled).map_err(AppError::forbidden)?;
+    }
+    let raw_params = raw_params.unwrap_or_default();
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_event_id);
+    let resumed = match last_event_id {
+        Some((id, after)) => core
+            .subscriptions()
+            .resume(id, after, &raw_params, mask.as_ref())
+            .await
+            .map(|(emitter, missed)| (id, emitter, missed)),
+        None => None,
+    };
+    let update_rx = core.subscribe_updates_with(
+        params.lag.unwrap_or(core.config().subscriber_lag),
+        core.config().subscriber_queue,
+    );
+    debug!("SSE update subscriber {}", update_rx.id());
+    let shutdown = core.shutdown().clone();
+    let subscriber = core.metrics().track_subscriber();
 
-    // Create a stream that emits on updates
-    let update_stream = BroadcastStream::new(update_rx).filter_map(|_| async { Some(()) });
+    // Create a stream that emits on updates. A receiver that lagged missed some, so
+    // it resyncs as after any other change; one cut off for lagging yields `None`
+    // and ends.
+    let update_stream = stream::unfold(Some(update_rx), |rx| async move {
+        let mut rx = rx?;
+        match rx.recv().await {
+            Ok(notice) => Some((Some(notice), Some(rx))),
+            Err(RecvError::Lagged(_)) => Some((Some(UpdateNotice::Changed), Some(rx))),
+            Err(RecvError::Disconnected) => Some((None, None)),
+            Err(RecvError::Closed) => None,
+        }
+    });
 
     // Prepend an immediate trigger to emit initial results
-    let trigger_stream = stream::once(async {}).chain(update_stream);
+    let trigger_stream = stream::once(async { Some(UpdateNotice::Changed) }).chain(update_stream);
 
     // For each trigger, execute the query and emit results
     let query_for_log = query.clone();
-    let event_stream = trigger_stream.then(move |_| {
+    let (id, emitter, missed) = match resumed {
+        Some(resumed) => {
+            debug!("SSE subscription {} resumed", resumed.0);
+            resumed
+        }
+        None => {
+            let id = core.subscriptions().next_id();
+            let mut emitter = configured;
+            emitter.mask = mask;
+            emitter.replay_capacity = core.config().sse_replay_events;
+            let emitter = Arc::new(tokio::sync::Mutex::new(emitter));
+            if core.config().sse_replay_events > 0 {
+                core.subscriptions().keep(id, raw_params, emitter.clone());
+            }
+            (id, emitter, Vec::new())
+        }
+    };
+    let heartbeat = core.config().sse_heartbeat;
+    let replayed = stream::iter(missed.into_iter().map(move |sent| sent.to_event(id)));
+    let event_stream = trigger_stream.filter_map(move |notice| {
+        // Keep the subscriber counted until the stream (and this closure) is dropped
+        let _subscriber = &subscriber;
         let core = core.clone();
         let query = query.clone();
+        let emitter = emitter.clone();
         async move {
-            match execute_and_encode(&core, &query).await {
-                Ok(data) => {
-                    debug!("SSE result: {} bytes", data.len());
-                    Event::default().event("result").data(data)
+            let Some(notice) = notice else {
+                warn!("SSE subscriber fell behind, disconnecting");
+                return Some(Event::default().event(LAGGED_EVENT).data(""));
+            };
+            let mut emitter = emitter.lock().await;
+            let sent = match emitter.next(&core, &query, annotations, &notice).await {
+                Ok(Some((event, data))) => {
+                    debug!("SSE {event}: {} bytes", data.len());
+                    emitter.record(event, data)
                 }
+                Ok(None) => return None,
                 Err(e) => {
                     warn!("SSE error: {}", e);
-                    Event::default().event("error").data(e)
+                    emitter.record("error", e)
                 }
-            }
+            };
+            core.subscriptions().touch(id);
+            Some(sent.to_event(id))
         }
     });
+    let event_stream = replayed.chain(event_stream);
+
+    // End the stream once the server starts shutting down, telling the client why
+    let closing = {
+        let shutdown = shutdown.clone();
+        async move { shutdown.closing().await }
+    };
+    let closed = stream::once(async move {
+        shutdown
+            .is_closing()
+            .then(|| Event::default().event(CLOSING_EVENT).data(""))
+    })
+    .filter_map(future::ready);
+    let event_stream = event_stream.take_until(closing).chain(closed);
 
     debug!("SSE subscription started for: {}", query_for_log);
-    Sse::new(event_stream.map(Ok)).keep_alive(KeepAlive::new().interval(Duration::from_secs(30)))
+    Ok(Sse::new(event_stream.map(Ok))
+        .keep_alive(KeepAlive::new().interval(heartbeat).text("heartbeat")))
 }
 
-/// Execute query and encode result as base64 Arrow IPC
-async fn execute_and_encode(core: &ServerCore, query: &str) -> Result<String, String> {
-    let df = core.execute_query(query).await.map_err(|e| e.to_string())?;
-    dataframe_to_base64_ipc(df).await.map_err(|e| e.to_string())
+/// Per-connection state deciding what each re-evaluation sends
+struct Emitter {
+    mode: EmitMode,
+    /// Hash of the last payload sent (`on_change`)
+    last_hash: Option<u64>,
+    /// Last full result (`diff`)
+    last_df: Option<DataFrame>,
+    /// Column mask of the credential the query runs as
+    mask: Option<ColumnMask>,
+    /// Columns, rows and encoding of each event
+    view: EventView,
+    /// Appends answered with `append` events between full results (`appends=true`)
+    resync: Option<u32>,
+    /// `append` events sent since the last full result, `None` before the first
+    appended: Option<u32>,
+    /// Tables the query reads, looked up on the first append
+    tables: Option<Vec<String>>,
+    /// Sequence number of the last event sent
+    seq: u64,
+    /// Last events sent, kept for replay
+    sent: VecDeque<SentEvent>,
+    replay_capacity: usize,
+}
+
+impl Emitter {
+    fn new(mode: EmitMode) -> Self {
+        Self {
+            mode,
+            last_hash: None,
+            last_df: None,
+            mask: None,
+            view: EventView::default(),
+            resync: None,
+            appended: None,
+            tables: None,
+            seq: 0,
+            sent: VecDeque::new(),
+            replay_capacity: 0,
+        }
+    }
+
+    /// An emitter configured by a subscription's query parameters
+    fn for_params(params: &SubscribeParams) -> Result<Self, String> {
+        let mode = params.emit.unwrap_or_default();
+        let resync = match params.appends {
+            Some(true) if mode != EmitMode::Always => {
+                return Err("`appends` requires emit=always".to_string());
+            }
+            Some(true) => Some(params.resync.unwrap_or(DEFAULT_RESYNC)),
+            _ => None,
+        };
+        let mut emitter = Self::new(mode);
+        emitter.view = EventView::from_params(params);
+        emitter.resync = resync;
+        Ok(emitter)
+    }
+
+    /// Number the next event and keep it for replay
+    fn record(&mut self, event: &'static str, data: String) -> SentEvent {
+        self.seq += 1;
+        let sent = SentEvent {
+            seq: self.seq,
+            event,
+            data,
+        };
+        if self.replay_capacity > 0 {
+            if self.sent.len() == self.replay_capacity {
+                self.sent.pop_front();
+            }
+            self.sent.push_back(sent.clone());
+        }
+        sent
+    }
+
+    /// Events sent after `seq`, or `None` if some of them are no longer kept
+    fn sent_after(&self, seq: u64) -> Option<Vec<SentEvent>> {
+        if seq > self.seq {
+            return None;
+        }
+        let oldest_kept = self.sent.front().map_or(self.seq + 1, |sent| sent.seq);
+        if seq + 1 < oldest_kept {
+            return None;
+        }
+        Some(
+            self.sent
+                .iter()
+                .filter(|sent| sent.seq > seq)
+                .cloned()
+                .collect(),
+        )
+    }
+
+    /// The event answering rows appended to one table: `Some(None)` when the query
+    /// doesn't read it, `None` when a full result is due instead
+    async fn append_event(
+        &mut self,
+        core: &ServerCore,
+        query: &str,
+        annotations: Annotations,
+        notice: &UpdateNotice,
+    ) -> Result<Option<Option<(&'static str, String)>>, String> {
+        let (Some(resync), Some(appended), UpdateNotice::Append { name, new_rows }) =
+            (self.resync, self.appended, notice)
+        else {
+            return Ok(None);
+        };
+        if self.tables.is_none() {
+            let compiled = core
+                .compile_query(query, &piql::Params::new())
+                .await
+                .map_err(|e| e.to_string())?;
+            self.tables = Some(compiled.referenced_tables());
+        }
+        if !self.tables.iter().flatten().any(|table| table == name) {
+            return Ok(Some(None));
+        }
+        if appended >= resync {
+            return Ok(None);
+        }
+        let df = core
+            .execute_query_on_rows(
+                query,
+                annotations,
+                name,
+                new_rows.clone(),
+                self.mask.as_ref(),
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+        let df = self.view.select(df)?;
+        self.appended = Some(appended + 1);
+        Ok(Some(Some(("append", self.view.encode(df).await?))))
+    }
+
+    /// Execute the query and encode the event answering `notice`, or `None` if
+    /// there is nothing to send
+    async fn next(
+        &mut self,
+        core: &ServerCore,
+        query: &str,
+        annotations: Annotations,
+        notice: &UpdateNotice,
+    ) -> Result<Option<(&'static str, String)>, String> {
+        if let Some(event) = self.append_event(core, query, annotations, notice).await? {
+            return Ok(event);
+        }
+        let (df, _) = core
+            .execute_query_as(query, &piql::Params::new(), annotations, self.mask.as_ref())
+            .await
+            .map_err(|e| e.to_string())?;
+        let df = self.view.select(df)?;
+        match self.mode {
+            EmitMode::Always => {
+                let data = self.view.encode(df).await?;
+                if self.resync.is_some() {
+                    self.appended = Some(0);
+                }
+                Ok(Some(("result", data)))
+            }
+            EmitMode::OnChange => {
+                let data = self.view.encode(df).await?;
+                let mut hasher = DefaultHasher::new();
+                data.hash(&mut hasher);
+                let hash = hasher.finish();
+                if self.last_hash.replace(hash) == Some(hash) {
+                    return Ok(None);
+                }
+                Ok(Some(("result", data)))
+            }
+            EmitMode::Diff => {
+                // Without its key column (see `columns`), rows are compared whole
+                let key = core
+                    .partition_key(query)
+                    .await
+                    .map_err(|e| e.to_string())?
+                    .filter(|key| df.get_column_index(key).is_some());
+                let first = self.last_df.is_none();
+                let previous = self
+                    .last_df
+                    .replace(df.clone())
+                    .unwrap_or_else(|| df.clear());
+                let diff = tokio::task::spawn_blocking(move || {
+                    piql::diff_results(&previous, &df, key.as_deref())
+                })
+                .await
+                .map_err(|e| e.to_string())?
+                .map_err(|e| e.to_string())?;
+                if !first && diff.height() == 0 {
+                    return Ok(None);
+                }
+                Ok(Some(("diff", self.view.encode(diff).await?)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::prelude::*;
+
+    async fn core_with_gold(gold: [i32; 2]) -> ServerCore {
+        let core = ServerCore::new();
+        core.insert_df("t", df! { "id" => &[1, 2], "gold" => &gold }.unwrap())
+            .await;
+        core
+    }
+
+    #[tokio::test]
+    async fn on_change_skips_identical_results() {
+        let core = core_with_gold([1, 2]).await;
+        let mut emitter = Emitter::new(EmitMode::OnChange);
+        let annotations = Annotations::default();
+
+        assert!(
+            emitter
+                .next(&core, "t", annotations, &UpdateNotice::Changed)
+                .await
+                .unwrap()
+                .is_some()
+        );
+        assert!(
+            emitter
+                .next(&core, "t", annotations, &UpdateNotice::Changed)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        core.insert_df("t", df! { "id" => &[1, 2], "gold" => &[1, 3] }.unwrap())
+            .await;
+        assert!(
+            emitter
+                .next(&core, "t", annotations, &UpdateNotice::Changed)
+                .await
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn diff_sends_changed_rows_only() {
+        let core = core_with_gold([1, 2]).await;
+        let mut emitter = Emitter::new(EmitMode::Diff);
+        let annotations = Annotations::default();
+
+        let (event, _) = emitter
+            .next(&core, "t", annotations, &UpdateNotice::Changed)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(event, "diff");
+        assert!(
+            emitter
+                .next(&core, "t", annotations, &UpdateNotice::Changed)
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        core.insert_df("t", df! { "id" => &[1, 2], "gold" => &[1, 3] }.unwrap())
+            .await;
+        let (event, _) = emitter
+            .next(&core, "t", annotations, &UpdateNotice::Changed)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(event, "diff");
+    }
+    #[tokio::test]
+    async fn json_events_send_a_page_of_selected_columns() {
+        let core = core_with_gold([1, 2]).await;
+        let mut emitter = Emitter::new(EmitMode::Always);
+        emitter.view = EventView {
+            format: EventFormat::Json,
+            columns: Some(vec!["gold".to_string()]),
+            offset: 1,
+            limit: Some(5),
+        };
+
+        let (_, data) = emitter
+            .next(&core, "t", Annotations::default(), &UpdateNotice::Changed)
+            .await
+            .unwrap()
+            .unwrap();
+        let event: serde_json::Value = serde_json::from_str(&data).unwrap();
+        assert_eq!(
+            event,
+            serde_json::json!({"total": 2, "offset": 1, "rows": [{"gold": 2}]})
+        );
+
+        emitter.view.offset = usize::MAX;
+        let (_, data) = emitter
+            .next(&core, "t", Annotations::default(), &UpdateNotice::Changed)
+            .await
+            .unwrap()
+            .unwrap();
+        let event: serde_json::Value = serde_json::from_str(&data).unwrap();
+        assert_eq!(event["rows"], serde_json::json!([]));
+
+        emitter.view.columns = Some(vec!["missing".to_string()]);
+        assert!(
+            emitter
+                .next(&core, "t", Annotations::default(), &UpdateNotice::Changed)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn appends_send_only_the_new_rows_until_resync() {
+        let core = core_with_gold([1, 2]).await;
+        let mut emitter = Emitter::new(EmitMode::Always);
+        emitter.view.format = EventFormat::Json;
+        emitter.resync = Some(1);
+        // Event name and row count of the next event
+        async fn next(
+            emitter: &mut Emitter,
+            core: &ServerCore,
+            notice: &UpdateNotice,
+        ) -> Option<(&'static str, u64)> {
+            let (event, data) = emitter
+                .next(core, "t.filter($gold > 1)", Annotations::default(), notice)
+                .await
+                .unwrap()?;
+            let data: serde_json::Value = serde_json::from_str(&data).unwrap();
+            Some((event, data["total"].as_u64().unwrap()))
+        }
+
+        assert_eq!(
+            next(&mut emitter, &core, &UpdateNotice::Changed).await,
+            Some(("result", 1))
+        );
+        let new_rows = df! { "id" => &[3, 4], "gold" => &[0, 7] }.unwrap();
+        core.append_tick("t", new_rows.clone()).await;
+        let appended = UpdateNotice::Append {
+            name: "t".to_string(),
+            new_rows: new_rows.clone(),
+        };
+        assert_eq!(
+            next(&mut emitter, &core, &appended).await,
+            Some(("append", 1))
+        );
+
+        let other = UpdateNotice::Append {
+            name: "other".to_string(),
+            new_rows: new_rows.clone(),
+        };
+        assert_eq!(next(&mut emitter, &core, &other).await, None);
+
+        // The second append is past `resync`, so the full result is sent
+        core.append_tick("t", new_rows).await;
+        assert_eq!(
+            next(&mut emitter, &core, &appended).await,
+            Some(("result", 3))
+        );
+    }
+
+    #[tokio::test]
+    async fn resuming_replays_kept_events_of_the_same_request() {
+        let mut emitter = Emitter::new(EmitMode::Always);
+        emitter.replay_capacity = 2;
+        for data in ["a", "b", "c"] {
+            emitter.record("result", data.to_string());
+        }
+        let seqs = |sent: Vec<SentEvent>| sent.iter().map(|e| e.seq).collect::<Vec<_>>();
+        assert_eq!(emitter.sent_after(1).map(seqs), Some(vec![2, 3]));
+        assert_eq!(emitter.sent_after(3).map(seqs), Some(vec![]));
+        // Event 1 was dropped from the ring; event 4 was never sent
+        assert!(emitter.sent_after(0).is_none());
+        assert!(emitter.sent_after(4).is_none());
+
+        let subscriptions = Subscriptions::default();
+        let id = subscriptions.next_id();
+        subscriptions.keep(
+            id,
+            "query=t".into(),
+            Arc::new(tokio::sync::Mutex::new(emitter)),
+        );
+        assert_eq!(parse_event_id(&format!("{id}-2")), Some((id, 2)));
+
+        let (_, missed) = subscriptions.resume(id, 2, "query=t", None).await.unwrap();
+        assert_eq!(missed.len(), 1);
+        assert_eq!(missed[0].data, "c");
+        assert!(subscriptions.resume(id, 2, "query=u", None).await.is_none());
+        assert!(
+            subscriptions
+                .resume(id + 1, 2, "query=t", None)
+                .await
+                .is_none()
+        );
+        let mask = ColumnMask::default();
+        assert!(
+            subscriptions
+                .resume(id, 2, "query=t", Some(&mask))
+                .await
+                .is_none()
+        );
+
+        // Saved subscriptions resume after a restart, and new ids don't collide
+        let saved = serde_json::to_string(&subscriptions.save().await).unwrap();
+        let restored = Subscriptions::default();
+        restored.restore(serde_json::from_str(&saved).unwrap(), 2);
+        let (_, missed) = restored.resume(id, 2, "query=t", None).await.unwrap();
+        assert_eq!(missed[0].data, "c");
+        assert!(restored.next_id() > id);
+    }
 }
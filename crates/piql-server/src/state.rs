@@ -1,52 +1,292 @@
 //! Server state with channel-based DataFrame updates
 
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 
-use piql::{DataFrameEntry, EvalContext, TimeSeriesConfig};
+use piql::{DataFrameEntry, EvalContext, PiqlError, RemoveMode, TimeSeriesConfig};
 use polars::prelude::*;
-use serde::Serialize;
-use tokio::sync::{RwLock, broadcast};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, RwLock};
 use utoipa::ToSchema;
 
+use crate::annotate::{self, Annotations, Provenance};
+use crate::cache::{self, CacheStatus, ResultCache};
+use crate::compute::{ComputeConfig, ComputePool};
+use crate::hooks::ReloadHook;
+use crate::mask::ColumnMask;
+use crate::materialize::{self, Materialization};
+use crate::metrics::{Metrics, QueryOutcome, UpdateKind};
+use crate::policy::QueryPolicy;
+use crate::query_log::{self, QUERY_LOG_TABLE, QueryLog, QueryLogEntry};
+use crate::table_stats::{self, TABLES_TABLE, TableOrigin, TableRecord, TableStats};
+use crate::updates::{self, LagPolicy, UpdateBus, UpdateReceiver};
+
 /// DataFrame update message
 #[derive(Clone)]
 pub enum DfUpdate {
-    Insert { name: String, df: DataFrame },
-    Remove { name: String },
-    Reload { name: String, df: DataFrame },
+    Insert {
+        name: String,
+        df: DataFrame,
+    },
+    Remove {
+        name: String,
+    },
+    Reload {
+        name: String,
+        df: DataFrame,
+    },
+    /// Rows added to the end of a table (inserted as a new table if it doesn't exist)
+    Append {
+        name: String,
+        new_rows: DataFrame,
+    },
+    /// Replace a table's rows with a scan every query re-reads, keeping its config
+    /// (tables with a reload hook are read into memory instead)
+    Scan {
+        name: String,
+        scan: Box<LazyFrame>,
+    },
+}
+
+/// What an update notification tells subscribers about the change
+#[derive(Debug, Clone)]
+pub enum UpdateNotice {
+    /// `new_rows` were appended to table `name`; no other table changed
+    Append { name: String, new_rows: DataFrame },
+    /// Tables were replaced or removed, or the tick or a table config changed
+    Changed,
+}
+
+/// The rows `new` adds to the end of `old`, if `new` is `old` plus at least one
+/// row with the same schema
+pub fn appended_rows(old: &DataFrame, new: &DataFrame) -> Option<DataFrame> {
+    if new.height() <= old.height() || new.schema() != old.schema() {
+        return None;
+    }
+    new.slice(0, old.height())
+        .equals_missing(old)
+        .then(|| new.slice(old.height() as i64, new.height() - old.height()))
 }
 
 /// Shared server state
 pub struct SharedState {
     pub(crate) ctx: RwLock<EvalContext>,
-    update_tx: broadcast::Sender<()>,
+    /// Per-subscriber queues of update notices
+    updates: UpdateBus,
     /// Maximum rows to return from queries (None = unlimited)
     max_rows: Option<u32>,
+    /// Timeout and size ceilings that fail a query instead of truncating it
+    limits: RwLock<piql::ResourceLimits>,
+    /// Constructs queries may use, checked before evaluation
+    policy: std::sync::RwLock<Arc<QueryPolicy>>,
+    /// Threads query and materialization collects run on
+    compute: std::sync::RwLock<Arc<ComputePool>>,
+    /// Name of the current run (multi-run mode), used for `_run` annotations
+    current_run: RwLock<Option<String>>,
+    /// Per-table transforms applied on insert/reload
+    hooks: RwLock<HashMap<String, ReloadHook>>,
+    /// Lazily computed table schemas, invalidated on every update to the table
+    schemas: RwLock<HashMap<String, TableSchema>>,
+    /// Derived tables re-evaluated whenever an upstream table changes
+    materializations: RwLock<HashMap<String, Materialization>>,
+    /// Query, subscription and update counters exported at `/metrics`
+    metrics: Arc<Metrics>,
+    /// Per-table version counters, bumped on every update (never reset, even on removal)
+    versions: RwLock<HashMap<String, u64>>,
+    /// Collected results of recent queries, validated against `versions`
+    cache: Mutex<ResultCache>,
+    /// Parsed and desugared queries by text, dropped when table configs change
+    prepared: Mutex<HashMap<String, piql::PreparedQuery>>,
+    /// Recently executed queries, published as the `_queries` table
+    query_log: Mutex<QueryLog>,
+    /// Size, update time and origin of each table, published as the `_tables` table
+    tables: Mutex<BTreeMap<String, TableRecord>>,
 }
 
+/// Run `f` on the blocking pool. Reading a scan (e.g. counting its rows) blocks on
+/// Polars' own runtime, which panics on a tokio worker thread.
+async fn off_runtime<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    tokio::task::spawn_blocking(f)
+        .await
+        .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+}
+
+/// Fail for the name of a built-in table, which can't be replaced
+fn reject_builtin(name: &str) -> Result<(), PiqlError> {
+    if let Some(purpose) = table_stats::builtin(name) {
+        return Err(piql::EvalError::Other(format!("`{name}` is reserved for {purpose}")).into());
+    }
+    Ok(())
+}
+
+/// Prepared queries kept before the whole set is dropped
+const PREPARED_CAPACITY: usize = 1024;
+
+/// Chunks a table may accumulate from appends before it is rechunked
+const MAX_APPENDED_CHUNKS: usize = 64;
+
 impl SharedState {
-    pub fn new() -> (Arc<Self>, broadcast::Receiver<()>) {
+    pub fn new() -> (Arc<Self>, UpdateReceiver) {
         Self::with_max_rows(None)
     }
 
-    pub fn with_max_rows(max_rows: Option<u32>) -> (Arc<Self>, broadcast::Receiver<()>) {
-        let (update_tx, update_rx) = broadcast::channel(16);
+    pub fn with_max_rows(max_rows: Option<u32>) -> (Arc<Self>, UpdateReceiver) {
+        let query_log = QueryLog::new(query_log::DEFAULT_CAPACITY);
+        let mut ctx = EvalContext::new();
+        ctx.dataframes.insert(
+            QUERY_LOG_TABLE.to_string(),
+            DataFrameEntry {
+                df: query_log.to_df().expect("empty query log"),
+                time_series: None,
+                scan: None,
+            },
+        );
+        ctx.dataframes.insert(
+            TABLES_TABLE.to_string(),
+            DataFrameEntry {
+                df: table_stats::to_df(&BTreeMap::new()).expect("empty table stats"),
+                time_series: None,
+                scan: None,
+            },
+        );
+        let metrics = Arc::new(Metrics::new());
+        let compute = ComputePool::new(ComputeConfig::default(), metrics.clone());
         let state = Arc::new(Self {
-            ctx: RwLock::new(EvalContext::new()),
-            update_tx,
+            ctx: RwLock::new(ctx),
+            updates: UpdateBus::new(),
             max_rows,
+            limits: RwLock::new(piql::ResourceLimits::default()),
+            policy: std::sync::RwLock::new(Arc::new(QueryPolicy::default())),
+            compute: std::sync::RwLock::new(Arc::new(compute)),
+            current_run: RwLock::new(None),
+            hooks: RwLock::new(HashMap::new()),
+            schemas: RwLock::new(HashMap::new()),
+            materializations: RwLock::new(HashMap::new()),
+            metrics,
+            versions: RwLock::new(HashMap::new()),
+            cache: Mutex::new(ResultCache::new(cache::DEFAULT_CAPACITY)),
+            prepared: Mutex::new(HashMap::new()),
+            query_log: Mutex::new(query_log),
+            tables: Mutex::new(BTreeMap::new()),
         });
+        let update_rx = state.subscribe_updates();
         (state, update_rx)
     }
 
-    /// Get a receiver for update notifications
-    pub fn subscribe_updates(&self) -> broadcast::Receiver<()> {
-        self.update_tx.subscribe()
+    /// Server metrics
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+
+    /// Get a receiver for update notifications, coalescing them when it falls behind
+    pub fn subscribe_updates(&self) -> UpdateReceiver {
+        self.subscribe_updates_with(LagPolicy::default(), updates::DEFAULT_QUEUE_CAPACITY)
     }
 
-    /// Apply a DataFrame update
+    /// Get a receiver queueing up to `capacity` notices, handling overflow per `policy`
+    pub fn subscribe_updates_with(&self, policy: LagPolicy, capacity: usize) -> UpdateReceiver {
+        self.updates.subscribe(policy, capacity)
+    }
+
+    /// Per-subscriber queue and lag metrics
+    pub fn update_bus(&self) -> &UpdateBus {
+        &self.updates
+    }
+
+    /// Register a hook run on every insert/reload of `name` (replaces any existing hook)
+    pub async fn set_reload_hook(&self, name: impl Into<String>, hook: ReloadHook) {
+        self.hooks.write().await.insert(name.into(), hook);
+    }
+
+    /// Remove the reload hook for `name`, if any
+    pub async fn remove_reload_hook(&self, name: &str) -> Option<ReloadHook> {
+        self.hooks.write().await.remove(name)
+    }
+
+    /// Run the table's reload hook (if any) on an incoming update.
+    ///
+    /// Returns `None` when the hook fails; the update is dropped and the previous
+    /// version of the table stays registered.
+    async fn run_reload_hook(&self, update: DfUpdate) -> Option<DfUpdate> {
+        let update = match update {
+            // Hooks transform rows, so the scan is read into memory for them
+            DfUpdate::Scan { name, scan } if self.hooks.read().await.contains_key(&name) => {
+                match tokio::task::spawn_blocking(move || scan.collect()).await {
+                    Ok(Ok(df)) => DfUpdate::Reload { name, df },
+                    Ok(Err(e)) => {
+                        log::error!("Reading {name} for its reload hook failed: {e}");
+                        return None;
+                    }
+                    Err(e) => {
+                        log::error!("Reading {name} for its reload hook panicked: {e}");
+                        return None;
+                    }
+                }
+            }
+            update => update,
+        };
+        type Rebuild = fn(String, DataFrame) -> DfUpdate;
+        let (name, df, rebuild): (String, DataFrame, Rebuild) = match update {
+            DfUpdate::Insert { name, df } => (name, df, |name, df| DfUpdate::Insert { name, df }),
+            DfUpdate::Reload { name, df } => (name, df, |name, df| DfUpdate::Reload { name, df }),
+            // The hook sees only the appended rows, so it should transform row by row
+            DfUpdate::Append { name, new_rows } => (name, new_rows, |name, new_rows| {
+                DfUpdate::Append { name, new_rows }
+            }),
+            DfUpdate::Remove { .. } | DfUpdate::Scan { .. } => return Some(update),
+        };
+        let Some(hook) = self.hooks.read().await.get(&name).cloned() else {
+            return Some(rebuild(name, df));
+        };
+
+        let hook_name = name.clone();
+        let result = tokio::task::spawn_blocking(move || hook.apply(&hook_name, df)).await;
+        match result {
+            Ok(Ok(df)) => Some(rebuild(name, df)),
+            Ok(Err(e)) => {
+                log::error!("Reload hook for {name} failed, keeping previous data: {e}");
+                None
+            }
+            Err(e) => {
+                log::error!("Reload hook for {name} panicked, keeping previous data: {e}");
+                None
+            }
+        }
+    }
+
+    /// Apply a DataFrame update, then refresh materialized views that depend on it
     pub async fn apply_update(&self, update: DfUpdate) {
+        if let Some(name) = self.apply_single_update(update).await {
+            self.refresh_materializations(&name).await;
+        }
+    }
+
+    /// Apply one update without cascading; returns the table name if it was applied
+    async fn apply_single_update(&self, update: DfUpdate) -> Option<String> {
+        let update = self.run_reload_hook(update).await?;
         let mut ctx = self.ctx.write().await;
+        let update = match update {
+            DfUpdate::Append { name, new_rows } if !ctx.dataframes.contains_key(&name) => {
+                DfUpdate::Insert { name, df: new_rows }
+            }
+            update => update,
+        };
+        let (updated, kind) = match &update {
+            DfUpdate::Insert { name, .. } => (name.clone(), UpdateKind::Insert),
+            DfUpdate::Remove { name } => (name.clone(), UpdateKind::Remove),
+            DfUpdate::Reload { name, .. } => (name.clone(), UpdateKind::Reload),
+            DfUpdate::Append { name, .. } => (name.clone(), UpdateKind::Append),
+            DfUpdate::Scan { name, .. } => (name.clone(), UpdateKind::Reload),
+        };
+        if let Some(purpose) = table_stats::builtin(&updated) {
+            log::warn!("Ignoring update to {updated}: the name is reserved for {purpose}");
+            return None;
+        }
+        if matches!(kind, UpdateKind::Insert | UpdateKind::Remove) {
+            // Inserting or removing a table resets its time-series config
+            self.prepared.lock().await.clear();
+        }
+        let mut notice = UpdateNotice::Changed;
         match update {
             DfUpdate::Insert { name, df } => {
                 ctx.dataframes.insert(
@@ -54,29 +294,152 @@ impl SharedState {
                     DataFrameEntry {
                         df,
                         time_series: None,
+                        scan: None,
                     },
                 );
             }
             DfUpdate::Remove { name } => {
                 ctx.dataframes.remove(&name);
+                self.materializations.write().await.remove(&name);
             }
             DfUpdate::Reload { name, df } => {
                 if let Some(entry) = ctx.dataframes.get_mut(&name) {
                     entry.df = df;
+                    entry.scan = None;
                 } else {
                     ctx.dataframes.insert(
                         name,
                         DataFrameEntry {
                             df,
                             time_series: None,
+                            scan: None,
                         },
                     );
                 }
             }
+            DfUpdate::Append { name, new_rows } => {
+                let entry = ctx
+                    .dataframes
+                    .get_mut(&name)
+                    .expect("appends to missing tables become inserts");
+                if let Some(scan) = &entry.scan {
+                    // The scan stays the source; the new rows are held after it
+                    if new_rows.schema() != entry.df.schema() {
+                        log::error!("Dropping rows appended to {name}: schema differs");
+                        return None;
+                    }
+                    let rows = new_rows.clone().lazy();
+                    match concat([scan.clone(), rows], UnionArgs::default()) {
+                        Ok(scan) => entry.scan = Some(scan),
+                        Err(e) => {
+                            log::error!("Dropping rows appended to {name}: {e}");
+                            return None;
+                        }
+                    }
+                } else if let Err(e) = entry.df.vstack_mut(&new_rows) {
+                    log::error!("Dropping rows appended to {name}: {e}");
+                    return None;
+                }
+                if entry.df.max_n_chunks() > MAX_APPENDED_CHUNKS {
+                    entry.df.rechunk_mut();
+                }
+                notice = UpdateNotice::Append { name, new_rows };
+            }
+            DfUpdate::Scan { name, scan } => {
+                let time_series = ctx
+                    .dataframes
+                    .get(&name)
+                    .and_then(|entry| entry.time_series.clone());
+                match DataFrameEntry::from_scan(*scan, time_series) {
+                    Ok(entry) => {
+                        ctx.dataframes.insert(name, entry);
+                    }
+                    Err(e) => {
+                        log::error!("Failed to scan {name}, keeping previous data: {e}");
+                        return None;
+                    }
+                }
+            }
         }
+        self.metrics.record_update(kind);
+        self.bump_version(&updated).await;
+        let entry = ctx.dataframes.get(&updated).cloned();
         drop(ctx);
+        self.schemas.write().await.remove(&updated);
+        // Counting a scan's rows reads its source, so it happens outside the lock
+        let record = match entry {
+            Some(entry) => {
+                Some(off_runtime(move || TableRecord::new(&entry, TableOrigin::default())).await)
+            }
+            None => None,
+        };
+        self.record_table(&updated, record, kind != UpdateKind::Insert)
+            .await;
         // Notify subscribers (ignore if no receivers)
-        let _ = self.update_tx.send(());
+        self.updates.send(notice);
+        Some(updated)
+    }
+
+    /// Re-evaluate every materialized view downstream of `changed`.
+    ///
+    /// A view whose query fails (e.g. an upstream table was removed) keeps its previous data.
+    async fn refresh_materializations(&self, changed: &str) {
+        let (order, views) = {
+            let views = self.materializations.read().await;
+            (materialize::refresh_order(changed, &views), views.clone())
+        };
+        for name in order {
+            let result = match self.prepare(&views[&name].query).await {
+                Ok(prepared) => {
+                    self.collect_query(prepared, &piql::Params::new(), Annotations::default(), None)
+                        .await
+                }
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(df) => {
+                    self.apply_single_update(DfUpdate::Reload { name, df })
+                        .await;
+                }
+                Err(e) => {
+                    log::warn!("Re-materializing {name} failed, keeping previous data: {e}");
+                }
+            }
+        }
+    }
+
+    /// Evaluate `query`, store the result as table `name`, and keep it up to date
+    /// whenever a table it reads changes. Replaces any previous view of that name.
+    pub async fn materialize(&self, name: &str, query: &str) -> Result<(), piql::PiqlError> {
+        reject_builtin(name)?;
+        let prepared = self.prepare(query).await?;
+        let dependencies = prepared.referenced_tables().to_vec();
+        // Reject views that would read themselves, directly or through other views
+        let mut views = self.materializations.read().await.clone();
+        views.remove(name);
+        materialize::dependency_graph(&views).insert(name, dependencies.iter().cloned())?;
+
+        let df = self
+            .collect_query(prepared, &piql::Params::new(), Annotations::default(), None)
+            .await?;
+        self.materializations.write().await.insert(
+            name.to_string(),
+            Materialization {
+                query: query.to_string(),
+                dependencies: dependencies.into_iter().collect(),
+            },
+        );
+        self.apply_update(DfUpdate::Insert {
+            name: name.to_string(),
+            df,
+        })
+        .await;
+        Ok(())
+    }
+
+    /// Registered materialized views by name
+    pub async fn materializations(&self) -> HashMap<String, Materialization> {
+        self.materializations.read().await.clone()
     }
 
     /// Insert a DataFrame
@@ -88,12 +451,162 @@ impl SharedState {
         .await;
     }
 
-    /// Remove a DataFrame
-    pub async fn remove_df(&self, name: &str) {
-        self.apply_update(DfUpdate::Remove {
+    /// Append rows (typically one tick's worth) to a table, creating it if needed
+    pub async fn append_tick(&self, name: impl Into<String>, new_rows: DataFrame) {
+        self.apply_update(DfUpdate::Append {
+            name: name.into(),
+            new_rows,
+        })
+        .await;
+    }
+
+    /// Register table `name` as a scan every query re-reads, so only the columns
+    /// and rows a query needs are read (replaces any table of that name, keeping its
+    /// time-series config)
+    pub async fn insert_scan(&self, name: impl Into<String>, scan: LazyFrame) {
+        self.apply_update(DfUpdate::Scan {
+            name: name.into(),
+            scan: Box::new(scan),
+        })
+        .await;
+    }
+
+    /// Read scan-backed table `name` into memory, so queries stop re-reading its
+    /// source (no-op for tables already in memory)
+    pub async fn pin_df(&self, name: &str) -> Result<(), PiqlError> {
+        let Some(entry) = self.ctx.read().await.dataframes.get(name).cloned() else {
+            return Err(PiqlError::UnknownTable(name.to_string()));
+        };
+        if !entry.is_scan() {
+            return Ok(());
+        }
+        let df = self
+            .compute()
+            .run(move || entry.collect())
+            .await?
+            .map_err(piql::EvalError::from)?;
+        self.apply_update(DfUpdate::Reload {
             name: name.to_string(),
+            df,
         })
         .await;
+        Ok(())
+    }
+
+    /// The update replacing table `name` with `df`: an `Append` of the trailing rows
+    /// when `df` extends the current table, else a `Reload`
+    ///
+    /// Tables with a reload hook always reload, since the registered table is the
+    /// hook's output rather than what was loaded.
+    pub async fn reload_update(&self, name: String, df: DataFrame) -> DfUpdate {
+        if !self.hooks.read().await.contains_key(&name) {
+            let ctx = self.ctx.read().await;
+            if let Some(new_rows) = ctx
+                .dataframes
+                .get(&name)
+                .filter(|entry| !entry.is_scan())
+                .and_then(|entry| appended_rows(&entry.df, &df))
+            {
+                return DfUpdate::Append { name, new_rows };
+            }
+        }
+        DfUpdate::Reload { name, df }
+    }
+
+    /// Remove a table, and with [`RemoveMode::Cascade`] the materialized views
+    /// downstream of it, which are returned. With [`RemoveMode::Fail`] a table that
+    /// views read is kept and [`PiqlError::TableInUse`] returned.
+    pub async fn remove_df(&self, name: &str, mode: RemoveMode) -> Result<Vec<String>, PiqlError> {
+        reject_builtin(name)?;
+        if !self.ctx.read().await.dataframes.contains_key(name) {
+            return Err(PiqlError::UnknownTable(name.to_string()));
+        }
+        let views = materialize::refresh_order(name, &*self.materializations.read().await);
+        if mode == RemoveMode::Fail && !views.is_empty() {
+            return Err(PiqlError::TableInUse {
+                table: name.to_string(),
+                dependents: views,
+            });
+        }
+        // Removals don't cascade: the views go with the table instead of being
+        // re-evaluated against it
+        for view in views.iter().rev() {
+            self.apply_single_update(DfUpdate::Remove { name: view.clone() })
+                .await;
+        }
+        self.apply_single_update(DfUpdate::Remove {
+            name: name.to_string(),
+        })
+        .await;
+        Ok(views)
+    }
+
+    /// Rename a table, keeping its data, time-series config and, for a materialized
+    /// view, its query
+    ///
+    /// Fails if `new` exists or a materialized view reads `old`, since its query
+    /// names it.
+    pub async fn rename_df(&self, old: &str, new: &str) -> Result<(), PiqlError> {
+        reject_builtin(old)?;
+        reject_builtin(new)?;
+        let readers: Vec<String> = {
+            let views = self.materializations.read().await;
+            let mut readers: Vec<String> = views
+                .iter()
+                .filter(|(_, view)| view.dependencies.contains(old))
+                .map(|(name, _)| name.clone())
+                .collect();
+            readers.sort();
+            readers
+        };
+        if !readers.is_empty() {
+            return Err(PiqlError::TableInUse {
+                table: old.to_string(),
+                dependents: readers,
+            });
+        }
+
+        let mut ctx = self.ctx.write().await;
+        if ctx.dataframes.contains_key(new) {
+            return Err(PiqlError::TableExists(new.to_string()));
+        }
+        let Some(entry) = ctx.dataframes.remove(old) else {
+            return Err(PiqlError::UnknownTable(old.to_string()));
+        };
+        ctx.dataframes.insert(new.to_string(), entry);
+        self.prepared.lock().await.clear();
+        {
+            let mut views = self.materializations.write().await;
+            if let Some(view) = views.remove(old) {
+                views.insert(new.to_string(), view);
+            }
+        }
+        self.metrics.record_update(UpdateKind::Remove);
+        self.metrics.record_update(UpdateKind::Insert);
+        self.bump_version(old).await;
+        self.bump_version(new).await;
+        drop(ctx);
+        {
+            let mut schemas = self.schemas.write().await;
+            schemas.remove(old);
+            schemas.remove(new);
+        }
+        {
+            let mut tables = self.tables.lock().await;
+            if let Some(record) = tables.remove(old) {
+                tables.insert(new.to_string(), record);
+            }
+            self.publish_tables(&tables).await;
+        }
+        self.updates.send(UpdateNotice::Changed);
+        // Views left reading `new` by an earlier removal pick the table back up
+        self.refresh_materializations(new).await;
+        Ok(())
+    }
+
+    /// Version counter of every table updated so far (absent = never updated)
+    pub(crate) async fn table_versions(&self) -> HashMap<String, u64> {
+        self.versions.read().await.clone()
     }
 
     /// List all DataFrame names
@@ -104,6 +617,152 @@ impl SharedState {
         names
     }
 
+    /// Names of the tables a credential with column mask `mask` can query
+    pub async fn list_dataframes_as(&self, mask: Option<&ColumnMask>) -> Vec<String> {
+        let Some(mask) = mask else {
+            return self.list_dataframes().await;
+        };
+        let ctx = self.masked_context(mask).await;
+        let mut names: Vec<String> = ctx.dataframes.into_keys().collect();
+        names.sort();
+        names
+    }
+
+    /// Completion suggestions for the query text before `cursor` (a byte offset).
+    /// With `mask` only the tables and columns the credential can see are suggested.
+    pub async fn complete(
+        &self,
+        query: &str,
+        cursor: usize,
+        mask: Option<&ColumnMask>,
+    ) -> Vec<piql::Completion> {
+        match mask {
+            Some(mask) => piql::complete(query, cursor, &self.masked_context(mask).await),
+            None => piql::complete(query, cursor, &*self.ctx.read().await),
+        }
+    }
+
+    /// Column names, dtypes and null counts of a table (cached until the table changes)
+    pub async fn table_schema(&self, name: &str) -> Option<TableSchema> {
+        if let Some(schema) = self.schemas.read().await.get(name) {
+            return Some(schema.clone());
+        }
+        // Hold the cache lock while reading the table so a concurrent update's
+        // invalidation can't land between computing and caching
+        let mut schemas = self.schemas.write().await;
+        let entry = self.ctx.read().await.dataframes.get(name)?.clone();
+        let owned = name.to_string();
+        let schema = match off_runtime(move || TableSchema::from_entry(&owned, &entry)).await {
+            Ok(schema) => schema,
+            Err(e) => {
+                log::warn!("Failed to read the schema of {name}: {e}");
+                return None;
+            }
+        };
+        schemas.insert(name.to_string(), schema.clone());
+        Some(schema)
+    }
+
+    /// Schemas of the tables in `names` as a credential with column mask `mask` sees
+    /// them, skipping tables it can't see (or that were removed). Without a mask these
+    /// are the cached [`Self::table_schema`]s.
+    pub async fn table_schemas_as(
+        &self,
+        names: &[String],
+        mask: Option<&ColumnMask>,
+    ) -> Vec<TableSchema> {
+        let Some(mask) = mask else {
+            let mut schemas = Vec::with_capacity(names.len());
+            for name in names {
+                if let Some(schema) = self.table_schema(name).await {
+                    schemas.push(schema);
+                }
+            }
+            return schemas;
+        };
+        // Read from the restricted tables, so views of masked tables are covered too
+        let ctx = self.masked_context(mask).await;
+        let names = names.to_vec();
+        off_runtime(move || {
+            names
+                .iter()
+                .filter_map(|name| {
+                    let entry = ctx.dataframes.get(name)?;
+                    TableSchema::from_entry(name, entry)
+                        .inspect_err(|e| log::warn!("Failed to read the schema of {name}: {e}"))
+                        .ok()
+                })
+                .collect()
+        })
+        .await
+    }
+
+    /// Size, update time and origin of a table. With `mask` the table is measured as
+    /// the credential sees it.
+    pub async fn table_stats(&self, name: &str, mask: Option<&ColumnMask>) -> Option<TableStats> {
+        // Read the table before locking the records, which `publish_tables` holds
+        // while taking the context lock
+        let entry = match mask {
+            Some(mask) => self.masked_context(mask).await.dataframes.remove(name)?,
+            None => self.ctx.read().await.dataframes.get(name)?.clone(),
+        };
+        let record = self.tables.lock().await.get(name).cloned();
+        let name = name.to_string();
+        Some(off_runtime(move || TableStats::new(&name, &entry, record.as_ref())).await)
+    }
+
+    /// Record where table `name` was loaded from; ignored for unknown tables.
+    /// Replacing the table with an `Insert` clears it, reloads and appends keep it.
+    pub async fn set_table_origin(&self, name: &str, origin: TableOrigin) {
+        let mut tables = self.tables.lock().await;
+        let Some(record) = tables.get_mut(name) else {
+            return;
+        };
+        record.origin = origin;
+        self.publish_tables(&tables).await;
+    }
+
+    /// Track the size of an updated table (`None` once removed), keeping its origin
+    /// if `keep_origin`, and republish `_tables`
+    async fn record_table(&self, name: &str, record: Option<TableRecord>, keep_origin: bool) {
+        let mut tables = self.tables.lock().await;
+        match record {
+            Some(mut record) => {
+                if keep_origin && let Some(previous) = tables.get(name) {
+                    record.origin = previous.origin.clone();
+                }
+                tables.insert(name.to_string(), record);
+            }
+            None => {
+                tables.remove(name);
+            }
+        }
+        self.publish_tables(&tables).await;
+    }
+
+    /// Replace the `_tables` table with `tables`, without notifying subscribers
+    async fn publish_tables(&self, tables: &BTreeMap<String, TableRecord>) {
+        let df = match table_stats::to_df(tables) {
+            Ok(df) => df,
+            Err(e) => {
+                log::error!("Failed to build {TABLES_TABLE}: {e}");
+                return;
+            }
+        };
+        let mut ctx = self.ctx.write().await;
+        ctx.dataframes.insert(
+            TABLES_TABLE.to_string(),
+            DataFrameEntry {
+                df,
+                time_series: None,
+                scan: None,
+            },
+        );
+        self.bump_version(TABLES_TABLE).await;
+        drop(ctx);
+        self.schemas.write().await.remove(TABLES_TABLE);
+    }
+
     /// Register per-table time-series metadata for scope/sugar behavior.
     pub async fn set_time_series_config(
         &self,
@@ -116,42 +775,592 @@ impl SharedState {
             .get_mut(name)
             .ok_or_else(|| piql::EvalError::UnknownIdent(name.to_string()))?;
         entry.time_series = Some(config);
+        self.prepared.lock().await.clear();
+        self.bump_version(name).await;
         drop(ctx);
         // Notify subscribers that query behavior may have changed.
-        let _ = self.update_tx.send(());
+        self.updates.send(UpdateNotice::Changed);
         Ok(())
     }
 
-    /// Execute a query and collect results (runs on blocking thread pool)
+    /// Invalidate cached results that read `name`. Callers hold the `ctx` write lock.
+    async fn bump_version(&self, name: &str) {
+        *self
+            .versions
+            .write()
+            .await
+            .entry(name.to_string())
+            .or_default() += 1;
+    }
+
+    /// Check every query against `policy` before evaluating it
+    pub(crate) fn set_policy(&self, policy: QueryPolicy) {
+        *self.policy.write().expect("policy lock poisoned") = Arc::new(policy);
+    }
+
+    /// Constructs queries may use
+    pub fn policy(&self) -> Arc<QueryPolicy> {
+        self.policy.read().expect("policy lock poisoned").clone()
+    }
+
+    /// Replace the compute pool; queries already queued on the old one still finish
+    pub(crate) fn set_compute(&self, config: ComputeConfig) {
+        let pool = ComputePool::new(config, self.metrics.clone());
+        *self.compute.write().expect("compute lock poisoned") = Arc::new(pool);
+    }
+
+    /// Pool query and materialization collects run on
+    pub fn compute(&self) -> Arc<ComputePool> {
+        self.compute.read().expect("compute lock poisoned").clone()
+    }
+
+    /// Fail queries that run longer or produce larger results than `limits`
+    pub async fn set_resource_limits(&self, limits: piql::ResourceLimits) {
+        *self.limits.write().await = limits;
+    }
+
+    /// Maximum number of cached query results (0 disables the cache)
+    pub async fn set_cache_capacity(&self, capacity: usize) {
+        self.cache.lock().await.set_capacity(capacity);
+    }
+
+    /// Maximum number of entries kept in the `_queries` table (0 disables the log)
+    pub async fn set_query_log_capacity(&self, capacity: usize) {
+        let mut log = self.query_log.lock().await;
+        log.set_capacity(capacity);
+        self.publish_query_log(&log).await;
+    }
+
+    /// Append a finished query to the log and republish `_queries`
+    async fn log_query<T>(
+        &self,
+        query: &str,
+        started_at: std::time::SystemTime,
+        duration: std::time::Duration,
+        result: &Result<(DataFrame, T, Option<i64>), piql::PiqlError>,
+    ) {
+        let mut log = self.query_log.lock().await;
+        if log.capacity() == 0 {
+            return;
+        }
+        log.record(QueryLogEntry {
+            query: query.to_string(),
+            started_at,
+            duration,
+            rows: result.as_ref().ok().map(|(df, ..)| df.height()),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        });
+        self.publish_query_log(&log).await;
+    }
+
+    /// Replace the `_queries` table with the log's entries, without notifying subscribers
+    async fn publish_query_log(&self, log: &QueryLog) {
+        let df = match log.to_df() {
+            Ok(df) => df,
+            Err(e) => {
+                log::error!("Failed to build {QUERY_LOG_TABLE}: {e}");
+                return;
+            }
+        };
+        let mut ctx = self.ctx.write().await;
+        ctx.dataframes.insert(
+            QUERY_LOG_TABLE.to_string(),
+            DataFrameEntry {
+                df,
+                time_series: None,
+                scan: None,
+            },
+        );
+        self.bump_version(QUERY_LOG_TABLE).await;
+        drop(ctx);
+        self.schemas.write().await.remove(QUERY_LOG_TABLE);
+    }
+
+    /// Drop all cached query results, returning how many were removed
+    pub async fn clear_cache(&self) -> usize {
+        self.cache.lock().await.clear()
+    }
+
+    /// Set the current simulation tick and notify subscribers
+    pub async fn set_tick(&self, tick: Option<i64>) {
+        self.ctx.write().await.tick = tick;
+        self.updates.send(UpdateNotice::Changed);
+    }
+
+    /// Set the current run name reported in `_run` annotations
+    pub async fn set_current_run(&self, run: Option<String>) {
+        *self.current_run.write().await = run;
+    }
+
+    /// Turn the plan-level query optimizer on (the default) or off
+    pub async fn set_optimizer(&self, enabled: bool) {
+        self.ctx.write().await.optimize = enabled;
+        self.prepared.lock().await.clear();
+    }
+
+    /// Keep the global string cache for the rest of the process (see
+    /// [`EvalContext::with_string_cache`])
+    pub async fn enable_string_cache(&self) {
+        piql::enable_string_cache();
+        self.ctx.write().await.string_cache = true;
+    }
+
+    /// Set the `_all::` run label column that `.by_run` groups over
+    pub async fn set_run_label_column(&self, column: impl Into<String>) {
+        self.ctx.write().await.run_label_column = column.into();
+    }
+
+    /// Execute a query and collect results (runs on the compute pool)
     pub async fn execute_query(&self, query: &str) -> Result<DataFrame, piql::PiqlError> {
-        let ctx = self.ctx.read().await.clone();
+        self.execute_query_annotated(query, Annotations::default())
+            .await
+    }
+
+    /// Compile a query and return its optimized plan and core AST without collecting
+    /// results. The query is checked against the policy as if it ran; with `mask` it
+    /// is planned against the tables as by [`Self::execute_query_masked`].
+    pub async fn explain_query(
+        &self,
+        query: &str,
+        mask: Option<&ColumnMask>,
+    ) -> Result<ExplainResponse, piql::PiqlError> {
+        let ctx = match mask {
+            Some(mask) => self.masked_context(mask).await,
+            None => self.ctx.read().await.clone(),
+        };
         let query = query.to_string();
         let max_rows = self.max_rows;
+        let policy = self.policy();
 
-        tokio::task::spawn_blocking(move || {
-            let result = piql::run(&query, &ctx)?;
-            match result {
-                piql::Value::DataFrame(lf, _) => {
-                    let lf = if let Some(limit) = max_rows {
-                        lf.limit(limit)
-                    } else {
-                        lf
-                    };
-                    lf.collect()
-                        .map_err(piql::EvalError::from)
-                        .map_err(piql::PiqlError::from)
+        self.compute()
+            .run(move || {
+                let compiled = policy
+                    .apply(piql::compile(&query, &ctx)?, &ctx)
+                    .map_err(|e| piql::PiqlError::from(piql::EvalError::Other(e)))?;
+                let core_ast = crate::explain::core_ast_json(compiled.core());
+                match piql::run_compiled(&compiled, &ctx)? {
+                    piql::Value::DataFrame(lf, _) => {
+                        let lf = if let Some(limit) = max_rows {
+                            lf.limit(limit)
+                        } else {
+                            lf
+                        };
+                        let plan = lf
+                            .explain(true)
+                            .map_err(piql::EvalError::from)
+                            .map_err(piql::PiqlError::from)?;
+                        Ok(ExplainResponse { plan, core_ast })
+                    }
+                    _ => Err(piql::PiqlError::Eval(piql::EvalError::TypeError {
+                        expected: "DataFrame".to_string(),
+                        got: "other value".to_string(),
+                    })),
                 }
-                _ => Err(piql::PiqlError::Eval(piql::EvalError::TypeError {
-                    expected: "DataFrame".to_string(),
-                    got: "other value".to_string(),
-                })),
+            })
+            .await?
+    }
+
+    /// Execute a query and append the requested provenance columns to the result
+    pub async fn execute_query_annotated(
+        &self,
+        query: &str,
+        annotations: Annotations,
+    ) -> Result<DataFrame, piql::PiqlError> {
+        self.execute_query_cached(query, annotations)
+            .await
+            .map(|(df, _)| df)
+    }
+
+    /// Execute a query through the result cache, reporting whether it was a hit
+    pub async fn execute_query_cached(
+        &self,
+        query: &str,
+        annotations: Annotations,
+    ) -> Result<(DataFrame, CacheStatus), piql::PiqlError> {
+        self.execute_query_with_params(query, &piql::Params::new(), annotations)
+            .await
+    }
+
+    /// Execute a query with `:name` placeholders bound to `params`, through the result cache
+    pub async fn execute_query_with_params(
+        &self,
+        query: &str,
+        params: &piql::Params,
+        annotations: Annotations,
+    ) -> Result<(DataFrame, CacheStatus), piql::PiqlError> {
+        let started_at = std::time::SystemTime::now();
+        let start = std::time::Instant::now();
+        let result = self.lookup_or_collect(query, params).await;
+        let elapsed = start.elapsed();
+        let rows = result.as_ref().map_or(0, |(df, ..)| df.height());
+        self.metrics
+            .record_query(QueryOutcome::of(&result), elapsed, rows);
+        self.log_query(query, started_at, elapsed, &result).await;
+        let (df, status, tick) = result?;
+        if let CacheStatus::Hit | CacheStatus::Miss = status {
+            self.metrics.record_cache(status == CacheStatus::Hit);
+        }
+
+        // Annotate after caching: `generated_at` and `_run` must reflect this request
+        let run = self.current_run.read().await.clone();
+        let provenance = Provenance {
+            tick,
+            run: run.as_deref(),
+            query,
+        };
+        let df = annotate::annotate(df, annotations, &provenance).map_err(piql::EvalError::from)?;
+        Ok((df, status))
+    }
+
+    /// Serve `query` from the result cache, or collect and cache it.
+    /// Also returns the tick the result was computed at.
+    async fn lookup_or_collect(
+        &self,
+        query: &str,
+        params: &piql::Params,
+    ) -> Result<(DataFrame, CacheStatus, Option<i64>), piql::PiqlError> {
+        let key = cache::cache_key(query, params);
+        let (tick, versions) = {
+            let ctx = self.ctx.read().await;
+            (ctx.tick, self.versions.read().await.clone())
+        };
+
+        let cached = {
+            let mut cache = self.cache.lock().await;
+            if cache.capacity() == 0 {
+                None
+            } else {
+                Some(cache.get(&key, tick, &versions))
             }
-        })
+        };
+        let Some(cached) = cached else {
+            let prepared = self.prepare(query).await?;
+            let df = self
+                .collect_query(prepared, params, Annotations::default(), self.max_rows)
+                .await?;
+            return Ok((df, CacheStatus::Bypass, tick));
+        };
+        if let Some(df) = cached {
+            return Ok((df, CacheStatus::Hit, tick));
+        }
+
+        let prepared = self.prepare(query).await?;
+        let tables = prepared.referenced_tables().to_vec();
+        let df = self
+            .collect_query(prepared, params, Annotations::default(), self.max_rows)
+            .await?;
+        // Versions from before evaluation: if a table changed meanwhile, the entry is
+        // merely stale on the next lookup
+        let inputs = tables
+            .into_iter()
+            .map(|table| {
+                let version = versions.get(&table).copied().unwrap_or(0);
+                (table, version)
+            })
+            .collect();
+        self.cache
+            .lock()
+            .await
+            .insert(key, tick, inputs, df.clone());
+        Ok((df, CacheStatus::Miss, tick))
+    }
+
+    /// The prepared form of `query`, parsed once and reused until table configs change
+    async fn prepare(&self, query: &str) -> Result<piql::PreparedQuery, piql::PiqlError> {
+        if let Some(prepared) = self.prepared.lock().await.get(query) {
+            return Ok(prepared.clone());
+        }
+        // Keep the context locked until the entry is stored, so a concurrent config
+        // change (which clears the map under the write lock) can't be missed
+        let ctx = self.ctx.read().await;
+        let prepared = ctx.prepare(query)?;
+        let mut cache = self.prepared.lock().await;
+        if cache.len() >= PREPARED_CAPACITY {
+            cache.clear();
+        }
+        cache.insert(query.to_string(), prepared.clone());
+        Ok(prepared)
+    }
+
+    /// Compile `query` against the current tables without evaluating it
+    pub async fn compile_query(
+        &self,
+        query: &str,
+        params: &piql::Params,
+    ) -> Result<piql::CompiledQuery, piql::PiqlError> {
+        let prepared = self.prepare(query).await?;
+        prepared.compile(params, &*self.ctx.read().await)
+    }
+
+    /// Names of `query`'s `:name` placeholders, sorted
+    pub async fn query_params(&self, query: &str) -> Result<Vec<String>, piql::PiqlError> {
+        Ok(self.prepare(query).await?.param_names())
+    }
+
+    /// Source tables, scoped tick range and `schema` of `query`'s result
+    pub async fn result_meta(
+        &self,
+        query: &str,
+        params: &piql::Params,
+        schema: &Schema,
+    ) -> Result<piql::QueryResultMeta, piql::PiqlError> {
+        let prepared = self.prepare(query).await?;
+        let ctx = self.ctx.read().await;
+        let compiled = prepared.compile(params, &ctx)?;
+        Ok(piql::QueryResultMeta::new(&compiled, &ctx, schema))
+    }
+
+    /// Partition key of the table `query` reads from, used to key result diffs
+    pub async fn partition_key(&self, query: &str) -> Result<Option<String>, piql::PiqlError> {
+        let prepared = self.prepare(query).await?;
+        Ok(prepared.partition_key(&*self.ctx.read().await))
+    }
+
+    /// Execute `query` with bare table names bound to the tables of run `run`
+    /// (`{run}::table`), bypassing the result cache; tables the run lacks keep their
+    /// latest version
+    pub async fn execute_query_in_run(
+        &self,
+        query: &str,
+        params: &piql::Params,
+        run: &str,
+    ) -> Result<DataFrame, piql::PiqlError> {
+        let ctx = run_context(&*self.ctx.read().await, run)
+            .ok_or_else(|| piql::EvalError::Other(format!("Unknown run: {run}")))?;
+        let prepared = ctx.prepare(query)?;
+        self.collect_in(ctx, prepared, params, Annotations::default(), self.max_rows)
+            .await
+    }
+
+    /// Execute a query with `:name` placeholders bound to `params`, bypassing the result
+    /// cache, query log and metrics. With `mask` the tables are restricted as by
+    /// [`Self::execute_query_masked`].
+    pub async fn execute_query_uncached(
+        &self,
+        query: &str,
+        params: &piql::Params,
+        mask: Option<&ColumnMask>,
+    ) -> Result<DataFrame, piql::PiqlError> {
+        let Some(mask) = mask else {
+            let prepared = self.prepare(query).await?;
+            return self
+                .collect_query(prepared, params, Annotations::default(), self.max_rows)
+                .await;
+        };
+        let ctx = self.masked_context(mask).await;
+        // Prepared against `ctx`, not through the shared prepared map
+        let prepared = ctx.prepare(query)?;
+        self.collect_in(ctx, prepared, params, Annotations::default(), self.max_rows)
+            .await
+    }
+
+    /// Execute a query as a credential with column mask `mask` sees the tables (see
+    /// [`ColumnMask::restrict`]), bypassing the result cache
+    pub async fn execute_query_masked(
+        &self,
+        query: &str,
+        params: &piql::Params,
+        annotations: Annotations,
+        mask: &ColumnMask,
+    ) -> Result<DataFrame, piql::PiqlError> {
+        let ctx = self.masked_context(mask).await;
+        self.execute_logged(ctx, query, params, annotations).await
+    }
+
+    /// The tables as a credential with column mask `mask` sees them
+    async fn masked_context(&self, mask: &ColumnMask) -> EvalContext {
+        let ctx = self.ctx.read().await.clone();
+        let views = self.materializations.read().await.clone();
+        let mask = mask.clone();
+        // Restricting resolves scan schemas and view plans, which may read files
+        off_runtime(move || mask.restrict(ctx, &views)).await
+    }
+
+    /// Execute each `(query, params)` against one snapshot of the tables, so every
+    /// result reflects the same tick, bypassing the result cache. With `parallel` the
+    /// queries are collected concurrently on the compute pool, else one at a time.
+    /// With `mask` the snapshot is restricted as by [`Self::execute_query_masked`].
+    pub async fn execute_batch(
+        &self,
+        queries: &[(String, piql::Params)],
+        parallel: bool,
+        mask: Option<&ColumnMask>,
+    ) -> Vec<Result<DataFrame, piql::PiqlError>> {
+        let ctx = match mask {
+            Some(mask) => self.masked_context(mask).await,
+            None => self.ctx.read().await.clone(),
+        };
+        let annotations = Annotations::default();
+        if parallel {
+            let runs = queries.iter().map(|(query, params)| {
+                self.execute_logged(ctx.clone(), query, params, annotations)
+            });
+            return futures::future::join_all(runs).await;
+        }
+        let mut results = Vec::with_capacity(queries.len());
+        for (query, params) in queries {
+            results.push(
+                self.execute_logged(ctx.clone(), query, params, annotations)
+                    .await,
+            );
+        }
+        results
+    }
+
+    /// Execute `query` against `ctx`, recording it in the metrics and query log
+    async fn execute_logged(
+        &self,
+        ctx: EvalContext,
+        query: &str,
+        params: &piql::Params,
+        annotations: Annotations,
+    ) -> Result<DataFrame, piql::PiqlError> {
+        let started_at = std::time::SystemTime::now();
+        let start = std::time::Instant::now();
+        let tick = ctx.tick;
+        // Prepared against `ctx`, not through the shared prepared map
+        let result = match ctx.prepare(query) {
+            Ok(prepared) => {
+                self.collect_in(ctx, prepared, params, annotations, self.max_rows)
+                    .await
+            }
+            Err(e) => Err(e),
+        }
+        .map(|df| (df, (), tick));
+        let elapsed = start.elapsed();
+        let rows = result.as_ref().map_or(0, |(df, ..)| df.height());
+        self.metrics
+            .record_query(QueryOutcome::of(&result), elapsed, rows);
+        self.log_query(query, started_at, elapsed, &result).await;
+        result.map(|(df, ..)| df)
+    }
+
+    /// Execute `query` with table `name` bound to only `rows`, bypassing the result
+    /// cache; used to evaluate a query over the rows just appended to `name`. With
+    /// `mask` the tables are restricted as by [`Self::execute_query_masked`].
+    pub async fn execute_query_on_rows(
+        &self,
+        query: &str,
+        annotations: Annotations,
+        name: &str,
+        rows: DataFrame,
+        mask: Option<&ColumnMask>,
+    ) -> Result<DataFrame, piql::PiqlError> {
+        let mut ctx = self.ctx.read().await.clone();
+        if let Some(entry) = ctx.dataframes.get_mut(name) {
+            entry.df = rows;
+            entry.scan = None;
+        }
+        let prepared = match mask {
+            Some(mask) => {
+                let views = self.materializations.read().await.clone();
+                let mask = mask.clone();
+                ctx = off_runtime(move || mask.restrict(ctx, &views)).await;
+                // Prepared against `ctx`, not through the shared prepared map
+                ctx.prepare(query)?
+            }
+            None => self.prepare(query).await?,
+        };
+        self.collect_in(
+            ctx,
+            prepared,
+            &piql::Params::new(),
+            annotations,
+            self.max_rows,
+        )
         .await
-        .map_err(|e| piql::PiqlError::Eval(piql::EvalError::Other(format!("task failed: {e}"))))?
+    }
+
+    /// Evaluate and collect a prepared query on the compute pool
+    async fn collect_query(
+        &self,
+        prepared: piql::PreparedQuery,
+        params: &piql::Params,
+        annotations: Annotations,
+        max_rows: Option<u32>,
+    ) -> Result<DataFrame, piql::PiqlError> {
+        let ctx = self.ctx.read().await.clone();
+        self.collect_in(ctx, prepared, params, annotations, max_rows)
+            .await
+    }
+
+    /// Evaluate and collect a prepared query against `ctx` on the compute pool,
+    /// cancelling the collect if the caller stops waiting for it
+    async fn collect_in(
+        &self,
+        ctx: EvalContext,
+        prepared: piql::PreparedQuery,
+        params: &piql::Params,
+        annotations: Annotations,
+        max_rows: Option<u32>,
+    ) -> Result<DataFrame, piql::PiqlError> {
+        let run = self.current_run.read().await.clone();
+        let params = params.clone();
+        let limits = *self.limits.read().await;
+        let policy = self.policy();
+
+        self.compute()
+            .run_cancellable(move |cancelled| {
+                let compiled = policy
+                    .apply(prepared.compile(&params, &ctx)?, &ctx)
+                    .map_err(|e| piql::PiqlError::from(piql::EvalError::Other(e)))?;
+                let result = piql::run_compiled(&compiled, &ctx)?;
+                match result {
+                    piql::Value::DataFrame(lf, _) => {
+                        let lf = if let Some(limit) = max_rows {
+                            lf.limit(limit)
+                        } else {
+                            lf
+                        };
+                        let provenance = Provenance {
+                            tick: ctx.tick,
+                            run: run.as_deref(),
+                            query: prepared.query(),
+                        };
+                        // Stopped early if the request is dropped
+                        let df = limits.collect_cancellable(lf, cancelled)?;
+                        annotate::annotate(df, annotations, &provenance)
+                            .map_err(piql::EvalError::from)
+                            .map_err(piql::PiqlError::from)
+                    }
+                    _ => Err(piql::PiqlError::Eval(piql::EvalError::TypeError {
+                        expected: "DataFrame".to_string(),
+                        got: "other value".to_string(),
+                    })),
+                }
+            })
+            .await?
     }
 }
 
+/// `ctx` with every `{run}::table` also registered as `table`, or `None` if the run
+/// has no tables. A bare table keeps its time-series config when the run's has none.
+fn run_context(ctx: &EvalContext, run: &str) -> Option<EvalContext> {
+    let prefix = format!("{run}::");
+    let mut scoped = ctx.clone();
+    let mut found = false;
+    for (name, entry) in &ctx.dataframes {
+        let Some(table) = name.strip_prefix(&prefix) else {
+            continue;
+        };
+        let time_series = entry.time_series.clone().or_else(|| {
+            ctx.dataframes
+                .get(table)
+                .and_then(|bare| bare.time_series.clone())
+        });
+        scoped.dataframes.insert(
+            table.to_string(),
+            DataFrameEntry {
+                df: entry.df.clone(),
+                time_series,
+                scan: entry.scan.clone(),
+            },
+        );
+        found = true;
+    }
+    found.then_some(scoped)
+}
+
 // ============ API Types ============
 
 #[derive(Serialize, ToSchema)]
@@ -159,7 +1368,270 @@ pub struct ErrorResponse {
     pub error: String,
 }
 
+#[derive(Serialize, ToSchema)]
+pub struct CacheClearResponse {
+    /// Number of cached results removed
+    pub cleared: usize,
+}
+
 #[derive(Serialize, ToSchema)]
 pub struct DataframesResponse {
     pub names: Vec<String>,
+    /// Per-table schemas (only with `?schemas=true`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schemas: Option<Vec<TableSchema>>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct FormatRequest {
+    pub query: String,
+    /// Line width before method chains are broken (default 80)
+    #[serde(default)]
+    pub width: Option<usize>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct FormatResponse {
+    /// Canonical formatting of the query (null when it doesn't parse)
+    pub formatted: Option<String>,
+    /// Parse errors, empty when `formatted` is set
+    pub errors: Vec<ParseDiagnostic>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ParseDiagnostic {
+    pub message: String,
+    /// Byte offset into the trimmed query
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl From<piql::ParseError> for ParseDiagnostic {
+    fn from(e: piql::ParseError) -> Self {
+        Self {
+            message: e.message,
+            offset: e.offset,
+            line: e.line,
+            column: e.column,
+        }
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CompleteRequest {
+    /// Query text being edited
+    pub query: String,
+    /// Byte offset of the cursor (default: end of `query`)
+    #[serde(default)]
+    pub cursor: Option<usize>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CompleteResponse {
+    /// Suggestions, best first
+    pub completions: Vec<CompletionItem>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CompletionItem {
+    pub label: String,
+    /// `table`, `column`, `method`, `function` or `directive`
+    pub kind: String,
+    /// Column dtype or method receiver
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// Byte offset where the replaced token starts; it ends at the cursor
+    pub replace_start: usize,
+}
+
+impl From<piql::Completion> for CompletionItem {
+    fn from(c: piql::Completion) -> Self {
+        Self {
+            label: c.label,
+            kind: c.kind.as_str().to_string(),
+            detail: c.detail,
+            replace_start: c.replace_start,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, ToSchema)]
+pub struct TableSchema {
+    pub name: String,
+    pub columns: Vec<ColumnSchema>,
+    /// Row count when the schema was computed
+    pub row_count: usize,
+}
+
+#[derive(Clone, Serialize, ToSchema)]
+pub struct ColumnSchema {
+    pub name: String,
+    /// Polars dtype, e.g. `i64`, `str`, `datetime[μs]`
+    pub dtype: String,
+    pub null_count: usize,
+}
+
+impl TableSchema {
+    /// Schema of a registered table; a scan-backed table's row and null counts are
+    /// read from its source
+    pub fn from_entry(name: &str, entry: &DataFrameEntry) -> PolarsResult<Self> {
+        let Some(scan) = &entry.scan else {
+            return Ok(Self::from_df(name, &entry.df));
+        };
+        let null_counts = scan.clone().select([col("*").null_count()]).collect()?;
+        let mut schema = Self::from_df(name, &entry.df);
+        for column in &mut schema.columns {
+            let count = null_counts.column(&column.name)?.get(0)?;
+            column.null_count = count.extract::<usize>().unwrap_or(0);
+        }
+        schema.row_count = entry.height()?;
+        Ok(schema)
+    }
+
+    pub fn from_df(name: &str, df: &DataFrame) -> Self {
+        let columns = df
+            .get_columns()
+            .iter()
+            .map(|c| ColumnSchema {
+                name: c.name().to_string(),
+                dtype: c.dtype().to_string(),
+                null_count: c.null_count(),
+            })
+            .collect();
+        Self {
+            name: name.to_string(),
+            columns,
+            row_count: df.height(),
+        }
+    }
+}
+
+/// JSON body of `POST /query`
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct QueryRequest {
+    /// PiQL query, optionally with `:name` placeholders
+    pub query: String,
+    /// Placeholder values: strings, numbers, booleans, null, or arrays of these
+    #[serde(default)]
+    #[schema(value_type = Object)]
+    pub params: HashMap<String, serde_json::Value>,
+}
+
+impl QueryRequest {
+    /// Convert the JSON placeholder values to piql parameters
+    pub fn piql_params(&self) -> Result<piql::Params, String> {
+        self.params
+            .iter()
+            .map(|(name, value)| Ok((name.clone(), json_to_param(name, value)?)))
+            .collect()
+    }
+}
+
+pub(crate) fn json_to_param(
+    name: &str,
+    value: &serde_json::Value,
+) -> Result<piql::ParamValue, String> {
+    use serde_json::Value as Json;
+    Ok(match value {
+        Json::Null => piql::ParamValue::Null,
+        Json::Bool(b) => piql::ParamValue::Bool(*b),
+        Json::Number(n) => match n.as_i64() {
+            Some(i) => piql::ParamValue::Int(i),
+            None => piql::ParamValue::Float(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        Json::String(s) => piql::ParamValue::String(s.clone()),
+        Json::Array(items) => piql::ParamValue::List(
+            items
+                .iter()
+                .map(|item| json_to_param(name, item))
+                .collect::<Result<_, _>>()?,
+        ),
+        Json::Object(_) => return Err(format!("parameter :{name} cannot be an object")),
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct MaterializationsResponse {
+    /// Materialized views, each listed after the views it reads
+    pub views: Vec<MaterializationNode>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct MaterializationNode {
+    pub name: String,
+    /// PiQL query producing the table
+    pub query: String,
+    /// Tables the query reads
+    pub dependencies: Vec<String>,
+    /// Views that read this one directly
+    pub dependents: Vec<String>,
+}
+
+/// JSON body of `PATCH /dataframes/{name}`
+#[derive(Deserialize, ToSchema)]
+pub struct RenameRequest {
+    /// New name of the table
+    pub name: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct MaterializeRequest {
+    /// Name to register the derived table under
+    pub name: String,
+    /// PiQL query producing the table
+    pub query: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ExplainResponse {
+    /// Optimized Polars logical plan
+    pub plan: String,
+    /// Desugared core AST (see `explain` module docs for the node shapes)
+    #[schema(value_type = Object)]
+    pub core_ast: serde_json::Value,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CapabilitiesResponse {
+    pub namespaces: Vec<NamespaceCapabilities>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct NamespaceCapabilities {
+    /// Receiver: `pl`, `DataFrame`, `GroupBy`, `Expr`, `str`, `dt`, `struct`
+    pub name: String,
+    pub methods: Vec<MethodCapability>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct MethodCapability {
+    pub name: String,
+    /// Minimum number of positional arguments
+    pub min_args: usize,
+    /// Maximum number of positional arguments (null = variadic)
+    pub max_args: Option<usize>,
+    /// Accepted keyword arguments (`*` = arbitrary names)
+    pub kwargs: Vec<String>,
+}
+
+impl CapabilitiesResponse {
+    pub fn current() -> Self {
+        let namespaces = piql::capabilities()
+            .into_iter()
+            .map(|(ns, methods)| NamespaceCapabilities {
+                name: ns.as_str().to_string(),
+                methods: methods
+                    .iter()
+                    .map(|m| MethodCapability {
+                        name: m.name.to_string(),
+                        min_args: m.min_args,
+                        max_args: m.max_args,
+                        kwargs: m.kwargs.iter().map(|k| k.to_string()).collect(),
+                    })
+                    .collect(),
+            })
+            .collect();
+        Self { namespaces }
+    }
 }
@@ -0,0 +1,198 @@
+//! Built-in `_tables` table: size and provenance of every registered table
+//!
+//! Each update records the table's row and column counts, its estimated in-memory
+//! size, when it changed and the file or run it was loaded from, so memory use can
+//! be queried like any table: `_tables.top(5, "bytes")`. `GET /dataframes/{name}/stats`
+//! reports the same for one table.
+//!
+//! Sizes are Polars' `estimated_size`: buffers shared between tables (e.g. a run's
+//! table and its `run::table` copy) count towards each. The built-in `_queries` and
+//! `_tables` are left out of the table. Like `_queries`, republishing bumps the
+//! table's version but doesn't notify subscribers.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use polars::prelude::*;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::query_log::QUERY_LOG_TABLE;
+use crate::schedule::timestamp;
+
+/// Reserved table name of the table statistics
+pub const TABLES_TABLE: &str = "_tables";
+
+/// What a built-in table holds, or `None` for a name tables can be registered under
+pub(crate) fn builtin(name: &str) -> Option<&'static str> {
+    match name {
+        QUERY_LOG_TABLE => Some("the query log"),
+        TABLES_TABLE => Some("table statistics"),
+        _ => None,
+    }
+}
+
+/// Where a table's data was loaded from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TableOrigin {
+    /// File or directory the table was read from
+    pub path: Option<String>,
+    /// Run the table belongs to (multi-run mode)
+    pub run: Option<String>,
+}
+
+impl TableOrigin {
+    /// A table read from `path`
+    pub fn file(path: &Path) -> Self {
+        Self {
+            path: Some(path.display().to_string()),
+            run: None,
+        }
+    }
+
+    /// A table of run `run_name`
+    pub fn run(run_name: &str) -> Self {
+        Self {
+            path: None,
+            run: Some(run_name.to_string()),
+        }
+    }
+}
+
+/// Size and provenance of a table, tracked across updates
+#[derive(Debug, Clone)]
+pub struct TableRecord {
+    pub rows: usize,
+    pub columns: usize,
+    pub bytes: usize,
+    pub scan: bool,
+    pub updated_at: DateTime<Utc>,
+    pub origin: TableOrigin,
+}
+
+impl TableRecord {
+    /// Record `entry` as updated now, keeping `origin`
+    pub fn new(entry: &piql::DataFrameEntry, origin: TableOrigin) -> Self {
+        Self {
+            rows: scanned_height(entry),
+            columns: entry.df.width(),
+            bytes: entry.estimated_size(),
+            scan: entry.is_scan(),
+            updated_at: Utc::now(),
+            origin,
+        }
+    }
+}
+
+/// Result of `GET /dataframes/{name}/stats`
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct TableStats {
+    pub name: String,
+    pub rows: usize,
+    pub columns: usize,
+    /// Estimated in-memory size in bytes (none for a scan-backed table)
+    pub bytes: usize,
+    /// Whether queries re-read the table from its source instead of memory
+    pub scan: bool,
+    /// When the table last changed (RFC 3339, UTC; absent for built-in tables)
+    pub updated_at: Option<String>,
+    /// File or directory the table was read from
+    pub path: Option<String>,
+    /// Run the table belongs to (multi-run mode)
+    pub run: Option<String>,
+}
+
+impl TableStats {
+    /// Stats of `entry` as registered now, with the update time and origin of
+    /// `record` if it is tracked
+    pub fn new(name: &str, entry: &piql::DataFrameEntry, record: Option<&TableRecord>) -> Self {
+        let origin = record.map(|r| r.origin.clone()).unwrap_or_default();
+        Self {
+            name: name.to_string(),
+            rows: scanned_height(entry),
+            columns: entry.df.width(),
+            bytes: entry.estimated_size(),
+            scan: entry.is_scan(),
+            updated_at: record.map(|r| timestamp(&r.updated_at)),
+            path: origin.path,
+            run: origin.run,
+        }
+    }
+}
+
+/// Rows of `entry`, counted by its scan if scan-backed (0 if the scan fails)
+fn scanned_height(entry: &piql::DataFrameEntry) -> usize {
+    entry.height().unwrap_or_else(|e| {
+        log::warn!("Failed to count rows: {e}");
+        0
+    })
+}
+
+/// The records as a DataFrame, one row per table by name
+///
+/// Columns: `name`, `rows`, `columns`, `bytes`, `scan`, `updated_at` (UTC,
+/// milliseconds), `path`, `run`.
+pub fn to_df(records: &BTreeMap<String, TableRecord>) -> PolarsResult<DataFrame> {
+    let name: Vec<&str> = records.keys().map(String::as_str).collect();
+    let rows: Vec<u64> = records.values().map(|r| r.rows as u64).collect();
+    let columns: Vec<u64> = records.values().map(|r| r.columns as u64).collect();
+    let bytes: Vec<u64> = records.values().map(|r| r.bytes as u64).collect();
+    let scan: Vec<bool> = records.values().map(|r| r.scan).collect();
+    let updated_at: Vec<i64> = records
+        .values()
+        .map(|r| r.updated_at.timestamp_millis())
+        .collect();
+    let path: Vec<Option<&str>> = records.values().map(|r| r.origin.path.as_deref()).collect();
+    let run: Vec<Option<&str>> = records.values().map(|r| r.origin.run.as_deref()).collect();
+
+    DataFrame::new(vec![
+        Column::new("name".into(), name),
+        Column::new("rows".into(), rows),
+        Column::new("columns".into(), columns),
+        Column::new("bytes".into(), bytes),
+        Column::new("scan".into(), scan),
+        Column::new("updated_at".into(), updated_at)
+            .cast(&DataType::Datetime(TimeUnit::Milliseconds, None))?,
+        Column::new("path".into(), path),
+        Column::new("run".into(), run),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::ServerCore;
+
+    #[tokio::test]
+    async fn tracks_size_and_origin_across_updates() {
+        let core = ServerCore::new();
+        core.insert_df("t", df! { "a" => &[1i64, 2, 3] }.unwrap())
+            .await;
+        core.set_table_origin("t", TableOrigin::file(Path::new("data/t.csv")))
+            .await;
+        core.append_tick("t", df! { "a" => &[4i64] }.unwrap()).await;
+
+        let stats = core.table_stats("t", None).await.unwrap();
+        assert_eq!((stats.rows, stats.columns), (4, 1));
+        assert!(stats.bytes >= 4 * size_of::<i64>());
+        assert!(stats.updated_at.is_some());
+        assert_eq!(stats.path.as_deref(), Some("data/t.csv"));
+
+        // Replacing the table forgets where the old one came from
+        core.insert_df("t", df! { "a" => &[1i64] }.unwrap()).await;
+        assert_eq!(core.table_stats("t", None).await.unwrap().path, None);
+
+        let df = core
+            .execute_query("_tables.filter($name == \"t\")")
+            .await
+            .unwrap();
+        assert_eq!(df.height(), 1);
+        assert_eq!(df.column("rows").unwrap().u64().unwrap().get(0), Some(1));
+
+        core.remove_df("t", piql::RemoveMode::Fail).await.unwrap();
+        let df = core.execute_query("_tables").await.unwrap();
+        assert_eq!(df.height(), 0);
+        assert!(core.table_stats("t", None).await.is_none());
+    }
+}
@@ -0,0 +1,381 @@
+//! Query templates: canned queries with `{{var}}` placeholders
+//!
+//! A template's placeholders become `:var` parameters when it is registered, so
+//! values given at run time are bound as literals by the parser's parameter binding
+//! (as with `/query`'s `params`), never spliced into the query text. A placeholder
+//! must stand where a value goes; one inside a string literal is rejected.
+//! Templates live in memory until the server stops.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use axum::Json;
+use axum::extract::{Extension, Path as UrlPath, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::Response;
+use log::info;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use utoipa::ToSchema;
+
+use crate::annotate::Annotations;
+use crate::core::ServerCore;
+use crate::error::AppError;
+use crate::mask::ColumnMask;
+use crate::schedule::valid_name;
+use crate::state::{ErrorResponse, json_to_param};
+
+/// A template to register
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct TemplateSpec {
+    /// Letters, digits, `_` and `-`
+    pub name: String,
+    /// PiQL query with `{{var}}` placeholders, e.g. `trades.filter($qty > {{min}})`
+    pub query: String,
+}
+
+/// A registered template
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct TemplateInfo {
+    pub name: String,
+    /// The query as registered, with its `{{var}}` placeholders
+    pub query: String,
+    /// Variables a run must give values for, sorted
+    pub vars: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TemplatesResponse {
+    pub templates: Vec<TemplateInfo>,
+}
+
+/// Values for a template's variables
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct TemplateRunRequest {
+    /// Value of each `{{var}}`: a string, number, bool, null or list of those
+    #[serde(default)]
+    #[schema(value_type = Object)]
+    pub vars: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, Error)]
+pub enum TemplateError {
+    #[error("invalid template name '{0}' (use letters, digits, '_' and '-')")]
+    InvalidName(String),
+    #[error("invalid placeholder '{0}' (expected {{{{name}}}} with a letter or '_' first)")]
+    InvalidPlaceholder(String),
+    #[error("placeholder {{{{{0}}}}} must stand where a value goes, not inside a string")]
+    QuotedPlaceholder(String),
+    #[error("template '{template}' has no variable '{var}'")]
+    UnknownVar { template: String, var: String },
+    #[error("{0}")]
+    InvalidVars(String),
+    #[error("Unknown template: {0}")]
+    Unknown(String),
+    #[error(transparent)]
+    Query(#[from] piql::PiqlError),
+}
+
+impl From<TemplateError> for AppError {
+    fn from(e: TemplateError) -> Self {
+        match e {
+            TemplateError::Query(e) => e.into(),
+            e => AppError::bad_request(e.to_string()),
+        }
+    }
+}
+
+struct Registered {
+    info: TemplateInfo,
+    /// The query with each `{{var}}` replaced by `:var`
+    query: String,
+}
+
+/// Registered templates by name
+#[derive(Default)]
+pub struct Templates {
+    templates: Mutex<BTreeMap<String, Registered>>,
+}
+
+impl Templates {
+    /// Registered templates, sorted by name
+    pub fn list(&self) -> Vec<TemplateInfo> {
+        let templates = self.templates.lock().unwrap();
+        templates.values().map(|t| t.info.clone()).collect()
+    }
+
+    /// Unregister `name`; returns whether it existed
+    pub fn remove(&self, name: &str) -> bool {
+        self.templates.lock().unwrap().remove(name).is_some()
+    }
+
+    /// The parameterized query and variables of `name`
+    fn get(&self, name: &str) -> Option<(String, Vec<String>)> {
+        let templates = self.templates.lock().unwrap();
+        templates
+            .get(name)
+            .map(|t| (t.query.clone(), t.info.vars.clone()))
+    }
+}
+
+/// Validate and register `spec`, replacing any template of the same name
+pub async fn add(core: &ServerCore, spec: TemplateSpec) -> Result<TemplateInfo, TemplateError> {
+    if !valid_name(&spec.name) {
+        return Err(TemplateError::InvalidName(spec.name));
+    }
+    let (query, placeholders) = to_params(&spec.query)?;
+    let vars = core.query_params(&query).await?;
+    // A placeholder the parser didn't see as a parameter was inside a string
+    if let Some(quoted) = placeholders.into_iter().find(|p| !vars.contains(p)) {
+        return Err(TemplateError::QuotedPlaceholder(quoted));
+    }
+
+    let info = TemplateInfo {
+        name: spec.name,
+        query: spec.query,
+        vars,
+    };
+    core.templates().templates.lock().unwrap().insert(
+        info.name.clone(),
+        Registered {
+            info: info.clone(),
+            query,
+        },
+    );
+    info!("Registered template '{}': {}", info.name, info.query);
+    Ok(info)
+}
+
+/// The parameterized query of template `name` and `vars` as its parameters
+pub async fn bind(
+    core: &ServerCore,
+    name: &str,
+    vars: &HashMap<String, serde_json::Value>,
+) -> Result<(String, piql::Params), TemplateError> {
+    let (query, known) = core
+        .templates()
+        .get(name)
+        .ok_or_else(|| TemplateError::Unknown(name.to_string()))?;
+    let params = vars
+        .iter()
+        .map(|(var, value)| {
+            if !known.contains(var) {
+                return Err(TemplateError::UnknownVar {
+                    template: name.to_string(),
+                    var: var.clone(),
+                });
+            }
+            let value = json_to_param(var, value).map_err(TemplateError::InvalidVars)?;
+            Ok((var.clone(), value))
+        })
+        .collect::<Result<piql::Params, _>>()?;
+    // Compiling reports the first variable without a value
+    core.compile_query(&query, &params).await?;
+    Ok((query, params))
+}
+
+/// `query` with each `{{var}}` replaced by `:var`, and the placeholder names
+fn to_params(query: &str) -> Result<(String, Vec<String>), TemplateError> {
+    let mut out = String::with_capacity(query.len());
+    let mut names = Vec::new();
+    let mut rest = query;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            return Err(TemplateError::InvalidPlaceholder(rest[start..].to_string()));
+        };
+        let name = after[..end].trim();
+        let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(TemplateError::InvalidPlaceholder(
+                rest[start..start + end + 4].to_string(),
+            ));
+        }
+        out.push(':');
+        out.push_str(name);
+        names.push(name.to_string());
+        rest = &after[end + 2..];
+    }
+    out.push_str(rest);
+    Ok((out, names))
+}
+
+// ============ HTTP ============
+
+/// List query templates
+#[utoipa::path(
+    get,
+    path = "/templates",
+    responses((status = 200, description = "Templates with their variables", body = TemplatesResponse))
+)]
+pub async fn list(State(core): State<Arc<ServerCore>>) -> Json<TemplatesResponse> {
+    Json(TemplatesResponse {
+        templates: core.templates().list(),
+    })
+}
+
+/// Create or replace a query template
+///
+/// `{{var}}` placeholders in the query are bound as literals when the template
+/// runs, so they must stand where a value goes (not inside a string).
+#[utoipa::path(
+    post,
+    path = "/templates",
+    request_body = TemplateSpec,
+    responses(
+        (status = 200, description = "The registered template", body = TemplateInfo),
+        (status = 400, description = "Invalid name, placeholder or query", body = ErrorResponse)
+    )
+)]
+pub async fn create(
+    State(core): State<Arc<ServerCore>>,
+    Json(spec): Json<TemplateSpec>,
+) -> Result<Json<TemplateInfo>, AppError> {
+    info!("POST /templates: {}", spec.name);
+    Ok(Json(add(&core, spec).await?))
+}
+
+/// Remove a query template
+#[utoipa::path(
+    delete,
+    path = "/templates/{name}",
+    params(("name" = String, Path, description = "Template name")),
+    responses(
+        (status = 204, description = "Template removed"),
+        (status = 400, description = "Unknown template", body = ErrorResponse)
+    )
+)]
+pub async fn delete(
+    State(core): State<Arc<ServerCore>>,
+    UrlPath(name): UrlPath<String>,
+) -> Result<StatusCode, AppError> {
+    info!("DELETE /templates/{name}");
+    if !core.templates().remove(&name) {
+        return Err(TemplateError::Unknown(name).into());
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Run a query template with values for its variables
+///
+/// The result is returned as by `/query`: an Arrow IPC stream, or compact JSON with
+/// `Accept: application/json`.
+#[utoipa::path(
+    post,
+    path = "/templates/{name}/run",
+    params(("name" = String, Path, description = "Template name")),
+    request_body = TemplateRunRequest,
+    responses(
+        (status = 200, description = "Arrow IPC stream, or compact JSON with `Accept: application/json`",
+            content_type = "application/vnd.apache.arrow.stream"),
+        (status = 400, description = "Unknown template, unknown or missing variable, or query error", body = ErrorResponse),
+        (status = 403, description = "Query reads a column masked for this credential", body = ErrorResponse),
+        (status = 422, description = "Query exceeded a resource limit", body = ErrorResponse)
+    )
+)]
+pub async fn run(
+    State(core): State<Arc<ServerCore>>,
+    mask: Option<Extension<ColumnMask>>,
+    UrlPath(name): UrlPath<String>,
+    headers: HeaderMap,
+    Json(request): Json<TemplateRunRequest>,
+) -> Result<Response, AppError> {
+    info!("POST /templates/{name}/run");
+    let (query, params) = bind(&core, &name, &request.vars).await?;
+    crate::http::respond_with_query(
+        &core,
+        mask.as_ref().map(|Extension(mask)| mask),
+        &query,
+        &params,
+        Annotations::default(),
+        &headers,
+        core.config().batch_size,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::prelude::*;
+
+    fn spec(name: &str, query: &str) -> TemplateSpec {
+        TemplateSpec {
+            name: name.to_string(),
+            query: query.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn templates_bind_vars_as_literals() {
+        let core = ServerCore::new();
+        core.insert_df(
+            "t",
+            df! { "name" => &["a", "b\" or true"], "qty" => &[1, 5] }.unwrap(),
+        )
+        .await;
+
+        let info = add(
+            &core,
+            spec(
+                "by_name",
+                r#"t.filter(($name == {{ name }}) & ($qty >= {{min}}))"#,
+            ),
+        )
+        .await
+        .unwrap();
+        assert_eq!(info.vars, ["min", "name"]);
+
+        // A value that would break out of a quoted string is still one literal
+        let vars = HashMap::from([
+            ("name".to_string(), serde_json::json!("b\" or true")),
+            ("min".to_string(), serde_json::json!(2)),
+        ]);
+        let (query, params) = bind(&core, "by_name", &vars).await.unwrap();
+        let (df, _) = core
+            .execute_query_with_params(&query, &params, Annotations::default())
+            .await
+            .unwrap();
+        assert_eq!(df.height(), 1);
+
+        let vars = HashMap::from([("name".to_string(), serde_json::json!("a"))]);
+        assert!(matches!(
+            bind(&core, "by_name", &vars).await,
+            Err(TemplateError::Query(piql::PiqlError::MissingParam(_)))
+        ));
+        let vars = HashMap::from([("other".to_string(), serde_json::json!(1))]);
+        assert!(matches!(
+            bind(&core, "by_name", &vars).await,
+            Err(TemplateError::UnknownVar { .. })
+        ));
+        assert_eq!(core.templates().list().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn invalid_templates_are_rejected() {
+        let core = ServerCore::new();
+        for (query, quoted) in [
+            (r#"t.filter($name == "{{name}}")"#, true),
+            ("t.filter($qty > {{1x}})", false),
+            ("t.filter($qty > {{min)", false),
+        ] {
+            let result = add(&core, spec("bad", query)).await;
+            match result {
+                Err(TemplateError::QuotedPlaceholder(_)) => assert!(quoted, "{query}"),
+                Err(TemplateError::InvalidPlaceholder(_)) => assert!(!quoted, "{query}"),
+                _ => panic!("{query} should be rejected"),
+            }
+        }
+        assert!(matches!(
+            add(&core, spec("../up", "t")).await,
+            Err(TemplateError::InvalidName(_))
+        ));
+        assert!(matches!(
+            add(&core, spec("broken", "t.filter({{x}}")).await,
+            Err(TemplateError::Query(_))
+        ));
+        assert!(core.templates().list().is_empty());
+    }
+}
@@ -0,0 +1,362 @@
+//! Update notifications fanned out to subscribers
+//!
+//! Every subscriber (an SSE stream, an alert) gets its own bounded queue of
+//! [`UpdateNotice`]s, so one slow consumer never holds up the others or the
+//! writer. What happens when a queue is full is the subscriber's [`LagPolicy`].
+
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::Notify;
+
+use crate::state::UpdateNotice;
+
+/// Notices queued per subscriber by default
+pub const DEFAULT_QUEUE_CAPACITY: usize = 16;
+
+/// What to do when a notice arrives for a subscriber whose queue is full
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LagPolicy {
+    /// Drop the oldest queued notice; the next receive reports the lag
+    DropOldest,
+    /// Replace the queue with a single [`UpdateNotice::Changed`], since every
+    /// subscriber re-reads the latest state on any notice
+    #[default]
+    Coalesce,
+    /// Stop notifying the subscriber; its next receive fails with
+    /// [`RecvError::Disconnected`]
+    Disconnect,
+}
+
+impl FromStr for LagPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop_oldest" => Ok(Self::DropOldest),
+            "coalesce" => Ok(Self::Coalesce),
+            "disconnect" => Ok(Self::Disconnect),
+            _ => Err(format!(
+                "unknown lag policy '{s}' (expected drop_oldest, coalesce or disconnect)"
+            )),
+        }
+    }
+}
+
+impl LagPolicy {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::DropOldest => "drop_oldest",
+            Self::Coalesce => "coalesce",
+            Self::Disconnect => "disconnect",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum RecvError {
+    /// This many notices were dropped (`drop_oldest`) since the last receive
+    #[error("subscriber lagged by {0} notices")]
+    Lagged(u64),
+    /// The subscriber fell behind and was cut off (`disconnect`)
+    #[error("subscriber disconnected for lagging")]
+    Disconnected,
+    /// The server state was dropped
+    #[error("update channel closed")]
+    Closed,
+}
+
+/// Queue depth and lag counters of one subscriber
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SubscriberStats {
+    pub id: u64,
+    pub policy: LagPolicy,
+    pub capacity: usize,
+    /// Notices waiting to be received
+    pub queued: usize,
+    /// Notices dropped by `drop_oldest`
+    pub dropped: u64,
+    /// Notices merged by `coalesce`
+    pub coalesced: u64,
+}
+
+/// Name, type, help text and per-subscriber value of an exported metric
+type MetricSeries = (
+    &'static str,
+    &'static str,
+    &'static str,
+    fn(&SubscriberStats) -> u64,
+);
+
+#[derive(Default)]
+struct Queue {
+    notices: VecDeque<UpdateNotice>,
+    /// Dropped notices not yet reported to the receiver
+    lagged: u64,
+    dropped: u64,
+    coalesced: u64,
+    disconnected: bool,
+    closed: bool,
+}
+
+struct Slot {
+    id: u64,
+    policy: LagPolicy,
+    capacity: usize,
+    queue: Mutex<Queue>,
+    notify: Notify,
+}
+
+/// Sends every notice to each subscriber's queue
+pub struct UpdateBus {
+    slots: Mutex<Vec<Arc<Slot>>>,
+    next_id: AtomicU64,
+    disconnects: AtomicU64,
+}
+
+impl UpdateBus {
+    pub fn new() -> Self {
+        Self {
+            slots: Mutex::new(Vec::new()),
+            next_id: AtomicU64::new(0),
+            disconnects: AtomicU64::new(0),
+        }
+    }
+
+    /// Add a subscriber queueing up to `capacity` notices
+    pub fn subscribe(&self, policy: LagPolicy, capacity: usize) -> UpdateReceiver {
+        let slot = Arc::new(Slot {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            policy,
+            capacity: capacity.max(1),
+            queue: Mutex::new(Queue::default()),
+            notify: Notify::new(),
+        });
+        self.slots.lock().unwrap().push(slot.clone());
+        UpdateReceiver { slot }
+    }
+
+    /// Queue `notice` for every subscriber, dropping subscribers whose receiver is gone
+    pub fn send(&self, notice: UpdateNotice) {
+        let mut slots = self.slots.lock().unwrap();
+        slots.retain(|slot| Arc::strong_count(slot) > 1);
+        for slot in slots.iter() {
+            let mut queue = slot.queue.lock().unwrap();
+            if queue.disconnected {
+                continue;
+            }
+            if queue.notices.len() >= slot.capacity {
+                match slot.policy {
+                    LagPolicy::DropOldest => {
+                        queue.notices.pop_front();
+                        queue.lagged += 1;
+                        queue.dropped += 1;
+                    }
+                    LagPolicy::Coalesce => {
+                        queue.coalesced += queue.notices.len() as u64;
+                        queue.notices.clear();
+                        queue.notices.push_back(UpdateNotice::Changed);
+                        drop(queue);
+                        slot.notify.notify_one();
+                        continue;
+                    }
+                    LagPolicy::Disconnect => {
+                        queue.notices.clear();
+                        queue.disconnected = true;
+                        self.disconnects.fetch_add(1, Ordering::Relaxed);
+                        drop(queue);
+                        slot.notify.notify_one();
+                        continue;
+                    }
+                }
+            }
+            queue.notices.push_back(notice.clone());
+            drop(queue);
+            slot.notify.notify_one();
+        }
+    }
+
+    /// Queue depth and lag counters of every live subscriber, by id
+    pub fn stats(&self) -> Vec<SubscriberStats> {
+        self.slots
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|slot| Arc::strong_count(slot) > 1)
+            .map(|slot| {
+                let queue = slot.queue.lock().unwrap();
+                SubscriberStats {
+                    id: slot.id,
+                    policy: slot.policy,
+                    capacity: slot.capacity,
+                    queued: queue.notices.len(),
+                    dropped: queue.dropped,
+                    coalesced: queue.coalesced,
+                }
+            })
+            .collect()
+    }
+
+    /// Subscribers cut off by the `disconnect` policy
+    pub fn disconnects(&self) -> u64 {
+        self.disconnects.load(Ordering::Relaxed)
+    }
+
+    /// Render per-subscriber metrics in the Prometheus text exposition format
+    pub fn render_metrics(&self) -> String {
+        let stats = self.stats();
+        let mut out = String::new();
+        let series: [MetricSeries; 3] = [
+            (
+                "piql_subscriber_queue_depth",
+                "gauge",
+                "Update notices waiting for a subscriber",
+                |s| s.queued as u64,
+            ),
+            (
+                "piql_subscriber_dropped_total",
+                "counter",
+                "Update notices dropped for a lagging subscriber",
+                |s| s.dropped,
+            ),
+            (
+                "piql_subscriber_coalesced_total",
+                "counter",
+                "Update notices merged for a lagging subscriber",
+                |s| s.coalesced,
+            ),
+        ];
+        for (name, kind, help, value) in series {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} {kind}");
+            for s in &stats {
+                let _ = writeln!(
+                    out,
+                    "{name}{{subscriber=\"{}\",policy=\"{}\"}} {}",
+                    s.id,
+                    s.policy.as_str(),
+                    value(s)
+                );
+            }
+        }
+        let name = "piql_subscriber_disconnects_total";
+        let _ = writeln!(out, "# HELP {name} Subscribers disconnected for lagging");
+        let _ = writeln!(out, "# TYPE {name} counter");
+        let _ = writeln!(out, "{name} {}", self.disconnects());
+        out
+    }
+}
+
+impl Default for UpdateBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for UpdateBus {
+    fn drop(&mut self) {
+        for slot in self.slots.get_mut().unwrap().iter() {
+            slot.queue.lock().unwrap().closed = true;
+            slot.notify.notify_one();
+        }
+    }
+}
+
+/// One subscriber's end of the [`UpdateBus`]
+pub struct UpdateReceiver {
+    slot: Arc<Slot>,
+}
+
+impl UpdateReceiver {
+    /// Id reported in subscriber metrics
+    pub fn id(&self) -> u64 {
+        self.slot.id
+    }
+
+    /// Wait for the next notice
+    pub async fn recv(&mut self) -> Result<UpdateNotice, RecvError> {
+        loop {
+            {
+                let mut queue = self.slot.queue.lock().unwrap();
+                if queue.lagged > 0 {
+                    return Err(RecvError::Lagged(std::mem::take(&mut queue.lagged)));
+                }
+                if let Some(notice) = queue.notices.pop_front() {
+                    return Ok(notice);
+                }
+                if queue.disconnected {
+                    return Err(RecvError::Disconnected);
+                }
+                if queue.closed {
+                    return Err(RecvError::Closed);
+                }
+            }
+            self.slot.notify.notified().await;
+        }
+    }
+
+    /// Receive a queued notice without waiting
+    pub fn try_recv(&mut self) -> Option<Result<UpdateNotice, RecvError>> {
+        let mut queue = self.slot.queue.lock().unwrap();
+        if queue.lagged > 0 {
+            return Some(Err(RecvError::Lagged(std::mem::take(&mut queue.lagged))));
+        }
+        queue.notices.pop_front().map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fill(bus: &UpdateBus, n: usize) {
+        for _ in 0..n {
+            bus.send(UpdateNotice::Changed);
+        }
+    }
+
+    #[tokio::test]
+    async fn full_queues_follow_the_subscriber_policy() {
+        let bus = UpdateBus::new();
+        let mut oldest = bus.subscribe(LagPolicy::DropOldest, 2);
+        let mut coalesce = bus.subscribe(LagPolicy::Coalesce, 2);
+        let mut disconnect = bus.subscribe(LagPolicy::Disconnect, 2);
+        fill(&bus, 3);
+
+        assert_eq!(oldest.recv().await.unwrap_err(), RecvError::Lagged(1));
+        assert!(oldest.recv().await.is_ok());
+        assert!(oldest.recv().await.is_ok());
+        assert!(oldest.try_recv().is_none());
+
+        assert!(coalesce.recv().await.is_ok());
+        assert!(coalesce.try_recv().is_none());
+
+        assert_eq!(
+            disconnect.recv().await.unwrap_err(),
+            RecvError::Disconnected
+        );
+        assert_eq!(bus.disconnects(), 1);
+
+        let stats = bus.stats();
+        assert_eq!(stats[0].dropped, 1);
+        assert_eq!(stats[1].coalesced, 2);
+        let text = bus.render_metrics();
+        assert!(text.contains(
+            "piql_subscriber_dropped_total{subscriber=\"0\",policy=\"drop_oldest\"} 1\n"
+        ));
+        assert!(text.contains("piql_subscriber_disconnects_total 1\n"));
+
+        drop(oldest);
+        fill(&bus, 1);
+        assert_eq!(bus.stats().len(), 2);
+        drop(bus);
+        assert!(coalesce.recv().await.is_ok());
+        assert_eq!(coalesce.recv().await.unwrap_err(), RecvError::Closed);
+    }
+}
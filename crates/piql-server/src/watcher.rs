@@ -2,19 +2,24 @@
 //!
 //! This module is feature-gated behind the `file-watcher` feature.
 
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
 
 use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use polars::prelude::PolarsError;
 use tokio::sync::mpsc;
+use tokio::time::Instant;
 
 use crate::core::ServerCore;
 use crate::loader::{
-    collect_files, df_name_from_path, is_supported_file, load_file, load_file_sync,
+    OnReloadFailure, ReloadPolicy, collect_datasets, collect_files, df_name_from_path,
+    is_supported_file, load_file_with_retry, scan_file_with_options,
 };
-use crate::runs::{RunRegistry, RunRegistryOptions};
+use crate::runs::{RunRegistryOptions, read_run_dir};
 use crate::state::DfUpdate;
+use crate::table_stats::TableOrigin;
 
 /// Watch paths for changes and send updates to ServerCore
 pub struct FileWatcher {
@@ -24,16 +29,38 @@ pub struct FileWatcher {
 impl FileWatcher {
     /// Create a new file watcher that monitors the given paths
     pub fn new(core: Arc<ServerCore>, paths: Vec<PathBuf>) -> notify::Result<Self> {
+        Self::with_policy(core, paths, ReloadPolicy::default())
+    }
+
+    /// Create a file watcher with a custom reload retry policy
+    pub fn with_policy(
+        core: Arc<ServerCore>,
+        paths: Vec<PathBuf>,
+        policy: ReloadPolicy,
+    ) -> notify::Result<Self> {
         let (tx, mut rx) = mpsc::channel::<PathBuf>(100);
+        // Hive-partitioned datasets reload as a whole when any of their files change
+        let datasets = collect_datasets(&paths);
 
         // Set up the notify watcher
         let tx_clone = tx.clone();
+        let watched_datasets = datasets.clone();
+        let ignore_policy = policy.clone();
         let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| {
             if let Ok(event) = res {
                 match event.kind {
                     EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) => {
                         for path in event.paths {
-                            if is_supported_file(&path) {
+                            if ignore_policy.is_ignored(&path) {
+                                continue;
+                            }
+                            if let Some(dataset) =
+                                watched_datasets.iter().find(|d| path.starts_with(d))
+                            {
+                                if is_supported_file(&path) {
+                                    let _ = tx_clone.blocking_send(dataset.clone());
+                                }
+                            } else if is_supported_file(&path) {
                                 let _ = tx_clone.blocking_send(path);
                             }
                         }
@@ -43,16 +70,21 @@ impl FileWatcher {
             }
         })?;
 
-        // Watch all provided paths
+        // Watch all provided paths, and datasets recursively
         for path in &paths {
-            watcher.watch(path, RecursiveMode::NonRecursive)?;
+            if !datasets.contains(path) {
+                watcher.watch(path, RecursiveMode::NonRecursive)?;
+            }
+        }
+        for dataset in &datasets {
+            watcher.watch(dataset, RecursiveMode::Recursive)?;
         }
 
         // Spawn task to process file change events
         tokio::spawn(async move {
-            // Debounce: collect events for a short window
+            // Debounce: reload once no change has arrived for `policy.debounce`
             let mut pending: std::collections::HashSet<PathBuf> = std::collections::HashSet::new();
-            let debounce_duration = Duration::from_millis(100);
+            let debounce_duration = policy.debounce;
 
             loop {
                 tokio::select! {
@@ -62,22 +94,34 @@ impl FileWatcher {
                     _ = tokio::time::sleep(debounce_duration), if !pending.is_empty() => {
                         for path in pending.drain() {
                             let update = if path.exists() {
-                                // load_file is async and uses spawn_blocking internally
-                                match load_file(&path).await {
-                                    Ok(df) => {
-                                        let name = df_name_from_path(&path);
-                                        DfUpdate::Reload { name, df }
-                                    }
-                                    Err(e) => {
-                                        eprintln!("Failed to reload {}: {}", path.display(), e);
-                                        continue;
-                                    }
+                                let name = df_name_from_path(&path);
+                                match load_update(&core, name.clone(), &path, &policy).await {
+                                    Ok(update) => update,
+                                    Err(_) => match policy.on_failure {
+                                        OnReloadFailure::KeepStale => {
+                                            log::warn!("Keeping stale data for {name} after failed reload");
+                                            continue;
+                                        }
+                                        OnReloadFailure::Remove => {
+                                            log::warn!("Removing {name} after failed reload");
+                                            DfUpdate::Remove { name }
+                                        }
+                                    },
                                 }
                             } else {
                                 let name = df_name_from_path(&path);
                                 DfUpdate::Remove { name }
                             };
+                            let loaded = match &update {
+                                DfUpdate::Reload { name, .. }
+                                | DfUpdate::Append { name, .. }
+                                | DfUpdate::Scan { name, .. } => Some(name.clone()),
+                                _ => None,
+                            };
                             core.apply_update(update).await;
+                            if let Some(name) = loaded {
+                                core.set_table_origin(&name, TableOrigin::file(&path)).await;
+                            }
                         }
                     }
                 }
@@ -93,17 +137,64 @@ pub async fn load_and_watch(
     core: Arc<ServerCore>,
     paths: Vec<PathBuf>,
 ) -> notify::Result<FileWatcher> {
-    // Load initial files (load_file is async, uses spawn_blocking internally)
-    let files = crate::loader::collect_files(&paths);
+    load_and_watch_with_policy(core, paths, ReloadPolicy::default()).await
+}
+
+/// Load initial files and start watching for changes, retrying failed loads per `policy`
+pub async fn load_and_watch_with_policy(
+    core: Arc<ServerCore>,
+    paths: Vec<PathBuf>,
+    policy: ReloadPolicy,
+) -> notify::Result<FileWatcher> {
+    // Load initial files (load_file_with_retry uses spawn_blocking internally)
+    let mut files = crate::loader::collect_files(&paths);
+    files.retain(|path| !policy.is_ignored(path));
+    files.extend(collect_datasets(&paths));
     for path in files {
-        if let Ok(df) = load_file(&path).await {
-            let name = df_name_from_path(&path);
-            core.insert_df(name, df).await;
+        let name = df_name_from_path(&path);
+        if policy.load.lazy {
+            match scan_file_with_options(&path, &policy.load).await {
+                Ok(scan) => core.insert_scan(name.clone(), scan).await,
+                Err(e) => {
+                    log::error!("Failed to scan {}: {e}", path.display());
+                    continue;
+                }
+            }
+        } else if let Ok(df) =
+            load_file_with_retry(&path, policy.retry_for(&path), &policy.load).await
+        {
+            core.insert_df(name.clone(), df).await;
+        } else {
+            continue;
         }
+        core.set_table_origin(&name, TableOrigin::file(&path)).await;
     }
 
     // Start watching
-    FileWatcher::new(core, paths)
+    FileWatcher::with_policy(core, paths, policy)
+}
+
+/// The update registering changed file `path` as table `name`: a new scan with
+/// [`LoadOptions::lazy`](crate::loader::LoadOptions::lazy), else its loaded rows
+/// (just the new ones if the file only grew)
+async fn load_update(
+    core: &ServerCore,
+    name: String,
+    path: &Path,
+    policy: &ReloadPolicy,
+) -> Result<DfUpdate, PolarsError> {
+    if policy.load.lazy {
+        let scan = scan_file_with_options(path, &policy.load)
+            .await
+            .inspect_err(|e| log::error!("Failed to scan {}: {e}", path.display()))?;
+        return Ok(DfUpdate::Scan {
+            name,
+            scan: Box::new(scan),
+        });
+    }
+    // load_file_with_retry uses spawn_blocking internally
+    let df = load_file_with_retry(path, policy.retry_for(path), &policy.load).await?;
+    Ok(core.reload_update(name, df).await)
 }
 
 // ============ Run Watcher ============
@@ -114,10 +205,12 @@ enum RunEvent {
     Ready(PathBuf),
     /// A subdirectory was removed
     Removed(PathBuf),
+    /// A data file in this run directory was written or deleted
+    Changed(PathBuf),
 }
 
-/// Watches a parent directory for run subdirectories (sentinel-based).
-/// Owns the RunRegistry — sole mutator, no locking needed.
+/// Watches a parent directory for run subdirectories, loading and unloading them
+/// through [`ServerCore::runs`].
 pub struct RunWatcher {
     _watcher: RecommendedWatcher,
 }
@@ -125,45 +218,32 @@ pub struct RunWatcher {
 #[derive(Debug, Clone, Default)]
 pub struct RunModeOptions {
     pub drop_existing_run_label_column: bool,
+    /// Treat every subdirectory holding data files as a run (`runs/<run>/<table>.parquet`)
+    /// and (re)load it once its files have been unchanged this long. `None` loads a
+    /// run only when its `_ready` sentinel appears.
+    pub settle: Option<Duration>,
 }
 
-/// Load all parquet files from a run directory into the registry.
-async fn load_run_dir(
-    registry: &mut RunRegistry,
-    run_name: &str,
-    run_dir: &std::path::Path,
-    core: &ServerCore,
-) {
-    let run_dir_owned = run_dir.to_path_buf();
-    let tables = match tokio::task::spawn_blocking(move || {
-        let files = collect_files(&[run_dir_owned]);
-        let mut tables = std::collections::HashMap::new();
-        for path in files {
-            match load_file_sync(&path) {
-                Ok(df) => {
-                    let name = df_name_from_path(&path);
-                    tables.insert(name, df);
-                }
-                Err(e) => {
-                    log::warn!("Failed to load {}: {}", path.display(), e);
-                }
-            }
-        }
-        tables
-    })
-    .await
-    {
-        Ok(tables) if !tables.is_empty() => tables,
-        Ok(_) => {
-            log::warn!("Run '{}' has no loadable tables, skipping", run_name);
-            return;
-        }
-        Err(e) => {
-            log::error!("Failed to load run '{}': {}", run_name, e);
-            return;
-        }
-    };
+/// The run directory (direct child of `parent`) containing `path`
+fn run_dir_of(parent: &Path, path: &Path) -> Option<PathBuf> {
+    let first = path.strip_prefix(parent).ok()?.components().next()?;
+    Some(parent.join(first))
+}
 
+/// Whether a directory holds any loadable table
+fn has_data_files(dir: &Path) -> bool {
+    !collect_files(&[dir.to_path_buf()]).is_empty()
+}
+
+/// Load all table files from a run directory into the registry.
+async fn load_run_dir(run_name: &str, run_dir: &Path, core: &ServerCore) {
+    let tables = read_run_dir(run_dir).await;
+    if tables.is_empty() {
+        log::warn!("Run '{}' has no loadable tables, skipping", run_name);
+        return;
+    }
+
+    let mut registry = core.runs().lock().await;
     if let Err(err) = registry.load_run(run_name, tables, core).await {
         log::error!("Failed to load run '{}': {}", run_name, err);
     }
@@ -175,17 +255,23 @@ pub async fn load_and_watch_runs(
     parent: PathBuf,
     options: RunModeOptions,
 ) -> notify::Result<RunWatcher> {
-    let mut registry = RunRegistry::with_options(RunRegistryOptions {
+    core.runs().lock().await.set_options(RunRegistryOptions {
         drop_existing_run_label_column: options.drop_existing_run_label_column,
+        dir: Some(parent.clone()),
         ..Default::default()
     });
 
-    // Scan for existing run subdirs with _ready sentinel
+    // Scan for existing run subdirs: with a _ready sentinel, or any with data when settling
     let mut run_dirs: Vec<_> = std::fs::read_dir(&parent)
         .into_iter()
         .flatten()
         .flatten()
-        .filter(|entry| entry.path().is_dir() && entry.path().join("_ready").exists())
+        .filter(|entry| {
+            let path = entry.path();
+            path.is_dir()
+                && (path.join("_ready").exists()
+                    || (options.settle.is_some() && has_data_files(&path)))
+        })
         .collect();
 
     // Sort by name (timestamp prefix → chronological order)
@@ -196,23 +282,21 @@ pub async fn load_and_watch_runs(
         let Some(run_name) = run_dir.file_name().and_then(|f| f.to_str()) else {
             continue;
         };
-        load_run_dir(&mut registry, run_name, &run_dir, &core).await;
+        load_run_dir(run_name, &run_dir, &core).await;
     }
 
-    // Now start watching — but we need to hand off the registry to the watcher.
-    // The watcher creates its own registry, so we need a different approach:
-    // we build the watcher with pre-loaded registry.
-    RunWatcher::with_registry(core, parent, registry)
+    RunWatcher::watch(core, parent, options.settle)
 }
 
 impl RunWatcher {
-    fn with_registry(
+    fn watch(
         core: Arc<ServerCore>,
         parent: PathBuf,
-        initial_registry: RunRegistry,
+        settle: Option<Duration>,
     ) -> notify::Result<Self> {
         let (tx, mut rx) = mpsc::channel::<RunEvent>(100);
 
+        let watched = parent.clone();
         let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| {
             if let Ok(event) = res {
                 for path in &event.paths {
@@ -230,6 +314,17 @@ impl RunWatcher {
                     if event.kind == EventKind::Remove(notify::event::RemoveKind::Folder) {
                         let _ = tx.blocking_send(RunEvent::Removed(path.clone()));
                     }
+                    let is_data_change = matches!(
+                        event.kind,
+                        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+                    ) && is_supported_file(path);
+                    if settle.is_some()
+                        && is_data_change
+                        && let Some(run_dir) = run_dir_of(&watched, path)
+                        && run_dir != *path
+                    {
+                        let _ = tx.blocking_send(RunEvent::Changed(run_dir));
+                    }
                 }
             }
         })?;
@@ -237,9 +332,35 @@ impl RunWatcher {
         watcher.watch(&parent, RecursiveMode::Recursive)?;
 
         tokio::spawn(async move {
-            let mut registry = initial_registry;
+            // Run directory → when its files last changed
+            let mut pending: HashMap<PathBuf, Instant> = HashMap::new();
+
+            loop {
+                let next_settled = settle
+                    .and_then(|settle| pending.values().min().map(|changed| *changed + settle));
+                let event = tokio::select! {
+                    event = rx.recv() => match event {
+                        Some(event) => event,
+                        None => break,
+                    },
+                    _ = tokio::time::sleep_until(next_settled.unwrap_or_else(Instant::now)),
+                        if next_settled.is_some() =>
+                    {
+                        let settle = settle.unwrap_or_default();
+                        let now = Instant::now();
+                        let settled: Vec<PathBuf> = pending
+                            .iter()
+                            .filter(|(_, changed)| now.duration_since(**changed) >= settle)
+                            .map(|(dir, _)| dir.clone())
+                            .collect();
+                        for run_dir in settled {
+                            pending.remove(&run_dir);
+                            reload_settled_run(&run_dir, &core).await;
+                        }
+                        continue;
+                    }
+                };
 
-            while let Some(event) = rx.recv().await {
                 match event {
                     RunEvent::Ready(sentinel_path) => {
                         let Some(run_dir) = sentinel_path.parent() else {
@@ -248,7 +369,7 @@ impl RunWatcher {
                         let Some(run_name) = run_dir.file_name().and_then(|f| f.to_str()) else {
                             continue;
                         };
-                        load_run_dir(&mut registry, run_name, run_dir, &core).await;
+                        load_run_dir(run_name, run_dir, &core).await;
                     }
                     RunEvent::Removed(path) => {
                         let dir = if path.file_name().and_then(|f| f.to_str()) == Some("_ready") {
@@ -259,7 +380,11 @@ impl RunWatcher {
                         let Some(run_name) = dir.file_name().and_then(|f| f.to_str()) else {
                             continue;
                         };
-                        registry.remove_run(run_name, &core).await;
+                        pending.remove(dir);
+                        core.runs().lock().await.remove_run(run_name, &core).await;
+                    }
+                    RunEvent::Changed(run_dir) => {
+                        pending.insert(run_dir, Instant::now());
                     }
                 }
             }
@@ -268,3 +393,77 @@ impl RunWatcher {
         Ok(Self { _watcher: watcher })
     }
 }
+
+/// Bring the registry in line with a run directory whose files stopped changing:
+/// unregister it if it's gone or empty, otherwise (re)load all of its tables.
+async fn reload_settled_run(run_dir: &Path, core: &ServerCore) {
+    let Some(run_name) = run_dir.file_name().and_then(|f| f.to_str()) else {
+        return;
+    };
+    core.runs().lock().await.remove_run(run_name, core).await;
+    if run_dir.is_dir() && has_data_files(run_dir) {
+        load_run_dir(run_name, run_dir, core).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::prelude::{ParquetWriter, df};
+
+    fn write_table(path: &Path, values: &[i64]) {
+        let mut df = df! { "x" => values }.unwrap();
+        let mut file = std::fs::File::create(path).unwrap();
+        ParquetWriter::new(&mut file).finish(&mut df).unwrap();
+    }
+
+    async fn wait_for(core: &ServerCore, check: impl Fn(&[String]) -> bool) -> bool {
+        for _ in 0..100 {
+            if check(&core.list_dataframes().await) {
+                return true;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        false
+    }
+
+    #[test]
+    fn run_dir_is_the_child_of_parent() {
+        let parent = Path::new("/data/runs");
+        assert_eq!(
+            run_dir_of(parent, Path::new("/data/runs/r1/ticks.parquet")),
+            Some(PathBuf::from("/data/runs/r1"))
+        );
+        assert_eq!(run_dir_of(parent, Path::new("/elsewhere/t.parquet")), None);
+        assert_eq!(run_dir_of(parent, parent), None);
+    }
+
+    #[tokio::test]
+    async fn settled_run_dirs_load_without_sentinel() {
+        let parent = std::env::temp_dir().join(format!("piql-runs-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&parent);
+        std::fs::create_dir_all(parent.join("r1")).unwrap();
+        write_table(&parent.join("r1/ticks.parquet"), &[1]);
+
+        let core = Arc::new(ServerCore::new());
+        let options = RunModeOptions {
+            settle: Some(Duration::from_millis(100)),
+            ..Default::default()
+        };
+        let _watcher = load_and_watch_runs(core.clone(), parent.clone(), options)
+            .await
+            .unwrap();
+        assert!(wait_for(&core, |names| names.iter().any(|n| n == "r1::ticks")).await);
+
+        std::fs::create_dir_all(parent.join("r2")).unwrap();
+        write_table(&parent.join("r2/ticks.parquet"), &[2, 3]);
+        assert!(wait_for(&core, |names| names.iter().any(|n| n == "r2::ticks")).await);
+        assert_eq!(core.execute_query("ticks").await.unwrap().height(), 2);
+
+        std::fs::remove_dir_all(parent.join("r2")).unwrap();
+        assert!(wait_for(&core, |names| !names.iter().any(|n| n == "r2::ticks")).await);
+        assert_eq!(core.execute_query("ticks").await.unwrap().height(), 1);
+
+        let _ = std::fs::remove_dir_all(&parent);
+    }
+}
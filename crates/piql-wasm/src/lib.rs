@@ -0,0 +1,109 @@
+//! WebAssembly bindings for browser editors
+//!
+//! Client-side parse errors, validation and formatting, without a round-trip to the
+//! server. Built on piql without its `eval` feature, so no Polars is linked:
+//!
+//! ```js
+//! import init, { parse_check, format } from "piql-wasm";
+//!
+//! await init();
+//! for (const d of parse_check("entities.filter($gold >")) {
+//!     console.log(d.message, d.offset);
+//! }
+//! format("entities .filter( $gold>100 )", 80); // "entities.filter($gold > 100)"
+//! ```
+//!
+//! Validation only knows the built-in method surface: directives, pipeline directives
+//! and functions registered on the server are accepted as-is.
+
+use wasm_bindgen::prelude::*;
+
+/// A problem found in a query
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub message: String,
+    /// Position of a parse error, in UTF-16 code units into the query (as JS string
+    /// indices). Validation errors have no position.
+    pub offset: Option<usize>,
+    /// Method a validation error is about
+    pub method: Option<String>,
+}
+
+/// Parse and validate `query`. Returns every parse error, or if it parses, every
+/// unknown method and invalid argument list. Empty when the query is fine.
+#[wasm_bindgen]
+pub fn parse_check(query: &str) -> Vec<Diagnostic> {
+    let parsed = piql::advanced::parse_recovering(query);
+    if !parsed.diagnostics.is_empty() {
+        return parsed
+            .diagnostics
+            .into_iter()
+            .map(|err| parse_diagnostic(query, err))
+            .collect();
+    }
+    match piql::validate(query) {
+        Ok(errors) => errors
+            .into_iter()
+            .map(|err| Diagnostic {
+                message: err.message,
+                offset: None,
+                method: Some(err.method),
+            })
+            .collect(),
+        Err(err) => vec![parse_diagnostic(query, err)],
+    }
+}
+
+/// Pretty-print `query` in canonical form, breaking method chains longer than
+/// `width`. Throws on a parse error.
+#[wasm_bindgen]
+pub fn format(query: &str, width: usize) -> Result<String, JsError> {
+    piql::format(query, width).map_err(|err| JsError::new(&err.to_string()))
+}
+
+fn parse_diagnostic(query: &str, err: piql::ParseError) -> Diagnostic {
+    Diagnostic {
+        offset: Some(js_offset(query, err.offset)),
+        message: err.message,
+        method: None,
+    }
+}
+
+/// Convert a byte offset into the trimmed query (as the parser reports it) to a
+/// UTF-16 offset into `query`
+fn js_offset(query: &str, offset: usize) -> usize {
+    let leading = query.len() - query.trim_start().len();
+    let end = (leading + offset).min(query.len());
+    query[..end].encode_utf16().count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_errors_have_js_offsets() {
+        assert_eq!(parse_check(r#"entities.filter($gold > 100)"#), []);
+
+        // Missing operand, then the unclosed argument list, both at the end
+        let diagnostics = parse_check("  entities.filter(\"é\" ==");
+        assert_eq!(diagnostics.len(), 2, "{diagnostics:?}");
+        // Two leading spaces, and "é" is one UTF-16 unit but two bytes
+        assert!(diagnostics.iter().all(|d| d.offset == Some(24)));
+        assert!(diagnostics.iter().all(|d| d.method.is_none()));
+    }
+
+    #[test]
+    fn validation_errors_name_the_method() {
+        let diagnostics = parse_check("entities.hed(5)");
+        assert_eq!(
+            diagnostics,
+            [Diagnostic {
+                message: "Unknown method .hed()".to_string(),
+                offset: None,
+                method: Some("hed".to_string()),
+            }]
+        );
+    }
+}
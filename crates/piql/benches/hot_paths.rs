@@ -13,16 +13,12 @@ fn large_eval_context() -> EvalContext {
     EvalContext::new().with_df("t", df)
 }
 
-fn seeded_engine() -> QueryEngine {
+fn seeded_engine(incremental: bool) -> QueryEngine {
     let mut engine = QueryEngine::new();
-    engine.register_base(
-        "events",
-        TimeSeriesConfig {
-            tick_column: "tick".into(),
-            partition_key: "entity_id".into(),
-        },
-    );
+    engine.set_incremental(incremental);
+    engine.register_base("events", TimeSeriesConfig::new("tick", "entity_id"));
     engine.subscribe("report", r#"events.window(-2, 0).filter($value > 10)"#);
+    engine.subscribe("latest", r#"events.filter($value > 10)"#);
     engine.set_tick(100);
 
     for tick in 95..=100 {
@@ -58,19 +54,51 @@ fn bench_compiled_query(c: &mut Criterion) {
 }
 
 fn bench_engine_tick(c: &mut Criterion) {
-    let mut engine = seeded_engine();
+    let mut engine = seeded_engine(false);
 
     c.bench_function("query_engine_on_tick", |b| {
         b.iter(|| {
             let _ = engine.on_tick(black_box(100)).unwrap();
         })
     });
+
+    let mut engine = seeded_engine(true);
+    c.bench_function("query_engine_on_tick_incremental", |b| {
+        b.iter(|| {
+            let _ = engine.on_tick(black_box(100)).unwrap();
+        })
+    });
+}
+
+fn bench_engine_tick_parallel(c: &mut Criterion) {
+    let mut engine = seeded_engine(false);
+    for i in 0..48 {
+        engine.subscribe(
+            format!("threshold_{i}"),
+            format!("events.window(-5, 0).filter($value > {i})"),
+        );
+    }
+
+    engine.set_parallelism(1);
+    c.bench_function("query_engine_on_tick_50_subscriptions_serial", |b| {
+        b.iter(|| {
+            let _ = engine.on_tick(black_box(100)).unwrap();
+        })
+    });
+
+    engine.set_parallelism(8);
+    c.bench_function("query_engine_on_tick_50_subscriptions_parallel", |b| {
+        b.iter(|| {
+            let _ = engine.on_tick(black_box(100)).unwrap();
+        })
+    });
 }
 
 criterion_group!(
     hot_paths,
     bench_run_filter,
     bench_compiled_query,
-    bench_engine_tick
+    bench_engine_tick,
+    bench_engine_tick_parallel
 );
 criterion_main!(hot_paths);
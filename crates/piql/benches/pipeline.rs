@@ -0,0 +1,68 @@
+use criterion::{BatchSize, Criterion, black_box, criterion_group, criterion_main};
+use piql::EvalContext;
+use piql::advanced::{eval, parse, transform};
+use polars::df;
+use polars::prelude::IntoLazy;
+
+const QUERIES: [(&str, &str); 3] = [
+    ("filter", "t.filter($x > 1000)"),
+    (
+        "chain",
+        r#"t.filter(($x > 1000) & ($y < 15000)).with_columns(($y / 2).alias("z")).sort("z", descending=True).head(100)"#,
+    ),
+    (
+        "aggregate",
+        r#"t.with_columns(($x % 10).alias("bucket")).group_by("bucket").agg($y.mean().alias("mean_y"), $x.max())"#,
+    ),
+];
+
+fn eval_context() -> EvalContext {
+    let df = df! {
+        "x" => (0..10_000).collect::<Vec<i32>>(),
+        "y" => (0..10_000).map(|n| n * 2).collect::<Vec<i32>>(),
+    }
+    .unwrap()
+    .lazy();
+    EvalContext::new().with_df("t", df)
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for (name, query) in QUERIES {
+        group.bench_function(name, |b| b.iter(|| parse(black_box(query)).unwrap()));
+    }
+    group.finish();
+}
+
+fn bench_transform(c: &mut Criterion) {
+    let mut group = c.benchmark_group("transform");
+    for (name, query) in QUERIES {
+        let surface = parse(query).unwrap();
+        group.bench_function(name, |b| {
+            b.iter_batched(|| surface.clone(), transform, BatchSize::SmallInput)
+        });
+    }
+    group.finish();
+}
+
+fn bench_eval(c: &mut Criterion) {
+    let ctx = eval_context();
+    let mut group = c.benchmark_group("eval");
+    for (name, query) in QUERIES {
+        let core = transform(parse(query).unwrap());
+        // Building the lazy plan, without collecting
+        group.bench_function(format!("{name}_plan"), |b| {
+            b.iter(|| eval(black_box(&core), black_box(&ctx)).unwrap())
+        });
+        group.bench_function(format!("{name}_collect"), |b| {
+            b.iter(|| match eval(black_box(&core), black_box(&ctx)).unwrap() {
+                piql::Value::DataFrame(lf, _) => lf.collect().unwrap(),
+                _ => unreachable!("query returns a DataFrame"),
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(pipeline, bench_parse, bench_transform, bench_eval);
+criterion_main!(pipeline);
@@ -0,0 +1,95 @@
+//! Saved query snippets
+//!
+//! `!name` in a query is replaced with the parsed text of the alias defined under
+//! `name` before parameters are bound and sugar is expanded. Aliases may reference
+//! other aliases; a reference back to an alias that is still being expanded is an
+//! error.
+
+use std::collections::HashMap;
+
+use crate::PiqlError;
+use crate::ast::surface::{Expr, SurfaceArg};
+
+/// Query text by alias name (without the leading `!`)
+pub type Aliases = HashMap<String, String>;
+
+/// Replace every `!name` with the query its alias stands for
+pub(crate) fn expand(expr: Expr, aliases: &Aliases) -> Result<Expr, PiqlError> {
+    expand_inner(expr, aliases, &mut Vec::new())
+}
+
+fn expand_inner(expr: Expr, aliases: &Aliases, stack: &mut Vec<String>) -> Result<Expr, PiqlError> {
+    Ok(match expr {
+        Expr::Alias(name) => {
+            if stack.contains(&name) {
+                let mut cycle = stack.clone();
+                cycle.push(name);
+                return Err(PiqlError::AliasCycle(format!("!{}", cycle.join(" -> !"))));
+            }
+            let Some(query) = aliases.get(&name) else {
+                return Err(PiqlError::UnknownAlias(name));
+            };
+            let body = crate::parse::parse(query)?;
+            stack.push(name);
+            let expanded = expand_inner(body, aliases, stack)?;
+            stack.pop();
+            expanded
+        }
+        Expr::Ident(_)
+        | Expr::Literal(_)
+        | Expr::ColShorthand(_)
+        | Expr::Param(_)
+        | Expr::Error => expr,
+        Expr::List(items) => Expr::List(
+            items
+                .into_iter()
+                .map(|e| expand_inner(e, aliases, stack))
+                .collect::<Result<_, _>>()?,
+        ),
+        Expr::Attr(base, name) => Expr::Attr(Box::new(expand_inner(*base, aliases, stack)?), name),
+        Expr::Call(callee, args) => Expr::Call(
+            Box::new(expand_inner(*callee, aliases, stack)?),
+            expand_args(args, aliases, stack)?,
+        ),
+        Expr::BinaryOp(lhs, op, rhs) => Expr::BinaryOp(
+            Box::new(expand_inner(*lhs, aliases, stack)?),
+            op,
+            Box::new(expand_inner(*rhs, aliases, stack)?),
+        ),
+        Expr::UnaryOp(op, inner) => {
+            Expr::UnaryOp(op, Box::new(expand_inner(*inner, aliases, stack)?))
+        }
+        Expr::Commented {
+            expr,
+            leading,
+            trailing,
+        } => Expr::Commented {
+            expr: Box::new(expand_inner(*expr, aliases, stack)?),
+            leading,
+            trailing,
+        },
+        Expr::Directive(name, args) => Expr::Directive(name, expand_args(args, aliases, stack)?),
+        Expr::PipelineDirective(base, name, args) => Expr::PipelineDirective(
+            Box::new(expand_inner(*base, aliases, stack)?),
+            name,
+            expand_args(args, aliases, stack)?,
+        ),
+    })
+}
+
+fn expand_args(
+    args: Vec<SurfaceArg>,
+    aliases: &Aliases,
+    stack: &mut Vec<String>,
+) -> Result<Vec<SurfaceArg>, PiqlError> {
+    args.into_iter()
+        .map(|arg| match arg {
+            SurfaceArg::Positional(e) => {
+                expand_inner(e, aliases, stack).map(SurfaceArg::Positional)
+            }
+            SurfaceArg::Keyword(k, e) => {
+                expand_inner(e, aliases, stack).map(|e| SurfaceArg::Keyword(k, e))
+            }
+        })
+        .collect()
+}
@@ -9,6 +9,12 @@ pub mod surface;
 
 // Shared types used by both surface and core ASTs
 
+/// Identifiers naming a function namespace (`pl.col`, `cs.numeric`) rather than a table
+#[cfg(feature = "eval")]
+pub(crate) fn is_namespace_ident(name: &str) -> bool {
+    matches!(name, "pl" | "cs")
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Literal {
     String(String),
@@ -42,6 +48,7 @@ pub enum BinOp {
     Mul,
     Div,
     Mod,
+    FloorDiv,
 
     // Comparison
     Eq,
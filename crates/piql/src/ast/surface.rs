@@ -38,6 +38,27 @@ pub enum Expr {
 
     /// Directive: `@merchant`, `@entity(42)`
     Directive(String, Vec<SurfaceArg>),
+
+    /// Pipeline directive in method position: `entities.@latest_per($entity_id)`
+    PipelineDirective(Box<Expr>, String, Vec<SurfaceArg>),
+
+    /// Named placeholder: `:threshold`, bound by `run_with_params`
+    Param(String),
+
+    /// Saved query snippet: `!rich_merchants`, defined with `define_alias`
+    Alias(String),
+
+    /// Placeholder for input that failed to parse (only from `parse_recovering`)
+    Error,
+
+    /// `# comment` lines attached to an expression (only from `parse_with_comments`).
+    /// Leading comments precede the expression, trailing ones follow it. Text excludes
+    /// the `#`.
+    Commented {
+        expr: Box<Expr>,
+        leading: Vec<String>,
+        trailing: Vec<String>,
+    },
 }
 
 impl Expr {
@@ -52,4 +73,94 @@ impl Expr {
     pub fn binop(self, op: BinOp, rhs: Expr) -> Self {
         Expr::BinaryOp(Box::new(self), op, Box::new(rhs))
     }
+
+    /// The expression with any attached comments removed
+    pub fn uncommented(&self) -> &Expr {
+        match self {
+            Expr::Commented { expr, .. } => expr.uncommented(),
+            other => other,
+        }
+    }
+
+    /// Drop all comments from the tree
+    pub fn strip_comments(self) -> Self {
+        let strip_args = |args: Vec<SurfaceArg>| {
+            args.into_iter()
+                .map(|arg| match arg {
+                    Arg::Positional(e) => Arg::Positional(e.strip_comments()),
+                    Arg::Keyword(k, e) => Arg::Keyword(k, e.strip_comments()),
+                })
+                .collect()
+        };
+        match self {
+            Expr::Commented { expr, .. } => expr.strip_comments(),
+            Expr::Ident(_)
+            | Expr::Literal(_)
+            | Expr::ColShorthand(_)
+            | Expr::Param(_)
+            | Expr::Alias(_)
+            | Expr::Error => self,
+            Expr::List(items) => Expr::List(items.into_iter().map(Expr::strip_comments).collect()),
+            Expr::Attr(base, name) => base.strip_comments().attr(name),
+            Expr::Call(callee, args) => callee.strip_comments().call(strip_args(args)),
+            Expr::BinaryOp(lhs, op, rhs) => lhs.strip_comments().binop(op, rhs.strip_comments()),
+            Expr::UnaryOp(op, inner) => Expr::UnaryOp(op, Box::new(inner.strip_comments())),
+            Expr::Directive(name, args) => Expr::Directive(name, strip_args(args)),
+            Expr::PipelineDirective(base, name, args) => {
+                Expr::PipelineDirective(Box::new(base.strip_comments()), name, strip_args(args))
+            }
+        }
+    }
+
+    /// Attach comments that precede this expression
+    pub(crate) fn with_leading_comments(self, mut comments: Vec<String>) -> Self {
+        if comments.is_empty() {
+            return self;
+        }
+        match self {
+            Expr::Commented {
+                expr,
+                leading,
+                trailing,
+            } => {
+                comments.extend(leading);
+                Expr::Commented {
+                    expr,
+                    leading: comments,
+                    trailing,
+                }
+            }
+            expr => Expr::Commented {
+                expr: Box::new(expr),
+                leading: comments,
+                trailing: Vec::new(),
+            },
+        }
+    }
+
+    /// Attach comments that follow this expression
+    pub(crate) fn with_trailing_comments(self, comments: Vec<String>) -> Self {
+        if comments.is_empty() {
+            return self;
+        }
+        match self {
+            Expr::Commented {
+                expr,
+                leading,
+                mut trailing,
+            } => {
+                trailing.extend(comments);
+                Expr::Commented {
+                    expr,
+                    leading,
+                    trailing,
+                }
+            }
+            expr => Expr::Commented {
+                expr: Box::new(expr),
+                leading: Vec::new(),
+                trailing: comments,
+            },
+        }
+    }
 }
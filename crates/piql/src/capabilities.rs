@@ -0,0 +1,292 @@
+//! Capability discovery: the method surface supported by the evaluator
+//!
+//! These tables mirror the dispatch functions in eval.rs (`eval_df_method`,
+//! `eval_expr_method`, `eval_str_method`, ...). When adding a method there, add it
+//! here too; the integration tests check that every entry actually dispatches.
+
+/// Receiver a method is called on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Namespace {
+    /// `pl.<fn>(...)`
+    Pl,
+    /// `df.<method>(...)`
+    DataFrame,
+    /// `df.group_by(...).<method>(...)`
+    GroupBy,
+    /// `expr.<method>(...)`
+    Expr,
+    /// `expr.str.<method>(...)`
+    Str,
+    /// `expr.dt.<method>(...)`
+    Dt,
+    /// `expr.struct.<method>(...)`
+    Struct,
+    /// `cs.<selector>(...)`
+    Selectors,
+}
+
+impl Namespace {
+    pub const ALL: [Namespace; 8] = [
+        Namespace::Pl,
+        Namespace::DataFrame,
+        Namespace::GroupBy,
+        Namespace::Expr,
+        Namespace::Str,
+        Namespace::Dt,
+        Namespace::Struct,
+        Namespace::Selectors,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Namespace::Pl => "pl",
+            Namespace::DataFrame => "DataFrame",
+            Namespace::GroupBy => "GroupBy",
+            Namespace::Expr => "Expr",
+            Namespace::Str => "str",
+            Namespace::Dt => "dt",
+            Namespace::Struct => "struct",
+            Namespace::Selectors => "cs",
+        }
+    }
+
+    /// Methods supported on this receiver
+    pub fn methods(&self) -> &'static [MethodSpec] {
+        match self {
+            Namespace::Pl => PL_FUNCTIONS,
+            Namespace::DataFrame => DATAFRAME_METHODS,
+            Namespace::GroupBy => GROUPBY_METHODS,
+            Namespace::Expr => EXPR_METHODS,
+            Namespace::Str => STR_METHODS,
+            Namespace::Dt => DT_METHODS,
+            Namespace::Struct => STRUCT_METHODS,
+            Namespace::Selectors => SELECTORS,
+        }
+    }
+}
+
+/// Signature of a single method
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MethodSpec {
+    pub name: &'static str,
+    /// Minimum number of positional arguments
+    pub min_args: usize,
+    /// Maximum number of positional arguments (`None` = variadic)
+    pub max_args: Option<usize>,
+    /// Accepted keyword arguments; `"*"` means arbitrary names (e.g. `rename(old="new")`)
+    pub kwargs: &'static [&'static str],
+}
+
+const fn m(
+    name: &'static str,
+    min_args: usize,
+    max_args: Option<usize>,
+    kwargs: &'static [&'static str],
+) -> MethodSpec {
+    MethodSpec {
+        name,
+        min_args,
+        max_args,
+        kwargs,
+    }
+}
+
+const NONE: &[&str] = &[];
+const STRPTIME_KWARGS: &[&str] = &["format", "strict"];
+const SCOPE_KWARGS: &[&str] = &["partial", "tick_col", "on"];
+
+pub const PL_FUNCTIONS: &[MethodSpec] = &[
+    m("col", 1, None, NONE),
+    m("lit", 1, Some(1), NONE),
+    m("len", 0, Some(0), NONE),
+    m("all", 0, Some(0), NONE),
+    m("exclude", 1, None, NONE),
+    m("coalesce", 1, None, NONE),
+    m("sum_horizontal", 1, None, NONE),
+    m("max_horizontal", 1, None, NONE),
+    m("min_horizontal", 1, None, NONE),
+    m("concat_str", 1, None, &["separator", "ignore_nulls"]),
+    m("when", 1, Some(1), NONE),
+];
+
+pub const DATAFRAME_METHODS: &[MethodSpec] = &[
+    m("filter", 1, Some(1), NONE),
+    m("select", 1, None, NONE),
+    m("with_columns", 1, None, NONE),
+    m("head", 0, Some(1), NONE),
+    m("tail", 0, Some(1), NONE),
+    m(
+        "sample",
+        0,
+        Some(1),
+        &["n", "fraction", "seed", "with_replacement"],
+    ),
+    m("shuffle", 0, Some(1), &["seed"]),
+    m("sort", 1, Some(1), &["descending"]),
+    m("drop", 1, None, NONE),
+    m("explode", 1, None, NONE),
+    m("unnest", 1, None, NONE),
+    m("drop_nulls", 0, Some(0), NONE),
+    m("reverse", 0, Some(0), NONE),
+    m("unique", 0, Some(1), NONE),
+    m("count", 0, Some(0), NONE),
+    m("height", 0, Some(0), NONE),
+    m("group_by", 1, None, NONE),
+    m("by_run", 0, Some(0), NONE),
+    m(
+        "group_by_dynamic",
+        1,
+        Some(3),
+        &[
+            "index_column",
+            "every",
+            "period",
+            "offset",
+            "closed",
+            "group_by",
+            "include_boundaries",
+        ],
+    ),
+    m("rename", 0, Some(2), &["*"]),
+    m("all", 0, Some(0), NONE),
+    m("window", 2, Some(2), SCOPE_KWARGS),
+    m("since", 1, Some(1), SCOPE_KWARGS),
+    m("at", 1, Some(1), SCOPE_KWARGS),
+    m("as_of", 1, Some(1), NONE),
+    m("latest", 0, Some(0), &["tick_col", "on"]),
+    m("last_ticks", 1, Some(1), &["tick_col", "on"]),
+    m("resample", 1, Some(1), &["agg", "tick_col", "on"]),
+    m("top", 2, Some(2), NONE),
+    m("describe", 0, Some(0), NONE),
+    m("explain", 0, Some(0), &["optimized"]),
+    m(
+        "join",
+        1,
+        Some(1),
+        &["how", "on", "left_on", "right_on", "suffix"],
+    ),
+    m(
+        "join_asof",
+        1,
+        Some(1),
+        &["on", "by", "strategy", "tolerance"],
+    ),
+];
+
+pub const GROUPBY_METHODS: &[MethodSpec] = &[
+    m("agg", 1, None, NONE),
+    m("len", 0, Some(0), NONE),
+    m("count", 0, Some(0), NONE),
+    m("sum", 0, Some(0), NONE),
+    m("mean", 0, Some(0), NONE),
+    m("max", 0, Some(0), NONE),
+    m("min", 0, Some(0), NONE),
+];
+
+pub const EXPR_METHODS: &[MethodSpec] = &[
+    m("alias", 1, Some(1), NONE),
+    m("over", 1, Some(1), NONE),
+    m("by_run", 0, Some(0), NONE),
+    m("is_between", 2, Some(2), NONE),
+    m("diff", 0, Some(0), NONE),
+    m("shift", 1, Some(1), NONE),
+    m("sum", 0, Some(0), NONE),
+    m("mean", 0, Some(0), NONE),
+    m("std", 0, Some(0), &["ddof"]),
+    m("var", 0, Some(0), &["ddof"]),
+    m("min", 0, Some(0), NONE),
+    m("max", 0, Some(0), NONE),
+    m("count", 0, Some(0), NONE),
+    m("first", 0, Some(0), NONE),
+    m("last", 0, Some(0), NONE),
+    m("cast", 1, Some(1), &["strict"]),
+    m("fill_null", 0, Some(1), &["strategy", "limit"]),
+    m("is_null", 0, Some(0), NONE),
+    m("exclude", 1, None, NONE),
+    m("is_in", 1, Some(1), &["nulls_equal"]),
+    m("is_duplicated", 0, Some(0), NONE),
+    m("is_first_distinct", 0, Some(0), NONE),
+    m("any", 0, Some(0), &["ignore_nulls"]),
+    m("all", 0, Some(0), &["ignore_nulls"]),
+    m("is_not_null", 0, Some(0), NONE),
+    m("unique", 0, Some(0), NONE),
+    m("abs", 0, Some(0), NONE),
+    m("pow", 1, Some(1), NONE),
+    m("sqrt", 0, Some(0), NONE),
+    m("log", 0, Some(1), &["base"]),
+    m("exp", 0, Some(0), NONE),
+    m("floor", 0, Some(0), NONE),
+    m("ceil", 0, Some(0), NONE),
+    m("sign", 0, Some(0), NONE),
+    m("round", 1, Some(1), NONE),
+    m("len", 0, Some(0), NONE),
+    m("n_unique", 0, Some(0), NONE),
+    m("cum_sum", 0, Some(0), NONE),
+    m("cum_max", 0, Some(0), NONE),
+    m("cum_min", 0, Some(0), NONE),
+    m("rank", 0, Some(0), NONE),
+    m("div_or", 2, Some(2), NONE),
+    m("nan_to_null", 0, Some(0), NONE),
+    m("fill_nan", 1, Some(1), NONE),
+    m("is_nan", 0, Some(0), NONE),
+    m("drop_nans", 0, Some(0), NONE),
+    m("clip", 2, Some(2), NONE),
+    m("reverse", 0, Some(0), NONE),
+];
+
+pub const STR_METHODS: &[MethodSpec] = &[
+    m("starts_with", 1, Some(1), NONE),
+    m("ends_with", 1, Some(1), NONE),
+    m("to_lowercase", 0, Some(0), NONE),
+    m("to_uppercase", 0, Some(0), NONE),
+    m("len_chars", 0, Some(0), NONE),
+    m("contains", 1, Some(1), &["literal"]),
+    m("replace", 2, Some(2), &["literal"]),
+    m("extract", 1, Some(2), NONE),
+    m("extract_all", 1, Some(1), NONE),
+    m("count_matches", 1, Some(1), &["literal"]),
+    m("strip_chars", 0, Some(1), NONE),
+    m("split", 1, Some(1), NONE),
+    m("zfill", 1, Some(1), NONE),
+    m("pad_start", 1, Some(2), &["fill_char"]),
+    m("to_datetime", 0, Some(1), STRPTIME_KWARGS),
+    m("to_date", 0, Some(1), STRPTIME_KWARGS),
+    m("strptime", 1, Some(2), STRPTIME_KWARGS),
+    m("slice", 2, Some(2), NONE),
+];
+
+pub const DT_METHODS: &[MethodSpec] = &[
+    m("year", 0, Some(0), NONE),
+    m("month", 0, Some(0), NONE),
+    m("day", 0, Some(0), NONE),
+    m("hour", 0, Some(0), NONE),
+    m("minute", 0, Some(0), NONE),
+    m("second", 0, Some(0), NONE),
+    m("weekday", 0, Some(0), NONE),
+    m("date", 0, Some(0), NONE),
+    m("timestamp", 0, Some(1), &["time_unit"]),
+    m("truncate", 1, Some(1), NONE),
+    m("offset_by", 1, Some(1), NONE),
+];
+
+pub const STRUCT_METHODS: &[MethodSpec] =
+    &[m("field", 1, Some(1), NONE), m("unnest", 0, Some(0), NONE)];
+
+pub const SELECTORS: &[MethodSpec] = &[
+    m("all", 0, Some(0), NONE),
+    m("numeric", 0, Some(0), NONE),
+    m("integer", 0, Some(0), NONE),
+    m("float", 0, Some(0), NONE),
+    m("string", 0, Some(0), NONE),
+    m("boolean", 0, Some(0), NONE),
+    m("temporal", 0, Some(0), NONE),
+    m("matches", 1, Some(1), NONE),
+];
+
+/// Full method surface, grouped by receiver
+pub fn capabilities() -> Vec<(Namespace, &'static [MethodSpec])> {
+    Namespace::ALL
+        .iter()
+        .map(|ns| (*ns, ns.methods()))
+        .collect()
+}
@@ -0,0 +1,337 @@
+//! Completion suggestions for query editors
+//!
+//! Works on the text before the cursor, which is usually not a complete query. A
+//! lightweight scan finds the token being typed and what precedes it:
+//! - start of the query: table names
+//! - after `$` or inside `pl.col("`: column names of the tables mentioned so far
+//! - after `.`: methods of the receiver, whose type is inferred by parsing the
+//!   receiver text
+//! - after `@`: registered directives, or pipeline directives after `.@`
+//! - after `!`: defined aliases
+//! - elsewhere inside arguments: `pl`, `cs` and user-defined functions
+
+use polars::prelude::*;
+
+use crate::ast::surface::Expr;
+use crate::capabilities::Namespace;
+use crate::eval::EvalContext;
+
+/// What a completion inserts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CompletionKind {
+    Table,
+    Column,
+    Method,
+    Function,
+    Directive,
+    Alias,
+}
+
+impl CompletionKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CompletionKind::Table => "table",
+            CompletionKind::Column => "column",
+            CompletionKind::Method => "method",
+            CompletionKind::Function => "function",
+            CompletionKind::Directive => "directive",
+            CompletionKind::Alias => "alias",
+        }
+    }
+}
+
+/// A single suggestion
+#[derive(Debug, Clone, PartialEq)]
+pub struct Completion {
+    pub label: String,
+    pub kind: CompletionKind,
+    /// Extra information: a column's dtype, a method's receiver
+    pub detail: Option<String>,
+    /// Byte offset where the replaced token starts; it ends at the cursor
+    pub replace_start: usize,
+}
+
+/// Suggestions for the token ending at `cursor_offset` (a byte offset into `query`),
+/// best matches first.
+pub fn complete(query: &str, cursor_offset: usize, ctx: &EvalContext) -> Vec<Completion> {
+    let mut cursor = cursor_offset.min(query.len());
+    while !query.is_char_boundary(cursor) {
+        cursor -= 1;
+    }
+    let prefix = &query[..cursor];
+    let scan = Scan::new(prefix);
+
+    if scan.in_comment {
+        return Vec::new();
+    }
+    if let Some(quote_start) = scan.string_start {
+        // Only `pl.col("...` strings complete, to column names
+        let before = prefix[..quote_start].trim_end();
+        if !before.ends_with("col(") {
+            return Vec::new();
+        }
+        let word_start = quote_start + 1;
+        return rank(
+            &prefix[word_start..],
+            column_candidates(prefix, ctx),
+            word_start,
+        );
+    }
+
+    let word_start = prefix
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| c.is_ascii_alphanumeric() || *c == '_')
+        .last()
+        .map_or(cursor, |(i, _)| i);
+    let word = &prefix[word_start..];
+    let candidates = match prefix[..word_start].chars().next_back() {
+        Some('$') => column_candidates(prefix, ctx),
+        Some('@') if prefix[..word_start - 1].ends_with('.') => ctx
+            .sugar
+            .pipeline_directive_names()
+            .map(|name| candidate(name, CompletionKind::Directive, None))
+            .collect(),
+        Some('@') => ctx
+            .sugar
+            .directive_names()
+            .map(|name| candidate(name, CompletionKind::Directive, None))
+            .collect(),
+        Some('!') => ctx
+            .aliases
+            .keys()
+            .map(|name| candidate(name, CompletionKind::Alias, None))
+            .collect(),
+        Some('.') => {
+            let dot = word_start - 1;
+            let receiver = &prefix[Scan::new(&prefix[..dot]).segment_start..dot];
+            method_candidates(receiver, ctx)
+        }
+        _ if scan.depth == 0 && prefix[..word_start].trim().is_empty() => table_names(ctx)
+            .into_iter()
+            .map(|name| candidate(&name, CompletionKind::Table, None))
+            .collect(),
+        _ if scan.depth > 0 => ["pl", "cs"]
+            .into_iter()
+            .map(|ns| candidate(ns, CompletionKind::Function, None))
+            .chain(
+                ctx.sugar
+                    .function_names()
+                    .map(|name| candidate(name, CompletionKind::Function, None)),
+            )
+            .collect(),
+        _ => Vec::new(),
+    };
+    rank(word, candidates, word_start)
+}
+
+/// Lexical state at the end of a query prefix
+struct Scan {
+    /// Offset of the opening quote when the prefix ends inside a string
+    string_start: Option<usize>,
+    in_comment: bool,
+    /// Unclosed `(` and `[`
+    depth: usize,
+    /// Start of the innermost argument or top-level expression being typed
+    segment_start: usize,
+}
+
+impl Scan {
+    fn new(text: &str) -> Self {
+        let mut string_start = None;
+        let mut in_comment = false;
+        let mut segments = vec![0usize];
+        let mut chars = text.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            if in_comment {
+                in_comment = c != '\n';
+                continue;
+            }
+            if let Some(start) = string_start {
+                let quote = text[start..].chars().next();
+                if c == '\\' {
+                    chars.next();
+                } else if Some(c) == quote {
+                    string_start = None;
+                }
+                continue;
+            }
+            match c {
+                '#' => in_comment = true,
+                '"' | '\'' => string_start = Some(i),
+                '(' | '[' => segments.push(i + 1),
+                ')' | ']' if segments.len() > 1 => {
+                    segments.pop();
+                }
+                ',' => *segments.last_mut().unwrap() = i + 1,
+                // Keyword argument `name=value`, but not `==`, `!=`, `<=`, `>=`
+                '=' if chars.peek().is_none_or(|(_, next)| *next != '=')
+                    && !text[..i].ends_with(['=', '!', '<', '>']) =>
+                {
+                    *segments.last_mut().unwrap() = i + 1
+                }
+                _ => {}
+            }
+        }
+        Scan {
+            string_start,
+            in_comment,
+            depth: segments.len() - 1,
+            segment_start: *segments.last().unwrap(),
+        }
+    }
+}
+
+fn candidate(label: &str, kind: CompletionKind, detail: Option<String>) -> Completion {
+    Completion {
+        label: label.to_string(),
+        kind,
+        detail,
+        replace_start: 0,
+    }
+}
+
+/// Keep candidates matching `word` (prefix matches before substring matches, then
+/// alphabetical), dropping duplicates
+fn rank(word: &str, candidates: Vec<Completion>, replace_start: usize) -> Vec<Completion> {
+    let needle = word.to_lowercase();
+    let mut scored: Vec<(u8, Completion)> = candidates
+        .into_iter()
+        .filter_map(|c| {
+            let label = c.label.to_lowercase();
+            let score = if c.label.starts_with(word) {
+                0
+            } else if label.starts_with(&needle) {
+                1
+            } else if label.contains(&needle) {
+                2
+            } else {
+                return None;
+            };
+            Some((score, c))
+        })
+        .collect();
+    scored.sort_by(|(a, x), (b, y)| a.cmp(b).then_with(|| x.label.cmp(&y.label)));
+    scored.dedup_by(|(_, x), (_, y)| x.label == y.label && x.kind == y.kind);
+    scored
+        .into_iter()
+        .map(|(_, c)| Completion { replace_start, ..c })
+        .collect()
+}
+
+fn table_names(ctx: &EvalContext) -> Vec<String> {
+    let mut names: Vec<String> = ctx
+        .dataframes
+        .keys()
+        .chain(ctx.base_tables.keys())
+        .cloned()
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+fn table_schema(ctx: &EvalContext, name: &str) -> Option<SchemaRef> {
+    if let Some(entry) = ctx.dataframes.get(name) {
+        return Some(entry.df.schema().clone());
+    }
+    let mut all = ctx.base_tables.get(name)?.all.clone()?;
+    all.collect_schema().ok()
+}
+
+/// Columns of the tables named in `prefix`, or of every table if none is
+fn column_candidates(prefix: &str, ctx: &EvalContext) -> Vec<Completion> {
+    let tables = table_names(ctx);
+    let mentioned: Vec<&String> = tables
+        .iter()
+        .filter(|name| {
+            prefix
+                .split(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == ':'))
+                .any(|token| token == name.as_str())
+        })
+        .collect();
+    let sources = if mentioned.is_empty() {
+        tables.iter().collect()
+    } else {
+        mentioned
+    };
+    sources
+        .into_iter()
+        .filter_map(|name| table_schema(ctx, name))
+        .flat_map(|schema| {
+            schema
+                .iter()
+                .map(|(col, dtype)| candidate(col, CompletionKind::Column, Some(dtype.to_string())))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+fn method_candidates(receiver: &str, ctx: &EvalContext) -> Vec<Completion> {
+    // Tolerate receivers that are themselves malformed
+    let expr = crate::parse::parse_recovering(receiver).expr;
+    let Some(namespace) = receiver_namespace(&expr, ctx) else {
+        return Vec::new();
+    };
+    let detail = Some(namespace.as_str().to_string());
+    let mut out: Vec<Completion> = namespace
+        .methods()
+        .iter()
+        .map(|m| candidate(m.name, CompletionKind::Method, detail.clone()))
+        .collect();
+    if namespace == Namespace::Expr {
+        out.extend(
+            ["str", "dt", "struct"]
+                .into_iter()
+                .map(|ns| candidate(ns, CompletionKind::Method, Some("namespace".into()))),
+        );
+        // `$col.delta`-style sugar only applies directly to a column shorthand
+        if matches!(expr, Expr::ColShorthand(_)) {
+            out.extend(
+                ctx.sugar
+                    .col_method_names()
+                    .map(|name| candidate(name, CompletionKind::Method, Some("sugar".into()))),
+            );
+        }
+    }
+    out
+}
+
+/// Receiver type of the expression directly before a `.`
+fn receiver_namespace(expr: &Expr, ctx: &EvalContext) -> Option<Namespace> {
+    match expr {
+        Expr::Ident(name) if name == "pl" => Some(Namespace::Pl),
+        Expr::Ident(name) if name == "cs" => Some(Namespace::Selectors),
+        Expr::Ident(name) => (ctx.dataframes.contains_key(name)
+            || ctx.base_tables.contains_key(name))
+        .then_some(Namespace::DataFrame),
+        Expr::ColShorthand(_) | Expr::Literal(_) | Expr::Param(_) | Expr::Directive(..) => {
+            Some(Namespace::Expr)
+        }
+        Expr::Attr(base, name) => match (receiver_namespace(base, ctx)?, name.as_str()) {
+            (Namespace::Expr, "str") => Some(Namespace::Str),
+            (Namespace::Expr, "dt") => Some(Namespace::Dt),
+            (Namespace::Expr, "struct") => Some(Namespace::Struct),
+            _ => None,
+        },
+        Expr::Call(callee, _) => match callee.as_ref() {
+            Expr::Attr(base, method) => match (receiver_namespace(base, ctx)?, method.as_str()) {
+                (Namespace::DataFrame, "group_by" | "group_by_dynamic") => Some(Namespace::GroupBy),
+                (Namespace::DataFrame | Namespace::GroupBy, _) => Some(Namespace::DataFrame),
+                _ => Some(Namespace::Expr),
+            },
+            // User-defined functions expand to expressions
+            Expr::Ident(name) if ctx.sugar.has_function(name) => Some(Namespace::Expr),
+            _ => None,
+        },
+        // `.` binds tighter than operators, so the receiver is the operand next to it
+        Expr::BinaryOp(_, _, rhs) => receiver_namespace(rhs, ctx),
+        Expr::UnaryOp(_, inner) => receiver_namespace(inner, ctx),
+        Expr::Commented { expr, .. } => receiver_namespace(expr, ctx),
+        // An alias may stand for anything; its body is not parsed here
+        Expr::Alias(_) => None,
+        // Pipeline directives rewrite the receiver into a pipeline of the same kind
+        Expr::PipelineDirective(base, ..) => receiver_namespace(base, ctx),
+        Expr::List(_) | Expr::Error => None,
+    }
+}
@@ -0,0 +1,70 @@
+//! Row-level diffs between successive subscription results
+//!
+//! A diff has the result's columns plus `_change`:
+//! - `added` → key not present in the previous result
+//! - `changed` → key present before with different values (new values)
+//! - `removed` → key no longer present (previous values)
+//! - `reset` → the columns changed; the rows are the full new result
+//!
+//! Rows are matched on a key column, normally the partition key of the table the
+//! query reads. Without one, whole rows are compared and only `added` and
+//! `removed` occur.
+
+use polars::prelude::*;
+
+use crate::PiqlError;
+use crate::eval::EvalError;
+
+/// Column holding the change kind of each diff row
+pub const CHANGE_COLUMN: &str = "_change";
+
+/// Rows added, changed and removed between `old` and `new`
+///
+/// An empty result means nothing changed (row order is ignored).
+pub fn diff_results(
+    old: &DataFrame,
+    new: &DataFrame,
+    key: Option<&str>,
+) -> Result<DataFrame, PiqlError> {
+    let tag = |lf: LazyFrame, change: &str| lf.with_column(lit(change).alias(CHANGE_COLUMN));
+    if old.schema() != new.schema() {
+        return Ok(tag(new.clone().lazy(), "reset")
+            .collect()
+            .map_err(EvalError::from)?);
+    }
+
+    let columns: Vec<Expr> = new
+        .get_column_names()
+        .into_iter()
+        .map(|name| col(name.clone()))
+        .collect();
+    let key = key.filter(|key| new.schema().contains(key));
+    let keys = match key {
+        Some(key) => vec![col(key)],
+        None => columns.clone(),
+    };
+    let join = |left: LazyFrame, right: LazyFrame, on: &[Expr], how: JoinType| {
+        let mut args = JoinArgs::new(how);
+        args.nulls_equal = true;
+        left.join(right, on.to_vec(), on.to_vec(), args)
+    };
+
+    let old = old.clone().lazy();
+    let new = new.clone().lazy();
+    let mut frames = vec![tag(
+        join(new.clone(), old.clone(), &keys, JoinType::Anti),
+        "added",
+    )];
+    if key.is_some() {
+        let kept = join(new.clone(), old.clone(), &keys, JoinType::Semi);
+        frames.push(tag(
+            join(kept, old.clone(), &columns, JoinType::Anti),
+            "changed",
+        ));
+    }
+    frames.push(tag(join(old, new, &keys, JoinType::Anti), "removed"));
+
+    Ok(concat(frames, UnionArgs::default())
+        .and_then(LazyFrame::collect)
+        .map_err(EvalError::from)?)
+}
@@ -7,10 +7,22 @@
 
 use indexmap::IndexMap;
 use polars::prelude::*;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
 
-use crate::eval::{EvalContext, TimeSeriesConfig};
-use crate::{CompiledQuery, PiqlError, Value, compile, run, run_compiled};
+use crate::diff::diff_results;
+use crate::eval::{EvalContext, EvictedTicks, Retention, TickDtype, TimeSeriesConfig};
+use crate::graph::DependencyGraph;
+use crate::incremental::{self, SubscriptionScope};
+use crate::lint::{self, LintWarning};
+use crate::prefix::{self, SharedPrefixes};
+use crate::snapshot::{
+    self, BaseTableSnapshot, Manifest, QueryEntry, SnapshotDumps, SnapshotError,
+};
+use crate::{CompiledQuery, Params, PiqlError, PreparedQuery, Value, compile, run, run_compiled};
 
 /// Query engine with materialized tables and subscriptions
 ///
@@ -36,17 +48,68 @@ pub struct QueryEngine {
     ctx: EvalContext,
 
     /// Materialized tables: name -> query
-    /// Evaluated in insertion order each tick (user ensures correct dependency order)
     materialized: IndexMap<String, CachedQuery>,
 
+    /// Tables each materialization reads; re-evaluation follows its topological order
+    graph: DependencyGraph,
+
     /// Subscribed queries: name -> query
     subscriptions: HashMap<String, CachedQuery>,
+
+    /// Evaluate now-scoped subscriptions against appended slices only
+    incremental: bool,
+
+    /// Base tables that received rows since the last tick
+    appended: HashSet<String>,
+
+    /// Maximum number of threads evaluating subscriptions in `on_tick()`
+    parallelism: usize,
+
+    /// Evaluate method-chain prefixes common to several subscriptions once per tick
+    share_prefixes: bool,
+
+    /// Shared prefixes collected by the last `on_tick()`
+    shared_prefixes: usize,
+}
+
+/// What `on_tick()` returns for a subscription
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmitMode {
+    /// The full result every tick
+    #[default]
+    Always,
+    /// The full result, only on ticks where it differs from the last one emitted
+    OnChange,
+    /// Only rows added, changed or removed since the last emission, keyed by the
+    /// source table's partition key (see [`crate::diff_results`])
+    Diff,
+}
+
+/// What removing a table does to the materializations and subscriptions reading it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RemoveMode {
+    /// Keep the table and fail with [`PiqlError::TableInUse`]
+    #[default]
+    Fail,
+    /// Remove them too, along with anything reading them in turn
+    Cascade,
 }
 
 #[derive(Clone)]
 struct CachedQuery {
     query: String,
     compiled: Option<CompiledQuery>,
+    /// Base tables read by a now-scoped query (`None` if it reads history)
+    now_tables: Option<BTreeSet<String>>,
+    /// Result of the last evaluation, reused while its tables are unchanged
+    last_result: Option<DataFrame>,
+    mode: EmitMode,
+    /// Last full result emitted, compared against in `OnChange`/`Diff` modes
+    emitted: Option<DataFrame>,
+    /// Row key for `Diff` mode
+    diff_key: Option<String>,
 }
 
 impl CachedQuery {
@@ -54,22 +117,74 @@ impl CachedQuery {
         Self {
             query,
             compiled: None,
+            now_tables: None,
+            last_result: None,
+            mode: EmitMode::Always,
+            emitted: None,
+            diff_key: None,
         }
     }
 
     fn from_compiled(query: String, compiled: CompiledQuery) -> Self {
         Self {
-            query,
             compiled: Some(compiled),
+            ..Self::new(query)
         }
     }
 
     fn get_or_compile(&mut self, ctx: &EvalContext) -> Result<&CompiledQuery, PiqlError> {
         if self.compiled.is_none() {
-            self.compiled = Some(compile(&self.query, ctx)?);
+            let compiled = compile(&self.query, ctx)?;
+            // `.as_of(n)` pins the query to a past tick, so appended slices don't apply
+            self.now_tables = match compiled.as_of() {
+                Some(_) => None,
+                None => incremental::now_scoped_tables(compiled.core(), ctx),
+            };
+            if self.mode == EmitMode::Diff {
+                self.diff_key = crate::prepare(&self.query, ctx)?.partition_key(ctx);
+            }
+            self.compiled = Some(compiled);
         }
         Ok(self.compiled.as_ref().expect("compiled query missing"))
     }
+
+    /// Apply the emit mode to a fresh result; `None` means there is nothing to emit
+    fn emit(&mut self, df: DataFrame) -> Result<Option<DataFrame>, PiqlError> {
+        let previous = match self.mode {
+            EmitMode::Always => return Ok(Some(df)),
+            EmitMode::OnChange | EmitMode::Diff => self.emitted.replace(df.clone()),
+        };
+        let Some(previous) = previous else {
+            // First emission: the full result (as added rows in diff mode)
+            return Ok(Some(match self.mode {
+                EmitMode::Diff => diff_results(&df.clear(), &df, self.diff_key.as_deref())?,
+                _ => df,
+            }));
+        };
+        if previous.equals_missing(&df) {
+            return Ok(None);
+        }
+        match self.mode {
+            EmitMode::Diff => {
+                let diff = diff_results(&previous, &df, self.diff_key.as_deref())?;
+                Ok((diff.height() > 0).then_some(diff))
+            }
+            _ => Ok(Some(df)),
+        }
+    }
+
+    fn invalidate(&mut self) {
+        self.compiled = None;
+        self.now_tables = None;
+        self.last_result = None;
+    }
+
+    fn scope(&self) -> SubscriptionScope {
+        match self.now_tables {
+            Some(_) => SubscriptionScope::Now,
+            None => SubscriptionScope::History,
+        }
+    }
 }
 
 impl QueryEngine {
@@ -77,7 +192,13 @@ impl QueryEngine {
         Self {
             ctx: EvalContext::new(),
             materialized: IndexMap::new(),
+            graph: DependencyGraph::new(),
             subscriptions: HashMap::new(),
+            incremental: false,
+            appended: HashSet::new(),
+            parallelism: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            share_prefixes: true,
+            shared_prefixes: 0,
         }
     }
 
@@ -89,6 +210,7 @@ impl QueryEngine {
             crate::eval::DataFrameEntry {
                 df: collected,
                 time_series: None,
+                scan: None,
             },
         );
     }
@@ -106,21 +228,148 @@ impl QueryEngine {
             crate::eval::DataFrameEntry {
                 df: collected,
                 time_series: Some(config),
+                scan: None,
             },
         );
     }
 
-    /// Update a base dataframe (e.g., after appending new rows, collects immediately)
+    /// Add a base dataframe read from `df` on every evaluation instead of collected
+    /// (see [`EvalContext::with_scan_df`])
+    pub fn add_scan_df(&mut self, name: impl Into<String>, df: LazyFrame) -> Result<(), PiqlError> {
+        let entry = crate::eval::DataFrameEntry::from_scan(df, None)
+            .map_err(crate::eval::EvalError::from)?;
+        self.ctx.dataframes.insert(name.into(), entry);
+        Ok(())
+    }
+
+    /// Read scan-backed table `name` into memory, so evaluations stop re-reading
+    /// its source (no-op for tables already in memory)
+    pub fn materialize_df(&mut self, name: &str) -> Result<(), PiqlError> {
+        Ok(self.ctx.materialize_df(name)?)
+    }
+
+    /// Update a base dataframe (e.g., after appending new rows, collects immediately;
+    /// a scan-backed table takes `df` as its new scan)
     pub fn update_df(&mut self, name: &str, df: LazyFrame) {
         if self.ctx.is_base_table(name) {
             // Replace both all/now pointers for registered base tables.
             self.ctx.update_base_table_ptrs(name, df.clone(), df);
+            self.appended.insert(name.to_string());
             return;
         }
 
         if let Some(entry) = self.ctx.dataframes.get_mut(name) {
-            entry.df = df.collect().expect("failed to collect DataFrame");
+            if entry.is_scan() {
+                entry.scan = Some(df);
+            } else {
+                entry.df = df.collect().expect("failed to collect DataFrame");
+            }
+        }
+    }
+
+    /// Remove a table (a base table, dataframe or materialization)
+    ///
+    /// Returns the materializations and subscriptions removed with it, which is only
+    /// ever non-empty with [`RemoveMode::Cascade`]; with [`RemoveMode::Fail`] a table
+    /// that anything reads is kept and [`PiqlError::TableInUse`] returned.
+    pub fn remove_df(&mut self, name: &str, mode: RemoveMode) -> Result<Vec<String>, PiqlError> {
+        if !self.has_table(name) {
+            return Err(PiqlError::UnknownTable(name.to_string()));
+        }
+        let (views, subscriptions) = self.dependents(name);
+        let dependents: Vec<String> = views.iter().chain(&subscriptions).cloned().collect();
+        if mode == RemoveMode::Fail && !dependents.is_empty() {
+            return Err(PiqlError::TableInUse {
+                table: name.to_string(),
+                dependents,
+            });
+        }
+        for view in &views {
+            self.drop_table(view);
+        }
+        for subscription in &subscriptions {
+            self.subscriptions.remove(subscription);
+        }
+        self.drop_table(name);
+        Ok(dependents)
+    }
+
+    /// Rename a table, keeping its data, time-series config and, for a
+    /// materialization, its query
+    ///
+    /// Fails if `new` exists, or if any materialization or subscription reads `old`,
+    /// since their queries name it.
+    pub fn rename_df(&mut self, old: &str, new: impl Into<String>) -> Result<(), PiqlError> {
+        let new = new.into();
+        if !self.has_table(old) {
+            return Err(PiqlError::UnknownTable(old.to_string()));
+        }
+        if self.has_table(&new) {
+            return Err(PiqlError::TableExists(new));
+        }
+        let (views, subscriptions) = self.dependents(old);
+        if !views.is_empty() || !subscriptions.is_empty() {
+            return Err(PiqlError::TableInUse {
+                table: old.to_string(),
+                dependents: views.into_iter().chain(subscriptions).collect(),
+            });
+        }
+
+        if let Some(dependencies) = self.graph.dependencies(old).cloned() {
+            let mut graph = self.graph.clone();
+            graph.remove(old);
+            graph.insert(new.clone(), dependencies)?;
+            self.graph = graph;
+        }
+        if let Some(index) = self.materialized.get_index_of(old)
+            && let Some((_, cached)) = self.materialized.shift_remove_index(index)
+        {
+            self.materialized.shift_insert(index, new.clone(), cached);
+        }
+        if let Some(entry) = self.ctx.dataframes.remove(old) {
+            self.ctx.dataframes.insert(new.clone(), entry);
         }
+        if let Some(entry) = self.ctx.base_tables.remove(old) {
+            self.ctx.base_tables.insert(new.clone(), entry);
+        }
+        if self.appended.remove(old) {
+            self.appended.insert(new);
+        }
+        Ok(())
+    }
+
+    fn has_table(&self, name: &str) -> bool {
+        self.ctx.dataframes.contains_key(name) || self.ctx.is_base_table(name)
+    }
+
+    /// Materializations downstream of `table` (in refresh order) and the
+    /// subscriptions reading it or them (sorted)
+    fn dependents(&self, table: &str) -> (Vec<String>, Vec<String>) {
+        let views = self.graph.refresh_order(table);
+        let mut subscriptions: Vec<String> = self
+            .subscriptions
+            .iter()
+            .filter(|(_, cached)| {
+                self.prepare(&cached.query).is_ok_and(|prepared| {
+                    prepared
+                        .referenced_tables()
+                        .iter()
+                        .any(|t| t == table || views.contains(t))
+                })
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        subscriptions.sort();
+        (views, subscriptions)
+    }
+
+    /// Forget table `name`, and its query if it is a materialization
+    fn drop_table(&mut self, name: &str) {
+        self.ctx.dataframes.remove(name);
+        self.ctx.base_tables.remove(name);
+        self.appended.remove(name);
+        self.materialized.shift_remove(name);
+        self.graph.remove(name);
     }
 
     /// Access the sugar registry for registering custom directives
@@ -135,8 +384,27 @@ impl QueryEngine {
     /// - `entities.all().filter(...)` → uses full history
     /// - `entities.window(-10, 0).filter(...)` → uses history with tick filter
     pub fn register_base(&mut self, name: impl Into<String>, config: TimeSeriesConfig) {
+        self.register_base_with_retention(name, config, Retention::KeepAll);
+    }
+
+    /// Register a base table that keeps only the history allowed by `retention`
+    ///
+    /// Older ticks are compacted on every `append_tick`. Scope methods that reach
+    /// into compacted ticks fail unless called with `partial=True`; `.all()`
+    /// returns whatever is retained.
+    pub fn register_base_with_retention(
+        &mut self,
+        name: impl Into<String>,
+        config: TimeSeriesConfig,
+        retention: Retention,
+    ) {
         // Register config in eval context (it holds the config for scope method routing)
-        self.ctx.register_base_table(name.into(), config);
+        let name = name.into();
+        self.appended.insert(name.clone());
+        self.ctx.register_base_table(name.clone(), config);
+        if let Some(entry) = self.ctx.base_tables.get_mut(&name) {
+            entry.retention = retention;
+        }
     }
 
     /// Append new tick data to a base table
@@ -154,18 +422,162 @@ impl QueryEngine {
                 .map_err(crate::eval::EvalError::from)?,
             None => rows.clone(),
         };
+        let (all, _) = self.apply_retention(name, all)?;
 
         // Update eval context with current ptrs
         self.ctx.update_base_table_ptrs(name, all, rows);
+        self.appended.insert(name.to_string());
 
         Ok(())
     }
 
+    /// Compact every base table to its retention policy
+    ///
+    /// `append_tick` already compacts the table it appends to; call this after
+    /// bulk loads. Returns the number of rows removed.
+    pub fn compact(&mut self) -> Result<usize, PiqlError> {
+        let mut names: Vec<String> = self.ctx.base_tables.keys().cloned().collect();
+        names.sort();
+        let mut removed = 0;
+        for name in names {
+            let (Some(all), Some(now)) =
+                (self.ctx.get_base_all(&name), self.ctx.get_base_now(&name))
+            else {
+                continue;
+            };
+            let (all, n) = self.apply_retention(&name, all)?;
+            if n > 0 {
+                self.ctx.update_base_table_ptrs(&name, all, now);
+                removed += n;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Filter a base table's history down to its retention policy, recording the
+    /// evicted ticks
+    fn apply_retention(
+        &mut self,
+        name: &str,
+        all: LazyFrame,
+    ) -> Result<(LazyFrame, usize), PiqlError> {
+        let entry = self
+            .ctx
+            .base_tables
+            .get_mut(name)
+            .ok_or_else(|| crate::eval::EvalError::UnknownIdent(name.to_string()))?;
+        let (recent, every) = match entry.retention {
+            Retention::KeepAll => return Ok((all, 0)),
+            Retention::LastTicks(n) => (n, None),
+            Retention::Downsample { recent, every } => (recent, Some(every)),
+        };
+        if recent < 1 || every.is_some_and(|every| every < 1) {
+            return Err(crate::eval::EvalError::ArgError(format!(
+                "retention for `{name}` must keep at least one tick"
+            ))
+            .into());
+        }
+        if entry.config.tick_dtype != TickDtype::Int {
+            return Err(crate::eval::EvalError::Other(format!(
+                "retention for `{name}` requires an integer tick column"
+            ))
+            .into());
+        }
+
+        let tick = col(entry.config.tick_column.as_str()).cast(DataType::Int64);
+        let df = all.collect().map_err(crate::eval::EvalError::from)?;
+        let ticks = df
+            .column(&entry.config.tick_column)
+            .and_then(|c| c.cast(&DataType::Int64))
+            .map_err(crate::eval::EvalError::from)?;
+        let ticks = ticks.i64().map_err(crate::eval::EvalError::from)?;
+        let (Some(first), Some(latest)) = (ticks.min(), ticks.max()) else {
+            return Ok((df.lazy(), 0));
+        };
+
+        let full_from = latest - recent + 1;
+        let keep = match every {
+            None => tick.gt_eq(lit(full_from)),
+            Some(every) => tick
+                .clone()
+                .gt_eq(lit(full_from))
+                .or((tick % lit(every)).eq(lit(0i64))),
+        };
+        let kept = df
+            .clone()
+            .lazy()
+            .filter(keep)
+            .collect()
+            .map_err(crate::eval::EvalError::from)?;
+        let removed = df.height() - kept.height();
+        if removed > 0 {
+            let previous = entry.evicted;
+            entry.evicted = Some(EvictedTicks {
+                first: previous.map_or(first, |e| e.first),
+                full_from: previous.map_or(full_from, |e| e.full_from.max(full_from)),
+                kept_every: every,
+            });
+        }
+        Ok((kept.lazy(), removed))
+    }
+
+    /// Save a query snippet referenced as `!name` in later queries
+    ///
+    /// Cached compilations are dropped so existing subscriptions pick up the new text.
+    pub fn define_alias(
+        &mut self,
+        name: impl Into<String>,
+        query: impl Into<String>,
+    ) -> Result<(), PiqlError> {
+        let name = name.into();
+        let previous = self.ctx.aliases.get(&name).cloned();
+        self.ctx.define_alias(name.clone(), query)?;
+
+        // Materializations may read different tables through the new alias text
+        let graph = match self.build_graph() {
+            Ok(graph) => graph,
+            Err(e) => {
+                match previous {
+                    Some(query) => self.ctx.aliases.insert(name, query),
+                    None => self.ctx.aliases.remove(&name),
+                };
+                return Err(e);
+            }
+        };
+        self.graph = graph;
+        for cached in self
+            .materialized
+            .values_mut()
+            .chain(self.subscriptions.values_mut())
+        {
+            cached.invalidate();
+        }
+        Ok(())
+    }
+
+    /// Dependency graph of the materialized tables, recompiling each query
+    fn build_graph(&self) -> Result<DependencyGraph, PiqlError> {
+        let mut graph = DependencyGraph::new();
+        for (name, cached) in &self.materialized {
+            graph.insert(
+                name.clone(),
+                compile(&cached.query, &self.ctx)?.referenced_tables(),
+            )?;
+        }
+        Ok(graph)
+    }
+
+    /// Materialized tables and the tables each one reads
+    pub fn dependency_graph(&self) -> &DependencyGraph {
+        &self.graph
+    }
+
     /// Add a materialized table
     ///
-    /// The query is evaluated immediately and stored. It will be re-evaluated
-    /// each tick before subscriptions.
-    /// Add them in dependency order (if A depends on B, add B first).
+    /// The query is evaluated immediately, so the tables it reads must exist. It
+    /// will be re-evaluated each tick before subscriptions, after every
+    /// materialization it reads. A materialization that would read itself, directly
+    /// or through others, is rejected.
     pub fn materialize(
         &mut self,
         name: impl Into<String>,
@@ -175,16 +587,22 @@ impl QueryEngine {
         let query = query.into();
         let compiled = compile(&query, &self.ctx)?;
 
+        let mut graph = self.graph.clone();
+        graph.insert(name.clone(), compiled.referenced_tables())?;
+
         // Evaluate immediately
         let result = run_compiled(&compiled, &self.ctx)?;
+        self.graph = graph;
         if let Some(collected) = collect_value_df(result)? {
             self.ctx.dataframes.insert(
                 name.clone(),
                 crate::eval::DataFrameEntry {
                     df: collected,
                     time_series: None,
+                    scan: None,
                 },
             );
+            record_current(&mut self.ctx, &name, &compiled);
         }
 
         self.materialized
@@ -200,19 +618,126 @@ impl QueryEngine {
             .insert(name.into(), CachedQuery::new(query.into()));
     }
 
+    /// Subscribe to a query, choosing what `on_tick()` returns for it
+    ///
+    /// With [`EmitMode::OnChange`] or [`EmitMode::Diff`] the subscription is left
+    /// out of `on_tick()` results on ticks where its result did not change.
+    pub fn subscribe_with_mode(
+        &mut self,
+        name: impl Into<String>,
+        query: impl Into<String>,
+        mode: EmitMode,
+    ) {
+        let mut cached = CachedQuery::new(query.into());
+        cached.mode = mode;
+        self.subscriptions.insert(name.into(), cached);
+    }
+
     /// Unsubscribe from a query
     pub fn unsubscribe(&mut self, name: &str) {
         self.subscriptions.remove(name);
     }
 
+    /// Enable incremental evaluation of now-scoped subscriptions
+    ///
+    /// A subscription that reads base tables only through their implicit `now`
+    /// pointer is evaluated against the rows appended in the latest tick, and its
+    /// previous result is reused on ticks where none of its tables received rows.
+    /// Other subscriptions are re-run in full. Results are the same either way.
+    pub fn set_incremental(&mut self, enabled: bool) {
+        self.incremental = enabled;
+        for cached in self.subscriptions.values_mut() {
+            cached.last_result = None;
+        }
+    }
+
+    /// Cap the number of threads evaluating subscriptions in `on_tick()`
+    ///
+    /// Defaults to the available parallelism; `1` evaluates them one after another
+    /// on the calling thread. Materializations are always evaluated serially.
+    pub fn set_parallelism(&mut self, threads: usize) {
+        self.parallelism = threads.max(1);
+    }
+
+    /// Share method-chain prefixes between subscriptions (on by default)
+    ///
+    /// Each tick, the longest prefix a subscription has in common with another one
+    /// (e.g. `entities.at(100).filter(@merchant)`) is collected once, and both are
+    /// evaluated from it. Subscriptions evaluated incrementally or with `.as_of(n)`
+    /// are always run on their own. Results are the same either way.
+    pub fn set_prefix_sharing(&mut self, enabled: bool) {
+        self.share_prefixes = enabled;
+    }
+
+    /// Number of shared prefixes the last `on_tick()` collected
+    pub fn shared_prefixes(&self) -> usize {
+        self.shared_prefixes
+    }
+
+    /// Turn the plan-level optimizer on (the default) or off
+    ///
+    /// Materializations and subscriptions are recompiled on the next tick. Results
+    /// are the same either way; turning it off evaluates queries as written.
+    pub fn set_optimizer(&mut self, enabled: bool) {
+        self.ctx.optimize = enabled;
+        for cached in self
+            .materialized
+            .values_mut()
+            .chain(self.subscriptions.values_mut())
+        {
+            cached.invalidate();
+        }
+    }
+
+    /// Whether a subscription reads only the latest tick or needs history
+    ///
+    /// `None` until the subscription has been compiled by its first `on_tick()`.
+    pub fn subscription_scope(&self, name: &str) -> Option<SubscriptionScope> {
+        self.subscriptions
+            .get(name)
+            .filter(|cached| cached.compiled.is_some())
+            .map(CachedQuery::scope)
+    }
+
+    /// Lint subscriptions for patterns that are expensive to re-evaluate every tick
+    ///
+    /// Subscriptions that fail to parse are skipped (they already error in `on_tick`).
+    pub fn lint_subscriptions(&self) -> Vec<LintWarning> {
+        let mut parsed: Vec<(&str, crate::ast::surface::Expr)> = self
+            .subscriptions
+            .iter()
+            .filter_map(|(name, cached)| {
+                crate::parse::parse(&cached.query)
+                    .ok()
+                    .map(|expr| (name.as_str(), expr))
+            })
+            .collect();
+        parsed.sort_by(|a, b| a.0.cmp(b.0));
+
+        let mut warnings: Vec<LintWarning> = parsed
+            .iter()
+            .flat_map(|(name, expr)| lint::lint_subscription(name, expr, &self.ctx))
+            .collect();
+        let refs: Vec<(&str, &crate::ast::surface::Expr)> =
+            parsed.iter().map(|(name, expr)| (*name, expr)).collect();
+        warnings.extend(lint::materialization_candidates(&refs));
+        warnings
+    }
+
     /// Process a tick: re-evaluate materialized tables and subscriptions
     ///
-    /// Returns results for all subscribed queries.
+    /// Returns results for all subscribed queries. Subscriptions are independent of
+    /// each other, so they are evaluated in parallel (see
+    /// [`set_parallelism`](Self::set_parallelism)); if several fail, the error of
+    /// the first by name is returned.
     pub fn on_tick(&mut self, tick: i64) -> Result<HashMap<String, DataFrame>, PiqlError> {
         self.ctx.tick = Some(tick);
 
-        // 1. Re-evaluate materialized tables in order
-        for (name, cached) in &mut self.materialized {
+        // 1. Re-evaluate materialized tables in dependency order
+        for name in self.graph.topological_order() {
+            let Some(cached) = self.materialized.get_mut(&name) else {
+                continue;
+            };
             let result = eval_cached_query(cached, &self.ctx)?;
             if let Some(collected) = collect_value_df(result)? {
                 // Store as new DF entry (no time-series config for derived tables)
@@ -221,28 +746,257 @@ impl QueryEngine {
                     crate::eval::DataFrameEntry {
                         df: collected,
                         time_series: None,
+                        scan: None,
                     },
                 );
+                if let Some(compiled) = &cached.compiled {
+                    record_current(&mut self.ctx, &name, compiled);
+                }
             }
         }
 
-        // 2. Evaluate all subscriptions
+        // 2. Collect the prefixes subscriptions evaluated in full have in common
+        let shared = if self.share_prefixes {
+            for cached in self.subscriptions.values_mut() {
+                // Compile errors are reported when the subscription is evaluated
+                let _ = cached.get_or_compile(&self.ctx);
+            }
+            let incremental = self.incremental;
+            prefix::share_prefixes(
+                self.subscriptions.iter().filter_map(|(name, cached)| {
+                    let compiled = cached.compiled.as_ref()?;
+                    let full =
+                        compiled.as_of().is_none() && !(incremental && cached.now_tables.is_some());
+                    full.then_some((name.as_str(), compiled))
+                }),
+                &self.ctx,
+            )
+        } else {
+            None
+        };
+        self.shared_prefixes = shared.as_ref().map_or(0, |shared| shared.evaluated);
+
+        // 3. Evaluate all subscriptions
+        let tick = SubscriptionTick {
+            ctx: &self.ctx,
+            slice_ctx: OnceLock::new(),
+            appended: &self.appended,
+            incremental: self.incremental,
+            shared: shared.as_ref(),
+        };
+        let mut pending: Vec<(&String, &mut CachedQuery)> = self.subscriptions.iter_mut().collect();
+        pending.sort_by(|a, b| a.0.cmp(b.0));
+        let threads = self.parallelism.min(pending.len());
+        let outcomes = if threads <= 1 {
+            pending
+                .into_iter()
+                .map(|(name, cached)| (name, tick.evaluate(name, cached)))
+                .collect()
+        } else {
+            // Threads take the next pending subscription until none are left
+            let next = AtomicUsize::new(0);
+            let slots: Vec<Mutex<Option<(&String, &mut CachedQuery)>>> = pending
+                .into_iter()
+                .map(|entry| Mutex::new(Some(entry)))
+                .collect();
+            let mut outcomes: Vec<_> = std::thread::scope(|scope| {
+                let workers: Vec<_> = (0..threads)
+                    .map(|_| {
+                        scope.spawn(|| {
+                            let mut done = Vec::new();
+                            while let Some(slot) = slots.get(next.fetch_add(1, Ordering::Relaxed)) {
+                                let (name, cached) = slot
+                                    .lock()
+                                    .expect("subscription slot poisoned")
+                                    .take()
+                                    .expect("subscription evaluated twice");
+                                done.push((name, tick.evaluate(name, cached)));
+                            }
+                            done
+                        })
+                    })
+                    .collect();
+                workers
+                    .into_iter()
+                    .flat_map(|worker| worker.join().expect("subscription thread panicked"))
+                    .collect()
+            });
+            outcomes.sort_by(|a, b| a.0.cmp(b.0));
+            outcomes
+        };
+
         let mut results = HashMap::new();
-        for (name, cached) in &mut self.subscriptions {
-            let result = eval_cached_query(cached, &self.ctx)?;
-            if let Some(collected) = collect_value_df(result)? {
-                results.insert(name.clone(), collected);
+        for (name, outcome) in outcomes {
+            if let Some(df) = outcome? {
+                results.insert(name.clone(), df);
             }
         }
+        self.appended.clear();
 
         Ok(results)
     }
 
+    /// Save base tables, plain tables, aliases, materializations and subscriptions
+    /// to `dir` (Parquet files plus a `manifest.json`)
+    ///
+    /// Materialized tables are saved as queries and re-evaluated on load.
+    pub fn save_snapshot(&self, dir: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        let mut dumps = SnapshotDumps::new();
+
+        let mut base_names: Vec<&String> = self.ctx.base_tables.keys().collect();
+        base_names.sort();
+        let mut base_tables = Vec::with_capacity(base_names.len());
+        for name in base_names {
+            let entry = &self.ctx.base_tables[name];
+            let all = match self.ctx.dataframes.get(name) {
+                Some(table) if entry.all.is_some() => Some(dumps.add(table.df.clone())),
+                _ => None,
+            };
+            let now = match &entry.now {
+                Some(now) => Some(dumps.add(now.clone().collect()?)),
+                None => None,
+            };
+            base_tables.push(BaseTableSnapshot {
+                name: name.clone(),
+                config: entry.config.clone(),
+                retention: entry.retention,
+                evicted: entry.evicted,
+                all,
+                now,
+            });
+        }
+
+        let mut names: Vec<&String> = self
+            .ctx
+            .dataframes
+            .keys()
+            .filter(|name| !self.ctx.is_base_table(name) && !self.materialized.contains_key(*name))
+            .collect();
+        names.sort();
+        let mut tables = Vec::with_capacity(names.len());
+        for name in names {
+            // Scan-backed tables are saved with their current rows
+            let entry = &self.ctx.dataframes[name];
+            tables.push(snapshot::TableEntry {
+                name: name.clone(),
+                file: dumps.add(entry.collect()?),
+                time_series: entry.time_series.clone(),
+            });
+        }
+
+        let query_entries = |queries: Vec<(&String, &CachedQuery)>| -> Vec<QueryEntry> {
+            queries
+                .into_iter()
+                .map(|(name, cached)| QueryEntry {
+                    name: name.clone(),
+                    query: cached.query.clone(),
+                    mode: cached.mode,
+                })
+                .collect()
+        };
+        let mut subscriptions: Vec<_> = self.subscriptions.iter().collect();
+        subscriptions.sort_by(|a, b| a.0.cmp(b.0));
+
+        let manifest = Manifest {
+            version: snapshot::MANIFEST_VERSION,
+            tick: self.ctx.tick,
+            default_tick_column: self.ctx.default_tick_column.clone(),
+            default_partition_key: self.ctx.default_partition_key.clone(),
+            tables,
+            base_tables,
+            aliases: self.ctx.aliases.clone().into_iter().collect(),
+            materializations: query_entries(
+                self.graph
+                    .topological_order()
+                    .iter()
+                    .filter_map(|name| self.materialized.get_key_value(name))
+                    .collect(),
+            ),
+            subscriptions: query_entries(subscriptions),
+        };
+        snapshot::write_snapshot(dir.as_ref(), &manifest, dumps)
+    }
+
+    /// Restore state saved by [`QueryEngine::save_snapshot`]
+    ///
+    /// Restored tables, aliases and subscriptions replace those with the same
+    /// name; materializations are re-evaluated in their saved order. Register sugar
+    /// directives the queries use before loading.
+    pub fn load_snapshot(&mut self, dir: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        let dir = dir.as_ref();
+        let manifest = snapshot::read_manifest(dir)?;
+
+        self.ctx.tick = manifest.tick;
+        if manifest.default_tick_column.is_some() {
+            self.ctx.default_tick_column = manifest.default_tick_column;
+        }
+        if manifest.default_partition_key.is_some() {
+            self.ctx.default_partition_key = manifest.default_partition_key;
+        }
+
+        for table in manifest.tables {
+            let df = snapshot::read_table(dir, &table.file)?;
+            self.ctx.dataframes.insert(
+                table.name,
+                crate::eval::DataFrameEntry {
+                    df,
+                    time_series: table.time_series,
+                    scan: None,
+                },
+            );
+        }
+
+        for base in manifest.base_tables {
+            self.register_base_with_retention(base.name.clone(), base.config, base.retention);
+            if let (Some(all), Some(now)) = (base.all, base.now) {
+                let all = snapshot::read_table(dir, &all)?;
+                let now = snapshot::read_table(dir, &now)?;
+                self.ctx
+                    .update_base_table_ptrs(&base.name, all.lazy(), now.lazy());
+            }
+            if let Some(entry) = self.ctx.base_tables.get_mut(&base.name) {
+                entry.evicted = base.evicted;
+            }
+        }
+
+        for (name, query) in manifest.aliases {
+            self.define_alias(name.clone(), query)
+                .map_err(|source| SnapshotError::Query { name, source })?;
+        }
+        for view in manifest.materializations {
+            self.materialize(view.name.clone(), view.query)
+                .map_err(|source| SnapshotError::Query {
+                    name: view.name,
+                    source,
+                })?;
+        }
+        for sub in manifest.subscriptions {
+            self.subscribe_with_mode(sub.name, sub.query, sub.mode);
+        }
+        Ok(())
+    }
+
     /// Run a one-off query without subscribing
     pub fn query(&self, query: &str) -> Result<Value, PiqlError> {
         run(query, &self.ctx)
     }
 
+    /// Prepare a query for repeated runs with [`QueryEngine::run_prepared`]
+    ///
+    /// Prepare again after `define_alias` or changing table configs.
+    pub fn prepare(&self, query: &str) -> Result<PreparedQuery, PiqlError> {
+        crate::prepare(query, &self.ctx)
+    }
+
+    /// Run a prepared query against the current tables and tick
+    pub fn run_prepared(
+        &self,
+        prepared: &PreparedQuery,
+        params: &Params,
+    ) -> Result<Value, PiqlError> {
+        prepared.run_with_params(params, &self.ctx)
+    }
+
     /// Get current tick
     pub fn tick(&self) -> Option<i64> {
         self.ctx.tick
@@ -275,6 +1029,75 @@ impl Default for QueryEngine {
     }
 }
 
+/// Shared state for evaluating subscriptions in one `on_tick()`
+struct SubscriptionTick<'a> {
+    ctx: &'a EvalContext,
+    /// Built by the first incremental subscription that needs it
+    slice_ctx: OnceLock<EvalContext>,
+    appended: &'a HashSet<String>,
+    incremental: bool,
+    /// Subscriptions rewritten to start from a prefix collected this tick
+    shared: Option<&'a SharedPrefixes>,
+}
+
+impl SubscriptionTick<'_> {
+    /// Evaluate one subscription; `None` means there is nothing to emit
+    fn evaluate(
+        &self,
+        name: &str,
+        cached: &mut CachedQuery,
+    ) -> Result<Option<DataFrame>, PiqlError> {
+        cached.get_or_compile(self.ctx)?;
+        let incremental = self.incremental && cached.now_tables.is_some();
+        let unchanged = incremental
+            && cached.last_result.is_some()
+            && !cached
+                .now_tables
+                .iter()
+                .flatten()
+                .any(|table| self.appended.contains(table));
+
+        let current = if unchanged {
+            cached.last_result.clone()
+        } else if incremental {
+            let ctx = self
+                .slice_ctx
+                .get_or_init(|| incremental::slice_context(self.ctx));
+            let result = eval_cached_query(cached, ctx)?;
+            cached.last_result = collect_value_df(result)?;
+            cached.last_result.clone()
+        } else if let Some(shared) = self.shared
+            && let Some(rebased) = shared.rebased.get(name)
+        {
+            collect_value_df(run_compiled(rebased, &shared.ctx)?)?
+        } else {
+            collect_value_df(eval_cached_query(cached, self.ctx)?)?
+        };
+        match current {
+            Some(df) => cached.emit(df),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Remember that materialized table `name` holds the result of `compiled`, unless
+/// the query could give other rows when re-derived: it runs `.as_of(n)`, is not
+/// deterministic (an unseeded `.sample(...)`), or reads a scan-backed table whose
+/// source may change underneath
+fn record_current(ctx: &mut EvalContext, name: &str, compiled: &CompiledQuery) {
+    let tables = compiled.referenced_tables();
+    let rederivable = compiled.as_of().is_none()
+        && crate::optimize::deterministic(compiled.core())
+        && !tables.iter().any(|table| {
+            ctx.dataframes
+                .get(table)
+                .is_some_and(|entry| entry.is_scan())
+        });
+    if rederivable {
+        ctx.record_materialization(name, compiled.core().clone(), &tables);
+    }
+}
+
 fn eval_cached_query(cached: &mut CachedQuery, ctx: &EvalContext) -> Result<Value, PiqlError> {
     let compiled = cached.get_or_compile(ctx)?;
     run_compiled(compiled, ctx)
@@ -7,6 +7,7 @@ use std::collections::HashMap;
 use polars::prelude::*;
 use polars::series::ops::NullBehavior;
 use polars_ops::series::RoundMode;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use crate::ast::core::{CoreArg, Expr};
@@ -29,6 +30,18 @@ pub enum EvalError {
     #[error("Polars error: {0}")]
     Polars(#[from] PolarsError),
 
+    #[error(
+        "{table}.{method}() reaches ticks evicted by the retention policy (history before tick {full_from} was compacted); pass partial=True to use the retained rows"
+    )]
+    Evicted {
+        table: String,
+        method: String,
+        full_from: i64,
+    },
+
+    #[error("Query exceeded a resource limit: {0}")]
+    ResourceLimit(crate::limits::LimitExceeded),
+
     #[error("{0}")]
     Other(String),
 }
@@ -36,6 +49,9 @@ pub enum EvalError {
 type Result<T> = std::result::Result<T, EvalError>;
 
 /// Runtime value produced by evaluation
+// Values are short-lived lazy plans; a dynamic LazyGroupBy carrying its window options
+// is bigger than the other variants, which is fine here
+#[allow(clippy::large_enum_variant)]
 #[derive(Clone)]
 pub enum Value {
     /// A Polars LazyFrame with source lineage metadata
@@ -46,6 +62,10 @@ pub enum Value {
     Expr(polars::prelude::Expr),
     /// A scalar/literal value (for use in expressions)
     Scalar(ScalarValue),
+    /// A collected single-column result (see `EvalContext::with_collapsed_results`)
+    Series(Series),
+    /// The cell of a collected 1x1 result (see `EvalContext::with_collapsed_results`)
+    ScalarResult(Scalar),
     /// The `pl` namespace object
     PlNamespace,
 }
@@ -56,27 +76,53 @@ pub enum DataFrameLineage {
     Table(String),
     /// Derived from a single table source.
     DerivedFrom(String),
-    /// Derived from multiple sources (e.g., join).
-    Ambiguous,
+    /// Derived from multiple sources (e.g., join), sorted by name.
+    Joined(Vec<LineageSource>),
     /// Source is unknown.
     Unknown,
 }
 
+/// One table contributing to a joined dataframe
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineageSource {
+    pub name: String,
+    /// The table's time-series config (None for static tables)
+    pub time_series: Option<TimeSeriesConfig>,
+}
+
 impl DataFrameLineage {
     fn derived(&self) -> Self {
         match self {
             Self::Table(name) | Self::DerivedFrom(name) => Self::DerivedFrom(name.clone()),
-            Self::Ambiguous => Self::Ambiguous,
+            Self::Joined(sources) => Self::Joined(sources.clone()),
             Self::Unknown => Self::Unknown,
         }
     }
 
-    fn source_name(&self) -> Option<&str> {
+    pub(crate) fn source_name(&self) -> Option<&str> {
         match self {
             Self::Table(name) | Self::DerivedFrom(name) => Some(name),
-            Self::Ambiguous | Self::Unknown => None,
+            Self::Joined(_) | Self::Unknown => None,
         }
     }
+
+    /// Lineage of a join of `self` with `other`: the union of both sides' sources
+    fn joined(&self, other: &Self, ctx: &EvalContext) -> Self {
+        let mut sources = Vec::new();
+        for side in [self, other] {
+            match side {
+                Self::Table(name) | Self::DerivedFrom(name) => sources.push(LineageSource {
+                    name: name.clone(),
+                    time_series: ctx.time_series_config_of(name).cloned(),
+                }),
+                Self::Joined(joined) => sources.extend(joined.iter().cloned()),
+                Self::Unknown => {}
+            }
+        }
+        sources.sort_by(|a, b| a.name.cmp(&b.name));
+        sources.dedup_by(|a, b| a.name == b.name);
+        Self::Joined(sources)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -88,21 +134,201 @@ pub enum ScalarValue {
     Null,
 }
 
+/// Type of values stored in a tick column
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TickDtype {
+    /// Integer simulation ticks; scope methods take integer offsets
+    #[default]
+    Int,
+    /// Timestamps; scope methods take datetime strings and duration strings ("-5m", "1h")
+    Datetime,
+}
+
 /// Configuration for time-series dataframes
-#[derive(Debug, Clone)]
+///
+/// Built with [`TimeSeriesConfig::new`], so new settings can be added without
+/// breaking callers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
 pub struct TimeSeriesConfig {
     /// Column name containing tick values
     pub tick_column: String,
     /// Partition key for windowed operations (e.g., "entity_id")
     pub partition_key: String,
+    /// Type of the tick column
+    #[serde(default)]
+    pub tick_dtype: TickDtype,
+}
+
+impl TimeSeriesConfig {
+    /// Integer ticks in `tick_column`, partitioned by `partition_key`
+    pub fn new(tick_column: impl Into<String>, partition_key: impl Into<String>) -> Self {
+        Self {
+            tick_column: tick_column.into(),
+            partition_key: partition_key.into(),
+            tick_dtype: TickDtype::Int,
+        }
+    }
+
+    /// Set the type of the tick column
+    pub fn with_tick_dtype(mut self, tick_dtype: TickDtype) -> Self {
+        self.tick_dtype = tick_dtype;
+        self
+    }
+}
+
+/// How much history a base table keeps
+///
+/// Enforced on integer tick columns by `QueryEngine::append_tick` and
+/// `QueryEngine::compact`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Retention {
+    /// Keep every tick
+    #[default]
+    KeepAll,
+    /// Keep only the latest `n` ticks
+    LastTicks(i64),
+    /// Keep the latest `recent` ticks in full and every `every`-th tick before them
+    Downsample { recent: i64, every: i64 },
+}
+
+/// Ticks a base table has lost to compaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EvictedTicks {
+    /// Earliest tick the table ever held
+    pub first: i64,
+    /// Ticks before this one were compacted
+    pub full_from: i64,
+    /// Compacted ticks that are multiples of this were kept (downsampling)
+    pub kept_every: Option<i64>,
+}
+
+impl EvictedTicks {
+    /// Whether any tick in `lo..=hi` was removed
+    pub fn overlaps(&self, lo: i64, hi: i64) -> bool {
+        let lo = lo.max(self.first);
+        let hi = hi.min(self.full_from - 1);
+        if lo > hi {
+            return false;
+        }
+        match self.kept_every {
+            None => true,
+            Some(every) => every > 1 && (hi > lo || lo.rem_euclid(every) != 0),
+        }
+    }
+}
+
+/// A fresh copy of `scan`. Polars caches the files a scan resolved to (and their
+/// row counts) in the plan, shared by every clone, so a clone would keep reading
+/// what the first collect saw.
+fn reread(scan: &LazyFrame) -> LazyFrame {
+    LazyFrame::from(uncached(&scan.logical_plan))
+}
+
+/// `plan` with the resolved-scan caches of its sources dropped
+fn uncached(plan: &DslPlan) -> DslPlan {
+    let mut plan = match plan {
+        // A plan already converted (e.g. by `collect_schema`) keeps its source DSL
+        DslPlan::IR { dsl, .. } => dsl.as_ref().clone(),
+        plan => plan.clone(),
+    };
+    match &mut plan {
+        DslPlan::Scan { cached_ir, .. } => *cached_ir = Default::default(),
+        DslPlan::Filter { input, .. }
+        | DslPlan::Select { input, .. }
+        | DslPlan::HStack { input, .. }
+        | DslPlan::MapFunction { input, .. }
+        | DslPlan::Slice { input, .. }
+        | DslPlan::Sort { input, .. }
+        | DslPlan::Distinct { input, .. }
+        | DslPlan::Cache { input, .. } => *input = Arc::new(uncached(input)),
+        DslPlan::Union { inputs, .. } => {
+            for input in inputs {
+                *input = uncached(input);
+            }
+        }
+        _ => {}
+    }
+    plan
 }
 
 /// A registered dataframe with optional time-series config
 #[derive(Clone)]
 pub struct DataFrameEntry {
-    /// Materialized DataFrame (collected on insert for fast repeated access)
+    /// Materialized DataFrame (collected on insert for fast repeated access); for a
+    /// scan-backed table, no rows but the scan's columns
     pub df: DataFrame,
     pub time_series: Option<TimeSeriesConfig>,
+    /// Source of a scan-backed table, re-read by every query instead of held in
+    /// memory (see [`EvalContext::with_scan_df`])
+    pub scan: Option<LazyFrame>,
+}
+
+impl DataFrameEntry {
+    /// A table read from `scan` on every access; resolves the scan's schema now
+    pub fn from_scan(
+        mut scan: LazyFrame,
+        time_series: Option<TimeSeriesConfig>,
+    ) -> PolarsResult<Self> {
+        let schema = scan.collect_schema()?;
+        Ok(Self {
+            df: DataFrame::empty_with_schema(&schema),
+            time_series,
+            scan: Some(scan),
+        })
+    }
+
+    /// Whether queries re-read the table from its scan
+    pub fn is_scan(&self) -> bool {
+        self.scan.is_some()
+    }
+
+    /// The table as a LazyFrame: its scan, or the frame held in memory
+    pub fn lazy(&self) -> LazyFrame {
+        match &self.scan {
+            Some(scan) => reread(scan),
+            None => self.df.clone().lazy(),
+        }
+    }
+
+    /// The table's rows, reading them from the scan if scan-backed
+    pub fn collect(&self) -> PolarsResult<DataFrame> {
+        match &self.scan {
+            Some(scan) => reread(scan).collect(),
+            None => Ok(self.df.clone()),
+        }
+    }
+
+    /// Number of rows, counted by the scan if scan-backed
+    pub fn height(&self) -> PolarsResult<usize> {
+        let Some(scan) = &self.scan else {
+            return Ok(self.df.height());
+        };
+        let counted = reread(scan).select([len()]).collect()?;
+        Ok(counted
+            .column("len")?
+            .get(0)?
+            .extract::<usize>()
+            .unwrap_or(0))
+    }
+
+    /// Read a scan-backed table into memory, so queries stop re-reading its source
+    /// (no-op for tables already in memory)
+    pub fn materialize(&mut self) -> PolarsResult<()> {
+        if let Some(scan) = &self.scan {
+            self.df = reread(scan).collect()?;
+            self.scan = None;
+        }
+        Ok(())
+    }
+
+    /// Estimated in-memory size of the table's buffers, in bytes (none for a
+    /// scan-backed table)
+    pub fn estimated_size(&self) -> usize {
+        self.df.estimated_size()
+    }
 }
 
 /// State for a base table tracked in eval context
@@ -114,8 +340,70 @@ pub struct BaseTableEntry {
     pub now: Option<LazyFrame>,
     /// Time-series configuration
     pub config: TimeSeriesConfig,
+    /// History kept by compaction
+    pub retention: Retention,
+    /// Ticks removed by compaction so far (None if nothing was evicted)
+    pub evicted: Option<EvictedTicks>,
+}
+
+/// Identity of a table's stored rows: the column buffers holding them, and
+/// whether a base table has history
+///
+/// Held as weak references, so a version keeps no data alive. Every write gives a
+/// table new buffers: one changed in place moves to a new allocation while weak
+/// references to it exist.
+#[derive(Clone)]
+pub(crate) struct TableVersion {
+    columns: Vec<std::sync::Weak<dyn SeriesTrait>>,
+    has_history: bool,
 }
 
+impl TableVersion {
+    /// Version of table `name` in `ctx` (None if there is no such table)
+    fn of(ctx: &EvalContext, name: &str) -> Option<Self> {
+        let base = ctx.base_tables.get(name);
+        let entry = ctx.dataframes.get(name);
+        if base.is_none() && entry.is_none() {
+            return None;
+        }
+        let columns = entry.map_or_else(Vec::new, |entry| {
+            entry
+                .df
+                .get_columns()
+                .iter()
+                .map(|column| Arc::downgrade(&column.as_materialized_series().0))
+                .collect()
+        });
+        Some(Self {
+            columns,
+            has_history: base.is_some_and(|base| base.all.is_some()),
+        })
+    }
+
+    fn same(&self, other: &Self) -> bool {
+        self.has_history == other.has_history
+            && self.columns.len() == other.columns.len()
+            && self
+                .columns
+                .iter()
+                .zip(&other.columns)
+                .all(|(a, b)| std::ptr::addr_eq(a.as_ptr(), b.as_ptr()))
+    }
+}
+
+/// A materialized table's query, and what its stored rows were derived from
+#[derive(Clone)]
+pub(crate) struct CurrentMaterialization {
+    definition: Expr,
+    /// Tick and default tick column and partition key the query ran with
+    settings: (Option<i64>, Option<String>, Option<String>),
+    /// Versions of the table and of each table the query reads, as it ran
+    versions: Vec<(String, Option<TableVersion>)>,
+}
+
+/// Label column naming the run of each row in `_all::` tables
+pub const DEFAULT_RUN_LABEL_COLUMN: &str = "_run";
+
 /// Evaluation context - holds named dataframes and configuration
 #[derive(Clone)]
 pub struct EvalContext {
@@ -128,8 +416,25 @@ pub struct EvalContext {
     pub default_tick_column: Option<String>,
     /// Default partition key for sugar methods when source table config is unavailable
     pub default_partition_key: Option<String>,
+    /// Label column of `_all::` tables, grouped/partitioned over by `.by_run`
+    pub run_label_column: String,
+    /// Collect results and return single columns as `Value::Series` and single cells
+    /// as `Value::ScalarResult` (off: every result stays a lazy `Value::DataFrame`)
+    pub collapse_results: bool,
+    /// Reorder method chains so filters run before expensive stages (see
+    /// [`crate::advanced::optimize`]); off evaluates queries exactly as written
+    pub optimize: bool,
+    /// Whether [`EvalContext::with_string_cache`] pinned the global categorical
+    /// mapping for the process
+    pub string_cache: bool,
     /// Sugar registry for directive expansion
     pub sugar: crate::sugar::SugarRegistry,
+    /// Saved query snippets referenced as `!name`
+    pub aliases: crate::alias::Aliases,
+    /// Core queries of materialized tables with the versions of the tables they
+    /// were derived from, so filters on them can run inside the query while those
+    /// are unchanged (kept by [`crate::QueryEngine`])
+    pub(crate) current_materializations: HashMap<String, CurrentMaterialization>,
 }
 
 impl EvalContext {
@@ -140,7 +445,13 @@ impl EvalContext {
             tick: None,
             default_tick_column: None,
             default_partition_key: None,
+            run_label_column: DEFAULT_RUN_LABEL_COLUMN.to_string(),
+            collapse_results: false,
+            optimize: true,
+            string_cache: false,
             sugar: crate::sugar::SugarRegistry::new(),
+            aliases: crate::alias::Aliases::new(),
+            current_materializations: HashMap::new(),
         }
     }
 
@@ -152,11 +463,21 @@ impl EvalContext {
             DataFrameEntry {
                 df: collected,
                 time_series: None,
+                scan: None,
             },
         );
         self
     }
 
+    /// Add a regular dataframe read from `df` on every query instead of collected,
+    /// e.g. a `scan_parquet` too large to hold in memory. Queries only read the
+    /// columns and rows they need; [`EvalContext::materialize_df`] collects it.
+    pub fn with_scan_df(mut self, name: impl Into<String>, df: LazyFrame) -> Self {
+        let entry = DataFrameEntry::from_scan(df, None).expect("failed to resolve scan schema");
+        self.dataframes.insert(name.into(), entry);
+        self
+    }
+
     /// Add a pre-collected dataframe
     pub fn with_materialized_df(mut self, name: impl Into<String>, df: DataFrame) -> Self {
         self.dataframes.insert(
@@ -164,6 +485,7 @@ impl EvalContext {
             DataFrameEntry {
                 df,
                 time_series: None,
+                scan: None,
             },
         );
         self
@@ -182,11 +504,23 @@ impl EvalContext {
             DataFrameEntry {
                 df: collected,
                 time_series: Some(config),
+                scan: None,
             },
         );
         self
     }
 
+    /// Read scan-backed table `name` into memory, so queries stop re-reading its
+    /// source (no-op for tables already in memory)
+    pub fn materialize_df(&mut self, name: &str) -> Result<()> {
+        let entry = self
+            .dataframes
+            .get_mut(name)
+            .ok_or_else(|| EvalError::UnknownIdent(name.to_string()))?;
+        entry.materialize()?;
+        Ok(())
+    }
+
     /// Set the current tick for time-based queries
     pub fn with_tick(mut self, tick: i64) -> Self {
         self.tick = Some(tick);
@@ -205,6 +539,64 @@ impl EvalContext {
         self
     }
 
+    /// Set the run label column used by `.by_run`
+    pub fn with_run_label_column(mut self, column: impl Into<String>) -> Self {
+        self.run_label_column = column.into();
+        self
+    }
+
+    /// Turn the plan-level optimizer on (the default) or off, e.g. to debug a query
+    /// as written
+    pub fn with_optimizer(mut self, enabled: bool) -> Self {
+        self.optimize = enabled;
+        self
+    }
+
+    /// Keep the global string cache (the categories behind `cast("categorical")` and
+    /// categorical columns loaded from files) alive for the rest of the process.
+    ///
+    /// Without it the cache is dropped whenever no column uses it, so each query
+    /// casting a string column rebuilds it and category codes change between
+    /// reloads. Enabling is process-wide and can't be undone; `false` only leaves
+    /// an already enabled cache in place.
+    pub fn with_string_cache(mut self, enabled: bool) -> Self {
+        if enabled {
+            enable_string_cache();
+        }
+        self.string_cache |= enabled;
+        self
+    }
+
+    /// Return single-column results as `Value::Series` and single cells as
+    /// `Value::ScalarResult` instead of 1-column/1x1 DataFrames
+    pub fn with_collapsed_results(mut self, collapse: bool) -> Self {
+        self.collapse_results = collapse;
+        self
+    }
+
+    /// Save `query` as a snippet that other queries reference as `!name`.
+    ///
+    /// The text is checked to parse now; references inside it are resolved when a
+    /// query using the alias is compiled.
+    pub fn define_alias(
+        &mut self,
+        name: impl Into<String>,
+        query: impl Into<String>,
+    ) -> std::result::Result<(), crate::ParseError> {
+        let query = query.into();
+        crate::parse::parse(&query)?;
+        self.aliases.insert(name.into(), query);
+        Ok(())
+    }
+
+    /// Prepare `query` against this context's aliases, sugar and table configs
+    pub fn prepare(
+        &self,
+        query: &str,
+    ) -> std::result::Result<crate::PreparedQuery, crate::PiqlError> {
+        crate::prepare(query, self)
+    }
+
     /// Get time-series config for a dataframe (if registered as time-series)
     pub fn get_time_series_config(&self, name: &str) -> Option<&TimeSeriesConfig> {
         self.dataframes
@@ -212,16 +604,29 @@ impl EvalContext {
             .and_then(|entry| entry.time_series.as_ref())
     }
 
+    /// Time-series config of a base table or time-series dataframe
+    pub(crate) fn time_series_config_of(&self, name: &str) -> Option<&TimeSeriesConfig> {
+        self.base_tables
+            .get(name)
+            .map(|entry| &entry.config)
+            .or_else(|| self.get_time_series_config(name))
+    }
+
     /// Build a SugarContext from this EvalContext for a specific dataframe
     pub fn sugar_context(&self, df_name: Option<&str>) -> crate::sugar::SugarContext {
         let partition_key = df_name
             .and_then(|name| self.get_time_series_config(name))
             .map(|ts| ts.partition_key.clone())
             .or_else(|| self.default_partition_key.clone());
+        let tick_column = df_name
+            .and_then(|name| self.get_time_series_config(name))
+            .map(|ts| ts.tick_column.clone())
+            .or_else(|| self.default_tick_column.clone());
 
         crate::sugar::SugarContext {
             tick: self.tick,
             partition_key,
+            tick_column,
         }
     }
 
@@ -233,6 +638,8 @@ impl EvalContext {
                 all: None,
                 now: None,
                 config,
+                retention: Retention::KeepAll,
+                evicted: None,
             },
         );
     }
@@ -249,11 +656,55 @@ impl EvalContext {
                 DataFrameEntry {
                     df: collected,
                     time_series: Some(entry.config.clone()),
+                    scan: None,
                 },
             );
         }
     }
 
+    /// Remember that materialized table `name` holds what `definition`, reading
+    /// `tables`, gives in this context
+    pub(crate) fn record_materialization(
+        &mut self,
+        name: &str,
+        definition: Expr,
+        tables: &[String],
+    ) {
+        let versions = std::iter::once(name)
+            .chain(tables.iter().map(String::as_str))
+            .map(|table| (table.to_string(), TableVersion::of(self, table)))
+            .collect();
+        let current = CurrentMaterialization {
+            definition,
+            settings: self.materialization_settings(),
+            versions,
+        };
+        self.current_materializations
+            .insert(name.to_string(), current);
+    }
+
+    /// The query of materialized table `name`, if its stored rows are still what
+    /// the query gives: neither they nor the tables it reads changed since it ran
+    pub(crate) fn current_definition(&self, name: &str) -> Option<&Expr> {
+        let current = self.current_materializations.get(name)?;
+        let unchanged = current.settings == self.materialization_settings()
+            && current.versions.iter().all(|(table, version)| {
+                match (version, TableVersion::of(self, table)) {
+                    (Some(recorded), Some(now)) => recorded.same(&now),
+                    (recorded, now) => recorded.is_none() && now.is_none(),
+                }
+            });
+        unchanged.then_some(&current.definition)
+    }
+
+    fn materialization_settings(&self) -> (Option<i64>, Option<String>, Option<String>) {
+        (
+            self.tick,
+            self.default_tick_column.clone(),
+            self.default_partition_key.clone(),
+        )
+    }
+
     /// Check if a name is a base table
     pub fn is_base_table(&self, name: &str) -> bool {
         self.base_tables.contains_key(name)
@@ -268,6 +719,27 @@ impl EvalContext {
     pub fn get_base_all(&self, name: &str) -> Option<LazyFrame> {
         self.base_tables.get(name).and_then(|e| e.all.clone())
     }
+
+    /// This context as it was at `tick`, for `.as_of(n)`: the current tick becomes
+    /// `tick`, and base tables with integer ticks drop later rows and see their rows
+    /// at `tick` as `now`
+    pub fn as_of(&self, tick: i64) -> Self {
+        let mut ctx = self.clone();
+        ctx.tick = Some(tick);
+        // Materialized tables hold their rows at the current tick, not at `tick`
+        ctx.current_materializations.clear();
+        for entry in ctx.base_tables.values_mut() {
+            if entry.config.tick_dtype != TickDtype::Int {
+                continue;
+            }
+            if let Some(all) = entry.all.take() {
+                let tick_col = col(entry.config.tick_column.as_str());
+                entry.now = Some(all.clone().filter(tick_col.clone().eq(lit(tick))));
+                entry.all = Some(all.filter(tick_col.lt_eq(lit(tick))));
+            }
+        }
+        ctx
+    }
 }
 
 impl Default for EvalContext {
@@ -307,7 +779,7 @@ fn eval_ident(name: &str, ctx: &EvalContext) -> Result<Value> {
             // Otherwise check regular dataframes
             if let Some(entry) = ctx.dataframes.get(name) {
                 Ok(Value::DataFrame(
-                    entry.df.clone().lazy(),
+                    entry.lazy(),
                     DataFrameLineage::Table(name.to_string()),
                 ))
             } else {
@@ -345,7 +817,7 @@ fn eval_attr(base: &Expr, attr: &str, ctx: &EvalContext) -> Result<Value> {
         Value::Expr(e) => {
             // Namespace markers - these get handled by the subsequent method call
             match attr {
-                "str" | "dt" | "list" => Ok(Value::Expr(e)),
+                "str" | "dt" | "list" | "struct" => Ok(Value::Expr(e)),
                 _ => Err(EvalError::UnknownMethod {
                     target: "Expr".to_string(),
                     method: attr.to_string(),
@@ -364,6 +836,10 @@ fn eval_attr(base: &Expr, attr: &str, ctx: &EvalContext) -> Result<Value> {
             expected: "Expr or DataFrame".to_string(),
             got: "Scalar".to_string(),
         }),
+        Value::Series(_) | Value::ScalarResult(_) => Err(EvalError::TypeError {
+            expected: "Expr or DataFrame".to_string(),
+            got: "collected result".to_string(),
+        }),
     }
 }
 
@@ -391,8 +867,29 @@ fn eval_method_call(
         }
         if namespace == "dt" {
             let e = eval_to_expr(inner_base, ctx)?;
-            return eval_dt_method(e, method);
+            return eval_dt_method(e, method, args);
         }
+        if namespace == "struct" {
+            let e = eval_to_expr(inner_base, ctx)?;
+            return eval_struct_method(e, method, args);
+        }
+    }
+
+    // `cs.numeric()`: column selectors, named after `import polars.selectors as cs`
+    if matches!(base_expr, Expr::Ident(name) if name == "cs") {
+        return eval_selector_function(method, args);
+    }
+
+    // A filter on a materialized table whose rows are current can run inside the
+    // table's query instead; the result keeps the lineage of the table it filters
+    if method == "filter"
+        && ctx.optimize
+        && let Expr::Ident(name) = base_expr
+        && let Some(definition) = ctx.current_definition(name)
+        && let Some(pushed) = crate::optimize::push_into_definition(definition, args, ctx)
+        && let Value::DataFrame(df, _) = eval(&pushed, ctx)?
+    {
+        return Ok(df_value(df, &DataFrameLineage::Table(name.clone())));
     }
 
     let base_val = eval(base_expr, ctx)?;
@@ -405,9 +902,11 @@ fn eval_method_call(
         }
         Value::GroupBy(gb, lineage) => eval_groupby_method(gb, lineage, method, args, ctx),
         Value::Expr(e) => eval_expr_method(e, method, args, ctx),
-        Value::Scalar(_) => Err(EvalError::TypeError {
+        // A literal (e.g. from a directive) used as an expression: `@now.alias("now")`
+        Value::Scalar(_) => eval_expr_method(eval_to_expr(base_expr, ctx)?, method, args, ctx),
+        Value::Series(_) | Value::ScalarResult(_) => Err(EvalError::TypeError {
             expected: "Expr or DataFrame".to_string(),
-            got: "Scalar".to_string(),
+            got: "collected result".to_string(),
         }),
     }
 }
@@ -417,7 +916,15 @@ fn eval_pl_function(name: &str, args: &[CoreArg], ctx: &EvalContext) -> Result<V
         "col" => {
             let col_names = collect_string_args(args)?;
             if col_names.len() == 1 {
+                // col() itself treats "*" and "^...$" as selectors
                 Ok(Value::Expr(col(&col_names[0])))
+            } else if col_names.iter().any(|name| is_regex_pattern(name)) {
+                let selector = col_names
+                    .iter()
+                    .map(|name| name_selector(name, true))
+                    .reduce(|acc, s| acc | s)
+                    .expect("at least one name");
+                Ok(Value::Expr(polars::prelude::Expr::Selector(selector)))
             } else {
                 // cols() returns Selector in polars 0.52+
                 let names: Arc<[PlSmallStr]> =
@@ -443,6 +950,46 @@ fn eval_pl_function(name: &str, args: &[CoreArg], ctx: &EvalContext) -> Result<V
             // pl.len() returns row count expression (like SQL COUNT(*))
             Ok(Value::Expr(polars::prelude::len()))
         }
+        "all" => Ok(Value::Expr(polars::prelude::Expr::Selector(
+            Selector::Wildcard,
+        ))),
+        "exclude" => {
+            let excluded = exclude_selector(args, "exclude")?;
+            Ok(Value::Expr(polars::prelude::Expr::Selector(
+                Selector::Wildcard - excluded,
+            )))
+        }
+        // Row-wise reductions; strings name columns, so `"^res_.*$"` selects by pattern
+        "sum_horizontal" | "max_horizontal" | "min_horizontal" => {
+            let exprs = collect_col_or_expr_args(args, ctx)?;
+            if exprs.is_empty() {
+                return Err(EvalError::ArgError(format!(
+                    "{name}() requires at least one expression"
+                )));
+            }
+            Ok(Value::Expr(horizontal(name, exprs)?))
+        }
+        "concat_str" => {
+            let exprs = collect_col_or_expr_args(args, ctx)?;
+            if exprs.is_empty() {
+                return Err(EvalError::ArgError(
+                    "concat_str() requires at least one expression".to_string(),
+                ));
+            }
+            let separator = get_kwarg_string(args, "separator").unwrap_or_default();
+            let ignore_nulls = get_kwarg_bool(args, "ignore_nulls").unwrap_or(false);
+            Ok(Value::Expr(concat_str(exprs, &separator, ignore_nulls)))
+        }
+        "coalesce" => {
+            // First non-null value across the arguments: pl.coalesce([$a, $b, 0])
+            let exprs = collect_expr_args(args, ctx)?;
+            if exprs.is_empty() {
+                return Err(EvalError::ArgError(
+                    "coalesce() requires at least one expression".to_string(),
+                ));
+            }
+            Ok(Value::Expr(polars::prelude::coalesce(&exprs)))
+        }
         _ => Err(EvalError::UnknownMethod {
             target: "pl".to_string(),
             method: name.to_string(),
@@ -450,6 +997,26 @@ fn eval_pl_function(name: &str, args: &[CoreArg], ctx: &EvalContext) -> Result<V
     }
 }
 
+fn eval_selector_function(name: &str, args: &[CoreArg]) -> Result<Value> {
+    let selector = match name {
+        "all" => Selector::Wildcard,
+        "numeric" => Selector::ByDType(DataTypeSelector::Numeric),
+        "integer" => Selector::ByDType(DataTypeSelector::Integer),
+        "float" => Selector::ByDType(DataTypeSelector::Float),
+        "temporal" => Selector::ByDType(DataTypeSelector::Temporal),
+        "string" => Selector::ByDType(DataTypeSelector::AnyOf([DataType::String].into())),
+        "boolean" => Selector::ByDType(DataTypeSelector::AnyOf([DataType::Boolean].into())),
+        "matches" => Selector::Matches(get_string_arg(args, 0, "matches")?.into()),
+        _ => {
+            return Err(EvalError::UnknownMethod {
+                target: "cs".to_string(),
+                method: name.to_string(),
+            });
+        }
+    };
+    Ok(Value::Expr(polars::prelude::Expr::Selector(selector)))
+}
+
 fn eval_df_method(
     df: LazyFrame,
     lineage: DataFrameLineage,
@@ -465,25 +1032,87 @@ fn eval_df_method(
             Ok(df_value(df.filter(pred_expr), &lineage))
         }
         "select" => {
-            let exprs = collect_expr_args(args, ctx)?;
+            let exprs = collect_named_expr_args(args, ctx)?;
             Ok(df_value(df.select(exprs), &lineage))
         }
         "with_columns" => {
-            let exprs = collect_expr_args(args, ctx)?;
+            let exprs = collect_named_expr_args(args, ctx)?;
             Ok(df_value(df.with_columns(exprs), &lineage))
         }
         "head" => {
-            let n = get_int_arg(args, 0, "head").unwrap_or(10) as u32;
+            let n = row_count(get_int_arg(args, 0, "head").unwrap_or(10), "head")?;
             Ok(df_value(df.limit(n), &lineage))
         }
+        "sample" => {
+            // Every column is sampled with the same seed, so rows stay aligned
+            let seed = Some(sample_seed(args, None, "sample")?);
+            let with_replacement = get_kwarg_bool(args, "with_replacement").unwrap_or(false);
+            let fraction = match get_kwarg_expr(args, "fraction") {
+                None => None,
+                Some(Expr::Literal(Literal::Float(f))) => Some(*f),
+                Some(Expr::Literal(Literal::Int(n))) => Some(*n as f64),
+                Some(_) => {
+                    return Err(EvalError::ArgError(
+                        "sample() fraction must be a number".to_string(),
+                    ));
+                }
+            };
+            let sampled = match (get_kwarg_int(args, "n"), fraction) {
+                (Some(_), Some(_)) => {
+                    return Err(EvalError::ArgError(
+                        "sample() takes either n or fraction, not both".to_string(),
+                    ));
+                }
+                (None, Some(f)) => col("*").sample_frac(lit(f), with_replacement, false, seed),
+                (n, None) => {
+                    let n = match n {
+                        Some(n) => n,
+                        None => get_int_arg(args, 0, "sample")?,
+                    };
+                    if n < 0 {
+                        return Err(EvalError::ArgError(
+                            "sample() n must be non-negative".to_string(),
+                        ));
+                    }
+                    col("*").sample_n(lit(n as u64), with_replacement, false, seed)
+                }
+            };
+            Ok(df_value(df.select([sampled]), &lineage))
+        }
+        "shuffle" => {
+            let seed = Some(sample_seed(args, Some(0), "shuffle")?);
+            Ok(df_value(df.select([col("*").shuffle(seed)]), &lineage))
+        }
         "sort" => {
             let col_names = get_strings_arg(args, 0, "sort")?;
-            let descending = get_kwarg_bool(args, "descending").unwrap_or(false);
-            let opts = SortMultipleOptions::new().with_order_descending(descending);
+            // One `descending` flag for every column, or a list with one per column
+            let opts = match get_kwarg_expr(args, "descending") {
+                Some(Expr::List(items)) => {
+                    let flags = items
+                        .iter()
+                        .map(|item| match item {
+                            Expr::Literal(Literal::Bool(b)) => Ok(*b),
+                            _ => Err(EvalError::ArgError(
+                                "sort() descending must be a bool or a list of bools".to_string(),
+                            )),
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+                    if flags.len() != col_names.len() {
+                        return Err(EvalError::ArgError(format!(
+                            "sort() got {} descending flags for {} columns",
+                            flags.len(),
+                            col_names.len()
+                        )));
+                    }
+                    SortMultipleOptions::new().with_order_descending_multi(flags)
+                }
+                _ => SortMultipleOptions::new()
+                    .with_order_descending(get_kwarg_bool(args, "descending").unwrap_or(false)),
+            };
             Ok(df_value(df.sort(&col_names, opts), &lineage))
         }
         "tail" => {
-            let n = get_int_arg(args, 0, "tail").unwrap_or(10) as u32;
+            let n = row_count(get_int_arg(args, 0, "tail").unwrap_or(10), "tail")?;
             Ok(df_value(df.tail(n), &lineage))
         }
         "drop" => {
@@ -504,6 +1133,15 @@ fn eval_df_method(
             };
             Ok(df_value(df.explode(selector), &lineage))
         }
+        "unnest" => {
+            let col_names = collect_string_args(args)?;
+            let names: Arc<[PlSmallStr]> = col_names.into_iter().map(PlSmallStr::from).collect();
+            let selector = Selector::ByName {
+                names,
+                strict: true,
+            };
+            Ok(df_value(df.unnest(selector, None), &lineage))
+        }
         "drop_nulls" => Ok(df_value(df.drop_nulls(None), &lineage)),
         "reverse" => Ok(df_value(df.reverse(), &lineage)),
         "unique" => {
@@ -541,9 +1179,60 @@ fn eval_df_method(
             ))
         }
         "group_by" => {
-            let col_names = collect_string_args(args)?;
-            let col_exprs: Vec<_> = col_names.iter().map(col).collect();
-            Ok(Value::GroupBy(df.group_by(col_exprs), lineage.derived()))
+            let keys = collect_col_or_expr_args(args, ctx)?;
+            Ok(Value::GroupBy(df.group_by(keys), lineage.derived()))
+        }
+        "by_run" => Ok(Value::GroupBy(
+            df.group_by([col(ctx.run_label_column.as_str())]),
+            lineage.derived(),
+        )),
+        "group_by_dynamic" => {
+            // Time-window groups over a sorted index column; Int columns take "10i"-style durations
+            let index_column = match get_kwarg_expr(args, "index_column") {
+                Some(e) => col_or_expr(e, ctx)?,
+                None => col_or_expr(get_positional_arg(args, 0, "group_by_dynamic")?, ctx)?,
+            };
+            let every =
+                get_duration_arg(args, Some(1), "every", "group_by_dynamic")?.ok_or_else(|| {
+                    EvalError::ArgError(
+                        "group_by_dynamic() requires an 'every' duration".to_string(),
+                    )
+                })?;
+            let period =
+                get_duration_arg(args, Some(2), "period", "group_by_dynamic")?.unwrap_or(every);
+            let offset = get_duration_arg(args, None, "offset", "group_by_dynamic")?
+                .unwrap_or_else(|| Duration::new(0));
+            let closed_window = match get_kwarg_string(args, "closed").as_deref() {
+                None | Some("left") => ClosedWindow::Left,
+                Some("right") => ClosedWindow::Right,
+                Some("both") => ClosedWindow::Both,
+                Some("none") => ClosedWindow::None,
+                Some(other) => {
+                    return Err(EvalError::ArgError(format!(
+                        "Unknown group_by_dynamic closed: {other} (expected left, right, both or none)"
+                    )));
+                }
+            };
+            let by = match get_kwarg_expr(args, "group_by") {
+                Some(Expr::List(items)) => items
+                    .iter()
+                    .map(|e| col_or_expr(e, ctx))
+                    .collect::<Result<Vec<_>>>()?,
+                Some(e) => vec![col_or_expr(e, ctx)?],
+                None => Vec::new(),
+            };
+            let options = DynamicGroupOptions {
+                every,
+                period,
+                offset,
+                closed_window,
+                include_boundaries: get_kwarg_bool(args, "include_boundaries").unwrap_or(false),
+                ..Default::default()
+            };
+            Ok(Value::GroupBy(
+                df.group_by_dynamic(index_column, by, options),
+                lineage.derived(),
+            ))
         }
         "rename" => {
             // Collect kwargs: rename(gold="coins", name="id")
@@ -581,43 +1270,185 @@ fn eval_df_method(
         }
         "window" => {
             // For direct base-table access, scope against `all`; otherwise scope current df.
-            let a = get_int_arg(args, 0, "window")?;
-            let b = get_int_arg(args, 1, "window")?;
-            let tick = ctx
-                .tick
-                .ok_or_else(|| EvalError::Other(".window() requires tick in context".into()))?;
-            let tick_col = resolve_scope_tick_column(&lineage, ctx, "window")?;
+            let (tick_col, tick_dtype) =
+                resolve_scope_tick_column(&df, &lineage, args, ctx, "window")?;
             let target_df = scope_target_df(df, &lineage, ctx, base_is_direct_ident);
 
-            let filtered = target_df.filter(col(&tick_col).is_between(
-                lit(tick + a),
-                lit(tick + b),
-                ClosedInterval::Both,
-            ));
+            let (lower, upper) = match tick_dtype {
+                TickDtype::Int => {
+                    let a = get_int_arg(args, 0, "window")?;
+                    let b = get_int_arg(args, 1, "window")?;
+                    let tick = ctx.tick.ok_or_else(|| {
+                        EvalError::Other(".window() requires tick in context".into())
+                    })?;
+                    check_evicted(
+                        &lineage,
+                        ctx,
+                        base_is_direct_ident,
+                        args,
+                        "window",
+                        tick + a,
+                        tick + b,
+                    )?;
+                    (lit(tick + a), lit(tick + b))
+                }
+                TickDtype::Datetime => {
+                    // Durations are relative to the latest timestamp in the table
+                    let a = get_datetime_scope_arg(args, 0, "window")?;
+                    let b = get_datetime_scope_arg(args, 1, "window")?;
+                    let latest = col(&tick_col).max();
+                    (
+                        latest.clone().dt().offset_by(lit(a)),
+                        latest.dt().offset_by(lit(b)),
+                    )
+                }
+            };
+
+            let filtered =
+                target_df.filter(col(&tick_col).is_between(lower, upper, ClosedInterval::Both));
             Ok(df_value(filtered, &lineage))
         }
         "since" => {
             // For direct base-table access, scope against `all`; otherwise scope current df.
-            let n = get_int_arg(args, 0, "since")?;
-            let tick_col = resolve_scope_tick_column(&lineage, ctx, "since")?;
+            let (tick_col, tick_dtype) =
+                resolve_scope_tick_column(&df, &lineage, args, ctx, "since")?;
+            let bound = scope_tick_bound(args, tick_dtype, "since")?;
+            if tick_dtype == TickDtype::Int {
+                let lo = get_int_arg(args, 0, "since")?;
+                check_evicted(
+                    &lineage,
+                    ctx,
+                    base_is_direct_ident,
+                    args,
+                    "since",
+                    lo,
+                    i64::MAX,
+                )?;
+            }
             let target_df = scope_target_df(df, &lineage, ctx, base_is_direct_ident);
 
-            let filtered = target_df.filter(col(&tick_col).gt_eq(lit(n)));
+            let filtered = target_df.filter(col(&tick_col).gt_eq(bound));
             Ok(df_value(filtered, &lineage))
         }
         "at" => {
             // For direct base-table access, scope against `all`; otherwise scope current df.
-            let n = get_int_arg(args, 0, "at")?;
-            let tick_col = resolve_scope_tick_column(&lineage, ctx, "at")?;
+            let (tick_col, tick_dtype) = resolve_scope_tick_column(&df, &lineage, args, ctx, "at")?;
+            let bound = scope_tick_bound(args, tick_dtype, "at")?;
+            if tick_dtype == TickDtype::Int {
+                let tick = get_int_arg(args, 0, "at")?;
+                check_evicted(&lineage, ctx, base_is_direct_ident, args, "at", tick, tick)?;
+            }
             let target_df = scope_target_df(df, &lineage, ctx, base_is_direct_ident);
 
-            let filtered = target_df.filter(col(&tick_col).eq(lit(n)));
+            let filtered = target_df.filter(col(&tick_col).eq(bound));
             Ok(df_value(filtered, &lineage))
         }
+        // Valid `.as_of(n)` calls are stripped at compile time (see `query::desugar`)
+        "as_of" => Err(EvalError::ArgError(
+            ".as_of() expects an integer tick literal and must be on the query's method chain"
+                .to_string(),
+        )),
+        "latest" => {
+            // Each partition's row(s) at its last tick, across history for base tables
+            let (tick_col, _) = resolve_scope_tick_column(&df, &lineage, args, ctx, "latest")?;
+            let partition_key = resolve_scope_partition_key(&lineage, ctx, "latest")?;
+            let target_df = scope_target_df(df, &lineage, ctx, base_is_direct_ident);
+
+            let last_tick = col(&tick_col).max().over([col(&partition_key)]);
+            Ok(df_value(
+                target_df.filter(col(&tick_col).eq(last_tick)),
+                &lineage,
+            ))
+        }
+        "last_ticks" => {
+            // The n most recent distinct ticks, so gaps in the tick sequence don't count
+            let (tick_col, _) = resolve_scope_tick_column(&df, &lineage, args, ctx, "last_ticks")?;
+            let n = get_int_arg(args, 0, "last_ticks")?;
+            if n < 1 {
+                return Err(EvalError::ArgError(
+                    ".last_ticks() expects a positive tick count".to_string(),
+                ));
+            }
+            let target_df = scope_target_df(df, &lineage, ctx, base_is_direct_ident);
+
+            let recency = col(&tick_col).rank(
+                RankOptions {
+                    method: RankMethod::Dense,
+                    descending: true,
+                },
+                None,
+            );
+            Ok(df_value(target_df.filter(recency.lt_eq(lit(n))), &lineage))
+        }
+        "resample" => {
+            // Bucket ticks per partition, across history for base tables: integer ticks
+            // into every-n buckets, datetime ticks by a duration string; the tick column
+            // becomes the bucket start
+            let (tick_col, tick_dtype) =
+                resolve_scope_tick_column(&df, &lineage, args, ctx, "resample")?;
+            let partition_key = resolve_scope_partition_key(&lineage, ctx, "resample")?;
+            let target_df = scope_target_df(df, &lineage, ctx, base_is_direct_ident);
+            let schema = target_df.clone().collect_schema()?;
+            let bucket = match tick_dtype {
+                TickDtype::Int => {
+                    let n = get_int_arg(args, 0, "resample")?;
+                    if n < 1 {
+                        return Err(EvalError::ArgError(
+                            ".resample() expects a positive tick interval".to_string(),
+                        ));
+                    }
+                    let bucket = col(&tick_col).floor_div(lit(n)) * lit(n);
+                    match schema.get(tick_col.as_str()) {
+                        Some(dtype) => bucket.cast(dtype.clone()),
+                        None => bucket,
+                    }
+                }
+                TickDtype::Datetime => {
+                    let every = get_datetime_scope_arg(args, 0, "resample")?;
+                    col(&tick_col).dt().truncate(lit(every))
+                }
+            };
+            let agg = get_kwarg_string(args, "agg").unwrap_or_else(|| "last".to_string());
+            if !matches!(
+                agg.as_str(),
+                "last" | "first" | "mean" | "sum" | "min" | "max"
+            ) {
+                return Err(EvalError::ArgError(format!(
+                    "Unknown resample agg: {agg} (expected last, first, mean, sum, min or max)"
+                )));
+            }
+
+            let exprs: Vec<polars::prelude::Expr> = schema
+                .iter()
+                .filter(|(name, _)| name.as_str() != tick_col && name.as_str() != partition_key)
+                .map(|(name, dtype)| {
+                    let c = col(name.as_str());
+                    let numeric = dtype.is_primitive_numeric() || dtype.is_float();
+                    // Non-numeric columns keep their last value under mean/sum
+                    match agg.as_str() {
+                        "first" => c.first(),
+                        "mean" if numeric => c.mean(),
+                        "sum" if numeric => c.sum(),
+                        "min" => c.min(),
+                        "max" => c.max(),
+                        _ => c.last(),
+                    }
+                })
+                .collect();
+            let result = target_df
+                .sort([tick_col.as_str()], SortMultipleOptions::default())
+                .group_by([col(&partition_key), bucket.alias(&tick_col)])
+                .agg(exprs)
+                .sort(
+                    [partition_key.as_str(), tick_col.as_str()],
+                    SortMultipleOptions::default(),
+                );
+            Ok(df_value(result, &lineage))
+        }
         // Convenience method
         "top" => {
             // .top(n, col) -> .sort(col, descending=True).head(n)
-            let n = get_int_arg(args, 0, "top")? as u32;
+            let n = row_count(get_int_arg(args, 0, "top")?, "top")?;
             let sort_col = get_string_arg(args, 1, "top")?;
             let opts = SortMultipleOptions::new().with_order_descending(true);
             Ok(df_value(df.sort([sort_col], opts).limit(n), &lineage))
@@ -675,11 +1506,19 @@ fn eval_df_method(
 
             Ok(df_value(result, &lineage))
         }
+        "explain" => {
+            // Plan text, one line per row; the query itself is never collected
+            let optimized = get_kwarg_bool(args, "optimized").unwrap_or(true);
+            let plan = df.explain(optimized)?;
+            let lines: Vec<&str> = plan.lines().collect();
+            let plan_df = DataFrame::new(vec![Column::new("plan".into(), lines)])?;
+            Ok(Value::DataFrame(plan_df.lazy(), DataFrameLineage::Unknown))
+        }
         "join" => {
             // Get the other dataframe (first positional arg)
             let other_expr = get_positional_arg(args, 0, "join")?;
-            let other = match eval(other_expr, ctx)? {
-                Value::DataFrame(lf, _) => lf,
+            let (other, other_lineage) = match eval(other_expr, ctx)? {
+                Value::DataFrame(lf, other_lineage) => (lf, other_lineage),
                 _ => {
                     return Err(EvalError::ArgError(
                         "join() first argument must be a DataFrame".to_string(),
@@ -695,14 +1534,20 @@ fn eval_df_method(
                 "right" => JoinType::Right,
                 "outer" | "full" => JoinType::Full,
                 "cross" => JoinType::Cross,
+                "semi" => JoinType::Semi,
+                "anti" => JoinType::Anti,
                 _ => return Err(EvalError::ArgError(format!("Unknown join type: {how}"))),
             };
+            // Semi/anti joins only filter the left side, so its lineage survives
+            let keeps_left_lineage = matches!(join_type, JoinType::Semi | JoinType::Anti);
+            let join_args = JoinArgs::new(join_type)
+                .with_suffix(get_kwarg_string(args, "suffix").map(PlSmallStr::from));
 
             // Get join columns - supports single string or list
             let result = if let Some(on_cols) = get_kwarg_strings(args, "on") {
                 // Same column name(s) on both sides
                 let on_exprs: Vec<_> = on_cols.iter().map(col).collect();
-                df.join(other, on_exprs.clone(), on_exprs, JoinArgs::new(join_type))
+                df.join(other, on_exprs.clone(), on_exprs, join_args)
             } else {
                 // Different column names
                 let left_cols = get_kwarg_strings(args, "left_on").ok_or_else(|| {
@@ -717,10 +1562,81 @@ fn eval_df_method(
                 })?;
                 let left_exprs: Vec<_> = left_cols.iter().map(col).collect();
                 let right_exprs: Vec<_> = right_cols.iter().map(col).collect();
-                df.join(other, left_exprs, right_exprs, JoinArgs::new(join_type))
+                df.join(other, left_exprs, right_exprs, join_args)
             };
 
-            Ok(Value::DataFrame(result, DataFrameLineage::Ambiguous))
+            if keeps_left_lineage {
+                Ok(df_value(result, &lineage))
+            } else {
+                Ok(Value::DataFrame(
+                    result,
+                    lineage.joined(&other_lineage, ctx),
+                ))
+            }
+        }
+        "join_asof" => {
+            // Nearest-key join; both frames must be sorted by `on` (within each `by` group)
+            let other_expr = get_positional_arg(args, 0, "join_asof")?;
+            let other = match eval(other_expr, ctx)? {
+                Value::DataFrame(lf, _) => lf,
+                _ => {
+                    return Err(EvalError::ArgError(
+                        "join_asof() first argument must be a DataFrame".to_string(),
+                    ));
+                }
+            };
+
+            let on = get_kwarg_string(args, "on").ok_or_else(|| {
+                EvalError::ArgError("join_asof() requires an 'on' kwarg".to_string())
+            })?;
+            let strategy = match get_kwarg_string(args, "strategy").as_deref() {
+                None | Some("backward") => AsofStrategy::Backward,
+                Some("forward") => AsofStrategy::Forward,
+                Some("nearest") => AsofStrategy::Nearest,
+                Some(other) => {
+                    return Err(EvalError::ArgError(format!(
+                        "Unknown join_asof strategy: {other} (expected backward, forward or nearest)"
+                    )));
+                }
+            };
+            let by = get_kwarg_strings(args, "by")
+                .map(|cols| cols.into_iter().map(PlSmallStr::from).collect::<Vec<_>>());
+
+            let mut options = AsOfOptions {
+                strategy,
+                left_by: by.clone(),
+                right_by: by,
+                allow_eq: true,
+                check_sortedness: true,
+                ..Default::default()
+            };
+            match get_kwarg_expr(args, "tolerance") {
+                None => {}
+                Some(Expr::Literal(Literal::Int(n))) => {
+                    options.tolerance = Some(Scalar::from(*n));
+                }
+                Some(Expr::Literal(Literal::Float(f))) => {
+                    options.tolerance = Some(Scalar::from(*f));
+                }
+                Some(Expr::Literal(Literal::String(s))) => {
+                    options.tolerance_str = Some(PlSmallStr::from(s.as_str()));
+                }
+                Some(_) => {
+                    return Err(EvalError::ArgError(
+                        "join_asof() tolerance must be a number or a duration string (e.g. \"5m\")"
+                            .to_string(),
+                    ));
+                }
+            }
+
+            let result = df.join(
+                other,
+                [col(on.as_str())],
+                [col(on.as_str())],
+                JoinArgs::new(JoinType::AsOf(Box::new(options))),
+            );
+            // One output row per left row, so the left side's lineage carries over
+            Ok(df_value(result, &lineage))
         }
         _ => Err(EvalError::UnknownMethod {
             target: "DataFrame".to_string(),
@@ -750,29 +1666,113 @@ fn scope_target_df(
     df
 }
 
-fn resolve_scope_tick_column(
+/// Fail if a scope method on a compacted base table reaches evicted ticks, unless
+/// it was called with `partial=True`
+fn check_evicted(
     lineage: &DataFrameLineage,
     ctx: &EvalContext,
+    base_is_direct_ident: bool,
+    args: &[CoreArg],
     method: &str,
-) -> Result<String> {
-    if matches!(lineage, DataFrameLineage::Ambiguous) {
-        return Err(EvalError::Other(format!(
-            ".{method}() has ambiguous lineage; call .at/.since/.window before joins or configure an explicit tick column"
-        )));
+    lo: i64,
+    hi: i64,
+) -> Result<()> {
+    if !base_is_direct_ident || get_kwarg_bool(args, "partial").unwrap_or(false) {
+        return Ok(());
+    }
+    if let Some(name) = lineage.source_name()
+        && let Some(evicted) = ctx.base_tables.get(name).and_then(|e| e.evicted)
+        && evicted.overlaps(lo, hi)
+    {
+        return Err(EvalError::Evicted {
+            table: name.to_string(),
+            method: method.to_string(),
+            full_from: evicted.full_from,
+        });
     }
+    Ok(())
+}
 
-    if let Some(name) = lineage.source_name() {
-        if let Some(entry) = ctx.base_tables.get(name) {
-            return Ok(entry.config.tick_column.clone());
+/// Tick literal for `.at`/`.since`: an integer, or a datetime string for datetime ticks
+fn scope_tick_bound(
+    args: &[CoreArg],
+    tick_dtype: TickDtype,
+    method: &str,
+) -> Result<polars::prelude::Expr> {
+    match tick_dtype {
+        TickDtype::Int => Ok(lit(get_int_arg(args, 0, method)?)),
+        TickDtype::Datetime => {
+            let s = get_datetime_scope_arg(args, 0, method)?;
+            Ok(lit(s)
+                .str()
+                .to_datetime(None, None, StrptimeOptions::default(), lit("raise")))
         }
+    }
+}
+
+fn get_datetime_scope_arg(args: &[CoreArg], idx: usize, method: &str) -> Result<String> {
+    match get_positional_arg(args, idx, method)? {
+        Expr::Literal(Literal::String(s)) => Ok(s.clone()),
+        _ => Err(EvalError::ArgError(format!(
+            ".{method}() on a datetime tick column expects string arguments (e.g. \"2024-01-01\" or \"-5m\")"
+        ))),
+    }
+}
 
-        if let Some(cfg) = ctx.get_time_series_config(name) {
-            return Ok(cfg.tick_column.clone());
+fn resolve_scope_tick_column(
+    df: &LazyFrame,
+    lineage: &DataFrameLineage,
+    args: &[CoreArg],
+    ctx: &EvalContext,
+    method: &str,
+) -> Result<(String, TickDtype)> {
+    // An explicit `tick_col=`/`on=` bypasses lineage; its dtype comes from the schema
+    if let Some(tick_col) =
+        get_kwarg_string(args, "tick_col").or_else(|| get_kwarg_string(args, "on"))
+    {
+        let dtype = df
+            .clone()
+            .collect_schema()?
+            .get(tick_col.as_str())
+            .cloned()
+            .ok_or_else(|| {
+                EvalError::ArgError(format!(".{method}() tick column '{tick_col}' not found"))
+            })?;
+        let tick_dtype = if matches!(dtype, DataType::Datetime(..)) {
+            TickDtype::Datetime
+        } else {
+            TickDtype::Int
+        };
+        return Ok((tick_col, tick_dtype));
+    }
+
+    if let DataFrameLineage::Joined(sources) = lineage {
+        // Static tables don't constrain the tick column; time-series ones must agree
+        let mut time_series = sources
+            .iter()
+            .filter_map(|source| Some((source.name.as_str(), source.time_series.as_ref()?)));
+        if let Some((first_name, first)) = time_series.next() {
+            for (name, cfg) in time_series {
+                if cfg.tick_column != first.tick_column || cfg.tick_dtype != first.tick_dtype {
+                    return Err(EvalError::Other(format!(
+                        ".{method}() is ambiguous: joined time series '{first_name}' (tick column '{}') and '{name}' (tick column '{}') disagree; call .at/.since/.window before joins",
+                        first.tick_column, cfg.tick_column
+                    )));
+                }
+            }
+            return Ok((first.tick_column.clone(), first.tick_dtype));
         }
     }
 
+    if let Some(cfg) = lineage
+        .source_name()
+        .and_then(|name| ctx.time_series_config_of(name))
+    {
+        return Ok((cfg.tick_column.clone(), cfg.tick_dtype));
+    }
+
     if let Some(default_tick) = &ctx.default_tick_column {
-        return Ok(default_tick.clone());
+        return Ok((default_tick.clone(), TickDtype::Int));
     }
 
     Err(EvalError::Other(format!(
@@ -780,6 +1780,31 @@ fn resolve_scope_tick_column(
     )))
 }
 
+/// Partition key for `.latest()`: the source's configured key, else the context default
+fn resolve_scope_partition_key(
+    lineage: &DataFrameLineage,
+    ctx: &EvalContext,
+    method: &str,
+) -> Result<String> {
+    let configured = match lineage {
+        DataFrameLineage::Joined(sources) => sources
+            .iter()
+            .find_map(|source| source.time_series.as_ref()),
+        _ => lineage
+            .source_name()
+            .and_then(|name| ctx.time_series_config_of(name)),
+    };
+    configured
+        .map(|cfg| cfg.partition_key.clone())
+        .filter(|key| !key.is_empty())
+        .or_else(|| ctx.default_partition_key.clone())
+        .ok_or_else(|| {
+            EvalError::Other(format!(
+                ".{method}() requires a partition key; register a time-series dataframe or set EvalContext::with_default_partition_key(...)"
+            ))
+        })
+}
+
 fn eval_groupby_method(
     gb: LazyGroupBy,
     lineage: DataFrameLineage,
@@ -792,6 +1817,37 @@ fn eval_groupby_method(
             let exprs = collect_expr_args(args, ctx)?;
             Ok(Value::DataFrame(gb.agg(exprs), lineage.derived())) // agg produces new shape
         }
+        // Row count per group
+        "len" | "count" => Ok(Value::DataFrame(
+            gb.agg([polars::prelude::len().alias(method)]),
+            lineage.derived(),
+        )),
+        // Shortcuts aggregating every non-key numeric column
+        "sum" | "mean" | "max" | "min" => {
+            let source = LazyFrame::from(gb.clone()).collect_schema()?;
+            // Aggregating nothing leaves just the key columns
+            let keys = gb
+                .clone()
+                .agg(Vec::<polars::prelude::Expr>::new())
+                .collect_schema()?;
+            let exprs: Vec<polars::prelude::Expr> = source
+                .iter()
+                .filter(|(name, dtype)| {
+                    !keys.contains(name.as_str())
+                        && (dtype.is_primitive_numeric() || dtype.is_float())
+                })
+                .map(|(name, _)| {
+                    let c = col(name.as_str());
+                    match method {
+                        "sum" => c.sum(),
+                        "mean" => c.mean(),
+                        "max" => c.max(),
+                        _ => c.min(),
+                    }
+                })
+                .collect();
+            Ok(Value::DataFrame(gb.agg(exprs), lineage.derived()))
+        }
         _ => Err(EvalError::UnknownMethod {
             target: "GroupBy".to_string(),
             method: method.to_string(),
@@ -815,6 +1871,7 @@ fn eval_expr_method(
             let partition_exprs: Vec<_> = partition_cols.iter().map(col).collect();
             Ok(Value::Expr(e.over(partition_exprs)))
         }
+        "by_run" => Ok(Value::Expr(e.over([col(ctx.run_label_column.as_str())]))),
         "is_between" => {
             let low = eval_to_expr(get_positional_arg(args, 0, "is_between")?, ctx)?;
             let high = eval_to_expr(get_positional_arg(args, 1, "is_between")?, ctx)?;
@@ -827,35 +1884,113 @@ fn eval_expr_method(
         }
         "sum" => Ok(Value::Expr(e.sum())),
         "mean" => Ok(Value::Expr(e.mean())),
+        "std" => Ok(Value::Expr(e.std(ddof_arg(args, "std")?))),
+        "var" => Ok(Value::Expr(e.var(ddof_arg(args, "var")?))),
         "min" => Ok(Value::Expr(e.min())),
         "max" => Ok(Value::Expr(e.max())),
         "count" => Ok(Value::Expr(e.count())),
         "first" => Ok(Value::Expr(e.first())),
         "last" => Ok(Value::Expr(e.last())),
         "cast" => {
-            // Simple cast support - just integers and floats for now
+            // Strict by default like Polars: values that don't fit raise instead of nulling
             let type_name = get_string_arg(args, 0, "cast")?;
-            let dtype = match type_name.as_str() {
-                "int" | "i64" => DataType::Int64,
-                "float" | "f64" => DataType::Float64,
-                "str" | "string" => DataType::String,
-                "bool" => DataType::Boolean,
-                _ => {
+            let dtype = parse_dtype(&type_name)?;
+            if get_kwarg_bool(args, "strict").unwrap_or(true) {
+                Ok(Value::Expr(e.strict_cast(dtype)))
+            } else {
+                Ok(Value::Expr(e.cast(dtype)))
+            }
+        }
+        "fill_null" => {
+            // fill_null(value) or fill_null(strategy="forward", limit=n)
+            let Some(strategy) = get_kwarg_string(args, "strategy") else {
+                let fill_val = eval_to_expr(get_positional_arg(args, 0, "fill_null")?, ctx)?;
+                return Ok(Value::Expr(e.fill_null(fill_val)));
+            };
+            let limit = match get_kwarg_expr(args, "limit") {
+                None => None,
+                Some(Expr::Literal(Literal::Int(n))) if *n >= 0 => Some(*n as IdxSize),
+                Some(_) => {
+                    return Err(EvalError::ArgError(
+                        "fill_null() limit must be a non-negative integer".to_string(),
+                    ));
+                }
+            };
+            let strategy = match strategy.as_str() {
+                "forward" => FillNullStrategy::Forward(limit),
+                "backward" => FillNullStrategy::Backward(limit),
+                "mean" => FillNullStrategy::Mean,
+                "min" => FillNullStrategy::Min,
+                "max" => FillNullStrategy::Max,
+                "zero" => FillNullStrategy::Zero,
+                "one" => FillNullStrategy::One,
+                other => {
                     return Err(EvalError::ArgError(format!(
-                        "Unknown type for cast: {type_name}"
+                        "Unknown fill_null strategy: {other} (expected forward, backward, mean, min, max, zero or one)"
                     )));
                 }
             };
-            Ok(Value::Expr(e.cast(dtype)))
-        }
-        "fill_null" => {
-            let fill_val = eval_to_expr(get_positional_arg(args, 0, "fill_null")?, ctx)?;
-            Ok(Value::Expr(e.fill_null(fill_val)))
+            Ok(Value::Expr(e.fill_null_with_strategy(strategy)))
         }
         "is_null" => Ok(Value::Expr(e.is_null())),
         "is_not_null" => Ok(Value::Expr(e.is_not_null())),
+        // pl.all().exclude("id"), cs.numeric().exclude("tick")
+        "exclude" => match e {
+            polars::prelude::Expr::Selector(selector) => {
+                let excluded = exclude_selector(args, "exclude")?;
+                Ok(Value::Expr(polars::prelude::Expr::Selector(
+                    selector - excluded,
+                )))
+            }
+            _ => Err(EvalError::ArgError(
+                "exclude() applies to selectors like pl.all() or cs.numeric()".to_string(),
+            )),
+        },
+        "is_in" => {
+            // A literal list is a fixed set; any other expression contributes all its values
+            let other = match get_positional_arg(args, 0, "is_in")? {
+                Expr::List(items) if items.is_empty() => {
+                    // Nothing is in the empty set, but keep the column's shape
+                    return Ok(Value::Expr(e.is_null().and(lit(false))));
+                }
+                Expr::List(items) => lit(literal_list_series(items, "is_in")?).implode(),
+                other => eval_to_expr(other, ctx)?.implode(),
+            };
+            let nulls_equal = get_kwarg_bool(args, "nulls_equal").unwrap_or(false);
+            Ok(Value::Expr(e.is_in(other, nulls_equal)))
+        }
+        "is_duplicated" => Ok(Value::Expr(e.is_duplicated())),
+        "is_first_distinct" => Ok(Value::Expr(e.is_first_distinct())),
+        "any" => {
+            let ignore_nulls = get_kwarg_bool(args, "ignore_nulls").unwrap_or(true);
+            Ok(Value::Expr(e.any(ignore_nulls)))
+        }
+        "all" => {
+            let ignore_nulls = get_kwarg_bool(args, "ignore_nulls").unwrap_or(true);
+            Ok(Value::Expr(e.all(ignore_nulls)))
+        }
         "unique" => Ok(Value::Expr(e.unique())),
         "abs" => Ok(Value::Expr(e.abs())),
+        "pow" => {
+            let exponent = eval_to_expr(get_positional_arg(args, 0, "pow")?, ctx)?;
+            Ok(Value::Expr(e.pow(exponent)))
+        }
+        "sqrt" => Ok(Value::Expr(e.sqrt())),
+        "log" => {
+            // Natural log unless a base is given: log(), log(10), log(base=2)
+            let base = match get_kwarg_expr(args, "base") {
+                Some(base) => eval_to_expr(base, ctx)?,
+                None => match get_positional_arg(args, 0, "log") {
+                    Ok(base) => eval_to_expr(base, ctx)?,
+                    Err(_) => lit(std::f64::consts::E),
+                },
+            };
+            Ok(Value::Expr(e.log(base)))
+        }
+        "exp" => Ok(Value::Expr(e.exp())),
+        "floor" => Ok(Value::Expr(e.floor())),
+        "ceil" => Ok(Value::Expr(e.ceil())),
+        "sign" => Ok(Value::Expr(e.sign())),
         "round" => {
             let decimals = get_int_arg(args, 0, "round")? as u32;
             Ok(Value::Expr(e.round(decimals, RoundMode::HalfToEven)))
@@ -866,6 +2001,23 @@ fn eval_expr_method(
         "cum_max" => Ok(Value::Expr(e.cum_max(false))),
         "cum_min" => Ok(Value::Expr(e.cum_min(false))),
         "rank" => Ok(Value::Expr(e.rank(Default::default(), None))),
+        "div_or" => {
+            // a.div_or(b, default): a / b, or default where b is zero or null
+            let denom = eval_to_expr(get_positional_arg(args, 0, "div_or")?, ctx)?;
+            let default = eval_to_expr(get_positional_arg(args, 1, "div_or")?, ctx)?;
+            Ok(Value::Expr(
+                when(denom.clone().neq(lit(0)))
+                    .then(e / denom)
+                    .otherwise(default),
+            ))
+        }
+        "nan_to_null" => Ok(Value::Expr(e.fill_nan(lit(NULL)))),
+        "fill_nan" => {
+            let fill_val = eval_to_expr(get_positional_arg(args, 0, "fill_nan")?, ctx)?;
+            Ok(Value::Expr(e.fill_nan(fill_val)))
+        }
+        "is_nan" => Ok(Value::Expr(e.is_nan())),
+        "drop_nans" => Ok(Value::Expr(e.drop_nans())),
         "clip" => {
             let min_val = eval_to_expr(get_positional_arg(args, 0, "clip")?, ctx)?;
             let max_val = eval_to_expr(get_positional_arg(args, 1, "clip")?, ctx)?;
@@ -895,17 +2047,100 @@ fn eval_str_method(e: polars::prelude::Expr, method: &str, args: &[CoreArg]) ->
         "len_chars" => Ok(Value::Expr(str_ns.len_chars())),
         "contains" => {
             let pattern = get_string_arg(args, 0, "contains")?;
-            Ok(Value::Expr(str_ns.contains(lit(pattern), false)))
+            if get_kwarg_bool(args, "literal").unwrap_or(false) {
+                Ok(Value::Expr(str_ns.contains_literal(lit(pattern))))
+            } else {
+                Ok(Value::Expr(str_ns.contains(lit(pattern), false)))
+            }
         }
         "replace" => {
             let pattern = get_string_arg(args, 0, "replace")?;
             let replacement = get_string_arg(args, 1, "replace")?;
+            let literal = get_kwarg_bool(args, "literal").unwrap_or(false);
             Ok(Value::Expr(str_ns.replace(
                 lit(pattern),
                 lit(replacement),
-                false,
+                literal,
             )))
         }
+        "extract" => {
+            // extract(pattern, group=1) - group 0 is the whole match
+            let pattern = get_string_arg(args, 0, "extract")?;
+            let group = match args.get(1) {
+                Some(Arg::Positional(_)) => get_int_arg(args, 1, "extract")?,
+                _ => get_kwarg_int(args, "group").unwrap_or(1),
+            };
+            if group < 0 {
+                return Err(EvalError::ArgError(
+                    "extract() group index must be non-negative".to_string(),
+                ));
+            }
+            Ok(Value::Expr(str_ns.extract(lit(pattern), group as usize)))
+        }
+        "extract_all" => {
+            let pattern = get_string_arg(args, 0, "extract_all")?;
+            Ok(Value::Expr(str_ns.extract_all(lit(pattern))))
+        }
+        "count_matches" => {
+            let pattern = get_string_arg(args, 0, "count_matches")?;
+            let literal = get_kwarg_bool(args, "literal").unwrap_or(false);
+            Ok(Value::Expr(str_ns.count_matches(lit(pattern), literal)))
+        }
+        "strip_chars" => {
+            // No argument strips whitespace
+            let chars = match args.first() {
+                Some(Arg::Positional(_)) => lit(get_string_arg(args, 0, "strip_chars")?),
+                _ => lit(NULL),
+            };
+            Ok(Value::Expr(str_ns.strip_chars(chars)))
+        }
+        "split" => {
+            let delim = get_string_arg(args, 0, "split")?;
+            Ok(Value::Expr(str_ns.split(lit(delim))))
+        }
+        "zfill" => {
+            let length = get_int_arg(args, 0, "zfill")?;
+            Ok(Value::Expr(str_ns.zfill(lit(length))))
+        }
+        "pad_start" => {
+            let length = get_int_arg(args, 0, "pad_start")?;
+            let fill_char = match get_kwarg_string(args, "fill_char") {
+                Some(s) => single_char(&s, "pad_start")?,
+                None if args.len() > 1 => {
+                    single_char(&get_string_arg(args, 1, "pad_start")?, "pad_start")?
+                }
+                None => ' ',
+            };
+            Ok(Value::Expr(str_ns.pad_start(lit(length), fill_char)))
+        }
+        "to_datetime" => {
+            // to_datetime() infers the format; to_datetime("%Y-%m-%d %H:%M:%S") is explicit
+            let options = strptime_options(args, "to_datetime", 0)?;
+            Ok(Value::Expr(str_ns.to_datetime(
+                None,
+                None,
+                options,
+                lit("raise"),
+            )))
+        }
+        "to_date" => {
+            let options = strptime_options(args, "to_date", 0)?;
+            Ok(Value::Expr(str_ns.to_date(options)))
+        }
+        "strptime" => {
+            // strptime("datetime" | "date", format)
+            let dtype = match get_string_arg(args, 0, "strptime")?.as_str() {
+                "datetime" => DataType::Datetime(TimeUnit::Microseconds, None),
+                "date" => DataType::Date,
+                other => {
+                    return Err(EvalError::ArgError(format!(
+                        "strptime() dtype must be \"datetime\" or \"date\", got \"{other}\""
+                    )));
+                }
+            };
+            let options = strptime_options(args, "strptime", 1)?;
+            Ok(Value::Expr(str_ns.strptime(dtype, options, lit("raise"))))
+        }
         "slice" => {
             let offset = get_int_arg(args, 0, "slice")?;
             let length = get_int_arg(args, 1, "slice")? as u64;
@@ -918,7 +2153,40 @@ fn eval_str_method(e: polars::prelude::Expr, method: &str, args: &[CoreArg]) ->
     }
 }
 
-fn eval_dt_method(e: polars::prelude::Expr, method: &str) -> Result<Value> {
+fn strptime_options(args: &[CoreArg], fn_name: &str, format_idx: usize) -> Result<StrptimeOptions> {
+    let format = match args.get(format_idx) {
+        Some(Arg::Positional(_)) => Some(get_string_arg(args, format_idx, fn_name)?),
+        _ => get_kwarg_string(args, "format"),
+    };
+    Ok(StrptimeOptions {
+        format: format.map(PlSmallStr::from),
+        strict: get_kwarg_bool(args, "strict").unwrap_or(true),
+        ..Default::default()
+    })
+}
+
+fn parse_time_unit(unit: &str) -> Result<TimeUnit> {
+    match unit {
+        "ns" => Ok(TimeUnit::Nanoseconds),
+        "us" => Ok(TimeUnit::Microseconds),
+        "ms" => Ok(TimeUnit::Milliseconds),
+        _ => Err(EvalError::ArgError(format!(
+            "Unknown time unit '{unit}' (expected ns, us or ms)"
+        ))),
+    }
+}
+
+fn single_char(s: &str, fn_name: &str) -> Result<char> {
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ => Err(EvalError::ArgError(format!(
+            "{fn_name}() fill character must be a single character"
+        ))),
+    }
+}
+
+fn eval_dt_method(e: polars::prelude::Expr, method: &str, args: &[CoreArg]) -> Result<Value> {
     let dt_ns = e.dt();
     match method {
         "year" => Ok(Value::Expr(dt_ns.year())),
@@ -927,6 +2195,27 @@ fn eval_dt_method(e: polars::prelude::Expr, method: &str) -> Result<Value> {
         "hour" => Ok(Value::Expr(dt_ns.hour())),
         "minute" => Ok(Value::Expr(dt_ns.minute())),
         "second" => Ok(Value::Expr(dt_ns.second())),
+        // Monday = 1, Sunday = 7
+        "weekday" => Ok(Value::Expr(dt_ns.weekday())),
+        "date" => Ok(Value::Expr(dt_ns.date())),
+        "timestamp" => {
+            // timestamp() or timestamp("ms") - defaults to microseconds like Polars
+            let unit = match args.first() {
+                Some(Arg::Positional(_)) => get_string_arg(args, 0, "timestamp")?,
+                _ => get_kwarg_string(args, "time_unit").unwrap_or_else(|| "us".to_string()),
+            };
+            Ok(Value::Expr(dt_ns.timestamp(parse_time_unit(&unit)?)))
+        }
+        "truncate" => {
+            // Duration string like "1h", "15m", "1d"
+            let every = get_string_arg(args, 0, "truncate")?;
+            Ok(Value::Expr(dt_ns.truncate(lit(every))))
+        }
+        "offset_by" => {
+            // Duration string like "-3d", "2h30m", "1mo"
+            let by = get_string_arg(args, 0, "offset_by")?;
+            Ok(Value::Expr(dt_ns.offset_by(lit(by))))
+        }
         _ => Err(EvalError::UnknownMethod {
             target: "dt".to_string(),
             method: method.to_string(),
@@ -934,6 +2223,22 @@ fn eval_dt_method(e: polars::prelude::Expr, method: &str) -> Result<Value> {
     }
 }
 
+fn eval_struct_method(e: polars::prelude::Expr, method: &str, args: &[CoreArg]) -> Result<Value> {
+    let struct_ns = e.struct_();
+    match method {
+        "field" => {
+            let name = get_string_arg(args, 0, "field")?;
+            Ok(Value::Expr(struct_ns.field_by_name(&name)))
+        }
+        // Expands to one column per field (only valid in select/with_columns)
+        "unnest" => Ok(Value::Expr(struct_ns.field_by_name("*"))),
+        _ => Err(EvalError::UnknownMethod {
+            target: "struct".to_string(),
+            method: method.to_string(),
+        }),
+    }
+}
+
 fn eval_binop(lhs: &Expr, op: BinOp, rhs: &Expr, ctx: &EvalContext) -> Result<Value> {
     let l = eval_to_expr(lhs, ctx)?;
     let r = eval_to_expr(rhs, ctx)?;
@@ -944,6 +2249,7 @@ fn eval_binop(lhs: &Expr, op: BinOp, rhs: &Expr, ctx: &EvalContext) -> Result<Va
         BinOp::Mul => l * r,
         BinOp::Div => l / r,
         BinOp::Mod => l % r,
+        BinOp::FloorDiv => l.floor_div(r),
         BinOp::Eq => l.eq(r),
         BinOp::Ne => l.neq(r),
         BinOp::Lt => l.lt(r),
@@ -1028,6 +2334,10 @@ fn eval_to_expr(expr: &Expr, ctx: &EvalContext) -> Result<polars::prelude::Expr>
             expected: "Expr".to_string(),
             got: "pl namespace".to_string(),
         }),
+        Value::Series(_) | Value::ScalarResult(_) => Err(EvalError::TypeError {
+            expected: "Expr".to_string(),
+            got: "collected result".to_string(),
+        }),
     }
 }
 
@@ -1041,6 +2351,217 @@ fn scalar_to_lit(s: ScalarValue) -> polars::prelude::Expr {
     }
 }
 
+/// Row-wise sum/max/min, ignoring nulls like Polars. Gathers each row into a list
+/// (which also expands selectors) rather than calling Polars' `*_horizontal`
+/// builders, which are only reachable through ambiguous glob re-exports.
+fn horizontal(name: &str, exprs: Vec<polars::prelude::Expr>) -> Result<polars::prelude::Expr> {
+    let row = concat_list(exprs)?.list();
+    Ok(match name {
+        "sum_horizontal" => row.sum(),
+        "max_horizontal" => row.max(),
+        _ => row.min(),
+    })
+}
+
+/// `"^gold_.*$"`-style names, which Polars matches as regular expressions
+fn is_regex_pattern(name: &str) -> bool {
+    name.starts_with('^') && name.ends_with('$')
+}
+
+/// Selector for a column name or `^...$` pattern
+fn name_selector(name: &str, strict: bool) -> Selector {
+    if is_regex_pattern(name) {
+        Selector::Matches(name.into())
+    } else {
+        Selector::ByName {
+            names: [PlSmallStr::from(name)].into(),
+            strict,
+        }
+    }
+}
+
+/// Union of the columns named by `args`; missing names are ignored
+fn exclude_selector(args: &[CoreArg], fn_name: &str) -> Result<Selector> {
+    collect_string_args(args)?
+        .iter()
+        .map(|name| name_selector(name, false))
+        .reduce(|acc, s| acc | s)
+        .ok_or_else(|| {
+            EvalError::ArgError(format!("{fn_name}() requires at least one column name"))
+        })
+}
+
+/// A Series from a list of literals: `[1, 2]`, `["a", "b"]`; ints widen to floats
+/// when mixed, and `null` is allowed anywhere
+fn literal_list_series(items: &[Expr], fn_name: &str) -> Result<Series> {
+    let lits = items
+        .iter()
+        .map(|e| match e {
+            Expr::Literal(l) => Ok(l),
+            _ => Err(EvalError::ArgError(format!(
+                "{fn_name}() list items must be literals"
+            ))),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let all = |pred: fn(&Literal) -> bool| lits.iter().all(|l| pred(l) || **l == Literal::Null);
+    let name = PlSmallStr::EMPTY;
+
+    let series = if all(|l| matches!(l, Literal::Int(_))) {
+        let values: Vec<Option<i64>> = lits
+            .iter()
+            .map(|l| match l {
+                Literal::Int(n) => Some(*n),
+                _ => None,
+            })
+            .collect();
+        Series::new(name, values)
+    } else if all(|l| matches!(l, Literal::Int(_) | Literal::Float(_))) {
+        let values: Vec<Option<f64>> = lits
+            .iter()
+            .map(|l| match l {
+                Literal::Int(n) => Some(*n as f64),
+                Literal::Float(f) => Some(*f),
+                _ => None,
+            })
+            .collect();
+        Series::new(name, values)
+    } else if all(|l| matches!(l, Literal::String(_))) {
+        let values: Vec<Option<&str>> = lits
+            .iter()
+            .map(|l| match l {
+                Literal::String(s) => Some(s.as_str()),
+                _ => None,
+            })
+            .collect();
+        Series::new(name, values)
+    } else if all(|l| matches!(l, Literal::Bool(_))) {
+        let values: Vec<Option<bool>> = lits
+            .iter()
+            .map(|l| match l {
+                Literal::Bool(b) => Some(*b),
+                _ => None,
+            })
+            .collect();
+        Series::new(name, values)
+    } else {
+        return Err(EvalError::ArgError(format!(
+            "{fn_name}() list items must all have the same type"
+        )));
+    };
+    Ok(series)
+}
+
+/// Dtype names accepted by `cast`: `"i32"`, `"u8"`, `"f32"`, `"date"`, `"datetime[ms]"`, ...
+pub fn parse_dtype(name: &str) -> Result<DataType> {
+    let time_unit = |unit: Option<&str>| match unit {
+        None | Some("us") => Ok(TimeUnit::Microseconds),
+        Some("ms") => Ok(TimeUnit::Milliseconds),
+        Some("ns") => Ok(TimeUnit::Nanoseconds),
+        Some(other) => Err(EvalError::ArgError(format!(
+            "Unknown time unit for cast: {other} (expected ms, us or ns)"
+        ))),
+    };
+    // `datetime[ms]` -> ("datetime", Some("ms"))
+    let (base, unit) = match name.split_once('[') {
+        Some((base, rest)) => match rest.strip_suffix(']') {
+            Some(unit) => (base, Some(unit.trim())),
+            None => (name, None),
+        },
+        None => (name, None),
+    };
+    let dtype = match (base, unit) {
+        ("int" | "i64" | "int64", None) => DataType::Int64,
+        ("i32" | "int32", None) => DataType::Int32,
+        ("i16" | "int16", None) => DataType::Int16,
+        ("i8" | "int8", None) => DataType::Int8,
+        ("u64" | "uint64", None) => DataType::UInt64,
+        ("u32" | "uint32", None) => DataType::UInt32,
+        ("u16" | "uint16", None) => DataType::UInt16,
+        ("u8" | "uint8", None) => DataType::UInt8,
+        ("float" | "f64" | "float64", None) => DataType::Float64,
+        ("f32" | "float32", None) => DataType::Float32,
+        ("str" | "string" | "utf8", None) => DataType::String,
+        ("bool" | "boolean", None) => DataType::Boolean,
+        ("date", None) => DataType::Date,
+        ("time", None) => DataType::Time,
+        ("datetime", unit) => DataType::Datetime(time_unit(unit)?, None),
+        ("duration", unit) => DataType::Duration(time_unit(unit)?),
+        ("categorical" | "cat", None) => DataType::from_categories(Categories::global()),
+        // `enum[buy, sell]`: a fixed set of categories, in order
+        ("enum", Some(categories)) => {
+            let categories = categories.split(',').map(str::trim);
+            DataType::from_frozen_categories(FrozenCategories::new(categories)?)
+        }
+        _ => {
+            return Err(EvalError::ArgError(format!(
+                "Unknown type for cast: {name}"
+            )));
+        }
+    };
+    Ok(dtype)
+}
+
+/// Global categorical mapping, held for the process once the string cache is enabled
+static STRING_CACHE: std::sync::OnceLock<Arc<CategoricalMapping>> = std::sync::OnceLock::new();
+
+/// Keep the global categorical mapping alive for the rest of the process
+pub fn enable_string_cache() {
+    STRING_CACHE.get_or_init(|| Categories::global().mapping());
+}
+
+/// Whether [`enable_string_cache`] has been called
+pub fn using_string_cache() -> bool {
+    STRING_CACHE.get().is_some()
+}
+
+/// String literals name columns (as in Polars' `group_by("a")`), anything else is an
+/// expression
+fn col_or_expr(e: &Expr, ctx: &EvalContext) -> Result<polars::prelude::Expr> {
+    match e {
+        Expr::Literal(Literal::String(name)) => Ok(col(name.as_str())),
+        _ => eval_to_expr(e, ctx),
+    }
+}
+
+/// Positional column names or expressions, flattening lists: `group_by("a", $b // 10)`
+fn collect_col_or_expr_args(
+    args: &[CoreArg],
+    ctx: &EvalContext,
+) -> Result<Vec<polars::prelude::Expr>> {
+    let mut keys = Vec::new();
+    for arg in args {
+        if let Arg::Positional(e) = arg {
+            if let Expr::List(items) = e {
+                for item in items {
+                    keys.push(col_or_expr(item, ctx)?);
+                }
+            } else {
+                keys.push(col_or_expr(e, ctx)?);
+            }
+        }
+    }
+    Ok(keys)
+}
+
+/// A duration string (`"1h"`, `"10i"`) given as kwarg `name` or positionally at `idx`
+fn get_duration_arg(
+    args: &[CoreArg],
+    idx: Option<usize>,
+    name: &str,
+    fn_name: &str,
+) -> Result<Option<Duration>> {
+    let expr = get_kwarg_expr(args, name).or_else(|| get_positional_arg(args, idx?, fn_name).ok());
+    match expr {
+        None => Ok(None),
+        Some(Expr::Literal(Literal::String(s))) => Duration::try_parse(s)
+            .map(Some)
+            .map_err(|e| EvalError::ArgError(format!("{fn_name}() {name}: {e}"))),
+        Some(_) => Err(EvalError::ArgError(format!(
+            "{fn_name}() {name} must be a duration string like \"1h\" or \"10i\""
+        ))),
+    }
+}
+
 fn collect_expr_args(args: &[CoreArg], ctx: &EvalContext) -> Result<Vec<polars::prelude::Expr>> {
     let mut exprs = Vec::new();
 
@@ -1064,6 +2585,20 @@ fn collect_expr_args(args: &[CoreArg], ctx: &EvalContext) -> Result<Vec<polars::
     Ok(exprs)
 }
 
+/// Like [`collect_expr_args`], with each keyword arg `name=expr` as `expr.alias(name)`
+fn collect_named_expr_args(
+    args: &[CoreArg],
+    ctx: &EvalContext,
+) -> Result<Vec<polars::prelude::Expr>> {
+    let mut exprs = collect_expr_args(args, ctx)?;
+    for arg in args {
+        if let Arg::Keyword(name, e) = arg {
+            exprs.push(eval_to_expr(e, ctx)?.alias(name.as_str()));
+        }
+    }
+    Ok(exprs)
+}
+
 fn get_positional_arg<'a>(args: &'a [CoreArg], idx: usize, fn_name: &str) -> Result<&'a Expr> {
     let mut pos_idx = 0;
     for arg in args {
@@ -1138,7 +2673,7 @@ fn get_strings_arg(args: &[CoreArg], idx: usize, fn_name: &str) -> Result<Vec<St
     )))
 }
 
-fn get_int_arg(args: &[CoreArg], idx: usize, fn_name: &str) -> Result<i64> {
+pub(crate) fn get_int_arg(args: &[CoreArg], idx: usize, fn_name: &str) -> Result<i64> {
     let expr = get_positional_arg(args, idx, fn_name)?;
     match expr {
         Expr::Literal(Literal::Int(n)) => Ok(*n),
@@ -1158,6 +2693,64 @@ fn get_int_arg(args: &[CoreArg], idx: usize, fn_name: &str) -> Result<i64> {
     }
 }
 
+/// A row count for `head`/`tail`/`top`; a plain `as u32` would turn -1 into "every row"
+fn row_count(n: i64, fn_name: &str) -> Result<u32> {
+    u32::try_from(n).map_err(|_| {
+        EvalError::ArgError(format!(
+            "{fn_name}() row count must be between 0 and {}, got {n}",
+            u32::MAX
+        ))
+    })
+}
+
+/// The `ddof=` kwarg (default 1), which polars takes as a `u8`
+fn ddof_arg(args: &[CoreArg], fn_name: &str) -> Result<u8> {
+    let ddof = get_kwarg_int(args, "ddof").unwrap_or(1);
+    u8::try_from(ddof).map_err(|_| {
+        EvalError::ArgError(format!(
+            "{fn_name}() ddof must be between 0 and 255, got {ddof}"
+        ))
+    })
+}
+
+fn get_kwarg_int(args: &[CoreArg], name: &str) -> Option<i64> {
+    for arg in args {
+        if let Arg::Keyword(k, v) = arg
+            && k == name
+        {
+            return match v {
+                Expr::Literal(Literal::Int(n)) => Some(*n),
+                // -3 parses as UnaryOp(Neg, Int(3))
+                Expr::UnaryOp(crate::ast::UnaryOp::Neg, inner) => match inner.as_ref() {
+                    Expr::Literal(Literal::Int(n)) => Some(-n),
+                    _ => None,
+                },
+                _ => None,
+            };
+        }
+    }
+    None
+}
+
+/// The `seed=` kwarg (or positional arg `idx`), or a fresh random seed. Columns are
+/// sampled independently, so they must all share one seed.
+fn sample_seed(args: &[CoreArg], idx: Option<usize>, fn_name: &str) -> Result<u64> {
+    let expr =
+        get_kwarg_expr(args, "seed").or_else(|| get_positional_arg(args, idx?, fn_name).ok());
+    match expr {
+        None => {
+            use std::hash::{BuildHasher, Hasher};
+            Ok(std::collections::hash_map::RandomState::new()
+                .build_hasher()
+                .finish())
+        }
+        Some(Expr::Literal(Literal::Int(seed))) if *seed >= 0 => Ok(*seed as u64),
+        Some(_) => Err(EvalError::ArgError(format!(
+            "{fn_name}() seed must be a non-negative integer"
+        ))),
+    }
+}
+
 fn get_kwarg_bool(args: &[CoreArg], name: &str) -> Option<bool> {
     for arg in args {
         if let Arg::Keyword(k, v) = arg
@@ -1181,6 +2774,13 @@ fn get_kwarg_string(args: &[CoreArg], name: &str) -> Option<String> {
     None
 }
 
+fn get_kwarg_expr<'a>(args: &'a [CoreArg], name: &str) -> Option<&'a Expr> {
+    args.iter().find_map(|arg| match arg {
+        Arg::Keyword(k, v) if k == name => Some(v),
+        _ => None,
+    })
+}
+
 /// Get a kwarg that can be either a single string/col or a list of strings/cols
 fn get_kwarg_strings(args: &[CoreArg], name: &str) -> Option<Vec<String>> {
     for arg in args {
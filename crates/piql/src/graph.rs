@@ -0,0 +1,124 @@
+//! Dependency graph of materialized tables
+//!
+//! Each node is a materialization and the tables its query reads; reads of other
+//! materializations are the edges. Inserting a node that would close a cycle is
+//! an error, so the graph always has a topological order.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::PiqlError;
+
+/// Materializations and the tables each one reads
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DependencyGraph {
+    nodes: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl DependencyGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add or replace materialization `name` reading `dependencies`
+    ///
+    /// Fails (leaving the graph unchanged) if `name` would end up reading itself.
+    pub fn insert(
+        &mut self,
+        name: impl Into<String>,
+        dependencies: impl IntoIterator<Item = String>,
+    ) -> Result<(), PiqlError> {
+        let name = name.into();
+        let dependencies: BTreeSet<String> = dependencies.into_iter().collect();
+        for dep in &dependencies {
+            if let Some(mut path) = self.path(dep, &name) {
+                path.insert(0, name.clone());
+                return Err(PiqlError::DependencyCycle(path.join(" -> ")));
+            }
+        }
+        self.nodes.insert(name, dependencies);
+        Ok(())
+    }
+
+    /// Remove a materialization, returning the tables it read
+    pub fn remove(&mut self, name: &str) -> Option<BTreeSet<String>> {
+        self.nodes.remove(name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.nodes.contains_key(name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Tables read by materialization `name`
+    pub fn dependencies(&self, name: &str) -> Option<&BTreeSet<String>> {
+        self.nodes.get(name)
+    }
+
+    /// Materializations that read `table` directly, sorted
+    pub fn dependents(&self, table: &str) -> Vec<String> {
+        self.nodes
+            .iter()
+            .filter(|(_, deps)| deps.contains(table))
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Every materialization, each after the materializations it reads
+    ///
+    /// Ties are broken by name, so the order is deterministic.
+    pub fn topological_order(&self) -> Vec<String> {
+        self.order_of(self.nodes.keys().cloned().collect())
+    }
+
+    /// Materializations downstream of `changed`, each after the ones it reads
+    pub fn refresh_order(&self, changed: &str) -> Vec<String> {
+        let mut affected = BTreeSet::new();
+        let mut frontier = vec![changed.to_string()];
+        while let Some(table) = frontier.pop() {
+            for name in self.dependents(&table) {
+                if affected.insert(name.clone()) {
+                    frontier.push(name);
+                }
+            }
+        }
+        self.order_of(affected)
+    }
+
+    /// Topological order of `pending`, considering only edges within it
+    fn order_of(&self, mut pending: BTreeSet<String>) -> Vec<String> {
+        let mut order = Vec::with_capacity(pending.len());
+        while !pending.is_empty() {
+            let ready: Vec<String> = pending
+                .iter()
+                .filter(|name| self.nodes[*name].iter().all(|dep| !pending.contains(dep)))
+                .cloned()
+                .collect();
+            // `insert` rejects cycles, so some node is always ready
+            debug_assert!(!ready.is_empty(), "dependency graph has a cycle");
+            if ready.is_empty() {
+                break;
+            }
+            for name in ready {
+                pending.remove(&name);
+                order.push(name);
+            }
+        }
+        order
+    }
+
+    /// A chain of reads from `from` to `to`, if there is one
+    fn path(&self, from: &str, to: &str) -> Option<Vec<String>> {
+        if from == to {
+            return Some(vec![to.to_string()]);
+        }
+        let deps = self.nodes.get(from)?;
+        deps.iter().find_map(|dep| {
+            let mut path = self.path(dep, to)?;
+            path.insert(0, from.to_string());
+            Some(path)
+        })
+    }
+}
@@ -0,0 +1,88 @@
+//! Incremental subscription evaluation
+//!
+//! A subscription is now-scoped when every table it reads is a base table read
+//! through its implicit `now` pointer, i.e. never through `.all()`, `.window()`,
+//! `.since()` or `.at()`. Such a subscription only ever sees the rows appended in
+//! the latest tick, so the engine can evaluate it against those slices alone and
+//! reuse its last result while none of its tables received new rows.
+
+use std::collections::BTreeSet;
+
+use crate::ast::core::Expr;
+use crate::ast::{Arg, is_namespace_ident};
+use crate::eval::EvalContext;
+
+/// Which data a subscription reads each tick
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubscriptionScope {
+    /// Only the rows appended to base tables in the latest tick
+    Now,
+    /// Full history, materialized tables or other dataframes
+    History,
+}
+
+/// Base tables a now-scoped query reads, or `None` if it needs more than the
+/// appended slices
+pub(crate) fn now_scoped_tables(expr: &Expr, ctx: &EvalContext) -> Option<BTreeSet<String>> {
+    let mut tables = BTreeSet::new();
+    collect(expr, ctx, &mut tables).then_some(tables)
+}
+
+fn collect(expr: &Expr, ctx: &EvalContext, tables: &mut BTreeSet<String>) -> bool {
+    match expr {
+        Expr::Ident(name) if is_namespace_ident(name) => true,
+        Expr::Ident(name) => {
+            if !ctx.is_base_table(name) {
+                return false;
+            }
+            tables.insert(name.clone());
+            true
+        }
+        Expr::Literal(_) => true,
+        Expr::Invalid(_) => false,
+        Expr::List(items) => items.iter().all(|e| collect(e, ctx, tables)),
+        Expr::Attr(base, method) => {
+            if matches!(
+                method.as_str(),
+                "all" | "window" | "since" | "at" | "latest" | "last_ticks" | "resample"
+            ) && let Expr::Ident(name) = base.as_ref()
+                && ctx.is_base_table(name)
+            {
+                return false;
+            }
+            collect(base, ctx, tables)
+        }
+        Expr::Call(callee, args) => {
+            collect(callee, ctx, tables)
+                && args.iter().all(|arg| match arg {
+                    Arg::Positional(e) | Arg::Keyword(_, e) => collect(e, ctx, tables),
+                })
+        }
+        Expr::BinaryOp(lhs, _, rhs) => collect(lhs, ctx, tables) && collect(rhs, ctx, tables),
+        Expr::UnaryOp(_, inner) => collect(inner, ctx, tables),
+        Expr::WhenThenOtherwise {
+            branches,
+            otherwise,
+        } => {
+            branches.iter().all(|(condition, value)| {
+                collect(condition, ctx, tables) && collect(value, ctx, tables)
+            }) && collect(otherwise, ctx, tables)
+        }
+    }
+}
+
+/// Context holding only the latest appended slice of each base table
+///
+/// Base tables that have not received rows yet keep their regular entries.
+pub(crate) fn slice_context(ctx: &EvalContext) -> EvalContext {
+    let mut slice = ctx.clone();
+    // Materialized queries re-derived against the slice would miss earlier rows
+    slice.current_materializations.clear();
+    for (name, entry) in &mut slice.base_tables {
+        if let Some(now) = &entry.now {
+            entry.all = Some(now.clone());
+            slice.dataframes.remove(name);
+        }
+    }
+    slice
+}
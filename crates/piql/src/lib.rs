@@ -8,22 +8,34 @@
 //! use piql::{QueryEngine, TimeSeriesConfig};
 //!
 //! let mut engine = QueryEngine::new();
-//! engine.add_time_series_df("entities", df, TimeSeriesConfig {
-//!     tick_column: "tick".into(),
-//!     partition_key: "entity_id".into(),
-//! });
+//! engine.add_time_series_df("entities", df, TimeSeriesConfig::new("tick", "entity_id"));
 //!
 //! // Register custom directives
 //! engine.sugar().register_directive("merchant", |_, _| { /* ... */ });
 //!
+//! // Register pipeline directives, e.g. `entities.@latest_per($entity_id)`
+//! engine.sugar().register_pipeline_directive("latest_per", |df, args, _| { /* ... */ });
+//!
+//! // Register expression-level functions, e.g. `wealth_bucket($gold)`
+//! engine.sugar().register_function("wealth_bucket", 1, |args, _| { /* ... */ });
+//!
+//! // Saved snippets, referenced as `!rich_merchants`
+//! engine.define_alias("rich_merchants", "entities.filter(@merchant & ($gold > 1000))")?;
+//!
 //! // Materialized intermediate results
 //! engine.materialize("merchants", "entities.filter(@merchant)")?;
 //!
 //! // Subscribe to queries
 //! engine.subscribe("top_merchants", "merchants.filter(@now).top(10, 'gold')");
 //!
+//! // Evaluate subscriptions that only read the latest tick against the appended rows
+//! engine.set_incremental(true);
+//!
 //! // Each tick
 //! let results = engine.on_tick(current_tick)?;
+//!
+//! // Persist history and queries; `load_snapshot` resumes after a restart
+//! engine.save_snapshot("snapshots/latest")?;
 //! ```
 //!
 //! ## Standalone Usage
@@ -38,83 +50,126 @@
 //!     .with_tick(1000);
 //!
 //! let result = run(r#"entities.filter($gold > 100)"#, &ctx)?;
+//!
+//! // Parse once, run many times with different parameters
+//! let prepared = ctx.prepare("entities.filter($gold > :min)")?;
+//! let params = Params::from([("min".to_string(), ParamValue::from(100))]);
+//! let result = prepared.run_with_params(&params, &ctx)?;
 //! ```
 //!
 //! ## Sugar Syntax
 //!
 //! - `$col` → `pl.col("col")`
 //! - `$col.delta` → `col.diff().over(partition)`
+//! - `$col.pct`, `$col.zscore` → percent change and z-score within the partition
+//! - `$col.rate` → `col.diff() / tick.diff()`, change per tick
 //! - `@directive(args)` → custom filter (registered at runtime)
+//! - `df.@directive(args)` → custom pipeline stage rewriting `df` (registered at runtime)
 //! - `.window(a, b)`, `.since(n)`, `.at(n)`, `.all()` → time scope
 //! - `.top(n, col)` → sort descending + head
-
+//! - `!name` → query text saved with `define_alias`
+//!
+//! ## SQL
+//!
+//! With the `sql` feature, [`parse_sql`] and [`sql_to_piql`] accept
+//! `SELECT ... FROM ... [JOIN] [WHERE] [GROUP BY] [HAVING] [ORDER BY] [LIMIT]`
+//! and translate it to the equivalent PiQL.
+//!
+//! ## Without Polars
+//!
+//! Evaluation sits behind the default `eval` feature. With `default-features = false`
+//! only the parser, sugar transform, [`validate`] and [`format`] are built, none of
+//! which depend on Polars, so the crate compiles to `wasm32-unknown-unknown` (see
+//! `piql-wasm`).
+
+// Alias expansion and parameter binding are only needed when evaluating
+#[cfg_attr(not(feature = "eval"), allow(dead_code))]
+mod alias;
 mod ast;
+mod capabilities;
+#[cfg(feature = "eval")]
+mod complete;
+#[cfg(feature = "eval")]
+mod diff;
+#[cfg(feature = "eval")]
 mod engine;
+#[cfg(feature = "eval")]
 mod eval;
+mod graph;
+#[cfg(feature = "eval")]
+mod incremental;
+#[cfg(feature = "eval")]
+mod limits;
+#[cfg(feature = "eval")]
+mod lint;
+#[cfg(feature = "eval")]
+mod meta;
+#[cfg(feature = "eval")]
+mod optimize;
+#[cfg_attr(not(feature = "eval"), allow(dead_code))]
+mod params;
 mod parse;
+#[cfg(feature = "eval")]
+mod prefix;
 mod pretty;
+#[cfg(feature = "eval")]
+mod query;
+#[cfg(feature = "eval")]
+mod snapshot;
+#[cfg(feature = "sql")]
+mod sql;
 #[doc(hidden)]
 mod sugar;
 mod transform;
+mod validate;
 
 use thiserror::Error;
 
 // ============ Primary Public API ============
 
-pub use engine::QueryEngine;
-pub use eval::{DataFrameEntry, DataFrameLineage, EvalContext, TimeSeriesConfig, Value};
-
-/// A query compiled to core AST for repeated execution.
-#[derive(Clone)]
-pub struct CompiledQuery {
-    core: ast::core::Expr,
-    query: String,
-}
-
-/// Compile a query once for repeated execution.
-pub fn compile(query: &str, ctx: &EvalContext) -> Result<CompiledQuery, PiqlError> {
-    let surface = parse::parse(query)?;
-    let root_df = infer_root_dataframe_name(&surface);
-    let sugar_ctx = ctx.sugar_context(root_df);
-    let core = transform::transform_with_sugar(surface, &ctx.sugar, &sugar_ctx);
-    Ok(CompiledQuery {
-        core,
-        query: query.to_string(),
-    })
-}
-
-/// Run a pre-compiled query.
-pub fn run_compiled(compiled: &CompiledQuery, ctx: &EvalContext) -> Result<Value, PiqlError> {
-    let result = eval::eval(&compiled.core, ctx).map_err(|source| PiqlError::EvalWithQuery {
-        query: compiled.query.clone(),
-        source,
-    })?;
-    Ok(result)
-}
-
-/// Run a one-off query
-pub fn run(query: &str, ctx: &EvalContext) -> Result<Value, PiqlError> {
-    let compiled = compile(query, ctx)?;
-    run_compiled(&compiled, ctx)
-}
-
-fn infer_root_dataframe_name(expr: &ast::surface::Expr) -> Option<&str> {
-    use ast::surface::Expr as SurfaceExpr;
-
-    match expr {
-        SurfaceExpr::Ident(name) if name != "pl" => Some(name.as_str()),
-        SurfaceExpr::Ident(_) => None,
-        SurfaceExpr::Attr(base, _) => infer_root_dataframe_name(base),
-        SurfaceExpr::Call(callee, _) => infer_root_dataframe_name(callee),
-        SurfaceExpr::BinaryOp(lhs, _, rhs) => {
-            infer_root_dataframe_name(lhs).or_else(|| infer_root_dataframe_name(rhs))
-        }
-        SurfaceExpr::UnaryOp(_, inner) => infer_root_dataframe_name(inner),
-        SurfaceExpr::List(items) => items.iter().find_map(infer_root_dataframe_name),
-        SurfaceExpr::Literal(_) | SurfaceExpr::ColShorthand(_) | SurfaceExpr::Directive(_, _) => {
-            None
-        }
-    }
+pub use alias::Aliases;
+pub use capabilities::{MethodSpec, Namespace, capabilities};
+#[cfg(feature = "eval")]
+pub use complete::{Completion, CompletionKind, complete};
+#[cfg(feature = "eval")]
+pub use diff::{CHANGE_COLUMN, diff_results};
+#[cfg(feature = "eval")]
+pub use engine::{EmitMode, QueryEngine, RemoveMode};
+#[cfg(feature = "eval")]
+pub use eval::{
+    BaseTableEntry, DEFAULT_RUN_LABEL_COLUMN, DataFrameEntry, DataFrameLineage, EvalContext,
+    EvictedTicks, LineageSource, Retention, TickDtype, TimeSeriesConfig, Value,
+    enable_string_cache, using_string_cache,
+};
+pub use graph::DependencyGraph;
+#[cfg(feature = "eval")]
+pub use incremental::SubscriptionScope;
+#[cfg(feature = "eval")]
+pub use limits::{LimitExceeded, ResourceLimits};
+#[cfg(feature = "eval")]
+pub use lint::{LintKind, LintWarning};
+#[cfg(feature = "eval")]
+pub use meta::{QueryResultMeta, TickRange, run_with_meta};
+pub use params::{ParamValue, Params};
+#[cfg(feature = "eval")]
+pub use query::{
+    CompiledQuery, PreparedQuery, collapse_result, compile, compile_with_params, prepare, run,
+    run_compiled, run_with_params,
+};
+#[cfg(feature = "eval")]
+pub use snapshot::{SnapshotDumps, SnapshotError, write_snapshot};
+#[cfg(feature = "sql")]
+pub use sql::{parse_sql, sql_to_piql};
+pub use validate::{ValidationError, validate};
+
+/// Parse and pretty-print a query in canonical form, breaking method chains longer
+/// than `width`. `#` comments are kept.
+///
+/// ```ignore
+/// assert_eq!(piql::format("entities .filter( $gold>100 )", 80)?, "entities.filter($gold > 100)");
+/// ```
+pub fn format(query: &str, width: usize) -> Result<String, ParseError> {
+    Ok(parse::parse_with_comments(query)?.pretty(width))
 }
 
 // ============ Errors ============
@@ -123,16 +178,38 @@ fn infer_root_dataframe_name(expr: &ast::surface::Expr) -> Option<&str> {
 pub enum PiqlError {
     #[error("Parse error: {0}")]
     Parse(#[from] parse::ParseError),
+    #[cfg(feature = "eval")]
     #[error("Eval error: {0}")]
     Eval(#[from] eval::EvalError),
+    #[cfg(feature = "eval")]
     #[error("Eval error in query `{query}`: {source}")]
     EvalWithQuery {
         query: String,
         #[source]
         source: eval::EvalError,
     },
+    #[error("Missing value for query parameter :{0}")]
+    MissingParam(String),
+    #[error("Unknown alias !{0}")]
+    UnknownAlias(String),
+    #[error("Alias refers to itself: {0}")]
+    AliasCycle(String),
+    #[error("Materialization reads itself: {0}")]
+    DependencyCycle(String),
+    #[error("Unknown table: {0}")]
+    UnknownTable(String),
+    #[error("Table already exists: {0}")]
+    TableExists(String),
+    #[error("Table {table} is read by {}", dependents.join(", "))]
+    TableInUse {
+        table: String,
+        dependents: Vec<String>,
+    },
+    #[error("SQL error: {0}")]
+    Sql(String),
 }
 
+#[cfg(feature = "eval")]
 pub use eval::EvalError;
 pub use parse::ParseError;
 
@@ -155,8 +232,11 @@ pub mod advanced {
     pub use crate::ast::core::{CoreArg, Expr as CoreExpr};
     pub use crate::ast::surface::{Expr as SurfaceExpr, SurfaceArg};
     pub use crate::ast::{Arg, Literal, UnaryOp};
-    pub use crate::eval::eval;
-    pub use crate::parse::parse;
+    #[cfg(feature = "eval")]
+    pub use crate::eval::{eval, parse_dtype};
+    #[cfg(feature = "eval")]
+    pub use crate::optimize::optimize;
+    pub use crate::parse::{RecoveredParse, parse, parse_recovering, parse_with_comments};
     pub use crate::pretty::pretty;
     pub use crate::transform::{transform, transform_with_sugar};
 }
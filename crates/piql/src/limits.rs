@@ -0,0 +1,191 @@
+//! Resource limits for collecting query results
+//!
+//! Checked before and during `collect`:
+//! - Before: the row ceiling (`max_rows`, and `max_bytes` divided by the estimated
+//!   row width from the schema) is pushed into the plan as a limit, so a runaway
+//!   join stops producing rows one past the ceiling.
+//! - During: with a timeout, the collect runs on Polars' own thread pool
+//!   ([`LazyFrame::collect_concurrently`]) and is cancelled once the deadline
+//!   passes (or, with [`ResourceLimits::collect_cancellable`], once the caller gives
+//!   up); Polars stops it at the next operator boundary. (On wasm there are no
+//!   threads to cancel from, and the timeout isn't enforced.)
+//! - After: the result's height and estimated size are compared to the limits.
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::{LazyLock, mpsc};
+use std::time::Duration;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+
+use polars::prelude::*;
+use thiserror::Error;
+
+use crate::eval::EvalError;
+
+/// Ceilings applied when collecting a query result (`None` = unlimited)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceLimits {
+    /// Wall-clock time allowed for collecting
+    pub timeout: Option<Duration>,
+    /// Most rows a result may have
+    pub max_rows: Option<usize>,
+    /// Most bytes a result may take in memory (estimated)
+    pub max_bytes: Option<usize>,
+}
+
+/// Which limit a query exceeded
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum LimitExceeded {
+    #[error("ran longer than {0:?}")]
+    Timeout(Duration),
+    #[error("result has more than {0} rows")]
+    Rows(usize),
+    #[error("result needs an estimated {estimated} bytes, more than the {limit} allowed")]
+    Bytes { estimated: usize, limit: usize },
+    /// Raised by hosts that queue queries for a bounded pool of workers, not by
+    /// [`ResourceLimits::collect`]
+    #[error("server is saturated: {0} queries are already waiting")]
+    Saturated(usize),
+}
+
+impl ResourceLimits {
+    pub fn is_unlimited(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Collect `lf`, failing with [`EvalError::ResourceLimit`] if a limit is exceeded
+    pub fn collect(&self, lf: LazyFrame) -> Result<DataFrame, EvalError> {
+        self.collect_inner(lf, None)
+    }
+
+    /// Like [`Self::collect`], but cancel the collect as soon as `cancelled` returns
+    /// true (checked while it runs), e.g. once nobody waits for the result
+    pub fn collect_cancellable(
+        &self,
+        lf: LazyFrame,
+        cancelled: &dyn Fn() -> bool,
+    ) -> Result<DataFrame, EvalError> {
+        self.collect_inner(lf, Some(cancelled))
+    }
+
+    fn collect_inner(
+        &self,
+        mut lf: LazyFrame,
+        cancelled: Option<&dyn Fn() -> bool>,
+    ) -> Result<DataFrame, EvalError> {
+        if self.is_unlimited() && cancelled.is_none() {
+            return Ok(lf.collect()?);
+        }
+
+        let row_width = estimated_row_width(&*lf.collect_schema()?);
+        let bytes_rows = self.max_bytes.map(|bytes| bytes / row_width.max(1));
+        let ceiling = match (self.max_rows, bytes_rows) {
+            (Some(rows), Some(bytes_rows)) => Some(rows.min(bytes_rows)),
+            (rows, bytes_rows) => rows.or(bytes_rows),
+        };
+        if let Some(ceiling) = ceiling {
+            // One row past the ceiling tells an exceeded limit apart from an exact fit
+            lf = lf.limit(IdxSize::try_from(ceiling.saturating_add(1)).unwrap_or(IdxSize::MAX));
+        }
+
+        let df = if self.timeout.is_some() || cancelled.is_some() {
+            collect_until(lf, self.timeout, cancelled.unwrap_or(&|| false))?
+        } else {
+            lf.collect()?
+        };
+
+        if let Some(rows) = self.max_rows
+            && df.height() > rows
+        {
+            return Err(EvalError::ResourceLimit(LimitExceeded::Rows(rows)));
+        }
+        if let Some(limit) = self.max_bytes {
+            let estimated = df.estimated_size().max(df.height() * row_width);
+            if estimated > limit || ceiling.is_some_and(|ceiling| df.height() > ceiling) {
+                return Err(EvalError::ResourceLimit(LimitExceeded::Bytes {
+                    estimated,
+                    limit,
+                }));
+            }
+        }
+        Ok(df)
+    }
+}
+
+/// Longest sleep between checks of whether a collect with a timeout has finished
+#[cfg(not(target_arch = "wasm32"))]
+const MAX_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Cancelled collects still winding down. Their results must still be received:
+/// Polars' worker panics (aborting the process) when the receiver is gone.
+#[cfg(not(target_arch = "wasm32"))]
+static CANCELLED: LazyLock<mpsc::Sender<InProcessQuery>> = LazyLock::new(|| {
+    let (tx, rx) = mpsc::channel::<InProcessQuery>();
+    std::thread::Builder::new()
+        .name("piql-cancelled".into())
+        .spawn(move || {
+            for query in rx {
+                let _ = query.fetch_blocking();
+            }
+        })
+        .expect("spawn thread for cancelled collects");
+    tx
+});
+
+/// Collect `lf` on Polars' thread pool, cancelling it after `timeout` or once
+/// `cancelled` returns true
+#[cfg(not(target_arch = "wasm32"))]
+fn collect_until(
+    lf: LazyFrame,
+    timeout: Option<Duration>,
+    cancelled: &dyn Fn() -> bool,
+) -> Result<DataFrame, EvalError> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let query = lf.collect_concurrently()?;
+    let mut interval = Duration::from_micros(50);
+    loop {
+        if let Some(result) = query.fetch() {
+            return Ok(result?);
+        }
+        let remaining = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        let error = match (timeout, remaining) {
+            (Some(timeout), Some(remaining)) if remaining.is_zero() => {
+                Some(EvalError::ResourceLimit(LimitExceeded::Timeout(timeout)))
+            }
+            _ if cancelled() => Some(EvalError::Other("query was cancelled".into())),
+            _ => None,
+        };
+        if let Some(error) = error {
+            query.cancel();
+            let _ = CANCELLED.send(query);
+            return Err(error);
+        }
+        let sleep = interval.min(remaining.unwrap_or(MAX_POLL_INTERVAL));
+        std::thread::sleep(sleep);
+        interval = (interval * 2).min(MAX_POLL_INTERVAL);
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn collect_until(
+    lf: LazyFrame,
+    _timeout: Option<Duration>,
+    _cancelled: &dyn Fn() -> bool,
+) -> Result<DataFrame, EvalError> {
+    Ok(lf.collect()?)
+}
+
+/// Approximate in-memory bytes per row
+fn estimated_row_width(schema: &Schema) -> usize {
+    schema
+        .iter_values()
+        .map(|dtype| match dtype.to_physical() {
+            DataType::Boolean | DataType::Int8 | DataType::UInt8 => 1,
+            DataType::Int16 | DataType::UInt16 => 2,
+            DataType::Int32 | DataType::UInt32 | DataType::Float32 => 4,
+            DataType::Int64 | DataType::UInt64 | DataType::Float64 => 8,
+            // Strings, lists and other variable-width values: a view plus some payload
+            _ => 16,
+        })
+        .sum()
+}
@@ -0,0 +1,237 @@
+//! Performance lints for subscription queries
+//!
+//! Subscriptions are re-evaluated (and collected) every tick, so patterns that are
+//! fine for one-off queries can dominate tick latency. Rules:
+//! - `.all()` on a base table (scans full history every tick)
+//! - joins whose keys don't exist on both sides, and cross joins
+//! - `.describe()` (several full aggregations per tick)
+//! - shared sub-pipelines across subscriptions (materialization candidates)
+
+use std::collections::{BTreeSet, HashMap};
+
+use crate::ast::Arg;
+use crate::ast::Literal;
+use crate::ast::surface::{Expr, SurfaceArg};
+use crate::eval::EvalContext;
+
+/// Kind of lint finding
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintKind {
+    /// `.all()` on a base table inside a per-tick subscription
+    AllOnBaseTable,
+    /// Join with no usable key overlap (cross join or keys missing on one side)
+    JoinWithoutKeyOverlap,
+    /// `.describe()` inside a per-tick subscription
+    DescribeInSubscription,
+    /// Sub-pipeline shared by several subscriptions
+    MaterializationCandidate,
+}
+
+/// A single lint finding
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintWarning {
+    pub kind: LintKind,
+    /// Subscriptions the finding applies to
+    pub subscriptions: Vec<String>,
+    pub message: String,
+}
+
+/// Lint a single subscription query against the current context.
+pub fn lint_subscription(name: &str, expr: &Expr, ctx: &EvalContext) -> Vec<LintWarning> {
+    let mut warnings = Vec::new();
+    walk(expr, &mut |e| {
+        let Expr::Call(callee, args) = e else {
+            return;
+        };
+        let Expr::Attr(base, method) = callee.as_ref() else {
+            return;
+        };
+        match method.as_str() {
+            "all" => {
+                if let Expr::Ident(table) = base.as_ref()
+                    && ctx.is_base_table(table)
+                {
+                    warnings.push(LintWarning {
+                        kind: LintKind::AllOnBaseTable,
+                        subscriptions: vec![name.to_string()],
+                        message: format!(
+                            "`{table}.all()` scans full history every tick; use .window()/.since() to bound it"
+                        ),
+                    });
+                }
+            }
+            "describe" => warnings.push(LintWarning {
+                kind: LintKind::DescribeInSubscription,
+                subscriptions: vec![name.to_string()],
+                message: "`.describe()` runs several full aggregations every tick".to_string(),
+            }),
+            "join" | "join_asof" => {
+                if let Some(message) = check_join_keys(base, args, ctx) {
+                    warnings.push(LintWarning {
+                        kind: LintKind::JoinWithoutKeyOverlap,
+                        subscriptions: vec![name.to_string()],
+                        message,
+                    });
+                }
+            }
+            _ => {}
+        }
+    });
+    warnings
+}
+
+/// Find DataFrame sub-pipelines shared by at least two subscriptions.
+///
+/// Only the longest shared prefix is reported for a given set of subscriptions.
+pub fn materialization_candidates(queries: &[(&str, &Expr)]) -> Vec<LintWarning> {
+    let mut users: HashMap<String, BTreeSet<String>> = HashMap::new();
+    for (name, expr) in queries {
+        walk(expr, &mut |e| {
+            if matches!(e, Expr::Call(..)) && is_table_pipeline(e) {
+                users
+                    .entry(e.to_string())
+                    .or_default()
+                    .insert(name.to_string());
+            }
+        });
+    }
+
+    let shared: Vec<(&String, &BTreeSet<String>)> =
+        users.iter().filter(|(_, subs)| subs.len() > 1).collect();
+
+    let mut warnings: Vec<LintWarning> = shared
+        .iter()
+        .filter(|(prefix, subs)| {
+            // Skip if a longer pipeline is shared by the same subscriptions
+            !shared.iter().any(|(other, other_subs)| {
+                other.len() > prefix.len()
+                    && other.starts_with(prefix.as_str())
+                    && other_subs == subs
+            })
+        })
+        .map(|(prefix, subs)| LintWarning {
+            kind: LintKind::MaterializationCandidate,
+            subscriptions: subs.iter().cloned().collect(),
+            message: format!(
+                "`{prefix}` is evaluated by {} subscriptions; consider materializing it",
+                subs.len()
+            ),
+        })
+        .collect();
+    warnings.sort_by(|a, b| a.message.cmp(&b.message));
+    warnings
+}
+
+fn check_join_keys(left: &Expr, args: &[SurfaceArg], ctx: &EvalContext) -> Option<String> {
+    if kwarg_string(args, "how").as_deref() == Some("cross") {
+        return Some("cross join produces |left| x |right| rows every tick".to_string());
+    }
+
+    let Expr::Ident(left_name) = left else {
+        return None;
+    };
+    let Some(Arg::Positional(Expr::Ident(right_name))) = args.first() else {
+        return None;
+    };
+    let left_schema = ctx.dataframes.get(left_name)?.df.schema();
+    let right_schema = ctx.dataframes.get(right_name)?.df.schema();
+
+    let (left_keys, right_keys) = match kwarg_strings(args, "on") {
+        Some(on) => (on.clone(), on),
+        None => (
+            kwarg_strings(args, "left_on")?,
+            kwarg_strings(args, "right_on")?,
+        ),
+    };
+
+    let missing: Vec<String> = left_keys
+        .iter()
+        .filter(|k| !left_schema.contains(k))
+        .map(|k| format!("{left_name}.{k}"))
+        .chain(
+            right_keys
+                .iter()
+                .filter(|k| !right_schema.contains(k))
+                .map(|k| format!("{right_name}.{k}")),
+        )
+        .collect();
+
+    if missing.is_empty() {
+        None
+    } else {
+        Some(format!(
+            "join keys not present on both sides: {}",
+            missing.join(", ")
+        ))
+    }
+}
+
+/// True for method chains rooted at a table identifier (not `pl` or `cs`)
+fn is_table_pipeline(expr: &Expr) -> bool {
+    match expr {
+        Expr::Ident(name) => !crate::ast::is_namespace_ident(name),
+        Expr::Call(callee, _) => match callee.as_ref() {
+            Expr::Attr(base, _) => is_table_pipeline(base),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+fn walk(expr: &Expr, f: &mut impl FnMut(&Expr)) {
+    f(expr);
+    match expr {
+        Expr::Ident(_)
+        | Expr::Literal(_)
+        | Expr::ColShorthand(_)
+        | Expr::Param(_)
+        | Expr::Alias(_)
+        | Expr::Error => {}
+        Expr::List(items) => items.iter().for_each(|e| walk(e, f)),
+        Expr::Attr(base, _) => walk(base, f),
+        Expr::Call(callee, args) => {
+            walk(callee, f);
+            args.iter().for_each(|a| walk(arg_expr(a), f));
+        }
+        Expr::BinaryOp(lhs, _, rhs) => {
+            walk(lhs, f);
+            walk(rhs, f);
+        }
+        Expr::UnaryOp(_, inner) => walk(inner, f),
+        Expr::Commented { expr, .. } => walk(expr, f),
+        Expr::Directive(_, args) => args.iter().for_each(|a| walk(arg_expr(a), f)),
+        Expr::PipelineDirective(base, _, args) => {
+            walk(base, f);
+            args.iter().for_each(|a| walk(arg_expr(a), f));
+        }
+    }
+}
+
+fn arg_expr(arg: &SurfaceArg) -> &Expr {
+    match arg {
+        Arg::Positional(e) | Arg::Keyword(_, e) => e,
+    }
+}
+
+fn kwarg_string(args: &[SurfaceArg], name: &str) -> Option<String> {
+    kwarg_strings(args, name).and_then(|mut v| (v.len() == 1).then(|| v.remove(0)))
+}
+
+fn kwarg_strings(args: &[SurfaceArg], name: &str) -> Option<Vec<String>> {
+    let value = args.iter().find_map(|a| match a {
+        Arg::Keyword(k, v) if k == name => Some(v),
+        _ => None,
+    })?;
+    match value {
+        Expr::Literal(Literal::String(s)) => Some(vec![s.clone()]),
+        Expr::ColShorthand(s) => Some(vec![s.clone()]),
+        Expr::List(items) => items
+            .iter()
+            .map(|e| match e {
+                Expr::Literal(Literal::String(s)) | Expr::ColShorthand(s) => Some(s.clone()),
+                _ => None,
+            })
+            .collect(),
+        _ => None,
+    }
+}
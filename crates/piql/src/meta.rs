@@ -0,0 +1,205 @@
+//! Metadata describing what fed a query result
+//!
+//! Sources are the tables a query reads. The tick range is the hull of the ranges
+//! its scope methods select (`.at(5)` → `5..=5`, `.window(-10, 0)` at tick 100 →
+//! `90..=100`, `.since(n)` and `.all()` leave bounds open), with a base table read
+//! through its implicit `now` counting as the current tick.
+
+use polars::prelude::*;
+use serde::Serialize;
+
+use crate::ast::Arg;
+use crate::ast::core::{CoreArg, Expr};
+use crate::eval::{self, EvalContext, TickDtype, Value};
+use crate::query::{self, CompiledQuery};
+use crate::{PiqlError, ast};
+
+/// Inclusive tick range; a `None` bound is open
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct TickRange {
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+}
+
+impl TickRange {
+    /// The smallest range covering both
+    fn hull(self, other: Self) -> Self {
+        Self {
+            from: self.from.zip(other.from).map(|(a, b)| a.min(b)),
+            to: self.to.zip(other.to).map(|(a, b)| a.max(b)),
+        }
+    }
+}
+
+/// Where a query result came from
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QueryResultMeta {
+    /// Tables the query reads, sorted
+    pub sources: Vec<String>,
+    /// Ticks selected from integer-tick time-series tables; `None` if the query reads
+    /// none, or the range depends on something other than literal integer ticks
+    pub ticks: Option<TickRange>,
+    /// Output columns and their dtypes
+    pub schema: Vec<(String, String)>,
+}
+
+impl QueryResultMeta {
+    /// Metadata of `compiled`'s result, whose schema is `schema`
+    pub fn new(compiled: &CompiledQuery, ctx: &EvalContext, schema: &Schema) -> Self {
+        let sources: Vec<String> = compiled
+            .referenced_tables()
+            .into_iter()
+            .filter(|name| ctx.dataframes.contains_key(name) || ctx.is_base_table(name))
+            .collect();
+        let mut scan = TickScan {
+            ctx,
+            tick: compiled.as_of().or(ctx.tick),
+            range: None,
+            unknown: false,
+        };
+        scan.visit(compiled.core(), false);
+        Self {
+            sources,
+            ticks: scan.range.filter(|_| !scan.unknown),
+            schema: schema
+                .iter()
+                .map(|(name, dtype)| (name.to_string(), dtype.to_string()))
+                .collect(),
+        }
+    }
+}
+
+/// Run a one-off query, also returning metadata about its result
+pub fn run_with_meta(
+    query: &str,
+    ctx: &EvalContext,
+) -> Result<(Value, QueryResultMeta), PiqlError> {
+    let compiled = query::compile(query, ctx)?;
+    let value = query::run_compiled(&compiled, ctx)?;
+    let schema = match &value {
+        Value::DataFrame(lf, _) => lf
+            .clone()
+            .collect_schema()
+            .map_err(eval::EvalError::from)?
+            .as_ref()
+            .clone(),
+        Value::Series(series) => Schema::from_iter([series.field().into_owned()]),
+        _ => Schema::default(),
+    };
+    let meta = QueryResultMeta::new(&compiled, ctx, &schema);
+    Ok((value, meta))
+}
+
+struct TickScan<'a> {
+    ctx: &'a EvalContext,
+    tick: Option<i64>,
+    range: Option<TickRange>,
+    unknown: bool,
+}
+
+impl TickScan<'_> {
+    fn add(&mut self, range: Option<TickRange>) {
+        match range {
+            Some(range) => self.range = Some(self.range.map_or(range, |r| r.hull(range))),
+            None => self.unknown = true,
+        }
+    }
+
+    /// Integer-tick time-series table `name`: Some(true) for base tables, Some(false)
+    /// for other time-series dataframes
+    fn int_tick_table(&self, name: &str) -> Option<bool> {
+        let config = self.ctx.time_series_config_of(name)?;
+        (config.tick_dtype == TickDtype::Int).then(|| self.ctx.is_base_table(name))
+    }
+
+    fn visit(&mut self, expr: &Expr, scoped: bool) {
+        match expr {
+            Expr::Ident(name) if !scoped && !ast::is_namespace_ident(name) => {
+                match self.int_tick_table(name) {
+                    // Implicit `now`
+                    Some(true) => self.add(self.tick.map(|t| TickRange {
+                        from: Some(t),
+                        to: Some(t),
+                    })),
+                    Some(false) => self.add(Some(TickRange {
+                        from: None,
+                        to: None,
+                    })),
+                    None => {}
+                }
+            }
+            Expr::Ident(_) | Expr::Literal(_) | Expr::Invalid(_) => {}
+            Expr::Call(callee, args) => {
+                if let Expr::Attr(base, method) = callee.as_ref()
+                    && let Some(range) = self.scope_range(method, args)
+                {
+                    // Only tables with integer ticks contribute a range
+                    let mut tables = Vec::new();
+                    collect_idents(base, &mut tables);
+                    if tables.iter().any(|t| self.int_tick_table(t).is_some()) {
+                        self.add(range);
+                    }
+                    self.visit(base, true);
+                } else {
+                    self.visit(callee, scoped);
+                }
+                for arg in args {
+                    match arg {
+                        Arg::Positional(e) | Arg::Keyword(_, e) => self.visit(e, false),
+                    }
+                }
+            }
+            Expr::List(items) => items.iter().for_each(|e| self.visit(e, false)),
+            Expr::Attr(base, _) => self.visit(base, scoped),
+            Expr::BinaryOp(lhs, _, rhs) => {
+                self.visit(lhs, false);
+                self.visit(rhs, false);
+            }
+            Expr::UnaryOp(_, inner) => self.visit(inner, false),
+            Expr::WhenThenOtherwise {
+                branches,
+                otherwise,
+            } => {
+                for (condition, value) in branches {
+                    self.visit(condition, false);
+                    self.visit(value, false);
+                }
+                self.visit(otherwise, false);
+            }
+        }
+    }
+
+    /// Range a scope method selects: `None` if `method` isn't one, `Some(None)` if the
+    /// range can't be determined
+    fn scope_range(&self, method: &str, args: &[CoreArg]) -> Option<Option<TickRange>> {
+        // Accepts negative literals, which parse as `UnaryOp(Neg, Int)`
+        let int_arg = |idx: usize| eval::get_int_arg(args, idx, method).ok();
+        let range = |from, to| Some(TickRange { from, to });
+        Some(match method {
+            "at" => int_arg(0).and_then(|n| range(Some(n), Some(n))),
+            "since" => int_arg(0).and_then(|n| range(Some(n), None)),
+            "window" => match (self.tick, int_arg(0), int_arg(1)) {
+                (Some(tick), Some(a), Some(b)) => range(Some(tick + a), Some(tick + b)),
+                _ => None,
+            },
+            "all" | "latest" | "last_ticks" | "resample" => range(None, self.tick),
+            _ => return None,
+        })
+    }
+}
+
+fn collect_idents(expr: &Expr, names: &mut Vec<String>) {
+    match expr {
+        Expr::Ident(name) => names.push(name.clone()),
+        Expr::Attr(base, _) => collect_idents(base, names),
+        Expr::Call(callee, args) => {
+            collect_idents(callee, names);
+            for arg in args {
+                match arg {
+                    Arg::Positional(e) | Arg::Keyword(_, e) => collect_idents(e, names),
+                }
+            }
+        }
+        _ => {}
+    }
+}
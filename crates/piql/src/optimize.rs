@@ -0,0 +1,458 @@
+//! Plan-level rewrites of the core AST, run after desugaring
+//!
+//! Reorders each method chain so scope methods and cheap row-wise predicates run
+//! before the expensive stages that keep the same rows:
+//!
+//! - `.filter(p)`, `.at(n)`, `.since(n)` and `.window(a, b)` move before `.sort(...)`
+//!   and `.reverse()`
+//! - they also move before `.with_columns(...)` when it defines none of the columns
+//!   they read (for scope methods, any table's tick column)
+//! - a filter directly after another merges into it as `a & b`
+//!
+//! Only predicates computing each row from that row alone move: one with a window
+//! (`$gold.delta`) or an aggregate (`$gold > $gold.mean()`) depends on which rows
+//! precede it and stays put. Aliases expand before this pass, so a filter on `!name`
+//! is pushed into the alias's definition. A scope method never moves onto a base
+//! table's name, where it would read the table's history instead of the current
+//! tick.
+//!
+//! A filter directly on a materialized table runs inside the table's query when
+//! eval finds its stored rows current (see [`push_into_definition`]): the filter
+//! is pushed down the query like any other and the tables it reads are filtered
+//! instead. A table changed since its query last ran (its stored rows, or any
+//! table the query reads), a query that could give other rows when re-run (an
+//! unseeded `.sample(...)`), or a filter that would only end up on top of the
+//! query leaves the stored rows to be filtered.
+//!
+//! Turn the pass off with [`EvalContext::with_optimizer`] to evaluate queries
+//! exactly as written.
+
+use std::collections::BTreeSet;
+
+use crate::ast::core::{CoreArg, Expr};
+use crate::ast::{Arg, BinOp, Literal};
+use crate::eval::EvalContext;
+
+/// Scope methods that keep the rows at some ticks and change nothing else
+const SCOPE_METHODS: &[&str] = &["at", "since", "window"];
+
+/// Stages that reorder rows without adding, removing or changing any
+const REORDERING_METHODS: &[&str] = &["sort", "reverse"];
+
+/// `pl.*` functions computing each row from that row alone
+const ROW_WISE_FUNCTIONS: &[&str] = &[
+    "col",
+    "lit",
+    "sum_horizontal",
+    "max_horizontal",
+    "min_horizontal",
+    "concat_str",
+    "coalesce",
+];
+
+/// `pl.*` functions taking positional strings as column names (or patterns)
+const COLUMN_ARG_FUNCTIONS: &[&str] = &[
+    "col",
+    "sum_horizontal",
+    "max_horizontal",
+    "min_horizontal",
+    "concat_str",
+];
+
+/// Expression methods computing each row from that row alone (besides every
+/// `.str` and `.dt` method)
+const ROW_WISE_METHODS: &[&str] = &[
+    "alias",
+    "is_between",
+    "cast",
+    "is_null",
+    "is_not_null",
+    "is_in",
+    "abs",
+    "pow",
+    "sqrt",
+    "log",
+    "exp",
+    "floor",
+    "ceil",
+    "sign",
+    "round",
+    "div_or",
+    "nan_to_null",
+    "fill_nan",
+    "is_nan",
+    "clip",
+];
+
+/// Rewrite every method chain in `expr`, innermost first
+pub fn optimize(expr: Expr, ctx: &EvalContext) -> Expr {
+    let expr = match expr {
+        Expr::Call(callee, args) => Expr::Call(
+            Box::new(optimize(*callee, ctx)),
+            args.into_iter().map(|arg| optimize_arg(arg, ctx)).collect(),
+        ),
+        Expr::Attr(base, name) => Expr::Attr(Box::new(optimize(*base, ctx)), name),
+        Expr::List(items) => Expr::List(items.into_iter().map(|e| optimize(e, ctx)).collect()),
+        Expr::BinaryOp(lhs, op, rhs) => Expr::BinaryOp(
+            Box::new(optimize(*lhs, ctx)),
+            op,
+            Box::new(optimize(*rhs, ctx)),
+        ),
+        Expr::UnaryOp(op, inner) => Expr::UnaryOp(op, Box::new(optimize(*inner, ctx))),
+        Expr::WhenThenOtherwise {
+            branches,
+            otherwise,
+        } => Expr::WhenThenOtherwise {
+            branches: branches
+                .into_iter()
+                .map(|(condition, value)| {
+                    (
+                        Box::new(optimize(*condition, ctx)),
+                        Box::new(optimize(*value, ctx)),
+                    )
+                })
+                .collect(),
+            otherwise: Box::new(optimize(*otherwise, ctx)),
+        },
+        other @ (Expr::Ident(_) | Expr::Literal(_) | Expr::Invalid(_)) => other,
+    };
+    push_down(expr, ctx)
+}
+
+fn optimize_arg(arg: CoreArg, ctx: &EvalContext) -> CoreArg {
+    match arg {
+        Arg::Positional(e) => Arg::Positional(optimize(e, ctx)),
+        Arg::Keyword(name, e) => Arg::Keyword(name, optimize(e, ctx)),
+    }
+}
+
+/// `base.method(args)`
+fn stage(base: Expr, method: String, args: Vec<CoreArg>) -> Expr {
+    base.attr(method).call(args)
+}
+
+/// `definition.filter(args)` with the filter pushed down `definition`, the query
+/// of a materialized table, or `None` unless the filter moves into it
+///
+/// Called by eval only while the table's stored rows are what `definition` gives,
+/// so both read the same rows.
+pub(crate) fn push_into_definition(
+    definition: &Expr,
+    args: &[CoreArg],
+    ctx: &EvalContext,
+) -> Option<Expr> {
+    if !predicate(args).is_some_and(row_wise) {
+        return None;
+    }
+    let pushed = push_down(
+        stage(definition.clone(), "filter".to_string(), args.to_vec()),
+        ctx,
+    );
+    // Left on top, the filter would re-derive every stored row just to drop some
+    let on_top = matches!(&pushed, Expr::Call(callee, _)
+        if matches!(&**callee, Expr::Attr(base, method) if method == "filter" && **base == *definition));
+    (!on_top).then_some(pushed)
+}
+
+/// Whether `expr` gives the same rows each time it runs on the same tables: it
+/// has no unseeded `.sample(...)` or `.shuffle()`, no `.unique(subset)` (which
+/// keeps any one of the duplicates) and no `.head(n)` or `.tail(n)` of rows in
+/// the arbitrary order a `group_by` or `unique` leaves them
+pub(crate) fn deterministic(expr: &Expr) -> bool {
+    match expr {
+        Expr::Call(callee, args) => {
+            deterministic(callee)
+                && args.iter().all(|arg| deterministic(arg_expr(arg)))
+                && match &**callee {
+                    Expr::Attr(base, method) => match method.as_str() {
+                        "sample" => has_seed(args, None),
+                        "shuffle" => has_seed(args, Some(0)),
+                        "unique" => args.is_empty(),
+                        "head" | "tail" => !arbitrary_order(base),
+                        _ => true,
+                    },
+                    _ => true,
+                }
+        }
+        Expr::Attr(base, _) => deterministic(base),
+        Expr::List(items) => items.iter().all(deterministic),
+        Expr::BinaryOp(lhs, _, rhs) => deterministic(lhs) && deterministic(rhs),
+        Expr::UnaryOp(_, inner) => deterministic(inner),
+        Expr::WhenThenOtherwise {
+            branches,
+            otherwise,
+        } => {
+            branches
+                .iter()
+                .all(|(condition, value)| deterministic(condition) && deterministic(value))
+                && deterministic(otherwise)
+        }
+        Expr::Ident(_) | Expr::Literal(_) | Expr::Invalid(_) => true,
+    }
+}
+
+/// Whether `.sample(...)` or `.shuffle(...)` called with `args` names a seed, as
+/// `seed=` or positional arg `idx`
+fn has_seed(args: &[CoreArg], idx: Option<usize>) -> bool {
+    args.iter()
+        .any(|arg| matches!(arg, Arg::Keyword(name, _) if name == "seed"))
+        || idx.is_some_and(|idx| {
+            args.iter()
+                .filter(|arg| matches!(arg, Arg::Positional(_)))
+                .nth(idx)
+                .is_some()
+        })
+}
+
+/// Whether the rows of the frame `expr` yields come in no particular order
+fn arbitrary_order(expr: &Expr) -> bool {
+    let Expr::Call(callee, _) = expr else {
+        return false;
+    };
+    let Expr::Attr(base, method) = &**callee else {
+        return false;
+    };
+    match method.as_str() {
+        "unique" => true,
+        "agg" => matches!(&**base, Expr::Call(inner, _)
+            if matches!(&**inner, Expr::Attr(_, name) if name == "group_by" || name == "by_run")),
+        "sort" | "top" => false,
+        _ => arbitrary_order(base),
+    }
+}
+
+/// Move the stage `expr` ends with before the stages it may pass, whose own chain
+/// is already optimized
+fn push_down(expr: Expr, ctx: &EvalContext) -> Expr {
+    let (base, method, args) = match expr {
+        Expr::Call(callee, args) => match *callee {
+            Expr::Attr(base, method) => (base, method, args),
+            callee => return callee.call(args),
+        },
+        expr => return expr,
+    };
+    let movable = match method.as_str() {
+        "filter" => predicate(&args).is_some_and(row_wise),
+        name => SCOPE_METHODS.contains(&name),
+    };
+    if !movable {
+        return stage(*base, method, args);
+    }
+
+    let (inner, inner_method, inner_args) = match *base {
+        Expr::Call(inner_callee, inner_args) => match *inner_callee {
+            Expr::Attr(inner, inner_method) => (inner, inner_method, inner_args),
+            inner_callee => return stage(inner_callee.call(inner_args), method, args),
+        },
+        base => return stage(base, method, args),
+    };
+
+    if method == "filter"
+        && inner_method == "filter"
+        && let (Some(first), Some(second)) = (predicate(&inner_args), predicate(&args))
+    {
+        let merged = first.clone().binop(BinOp::And, second.clone());
+        return stage(*inner, method, vec![Arg::Positional(merged)]);
+    }
+
+    if can_pass(&method, &args, &inner, &inner_method, &inner_args, ctx) {
+        // Run this stage first, as early as it goes, then the one it passed
+        let moved = push_down(stage(*inner, method, args), ctx);
+        return stage(moved, inner_method, inner_args);
+    }
+    stage(stage(*inner, inner_method, inner_args), method, args)
+}
+
+/// Whether `.method(args)` gives the same rows before `inner.inner_method(inner_args)`
+/// as after it
+fn can_pass(
+    method: &str,
+    args: &[CoreArg],
+    inner: &Expr,
+    inner_method: &str,
+    inner_args: &[CoreArg],
+    ctx: &EvalContext,
+) -> bool {
+    // On a base table's name, a scope method reads history instead of `now`
+    if method != "filter"
+        && matches!(inner, Expr::Ident(name) if ctx.base_tables.contains_key(name))
+    {
+        return false;
+    }
+
+    if REORDERING_METHODS.contains(&inner_method) {
+        // Sort keys computed over fewer rows could order them differently
+        return inner_args.iter().all(|arg| row_wise(arg_expr(arg)));
+    }
+
+    if inner_method == "with_columns" {
+        let mut defined = BTreeSet::new();
+        let defines_known_columns = inner_args.iter().all(|arg| {
+            row_wise(arg_expr(arg))
+                && match arg {
+                    Arg::Keyword(name, _) => {
+                        defined.insert(name.clone());
+                        true
+                    }
+                    Arg::Positional(e) => output_names(e, &mut defined),
+                }
+        });
+        let mut read = BTreeSet::new();
+        let reads_known_columns = match method {
+            "filter" => predicate(args).is_some_and(|pred| columns(pred, &mut read)),
+            _ => {
+                read.extend(tick_columns(args, ctx));
+                true
+            }
+        };
+        return defines_known_columns && reads_known_columns && read.is_disjoint(&defined);
+    }
+    false
+}
+
+/// Every column a scope method called with `args` could take ticks from: its
+/// `tick_col=`, or any table's configured tick column
+fn tick_columns(args: &[CoreArg], ctx: &EvalContext) -> BTreeSet<String> {
+    let mut names: BTreeSet<String> = ctx
+        .base_tables
+        .values()
+        .map(|entry| entry.config.tick_column.clone())
+        .chain(
+            ctx.dataframes
+                .values()
+                .filter_map(|entry| entry.time_series.as_ref())
+                .map(|config| config.tick_column.clone()),
+        )
+        .chain(ctx.default_tick_column.clone())
+        .collect();
+    for arg in args {
+        if let Arg::Keyword(_, Expr::Literal(Literal::String(name))) = arg {
+            names.insert(name.clone());
+        }
+    }
+    names
+}
+
+/// The predicate of a `.filter(p)` call
+fn predicate(args: &[CoreArg]) -> Option<&Expr> {
+    match args {
+        [Arg::Positional(pred)] => Some(pred),
+        _ => None,
+    }
+}
+
+fn arg_expr(arg: &CoreArg) -> &Expr {
+    match arg {
+        Arg::Positional(e) | Arg::Keyword(_, e) => e,
+    }
+}
+
+/// Whether `expr` computes each row from that row alone
+fn row_wise(expr: &Expr) -> bool {
+    match expr {
+        Expr::Literal(_) => true,
+        Expr::List(items) => items.iter().all(row_wise),
+        Expr::BinaryOp(lhs, _, rhs) => row_wise(lhs) && row_wise(rhs),
+        Expr::UnaryOp(_, inner) => row_wise(inner),
+        Expr::WhenThenOtherwise {
+            branches,
+            otherwise,
+        } => {
+            branches
+                .iter()
+                .all(|(condition, value)| row_wise(condition) && row_wise(value))
+                && row_wise(otherwise)
+        }
+        Expr::Call(callee, args) => {
+            args.iter().all(|arg| row_wise(arg_expr(arg)))
+                && match &**callee {
+                    Expr::Attr(base, name) => match &**base {
+                        Expr::Ident(ns) if ns == "pl" => {
+                            ROW_WISE_FUNCTIONS.contains(&name.as_str())
+                        }
+                        Expr::Attr(inner, ns) if ns == "str" || ns == "dt" => row_wise(inner),
+                        // `is_in` against an expression tests the whole column it yields
+                        _ if name == "is_in" => {
+                            matches!(args.first(), Some(Arg::Positional(Expr::List(_))))
+                                && row_wise(base)
+                        }
+                        base => ROW_WISE_METHODS.contains(&name.as_str()) && row_wise(base),
+                    },
+                    _ => false,
+                }
+        }
+        Expr::Ident(_) | Expr::Attr(..) | Expr::Invalid(_) => false,
+    }
+}
+
+/// Add the columns `expr` reads to `out`; false if it may read columns not named
+/// literally (a regex, a selector, `pl.all()`)
+fn columns(expr: &Expr, out: &mut BTreeSet<String>) -> bool {
+    match expr {
+        Expr::Call(callee, args) => match &**callee {
+            Expr::Attr(base, name) if matches!(&**base, Expr::Ident(ns) if ns == "pl") => {
+                if !COLUMN_ARG_FUNCTIONS.contains(&name.as_str()) {
+                    return args.iter().all(|arg| columns(arg_expr(arg), out));
+                }
+                args.iter().all(|arg| match arg {
+                    Arg::Positional(e) => column_arg(e, name == "col", out),
+                    Arg::Keyword(_, e) => columns(e, out),
+                })
+            }
+            callee => columns(callee, out) && args.iter().all(|arg| columns(arg_expr(arg), out)),
+        },
+        Expr::Literal(_) => true,
+        Expr::List(items) => items.iter().all(|e| columns(e, out)),
+        Expr::Attr(base, _) => columns(base, out),
+        Expr::BinaryOp(lhs, _, rhs) => columns(lhs, out) && columns(rhs, out),
+        Expr::UnaryOp(_, inner) => columns(inner, out),
+        Expr::WhenThenOtherwise {
+            branches,
+            otherwise,
+        } => {
+            branches
+                .iter()
+                .all(|(condition, value)| columns(condition, out) && columns(value, out))
+                && columns(otherwise, out)
+        }
+        Expr::Ident(_) | Expr::Invalid(_) => false,
+    }
+}
+
+/// Add the columns a positional argument of a [`COLUMN_ARG_FUNCTIONS`] call reads
+/// to `out`; `names_only` for `pl.col`, which takes no expressions
+fn column_arg(expr: &Expr, names_only: bool, out: &mut BTreeSet<String>) -> bool {
+    match expr {
+        Expr::Literal(Literal::String(name)) => column_name(name, out),
+        Expr::List(items) => items.iter().all(|e| column_arg(e, names_only, out)),
+        _ if names_only => false,
+        e => columns(e, out),
+    }
+}
+
+fn column_name(name: &str, out: &mut BTreeSet<String>) -> bool {
+    if name == "*" || name.starts_with('^') {
+        return false;
+    }
+    out.insert(name.to_string());
+    true
+}
+
+/// Add the columns a positional `with_columns` argument defines to `out`
+fn output_names(expr: &Expr, out: &mut BTreeSet<String>) -> bool {
+    match expr {
+        Expr::List(items) => items.iter().all(|e| output_names(e, out)),
+        Expr::Call(callee, args) if matches!(&**callee, Expr::Attr(_, name) if name == "alias") => {
+            match predicate(args) {
+                Some(Expr::Literal(Literal::String(name))) => {
+                    out.insert(name.clone());
+                    true
+                }
+                _ => false,
+            }
+        }
+        // Unaliased, an expression is named after its first column; any it reads
+        // is a safe superset
+        e => columns(e, out),
+    }
+}
@@ -0,0 +1,179 @@
+//! Named query parameters
+//!
+//! `:name` placeholders in a query are replaced with values from a [`Params`] map
+//! before desugaring, so callers never splice values into query text.
+
+use std::collections::{BTreeSet, HashMap};
+
+use crate::ast::Literal;
+use crate::ast::surface::{Expr, SurfaceArg};
+
+/// Value bound to a `:name` placeholder
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParamValue {
+    String(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Null,
+    List(Vec<ParamValue>),
+}
+
+/// Placeholder values by name (without the leading `:`)
+pub type Params = HashMap<String, ParamValue>;
+
+impl From<&str> for ParamValue {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_string())
+    }
+}
+
+impl From<String> for ParamValue {
+    fn from(value: String) -> Self {
+        Self::String(value)
+    }
+}
+
+impl From<i64> for ParamValue {
+    fn from(value: i64) -> Self {
+        Self::Int(value)
+    }
+}
+
+impl From<i32> for ParamValue {
+    fn from(value: i32) -> Self {
+        Self::Int(value.into())
+    }
+}
+
+impl From<f64> for ParamValue {
+    fn from(value: f64) -> Self {
+        Self::Float(value)
+    }
+}
+
+impl From<bool> for ParamValue {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+impl<T: Into<ParamValue>> From<Vec<T>> for ParamValue {
+    fn from(values: Vec<T>) -> Self {
+        Self::List(values.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T: Into<ParamValue>> From<Option<T>> for ParamValue {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Self::Null, Into::into)
+    }
+}
+
+impl ParamValue {
+    fn into_expr(self) -> Expr {
+        match self {
+            Self::String(s) => Expr::Literal(Literal::String(s)),
+            Self::Int(i) => Expr::Literal(Literal::Int(i)),
+            Self::Float(f) => Expr::Literal(Literal::Float(f)),
+            Self::Bool(b) => Expr::Literal(Literal::Bool(b)),
+            Self::Null => Expr::Literal(Literal::Null),
+            Self::List(items) => Expr::List(items.into_iter().map(Self::into_expr).collect()),
+        }
+    }
+}
+
+/// Replace every `:name` placeholder with its value.
+///
+/// Returns the name of the first placeholder without a value.
+pub(crate) fn bind(expr: Expr, params: &Params) -> Result<Expr, String> {
+    Ok(match expr {
+        Expr::Param(name) => match params.get(&name) {
+            Some(value) => value.clone().into_expr(),
+            None => return Err(name),
+        },
+        Expr::Ident(_)
+        | Expr::Literal(_)
+        | Expr::ColShorthand(_)
+        | Expr::Alias(_)
+        | Expr::Error => expr,
+        Expr::List(items) => Expr::List(
+            items
+                .into_iter()
+                .map(|e| bind(e, params))
+                .collect::<Result<_, _>>()?,
+        ),
+        Expr::Attr(base, name) => Expr::Attr(Box::new(bind(*base, params)?), name),
+        Expr::Call(callee, args) => {
+            Expr::Call(Box::new(bind(*callee, params)?), bind_args(args, params)?)
+        }
+        Expr::BinaryOp(lhs, op, rhs) => Expr::BinaryOp(
+            Box::new(bind(*lhs, params)?),
+            op,
+            Box::new(bind(*rhs, params)?),
+        ),
+        Expr::UnaryOp(op, inner) => Expr::UnaryOp(op, Box::new(bind(*inner, params)?)),
+        Expr::Commented {
+            expr,
+            leading,
+            trailing,
+        } => Expr::Commented {
+            expr: Box::new(bind(*expr, params)?),
+            leading,
+            trailing,
+        },
+        Expr::Directive(name, args) => Expr::Directive(name, bind_args(args, params)?),
+        Expr::PipelineDirective(base, name, args) => Expr::PipelineDirective(
+            Box::new(bind(*base, params)?),
+            name,
+            bind_args(args, params)?,
+        ),
+    })
+}
+
+/// Add the name of every `:name` placeholder in `expr` to `names`
+pub(crate) fn placeholders(expr: &Expr, names: &mut BTreeSet<String>) {
+    match expr {
+        Expr::Param(name) => {
+            names.insert(name.clone());
+        }
+        Expr::Ident(_)
+        | Expr::Literal(_)
+        | Expr::ColShorthand(_)
+        | Expr::Alias(_)
+        | Expr::Error => {}
+        Expr::List(items) => items.iter().for_each(|e| placeholders(e, names)),
+        Expr::Attr(base, _) => placeholders(base, names),
+        Expr::Call(callee, args) => {
+            placeholders(callee, names);
+            arg_placeholders(args, names);
+        }
+        Expr::BinaryOp(lhs, _, rhs) => {
+            placeholders(lhs, names);
+            placeholders(rhs, names);
+        }
+        Expr::UnaryOp(_, inner) | Expr::Commented { expr: inner, .. } => placeholders(inner, names),
+        Expr::Directive(_, args) => arg_placeholders(args, names),
+        Expr::PipelineDirective(base, _, args) => {
+            placeholders(base, names);
+            arg_placeholders(args, names);
+        }
+    }
+}
+
+fn arg_placeholders(args: &[SurfaceArg], names: &mut BTreeSet<String>) {
+    for arg in args {
+        match arg {
+            SurfaceArg::Positional(e) | SurfaceArg::Keyword(_, e) => placeholders(e, names),
+        }
+    }
+}
+
+fn bind_args(args: Vec<SurfaceArg>, params: &Params) -> Result<Vec<SurfaceArg>, String> {
+    args.into_iter()
+        .map(|arg| match arg {
+            SurfaceArg::Positional(e) => bind(e, params).map(SurfaceArg::Positional),
+            SurfaceArg::Keyword(k, e) => bind(e, params).map(|e| SurfaceArg::Keyword(k, e)),
+        })
+        .collect()
+}
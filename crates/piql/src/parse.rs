@@ -3,7 +3,8 @@
 //! Produces surface::Expr which is then transformed to core::Expr before eval.
 
 use winnow::ascii::{digit1, multispace0};
-use winnow::combinator::{alt, delimited, opt, preceded, repeat, separated, terminated};
+use winnow::combinator::{alt, cut_err, delimited, opt, preceded, repeat, separated, terminated};
+use winnow::error::{ContextError, ErrMode, StrContext};
 use winnow::prelude::*;
 use winnow::token::{one_of, take_while};
 
@@ -18,14 +19,16 @@ pub struct ParseError {
     pub offset: usize,
     pub line: usize,
     pub column: usize,
+    /// The offending source line with a caret under `column`
+    pub snippet: String,
 }
 
 impl std::fmt::Display for ParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "{} (line {}, column {}, offset {})",
-            self.message, self.line, self.column, self.offset
+            "{} (line {}, column {}, offset {})\n{}",
+            self.message, self.line, self.column, self.offset, self.snippet
         )
     }
 }
@@ -33,12 +36,21 @@ impl std::fmt::Display for ParseError {
 impl std::error::Error for ParseError {}
 
 /// Parse a PiQL expression from a string
+///
+/// `#` comments are skipped; use [`parse_with_comments`] to keep them.
 pub fn parse(input: &str) -> Result<Expr, ParseError> {
+    parse_with_comments(input).map(Expr::strip_comments)
+}
+
+/// Parse a PiQL expression, keeping `# comments` as [`Expr::Commented`] nodes so the
+/// pretty-printer can reproduce them
+pub fn parse_with_comments(input: &str) -> Result<Expr, ParseError> {
     let input = input.trim();
     let mut stream = input;
     match expr.parse_next(&mut stream) {
         Ok(parsed) => {
-            if stream.trim().is_empty() {
+            let _ = ws(&mut stream);
+            if stream.is_empty() {
                 Ok(parsed)
             } else {
                 let offset = trailing_input_offset(input, stream);
@@ -51,11 +63,146 @@ pub fn parse(input: &str) -> Result<Expr, ParseError> {
         }
         Err(e) => {
             let offset = input.len().saturating_sub(stream.len());
-            Err(build_parse_error(format!("{:?}", e), input, offset))
+            let message = match e.into_inner() {
+                Ok(err) => error_message(&err),
+                Err(_) => "incomplete input".to_string(),
+            };
+            Err(build_parse_error(message, input, offset))
         }
     }
 }
 
+/// Best-effort parse of possibly incomplete input
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecoveredParse {
+    /// Surface AST with [`Expr::Error`] where input was missing or malformed, and
+    /// empty attribute names after a dangling `.`
+    pub expr: Expr,
+    /// Every error encountered, with offsets into the (trimmed) input
+    pub diagnostics: Vec<ParseError>,
+}
+
+/// Stands in for a missing expression or name while recovering
+const PLACEHOLDER: &str = "__piql_error__";
+
+/// Upper bound on repairs, so pathological input can't loop forever
+const MAX_REPAIRS: usize = 64;
+
+/// Parse without failing, for interactive tooling working on partial queries such
+/// as `entities.filter($gold >`.
+///
+/// Each error is recorded and repaired in a working copy of the input (a placeholder
+/// for a missing expression or name, a closing bracket or quote, or dropping trailing
+/// input), then parsing is retried. `#` comments are skipped.
+pub fn parse_recovering(input: &str) -> RecoveredParse {
+    let original = input.trim();
+    let mut working = original.to_string();
+    // (position in `working`, length) of each inserted repair
+    let mut inserts: Vec<(usize, usize)> = Vec::new();
+    let mut diagnostics = Vec::new();
+
+    for _ in 0..MAX_REPAIRS {
+        let err = match parse_with_comments(&working) {
+            Ok(expr) => {
+                return RecoveredParse {
+                    expr: replace_placeholders(expr.strip_comments()),
+                    diagnostics,
+                };
+            }
+            Err(err) => err,
+        };
+        let inserted: usize = inserts
+            .iter()
+            .filter(|(pos, _)| *pos < err.offset)
+            .map(|(_, len)| len)
+            .sum();
+        let offset = err.offset.saturating_sub(inserted).min(original.len());
+        diagnostics.push(build_parse_error(err.message.clone(), original, offset));
+
+        let repair = match err.message.as_str() {
+            "unexpected trailing input" => {
+                working.truncate(err.offset);
+                continue;
+            }
+            "unclosed string literal" => {
+                let quote = working[err.offset..].chars().next().unwrap_or('"');
+                (working.len(), quote.to_string())
+            }
+            m if m.contains("')'") => (err.offset, ")".to_string()),
+            m if m.contains("']'") => (err.offset, "]".to_string()),
+            m if m.starts_with("expected") => (err.offset, PLACEHOLDER.to_string()),
+            _ => break,
+        };
+        working.insert_str(repair.0, &repair.1);
+        inserts.push((repair.0, repair.1.len()));
+    }
+
+    RecoveredParse {
+        expr: Expr::Error,
+        diagnostics,
+    }
+}
+
+/// Turn repair placeholders into [`Expr::Error`] nodes (or empty attribute names)
+fn replace_placeholders(expr: Expr) -> Expr {
+    let replace_args = |args: Vec<SurfaceArg>| {
+        args.into_iter()
+            .map(|arg| match arg {
+                SurfaceArg::Positional(e) => SurfaceArg::Positional(replace_placeholders(e)),
+                SurfaceArg::Keyword(k, e) => SurfaceArg::Keyword(k, replace_placeholders(e)),
+            })
+            .collect()
+    };
+    match expr {
+        Expr::Ident(name) | Expr::ColShorthand(name) | Expr::Param(name) | Expr::Alias(name)
+            if name == PLACEHOLDER =>
+        {
+            Expr::Error
+        }
+        Expr::Directive(name, _) if name == PLACEHOLDER => Expr::Error,
+        Expr::Attr(base, name) => {
+            let name = if name == PLACEHOLDER {
+                String::new()
+            } else {
+                name
+            };
+            replace_placeholders(*base).attr(name)
+        }
+        Expr::List(items) => Expr::List(items.into_iter().map(replace_placeholders).collect()),
+        Expr::Call(callee, args) => replace_placeholders(*callee).call(replace_args(args)),
+        Expr::BinaryOp(lhs, op, rhs) => {
+            replace_placeholders(*lhs).binop(op, replace_placeholders(*rhs))
+        }
+        Expr::UnaryOp(op, inner) => Expr::UnaryOp(op, Box::new(replace_placeholders(*inner))),
+        Expr::Directive(name, args) => Expr::Directive(name, replace_args(args)),
+        Expr::PipelineDirective(base, name, args) => Expr::PipelineDirective(
+            Box::new(replace_placeholders(*base)),
+            name,
+            replace_args(args),
+        ),
+        Expr::Commented {
+            expr,
+            leading,
+            trailing,
+        } => Expr::Commented {
+            expr: Box::new(replace_placeholders(*expr)),
+            leading,
+            trailing,
+        },
+        other => other,
+    }
+}
+
+/// The innermost context label, or a generic message for uncommitted failures
+fn error_message(err: &ContextError) -> String {
+    err.context()
+        .find_map(|ctx| match ctx {
+            StrContext::Label(label) => Some(label.to_string()),
+            _ => None,
+        })
+        .unwrap_or_else(|| "expected expression".to_string())
+}
+
 fn build_parse_error(message: String, input: &str, offset: usize) -> ParseError {
     let (line, column) = offset_to_line_column(input, offset);
     ParseError {
@@ -63,9 +210,26 @@ fn build_parse_error(message: String, input: &str, offset: usize) -> ParseError
         offset,
         line,
         column,
+        snippet: render_snippet(input, line, column),
     }
 }
 
+/// Render `line` of `input` with a caret under `column`:
+///
+/// ```text
+/// 1 | entities.filter($gold >)
+///   |                        ^
+/// ```
+fn render_snippet(input: &str, line: usize, column: usize) -> String {
+    let text = input.lines().nth(line - 1).unwrap_or("");
+    let gutter = line.to_string();
+    format!(
+        "{gutter} | {text}\n{pad} | {caret}^",
+        pad = " ".repeat(gutter.len()),
+        caret = " ".repeat(column - 1),
+    )
+}
+
 fn offset_to_line_column(input: &str, offset: usize) -> (usize, usize) {
     let bounded = offset.min(input.len());
     let mut line = 1usize;
@@ -93,6 +257,15 @@ fn trailing_input_offset(input: &str, trailing: &str) -> usize {
     base + non_ws
 }
 
+/// Commit to `parser` once its prefix has matched: failures are reported as `message`
+/// at the failing position instead of backtracking
+fn committed<'a, O>(
+    parser: impl Parser<&'a str, O, ErrMode<ContextError>>,
+    message: &'static str,
+) -> impl Parser<&'a str, O, ErrMode<ContextError>> {
+    cut_err(parser).context(StrContext::Label(message))
+}
+
 // ============ Top-level expression (handles precedence) ============
 
 fn expr(input: &mut &str) -> PResult<Expr> {
@@ -101,7 +274,14 @@ fn expr(input: &mut &str) -> PResult<Expr> {
 
 fn or_expr(input: &mut &str) -> PResult<Expr> {
     let first = and_expr.parse_next(input)?;
-    let rest: Vec<Expr> = repeat(0.., preceded((ws, '|', ws), and_expr)).parse_next(input)?;
+    let rest: Vec<Expr> = repeat(
+        0..,
+        preceded(
+            (ws, '|'),
+            committed(and_expr, "expected expression after '|'"),
+        ),
+    )
+    .parse_next(input)?;
     Ok(rest.into_iter().fold(first, |l, r| {
         Expr::BinaryOp(Box::new(l), BinOp::Or, Box::new(r))
     }))
@@ -109,7 +289,14 @@ fn or_expr(input: &mut &str) -> PResult<Expr> {
 
 fn and_expr(input: &mut &str) -> PResult<Expr> {
     let first = cmp_expr.parse_next(input)?;
-    let rest: Vec<Expr> = repeat(0.., preceded((ws, '&', ws), cmp_expr)).parse_next(input)?;
+    let rest: Vec<Expr> = repeat(
+        0..,
+        preceded(
+            (ws, '&'),
+            committed(cmp_expr, "expected expression after '&'"),
+        ),
+    )
+    .parse_next(input)?;
     Ok(rest.into_iter().fold(first, |l, r| {
         Expr::BinaryOp(Box::new(l), BinOp::And, Box::new(r))
     }))
@@ -117,8 +304,13 @@ fn and_expr(input: &mut &str) -> PResult<Expr> {
 
 fn cmp_expr(input: &mut &str) -> PResult<Expr> {
     let left = add_expr.parse_next(input)?;
-    let rest: Option<(BinOp, Expr)> =
-        opt((ws, cmp_op, ws, add_expr).map(|(_, op, _, e)| (op, e))).parse_next(input)?;
+    let rest: Option<(BinOp, Expr)> = opt((
+        ws,
+        cmp_op,
+        committed(add_expr, "expected expression after comparison operator"),
+    )
+        .map(|(_, op, e)| (op, e)))
+    .parse_next(input)?;
     match rest {
         Some((op, right)) => Ok(Expr::BinaryOp(Box::new(left), op, Box::new(right))),
         None => Ok(left),
@@ -139,8 +331,16 @@ fn cmp_op(input: &mut &str) -> PResult<BinOp> {
 
 fn add_expr(input: &mut &str) -> PResult<Expr> {
     let first = mul_expr.parse_next(input)?;
-    let rest: Vec<(BinOp, Expr)> =
-        repeat(0.., (ws, add_op, ws, mul_expr).map(|(_, op, _, e)| (op, e))).parse_next(input)?;
+    let rest: Vec<(BinOp, Expr)> = repeat(
+        0..,
+        (
+            ws,
+            add_op,
+            committed(mul_expr, "expected expression after operator"),
+        )
+            .map(|(_, op, e)| (op, e)),
+    )
+    .parse_next(input)?;
     Ok(rest.into_iter().fold(first, |l, (op, r)| {
         Expr::BinaryOp(Box::new(l), op, Box::new(r))
     }))
@@ -154,7 +354,12 @@ fn mul_expr(input: &mut &str) -> PResult<Expr> {
     let first = unary_expr.parse_next(input)?;
     let rest: Vec<(BinOp, Expr)> = repeat(
         0..,
-        (ws, mul_op, ws, unary_expr).map(|(_, op, _, e)| (op, e)),
+        (
+            ws,
+            mul_op,
+            committed(unary_expr, "expected expression after operator"),
+        )
+            .map(|(_, op, e)| (op, e)),
     )
     .parse_next(input)?;
     Ok(rest.into_iter().fold(first, |l, (op, r)| {
@@ -165,19 +370,26 @@ fn mul_expr(input: &mut &str) -> PResult<Expr> {
 fn mul_op(input: &mut &str) -> PResult<BinOp> {
     alt((
         '*'.value(BinOp::Mul),
+        "//".value(BinOp::FloorDiv),
         '/'.value(BinOp::Div),
         '%'.value(BinOp::Mod),
     ))
     .parse_next(input)
 }
 
+/// Operands start here, so comments before them are kept as leading comments; the
+/// parsers leading up to an operand leave whitespace and comments for it to consume.
 fn unary_expr(input: &mut &str) -> PResult<Expr> {
-    alt((
-        preceded(('-', ws), unary_expr).map(|e| Expr::UnaryOp(UnaryOp::Neg, Box::new(e))),
-        preceded(('~', ws), unary_expr).map(|e| Expr::UnaryOp(UnaryOp::Not, Box::new(e))),
+    let leading = comments.parse_next(input)?;
+    let expr = alt((
+        preceded('-', committed(unary_expr, "expected expression after '-'"))
+            .map(|e| Expr::UnaryOp(UnaryOp::Neg, Box::new(e))),
+        preceded('~', committed(unary_expr, "expected expression after '~'"))
+            .map(|e| Expr::UnaryOp(UnaryOp::Not, Box::new(e))),
         postfix_expr,
     ))
-    .parse_next(input)
+    .parse_next(input)?;
+    Ok(expr.with_leading_comments(leading))
 }
 
 // ============ Postfix expressions (.attr and (call)) ============
@@ -185,24 +397,35 @@ fn unary_expr(input: &mut &str) -> PResult<Expr> {
 enum Postfix {
     Attr(String),
     Call(Vec<SurfaceArg>),
+    Directive(String, Vec<SurfaceArg>),
 }
 
 fn postfix_expr(input: &mut &str) -> PResult<Expr> {
-    let base = primary.parse_next(input)?;
-    let ops: Vec<Postfix> = repeat(0.., postfix_op).parse_next(input)?;
-
-    Ok(ops.into_iter().fold(base, |acc, op| match op {
-        Postfix::Attr(name) => Expr::Attr(Box::new(acc), name),
-        Postfix::Call(args) => Expr::Call(Box::new(acc), args),
-    }))
+    let mut acc = primary.parse_next(input)?;
+    loop {
+        // Comments between chain segments stay with the expression before them
+        let trailing = comments.parse_next(input)?;
+        acc = acc.with_trailing_comments(trailing);
+        match opt(alt((pipeline_directive, attr_access, call_expr))).parse_next(input)? {
+            Some(Postfix::Attr(name)) => acc = Expr::Attr(Box::new(acc), name),
+            Some(Postfix::Call(args)) => acc = Expr::Call(Box::new(acc), args),
+            Some(Postfix::Directive(name, args)) => {
+                acc = Expr::PipelineDirective(Box::new(acc), name, args)
+            }
+            None => return Ok(acc),
+        }
+    }
 }
 
-fn postfix_op(input: &mut &str) -> PResult<Postfix> {
-    preceded(ws, alt((attr_access, call_expr))).parse_next(input)
+/// Parse pipeline directive: .@latest_per($entity_id)
+fn pipeline_directive(input: &mut &str) -> PResult<Postfix> {
+    preceded('.', directive_parts)
+        .map(|(name, args)| Postfix::Directive(name, args))
+        .parse_next(input)
 }
 
 fn attr_access(input: &mut &str) -> PResult<Postfix> {
-    preceded('.', ident_str)
+    preceded('.', committed(ident_str, "expected name after '.'"))
         .map(Postfix::Attr)
         .parse_next(input)
 }
@@ -210,8 +433,8 @@ fn attr_access(input: &mut &str) -> PResult<Postfix> {
 fn call_expr(input: &mut &str) -> PResult<Postfix> {
     delimited(
         '(',
-        (ws, opt(call_args), ws).map(|(_, args, _)| args.unwrap_or_default()),
-        ')',
+        (opt(call_args), ws).map(|(args, _)| args.unwrap_or_default()),
+        committed(')', "expected ',' or ')' to close argument list"),
     )
     .map(Postfix::Call)
     .parse_next(input)
@@ -219,7 +442,7 @@ fn call_expr(input: &mut &str) -> PResult<Postfix> {
 
 fn call_args(input: &mut &str) -> PResult<Vec<SurfaceArg>> {
     terminated(
-        separated(1.., call_arg, (ws, ',', ws)),
+        separated(1.., call_arg, (ws, ',')),
         opt((ws, ',')), // trailing comma
     )
     .parse_next(input)
@@ -227,8 +450,10 @@ fn call_args(input: &mut &str) -> PResult<Vec<SurfaceArg>> {
 
 fn call_arg(input: &mut &str) -> PResult<SurfaceArg> {
     alt((
-        // keyword arg: name=expr
-        (ident_str, ws, '=', ws, expr).map(|(name, _, _, _, e)| SurfaceArg::Keyword(name, e)),
+        // keyword arg: name=expr (comments before the name move to the value)
+        (comments, ident_str, ws, '=', expr).map(|(leading, name, _, _, e)| {
+            SurfaceArg::Keyword(name, e.with_leading_comments(leading))
+        }),
         // positional arg
         expr.map(SurfaceArg::Positional),
     ))
@@ -245,6 +470,8 @@ fn primary(input: &mut &str) -> PResult<Expr> {
             list_expr,
             col_shorthand,
             directive,
+            param,
+            alias,
             literal.map(Expr::Literal),
             ident.map(Expr::Ident),
         )),
@@ -254,34 +481,80 @@ fn primary(input: &mut &str) -> PResult<Expr> {
 
 /// Parse column shorthand: $gold -> ColShorthand("gold")
 fn col_shorthand(input: &mut &str) -> PResult<Expr> {
-    preceded('$', ident_str)
+    preceded('$', committed(ident_str, "expected column name after '$'"))
         .map(Expr::ColShorthand)
         .parse_next(input)
 }
 
 /// Parse directive: @merchant, @entity(42)
 fn directive(input: &mut &str) -> PResult<Expr> {
+    directive_parts
+        .map(|(name, args)| Expr::Directive(name, args))
+        .parse_next(input)
+}
+
+/// Name and arguments of `@name` or `@name(args)`
+fn directive_parts(input: &mut &str) -> PResult<(String, Vec<SurfaceArg>)> {
     (
-        preceded('@', ident_str),
-        opt(delimited(('(', ws), call_args, (ws, ')'))),
+        preceded(
+            '@',
+            committed(ident_str, "expected directive name after '@'"),
+        ),
+        opt(delimited(
+            '(',
+            committed(call_args, "expected directive arguments after '('"),
+            (
+                ws,
+                committed(')', "expected ',' or ')' to close argument list"),
+            ),
+        )),
     )
-        .map(|(name, args)| Expr::Directive(name, args.unwrap_or_default()))
+        .map(|(name, args)| (name, args.unwrap_or_default()))
         .parse_next(input)
 }
 
+/// Parse alias reference: !rich_merchants -> Alias("rich_merchants")
+fn alias(input: &mut &str) -> PResult<Expr> {
+    preceded('!', committed(ident_str, "expected alias name after '!'"))
+        .map(Expr::Alias)
+        .parse_next(input)
+}
+
+/// Parse placeholder: :threshold -> Param("threshold")
+fn param(input: &mut &str) -> PResult<Expr> {
+    preceded(
+        ':',
+        committed(
+            (
+                one_of(|c: char| c.is_ascii_alphabetic() || c == '_'),
+                take_while(0.., |c: char| c.is_ascii_alphanumeric() || c == '_'),
+            )
+                .take(),
+            "expected parameter name after ':'",
+        ),
+    )
+    .map(|name: &str| Expr::Param(name.to_string()))
+    .parse_next(input)
+}
+
 fn paren_expr(input: &mut &str) -> PResult<Expr> {
-    delimited(('(', ws), expr, (ws, ')')).parse_next(input)
+    delimited(
+        '(',
+        committed(expr, "expected expression after '('"),
+        (
+            ws,
+            committed(')', "expected ')' to close parenthesized expression"),
+        ),
+    )
+    .parse_next(input)
 }
 
 fn list_expr(input: &mut &str) -> PResult<Expr> {
     delimited(
-        ('[', ws),
-        opt(terminated(
-            separated(1.., expr, (ws, ',', ws)),
-            opt((ws, ',')),
-        ))
-        .map(|items| items.unwrap_or_default()),
-        (ws, ']'),
+        '[',
+        opt(terminated(separated(1.., expr, (ws, ',')), opt((ws, ','))))
+            .map(|items| items.unwrap_or_default()),
+        (ws, committed(']', "expected ',' or ']' to close list")),
     )
     .map(Expr::List)
     .parse_next(input)
@@ -351,12 +624,18 @@ fn float_lit(input: &mut &str) -> PResult<Literal> {
 }
 
 fn string_lit(input: &mut &str) -> PResult<Literal> {
-    alt((
-        delimited('"', string_contents('"'), '"'),
-        delimited('\'', string_contents('\''), '\''),
-    ))
-    .map(Literal::String)
-    .parse_next(input)
+    let start = *input;
+    let quote = one_of(['"', '\'']).parse_next(input)?;
+    match terminated(string_contents(quote), quote).parse_next(input) {
+        Ok(s) => Ok(Literal::String(s)),
+        Err(_) => {
+            // Point at the opening quote rather than the end of input
+            *input = start;
+            let mut err = ContextError::new();
+            err.push(StrContext::Label("unclosed string literal"));
+            Err(ErrMode::Cut(err))
+        }
+    }
 }
 
 fn string_contents<'a>(quote: char) -> impl FnMut(&mut &'a str) -> PResult<String> {
@@ -400,10 +679,24 @@ fn string_contents<'a>(quote: char) -> impl FnMut(&mut &'a str) -> PResult<Strin
     }
 }
 
-// ============ Whitespace ============
+// ============ Whitespace and comments ============
 
 fn ws(input: &mut &str) -> PResult<()> {
-    multispace0.void().parse_next(input)
+    comments.void().parse_next(input)
+}
+
+/// Skip whitespace and `#`-to-end-of-line comments, returning the comment texts
+fn comments(input: &mut &str) -> PResult<Vec<String>> {
+    let mut out = Vec::new();
+    loop {
+        multispace0.parse_next(input)?;
+        let Some(rest) = input.strip_prefix('#') else {
+            return Ok(out);
+        };
+        let end = rest.find('\n').unwrap_or(rest.len());
+        out.push(rest[..end].trim_end().to_string());
+        *input = &rest[end..];
+    }
 }
 
 // ============ Sanity Tests ============
@@ -413,6 +706,84 @@ fn ws(input: &mut &str) -> PResult<()> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn parse_skips_comments() {
+        let with = parse("# top\ndf # base\n  .filter($x > 1) # why\n  .head(5)\n# end").unwrap();
+        assert_eq!(with, parse("df.filter($x > 1).head(5)").unwrap());
+
+        // `#` inside strings is not a comment
+        assert_eq!(
+            parse(r#""a # b""#).unwrap(),
+            Expr::Literal(Literal::String("a # b".into()))
+        );
+    }
+
+    #[test]
+    fn parse_with_comments_attaches_to_neighbours() {
+        // Leading comments belong to the whole operand, trailing ones to the chain so far
+        let expr = parse_with_comments("# top\ndf # base\n.head(5)").unwrap();
+        let df = Expr::Commented {
+            expr: Box::new(Expr::Ident("df".into())),
+            leading: vec![],
+            trailing: vec![" base".into()],
+        };
+        let expected = Expr::Commented {
+            expr: Box::new(
+                df.attr("head")
+                    .call(vec![SurfaceArg::Positional(Expr::Literal(Literal::Int(5)))]),
+            ),
+            leading: vec![" top".into()],
+            trailing: vec![],
+        };
+        assert_eq!(expr, expected);
+    }
+
+    #[test]
+    fn parse_recovering_fills_in_partial_queries() {
+        let recovered = parse_recovering("entities.filter($gold >");
+        assert_eq!(
+            recovered.expr,
+            Expr::Ident("entities".into())
+                .attr("filter")
+                .call(vec![SurfaceArg::Positional(
+                    Expr::ColShorthand("gold".into()).binop(BinOp::Gt, Expr::Error)
+                )])
+        );
+        let messages: Vec<_> = recovered
+            .diagnostics
+            .iter()
+            .map(|d| d.message.as_str())
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "expected expression after comparison operator",
+                "expected ',' or ')' to close argument list"
+            ]
+        );
+        // Offsets refer to the original input, not the repaired copy
+        assert!(recovered.diagnostics.iter().all(|d| d.offset == 23));
+    }
+
+    #[test]
+    fn parse_recovering_handles_dangling_dot_and_strings() {
+        let recovered = parse_recovering("entities.");
+        assert_eq!(recovered.expr, Expr::Ident("entities".into()).attr(""));
+
+        let recovered = parse_recovering("df.filter($name == 'bo");
+        assert_eq!(recovered.diagnostics[0].message, "unclosed string literal");
+        assert_eq!(recovered.diagnostics[0].offset, 19);
+        assert!(matches!(recovered.expr, Expr::Call(..)));
+
+        let recovered = parse_recovering("df.head(1) )");
+        assert_eq!(recovered.diagnostics.len(), 1);
+        assert_eq!(recovered.expr, parse("df.head(1)").unwrap());
+
+        // Valid input parses cleanly
+        let recovered = parse_recovering("df.head(1)");
+        assert!(recovered.diagnostics.is_empty());
+    }
+
     #[test]
     fn parse_literals() {
         assert!(matches!(
@@ -495,6 +866,39 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parse_alias() {
+        let result = parse("!rich.head(3)").unwrap();
+        let Expr::Call(callee, _) = result else {
+            panic!("Expected call");
+        };
+        assert!(matches!(*callee, Expr::Attr(ref base, _) if **base == Expr::Alias("rich".into())));
+
+        // `!=` is still an operator
+        let result = parse("$a != !limit").unwrap();
+        assert!(
+            matches!(result, Expr::BinaryOp(_, BinOp::Ne, ref rhs) if **rhs == Expr::Alias("limit".into()))
+        );
+    }
+
+    #[test]
+    fn parse_pipeline_directive() {
+        let result = parse("entities.@latest_per($entity_id).head(5)").unwrap();
+        let Expr::Call(callee, _) = result else {
+            panic!("Expected call");
+        };
+        let Expr::Attr(base, _) = *callee else {
+            panic!("Expected attr");
+        };
+        if let Expr::PipelineDirective(receiver, name, args) = *base {
+            assert!(matches!(*receiver, Expr::Ident(ref s) if s == "entities"));
+            assert_eq!(name, "latest_per");
+            assert_eq!(args.len(), 1);
+        } else {
+            panic!("Expected pipeline directive");
+        }
+    }
+
     #[test]
     fn parse_string_unknown_escape_non_ascii() {
         let result = parse("\"\\é\"").unwrap();
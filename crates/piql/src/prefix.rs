@@ -0,0 +1,164 @@
+//! Method-chain prefixes shared by subscriptions
+//!
+//! Subscriptions often start the same way (`entities.at(100).filter(@merchant)`)
+//! and differ only in their last few stages. Each tick, the engine finds the
+//! longest prefix of each subscription's chain that another subscription also
+//! starts with, collects every such prefix once, and evaluates the rest of each
+//! subscription against the collected table. A prefix that itself starts with a
+//! shorter shared one is built from it in turn.
+//!
+//! Only prefixes evaluating to a frame read from a single table are shared: the
+//! collected table takes that table's time-series config, so scope methods and
+//! sugar later in the chain resolve the same tick column and partition key.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::ast::core::Expr;
+use crate::eval::{self, DataFrameEntry, EvalContext, Value};
+use crate::query::CompiledQuery;
+
+/// Subscriptions rewritten to read their shared prefixes, and the context holding
+/// the collected prefixes
+pub(crate) struct SharedPrefixes {
+    pub ctx: EvalContext,
+    /// Rewritten query of each subscription with a shared prefix, by name
+    pub rebased: HashMap<String, CompiledQuery>,
+    /// Prefixes collected this tick
+    pub evaluated: usize,
+}
+
+/// Names the collected prefixes are registered under. `#` can't appear in an
+/// identifier, so no query reads a table of this name by accident.
+fn prefix_table_name(index: usize) -> String {
+    format!("prefix#{index}")
+}
+
+/// Proper and improper prefixes of `expr`'s method chain, longest (`expr`) first;
+/// the table the chain starts from is left out
+fn chain_prefixes(expr: &Expr) -> Vec<&Expr> {
+    let mut prefixes = Vec::new();
+    let mut current = expr;
+    loop {
+        let base = match current {
+            Expr::Call(callee, _) => match callee.as_ref() {
+                Expr::Attr(base, _) => base,
+                _ => break,
+            },
+            Expr::Attr(base, _) => base,
+            _ => break,
+        };
+        prefixes.push(current);
+        current = base;
+    }
+    prefixes
+}
+
+/// Structural key of a prefix, equal for equal ASTs
+fn key(expr: &Expr) -> String {
+    format!("{expr:?}")
+}
+
+/// Replace the longest prefix of `expr`'s chain found in `tables` with a read of
+/// its collected table
+fn rebase(expr: &Expr, tables: &HashMap<String, String>) -> Option<Expr> {
+    if let Some(name) = tables.get(&key(expr)) {
+        return Some(Expr::Ident(name.clone()));
+    }
+    match expr {
+        Expr::Call(callee, args) => match callee.as_ref() {
+            Expr::Attr(base, method) => {
+                rebase(base, tables).map(|base| base.attr(method.clone()).call(args.clone()))
+            }
+            _ => None,
+        },
+        Expr::Attr(base, name) => rebase(base, tables).map(|base| base.attr(name.clone())),
+        _ => None,
+    }
+}
+
+/// A subscription's name and query, with its chain prefixes keyed by their text
+type KeyedPrefixes<'a> = (&'a str, &'a CompiledQuery, Vec<(String, &'a Expr)>);
+
+/// Collect the prefixes `subscriptions` share and rewrite each subscription to
+/// read its longest one; `None` if no two subscriptions start alike
+pub(crate) fn share_prefixes<'a>(
+    subscriptions: impl IntoIterator<Item = (&'a str, &'a CompiledQuery)>,
+    ctx: &EvalContext,
+) -> Option<SharedPrefixes> {
+    let subscriptions: Vec<KeyedPrefixes> = subscriptions
+        .into_iter()
+        .map(|(name, compiled)| {
+            let prefixes = chain_prefixes(compiled.core())
+                .into_iter()
+                .map(|prefix| (key(prefix), prefix))
+                .collect();
+            (name, compiled, prefixes)
+        })
+        .collect();
+
+    // Subscriptions starting with each prefix
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for (_, _, prefixes) in &subscriptions {
+        let distinct: HashSet<&str> = prefixes.iter().map(|(key, _)| key.as_str()).collect();
+        for key in distinct {
+            *counts.entry(key).or_default() += 1;
+        }
+    }
+
+    // Each subscription's longest shared prefix, shortest (by chain length) first
+    // so longer ones can start from them
+    let mut chosen: BTreeMap<(usize, &str), &Expr> = BTreeMap::new();
+    for (_, _, prefixes) in &subscriptions {
+        let depth = prefixes.len();
+        if let Some((index, (key, prefix))) = prefixes
+            .iter()
+            .enumerate()
+            .find(|(_, (key, _))| counts[key.as_str()] > 1)
+        {
+            chosen.insert((depth - index, key.as_str()), *prefix);
+        }
+    }
+    if chosen.is_empty() {
+        return None;
+    }
+
+    let mut shared_ctx = ctx.clone();
+    let mut tables = HashMap::new();
+    for ((_, key), prefix) in chosen {
+        let prefix = rebase(prefix, &tables).unwrap_or_else(|| prefix.clone());
+        // A prefix that fails stays unshared, so each subscription reports the error
+        let Ok(Value::DataFrame(lf, lineage)) = eval::eval(&prefix, &shared_ctx) else {
+            continue;
+        };
+        let Some(source) = lineage.source_name() else {
+            continue;
+        };
+        let time_series = shared_ctx.time_series_config_of(source).cloned();
+        let Ok(df) = lf.collect() else {
+            continue;
+        };
+        let name = prefix_table_name(tables.len());
+        shared_ctx.dataframes.insert(
+            name.clone(),
+            DataFrameEntry {
+                df,
+                time_series,
+                scan: None,
+            },
+        );
+        tables.insert(key.to_string(), name);
+    }
+
+    let rebased = subscriptions
+        .iter()
+        .filter_map(|(name, compiled, _)| {
+            let core = rebase(compiled.core(), &tables)?;
+            Some((name.to_string(), (*compiled).clone().with_core(core)))
+        })
+        .collect();
+    Some(SharedPrefixes {
+        ctx: shared_ctx,
+        evaluated: tables.len(),
+        rebased,
+    })
+}
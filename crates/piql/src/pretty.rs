@@ -41,6 +41,7 @@ impl Display for BinOp {
             BinOp::Mul => "*",
             BinOp::Div => "/",
             BinOp::Mod => "%",
+            BinOp::FloorDiv => "//",
             BinOp::Eq => "==",
             BinOp::Ne => "!=",
             BinOp::Lt => "<",
@@ -119,6 +120,28 @@ impl Display for Expr {
                 }
             }
             Expr::ColShorthand(name) => write!(f, "${}", name),
+            Expr::Param(name) => write!(f, ":{}", name),
+            Expr::Alias(name) => write!(f, "!{}", name),
+            Expr::Error => write!(f, "<error>"),
+            Expr::Commented {
+                expr,
+                leading,
+                trailing,
+            } => {
+                for comment in leading {
+                    writeln!(f, "#{}", comment)?;
+                }
+                // Parenthesize operators so the comments stay attached to the same node
+                if matches!(expr.as_ref(), Expr::BinaryOp(..) | Expr::UnaryOp(..)) {
+                    write!(f, "({})", expr)?;
+                } else {
+                    write!(f, "{}", expr)?;
+                }
+                for comment in trailing {
+                    writeln!(f, " #{}", comment)?;
+                }
+                Ok(())
+            }
             Expr::Directive(name, args) => {
                 write!(f, "@{}", name)?;
                 if !args.is_empty() {
@@ -128,6 +151,20 @@ impl Display for Expr {
                 }
                 Ok(())
             }
+            Expr::PipelineDirective(base, name, args) => {
+                let needs_parens = matches!(base.as_ref(), Expr::BinaryOp(..) | Expr::UnaryOp(..));
+                if needs_parens {
+                    write!(f, "({}).@{}", base, name)?;
+                } else {
+                    write!(f, "{}.@{}", base, name)?;
+                }
+                if !args.is_empty() {
+                    write!(f, "(")?;
+                    write_args(f, args)?;
+                    write!(f, ")")?;
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -177,6 +214,8 @@ enum ChainSegment<'a> {
     Attr(&'a str),
     /// Method call: `.name(args)`
     Call(&'a str, &'a [SurfaceArg]),
+    /// Pipeline directive: `.@name(args)`
+    Directive(&'a str, &'a [SurfaceArg]),
 }
 
 /// Flatten a method chain into segments
@@ -201,6 +240,10 @@ fn collect_chain<'a>(expr: &'a Expr, segments: &mut Vec<ChainSegment<'a>>) {
             collect_chain(base, segments);
             segments.push(ChainSegment::Attr(name));
         }
+        Expr::PipelineDirective(base, name, args) => {
+            collect_chain(base, segments);
+            segments.push(ChainSegment::Directive(name, args));
+        }
         _ => {
             segments.push(ChainSegment::Base(expr));
         }
@@ -252,33 +295,16 @@ pub fn pretty(expr: &Expr, width: usize) -> String {
                 if i > 1 {
                     result.push_str("\n    ");
                 }
-                let args_str = format_args(args);
-
-                // If args are long, consider breaking them too
-                let call_line = format!(".{}({})", name, args_str);
-                if call_line.len() > width - 4 && args.len() > 1 {
-                    // Break args across lines
-                    write!(result, ".{}(\n        ", name).unwrap();
-                    for (j, arg) in args.iter().enumerate() {
-                        if j > 0 {
-                            result.push_str(",\n        ");
-                        }
-                        // Recursively pretty print arg expressions
-                        let arg_str = match arg {
-                            Arg::Positional(e) => pretty(e, width.saturating_sub(8)),
-                            Arg::Keyword(k, e) => {
-                                format!(
-                                    "{}={}",
-                                    k,
-                                    pretty(e, width.saturating_sub(8 + k.len() + 1))
-                                )
-                            }
-                        };
-                        result.push_str(&arg_str);
-                    }
-                    result.push_str("\n    )");
+                write_call_segment(&mut result, name, args, width);
+            }
+            ChainSegment::Directive(name, args) => {
+                if i > 1 {
+                    result.push_str("\n    ");
+                }
+                if args.is_empty() {
+                    write!(result, ".@{}", name).unwrap();
                 } else {
-                    result.push_str(&call_line);
+                    write_call_segment(&mut result, &format!("@{name}"), args, width);
                 }
             }
         }
@@ -287,6 +313,34 @@ pub fn pretty(expr: &Expr, width: usize) -> String {
     result
 }
 
+/// Write `.name(args)`, breaking the arguments across lines if they are long
+fn write_call_segment(result: &mut String, name: &str, args: &[SurfaceArg], width: usize) {
+    let args_str = format_args(args);
+
+    // If args are long, consider breaking them too
+    let call_line = format!(".{}({})", name, args_str);
+    if call_line.len() > width - 4 && args.len() > 1 {
+        // Break args across lines
+        write!(result, ".{}(\n        ", name).unwrap();
+        for (j, arg) in args.iter().enumerate() {
+            if j > 0 {
+                result.push_str(",\n        ");
+            }
+            // Recursively pretty print arg expressions
+            let arg_str = match arg {
+                Arg::Positional(e) => pretty(e, width.saturating_sub(8)),
+                Arg::Keyword(k, e) => {
+                    format!("{}={}", k, pretty(e, width.saturating_sub(8 + k.len() + 1)))
+                }
+            };
+            result.push_str(&arg_str);
+        }
+        result.push_str("\n    )");
+    } else {
+        result.push_str(&call_line);
+    }
+}
+
 impl Expr {
     /// Pretty print with intelligent line breaking at the given width
     pub fn pretty(&self, width: usize) -> String {
@@ -311,6 +365,9 @@ mod tests {
 
         let expr = parse("@entity(42)").unwrap();
         assert_eq!(expr.to_string(), "@entity(42)");
+
+        let expr = parse("entities.@latest_per($entity_id).head(5)").unwrap();
+        assert_eq!(expr.to_string(), "entities.@latest_per($entity_id).head(5)");
     }
 
     #[test]
@@ -411,6 +468,23 @@ mod tests {
         }
     }
 
+    #[test]
+    fn comments_round_trip() {
+        use crate::parse::parse_with_comments;
+
+        for q in [
+            "# daily report\nentities.filter($gold > 100) # rich only\n.head(5)",
+            "df.select($a * # scaled\n($b + 1))",
+            "df.filter(# inline\n$x > 1 # trailing\n)",
+        ] {
+            let expr = parse_with_comments(q).unwrap();
+            let printed = expr.to_string();
+            assert!(printed.contains('#'), "comments dropped: {printed}");
+            let reparsed = parse_with_comments(&printed).unwrap();
+            assert_eq!(expr, reparsed, "round trip failed for: {q}\n{printed}");
+        }
+    }
+
     #[test]
     fn literal_display_round_trip() {
         for q in ["True", "False", "None", "1.0"] {
@@ -0,0 +1,357 @@
+//! Compiling and running queries against an [`EvalContext`]
+
+use std::collections::BTreeSet;
+
+use polars::prelude::{DataFrame, IntoLazy, PolarsResult, Scalar};
+
+use crate::ast;
+use crate::eval::{self, DataFrameLineage, EvalContext, Value};
+use crate::params::{self, Params};
+use crate::{PiqlError, alias, optimize, parse, transform};
+
+/// A query compiled to core AST for repeated execution.
+#[derive(Clone)]
+pub struct CompiledQuery {
+    core: ast::core::Expr,
+    query: String,
+    /// Tick set by `.as_of(n)`, evaluated against [`EvalContext::as_of`]
+    as_of: Option<i64>,
+}
+
+/// Compile a query once for repeated execution.
+pub fn compile(query: &str, ctx: &EvalContext) -> Result<CompiledQuery, PiqlError> {
+    compile_with_params(query, &Params::new(), ctx)
+}
+
+/// Compile a query, binding `:name` placeholders to `params`.
+pub fn compile_with_params(
+    query: &str,
+    params: &Params,
+    ctx: &EvalContext,
+) -> Result<CompiledQuery, PiqlError> {
+    let surface = parse::parse(query)?;
+    let surface = alias::expand(surface, &ctx.aliases)?;
+    let surface = params::bind(surface, params).map_err(PiqlError::MissingParam)?;
+    let root_df = infer_root_dataframe_name(&surface).map(str::to_string);
+    let (core, as_of) = desugar(surface, root_df.as_deref(), ctx);
+    Ok(CompiledQuery {
+        core,
+        query: query.to_string(),
+        as_of,
+    })
+}
+
+/// Core AST of `surface`, and the tick of its `.as_of(n)` call if it has one
+fn desugar(
+    surface: ast::surface::Expr,
+    root_df: Option<&str>,
+    ctx: &EvalContext,
+) -> (ast::core::Expr, Option<i64>) {
+    let (surface, as_of) = extract_as_of(surface);
+    let mut sugar_ctx = ctx.sugar_context(root_df);
+    if as_of.is_some() {
+        sugar_ctx.tick = as_of;
+    }
+    let core = transform::transform_with_sugar(surface, &ctx.sugar, &sugar_ctx);
+    if ctx.optimize {
+        (optimize::optimize(core, ctx), as_of)
+    } else {
+        (core, as_of)
+    }
+}
+
+/// Strip `.as_of(n)` calls from the query's method chain, returning the outermost
+/// tick; calls with a non-literal tick are left for eval to reject
+fn extract_as_of(expr: ast::surface::Expr) -> (ast::surface::Expr, Option<i64>) {
+    use ast::surface::Expr as SurfaceExpr;
+
+    match expr {
+        SurfaceExpr::Call(callee, args) => match (*callee, args.as_slice()) {
+            (
+                SurfaceExpr::Attr(base, method),
+                [ast::Arg::Positional(SurfaceExpr::Literal(ast::Literal::Int(tick)))],
+            ) if method == "as_of" => {
+                let (base, _) = extract_as_of(*base);
+                (base, Some(*tick))
+            }
+            (callee, _) => {
+                let (callee, as_of) = extract_as_of(callee);
+                (SurfaceExpr::Call(Box::new(callee), args), as_of)
+            }
+        },
+        SurfaceExpr::Attr(base, name) => {
+            let (base, as_of) = extract_as_of(*base);
+            (SurfaceExpr::Attr(Box::new(base), name), as_of)
+        }
+        SurfaceExpr::PipelineDirective(base, name, args) => {
+            let (base, as_of) = extract_as_of(*base);
+            (
+                SurfaceExpr::PipelineDirective(Box::new(base), name, args),
+                as_of,
+            )
+        }
+        SurfaceExpr::Commented {
+            expr,
+            leading,
+            trailing,
+        } => {
+            let (expr, as_of) = extract_as_of(*expr);
+            (
+                SurfaceExpr::Commented {
+                    expr: Box::new(expr),
+                    leading,
+                    trailing,
+                },
+                as_of,
+            )
+        }
+        other => (other, None),
+    }
+}
+
+/// A query parsed once for repeated execution, possibly with different parameters.
+///
+/// Parsing, alias expansion and root-table resolution happen in [`prepare`]. A
+/// query without `:name` placeholders also keeps its desugared core AST; one with
+/// placeholders is bound and desugared on each run, without re-parsing. Prepare
+/// again after changing aliases, sugar or time-series configs.
+#[derive(Clone)]
+pub struct PreparedQuery {
+    query: String,
+    surface: ast::surface::Expr,
+    root_df: Option<String>,
+    tables: Vec<String>,
+    compiled: Option<CompiledQuery>,
+}
+
+/// Prepare a query for repeated execution.
+pub fn prepare(query: &str, ctx: &EvalContext) -> Result<PreparedQuery, PiqlError> {
+    let surface = parse::parse(query)?;
+    let surface = alias::expand(surface, &ctx.aliases)?;
+    let root_df = infer_root_dataframe_name(&surface).map(str::to_string);
+    // Unbound placeholders desugar to invalid nodes, which don't hide table names
+    let (core, as_of) = desugar(surface.clone(), root_df.as_deref(), ctx);
+    let mut tables = BTreeSet::new();
+    collect_table_idents(&core, &mut tables);
+    let compiled = params::bind(surface.clone(), &Params::new())
+        .is_ok()
+        .then(|| CompiledQuery {
+            core,
+            query: query.to_string(),
+            as_of,
+        });
+    Ok(PreparedQuery {
+        query: query.to_string(),
+        surface,
+        root_df,
+        tables: tables.into_iter().collect(),
+        compiled,
+    })
+}
+
+impl PreparedQuery {
+    /// Original query text
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    /// Names of the tables the query reads, sorted and deduplicated
+    pub fn referenced_tables(&self) -> &[String] {
+        &self.tables
+    }
+
+    /// Partition key of the table the query reads from, or the context default
+    pub fn partition_key(&self, ctx: &EvalContext) -> Option<String> {
+        ctx.sugar_context(self.root_df.as_deref()).partition_key
+    }
+
+    /// Whether the query has `:name` placeholders to bind on each run
+    pub fn has_params(&self) -> bool {
+        self.compiled.is_none()
+    }
+
+    /// Names of the query's `:name` placeholders, sorted and deduplicated
+    pub fn param_names(&self) -> Vec<String> {
+        let mut names = BTreeSet::new();
+        params::placeholders(&self.surface, &mut names);
+        names.into_iter().collect()
+    }
+
+    /// The compiled query with `params` bound (ignored if it has no placeholders)
+    pub fn compile(&self, params: &Params, ctx: &EvalContext) -> Result<CompiledQuery, PiqlError> {
+        if let Some(compiled) = &self.compiled {
+            return Ok(compiled.clone());
+        }
+        let surface =
+            params::bind(self.surface.clone(), params).map_err(PiqlError::MissingParam)?;
+        let (core, as_of) = desugar(surface, self.root_df.as_deref(), ctx);
+        Ok(CompiledQuery {
+            core,
+            query: self.query.clone(),
+            as_of,
+        })
+    }
+
+    /// Run the query
+    pub fn run(&self, ctx: &EvalContext) -> Result<Value, PiqlError> {
+        self.run_with_params(&Params::new(), ctx)
+    }
+
+    /// Run the query with `:name` placeholders bound to `params`
+    pub fn run_with_params(&self, params: &Params, ctx: &EvalContext) -> Result<Value, PiqlError> {
+        match &self.compiled {
+            Some(compiled) => run_compiled(compiled, ctx),
+            None => run_compiled(&self.compile(params, ctx)?, ctx),
+        }
+    }
+}
+
+impl CompiledQuery {
+    /// Desugared core AST the query evaluates
+    pub fn core(&self) -> &ast::core::Expr {
+        &self.core
+    }
+
+    /// Original query text
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    /// Tick the query is evaluated at, if it calls `.as_of(n)`
+    pub fn as_of(&self) -> Option<i64> {
+        self.as_of
+    }
+
+    /// The same query evaluating `core` instead, e.g. after rewriting the AST
+    pub fn with_core(self, core: ast::core::Expr) -> Self {
+        Self { core, ..self }
+    }
+
+    /// Names of the tables the query reads, sorted and deduplicated
+    pub fn referenced_tables(&self) -> Vec<String> {
+        let mut names = BTreeSet::new();
+        collect_table_idents(&self.core, &mut names);
+        names.into_iter().collect()
+    }
+}
+
+fn collect_table_idents(expr: &ast::core::Expr, names: &mut BTreeSet<String>) {
+    use ast::core::Expr as CoreExpr;
+
+    match expr {
+        CoreExpr::Ident(name) if !ast::is_namespace_ident(name) => {
+            names.insert(name.clone());
+        }
+        CoreExpr::Ident(_) | CoreExpr::Literal(_) | CoreExpr::Invalid(_) => {}
+        CoreExpr::List(items) => items.iter().for_each(|e| collect_table_idents(e, names)),
+        CoreExpr::Attr(base, _) => collect_table_idents(base, names),
+        CoreExpr::Call(callee, args) => {
+            collect_table_idents(callee, names);
+            for arg in args {
+                match arg {
+                    ast::Arg::Positional(e) | ast::Arg::Keyword(_, e) => {
+                        collect_table_idents(e, names)
+                    }
+                }
+            }
+        }
+        CoreExpr::BinaryOp(lhs, _, rhs) => {
+            collect_table_idents(lhs, names);
+            collect_table_idents(rhs, names);
+        }
+        CoreExpr::UnaryOp(_, inner) => collect_table_idents(inner, names),
+        CoreExpr::WhenThenOtherwise {
+            branches,
+            otherwise,
+        } => {
+            for (condition, value) in branches {
+                collect_table_idents(condition, names);
+                collect_table_idents(value, names);
+            }
+            collect_table_idents(otherwise, names);
+        }
+    }
+}
+
+/// Run a pre-compiled query.
+pub fn run_compiled(compiled: &CompiledQuery, ctx: &EvalContext) -> Result<Value, PiqlError> {
+    let with_query = |source| PiqlError::EvalWithQuery {
+        query: compiled.query.clone(),
+        source,
+    };
+    let result = match compiled.as_of {
+        Some(tick) => eval::eval(&compiled.core, &ctx.as_of(tick)),
+        None => eval::eval(&compiled.core, ctx),
+    };
+    let result = result.map_err(with_query)?;
+    match result {
+        Value::DataFrame(lf, lineage) if ctx.collapse_results => {
+            let df = lf
+                .collect()
+                .map_err(|e| with_query(eval::EvalError::from(e)))?;
+            collapse_result(df, lineage).map_err(|e| with_query(eval::EvalError::from(e)))
+        }
+        result => Ok(result),
+    }
+}
+
+/// A collected result as the narrowest value: the cell of a 1x1 frame, the column
+/// of a single-column frame, or else the frame itself
+pub fn collapse_result(df: DataFrame, lineage: DataFrameLineage) -> PolarsResult<Value> {
+    let [column] = df.get_columns() else {
+        return Ok(Value::DataFrame(df.lazy(), lineage));
+    };
+    if column.len() == 1 {
+        let value = column.get(0)?.into_static();
+        return Ok(Value::ScalarResult(Scalar::new(
+            column.dtype().clone(),
+            value,
+        )));
+    }
+    Ok(Value::Series(column.as_materialized_series().clone()))
+}
+
+/// Run a one-off query
+pub fn run(query: &str, ctx: &EvalContext) -> Result<Value, PiqlError> {
+    let compiled = compile(query, ctx)?;
+    run_compiled(&compiled, ctx)
+}
+
+/// Run a one-off query with `:name` placeholders bound to `params`
+///
+/// ```ignore
+/// let params = Params::from([("threshold".to_string(), ParamValue::from(100))]);
+/// run_with_params("entities.filter($gold > :threshold)", &params, &ctx)?;
+/// ```
+pub fn run_with_params(
+    query: &str,
+    params: &Params,
+    ctx: &EvalContext,
+) -> Result<Value, PiqlError> {
+    let compiled = compile_with_params(query, params, ctx)?;
+    run_compiled(&compiled, ctx)
+}
+
+fn infer_root_dataframe_name(expr: &ast::surface::Expr) -> Option<&str> {
+    use ast::surface::Expr as SurfaceExpr;
+
+    match expr {
+        SurfaceExpr::Ident(name) if !ast::is_namespace_ident(name) => Some(name.as_str()),
+        SurfaceExpr::Ident(_) => None,
+        SurfaceExpr::Attr(base, _) => infer_root_dataframe_name(base),
+        SurfaceExpr::Call(callee, _) => infer_root_dataframe_name(callee),
+        SurfaceExpr::BinaryOp(lhs, _, rhs) => {
+            infer_root_dataframe_name(lhs).or_else(|| infer_root_dataframe_name(rhs))
+        }
+        SurfaceExpr::UnaryOp(_, inner) => infer_root_dataframe_name(inner),
+        SurfaceExpr::Commented { expr, .. } => infer_root_dataframe_name(expr),
+        SurfaceExpr::PipelineDirective(base, _, _) => infer_root_dataframe_name(base),
+        SurfaceExpr::List(items) => items.iter().find_map(infer_root_dataframe_name),
+        SurfaceExpr::Literal(_)
+        | SurfaceExpr::ColShorthand(_)
+        | SurfaceExpr::Directive(_, _)
+        | SurfaceExpr::Param(_)
+        | SurfaceExpr::Alias(_)
+        | SurfaceExpr::Error => None,
+    }
+}
@@ -0,0 +1,215 @@
+//! Persist and restore `QueryEngine` state
+//!
+//! A snapshot directory holds:
+//! - `manifest.json` → table configs, aliases, materialization and subscription queries
+//! - `tables/<save>-<n>.parquet` → data of plain tables, and the `all`/`now` rows of
+//!   base tables
+//!
+//! Materialized tables are not dumped; they are re-evaluated from the restored
+//! tables. Sugar directives are code, so register them before loading.
+//!
+//! [`write_snapshot`] is shared with piql-server's snapshots: every save writes its
+//! tables under fresh names, swaps in the new manifest with a rename, and only then
+//! deletes the previous save's files. A save interrupted at any point leaves the
+//! previous manifest and all of its files intact. Saves in one process are written
+//! one at a time, so one save's cleanup can't delete another's files.
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::PiqlError;
+use crate::engine::EmitMode;
+use crate::eval::{EvictedTicks, Retention, TimeSeriesConfig};
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+const TABLES_DIR: &str = "tables";
+pub(crate) const MANIFEST_VERSION: u32 = 1;
+
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("snapshot I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid snapshot manifest: {0}")]
+    Manifest(#[from] serde_json::Error),
+    #[error("unsupported snapshot version {0}")]
+    Version(u32),
+    #[error(transparent)]
+    Polars(#[from] PolarsError),
+    #[error("failed to restore `{name}`: {source}")]
+    Query {
+        name: String,
+        #[source]
+        source: PiqlError,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Manifest {
+    pub version: u32,
+    #[serde(default)]
+    pub tick: Option<i64>,
+    #[serde(default)]
+    pub default_tick_column: Option<String>,
+    #[serde(default)]
+    pub default_partition_key: Option<String>,
+    pub tables: Vec<TableEntry>,
+    pub base_tables: Vec<BaseTableSnapshot>,
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
+    /// In evaluation order
+    pub materializations: Vec<QueryEntry>,
+    pub subscriptions: Vec<QueryEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct TableEntry {
+    pub name: String,
+    /// Parquet file, relative to the snapshot directory
+    pub file: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_series: Option<TimeSeriesConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct BaseTableSnapshot {
+    pub name: String,
+    pub config: TimeSeriesConfig,
+    #[serde(default)]
+    pub retention: Retention,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub evicted: Option<EvictedTicks>,
+    /// Full history and latest tick rows (absent until the first append)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub all: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub now: Option<PathBuf>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct QueryEntry {
+    pub name: String,
+    pub query: String,
+    #[serde(default, skip_serializing_if = "is_always")]
+    pub mode: EmitMode,
+}
+
+fn is_always(mode: &EmitMode) -> bool {
+    *mode == EmitMode::Always
+}
+
+/// Saves started by this process, distinguishing saves within one clock tick
+static SAVES: AtomicU64 = AtomicU64::new(0);
+
+/// Held while a save writes, so concurrent saves don't remove each other's files
+static WRITES: Mutex<()> = Mutex::new(());
+
+/// Collects the DataFrames to dump alongside a snapshot manifest
+pub struct SnapshotDumps {
+    /// Prefix naming this save's files apart from any earlier save's
+    save: String,
+    files: Vec<(PathBuf, DataFrame)>,
+}
+
+impl SnapshotDumps {
+    pub fn new() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos());
+        let n = SAVES.fetch_add(1, Ordering::Relaxed);
+        Self {
+            save: format!("{nanos:x}-{:x}-{n}", std::process::id()),
+            files: Vec::new(),
+        }
+    }
+
+    /// Queue `df` for writing; returns its path relative to the snapshot directory
+    pub fn add(&mut self, df: DataFrame) -> PathBuf {
+        let file =
+            Path::new(TABLES_DIR).join(format!("{}-{}.parquet", self.save, self.files.len()));
+        self.files.push((file.clone(), df));
+        file
+    }
+}
+
+impl Default for SnapshotDumps {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Write the queued tables and then `manifest` as `manifest.json` in `dir`, and
+/// delete table files no longer referenced
+///
+/// The tables are new files and the manifest replaces the old one by rename, so an
+/// interrupted save leaves the previous snapshot readable; files it left behind are
+/// deleted by the next save. Concurrent saves wait for each other.
+pub fn write_snapshot(
+    dir: &Path,
+    manifest: &impl Serialize,
+    dumps: SnapshotDumps,
+) -> Result<(), SnapshotError> {
+    // A panicked save leaves nothing this one relies on
+    let _writing = WRITES.lock().unwrap_or_else(|e| e.into_inner());
+    let tables = dir.join(TABLES_DIR);
+    std::fs::create_dir_all(&tables)?;
+    let mut written = HashSet::with_capacity(dumps.files.len());
+    for (file, mut df) in dumps.files {
+        let path = dir.join(&file);
+        let out = std::fs::File::create(&path)?;
+        ParquetWriter::new(&out).finish(&mut df)?;
+        out.sync_all()?;
+        written.insert(path);
+    }
+    let tmp = dir.join(format!("{MANIFEST_FILE}.{}.tmp", dumps.save));
+    std::fs::write(&tmp, serde_json::to_vec_pretty(manifest)?)?;
+    std::fs::File::open(&tmp)?.sync_all()?;
+    std::fs::rename(tmp, dir.join(MANIFEST_FILE))?;
+
+    // Files of earlier (or interrupted) saves
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("");
+        if name.starts_with(MANIFEST_FILE) && name.ends_with(".tmp") {
+            remove_stale(&path);
+        }
+    }
+    for entry in std::fs::read_dir(&tables)? {
+        let path = entry?.path();
+        if path.is_file() && !written.contains(&path) {
+            remove_stale(&path);
+        }
+    }
+    Ok(())
+}
+
+/// Delete a file a save no longer needs; one left behind only wastes space
+fn remove_stale(path: &Path) {
+    if let Err(e) = std::fs::remove_file(path) {
+        log::warn!(
+            "Failed to remove stale snapshot file {}: {e}",
+            path.display()
+        );
+    }
+}
+
+pub(crate) fn read_manifest(dir: &Path) -> Result<Manifest, SnapshotError> {
+    let manifest: Manifest = serde_json::from_slice(&std::fs::read(dir.join(MANIFEST_FILE))?)?;
+    if manifest.version != MANIFEST_VERSION {
+        return Err(SnapshotError::Version(manifest.version));
+    }
+    Ok(manifest)
+}
+
+pub(crate) fn read_table(dir: &Path, file: &Path) -> Result<DataFrame, SnapshotError> {
+    Ok(ParquetReader::new(std::fs::File::open(dir.join(file))?).finish()?)
+}
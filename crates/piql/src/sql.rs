@@ -0,0 +1,1033 @@
+//! SQL front-end (feature `sql`)
+//!
+//! Translates a subset of SQL — `SELECT ... FROM ... [JOIN ...] [WHERE ...]
+//! [GROUP BY ...] [HAVING ...] [ORDER BY ...] [LIMIT n]` over registered tables —
+//! into the PiQL method chain a hand-written query would use, so both dialects
+//! evaluate through the same core AST:
+//!
+//! ```text
+//! SELECT type, SUM(gold) AS total FROM entities WHERE gold > 10 GROUP BY type
+//! → entities.filter(pl.col("gold") > 10).group_by(pl.col("type")).agg([pl.col("gold").sum().alias("total")])
+//! ```
+//!
+//! `:name` placeholders become PiQL parameters.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use sqlparser::ast::{
+    self as sql, BinaryOperator, Distinct, DuplicateTreatment, FunctionArg, FunctionArgExpr,
+    FunctionArguments, GroupByExpr, JoinConstraint, JoinOperator, OrderBy, SelectItem, SetExpr,
+    Statement, TableFactor, UnaryOperator,
+};
+use sqlparser::dialect::GenericDialect;
+use sqlparser::parser::Parser;
+
+use crate::PiqlError;
+use crate::ast::core::Expr as CoreExpr;
+use crate::ast::surface::{Expr, SurfaceArg};
+use crate::ast::{BinOp, Literal, UnaryOp};
+
+/// Aggregate functions, by SQL name, and the PiQL method each becomes
+const AGGREGATES: &[(&str, &str)] = &[
+    ("count", "count"),
+    ("sum", "sum"),
+    ("min", "min"),
+    ("max", "max"),
+    ("avg", "mean"),
+    ("mean", "mean"),
+    ("median", "median"),
+    ("stddev", "std"),
+    ("stddev_samp", "std"),
+    ("variance", "var"),
+    ("var_samp", "var"),
+    ("first", "first"),
+    ("last", "last"),
+];
+
+type Result<T> = std::result::Result<T, PiqlError>;
+
+/// Parse a SQL query into the core AST PiQL queries compile to
+pub fn parse_sql(query: &str) -> Result<CoreExpr> {
+    Ok(crate::transform::transform(translate(query)?))
+}
+
+/// Translate a SQL query into equivalent PiQL text
+///
+/// ```ignore
+/// assert_eq!(
+///     piql::sql_to_piql("SELECT name FROM entities WHERE gold > 100")?,
+///     r#"entities.filter(pl.col("gold") > 100).select([pl.col("name")])"#,
+/// );
+/// ```
+pub fn sql_to_piql(query: &str) -> Result<String> {
+    Ok(crate::pretty::pretty(&translate(query)?, 80))
+}
+
+/// Translate a SQL query into the surface AST
+pub(crate) fn translate(query: &str) -> Result<Expr> {
+    let statements =
+        Parser::parse_sql(&GenericDialect {}, query).map_err(|e| PiqlError::Sql(e.to_string()))?;
+    match statements.as_slice() {
+        [Statement::Query(query)] => translate_query(query),
+        _ => Err(unsupported("anything but a single SELECT statement")),
+    }
+}
+
+fn unsupported(what: impl std::fmt::Display) -> PiqlError {
+    PiqlError::Sql(format!("unsupported: {what}"))
+}
+
+fn translate_query(query: &sql::Query) -> Result<Expr> {
+    if query.with.is_some() {
+        return Err(unsupported("WITH"));
+    }
+    if query.offset.is_some() || query.fetch.is_some() {
+        return Err(unsupported("OFFSET/FETCH"));
+    }
+    let SetExpr::Select(select) = query.body.as_ref() else {
+        return Err(unsupported(format!("{}", query.body)));
+    };
+    let scope = Scope::new(select)?;
+    let (mut df, outputs) = translate_select(select, &scope)?;
+    if let Some(order_by) = &query.order_by {
+        df = sort(df, order_by, &outputs, &scope)?;
+    }
+    if let Some(limit) = &query.limit {
+        let n = match literal(limit) {
+            Some(Literal::Int(n)) if n >= 0 => n,
+            _ => {
+                return Err(PiqlError::Sql(format!(
+                    "LIMIT must be a count, got {limit}"
+                )));
+            }
+        };
+        df = method(df, "head", vec![SurfaceArg::pos(int(n))]);
+    }
+    Ok(df)
+}
+
+/// The table chain for a SELECT, and its output column names in order (`None`
+/// for unnamed expressions; empty for `*`)
+fn translate_select(select: &sql::Select, scope: &Scope) -> Result<(Expr, Vec<Option<String>>)> {
+    let from = match select.from.as_slice() {
+        [from] => from,
+        [] => return Err(unsupported("SELECT without FROM")),
+        _ => return Err(unsupported("comma joins (use JOIN ... ON)")),
+    };
+    if select.top.is_some() || select.prewhere.is_some() || select.qualify.is_some() {
+        return Err(unsupported("TOP/PREWHERE/QUALIFY"));
+    }
+
+    let mut df = table(&from.relation)?.0;
+    for (i, join) in from.joins.iter().enumerate() {
+        df = translate_join(df, join, i + 1, scope)?;
+    }
+    if let Some(selection) = &select.selection {
+        df = method(df, "filter", vec![SurfaceArg::pos(expr(selection, scope)?)]);
+    }
+
+    let keys = match &select.group_by {
+        GroupByExpr::Expressions(keys, modifiers) if modifiers.is_empty() => keys
+            .iter()
+            .map(|key| {
+                column_name(key, scope).ok_or_else(|| unsupported(format!("GROUP BY {key}")))
+            })
+            .collect::<Result<Vec<_>>>()?,
+        GroupByExpr::Expressions(..) | GroupByExpr::All(_) => {
+            return Err(unsupported("GROUP BY ALL/ROLLUP/CUBE"));
+        }
+    };
+    let aggregated = select.projection.iter().any(|item| match item {
+        SelectItem::UnnamedExpr(e) | SelectItem::ExprWithAlias { expr: e, .. } => {
+            contains_aggregate(e)
+        }
+        _ => false,
+    });
+
+    let (df, outputs) = if !keys.is_empty() || aggregated || select.having.is_some() {
+        translate_aggregation(df, select, &keys, scope)?
+    } else {
+        translate_projection(df, &select.projection, scope)?
+    };
+
+    let df = match &select.distinct {
+        None => df,
+        Some(Distinct::Distinct) => method(df, "unique", vec![]),
+        Some(Distinct::On(_)) => return Err(unsupported("DISTINCT ON")),
+    };
+    Ok((df, outputs))
+}
+
+/// `SELECT a, b + 1 AS c` (no aggregates) as `select` or, next to `*`, `with_columns`
+fn translate_projection(
+    df: Expr,
+    projection: &[SelectItem],
+    scope: &Scope,
+) -> Result<(Expr, Vec<Option<String>>)> {
+    let mut wildcard = false;
+    let mut exprs = Vec::new();
+    let mut outputs = Vec::new();
+    for item in projection {
+        match item {
+            SelectItem::Wildcard(_) | SelectItem::QualifiedWildcard(..) => wildcard = true,
+            SelectItem::UnnamedExpr(e) => {
+                exprs.push(expr(e, scope)?);
+                outputs.push(column_name(e, scope));
+            }
+            SelectItem::ExprWithAlias { expr: e, alias } => {
+                exprs.push(aliased(expr(e, scope)?, &alias.value));
+                outputs.push(Some(alias.value.clone()));
+            }
+        }
+    }
+    if wildcard {
+        let df = if exprs.is_empty() {
+            df
+        } else {
+            method(df, "with_columns", vec![SurfaceArg::pos(Expr::List(exprs))])
+        };
+        return Ok((df, Vec::new()));
+    }
+    Ok((
+        method(df, "select", vec![SurfaceArg::pos(Expr::List(exprs))]),
+        outputs,
+    ))
+}
+
+/// GROUP BY (or a bare aggregate SELECT) as `group_by(...).agg([...])`, then HAVING
+/// as a filter over the aggregated columns
+fn translate_aggregation(
+    df: Expr,
+    select: &sql::Select,
+    keys: &[String],
+    scope: &Scope,
+) -> Result<(Expr, Vec<Option<String>>)> {
+    // Every aggregate output with its name, in projection order
+    let mut aggregates: Vec<(sql::Expr, String)> = Vec::new();
+    let mut outputs = Vec::new();
+    for item in &select.projection {
+        let (e, alias) = match item {
+            SelectItem::UnnamedExpr(e) => (e, None),
+            SelectItem::ExprWithAlias { expr: e, alias } => (e, Some(alias.value.clone())),
+            _ => return Err(unsupported("* in an aggregating SELECT")),
+        };
+        let name = alias.clone().or_else(|| default_name(e, scope));
+        if let Some(key) = column_name(e, scope).filter(|name| keys.contains(name)) {
+            if alias.as_ref().is_some_and(|alias| *alias != key) {
+                return Err(unsupported(format!("renaming GROUP BY column {key}")));
+            }
+            outputs.push(Some(key));
+            continue;
+        }
+        if !contains_aggregate(e) {
+            return Err(PiqlError::Sql(format!(
+                "{e} must appear in GROUP BY or be aggregated"
+            )));
+        }
+        let name =
+            name.ok_or_else(|| PiqlError::Sql(format!("give the aggregate {e} a name with AS")))?;
+        aggregates.push((e.clone(), name.clone()));
+        outputs.push(Some(name));
+    }
+    let selected = aggregates.len();
+
+    // HAVING may aggregate columns the SELECT doesn't; those are computed under
+    // hidden names and dropped by the final select
+    let having = match &select.having {
+        Some(having) => Some(having_expr(having, &mut aggregates, scope)?),
+        None => None,
+    };
+
+    let agg_exprs = aggregates
+        .iter()
+        .map(|(e, name)| Ok(aliased(expr(e, scope)?, name)))
+        .collect::<Result<Vec<_>>>()?;
+    let mut df = if keys.is_empty() {
+        method(df, "select", vec![SurfaceArg::pos(Expr::List(agg_exprs))])
+    } else {
+        let keys = keys.iter().map(|key| SurfaceArg::pos(col(key))).collect();
+        method(
+            method(df, "group_by", keys),
+            "agg",
+            vec![SurfaceArg::pos(Expr::List(agg_exprs))],
+        )
+    };
+    if let Some(having) = having {
+        df = method(df, "filter", vec![SurfaceArg::pos(having)]);
+    }
+
+    // Aggregation yields the keys then the aggregates; reorder (and drop hidden
+    // HAVING columns) only when the SELECT asks for something else
+    let natural: Vec<Option<String>> = keys
+        .iter()
+        .cloned()
+        .chain(aggregates[..selected].iter().map(|(_, name)| name.clone()))
+        .map(Some)
+        .collect();
+    if outputs != natural || aggregates.len() > selected {
+        let columns = outputs.iter().flatten().map(|name| col(name)).collect();
+        df = method(df, "select", vec![SurfaceArg::pos(Expr::List(columns))]);
+    }
+    Ok((df, outputs))
+}
+
+/// A HAVING condition over aggregated columns: aggregates the SELECT computes
+/// refer to its output, others are added to `aggregates` under hidden names
+fn having_expr(
+    e: &sql::Expr,
+    aggregates: &mut Vec<(sql::Expr, String)>,
+    scope: &Scope,
+) -> Result<Expr> {
+    if is_aggregate_call(e) {
+        let name = match aggregates.iter().find(|(agg, _)| agg == e) {
+            Some((_, name)) => name.clone(),
+            None => {
+                let name = format!("__having_{}", aggregates.len());
+                aggregates.push((e.clone(), name.clone()));
+                name
+            }
+        };
+        return Ok(col(&name));
+    }
+    match e {
+        sql::Expr::BinaryOp { left, op, right } => Ok(Expr::BinaryOp(
+            Box::new(having_expr(left, aggregates, scope)?),
+            binary_op(op)?,
+            Box::new(having_expr(right, aggregates, scope)?),
+        )),
+        sql::Expr::UnaryOp {
+            op: UnaryOperator::Not,
+            expr: inner,
+        } => Ok(Expr::UnaryOp(
+            UnaryOp::Not,
+            Box::new(having_expr(inner, aggregates, scope)?),
+        )),
+        sql::Expr::Nested(inner) => having_expr(inner, aggregates, scope),
+        _ => expr(e, scope),
+    }
+}
+
+/// `join` of the table at index `side` of `scope` onto `left`
+fn translate_join(left: Expr, join: &sql::Join, side: usize, scope: &Scope) -> Result<Expr> {
+    let right = table(&join.relation)?.0;
+    let (how, constraint) = join_kind(join)?;
+
+    let mut args = vec![SurfaceArg::pos(right)];
+    match constraint {
+        JoinConstraint::Using(columns) => {
+            let names = columns.iter().map(|c| c.value.clone()).collect();
+            args.push(SurfaceArg::kw("on", strings(names)));
+        }
+        JoinConstraint::On(on) => {
+            // The left key is named as after the earlier joins, the right key as in
+            // its own table
+            let mut pairs = Vec::new();
+            for (l, r) in join_keys(on, side, scope)? {
+                let (Some(l), Some(r)) = (column_name(l, scope), unqualified(r)) else {
+                    return Err(unsupported(format!("join condition {on}")));
+                };
+                pairs.push((l, r));
+            }
+            if pairs.iter().all(|(l, r)| l == r) {
+                let names = pairs.into_iter().map(|(l, _)| l).collect();
+                args.push(SurfaceArg::kw("on", strings(names)));
+            } else {
+                let (left_on, right_on) = pairs.into_iter().unzip();
+                args.push(SurfaceArg::kw("left_on", strings(left_on)));
+                args.push(SurfaceArg::kw("right_on", strings(right_on)));
+            }
+        }
+        JoinConstraint::Natural | JoinConstraint::None => {
+            return Err(unsupported("JOIN without ON or USING"));
+        }
+    }
+    if how != "inner" {
+        args.push(SurfaceArg::kw("how", string(how)));
+    }
+    Ok(method(left, "join", args))
+}
+
+/// PiQL join type of `join`, and its constraint
+fn join_kind(join: &sql::Join) -> Result<(&'static str, &JoinConstraint)> {
+    Ok(match &join.join_operator {
+        JoinOperator::Inner(c) => ("inner", c),
+        JoinOperator::LeftOuter(c) => ("left", c),
+        JoinOperator::RightOuter(c) => ("right", c),
+        JoinOperator::FullOuter(c) => ("full", c),
+        JoinOperator::Semi(c) | JoinOperator::LeftSemi(c) => ("semi", c),
+        JoinOperator::Anti(c) | JoinOperator::LeftAnti(c) => ("anti", c),
+        other => return Err(unsupported(format!("{other:?}"))),
+    })
+}
+
+/// Operand pairs (left, right) of an `a = b AND c = d` join condition. An operand
+/// qualified with the joined table's name or alias is taken as its right side.
+fn join_keys<'a>(
+    on: &'a sql::Expr,
+    side: usize,
+    scope: &Scope,
+) -> Result<Vec<(&'a sql::Expr, &'a sql::Expr)>> {
+    match on {
+        sql::Expr::Nested(inner) => join_keys(inner, side, scope),
+        sql::Expr::BinaryOp {
+            left,
+            op: BinaryOperator::And,
+            right,
+        } => {
+            let mut pairs = join_keys(left, side, scope)?;
+            pairs.extend(join_keys(right, side, scope)?);
+            Ok(pairs)
+        }
+        sql::Expr::BinaryOp {
+            left,
+            op: BinaryOperator::Eq,
+            right,
+        } => {
+            let (left, right) = (left.as_ref(), right.as_ref());
+            if unqualified(left).is_none() || unqualified(right).is_none() {
+                return Err(unsupported(format!("join condition {on}")));
+            }
+            Ok(vec![if scope.side(left)? == Some(side) {
+                (right, left)
+            } else {
+                (left, right)
+            }])
+        }
+        _ => Err(unsupported(format!(
+            "join condition {on} (only column equalities joined by AND)"
+        ))),
+    }
+}
+
+/// A FROM/JOIN table, and the names it can be referred to by (name and alias)
+fn table(factor: &TableFactor) -> Result<(Expr, Vec<String>)> {
+    let TableFactor::Table { name, alias, .. } = factor else {
+        return Err(unsupported(format!("FROM {factor}")));
+    };
+    let [table] = name.0.as_slice() else {
+        return Err(unsupported(format!("qualified table name {name}")));
+    };
+    let mut names = vec![table.value.clone()];
+    names.extend(alias.iter().map(|alias| alias.name.value.clone()));
+    Ok((Expr::Ident(table.value.clone()), names))
+}
+
+/// The tables a SELECT reads, and how their qualified columns are named after the
+/// joins
+///
+/// A joined table's column that collides with an earlier table's takes Polars'
+/// `_right` suffix, and a key the join merges into the left key takes the left
+/// key's name. Translation doesn't see schemas, so a collision is only known when
+/// the query qualifies the column with both tables; a column qualified with a
+/// joined table must then be qualified everywhere.
+#[derive(Default)]
+struct Scope {
+    /// Names (table and alias) of the FROM table, then of each JOIN table
+    tables: Vec<Vec<String>>,
+    /// Columns, by table index and name, that are named otherwise after the joins
+    renamed: HashMap<(usize, String), String>,
+}
+
+impl Scope {
+    fn new(select: &sql::Select) -> Result<Self> {
+        let mut scope = Scope::default();
+        let Some(from) = select.from.first() else {
+            return Ok(scope);
+        };
+        scope.tables.push(table(&from.relation)?.1);
+        for join in &from.joins {
+            scope.tables.push(table(&join.relation)?.1);
+        }
+
+        // Every column the SELECT mentions; ORDER BY names its outputs instead
+        let mut refs = Vec::new();
+        for item in &select.projection {
+            if let SelectItem::UnnamedExpr(e) | SelectItem::ExprWithAlias { expr: e, .. } = item {
+                column_refs(e, &mut refs);
+            }
+        }
+        for e in select.selection.iter().chain(&select.having) {
+            column_refs(e, &mut refs);
+        }
+        if let GroupByExpr::Expressions(keys, _) = &select.group_by {
+            for key in keys {
+                column_refs(key, &mut refs);
+            }
+        }
+        for join in &from.joins {
+            if let (_, JoinConstraint::On(on)) = join_kind(join)? {
+                column_refs(on, &mut refs);
+            }
+        }
+        let mut qualified: BTreeMap<String, BTreeSet<usize>> = BTreeMap::new();
+        let mut bare = HashSet::new();
+        for e in refs {
+            let column = unqualified(e).unwrap_or_default();
+            match scope.side(e)? {
+                Some(side) => {
+                    qualified.entry(column).or_default().insert(side);
+                }
+                None => {
+                    bare.insert(column);
+                }
+            }
+        }
+
+        let mut merged = HashSet::new();
+        for (i, join) in from.joins.iter().enumerate() {
+            let side = i + 1;
+            // Polars keeps both keys of a full join, and semi/anti joins drop the
+            // right table's columns altogether
+            match join_kind(join)? {
+                ("full", _) => {}
+                (_, JoinConstraint::Using(columns)) => {
+                    merged.extend(columns.iter().map(|c| (side, c.value.clone())));
+                }
+                (_, JoinConstraint::On(on)) => {
+                    for (l, r) in join_keys(on, side, &scope)? {
+                        if let (Some(l), Some(r)) = (column_name(l, &scope), unqualified(r)) {
+                            merged.insert((side, r.clone()));
+                            if l != r {
+                                scope.renamed.insert((side, r), l);
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+
+            for (column, sides) in &qualified {
+                let key = (side, column.clone());
+                if !sides.contains(&side) || merged.contains(&key) {
+                    continue;
+                }
+                if bare.contains(column) {
+                    return Err(PiqlError::Sql(format!(
+                        "ambiguous column {column}: qualify it with its table"
+                    )));
+                }
+                let collides = sides
+                    .range(..side)
+                    .any(|earlier| !merged.contains(&(*earlier, column.clone())));
+                if collides {
+                    let suffixed = format!("{column}_right");
+                    if scope.renamed.values().any(|name| *name == suffixed) {
+                        return Err(unsupported(format!(
+                            "column {column} of more than two joined tables"
+                        )));
+                    }
+                    scope.renamed.insert(key, suffixed);
+                }
+            }
+        }
+        Ok(scope)
+    }
+
+    /// Index of the table a column reference is qualified with, if any
+    fn side(&self, e: &sql::Expr) -> Result<Option<usize>> {
+        let sql::Expr::CompoundIdentifier(parts) = e else {
+            return Ok(None);
+        };
+        let [.., qualifier, _] = parts.as_slice() else {
+            return Ok(None);
+        };
+        self.tables
+            .iter()
+            .position(|names| names.contains(&qualifier.value))
+            .map(Some)
+            .ok_or_else(|| PiqlError::Sql(format!("unknown table {qualifier} in {e}")))
+    }
+}
+
+/// Column references (identifiers) in `e`
+fn column_refs<'a>(e: &'a sql::Expr, refs: &mut Vec<&'a sql::Expr>) {
+    match e {
+        sql::Expr::Identifier(_) | sql::Expr::CompoundIdentifier(_) => refs.push(e),
+        sql::Expr::BinaryOp { left, right, .. } => {
+            column_refs(left, refs);
+            column_refs(right, refs);
+        }
+        sql::Expr::UnaryOp { expr, .. }
+        | sql::Expr::Nested(expr)
+        | sql::Expr::Cast { expr, .. }
+        | sql::Expr::IsNull(expr)
+        | sql::Expr::IsNotNull(expr)
+        | sql::Expr::Like { expr, .. }
+        | sql::Expr::ILike { expr, .. }
+        | sql::Expr::InList { expr, .. } => column_refs(expr, refs),
+        sql::Expr::Between {
+            expr, low, high, ..
+        } => {
+            for e in [expr, low, high] {
+                column_refs(e, refs);
+            }
+        }
+        sql::Expr::Function(f) => {
+            if let FunctionArguments::List(list) = &f.args {
+                for arg in &list.args {
+                    if let FunctionArg::Unnamed(FunctionArgExpr::Expr(e)) = arg {
+                        column_refs(e, refs);
+                    }
+                }
+            }
+        }
+        sql::Expr::Case {
+            operand,
+            conditions,
+            results,
+            else_result,
+        } => {
+            let branches = conditions.iter().chain(results);
+            for e in operand.iter().map(|e| e.as_ref()).chain(branches) {
+                column_refs(e, refs);
+            }
+            if let Some(e) = else_result {
+                column_refs(e, refs);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// ORDER BY output columns (by name or 1-based position) as one `sort`
+fn sort(df: Expr, order_by: &OrderBy, outputs: &[Option<String>], scope: &Scope) -> Result<Expr> {
+    let mut names = Vec::new();
+    let mut descending = Vec::new();
+    for item in &order_by.exprs {
+        let name = match literal(&item.expr) {
+            Some(Literal::Int(position)) => position
+                .checked_sub(1)
+                .and_then(|i| outputs.get(i as usize).cloned().flatten())
+                .ok_or_else(|| {
+                    PiqlError::Sql(format!(
+                        "ORDER BY {position} must refer to a named SELECT column"
+                    ))
+                })?,
+            _ => column_name(&item.expr, scope).ok_or_else(|| {
+                unsupported(format!(
+                    "ORDER BY {} (order by a column or alias)",
+                    item.expr
+                ))
+            })?,
+        };
+        if item.nulls_first.is_some() {
+            return Err(unsupported("NULLS FIRST/LAST"));
+        }
+        names.push(name);
+        descending.push(item.asc == Some(false));
+    }
+
+    let mut args = vec![SurfaceArg::pos(strings(names))];
+    if descending.iter().all(|d| *d) {
+        args.push(SurfaceArg::kw(
+            "descending",
+            Expr::Literal(Literal::Bool(true)),
+        ));
+    } else if descending.iter().any(|d| *d) {
+        let flags = descending
+            .into_iter()
+            .map(|d| Expr::Literal(Literal::Bool(d)))
+            .collect();
+        args.push(SurfaceArg::kw("descending", Expr::List(flags)));
+    }
+    Ok(method(df, "sort", args))
+}
+
+// ============ Expressions ============
+
+fn expr(e: &sql::Expr, scope: &Scope) -> Result<Expr> {
+    if let Some(value) = literal(e) {
+        return Ok(Expr::Literal(value));
+    }
+    match e {
+        sql::Expr::Identifier(_) | sql::Expr::CompoundIdentifier(_) => {
+            Ok(col(&column_name(e, scope).unwrap_or_default()))
+        }
+        sql::Expr::Value(sql::Value::Placeholder(p)) => match p.strip_prefix(':') {
+            Some(name) => Ok(Expr::Param(name.to_string())),
+            None => Err(unsupported(format!("placeholder {p} (use :name)"))),
+        },
+        sql::Expr::Nested(inner) => expr(inner, scope),
+        sql::Expr::UnaryOp { op, expr: inner } => match op {
+            UnaryOperator::Minus => Ok(Expr::UnaryOp(UnaryOp::Neg, Box::new(expr(inner, scope)?))),
+            UnaryOperator::Not => Ok(Expr::UnaryOp(UnaryOp::Not, Box::new(expr(inner, scope)?))),
+            UnaryOperator::Plus => expr(inner, scope),
+            _ => Err(unsupported(format!("operator {op}"))),
+        },
+        sql::Expr::BinaryOp { left, op, right } => Ok(Expr::BinaryOp(
+            Box::new(expr(left, scope)?),
+            binary_op(op)?,
+            Box::new(expr(right, scope)?),
+        )),
+        sql::Expr::IsNull(inner) => Ok(method(expr(inner, scope)?, "is_null", vec![])),
+        sql::Expr::IsNotNull(inner) => Ok(method(expr(inner, scope)?, "is_not_null", vec![])),
+        sql::Expr::InList {
+            expr: inner,
+            list,
+            negated,
+        } => {
+            let items = list
+                .iter()
+                .map(|item| {
+                    literal(item)
+                        .map(Expr::Literal)
+                        .ok_or_else(|| unsupported(format!("IN with non-literal {item}")))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let is_in = method(
+                expr(inner, scope)?,
+                "is_in",
+                vec![SurfaceArg::pos(Expr::List(items))],
+            );
+            Ok(negate_if(*negated, is_in))
+        }
+        sql::Expr::Between {
+            expr: inner,
+            negated,
+            low,
+            high,
+        } => {
+            let between = method(
+                expr(inner, scope)?,
+                "is_between",
+                vec![
+                    SurfaceArg::pos(expr(low, scope)?),
+                    SurfaceArg::pos(expr(high, scope)?),
+                ],
+            );
+            Ok(negate_if(*negated, between))
+        }
+        sql::Expr::Like {
+            negated,
+            any: false,
+            expr: inner,
+            pattern,
+            escape_char: None,
+        } => like(inner, pattern, *negated, false, scope),
+        sql::Expr::ILike {
+            negated,
+            any: false,
+            expr: inner,
+            pattern,
+            escape_char: None,
+        } => like(inner, pattern, *negated, true, scope),
+        sql::Expr::Case {
+            operand,
+            conditions,
+            results,
+            else_result,
+        } => {
+            let mut chain = Expr::Ident("pl".into());
+            for (condition, result) in conditions.iter().zip(results) {
+                let condition = match operand {
+                    Some(operand) => {
+                        expr(operand, scope)?.binop(BinOp::Eq, expr(condition, scope)?)
+                    }
+                    None => expr(condition, scope)?,
+                };
+                chain = method(chain, "when", vec![SurfaceArg::pos(condition)]);
+                chain = method(chain, "then", vec![SurfaceArg::pos(expr(result, scope)?)]);
+            }
+            let otherwise = match else_result {
+                Some(e) => expr(e, scope)?,
+                None => Expr::Literal(Literal::Null),
+            };
+            Ok(method(chain, "otherwise", vec![SurfaceArg::pos(otherwise)]))
+        }
+        sql::Expr::Cast {
+            expr: inner,
+            data_type,
+            format: None,
+            ..
+        } => Ok(method(
+            expr(inner, scope)?,
+            "cast",
+            vec![SurfaceArg::pos(string(&dtype_name(data_type)?))],
+        )),
+        sql::Expr::Function(f) => function(f, scope),
+        _ => Err(unsupported(e)),
+    }
+}
+
+/// Literal values, with negative numbers folded in (`IN (-1, 2)`)
+fn literal(e: &sql::Expr) -> Option<Literal> {
+    match e {
+        sql::Expr::Value(value) => match value {
+            sql::Value::Number(n, _) => n
+                .parse()
+                .map(Literal::Int)
+                .or_else(|_| n.parse().map(Literal::Float))
+                .ok(),
+            sql::Value::SingleQuotedString(s) => Some(Literal::String(s.clone())),
+            sql::Value::Boolean(b) => Some(Literal::Bool(*b)),
+            sql::Value::Null => Some(Literal::Null),
+            _ => None,
+        },
+        sql::Expr::UnaryOp {
+            op: UnaryOperator::Minus,
+            expr,
+        } => match literal(expr)? {
+            Literal::Int(n) => Some(Literal::Int(-n)),
+            Literal::Float(f) => Some(Literal::Float(-f)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn binary_op(op: &BinaryOperator) -> Result<BinOp> {
+    Ok(match op {
+        BinaryOperator::Plus => BinOp::Add,
+        BinaryOperator::Minus => BinOp::Sub,
+        BinaryOperator::Multiply => BinOp::Mul,
+        BinaryOperator::Divide => BinOp::Div,
+        BinaryOperator::Modulo => BinOp::Mod,
+        BinaryOperator::Eq => BinOp::Eq,
+        BinaryOperator::NotEq => BinOp::Ne,
+        BinaryOperator::Lt => BinOp::Lt,
+        BinaryOperator::LtEq => BinOp::Le,
+        BinaryOperator::Gt => BinOp::Gt,
+        BinaryOperator::GtEq => BinOp::Ge,
+        BinaryOperator::And => BinOp::And,
+        BinaryOperator::Or => BinOp::Or,
+        _ => return Err(unsupported(format!("operator {op}"))),
+    })
+}
+
+/// `x LIKE 'a%'` as an anchored regex match
+fn like(
+    inner: &sql::Expr,
+    pattern: &sql::Expr,
+    negated: bool,
+    ignore_case: bool,
+    scope: &Scope,
+) -> Result<Expr> {
+    let Some(Literal::String(pattern)) = literal(pattern) else {
+        return Err(unsupported(format!(
+            "LIKE with non-literal pattern {pattern}"
+        )));
+    };
+    let mut regex = String::from(if ignore_case { "(?is)^" } else { "(?s)^" });
+    for c in pattern.chars() {
+        match c {
+            '%' => regex.push_str(".*"),
+            '_' => regex.push('.'),
+            c if "\\.+*?()|[]{}^$".contains(c) => {
+                regex.push('\\');
+                regex.push(c);
+            }
+            c => regex.push(c),
+        }
+    }
+    regex.push('$');
+    let contains = method(
+        expr(inner, scope)?.attr("str"),
+        "contains",
+        vec![SurfaceArg::pos(string(&regex))],
+    );
+    Ok(negate_if(negated, contains))
+}
+
+fn function(f: &sql::Function, scope: &Scope) -> Result<Expr> {
+    if f.over.is_some() || f.filter.is_some() {
+        return Err(unsupported(format!("window/filtered function {f}")));
+    }
+    let name = f.name.to_string().to_lowercase();
+    let FunctionArguments::List(list) = &f.args else {
+        return Err(unsupported(format!("function {f}")));
+    };
+    let distinct = list.duplicate_treatment == Some(DuplicateTreatment::Distinct);
+    let args: Vec<&FunctionArgExpr> = list
+        .args
+        .iter()
+        .map(|arg| match arg {
+            FunctionArg::Unnamed(arg) => Ok(arg),
+            _ => Err(unsupported(format!("named arguments in {f}"))),
+        })
+        .collect::<Result<_>>()?;
+    let arg = |i: usize| match args.get(i) {
+        Some(FunctionArgExpr::Expr(e)) => expr(e, scope),
+        _ => Err(PiqlError::Sql(format!(
+            "{f}: expected an expression argument"
+        ))),
+    };
+
+    match (name.as_str(), args.as_slice()) {
+        ("count", [FunctionArgExpr::Wildcard]) => {
+            return Ok(method(Expr::Ident("pl".into()), "len", vec![]));
+        }
+        ("count", [_]) if distinct => return Ok(method(arg(0)?, "n_unique", vec![])),
+        _ if distinct => return Err(unsupported(format!("DISTINCT in {f}"))),
+        (_, [_]) => {
+            if let Some(aggregate) = aggregate_method(&name) {
+                return Ok(method(arg(0)?, aggregate, vec![]));
+            }
+        }
+        _ => {}
+    }
+    match (name.as_str(), args.as_slice()) {
+        ("abs", [_]) => Ok(method(arg(0)?, "abs", vec![])),
+        ("round", [_]) => Ok(method(arg(0)?, "round", vec![SurfaceArg::pos(int(0))])),
+        ("round", [_, _]) => Ok(method(arg(0)?, "round", vec![SurfaceArg::pos(arg(1)?)])),
+        ("lower", [_]) => Ok(method(arg(0)?.attr("str"), "to_lowercase", vec![])),
+        ("upper", [_]) => Ok(method(arg(0)?.attr("str"), "to_uppercase", vec![])),
+        ("length" | "char_length", [_]) => Ok(method(arg(0)?.attr("str"), "len_chars", vec![])),
+        ("coalesce", [_, ..]) => {
+            let items = (0..args.len()).map(arg).collect::<Result<Vec<_>>>()?;
+            Ok(method(
+                Expr::Ident("pl".into()),
+                "coalesce",
+                vec![SurfaceArg::pos(Expr::List(items))],
+            ))
+        }
+        _ => Err(unsupported(format!("function {f}"))),
+    }
+}
+
+fn aggregate_method(name: &str) -> Option<&'static str> {
+    AGGREGATES
+        .iter()
+        .find(|(sql_name, _)| *sql_name == name)
+        .map(|(_, method)| *method)
+}
+
+fn is_aggregate_call(e: &sql::Expr) -> bool {
+    matches!(e, sql::Expr::Function(f)
+        if f.over.is_none() && aggregate_method(&f.name.to_string().to_lowercase()).is_some())
+}
+
+fn contains_aggregate(e: &sql::Expr) -> bool {
+    if is_aggregate_call(e) {
+        return true;
+    }
+    match e {
+        sql::Expr::BinaryOp { left, right, .. } => {
+            contains_aggregate(left) || contains_aggregate(right)
+        }
+        sql::Expr::UnaryOp { expr, .. }
+        | sql::Expr::Nested(expr)
+        | sql::Expr::Cast { expr, .. }
+        | sql::Expr::IsNull(expr)
+        | sql::Expr::IsNotNull(expr) => contains_aggregate(expr),
+        sql::Expr::Function(f) => match &f.args {
+            FunctionArguments::List(list) => list.args.iter().any(|arg| {
+                matches!(arg, FunctionArg::Unnamed(FunctionArgExpr::Expr(e)) if contains_aggregate(e))
+            }),
+            _ => false,
+        },
+        sql::Expr::Case {
+            conditions,
+            results,
+            else_result,
+            ..
+        } => {
+            conditions.iter().chain(results).any(contains_aggregate)
+                || else_result.as_deref().is_some_and(contains_aggregate)
+        }
+        _ => false,
+    }
+}
+
+/// Column a (possibly table-qualified) identifier refers to, as named after the joins
+fn column_name(e: &sql::Expr, scope: &Scope) -> Option<String> {
+    let column = unqualified(e)?;
+    match scope.side(e) {
+        Ok(Some(side)) => Some(
+            scope
+                .renamed
+                .get(&(side, column.clone()))
+                .cloned()
+                .unwrap_or(column),
+        ),
+        _ => Some(column),
+    }
+}
+
+/// Column an identifier names, ignoring any table qualifier
+fn unqualified(e: &sql::Expr) -> Option<String> {
+    match e {
+        sql::Expr::Identifier(ident) => Some(ident.value.clone()),
+        sql::Expr::CompoundIdentifier(parts) => parts.last().map(|ident| ident.value.clone()),
+        _ => None,
+    }
+}
+
+/// Output name of an unaliased SELECT item: the column, `count` for `COUNT(*)`,
+/// or the aggregated column (as Polars names it)
+fn default_name(e: &sql::Expr, scope: &Scope) -> Option<String> {
+    if let Some(name) = column_name(e, scope) {
+        return Some(name);
+    }
+    let sql::Expr::Function(f) = e else {
+        return None;
+    };
+    let FunctionArguments::List(list) = &f.args else {
+        return None;
+    };
+    match list.args.as_slice() {
+        [FunctionArg::Unnamed(FunctionArgExpr::Wildcard)] => Some("count".to_string()),
+        [FunctionArg::Unnamed(FunctionArgExpr::Expr(arg))] => column_name(arg, scope),
+        _ => None,
+    }
+}
+
+/// PiQL dtype name for a SQL type
+fn dtype_name(data_type: &sql::DataType) -> Result<String> {
+    use sql::DataType as T;
+    Ok(match data_type {
+        T::TinyInt(_) => "i8",
+        T::SmallInt(_) => "i16",
+        T::Int(_) | T::Integer(_) => "i32",
+        T::BigInt(_) => "i64",
+        T::Real | T::Float4 => "f32",
+        T::Float(_) | T::Double | T::DoublePrecision | T::Float8 => "f64",
+        T::Decimal(_) | T::Numeric(_) => "f64",
+        T::Varchar(_) | T::Char(_) | T::Text | T::String(_) => "str",
+        T::Boolean | T::Bool => "bool",
+        T::Date => "date",
+        other => return Err(unsupported(format!("CAST to {other}"))),
+    }
+    .to_string())
+}
+
+// ============ Surface AST builders ============
+
+fn col(name: &str) -> Expr {
+    method(
+        Expr::Ident("pl".into()),
+        "col",
+        vec![SurfaceArg::pos(string(name))],
+    )
+}
+
+fn method(base: Expr, name: &str, args: Vec<SurfaceArg>) -> Expr {
+    base.attr(name).call(args)
+}
+
+fn aliased(e: Expr, name: &str) -> Expr {
+    method(e, "alias", vec![SurfaceArg::pos(string(name))])
+}
+
+fn negate_if(negated: bool, e: Expr) -> Expr {
+    if negated {
+        Expr::UnaryOp(UnaryOp::Not, Box::new(e))
+    } else {
+        e
+    }
+}
+
+fn string(s: &str) -> Expr {
+    Expr::Literal(Literal::String(s.to_string()))
+}
+
+fn int(n: i64) -> Expr {
+    Expr::Literal(Literal::Int(n))
+}
+
+/// A single name, or a list of names
+fn strings(names: Vec<String>) -> Expr {
+    match names.as_slice() {
+        [name] => string(name),
+        _ => Expr::List(names.iter().map(|name| string(name)).collect()),
+    }
+}
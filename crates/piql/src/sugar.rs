@@ -1,8 +1,9 @@
 //! Sugar system for PiQL
 //!
 //! Provides:
-//! - SugarContext: Runtime values for sugar expansion (tick, partition_key)
-//! - SugarRegistry: Handlers for @directives and $col.method sugar
+//! - SugarContext: Runtime values for sugar expansion (tick, partition_key, tick_column)
+//! - SugarRegistry: Handlers for @directives, .@pipeline directives, $col.method sugar and
+//!   user-defined functions
 
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -17,6 +18,8 @@ pub struct SugarContext {
     pub tick: Option<i64>,
     /// Partition key for windowed operations (from current DF's TimeSeriesConfig)
     pub partition_key: Option<String>,
+    /// Tick column of the current DF (for $col.rate)
+    pub tick_column: Option<String>,
 }
 
 impl SugarContext {
@@ -33,23 +36,40 @@ impl SugarContext {
         self.partition_key = Some(key.into());
         self
     }
+
+    pub fn with_tick_column(mut self, column: impl Into<String>) -> Self {
+        self.tick_column = Some(column.into());
+        self
+    }
 }
 
 /// Handler for @directive(args) sugar
 pub type DirectiveHandler =
     Arc<dyn Fn(&[CoreArg], &SugarContext) -> CoreExpr + Send + Sync + 'static>;
 
+/// Handler for df.@directive(args) sugar; receives the receiver expression
+pub type PipelineDirectiveHandler =
+    Arc<dyn Fn(CoreExpr, &[CoreArg], &SugarContext) -> CoreExpr + Send + Sync + 'static>;
+
 /// Handler for $col.method(args) sugar
 pub type ColMethodHandler =
     Arc<dyn Fn(CoreExpr, &[CoreArg], &SugarContext) -> CoreExpr + Send + Sync + 'static>;
 
+/// Handler for user-defined name(args) functions
+pub type FunctionHandler =
+    Arc<dyn Fn(&[CoreArg], &SugarContext) -> CoreExpr + Send + Sync + 'static>;
+
 /// Registry of sugar handlers
 #[derive(Default, Clone)]
 pub struct SugarRegistry {
     /// @directive handlers by name
     directives: HashMap<String, DirectiveHandler>,
+    /// df.@directive handlers by name
+    pipeline_directives: HashMap<String, PipelineDirectiveHandler>,
     /// $col.method handlers by method name
     col_methods: HashMap<String, ColMethodHandler>,
+    /// User-defined functions by name, with their positional arity
+    functions: HashMap<String, (usize, FunctionHandler)>,
 }
 
 impl SugarRegistry {
@@ -67,6 +87,19 @@ impl SugarRegistry {
         self.directives.insert(name.into(), Arc::new(handler));
     }
 
+    /// Register a custom pipeline directive, used in method position as
+    /// `df.@name(args)`.
+    ///
+    /// The handler receives the desugared receiver and arguments and returns the
+    /// expression replacing the whole `df.@name(args)`, e.g. a sort + group_by pipeline.
+    pub fn register_pipeline_directive<F>(&mut self, name: impl Into<String>, handler: F)
+    where
+        F: Fn(CoreExpr, &[CoreArg], &SugarContext) -> CoreExpr + Send + Sync + 'static,
+    {
+        self.pipeline_directives
+            .insert(name.into(), Arc::new(handler));
+    }
+
     /// Register a custom $col.method handler
     pub fn register_col_method<F>(&mut self, name: impl Into<String>, handler: F)
     where
@@ -75,6 +108,44 @@ impl SugarRegistry {
         self.col_methods.insert(name.into(), Arc::new(handler));
     }
 
+    /// Register a user-defined function, called as `name(args)` in queries.
+    ///
+    /// The handler receives the desugared arguments and returns the expression the
+    /// call expands to. Calls with a different number of positional arguments than
+    /// `arity` are rejected.
+    pub fn register_function<F>(&mut self, name: impl Into<String>, arity: usize, handler: F)
+    where
+        F: Fn(&[CoreArg], &SugarContext) -> CoreExpr + Send + Sync + 'static,
+    {
+        self.functions
+            .insert(name.into(), (arity, Arc::new(handler)));
+    }
+
+    /// Check if a name is a registered user-defined function
+    pub fn has_function(&self, name: &str) -> bool {
+        self.functions.contains_key(name)
+    }
+
+    /// Expand a user-defined name(args) call
+    pub fn expand_function(
+        &self,
+        name: &str,
+        args: &[CoreArg],
+        ctx: &SugarContext,
+    ) -> Option<CoreExpr> {
+        let (arity, handler) = self.functions.get(name)?;
+        let given = args
+            .iter()
+            .filter(|a| matches!(a, Arg::Positional(_)))
+            .count();
+        if given != *arity {
+            return Some(CoreExpr::Invalid(format!(
+                "{name}() takes {arity} argument(s), got {given}"
+            )));
+        }
+        Some(handler(args, ctx))
+    }
+
     /// Expand a @directive(args)
     pub fn expand_directive(
         &self,
@@ -85,6 +156,19 @@ impl SugarRegistry {
         self.directives.get(name).map(|handler| handler(args, ctx))
     }
 
+    /// Expand a receiver.@directive(args)
+    pub fn expand_pipeline_directive(
+        &self,
+        receiver: CoreExpr,
+        name: &str,
+        args: &[CoreArg],
+        ctx: &SugarContext,
+    ) -> Option<CoreExpr> {
+        self.pipeline_directives
+            .get(name)
+            .map(|handler| handler(receiver, args, ctx))
+    }
+
     /// Expand a $col.method(args)
     pub fn expand_col_method(
         &self,
@@ -98,6 +182,26 @@ impl SugarRegistry {
             .map(|handler| handler(col_expr, args, ctx))
     }
 
+    /// Names of registered @directives
+    pub fn directive_names(&self) -> impl Iterator<Item = &str> {
+        self.directives.keys().map(String::as_str)
+    }
+
+    /// Names of registered .@pipeline directives
+    pub fn pipeline_directive_names(&self) -> impl Iterator<Item = &str> {
+        self.pipeline_directives.keys().map(String::as_str)
+    }
+
+    /// Names of registered $col.methods
+    pub fn col_method_names(&self) -> impl Iterator<Item = &str> {
+        self.col_methods.keys().map(String::as_str)
+    }
+
+    /// Names of registered user-defined functions
+    pub fn function_names(&self) -> impl Iterator<Item = &str> {
+        self.functions.keys().map(String::as_str)
+    }
+
     /// Check if a method name is a registered col method
     pub fn has_col_method(&self, name: &str) -> bool {
         self.col_methods.contains_key(name)
@@ -136,20 +240,58 @@ impl SugarRegistry {
         });
 
         // $col.pct(n) -> (col - col.shift(n)) / col.shift(n) [optionally partitioned]
+        // $col.pct -> same with n = 1
         self.register_col_method("pct", |col_expr, args, ctx| {
-            let shifted_base = helpers::method_call(col_expr.clone(), "shift", args.to_vec());
-            let shifted = if let Some(partition) = ctx.partition_key.as_deref() {
-                helpers::method_call(
-                    shifted_base,
-                    "over",
-                    vec![Arg::pos(helpers::lit_str(partition))],
-                )
+            let shift_args = if args.is_empty() {
+                vec![Arg::pos(helpers::lit_int(1))]
             } else {
-                shifted_base
+                args.to_vec()
             };
+            let shifted = over_partition(
+                helpers::method_call(col_expr.clone(), "shift", shift_args),
+                ctx,
+            );
             let diff = helpers::binop(col_expr, BinOp::Sub, shifted.clone());
             helpers::binop(diff, BinOp::Div, shifted)
         });
+
+        // $col.zscore -> (col - col.mean()) / col.std() [optionally partitioned]
+        self.register_col_method("zscore", |col_expr, _args, ctx| {
+            let mean = over_partition(helpers::method_call(col_expr.clone(), "mean", vec![]), ctx);
+            let std = over_partition(helpers::method_call(col_expr.clone(), "std", vec![]), ctx);
+            let centered = helpers::binop(col_expr, BinOp::Sub, mean);
+            helpers::binop(centered, BinOp::Div, std)
+        });
+
+        // $col.rate -> col.diff().cast("f64") / tick.diff() [optionally partitioned]
+        self.register_col_method("rate", |col_expr, _args, ctx| {
+            let Some(tick_column) = ctx.tick_column.as_deref() else {
+                return CoreExpr::Invalid(
+                    "$col.rate requires a tick column; register a time-series dataframe or set EvalContext::with_default_tick_column(...)".into(),
+                );
+            };
+            // Float so integer columns don't truncate the rate
+            let value_diff = helpers::method_call(
+                over_partition(helpers::method_call(col_expr, "diff", vec![]), ctx),
+                "cast",
+                vec![Arg::pos(helpers::lit_str("f64"))],
+            );
+            let tick_diff = over_partition(
+                helpers::method_call(helpers::pl_col(tick_column), "diff", vec![]),
+                ctx,
+            );
+            helpers::binop(value_diff, BinOp::Div, tick_diff)
+        });
+    }
+}
+
+/// Wrap `expr` in `.over(partition)` when the context has a partition key
+fn over_partition(expr: CoreExpr, ctx: &SugarContext) -> CoreExpr {
+    match ctx.partition_key.as_deref() {
+        Some(partition) => {
+            helpers::method_call(expr, "over", vec![Arg::pos(helpers::lit_str(partition))])
+        }
+        None => expr,
     }
 }
 
@@ -191,6 +333,30 @@ pub mod helpers {
         )
     }
 
+    /// Build pl.when(c1).then(v1)...otherwise(default) from (condition, value) pairs
+    pub fn when_then_otherwise(
+        branches: Vec<(CoreExpr, CoreExpr)>,
+        otherwise: CoreExpr,
+    ) -> CoreExpr {
+        CoreExpr::WhenThenOtherwise {
+            branches: branches
+                .into_iter()
+                .map(|(cond, then)| (Box::new(cond), Box::new(then)))
+                .collect(),
+            otherwise: Box::new(otherwise),
+        }
+    }
+
+    /// Get the expression of the idx-th positional arg
+    pub fn positional_arg(args: &[CoreArg], idx: usize) -> Option<&CoreExpr> {
+        args.iter()
+            .filter_map(|arg| match arg {
+                Arg::Positional(e) => Some(e),
+                Arg::Keyword(..) => None,
+            })
+            .nth(idx)
+    }
+
     /// Extract integer from first positional arg
     pub fn get_int_arg(args: &[CoreArg], idx: usize) -> Option<i64> {
         let mut pos_idx = 0;
@@ -2,7 +2,8 @@
 //!
 //! This pass:
 //! - Recognizes when/then/otherwise chains and converts to WhenThenOtherwise
-//! - Expands sugar: $col, @directive, $col.method
+//! - Expands sugar: $col, @directive, .@pipeline_directive, $col.method, `.by_run`,
+//!   user-defined functions
 
 use crate::ast::Arg;
 use crate::ast::core::{CoreArg, Expr as CoreExpr};
@@ -36,6 +37,10 @@ fn transform_expr(expr: SurfaceExpr, registry: &SugarRegistry, ctx: &SugarContex
                 .collect(),
         ),
         SurfaceExpr::Attr(base, name) => {
+            // Sugar: x.by_run -> x.by_run(), with a bare `$col.mean` base called too
+            if name == "by_run" {
+                return by_run(transform_expr(*base, registry, ctx), vec![]);
+            }
             // Check for $col.method pattern (no args - like $col.delta)
             if let SurfaceExpr::ColShorthand(ref col_name) = *base
                 && let Some(expanded) =
@@ -65,9 +70,40 @@ fn transform_expr(expr: SurfaceExpr, registry: &SugarRegistry, ctx: &SugarContex
                 .expand_directive(&name, &core_args, ctx)
                 .unwrap_or_else(|| CoreExpr::Invalid(format!("Unknown directive: @{name}")))
         }
+        // Sugar: df.@directive(args) -> receiver rewritten via registry
+        SurfaceExpr::PipelineDirective(base, name, args) => {
+            let receiver = transform_expr(*base, registry, ctx);
+            let core_args: Vec<CoreArg> = args
+                .into_iter()
+                .map(|a| transform_arg(a, registry, ctx))
+                .collect();
+            registry
+                .expand_pipeline_directive(receiver, &name, &core_args, ctx)
+                .unwrap_or_else(|| {
+                    CoreExpr::Invalid(format!("Unknown pipeline directive: .@{name}"))
+                })
+        }
+        // Placeholders are bound before transform; any left over have no value
+        SurfaceExpr::Param(name) => CoreExpr::Invalid(format!("Unbound parameter :{name}")),
+        // Aliases are expanded before transform
+        SurfaceExpr::Alias(name) => CoreExpr::Invalid(format!("Unexpanded alias !{name}")),
+        SurfaceExpr::Commented { expr, .. } => transform_expr(*expr, registry, ctx),
+        SurfaceExpr::Error => CoreExpr::Invalid("Query contains a parse error".to_string()),
         SurfaceExpr::Call(callee, args) => {
+            // Sugar: x.by_run() -> partition/group by the run label column
+            let callee = match *callee {
+                SurfaceExpr::Attr(base, method) if method == "by_run" => {
+                    let core_args = args
+                        .into_iter()
+                        .map(|a| transform_arg(a, registry, ctx))
+                        .collect();
+                    return by_run(transform_expr(*base, registry, ctx), core_args);
+                }
+                callee => callee,
+            };
+
             // Check for .otherwise() pattern - signals end of when chain
-            if let SurfaceExpr::Attr(ref base, ref method) = *callee
+            if let SurfaceExpr::Attr(ref base, ref method) = callee
                 && method == "otherwise"
                 && let Some(when_chain) = try_extract_when_chain(base)
             {
@@ -75,7 +111,7 @@ fn transform_expr(expr: SurfaceExpr, registry: &SugarRegistry, ctx: &SugarContex
             }
 
             // Check for $col.method() pattern - sugar for col methods
-            if let SurfaceExpr::Attr(ref base, ref method) = *callee
+            if let SurfaceExpr::Attr(ref base, ref method) = callee
                 && let SurfaceExpr::ColShorthand(ref col_name) = **base
             {
                 let core_args: Vec<CoreArg> = args
@@ -100,9 +136,22 @@ fn transform_expr(expr: SurfaceExpr, registry: &SugarRegistry, ctx: &SugarContex
                 );
             }
 
+            // User-defined function: name(args)
+            if let SurfaceExpr::Ident(ref name) = callee
+                && registry.has_function(name)
+            {
+                let core_args: Vec<CoreArg> = args
+                    .into_iter()
+                    .map(|a| transform_arg(a, registry, ctx))
+                    .collect();
+                return registry
+                    .expand_function(name, &core_args, ctx)
+                    .unwrap_or_else(|| CoreExpr::Invalid(format!("Unknown function: {name}")));
+            }
+
             // Normal call
             CoreExpr::Call(
-                Box::new(transform_expr(*callee, registry, ctx)),
+                Box::new(transform_expr(callee, registry, ctx)),
                 args.into_iter()
                     .map(|a| transform_arg(a, registry, ctx))
                     .collect(),
@@ -111,6 +160,22 @@ fn transform_expr(expr: SurfaceExpr, registry: &SugarRegistry, ctx: &SugarContex
     }
 }
 
+/// Build `base.by_run(args)`, calling a bare method attribute base (`$col.mean`) first
+fn by_run(base: CoreExpr, args: Vec<CoreArg>) -> CoreExpr {
+    let base = match base {
+        CoreExpr::Attr(inner, method)
+            if !matches!(method.as_str(), "str" | "dt" | "list" | "struct") =>
+        {
+            CoreExpr::Call(Box::new(CoreExpr::Attr(inner, method)), vec![])
+        }
+        base => base,
+    };
+    CoreExpr::Call(
+        Box::new(CoreExpr::Attr(Box::new(base), "by_run".into())),
+        args,
+    )
+}
+
 fn transform_arg(arg: SurfaceArg, registry: &SugarRegistry, ctx: &SugarContext) -> CoreArg {
     match arg {
         Arg::Positional(e) => Arg::Positional(transform_expr(e, registry, ctx)),
@@ -0,0 +1,151 @@
+//! Static validation of method calls, without a Polars context
+//!
+//! Checks every `receiver.method(args)` call in the desugared query against the
+//! [`capabilities`](crate::capabilities) tables: the method must exist for its
+//! receiver, and take the given number of positional and keyword arguments.
+//!
+//! Receivers are resolved syntactically, like eval dispatch: `pl.`, `cs.`, `.str.`,
+//! `.dt.` and `.struct.` pick their namespace, anything else may be a DataFrame, a
+//! group_by or an expression. Sugar registered at runtime (directives, custom
+//! `$col.method`s, functions) is unknown here: directives and functions are skipped,
+//! custom col methods are reported like any unknown method.
+
+use crate::ast::Arg;
+use crate::ast::core::{CoreArg, Expr};
+use crate::capabilities::{MethodSpec, Namespace};
+use crate::parse::{self, ParseError};
+use crate::transform;
+
+/// A call the evaluator would reject
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    /// Method the error is about
+    pub method: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Parse `query` and check its method calls. Returns every invalid call, in
+/// evaluation order (receivers before the calls on them).
+///
+/// ```ignore
+/// let errors = piql::validate("entities.filter($gold > 1).hed(5)")?;
+/// assert_eq!(errors[0].message, "Unknown method .hed()");
+/// ```
+pub fn validate(query: &str) -> Result<Vec<ValidationError>, ParseError> {
+    let core = transform::transform(parse::parse(query)?);
+    let mut errors = Vec::new();
+    check(&core, &mut errors);
+    Ok(errors)
+}
+
+fn check(expr: &Expr, errors: &mut Vec<ValidationError>) {
+    match expr {
+        Expr::Ident(_) | Expr::Literal(_) | Expr::Invalid(_) => {}
+        Expr::List(items) => items.iter().for_each(|e| check(e, errors)),
+        Expr::Attr(base, _) => check(base, errors),
+        Expr::Call(callee, args) => {
+            check(callee, errors);
+            for arg in args {
+                match arg {
+                    Arg::Positional(e) | Arg::Keyword(_, e) => check(e, errors),
+                }
+            }
+            if let Expr::Attr(base, method) = callee.as_ref()
+                && let Some(message) = check_call(base, method, args)
+            {
+                errors.push(ValidationError {
+                    method: method.clone(),
+                    message,
+                });
+            }
+        }
+        Expr::BinaryOp(lhs, _, rhs) => {
+            check(lhs, errors);
+            check(rhs, errors);
+        }
+        Expr::UnaryOp(_, inner) => check(inner, errors),
+        Expr::WhenThenOtherwise {
+            branches,
+            otherwise,
+        } => {
+            for (condition, value) in branches {
+                check(condition, errors);
+                check(value, errors);
+            }
+            check(otherwise, errors);
+        }
+    }
+}
+
+/// Namespaces eval may dispatch a call on `base` to
+fn receivers(base: &Expr) -> &'static [Namespace] {
+    match base {
+        Expr::Ident(name) if name == "pl" => &[Namespace::Pl],
+        Expr::Ident(name) if name == "cs" => &[Namespace::Selectors],
+        Expr::Attr(_, namespace) if namespace == "str" => &[Namespace::Str],
+        Expr::Attr(_, namespace) if namespace == "dt" => &[Namespace::Dt],
+        Expr::Attr(_, namespace) if namespace == "struct" => &[Namespace::Struct],
+        _ => &[Namespace::DataFrame, Namespace::GroupBy, Namespace::Expr],
+    }
+}
+
+fn check_call(base: &Expr, method: &str, args: &[CoreArg]) -> Option<String> {
+    let specs: Vec<&MethodSpec> = receivers(base)
+        .iter()
+        .flat_map(|ns| ns.methods())
+        .filter(|spec| spec.name == method)
+        .collect();
+    if specs.is_empty() {
+        return Some(format!("Unknown method .{method}()"));
+    }
+
+    let positional = args
+        .iter()
+        .filter(|arg| matches!(arg, Arg::Positional(_)))
+        .count();
+    let arity_ok: Vec<&MethodSpec> = specs
+        .iter()
+        .copied()
+        .filter(|spec| {
+            positional >= spec.min_args && spec.max_args.is_none_or(|max| positional <= max)
+        })
+        .collect();
+    if arity_ok.is_empty() {
+        return Some(format!(
+            ".{method}() takes {}, got {positional}",
+            describe_arity(&specs)
+        ));
+    }
+
+    args.iter().find_map(|arg| match arg {
+        Arg::Keyword(name, _)
+            if !arity_ok
+                .iter()
+                .any(|spec| spec.kwargs.iter().any(|k| *k == "*" || k == name)) =>
+        {
+            Some(format!(".{method}() has no keyword argument `{name}`"))
+        }
+        _ => None,
+    })
+}
+
+/// Positional argument count accepted by any of `specs`, e.g. "1 to 2 arguments"
+fn describe_arity(specs: &[&MethodSpec]) -> String {
+    let min = specs.iter().map(|s| s.min_args).min().unwrap_or(0);
+    let max = specs
+        .iter()
+        .map(|s| s.max_args)
+        .try_fold(0, |acc, max| max.map(|m| acc.max(m)));
+    let plural = |n: usize| if n == 1 { "argument" } else { "arguments" };
+    match max {
+        None => format!("at least {min} {}", plural(min)),
+        Some(max) if max == min => format!("{min} {}", plural(min)),
+        Some(max) => format!("{min} to {max} arguments"),
+    }
+}
@@ -2,8 +2,16 @@
 //!
 //! These tests exercise the full parse → eval pipeline.
 
-use piql::expr_helpers::{binop, lit_int, lit_str, pl_col};
-use piql::{BinOp, EvalContext, QueryEngine, TimeSeriesConfig, Value, run};
+#![cfg(feature = "eval")]
+
+use piql::advanced::{Arg, CoreExpr};
+use piql::expr_helpers::{binop, lit_int, lit_str, method_call, pl_col};
+use piql::{
+    BinOp, CHANGE_COLUMN, CompletionKind, EmitMode, EvalContext, EvalError, LimitExceeded,
+    LintKind, Namespace, ParamValue, Params, PiqlError, QueryEngine, RemoveMode, ResourceLimits,
+    Retention, SubscriptionScope, TickDtype, TickRange, TimeSeriesConfig, Value, capabilities,
+    compile, complete, run, run_with_meta, run_with_params,
+};
 use polars::prelude::*;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
@@ -75,6 +83,34 @@ fn filter_with_arithmetic() {
     );
 }
 
+// ============ Collapsed results ============
+
+#[test]
+fn collapsed_results_unwrap_single_columns_and_cells() {
+    let ctx = setup_test_df().with_collapsed_results(true);
+
+    let Value::ScalarResult(total) = run("entities.select($gold.sum())", &ctx).unwrap() else {
+        panic!("expected a scalar result");
+    };
+    assert_eq!(total.value().extract::<i64>(), Some(400));
+
+    let Value::Series(names) = run("entities.select($name)", &ctx).unwrap() else {
+        panic!("expected a series");
+    };
+    assert_eq!(names.name().as_str(), "name");
+    assert_eq!(names.len(), 3);
+
+    // Wider results, and every result without the flag, stay DataFrames
+    assert!(matches!(
+        run("entities.select($name, $gold)", &ctx).unwrap(),
+        Value::DataFrame(..)
+    ));
+    assert_eq!(
+        run_to_df("entities.select($gold.sum())", &setup_test_df()).shape(),
+        (1, 1)
+    );
+}
+
 // ============ Logical operators ============
 
 #[test]
@@ -191,6 +227,24 @@ fn head_default() {
     assert_eq!(df.height(), 3); // default is 10, we only have 3
 }
 
+#[test]
+fn negative_row_counts_are_rejected() {
+    let ctx = setup_test_df();
+    for query in [
+        "entities.head(-1)",
+        "entities.tail(-1)",
+        r#"entities.top(-1, "gold")"#,
+    ] {
+        assert!(matches!(
+            run(query, &ctx),
+            Err(PiqlError::EvalWithQuery {
+                source: EvalError::ArgError(_),
+                ..
+            })
+        ));
+    }
+}
+
 // ============ String namespace ============
 
 #[test]
@@ -455,6 +509,148 @@ fn group_by_with_col_shorthand() {
     assert_eq!(df.height(), 2); // merchant and producer
 }
 
+#[test]
+fn group_by_count_shortcut() {
+    let ctx = setup_test_df();
+    let df = run_to_df(r#"entities.group_by("type").count().sort("type")"#, &ctx);
+    assert_eq!(df.get_column_names(), vec!["type", "count"]);
+    let counts: Vec<u32> = df
+        .column("count")
+        .unwrap()
+        .u32()
+        .unwrap()
+        .into_no_null_iter()
+        .collect();
+    assert_eq!(counts, vec![2, 1]);
+
+    let df = run_to_df(r#"entities.group_by("type").len()"#, &ctx);
+    assert_eq!(df.get_column_names(), vec!["type", "len"]);
+}
+
+#[test]
+fn group_by_aggregation_shortcuts_cover_numeric_columns() {
+    let df = df! {
+        "type" => &["a", "a", "b"],
+        "name" => &["x", "y", "z"],
+        "gold" => &[10i64, 20, 5],
+        "score" => &[1.0, 3.0, 2.0],
+    }
+    .unwrap()
+    .lazy();
+    let ctx = EvalContext::new().with_df("df", df);
+
+    let sum = run_to_df(r#"df.group_by("type").sum().sort("type")"#, &ctx);
+    assert_eq!(sum.get_column_names(), vec!["type", "gold", "score"]);
+    let gold: Vec<i64> = sum
+        .column("gold")
+        .unwrap()
+        .i64()
+        .unwrap()
+        .into_no_null_iter()
+        .collect();
+    assert_eq!(gold, vec![30, 5]);
+
+    let mean = run_to_df(r#"df.group_by("type").mean().sort("type")"#, &ctx);
+    let score: Vec<f64> = mean
+        .column("score")
+        .unwrap()
+        .f64()
+        .unwrap()
+        .into_no_null_iter()
+        .collect();
+    assert_eq!(score, vec![2.0, 2.0]);
+
+    let max = run_to_df(r#"df.group_by("type").max().sort("type")"#, &ctx);
+    let min = run_to_df(r#"df.group_by("type").min().sort("type")"#, &ctx);
+    assert_eq!(max.column("gold").unwrap().i64().unwrap().get(0), Some(20));
+    assert_eq!(min.column("gold").unwrap().i64().unwrap().get(0), Some(10));
+}
+
+#[test]
+fn group_by_expression_key() {
+    let df = df! {
+        "tick" => &[1i64, 5, 12, 18, 25],
+        "val" => &[1i64, 2, 3, 4, 5],
+    }
+    .unwrap()
+    .lazy();
+
+    let ctx = EvalContext::new().with_df("df", df);
+    let result = run_to_df(
+        r#"df.group_by(($tick // 10).alias("bucket")).agg($val.sum().alias("total")).sort("bucket")"#,
+        &ctx,
+    );
+    let totals: Vec<i64> = result
+        .column("total")
+        .unwrap()
+        .i64()
+        .unwrap()
+        .into_no_null_iter()
+        .collect();
+    assert_eq!(totals, vec![3, 7, 5]);
+}
+
+#[test]
+fn floor_div_operator() {
+    let ctx = setup_test_df();
+    let df = run_to_df(r#"entities.select(($gold // 100).alias("hundreds"))"#, &ctx);
+    let hundreds: Vec<i32> = df
+        .column("hundreds")
+        .unwrap()
+        .i32()
+        .unwrap()
+        .into_no_null_iter()
+        .collect();
+    assert_eq!(hundreds, vec![1, 2, 0]);
+}
+
+#[test]
+fn group_by_dynamic_int_index() {
+    let df = df! {
+        "tick" => &[0i64, 3, 10, 14, 21],
+        "val" => &[1i64, 2, 3, 4, 5],
+    }
+    .unwrap()
+    .lazy();
+
+    let ctx = EvalContext::new().with_df("df", df);
+    let result = run_to_df(
+        r#"df.group_by_dynamic("tick", "10i").agg($val.sum().alias("total"))"#,
+        &ctx,
+    );
+    let totals: Vec<i64> = result
+        .column("total")
+        .unwrap()
+        .i64()
+        .unwrap()
+        .into_no_null_iter()
+        .collect();
+    assert_eq!(totals, vec![3, 7, 5]);
+
+    // A period longer than `every` gives overlapping windows
+    let result = run_to_df(
+        r#"df.group_by_dynamic("tick", every="10i", period="20i").agg($val.sum().alias("total"))"#,
+        &ctx,
+    );
+    let totals: Vec<i64> = result
+        .column("total")
+        .unwrap()
+        .i64()
+        .unwrap()
+        .into_no_null_iter()
+        .collect();
+    assert_eq!(totals, vec![10, 12, 5]);
+}
+
+#[test]
+fn group_by_dynamic_rejects_bad_duration() {
+    let ctx = setup_test_df();
+    let err = run(r#"entities.group_by_dynamic("gold", "often")"#, &ctx)
+        .err()
+        .expect("invalid duration should fail");
+    assert!(err.to_string().contains("every"), "{err}");
+}
+
 #[test]
 fn drop_with_col_shorthand() {
     let ctx = setup_test_df();
@@ -519,6 +715,139 @@ fn join_with_how() {
     assert_eq!(df.height(), 3);
 }
 
+#[test]
+fn join_semi_and_anti() {
+    let left = df! { "a" => &[1, 2, 3], "v" => &[10, 20, 30] }
+        .unwrap()
+        .lazy();
+    let right = df! { "a" => &[2, 3, 3, 4] }.unwrap().lazy();
+
+    let ctx = EvalContext::new()
+        .with_df("left", left)
+        .with_df("right", right);
+
+    let semi = run_to_df(r#"left.join(right, on="a", how="semi").sort("a")"#, &ctx);
+    assert_eq!(semi.height(), 2);
+    assert_eq!(semi.get_column_names(), &["a", "v"]);
+
+    let anti = run_to_df(r#"left.join(right, on="a", how="anti")"#, &ctx);
+    assert_eq!(anti.height(), 1);
+    assert_eq!(anti.column("a").unwrap().i32().unwrap().get(0), Some(1));
+}
+
+#[test]
+fn join_suffix_renames_collisions() {
+    let left = df! { "a" => &[1, 2], "v" => &[10, 20] }.unwrap().lazy();
+    let right = df! { "a" => &[1, 2], "v" => &[100, 200] }.unwrap().lazy();
+
+    let ctx = EvalContext::new()
+        .with_df("left", left)
+        .with_df("right", right);
+
+    let df = run_to_df(r#"left.join(right, on="a", suffix="_r")"#, &ctx);
+    assert!(df.column("v_r").is_ok());
+    assert!(df.column("v_right").is_err());
+}
+
+#[test]
+fn anti_join_keeps_left_lineage_for_scope_methods() {
+    let ctx = asof_ctx();
+    let df = run_to_df(
+        r#"trades.join(quotes.filter($tick > 4), on="item", how="anti").at(5)"#,
+        &ctx,
+    );
+    assert_eq!(df.height(), 1);
+}
+
+fn asof_ctx() -> EvalContext {
+    let trades = df! {
+        "tick" => &[2i64, 3, 5, 6],
+        "item" => &["ore", "fish", "ore", "fish"],
+        "qty" => &[1, 2, 3, 4],
+    }
+    .unwrap()
+    .lazy();
+    let quotes = df! {
+        "tick" => &[1i64, 1, 4, 5],
+        "item" => &["ore", "fish", "ore", "fish"],
+        "price" => &[10.0, 20.0, 11.0, 21.0],
+    }
+    .unwrap()
+    .lazy();
+
+    EvalContext::new()
+        .with_time_series_df("trades", trades, TimeSeriesConfig::new("tick", "item"))
+        .with_df("quotes", quotes)
+}
+
+#[test]
+fn join_asof_backward_by_group() {
+    let ctx = asof_ctx();
+    let df = run_to_df(
+        r#"trades.join_asof(quotes, on="tick", by="item").sort("tick")"#,
+        &ctx,
+    );
+    assert_eq!(df.height(), 4);
+    let price: Vec<_> = df
+        .column("price")
+        .unwrap()
+        .f64()
+        .unwrap()
+        .into_iter()
+        .collect();
+    assert_eq!(price, vec![Some(10.0), Some(20.0), Some(11.0), Some(21.0)]);
+}
+
+#[test]
+fn join_asof_strategy_and_tolerance() {
+    let ctx = asof_ctx();
+    let df = run_to_df(
+        r#"trades.join_asof(quotes, on="tick", by="item", strategy="forward").sort("tick")"#,
+        &ctx,
+    );
+    let price: Vec<_> = df
+        .column("price")
+        .unwrap()
+        .f64()
+        .unwrap()
+        .into_iter()
+        .collect();
+    assert_eq!(price, vec![Some(11.0), Some(21.0), None, None]);
+
+    let df = run_to_df(
+        r#"trades.join_asof(quotes, on="tick", by="item", tolerance=1).sort("tick")"#,
+        &ctx,
+    );
+    let price: Vec<_> = df
+        .column("price")
+        .unwrap()
+        .f64()
+        .unwrap()
+        .into_iter()
+        .collect();
+    assert_eq!(price, vec![Some(10.0), None, Some(11.0), Some(21.0)]);
+
+    assert!(
+        run(
+            r#"trades.join_asof(quotes, on="tick", strategy="sideways")"#,
+            &ctx
+        )
+        .is_err()
+    );
+    assert!(run(r#"trades.join_asof(quotes)"#, &ctx).is_err());
+}
+
+#[test]
+fn join_asof_keeps_left_lineage_for_scope_methods() {
+    let ctx = asof_ctx();
+    let df = run_to_df(
+        r#"trades.join_asof(quotes, on="tick", by="item").since(5)"#,
+        &ctx,
+    );
+    assert_eq!(df.height(), 2);
+    assert!(df.column("price").is_ok());
+}
+
 // ============ over (window functions) ============
 
 #[test]
@@ -585,6 +914,91 @@ fn is_between_filter() {
     assert_eq!(df.height(), 1); // alice with 100
 }
 
+// ============ is_in / boolean methods ============
+
+#[test]
+fn is_in_literal_list() {
+    let ctx = setup_test_df();
+    let df = run_to_df(r#"entities.filter($name.is_in(["alice", "bob"]))"#, &ctx);
+    assert_eq!(df.height(), 2);
+
+    let df = run_to_df(r#"entities.filter($gold.is_in([50, 250]))"#, &ctx);
+    assert_eq!(df.height(), 2);
+
+    let df = run_to_df(r#"entities.filter($gold.is_in([]))"#, &ctx);
+    assert_eq!(df.height(), 0);
+}
+
+#[test]
+fn is_in_other_column() {
+    let df = df! {
+        "a" => &[1, 2, 3],
+        "b" => &[3, 4, 1],
+    }
+    .unwrap()
+    .lazy();
+    let ctx = EvalContext::new().with_df("df", df);
+    let result = run_to_df(r#"df.filter($a.is_in($b))"#, &ctx);
+    let a: Vec<i32> = result
+        .column("a")
+        .unwrap()
+        .i32()
+        .unwrap()
+        .into_no_null_iter()
+        .collect();
+    assert_eq!(a, vec![1, 3]);
+}
+
+#[test]
+fn is_in_rejects_mixed_list() {
+    let ctx = setup_test_df();
+    let err = run(r#"entities.filter($gold.is_in([1, "a"]))"#, &ctx)
+        .err()
+        .expect("mixed list should fail");
+    assert!(err.to_string().contains("same type"), "{err}");
+}
+
+#[test]
+fn is_duplicated_and_is_first_distinct() {
+    let ctx = setup_test_df();
+    let dup = run_to_df(r#"entities.filter($type.is_duplicated())"#, &ctx);
+    assert_eq!(dup.height(), 2);
+
+    let first = run_to_df(r#"entities.filter($type.is_first_distinct())"#, &ctx);
+    let names: Vec<&str> = first
+        .column("name")
+        .unwrap()
+        .str()
+        .unwrap()
+        .into_no_null_iter()
+        .collect();
+    assert_eq!(names, vec!["alice", "bob"]);
+}
+
+#[test]
+fn any_all_reducers() {
+    let ctx = setup_test_df();
+    let df = run_to_df(
+        r#"entities.select(($gold > 200).any().alias("any"), ($gold > 10).all().alias("all"))"#,
+        &ctx,
+    );
+    assert_eq!(df.column("any").unwrap().bool().unwrap().get(0), Some(true));
+    assert_eq!(df.column("all").unwrap().bool().unwrap().get(0), Some(true));
+
+    let df = run_to_df(
+        r#"entities.group_by("type").agg(($gold > 75).all().alias("rich")).sort("type")"#,
+        &ctx,
+    );
+    let rich: Vec<bool> = df
+        .column("rich")
+        .unwrap()
+        .bool()
+        .unwrap()
+        .into_no_null_iter()
+        .collect();
+    assert_eq!(rich, vec![false, true]);
+}
+
 // ============ fill_null / is_null ============
 
 #[test]
@@ -614,6 +1028,89 @@ fn filter_is_null() {
     assert_eq!(result.height(), 1);
 }
 
+#[test]
+fn fill_null_strategies() {
+    let df = df! {
+        "a" => &[Some(1.0), None, None, Some(4.0)],
+    }
+    .unwrap()
+    .lazy();
+    let ctx = EvalContext::new().with_df("df", df);
+    let values = |query: &str| -> Vec<Option<f64>> {
+        run_to_df(query, &ctx)
+            .column("a")
+            .unwrap()
+            .f64()
+            .unwrap()
+            .into_iter()
+            .collect()
+    };
+
+    assert_eq!(
+        values(r#"df.select($a.fill_null(strategy="forward"))"#),
+        vec![Some(1.0), Some(1.0), Some(1.0), Some(4.0)]
+    );
+    assert_eq!(
+        values(r#"df.select($a.fill_null(strategy="backward", limit=1))"#),
+        vec![Some(1.0), None, Some(4.0), Some(4.0)]
+    );
+    assert_eq!(
+        values(r#"df.select($a.fill_null(strategy="mean"))"#),
+        vec![Some(1.0), Some(2.5), Some(2.5), Some(4.0)]
+    );
+
+    let err = run(r#"df.select($a.fill_null(strategy="sideways"))"#, &ctx)
+        .err()
+        .expect("unknown strategy should fail");
+    assert!(err.to_string().contains("sideways"), "{err}");
+}
+
+#[test]
+fn nan_handling() {
+    let df = df! {
+        "a" => &[1.0, f64::NAN, 3.0],
+    }
+    .unwrap()
+    .lazy();
+    let ctx = EvalContext::new().with_df("df", df);
+
+    let filled = run_to_df(r#"df.select($a.fill_nan(0.0))"#, &ctx);
+    let a: Vec<f64> = filled
+        .column("a")
+        .unwrap()
+        .f64()
+        .unwrap()
+        .into_no_null_iter()
+        .collect();
+    assert_eq!(a, vec![1.0, 0.0, 3.0]);
+
+    let nans = run_to_df(r#"df.filter($a.is_nan())"#, &ctx);
+    assert_eq!(nans.height(), 1);
+
+    let dropped = run_to_df(r#"df.select($a.drop_nans())"#, &ctx);
+    assert_eq!(dropped.height(), 2);
+}
+
+#[test]
+fn coalesce_takes_first_non_null() {
+    let df = df! {
+        "a" => &[Some(1), None, None],
+        "b" => &[Some(10), Some(20), None],
+    }
+    .unwrap()
+    .lazy();
+    let ctx = EvalContext::new().with_df("df", df);
+    let result = run_to_df(r#"df.select(pl.coalesce([$a, $b, 0]).alias("c"))"#, &ctx);
+    let c: Vec<i32> = result
+        .column("c")
+        .unwrap()
+        .i32()
+        .unwrap()
+        .into_no_null_iter()
+        .collect();
+    assert_eq!(c, vec![1, 20, 0]);
+}
+
 // ============ cast ============
 
 #[test]
@@ -626,10 +1123,101 @@ fn cast_to_float() {
     assert!(df.column("gold_f").is_ok());
 }
 
-// ============ unique / n_unique ============
-
 #[test]
-fn unique_values() {
+fn cast_to_sized_and_temporal_types() {
+    let df = df! {
+        "tick" => &[1i64, 2],
+        "day" => &["2024-01-01", "2024-02-29"],
+        "label" => &["a", "b"],
+    }
+    .unwrap()
+    .lazy();
+    let ctx = EvalContext::new().with_df("df", df);
+    let result = run_to_df(
+        r#"df.select(
+            $tick.cast("u32").alias("u"),
+            $tick.cast("f32").alias("f"),
+            $day.cast("date").alias("d"),
+            $tick.cast("datetime[ms]").alias("dt"),
+            $tick.cast("duration[ns]").alias("dur"),
+            $label.cast("categorical").alias("cat")
+        )"#,
+        &ctx,
+    );
+    let dtypes: Vec<String> = result.dtypes().iter().map(|d| d.to_string()).collect();
+    assert_eq!(
+        dtypes,
+        ["u32", "f32", "date", "datetime[ms]", "duration[ns]", "cat"]
+    );
+}
+
+#[test]
+fn cast_to_enum_and_join_categoricals() {
+    let trades = df! {
+        "trader" => &["t1", "t2", "t1"],
+        "side" => &["buy", "sell", "buy"],
+    }
+    .unwrap()
+    .lazy();
+    let traders = df! {
+        "trader" => &["t1", "t2"],
+        "desk" => &["a", "b"],
+    }
+    .unwrap()
+    .lazy();
+    let ctx = EvalContext::new()
+        .with_string_cache(true)
+        .with_df("trades", trades)
+        .with_df("traders", traders);
+    assert!(ctx.string_cache && piql::using_string_cache());
+
+    let result = run_to_df(
+        r#"trades
+            .with_columns($trader.cast("categorical"), $side.cast("enum[buy, sell]"))
+            .join(traders.with_columns($trader.cast("cat")), on="trader")
+            .sort("desk")"#,
+        &ctx,
+    );
+    assert!(matches!(
+        result.column("side").unwrap().dtype(),
+        DataType::Enum(..)
+    ));
+    assert_eq!(result.height(), 3);
+
+    // Values outside the enum's categories fail the strict cast
+    let strict = match run(r#"trades.select($trader.cast("enum[buy, sell]"))"#, &ctx).unwrap() {
+        Value::DataFrame(lf, _) => lf.collect(),
+        _ => panic!("Expected DataFrame"),
+    };
+    assert!(strict.is_err());
+}
+
+#[test]
+fn cast_is_strict_unless_disabled() {
+    let df = df! { "s" => &["1", "x"] }.unwrap().lazy();
+    let ctx = EvalContext::new().with_df("df", df);
+
+    let strict = match run(r#"df.select($s.cast("i32"))"#, &ctx).unwrap() {
+        Value::DataFrame(lf, _) => lf.collect(),
+        _ => panic!("Expected DataFrame"),
+    };
+    assert!(strict.is_err());
+
+    let lenient = run_to_df(r#"df.select($s.cast("i32", strict=False))"#, &ctx);
+    let s = lenient.column("s").unwrap().i32().unwrap();
+    assert_eq!(s.get(0), Some(1));
+    assert_eq!(s.get(1), None);
+
+    let err = run(r#"df.select($s.cast("decimal"))"#, &ctx)
+        .err()
+        .expect("unknown dtype should fail");
+    assert!(err.to_string().contains("Unknown type for cast"), "{err}");
+}
+
+// ============ unique / n_unique ============
+
+#[test]
+fn unique_values() {
     let ctx = setup_test_df();
     let df = run_to_df(r#"entities.select(pl.col("type").unique())"#, &ctx);
     assert_eq!(df.height(), 2); // merchant, producer
@@ -726,6 +1314,72 @@ fn explode_list() {
     assert_eq!(result.height(), 3); // 2 tags for id=1, 1 tag for id=2
 }
 
+// ============ struct namespace / unnest ============
+
+fn setup_struct_df() -> EvalContext {
+    let pos = df! {
+        "x" => &[1, 2, 3],
+        "y" => &[10, 20, 30],
+    }
+    .unwrap()
+    .into_struct("pos".into())
+    .into_series();
+    let df = DataFrame::new(vec![
+        Series::new("id".into(), &[1, 2, 3]).into(),
+        pos.into(),
+    ])
+    .unwrap()
+    .lazy();
+
+    EvalContext::new().with_df("df", df)
+}
+
+#[test]
+fn struct_field() {
+    let ctx = setup_struct_df();
+    let df = run_to_df(r#"df.select(pl.col("pos").struct.field("y"))"#, &ctx);
+    assert_eq!(df.get_column_names(), &["y"]);
+    let y: Vec<i32> = df
+        .column("y")
+        .unwrap()
+        .i32()
+        .unwrap()
+        .into_no_null_iter()
+        .collect();
+    assert_eq!(y, vec![10, 20, 30]);
+}
+
+#[test]
+fn struct_field_in_filter() {
+    let ctx = setup_struct_df();
+    let df = run_to_df(r#"df.filter($pos.struct.field("x") > 1)"#, &ctx);
+    assert_eq!(df.height(), 2);
+}
+
+#[test]
+fn struct_unnest_expr() {
+    let ctx = setup_struct_df();
+    let df = run_to_df(
+        r#"df.select(pl.col("id"), pl.col("pos").struct.unnest())"#,
+        &ctx,
+    );
+    assert_eq!(df.get_column_names(), &["id", "x", "y"]);
+}
+
+#[test]
+fn struct_unknown_method() {
+    let ctx = setup_struct_df();
+    assert!(run(r#"df.select(pl.col("pos").struct.bogus())"#, &ctx).is_err());
+}
+
+#[test]
+fn unnest_dataframe() {
+    let ctx = setup_struct_df();
+    let df = run_to_df(r#"df.unnest("pos")"#, &ctx);
+    assert_eq!(df.get_column_names(), &["id", "x", "y"]);
+    assert_eq!(df.height(), 3);
+}
+
 // ============ Edge cases / regression tests ============
 
 #[test]
@@ -887,6 +1541,74 @@ fn expr_round() {
     assert!((rounded.get(1).unwrap() - 5.7).abs() < 0.01);
 }
 
+#[test]
+fn expr_pow_sqrt_log_exp() {
+    let df = df! {
+        "val" => &[1.0, 4.0, 100.0],
+    }
+    .unwrap()
+    .lazy();
+
+    let ctx = EvalContext::new().with_df("df", df);
+    let result = run_to_df(
+        r#"df.select(
+            $val.pow(2).alias("squared"),
+            $val.sqrt().alias("root"),
+            $val.log(10).alias("log10"),
+            $val.log(base=2).alias("log2"),
+            $val.log().exp().alias("roundtrip")
+        )"#,
+        &ctx,
+    );
+    let get = |name: &str, idx: usize| {
+        result
+            .column(name)
+            .unwrap()
+            .f64()
+            .unwrap()
+            .get(idx)
+            .unwrap()
+    };
+    assert!((get("squared", 1) - 16.0).abs() < 1e-9);
+    assert!((get("root", 1) - 2.0).abs() < 1e-9);
+    assert!((get("log10", 2) - 2.0).abs() < 1e-9);
+    assert!((get("log2", 1) - 2.0).abs() < 1e-9);
+    assert!((get("roundtrip", 2) - 100.0).abs() < 1e-9);
+}
+
+#[test]
+fn expr_floor_ceil_sign() {
+    let df = df! {
+        "val" => &[-1.5, 0.0, 2.25],
+    }
+    .unwrap()
+    .lazy();
+
+    let ctx = EvalContext::new().with_df("df", df);
+    let result = run_to_df(
+        r#"df.select(
+            $val.floor().alias("floor"),
+            $val.ceil().alias("ceil"),
+            $val.sign().alias("sign")
+        )"#,
+        &ctx,
+    );
+    let floats = |name: &str| -> Vec<f64> {
+        result
+            .column(name)
+            .unwrap()
+            .cast(&DataType::Float64)
+            .unwrap()
+            .f64()
+            .unwrap()
+            .into_no_null_iter()
+            .collect()
+    };
+    assert_eq!(floats("floor"), vec![-2.0, 0.0, 2.0]);
+    assert_eq!(floats("ceil"), vec![-1.0, 0.0, 3.0]);
+    assert_eq!(floats("sign"), vec![-1.0, 0.0, 1.0]);
+}
+
 #[test]
 fn expr_len() {
     let ctx = setup_test_df();
@@ -972,184 +1694,547 @@ fn str_slice() {
     assert_eq!(sliced.get(1).unwrap(), "wor");
 }
 
-// ============ DataFrame methods ============
-
-#[test]
-fn drop_nulls_df() {
+fn setup_codes_df() -> EvalContext {
     let df = df! {
-        "a" => &[Some(1), None, Some(3)],
-        "b" => &[Some("x"), Some("y"), None],
+        "code" => &["ab-12-x", "cd-345-y", "a.b"],
     }
     .unwrap()
     .lazy();
-
-    let ctx = EvalContext::new().with_df("df", df);
-    let result = run_to_df(r#"df.drop_nulls()"#, &ctx);
-    assert_eq!(result.height(), 1); // only row 0 has no nulls... wait, row 0 has all values
+    EvalContext::new().with_df("df", df)
 }
 
-// ============ Cumulative functions ============
-
 #[test]
-fn expr_cum_sum() {
-    let df = df! {
-        "val" => &[1, 2, 3, 4],
-    }
-    .unwrap()
-    .lazy();
+fn str_contains_literal() {
+    let ctx = setup_codes_df();
+    // "." is a regex wildcard unless literal=True
+    let regex = run_to_df(r#"df.filter(pl.col("code").str.contains("."))"#, &ctx);
+    assert_eq!(regex.height(), 3);
+    let literal = run_to_df(
+        r#"df.filter(pl.col("code").str.contains(".", literal=True))"#,
+        &ctx,
+    );
+    assert_eq!(literal.height(), 1);
+}
 
-    let ctx = EvalContext::new().with_df("df", df);
+#[test]
+fn str_replace_literal() {
+    let ctx = setup_codes_df();
     let result = run_to_df(
-        r#"df.with_columns(pl.col("val").cum_sum().alias("running_total"))"#,
+        r#"df.select(pl.col("code").str.replace(".", "_", literal=True))"#,
         &ctx,
     );
-    let running = result.column("running_total").unwrap().i32().unwrap();
-    assert_eq!(running.get(0).unwrap(), 1);
-    assert_eq!(running.get(1).unwrap(), 3);
-    assert_eq!(running.get(2).unwrap(), 6);
-    assert_eq!(running.get(3).unwrap(), 10);
+    let code = result.column("code").unwrap().str().unwrap();
+    assert_eq!(code.get(0).unwrap(), "ab-12-x");
+    assert_eq!(code.get(2).unwrap(), "a_b");
 }
 
 #[test]
-fn expr_cum_max() {
-    let df = df! {
-        "val" => &[1, 3, 2, 5, 4],
-    }
-    .unwrap()
-    .lazy();
-
-    let ctx = EvalContext::new().with_df("df", df);
+fn str_extract() {
+    let ctx = setup_codes_df();
     let result = run_to_df(
-        r#"df.with_columns(pl.col("val").cum_max().alias("running_max"))"#,
+        r#"df.select(pl.col("code").str.extract("([a-z]+)-([0-9]+)", 2))"#,
         &ctx,
     );
-    let running = result.column("running_max").unwrap().i32().unwrap();
-    assert_eq!(running.get(0).unwrap(), 1);
-    assert_eq!(running.get(1).unwrap(), 3);
-    assert_eq!(running.get(2).unwrap(), 3);
-    assert_eq!(running.get(3).unwrap(), 5);
+    let code = result.column("code").unwrap().str().unwrap();
+    assert_eq!(code.get(0).unwrap(), "12");
+    assert_eq!(code.get(1).unwrap(), "345");
+    assert_eq!(code.get(2), None);
 }
 
 #[test]
-fn expr_cum_min() {
-    let df = df! {
-        "val" => &[5, 3, 4, 1, 2],
-    }
-    .unwrap()
-    .lazy();
-
-    let ctx = EvalContext::new().with_df("df", df);
+fn str_extract_default_group() {
+    let ctx = setup_codes_df();
     let result = run_to_df(
-        r#"df.with_columns(pl.col("val").cum_min().alias("running_min"))"#,
+        r#"df.select(pl.col("code").str.extract("-([0-9]+)-"))"#,
         &ctx,
     );
-    let running = result.column("running_min").unwrap().i32().unwrap();
-    assert_eq!(running.get(0).unwrap(), 5);
-    assert_eq!(running.get(1).unwrap(), 3);
-    assert_eq!(running.get(2).unwrap(), 3);
-    assert_eq!(running.get(3).unwrap(), 1);
+    let code = result.column("code").unwrap().str().unwrap();
+    assert_eq!(code.get(0).unwrap(), "12");
 }
 
-// ============ Rank, clip, reverse ============
+#[test]
+fn str_extract_group_keyword() {
+    let ctx = setup_codes_df();
+    let result = run_to_df(
+        r#"df.select(pl.col("code").str.extract("([a-z]+)-([0-9]+)", group=1))"#,
+        &ctx,
+    );
+    let code = result.column("code").unwrap().str().unwrap();
+    assert_eq!(code.get(0).unwrap(), "ab");
+    assert_eq!(code.get(1).unwrap(), "cd");
+}
 
 #[test]
-fn expr_rank() {
-    let df = df! {
-        "val" => &[10, 30, 20, 40],
-    }
-    .unwrap()
-    .lazy();
+fn str_extract_all_and_count_matches() {
+    let ctx = setup_codes_df();
+    let result = run_to_df(
+        r#"df.select(
+            pl.col("code").str.extract_all("[a-z]").alias("letters"),
+            pl.col("code").str.count_matches("[a-z]").alias("n")
+        )"#,
+        &ctx,
+    );
+    let n = result.column("n").unwrap().u32().unwrap();
+    assert_eq!(n.get(0).unwrap(), 3);
+    assert_eq!(n.get(2).unwrap(), 2);
+    let letters = result.column("letters").unwrap().list().unwrap();
+    assert_eq!(letters.get_as_series(1).unwrap().len(), 3);
+}
 
-    let ctx = EvalContext::new().with_df("df", df);
+#[test]
+fn str_split() {
+    let ctx = setup_codes_df();
     let result = run_to_df(
-        r#"df.with_columns(pl.col("val").rank().alias("ranked"))"#,
+        r#"df.select(pl.col("code").str.split("-").alias("parts")).explode("parts")"#,
         &ctx,
     );
-    let ranked = result.column("ranked").unwrap().u32().unwrap();
-    assert_eq!(ranked.get(0).unwrap(), 1); // 10 is rank 1
-    assert_eq!(ranked.get(1).unwrap(), 3); // 30 is rank 3
-    assert_eq!(ranked.get(2).unwrap(), 2); // 20 is rank 2
-    assert_eq!(ranked.get(3).unwrap(), 4); // 40 is rank 4
+    assert_eq!(result.height(), 7);
 }
 
 #[test]
-fn expr_clip() {
+fn str_strip_chars() {
     let df = df! {
-        "val" => &[1, 5, 10, 15, 20],
+        "text" => &["  padded  ", "--dashes--"],
     }
     .unwrap()
     .lazy();
-
     let ctx = EvalContext::new().with_df("df", df);
+
     let result = run_to_df(
-        r#"df.with_columns(pl.col("val").clip(5, 15).alias("clipped"))"#,
+        r#"df.select(
+            pl.col("text").str.strip_chars().alias("ws"),
+            pl.col("text").str.strip_chars("-").alias("dash")
+        )"#,
         &ctx,
     );
-    let clipped = result.column("clipped").unwrap().i32().unwrap();
-    assert_eq!(clipped.get(0).unwrap(), 5); // 1 clipped to 5
-    assert_eq!(clipped.get(1).unwrap(), 5); // 5 stays
-    assert_eq!(clipped.get(2).unwrap(), 10); // 10 stays
-    assert_eq!(clipped.get(3).unwrap(), 15); // 15 stays
-    assert_eq!(clipped.get(4).unwrap(), 15); // 20 clipped to 15
+    assert_eq!(
+        result.column("ws").unwrap().str().unwrap().get(0),
+        Some("padded")
+    );
+    assert_eq!(
+        result.column("dash").unwrap().str().unwrap().get(1),
+        Some("dashes")
+    );
 }
 
 #[test]
-fn expr_reverse() {
+fn str_zfill_and_pad_start() {
     let df = df! {
-        "val" => &[1, 2, 3, 4],
+        "id" => &["7", "42"],
     }
     .unwrap()
     .lazy();
-
     let ctx = EvalContext::new().with_df("df", df);
+
     let result = run_to_df(
-        r#"df.with_columns(pl.col("val").reverse().alias("reversed"))"#,
+        r#"df.select(
+            pl.col("id").str.zfill(3).alias("z"),
+            pl.col("id").str.pad_start(4).alias("p"),
+            pl.col("id").str.pad_start(4, "*").alias("star")
+        )"#,
         &ctx,
     );
-    let reversed = result.column("reversed").unwrap().i32().unwrap();
-    assert_eq!(reversed.get(0).unwrap(), 4);
-    assert_eq!(reversed.get(1).unwrap(), 3);
-    assert_eq!(reversed.get(2).unwrap(), 2);
-    assert_eq!(reversed.get(3).unwrap(), 1);
+    assert_eq!(
+        result.column("z").unwrap().str().unwrap().get(0),
+        Some("007")
+    );
+    assert_eq!(
+        result.column("p").unwrap().str().unwrap().get(1),
+        Some("  42")
+    );
+    assert_eq!(
+        result.column("star").unwrap().str().unwrap().get(0),
+        Some("***7")
+    );
 }
 
 #[test]
-fn df_reverse() {
+fn str_pad_start_rejects_multi_char_fill() {
+    let ctx = setup_codes_df();
+    assert!(run(r#"df.select(pl.col("code").str.pad_start(4, "ab"))"#, &ctx).is_err());
+}
+
+// ============ Datetime parsing / arithmetic ============
+
+fn setup_log_df() -> EvalContext {
     let df = df! {
-        "val" => &[1, 2, 3],
+        "ts" => &["2024-03-01 10:15:00", "2024-03-01 10:45:30", "2024-03-02 23:59:59"],
+        "day" => &["01/03/2024", "02/03/2024", "03/03/2024"],
     }
     .unwrap()
     .lazy();
-
-    let ctx = EvalContext::new().with_df("df", df);
-    let result = run_to_df(r#"df.reverse()"#, &ctx);
-    let val = result.column("val").unwrap().i32().unwrap();
-    assert_eq!(val.get(0).unwrap(), 3);
-    assert_eq!(val.get(1).unwrap(), 2);
-    assert_eq!(val.get(2).unwrap(), 1);
+    EvalContext::new().with_df("logs", df)
 }
 
-// ============ Sugar: $col ============
+#[test]
+fn str_to_datetime_with_format() {
+    let ctx = setup_log_df();
+    let df = run_to_df(
+        r#"logs.select(pl.col("ts").str.to_datetime("%Y-%m-%d %H:%M:%S").dt.hour().alias("h"))"#,
+        &ctx,
+    );
+    let h = df.column("h").unwrap().i8().unwrap();
+    assert_eq!(h.get(0), Some(10));
+    assert_eq!(h.get(2), Some(23));
+}
 
 #[test]
-fn sugar_col_shorthand() {
-    let ctx = setup_test_df();
-    // $gold should expand to pl.col("gold")
-    let df = run_to_df(r#"entities.filter($gold > 100)"#, &ctx);
-    assert_eq!(df.height(), 1);
-    assert_eq!(
-        df.column("name").unwrap().str().unwrap().get(0).unwrap(),
-        "bob"
+fn str_strptime_date() {
+    let ctx = setup_log_df();
+    let df = run_to_df(
+        r#"logs.select(pl.col("day").str.strptime("date", "%d/%m/%Y").dt.day().alias("d"))"#,
+        &ctx,
     );
+    let d = df.column("d").unwrap().i8().unwrap();
+    assert_eq!(d.get(1), Some(2));
 }
 
 #[test]
-fn sugar_col_shorthand_in_select() {
-    let ctx = setup_test_df();
-    let df = run_to_df(r#"entities.select($name, $gold)"#, &ctx);
-    assert_eq!(df.width(), 2);
-    assert!(df.column("name").is_ok());
-    assert!(df.column("gold").is_ok());
+fn str_strptime_rejects_unknown_dtype() {
+    let ctx = setup_log_df();
+    assert!(
+        run(
+            r#"logs.select(pl.col("ts").str.strptime("time", "%H"))"#,
+            &ctx
+        )
+        .is_err()
+    );
+}
+
+#[test]
+fn dt_truncate_buckets() {
+    let ctx = setup_log_df();
+    let df = run_to_df(
+        r#"logs
+            .with_columns(pl.col("ts").str.to_datetime("%Y-%m-%d %H:%M:%S").dt.truncate("1h").alias("bucket"))
+            .group_by("bucket")
+            .agg(pl.len().alias("n"))"#,
+        &ctx,
+    );
+    assert_eq!(df.height(), 2);
+}
+
+#[test]
+fn dt_offset_by_and_date() {
+    let ctx = setup_log_df();
+    let df = run_to_df(
+        r#"logs.select(
+            pl.col("ts").str.to_datetime("%Y-%m-%d %H:%M:%S").dt.offset_by("-3d").dt.date().alias("d")
+        )"#,
+        &ctx,
+    );
+    let d = df.column("d").unwrap().cast(&DataType::String).unwrap();
+    assert_eq!(d.str().unwrap().get(0), Some("2024-02-27"));
+}
+
+#[test]
+fn dt_weekday_and_timestamp() {
+    let ctx = setup_log_df();
+    let df = run_to_df(
+        r#"logs.select(
+            pl.col("ts").str.to_datetime("%Y-%m-%d %H:%M:%S").dt.weekday().alias("wd"),
+            pl.col("ts").str.to_datetime("%Y-%m-%d %H:%M:%S").dt.timestamp("ms").alias("ms")
+        )"#,
+        &ctx,
+    );
+    // 2024-03-01 was a Friday
+    assert_eq!(df.column("wd").unwrap().i8().unwrap().get(0), Some(5));
+    assert_eq!(
+        df.column("ms").unwrap().i64().unwrap().get(0),
+        Some(1_709_288_100_000)
+    );
+}
+
+#[test]
+fn dt_timestamp_rejects_unknown_unit() {
+    let ctx = setup_log_df();
+    assert!(
+        run(
+            r#"logs.select(pl.col("ts").str.to_datetime().dt.timestamp("s"))"#,
+            &ctx
+        )
+        .is_err()
+    );
+}
+
+// ============ DataFrame methods ============
+
+#[test]
+fn drop_nulls_df() {
+    let df = df! {
+        "a" => &[Some(1), None, Some(3)],
+        "b" => &[Some("x"), Some("y"), None],
+    }
+    .unwrap()
+    .lazy();
+
+    let ctx = EvalContext::new().with_df("df", df);
+    let result = run_to_df(r#"df.drop_nulls()"#, &ctx);
+    assert_eq!(result.height(), 1); // only row 0 has no nulls... wait, row 0 has all values
+}
+
+#[test]
+fn sample_rows_stay_aligned() {
+    let df = df! {
+        "a" => (0..100i64).collect::<Vec<_>>(),
+        "b" => (0..100i64).map(|i| i * 10).collect::<Vec<_>>(),
+    }
+    .unwrap()
+    .lazy();
+    let ctx = EvalContext::new().with_df("df", df);
+
+    let sampled = run_to_df("df.sample(n=10, seed=7)", &ctx);
+    assert_eq!(sampled.height(), 10);
+    let a = sampled.column("a").unwrap().i64().unwrap();
+    let b = sampled.column("b").unwrap().i64().unwrap();
+    for (a, b) in a.into_no_null_iter().zip(b.into_no_null_iter()) {
+        assert_eq!(b, a * 10);
+    }
+
+    // Same seed, same rows
+    let again = run_to_df("df.sample(10, seed=7)", &ctx);
+    assert!(sampled.equals(&again));
+
+    let fraction = run_to_df("df.sample(fraction=0.25, seed=1)", &ctx);
+    assert_eq!(fraction.height(), 25);
+
+    let err = run("df.sample(n=1, fraction=0.5)", &ctx)
+        .err()
+        .expect("n and fraction together should fail");
+    assert!(err.to_string().contains("not both"), "{err}");
+}
+
+#[test]
+fn shuffle_keeps_every_row() {
+    let ctx = setup_test_df();
+    let shuffled = run_to_df("entities.shuffle(3)", &ctx);
+    assert_eq!(shuffled.height(), 3);
+    let mut names: Vec<&str> = shuffled
+        .column("name")
+        .unwrap()
+        .str()
+        .unwrap()
+        .into_no_null_iter()
+        .collect();
+    names.sort();
+    assert_eq!(names, vec!["alice", "bob", "charlie"]);
+    assert!(shuffled.equals(&run_to_df("entities.shuffle(seed=3)", &ctx)));
+}
+
+// ============ Cumulative functions ============
+
+#[test]
+fn expr_cum_sum() {
+    let df = df! {
+        "val" => &[1, 2, 3, 4],
+    }
+    .unwrap()
+    .lazy();
+
+    let ctx = EvalContext::new().with_df("df", df);
+    let result = run_to_df(
+        r#"df.with_columns(pl.col("val").cum_sum().alias("running_total"))"#,
+        &ctx,
+    );
+    let running = result.column("running_total").unwrap().i32().unwrap();
+    assert_eq!(running.get(0).unwrap(), 1);
+    assert_eq!(running.get(1).unwrap(), 3);
+    assert_eq!(running.get(2).unwrap(), 6);
+    assert_eq!(running.get(3).unwrap(), 10);
+}
+
+#[test]
+fn expr_cum_max() {
+    let df = df! {
+        "val" => &[1, 3, 2, 5, 4],
+    }
+    .unwrap()
+    .lazy();
+
+    let ctx = EvalContext::new().with_df("df", df);
+    let result = run_to_df(
+        r#"df.with_columns(pl.col("val").cum_max().alias("running_max"))"#,
+        &ctx,
+    );
+    let running = result.column("running_max").unwrap().i32().unwrap();
+    assert_eq!(running.get(0).unwrap(), 1);
+    assert_eq!(running.get(1).unwrap(), 3);
+    assert_eq!(running.get(2).unwrap(), 3);
+    assert_eq!(running.get(3).unwrap(), 5);
+}
+
+#[test]
+fn expr_cum_min() {
+    let df = df! {
+        "val" => &[5, 3, 4, 1, 2],
+    }
+    .unwrap()
+    .lazy();
+
+    let ctx = EvalContext::new().with_df("df", df);
+    let result = run_to_df(
+        r#"df.with_columns(pl.col("val").cum_min().alias("running_min"))"#,
+        &ctx,
+    );
+    let running = result.column("running_min").unwrap().i32().unwrap();
+    assert_eq!(running.get(0).unwrap(), 5);
+    assert_eq!(running.get(1).unwrap(), 3);
+    assert_eq!(running.get(2).unwrap(), 3);
+    assert_eq!(running.get(3).unwrap(), 1);
+}
+
+// ============ Rank, clip, reverse ============
+
+#[test]
+fn expr_rank() {
+    let df = df! {
+        "val" => &[10, 30, 20, 40],
+    }
+    .unwrap()
+    .lazy();
+
+    let ctx = EvalContext::new().with_df("df", df);
+    let result = run_to_df(
+        r#"df.with_columns(pl.col("val").rank().alias("ranked"))"#,
+        &ctx,
+    );
+    let ranked = result.column("ranked").unwrap().u32().unwrap();
+    assert_eq!(ranked.get(0).unwrap(), 1); // 10 is rank 1
+    assert_eq!(ranked.get(1).unwrap(), 3); // 30 is rank 3
+    assert_eq!(ranked.get(2).unwrap(), 2); // 20 is rank 2
+    assert_eq!(ranked.get(3).unwrap(), 4); // 40 is rank 4
+}
+
+#[test]
+fn expr_clip() {
+    let df = df! {
+        "val" => &[1, 5, 10, 15, 20],
+    }
+    .unwrap()
+    .lazy();
+
+    let ctx = EvalContext::new().with_df("df", df);
+    let result = run_to_df(
+        r#"df.with_columns(pl.col("val").clip(5, 15).alias("clipped"))"#,
+        &ctx,
+    );
+    let clipped = result.column("clipped").unwrap().i32().unwrap();
+    assert_eq!(clipped.get(0).unwrap(), 5); // 1 clipped to 5
+    assert_eq!(clipped.get(1).unwrap(), 5); // 5 stays
+    assert_eq!(clipped.get(2).unwrap(), 10); // 10 stays
+    assert_eq!(clipped.get(3).unwrap(), 15); // 15 stays
+    assert_eq!(clipped.get(4).unwrap(), 15); // 20 clipped to 15
+}
+
+#[test]
+fn expr_reverse() {
+    let df = df! {
+        "val" => &[1, 2, 3, 4],
+    }
+    .unwrap()
+    .lazy();
+
+    let ctx = EvalContext::new().with_df("df", df);
+    let result = run_to_df(
+        r#"df.with_columns(pl.col("val").reverse().alias("reversed"))"#,
+        &ctx,
+    );
+    let reversed = result.column("reversed").unwrap().i32().unwrap();
+    assert_eq!(reversed.get(0).unwrap(), 4);
+    assert_eq!(reversed.get(1).unwrap(), 3);
+    assert_eq!(reversed.get(2).unwrap(), 2);
+    assert_eq!(reversed.get(3).unwrap(), 1);
+}
+
+#[test]
+fn df_reverse() {
+    let df = df! {
+        "val" => &[1, 2, 3],
+    }
+    .unwrap()
+    .lazy();
+
+    let ctx = EvalContext::new().with_df("df", df);
+    let result = run_to_df(r#"df.reverse()"#, &ctx);
+    let val = result.column("val").unwrap().i32().unwrap();
+    assert_eq!(val.get(0).unwrap(), 3);
+    assert_eq!(val.get(1).unwrap(), 2);
+    assert_eq!(val.get(2).unwrap(), 1);
+}
+
+// ============ div_or / nan_to_null ============
+
+fn setup_ratio_df() -> EvalContext {
+    let df = df! {
+        "gold" => &[Some(100.0), Some(50.0), Some(0.0), Some(30.0)],
+        "trades" => &[Some(4.0), Some(0.0), Some(0.0), None],
+    }
+    .unwrap()
+    .lazy();
+    EvalContext::new().with_df("df", df)
+}
+
+#[test]
+fn div_or_replaces_zero_denominators() {
+    let ctx = setup_ratio_df();
+    let df = run_to_df(r#"df.select($gold.div_or($trades, 0.0))"#, &ctx);
+    assert_eq!(df.get_column_names(), &["gold"]);
+    let ratio: Vec<Option<f64>> = df
+        .column("gold")
+        .unwrap()
+        .f64()
+        .unwrap()
+        .into_iter()
+        .collect();
+    assert_eq!(ratio, vec![Some(25.0), Some(0.0), Some(0.0), Some(0.0)]);
+}
+
+#[test]
+fn div_or_with_null_default() {
+    let ctx = setup_ratio_df();
+    let df = run_to_df(
+        r#"df.select($gold.div_or($trades, None).alias("per_trade"))"#,
+        &ctx,
+    );
+    assert_eq!(df.column("per_trade").unwrap().null_count(), 3);
+}
+
+#[test]
+fn nan_to_null() {
+    let ctx = setup_ratio_df();
+    let df = run_to_df(
+        r#"df.select(($gold / $trades).nan_to_null().alias("ratio"))"#,
+        &ctx,
+    );
+    let ratio = df.column("ratio").unwrap().f64().unwrap();
+    assert_eq!(ratio.get(0), Some(25.0));
+    assert!(ratio.get(1).unwrap().is_infinite());
+    // 0.0 / 0.0 is NaN -> null; null denominator stays null
+    assert_eq!(ratio.get(2), None);
+    assert_eq!(ratio.get(3), None);
+}
+
+// ============ Sugar: $col ============
+
+#[test]
+fn sugar_col_shorthand() {
+    let ctx = setup_test_df();
+    // $gold should expand to pl.col("gold")
+    let df = run_to_df(r#"entities.filter($gold > 100)"#, &ctx);
+    assert_eq!(df.height(), 1);
+    assert_eq!(
+        df.column("name").unwrap().str().unwrap().get(0).unwrap(),
+        "bob"
+    );
+}
+
+#[test]
+fn sugar_col_shorthand_in_select() {
+    let ctx = setup_test_df();
+    let df = run_to_df(r#"entities.select($name, $gold)"#, &ctx);
+    assert_eq!(df.width(), 2);
+    assert!(df.column("name").is_ok());
+    assert!(df.column("gold").is_ok());
 }
 
 #[test]
@@ -1215,6 +2300,180 @@ fn sugar_col_delta_without_partition_is_unpartitioned() {
     assert_eq!(changes.get(4).unwrap(), 50);
 }
 
+#[test]
+fn sugar_col_pct_defaults_to_one_period() {
+    let df = df! {
+        "entity_id" => &[1, 1, 1, 2, 2],
+        "gold" => &[100.0, 150.0, 120.0, 200.0, 250.0],
+    }
+    .unwrap()
+    .lazy();
+
+    let ctx = EvalContext::new()
+        .with_df("entities", df)
+        .with_default_partition_key("entity_id");
+    let result = run_to_df(r#"entities.with_columns($gold.pct.alias("p"))"#, &ctx);
+
+    let pct = result.column("p").unwrap().f64().unwrap();
+    assert!(pct.get(0).is_none());
+    assert_eq!(pct.get(1).unwrap(), 0.5);
+    assert_eq!(pct.get(2).unwrap(), -0.2);
+    assert!(pct.get(3).is_none());
+    assert_eq!(pct.get(4).unwrap(), 0.25);
+}
+
+#[test]
+fn sugar_col_zscore() {
+    let df = df! {
+        "entity_id" => &[1, 1, 1, 2, 2, 2],
+        "gold" => &[1.0, 2.0, 3.0, 10.0, 20.0, 30.0],
+    }
+    .unwrap()
+    .lazy();
+
+    // Partitioned: each entity is standardized on its own
+    let ctx = EvalContext::new()
+        .with_df("entities", df.clone())
+        .with_default_partition_key("entity_id");
+    let result = run_to_df(r#"entities.with_columns($gold.zscore.alias("z"))"#, &ctx);
+    let z: Vec<f64> = result
+        .column("z")
+        .unwrap()
+        .f64()
+        .unwrap()
+        .into_no_null_iter()
+        .collect();
+    assert_eq!(z, vec![-1.0, 0.0, 1.0, -1.0, 0.0, 1.0]);
+
+    // Unpartitioned: over the whole frame
+    let ctx = EvalContext::new().with_df("entities", df);
+    let result = run_to_df(r#"entities.with_columns($gold.zscore.alias("z"))"#, &ctx);
+    let z = result.column("z").unwrap().f64().unwrap();
+    assert!(z.get(0).unwrap() < z.get(2).unwrap());
+    // Mean of the whole frame is 11
+    assert!(z.get(3).unwrap() < 0.0 && z.get(4).unwrap() > 0.0);
+}
+
+#[test]
+fn sugar_col_rate_divides_by_tick_spacing() {
+    let df = df! {
+        "entity_id" => &[1, 1, 1, 2, 2],
+        "tick" => &[1, 3, 7, 1, 2],
+        "gold" => &[100, 120, 100, 200, 250],
+    }
+    .unwrap()
+    .lazy();
+
+    let ctx = EvalContext::new()
+        .with_df("entities", df.clone())
+        .with_default_partition_key("entity_id")
+        .with_default_tick_column("tick");
+    let result = run_to_df(r#"entities.with_columns($gold.rate.alias("r"))"#, &ctx);
+
+    let rate = result.column("r").unwrap().f64().unwrap();
+    assert!(rate.get(0).is_none());
+    assert_eq!(rate.get(1).unwrap(), 10.0); // 20 over 2 ticks
+    assert_eq!(rate.get(2).unwrap(), -5.0); // -20 over 4 ticks
+    assert!(rate.get(3).is_none());
+    assert_eq!(rate.get(4).unwrap(), 50.0);
+
+    // Without a tick column the sugar reports an error
+    let ctx = EvalContext::new().with_df("entities", df);
+    let err = run(r#"entities.with_columns($gold.rate)"#, &ctx)
+        .err()
+        .expect("rate without a tick column should fail");
+    assert!(err.to_string().contains("tick column"), "{err}");
+}
+
+#[test]
+fn sugar_by_run_partitions_over_run_label() {
+    let df = df! {
+        "_run" => &["r1", "r1", "r2"],
+        "gold" => &[10.0, 30.0, 100.0],
+    }
+    .unwrap()
+    .lazy();
+    let ctx = EvalContext::new().with_df("_all::entities", df);
+
+    // Bare attribute and call forms both expand to `.over("_run")`
+    for query in [
+        r#"_all::entities.with_columns($gold.mean.by_run.alias("m"))"#,
+        r#"_all::entities.with_columns($gold.mean().by_run().alias("m"))"#,
+    ] {
+        let result = run_to_df(query, &ctx);
+        let mean: Vec<f64> = result
+            .column("m")
+            .unwrap()
+            .f64()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        assert_eq!(mean, vec![20.0, 20.0, 100.0], "{query}");
+    }
+
+    let result = run_to_df(
+        r#"_all::entities.by_run().agg($gold.sum()).sort("_run")"#,
+        &ctx,
+    );
+    let total: Vec<f64> = result
+        .column("gold")
+        .unwrap()
+        .f64()
+        .unwrap()
+        .into_no_null_iter()
+        .collect();
+    assert_eq!(total, vec![40.0, 100.0]);
+}
+
+#[test]
+fn sugar_by_run_uses_configured_label_column() {
+    let df = df! {
+        "run" => &["a", "b", "b"],
+        "gold" => &[1, 2, 4],
+    }
+    .unwrap()
+    .lazy();
+    let ctx = EvalContext::new()
+        .with_df("_all::entities", df)
+        .with_run_label_column("run");
+    let result = run_to_df(
+        r#"_all::entities.with_columns($gold.sum.by_run.alias("s"))"#,
+        &ctx,
+    );
+    let sums: Vec<i32> = result
+        .column("s")
+        .unwrap()
+        .i32()
+        .unwrap()
+        .into_no_null_iter()
+        .collect();
+    assert_eq!(sums, vec![1, 6, 6]);
+}
+
+#[test]
+fn expr_std_var() {
+    let df = df! { "x" => &[1.0, 2.0, 3.0, 4.0] }.unwrap().lazy();
+    let ctx = EvalContext::new().with_df("t", df);
+    let result = run_to_df(
+        r#"t.select($x.var().alias("v"), $x.var(ddof=0).alias("v0"), $x.std().alias("s"))"#,
+        &ctx,
+    );
+    let get = |c: &str| result.column(c).unwrap().f64().unwrap().get(0).unwrap();
+    assert!((get("v") - 5.0 / 3.0).abs() < 1e-12);
+    assert_eq!(get("v0"), 1.25);
+    assert!((get("s") - (5.0f64 / 3.0).sqrt()).abs() < 1e-12);
+
+    for query in ["t.select($x.std(ddof=-1))", "t.select($x.var(ddof=256))"] {
+        assert!(matches!(
+            run(query, &ctx),
+            Err(PiqlError::EvalWithQuery {
+                source: EvalError::ArgError(_),
+                ..
+            })
+        ));
+    }
+}
+
 // ============ Scope Methods ============
 
 #[test]
@@ -1302,59 +2561,229 @@ fn scope_at_without_tick_config_errors() {
     }
     .unwrap()
     .lazy();
-
-    let ctx = EvalContext::new().with_df("data", df);
-    match run(r#"data.at(2)"#, &ctx) {
-        Ok(_) => panic!("expected missing tick-column configuration error"),
-        Err(err) => assert!(
-            err.to_string()
-                .contains("requires tick column configuration"),
-            "unexpected error: {err}"
-        ),
+
+    let ctx = EvalContext::new().with_df("data", df);
+    match run(r#"data.at(2)"#, &ctx) {
+        Ok(_) => panic!("expected missing tick-column configuration error"),
+        Err(err) => assert!(
+            err.to_string()
+                .contains("requires tick column configuration"),
+            "unexpected error: {err}"
+        ),
+    }
+}
+
+fn joined_time_series_ctx(right_tick: &str) -> EvalContext {
+    let left = df! {
+        "id" => &[1, 2, 1, 2],
+        "tick" => &[1, 1, 2, 2],
+        "value_l" => &[10, 20, 11, 21],
+    }
+    .unwrap()
+    .lazy();
+    let right = df! {
+        "id" => &[1, 2],
+        right_tick => &[1, 1],
+        "value_r" => &[100, 200],
+    }
+    .unwrap()
+    .lazy();
+    let dims = df! {
+        "id" => &[1, 2],
+        "name" => &["alice", "bob"],
+    }
+    .unwrap()
+    .lazy();
+
+    EvalContext::new()
+        .with_time_series_df("left", left, TimeSeriesConfig::new("tick", "id"))
+        .with_time_series_df("right", right, TimeSeriesConfig::new(right_tick, "id"))
+        .with_df("dims", dims)
+}
+
+#[test]
+fn scope_on_time_series_joined_to_static_table() {
+    let ctx = joined_time_series_ctx("tick");
+
+    let df = run_to_df(r#"left.join(dims, on="id", how="left").at(2)"#, &ctx);
+    assert_eq!(df.height(), 2);
+    assert!(df.column("name").is_ok());
+
+    // Static side first: the time-series side still decides the tick column
+    let df = run_to_df(r#"dims.join(left, on="id").since(1)"#, &ctx);
+    assert_eq!(df.height(), 4);
+}
+
+#[test]
+fn scope_on_joined_time_series_with_shared_tick_column() {
+    let ctx = joined_time_series_ctx("tick");
+    let df = run_to_df(
+        r#"left.join(right, on="id").join(dims, on="id").at(1)"#,
+        &ctx,
+    );
+    assert_eq!(df.height(), 2);
+}
+
+#[test]
+fn scope_on_joined_time_series_with_conflicting_tick_columns_fails() {
+    let ctx = joined_time_series_ctx("t");
+
+    match run(r#"left.join(right, on="id").at(1)"#, &ctx) {
+        Ok(_) => panic!("expected conflicting tick column error"),
+        Err(err) => assert!(
+            err.to_string().contains("disagree"),
+            "unexpected error: {err}"
+        ),
+    }
+    // Scoping before the join is unaffected
+    assert_eq!(
+        run_to_df(r#"left.at(1).join(right, on="id")"#, &ctx).height(),
+        2
+    );
+}
+
+#[test]
+fn scope_tick_column_override_bypasses_lineage() {
+    let ctx = joined_time_series_ctx("t");
+
+    let df = run_to_df(r#"left.join(right, on="id").at(1, tick_col="tick")"#, &ctx);
+    assert_eq!(df.height(), 2);
+    let df = run_to_df(r#"left.join(right, on="id").since(1, on="t")"#, &ctx);
+    assert_eq!(df.height(), 4);
+
+    // Static tables have no config, but an explicit column is enough
+    let df = run_to_df(r#"dims.at(2, tick_col="id")"#, &ctx);
+    assert_eq!(df.height(), 1);
+
+    match run(r#"left.at(1, tick_col="step")"#, &ctx) {
+        Ok(_) => panic!("expected missing tick column error"),
+        Err(err) => assert!(
+            err.to_string().contains("'step'"),
+            "unexpected error: {err}"
+        ),
+    }
+}
+
+#[test]
+fn result_meta_reports_sources_ticks_and_schema() {
+    let ctx = joined_time_series_ctx("tick").with_tick(10);
+    let meta = |query: &str| run_with_meta(query, &ctx).unwrap().1;
+
+    let result = meta(r#"left.window(-2, 0).join(dims, on="id")"#);
+    assert_eq!(result.sources, ["dims", "left"]);
+    assert_eq!(
+        result.ticks,
+        Some(TickRange {
+            from: Some(8),
+            to: Some(10)
+        })
+    );
+    assert!(
+        result
+            .schema
+            .contains(&("name".to_string(), "str".to_string()))
+    );
+
+    let result = meta(r#"left.at(1).join(right.at(3), on="id")"#);
+    assert_eq!(
+        result.ticks,
+        Some(TickRange {
+            from: Some(1),
+            to: Some(3)
+        })
+    );
+    let result = meta("left.since(4)");
+    assert_eq!(
+        result.ticks,
+        Some(TickRange {
+            from: Some(4),
+            to: None
+        })
+    );
+    assert_eq!(meta("dims.head(1)").ticks, None);
+}
+
+#[test]
+fn scope_latest_and_last_ticks() {
+    let history = df! {
+        "id" => &[1, 2, 1, 1, 2],
+        "tick" => &[1, 1, 2, 5, 5],
+        "value" => &[10, 20, 11, 15, 25],
+    }
+    .unwrap()
+    .lazy();
+    let sparse = df! {
+        "id" => &[1, 2, 1],
+        "tick" => &[1, 1, 3],
+        "value" => &[10, 20, 13],
     }
+    .unwrap()
+    .lazy();
+    let config = TimeSeriesConfig::new("tick", "id");
+    let ctx = EvalContext::new()
+        .with_time_series_df("history", history, config.clone())
+        .with_time_series_df("sparse", sparse, config);
+
+    // Entity 2 never reached tick 3, so its latest row is still from tick 1
+    let df = run_to_df(r#"sparse.latest().sort("id")"#, &ctx);
+    let ticks: Vec<_> = df.column("tick").unwrap().i32().unwrap().iter().collect();
+    assert_eq!(ticks, [Some(3), Some(1)]);
+
+    // Ticks 2 and 5 are the two most recent, despite the gap between them
+    let df = run_to_df(r#"history.last_ticks(2)"#, &ctx);
+    assert_eq!(df.height(), 3);
+    assert!(run(r#"history.last_ticks(0)"#, &ctx).is_err());
 }
 
 #[test]
-fn scope_on_joined_time_series_reports_ambiguous_lineage() {
-    let left = df! {
-        "id" => &[1, 2],
-        "tick" => &[1, 1],
-        "value_l" => &[10, 20],
-    }
-    .unwrap()
-    .lazy();
-    let right = df! {
-        "id" => &[1, 2],
-        "tick" => &[1, 1],
-        "value_r" => &[100, 200],
+fn resample_buckets_ticks_per_partition() {
+    let history = df! {
+        "id" => &[1, 1, 1, 1, 2, 2],
+        "tick" => &[0, 4, 10, 19, 3, 12],
+        "value" => &[1.0, 3.0, 5.0, 7.0, 10.0, 20.0],
+        "label" => &["a", "b", "c", "d", "e", "f"],
     }
     .unwrap()
     .lazy();
+    let ctx = EvalContext::new().with_time_series_df(
+        "history",
+        history,
+        TimeSeriesConfig::new("tick", "id"),
+    );
 
-    let ctx = EvalContext::new()
-        .with_time_series_df(
-            "left",
-            left,
-            TimeSeriesConfig {
-                tick_column: "tick".into(),
-                partition_key: "id".into(),
-            },
-        )
-        .with_time_series_df(
-            "right",
-            right,
-            TimeSeriesConfig {
-                tick_column: "tick".into(),
-                partition_key: "id".into(),
-            },
-        );
+    let df = run_to_df(r#"history.resample(10)"#, &ctx);
+    let ticks: Vec<_> = df.column("tick").unwrap().i32().unwrap().iter().collect();
+    assert_eq!(ticks, [Some(0), Some(10), Some(0), Some(10)]);
+    let labels: Vec<_> = df.column("label").unwrap().str().unwrap().iter().collect();
+    assert_eq!(labels, [Some("b"), Some("d"), Some("e"), Some("f")]);
 
-    match run(r#"left.join(right, on="id").at(1)"#, &ctx) {
-        Ok(_) => panic!("expected ambiguous lineage error"),
-        Err(err) => assert!(
-            err.to_string().contains("ambiguous lineage"),
-            "unexpected error: {err}"
-        ),
+    let df = run_to_df(r#"history.resample(10, agg="mean")"#, &ctx);
+    let values: Vec<_> = df.column("value").unwrap().f64().unwrap().iter().collect();
+    assert_eq!(values, [Some(2.0), Some(6.0), Some(10.0), Some(20.0)]);
+    // Non-numeric columns keep their last value
+    assert_eq!(df.column("label").unwrap().str().unwrap().get(0), Some("b"));
+
+    assert!(run(r#"history.resample(10, agg="median")"#, &ctx).is_err());
+    assert!(run(r#"history.resample(0)"#, &ctx).is_err());
+}
+
+#[test]
+fn resample_reads_base_table_history() {
+    let mut engine = QueryEngine::new();
+    engine.register_base("entities", TimeSeriesConfig::new("tick", "id"));
+    for tick in [5, 15, 25] {
+        let rows = df! { "tick" => &[tick], "id" => &[1], "gold" => &[tick * 2] }.unwrap();
+        engine.append_tick("entities", rows.lazy()).unwrap();
+    }
+    engine.on_tick(25).unwrap();
+
+    for query in ["entities.resample(10)", "entities.all().resample(10)"] {
+        let df = match engine.query(query).unwrap() {
+            Value::DataFrame(lf, _) => lf.collect().unwrap(),
+            _ => panic!("Expected DataFrame"),
+        };
+        let ticks: Vec<_> = df.column("tick").unwrap().i32().unwrap().iter().collect();
+        assert_eq!(ticks, [Some(0), Some(10), Some(20)], "{query}");
     }
 }
 
@@ -1369,6 +2798,58 @@ fn scope_top() {
     assert_eq!(gold.get(1).unwrap(), 100); // alice
 }
 
+fn setup_datetime_ticks() -> EvalContext {
+    let df = df! {
+        "ts" => &[
+            "2024-01-01 00:00:00",
+            "2024-01-01 11:50:00",
+            "2024-01-01 11:57:00",
+            "2024-01-01 12:00:00",
+        ],
+        "host" => &["a", "b", "a", "b"],
+    }
+    .unwrap()
+    .lazy()
+    .with_column(col("ts").str().to_datetime(
+        None,
+        None,
+        StrptimeOptions::default(),
+        lit("raise"),
+    ));
+
+    EvalContext::new().with_time_series_df(
+        "logs",
+        df,
+        TimeSeriesConfig::new("ts", "host").with_tick_dtype(TickDtype::Datetime),
+    )
+}
+
+#[test]
+fn scope_window_datetime_durations() {
+    let ctx = setup_datetime_ticks();
+    // Relative to the latest timestamp (12:00)
+    let result = run_to_df(r#"logs.window("-5m", "0s")"#, &ctx);
+    assert_eq!(result.height(), 2);
+    let result = run_to_df(r#"logs.window("-1h", "-1m")"#, &ctx);
+    assert_eq!(result.height(), 2);
+}
+
+#[test]
+fn scope_since_and_at_datetime() {
+    let ctx = setup_datetime_ticks();
+    let result = run_to_df(r#"logs.since("2024-01-01 11:55:00")"#, &ctx);
+    assert_eq!(result.height(), 2);
+    let result = run_to_df(r#"logs.at("2024-01-01 00:00:00")"#, &ctx);
+    assert_eq!(result.height(), 1);
+}
+
+#[test]
+fn scope_datetime_rejects_integer_args() {
+    let ctx = setup_datetime_ticks();
+    let err = run(r#"logs.since(3)"#, &ctx).err().unwrap().to_string();
+    assert!(err.contains("datetime tick column"), "{err}");
+}
+
 // ============ Custom Directives ============
 
 #[test]
@@ -1417,6 +2898,112 @@ fn custom_directive_entity_with_arg() {
     );
 }
 
+#[test]
+fn pipeline_directive_rewrites_receiver() {
+    let df = df! {
+        "entity_id" => &[1, 2, 1, 2, 1],
+        "tick" => &[1, 1, 2, 2, 3],
+        "gold" => &[10, 20, 11, 21, 12],
+    }
+    .unwrap()
+    .lazy();
+    let mut ctx = EvalContext::new().with_df("entities", df);
+
+    // df.@latest_per(keys) -> df.sort("tick").group_by(keys).agg(pl.all().last())
+    ctx.sugar
+        .register_pipeline_directive("latest_per", |df, args, _| {
+            let all_last = method_call(
+                method_call(CoreExpr::Ident("pl".into()), "all", vec![]),
+                "last",
+                vec![],
+            );
+            let sorted = method_call(df, "sort", vec![Arg::pos(lit_str("tick"))]);
+            let grouped = method_call(sorted, "group_by", args.to_vec());
+            method_call(grouped, "agg", vec![Arg::pos(all_last)])
+        });
+
+    let result = run_to_df(
+        r#"entities.filter($gold > 0).@latest_per($entity_id).sort("entity_id")"#,
+        &ctx,
+    );
+    let ticks: Vec<i32> = result
+        .column("tick")
+        .unwrap()
+        .i32()
+        .unwrap()
+        .into_no_null_iter()
+        .collect();
+    assert_eq!(ticks, [3, 2]);
+    let gold: Vec<i32> = result
+        .column("gold")
+        .unwrap()
+        .i32()
+        .unwrap()
+        .into_no_null_iter()
+        .collect();
+    assert_eq!(gold, [12, 21]);
+
+    let err = run(r#"entities.@nope"#, &ctx)
+        .err()
+        .expect("unknown pipeline directive should fail");
+    assert!(err.to_string().contains(".@nope"), "{err}");
+}
+
+#[test]
+fn user_defined_function_expands_in_expressions() {
+    let mut ctx = setup_test_df();
+
+    // wealth_bucket(x) -> pl.when(x > 200).then("rich").when(x > 75).then("mid").otherwise("poor")
+    ctx.sugar.register_function("wealth_bucket", 1, |args, _| {
+        let gold = piql::expr_helpers::positional_arg(args, 0).unwrap().clone();
+        piql::expr_helpers::when_then_otherwise(
+            vec![
+                (
+                    binop(gold.clone(), BinOp::Gt, lit_int(200)),
+                    lit_str("rich"),
+                ),
+                (binop(gold, BinOp::Gt, lit_int(75)), lit_str("mid")),
+            ],
+            lit_str("poor"),
+        )
+    });
+
+    let result = run_to_df(
+        r#"entities.with_columns(wealth_bucket($gold).alias("bucket")).sort("name")"#,
+        &ctx,
+    );
+    let buckets: Vec<_> = result
+        .column("bucket")
+        .unwrap()
+        .str()
+        .unwrap()
+        .into_no_null_iter()
+        .collect();
+    assert_eq!(buckets, ["mid", "rich", "poor"]);
+
+    // Usable anywhere an expression is, e.g. inside filters
+    let result = run_to_df(r#"entities.filter(wealth_bucket($gold) == "poor")"#, &ctx);
+    assert_eq!(result.height(), 1);
+}
+
+#[test]
+fn user_defined_function_checks_arity() {
+    let mut ctx = setup_test_df();
+    ctx.sugar.register_function("double", 1, |args, _| {
+        let x = piql::expr_helpers::positional_arg(args, 0).unwrap().clone();
+        binop(x, BinOp::Mul, lit_int(2))
+    });
+
+    match run(r#"entities.select(double($gold, 3))"#, &ctx) {
+        Ok(_) => panic!("expected arity error"),
+        Err(err) => assert!(
+            err.to_string()
+                .contains("double() takes 1 argument(s), got 2"),
+            "unexpected error: {err}"
+        ),
+    }
+}
+
 #[test]
 fn unknown_directive_returns_error() {
     let ctx = setup_test_df();
@@ -1456,6 +3043,55 @@ fn parse_error_reports_line_and_column() {
     assert!(msg.contains("column"), "unexpected error: {msg}");
 }
 
+#[test]
+fn parse_errors_name_what_was_expected() {
+    let cases = [
+        (
+            "entities.filter($gold >)",
+            "expected expression after comparison operator",
+            24,
+        ),
+        ("entities.", "expected name after '.'", 10),
+        (
+            "entities.filter($gold > 1",
+            "expected ',' or ')' to close argument list",
+            26,
+        ),
+        (
+            "entities.filter($name == 'bob)",
+            "unclosed string literal",
+            26,
+        ),
+        (
+            "entities.select([$a, $b)",
+            "expected ',' or ']' to close list",
+            24,
+        ),
+    ];
+    for (query, message, column) in cases {
+        let err = match piql::advanced::parse(query) {
+            Ok(expr) => panic!("expected parse error for {query}, got {expr:?}"),
+            Err(err) => err,
+        };
+        assert_eq!(err.message, message, "for {query}");
+        assert_eq!(err.column, column, "for {query}");
+    }
+}
+
+#[test]
+fn parse_error_renders_snippet_with_caret() {
+    let ctx = setup_test_df();
+    let err = match run("entities\n  .filter($gold >)", &ctx) {
+        Ok(_) => panic!("expected parse error"),
+        Err(err) => err,
+    };
+    let msg = err.to_string();
+    assert!(
+        msg.ends_with("2 |   .filter($gold >)\n  |                  ^"),
+        "unexpected error: {msg}"
+    );
+}
+
 #[test]
 fn eval_error_includes_query_context() {
     let ctx = setup_test_df();
@@ -1479,14 +3115,7 @@ fn time_series_df_with_config() {
     .lazy();
 
     let ctx = EvalContext::new()
-        .with_time_series_df(
-            "entities",
-            df,
-            TimeSeriesConfig {
-                tick_column: "tick".into(),
-                partition_key: "entity_id".into(),
-            },
-        )
+        .with_time_series_df("entities", df, TimeSeriesConfig::new("tick", "entity_id"))
         .with_tick(2);
 
     // Use window to filter and delta which uses partition_key from config
@@ -1511,10 +3140,7 @@ fn time_series_df_custom_tick_column() {
     let ctx = EvalContext::new().with_time_series_df(
         "entities",
         df,
-        TimeSeriesConfig {
-            tick_column: "step".into(),
-            partition_key: "entity_id".into(),
-        },
+        TimeSeriesConfig::new("step", "entity_id"),
     );
 
     let result = run_to_df(r#"entities.at(2)"#, &ctx);
@@ -1534,10 +3160,7 @@ fn run_infers_root_df_for_partition_sugar() {
     let ctx = EvalContext::new().with_time_series_df(
         "entities",
         df,
-        TimeSeriesConfig {
-            tick_column: "step".into(),
-            partition_key: "account_id".into(),
-        },
+        TimeSeriesConfig::new("step", "account_id"),
     );
 
     let result = run_to_df(
@@ -1621,14 +3244,7 @@ fn query_engine_subscriptions() {
     .lazy();
 
     let mut engine = QueryEngine::new();
-    engine.add_time_series_df(
-        "entities",
-        df,
-        TimeSeriesConfig {
-            tick_column: "tick".into(),
-            partition_key: "entity_id".into(),
-        },
-    );
+    engine.add_time_series_df("entities", df, TimeSeriesConfig::new("tick", "entity_id"));
 
     // Subscribe to queries
     engine.subscribe("rich", r#"entities.at(2).filter($gold > 100)"#);
@@ -1675,34 +3291,367 @@ fn query_engine_materialized_chain() {
     assert_eq!(report.height(), 1);
 }
 
+fn dependency_engine() -> QueryEngine {
+    let mut engine = QueryEngine::new();
+    engine.register_base("entities", TimeSeriesConfig::new("tick", "entity_id"));
+    let tick1 = df! {
+        "tick" => &[1, 1],
+        "entity_id" => &[1, 2],
+        "gold" => &[100, 200],
+    }
+    .unwrap()
+    .lazy();
+    engine.append_tick("entities", tick1).unwrap();
+    engine.set_tick(1);
+    engine
+}
+
+#[test]
+fn query_engine_materializations_refresh_in_dependency_order() {
+    let mut engine = dependency_engine();
+    engine.define_alias("src", "entities").unwrap();
+    engine
+        .materialize("first", "!src.filter($gold > 0)")
+        .unwrap();
+    engine
+        .materialize("second", "entities.filter($gold > 150)")
+        .unwrap();
+
+    // `first` now reads `second`, although it was registered before it
+    engine.define_alias("src", "second").unwrap();
+    let graph = engine.dependency_graph();
+    assert_eq!(graph.topological_order(), vec!["second", "first"]);
+    assert_eq!(graph.dependents("second"), vec!["first"]);
+    assert_eq!(graph.refresh_order("entities"), vec!["second", "first"]);
+
+    let tick2 = df! {
+        "tick" => &[2, 2],
+        "entity_id" => &[1, 2],
+        "gold" => &[300, 400],
+    }
+    .unwrap()
+    .lazy();
+    engine.append_tick("entities", tick2).unwrap();
+    engine.subscribe("out", "first");
+    assert_eq!(engine.on_tick(2).unwrap()["out"].height(), 2);
+}
+
+#[test]
+fn query_engine_rejects_materialization_cycles() {
+    let mut engine = dependency_engine();
+    let is_cycle = |result: Result<(), PiqlError>, expected: &str| matches!(result, Err(PiqlError::DependencyCycle(ref path)) if path == expected);
+
+    assert!(is_cycle(engine.materialize("s", "s.head(1)"), "s -> s"));
+    assert!(!engine.dependency_graph().contains("s"));
+
+    engine.materialize("x", "entities.head(2)").unwrap();
+    engine.materialize("y", "x.head(1)").unwrap();
+    assert!(is_cycle(
+        engine.materialize("x", "y.head(1)"),
+        "x -> y -> x"
+    ));
+    assert_eq!(engine.on_tick(1).unwrap().len(), 0);
+
+    // An alias redefinition that would close a cycle is rejected and undone
+    engine.define_alias("src", "entities").unwrap();
+    engine.materialize("z", "!src.head(1)").unwrap();
+    assert!(is_cycle(engine.define_alias("src", "z"), "z -> z"));
+    let Value::DataFrame(lf, _) = engine.query("!src").unwrap() else {
+        panic!("Expected DataFrame");
+    };
+    assert_eq!(lf.collect().unwrap().height(), 2);
+}
+
+#[test]
+fn query_engine_subscription_directive_is_compiled_once() {
+    let df = df! {
+        "type" => &["a", "b", "a"],
+        "value" => &[1, 2, 3],
+    }
+    .unwrap()
+    .lazy();
+
+    let mut engine = QueryEngine::new();
+    engine.add_base_df("entities", df);
+
+    let expansion_count = Arc::new(AtomicUsize::new(0));
+    let expansion_count_clone = expansion_count.clone();
+    engine.sugar().register_directive("counted", move |_, _| {
+        expansion_count_clone.fetch_add(1, Ordering::SeqCst);
+        binop(pl_col("type"), BinOp::Eq, lit_str("a"))
+    });
+
+    engine.subscribe("only_a", r#"entities.filter(@counted)"#);
+    engine.on_tick(1).unwrap();
+    engine.on_tick(2).unwrap();
+
+    assert_eq!(
+        expansion_count.load(Ordering::SeqCst),
+        1,
+        "directive expansion should happen once at compile time"
+    );
+}
+
+#[test]
+fn compiled_query_reports_referenced_tables() {
+    let ctx = EvalContext::new();
+    let compiled = piql::compile(
+        r#"entities.join(locations.filter($region == "coast"), left_on="location_id", right_on="id").filter(pl.col("gold") > 1)"#,
+        &ctx,
+    )
+    .unwrap();
+    assert_eq!(compiled.referenced_tables(), vec!["entities", "locations"]);
+}
+
+#[test]
+fn query_engine_remove_df_fails_or_cascades() {
+    let df = df! { "gold" => &[100, 250, 50] }.unwrap().lazy();
+    let mut engine = QueryEngine::new();
+    engine.add_base_df("entities", df.clone());
+    engine.add_base_df("other", df);
+    engine
+        .materialize("rich", "entities.filter($gold > 75)")
+        .unwrap();
+    engine.materialize("top", "rich.head(1)").unwrap();
+    engine.subscribe("watch", "top");
+    engine.subscribe("unrelated", "other");
+
+    let Err(PiqlError::TableInUse { table, dependents }) =
+        engine.remove_df("entities", RemoveMode::Fail)
+    else {
+        panic!("removing a table that views read should fail");
+    };
+    assert_eq!(table, "entities");
+    assert_eq!(dependents, ["rich", "top", "watch"]);
+    assert!(engine.query("top").is_ok());
+
+    let removed = engine.remove_df("entities", RemoveMode::Cascade).unwrap();
+    assert_eq!(removed, ["rich", "top", "watch"]);
+    assert_eq!(engine.dataframe_names(), ["other"]);
+    assert!(engine.dependency_graph().is_empty());
+    let results = engine.on_tick(1).unwrap();
+    assert_eq!(results.keys().collect::<Vec<_>>(), ["unrelated"]);
+
+    assert!(matches!(
+        engine.remove_df("entities", RemoveMode::Cascade),
+        Err(PiqlError::UnknownTable(_))
+    ));
+    assert!(matches!(
+        engine.remove_df("other", RemoveMode::Fail),
+        Err(PiqlError::TableInUse { ref dependents, .. }) if dependents == &["unrelated"]
+    ));
+}
+
+#[test]
+fn query_engine_rename_df_keeps_data_and_queries() {
+    let df = df! { "gold" => &[100, 250, 50] }.unwrap().lazy();
+    let mut engine = QueryEngine::new();
+    engine.add_base_df("entities", df.clone());
+    engine.add_base_df("taken", df);
+    engine
+        .materialize("rich", "entities.filter($gold > 75)")
+        .unwrap();
+
+    assert!(matches!(
+        engine.rename_df("entities", "people"),
+        Err(PiqlError::TableInUse { .. })
+    ));
+    assert!(matches!(
+        engine.rename_df("rich", "taken"),
+        Err(PiqlError::TableExists(_))
+    ));
+
+    // A materialization keeps refreshing under its new name
+    engine.rename_df("rich", "wealthy").unwrap();
+    assert!(engine.dependency_graph().contains("wealthy"));
+    assert!(!engine.dependency_graph().contains("rich"));
+    engine.update_df("entities", df! { "gold" => &[500] }.unwrap().lazy());
+    engine.subscribe("w", "wealthy");
+    let results = engine.on_tick(1).unwrap();
+    assert_eq!(results["w"].height(), 1);
+    assert!(engine.query("rich").is_err());
+}
+
+#[test]
+fn query_engine_as_of_rebinds_tick_for_one_query() {
+    let mut engine = dependency_engine();
+    for tick in [2, 3] {
+        let rows = df! {
+            "tick" => &[tick, tick],
+            "entity_id" => &[1, 2],
+            "gold" => &[100 * tick, 200 * tick],
+        }
+        .unwrap()
+        .lazy();
+        engine.append_tick("entities", rows).unwrap();
+    }
+    engine.set_tick(3);
+    engine
+        .sugar()
+        .register_directive("now", |_, ctx| lit_int(ctx.tick.unwrap_or(-1)));
+    let query = |query: &str| match engine.query(query).unwrap() {
+        Value::DataFrame(lf, _) => lf.collect().unwrap(),
+        _ => panic!("Expected DataFrame"),
+    };
+
+    let df = query("entities.as_of(2)");
+    let ticks: Vec<_> = df.column("tick").unwrap().i32().unwrap().iter().collect();
+    assert_eq!(ticks, [Some(2), Some(2)]);
+
+    // Scope methods and directives see tick 2, and later rows are gone
+    let df = query("entities.window(-1, 0).as_of(2)");
+    assert_eq!(df.height(), 4);
+    assert_eq!(query("entities.all().as_of(1)").height(), 2);
+    let df = query(r#"entities.as_of(2).with_columns(@now.alias("now"))"#);
+    let now = df.column("now").unwrap().get(0).unwrap();
+    assert_eq!(now.extract::<i64>(), Some(2));
+
+    // The engine itself is unchanged
+    assert_eq!(engine.tick(), Some(3));
+    let df = query("entities");
+    assert_eq!(df.column("tick").unwrap().i32().unwrap().get(0), Some(3));
+
+    assert!(engine.query("entities.as_of($gold)").is_err());
+}
+
+// ============ Capabilities ============
+
+#[test]
+fn capabilities_cover_every_namespace() {
+    let caps = capabilities();
+    assert_eq!(caps.len(), Namespace::ALL.len());
+    for (ns, methods) in caps {
+        assert!(!methods.is_empty(), "{} has no methods", ns.as_str());
+        for spec in methods {
+            if let Some(max) = spec.max_args {
+                assert!(spec.min_args <= max, "{}.{}", ns.as_str(), spec.name);
+            }
+        }
+    }
+}
+
+#[test]
+fn capabilities_match_eval_dispatch() {
+    let df = df! {
+        "a" => &[1, 2],
+        "s" => &["x", "y"],
+    }
+    .unwrap()
+    .lazy();
+    let ctx = EvalContext::new().with_df("df", df);
+
+    for (ns, methods) in capabilities() {
+        for spec in methods {
+            let name = spec.name;
+            let query = match ns {
+                Namespace::Pl if name == "when" => {
+                    r#"df.select(pl.when($a > 1).then(1).otherwise(0))"#.to_string()
+                }
+                Namespace::Pl => format!("df.select(pl.{name}())"),
+                Namespace::DataFrame => format!("df.{name}()"),
+                Namespace::GroupBy => format!(r#"df.group_by("s").{name}()"#),
+                Namespace::Expr => format!("df.select($a.{name}())"),
+                Namespace::Str => format!("df.select($s.str.{name}())"),
+                Namespace::Dt => format!("df.select($a.dt.{name}())"),
+                Namespace::Struct => format!("df.select($a.struct.{name}())"),
+                Namespace::Selectors => format!("df.select(cs.{name}())"),
+            };
+            // Argument/type errors are fine; only an unknown method means the table is stale
+            if let Err(err) = run(&query, &ctx) {
+                assert!(
+                    !err.to_string().contains("Unknown method"),
+                    "{}.{name} is listed but not dispatched: {err}",
+                    ns.as_str()
+                );
+            }
+        }
+    }
+}
+
 #[test]
-fn query_engine_subscription_directive_is_compiled_once() {
-    let df = df! {
-        "type" => &["a", "b", "a"],
-        "value" => &[1, 2, 3],
+fn unlisted_method_is_unknown() {
+    let ctx = setup_test_df();
+    let err = run("entities.not_a_method()", &ctx).err().unwrap();
+    assert!(err.to_string().contains("Unknown method"));
+    assert!(
+        !capabilities()
+            .iter()
+            .any(|(_, methods)| methods.iter().any(|m| m.name == "not_a_method"))
+    );
+}
+
+// ============ Subscription Lints ============
+
+#[test]
+fn lint_flags_all_and_describe_in_subscriptions() {
+    let mut engine = QueryEngine::new();
+    engine.register_base("entities", TimeSeriesConfig::new("tick", "entity_id"));
+    let tick1 = df! {
+        "tick" => &[1, 1],
+        "entity_id" => &[1, 2],
+        "gold" => &[100, 200],
     }
     .unwrap()
     .lazy();
+    engine.append_tick("entities", tick1).unwrap();
 
-    let mut engine = QueryEngine::new();
-    engine.add_base_df("entities", df);
+    engine.subscribe("history", r#"entities.all().filter($gold > 100)"#);
+    engine.subscribe("stats", r#"entities.describe()"#);
+    engine.subscribe("bounded", r#"entities.window(-5, 0)"#);
 
-    let expansion_count = Arc::new(AtomicUsize::new(0));
-    let expansion_count_clone = expansion_count.clone();
-    engine.sugar().register_directive("counted", move |_, _| {
-        expansion_count_clone.fetch_add(1, Ordering::SeqCst);
-        binop(pl_col("type"), BinOp::Eq, lit_str("a"))
-    });
+    let warnings = engine.lint_subscriptions();
+    let kinds: Vec<_> = warnings
+        .iter()
+        .map(|w| (w.kind, w.subscriptions[0].as_str()))
+        .collect();
+    assert!(kinds.contains(&(LintKind::AllOnBaseTable, "history")));
+    assert!(kinds.contains(&(LintKind::DescribeInSubscription, "stats")));
+    assert!(!kinds.iter().any(|(_, name)| *name == "bounded"));
+}
 
-    engine.subscribe("only_a", r#"entities.filter(@counted)"#);
-    engine.on_tick(1).unwrap();
-    engine.on_tick(2).unwrap();
+#[test]
+fn lint_flags_join_keys_missing_on_one_side() {
+    let left = df! { "id" => &[1, 2], "a" => &[1, 2] }.unwrap().lazy();
+    let right = df! { "other_id" => &[1, 2], "b" => &[3, 4] }
+        .unwrap()
+        .lazy();
 
-    assert_eq!(
-        expansion_count.load(Ordering::SeqCst),
-        1,
-        "directive expansion should happen once at compile time"
+    let mut engine = QueryEngine::new();
+    engine.add_base_df("left", left);
+    engine.add_base_df("right", right);
+    engine.subscribe("bad", r#"left.join(right, on="id")"#);
+    engine.subscribe(
+        "good",
+        r#"left.join(right, left_on="id", right_on="other_id")"#,
+    );
+
+    let warnings = engine.lint_subscriptions();
+    assert_eq!(warnings.len(), 1, "unexpected warnings: {warnings:?}");
+    assert_eq!(warnings[0].kind, LintKind::JoinWithoutKeyOverlap);
+    assert_eq!(warnings[0].subscriptions, vec!["bad".to_string()]);
+    assert!(warnings[0].message.contains("right.id"));
+}
+
+#[test]
+fn lint_suggests_materializing_shared_pipelines() {
+    let mut engine = QueryEngine::new();
+    engine.add_base_df(
+        "entities",
+        df! { "gold" => &[1, 200], "type" => &["a", "b"] }
+            .unwrap()
+            .lazy(),
     );
+    engine.subscribe(
+        "rich_a",
+        r#"entities.filter($gold > 100).filter($type == "a")"#,
+    );
+    engine.subscribe("rich_top", r#"entities.filter($gold > 100).top(5, "gold")"#);
+
+    let warnings = engine.lint_subscriptions();
+    assert_eq!(warnings.len(), 1, "unexpected warnings: {warnings:?}");
+    assert_eq!(warnings[0].kind, LintKind::MaterializationCandidate);
+    assert_eq!(warnings[0].subscriptions, vec!["rich_a", "rich_top"]);
+    assert!(warnings[0].message.contains("entities.filter($gold > 100)"));
 }
 
 // ============ Base Table Routing ============
@@ -1711,13 +3660,7 @@ fn query_engine_subscription_directive_is_compiled_once() {
 fn base_table_implicit_now() {
     // Test that queries on base tables without scope use `now` ptr
     let mut engine = QueryEngine::new();
-    engine.register_base(
-        "entities",
-        TimeSeriesConfig {
-            tick_column: "tick".into(),
-            partition_key: "entity_id".into(),
-        },
-    );
+    engine.register_base("entities", TimeSeriesConfig::new("tick", "entity_id"));
 
     // Tick 1: add some entities
     let tick1 = df! {
@@ -1771,13 +3714,7 @@ fn base_table_implicit_now() {
 fn base_table_all_scope() {
     // Test that .all() returns full history
     let mut engine = QueryEngine::new();
-    engine.register_base(
-        "entities",
-        TimeSeriesConfig {
-            tick_column: "tick".into(),
-            partition_key: "entity_id".into(),
-        },
-    );
+    engine.register_base("entities", TimeSeriesConfig::new("tick", "entity_id"));
 
     // Add data for tick 1 and 2
     let tick1 = df! {
@@ -1812,13 +3749,7 @@ fn base_table_all_scope() {
 fn base_table_window_scope() {
     // Test that .window() returns filtered history
     let mut engine = QueryEngine::new();
-    engine.register_base(
-        "entities",
-        TimeSeriesConfig {
-            tick_column: "tick".into(),
-            partition_key: "entity_id".into(),
-        },
-    );
+    engine.register_base("entities", TimeSeriesConfig::new("tick", "entity_id"));
 
     // Add data for ticks 1, 2, 3
     for tick in 1..=3 {
@@ -1843,43 +3774,636 @@ fn base_table_window_scope() {
     }
 }
 
-#[test]
-fn update_df_updates_registered_base_table_pointers() {
+#[test]
+fn update_df_updates_registered_base_table_pointers() {
+    let mut engine = QueryEngine::new();
+    engine.register_base("entities", TimeSeriesConfig::new("tick", "entity_id"));
+
+    let tick1 = df! {
+        "tick" => &[1],
+        "entity_id" => &[1],
+        "gold" => &[100],
+    }
+    .unwrap()
+    .lazy();
+    engine.append_tick("entities", tick1).unwrap();
+
+    let replaced = df! {
+        "tick" => &[99],
+        "entity_id" => &[1],
+        "gold" => &[999],
+    }
+    .unwrap()
+    .lazy();
+    engine.update_df("entities", replaced);
+
+    let result = engine.query("entities").unwrap();
+    if let Value::DataFrame(lf, _) = result {
+        let df = lf.collect().unwrap();
+        let gold = df.column("gold").unwrap().i32().unwrap().get(0).unwrap();
+        assert_eq!(gold, 999);
+    } else {
+        panic!("Expected DataFrame");
+    }
+}
+
+// ============ Incremental Subscriptions ============
+
+fn incremental_engine(incremental: bool) -> QueryEngine {
+    let mut engine = QueryEngine::new();
+    engine.set_incremental(incremental);
+    for name in ["entities", "orders"] {
+        engine.register_base(name, TimeSeriesConfig::new("tick", "entity_id"));
+    }
+    engine.subscribe("rich_now", r#"entities.filter($gold > 100)"#);
+    engine.subscribe("orders_now", r#"orders.select([$entity_id, $qty])"#);
+    engine.subscribe("recent", r#"entities.window(-1, 0).filter($gold > 100)"#);
+    engine
+}
+
+fn append_gold(engine: &mut QueryEngine, tick: i32, gold: [i32; 2]) {
+    let rows = df! {
+        "tick" => &[tick, tick],
+        "entity_id" => &[1, 2],
+        "gold" => &gold,
+    }
+    .unwrap()
+    .lazy();
+    engine.append_tick("entities", rows).unwrap();
+}
+
+#[test]
+fn incremental_subscriptions_match_full_evaluation() {
+    let mut full = incremental_engine(false);
+    let mut incremental = incremental_engine(true);
+    let orders = df! { "tick" => &[1], "entity_id" => &[1], "qty" => &[3] }
+        .unwrap()
+        .lazy();
+
+    for engine in [&mut full, &mut incremental] {
+        engine.append_tick("orders", orders.clone()).unwrap();
+    }
+    let mut actual = std::collections::HashMap::new();
+    for (tick, gold) in [(1, [50, 150]), (2, [120, 80]), (3, [200, 300])] {
+        for engine in [&mut full, &mut incremental] {
+            append_gold(engine, tick, gold);
+        }
+        let expected = full.on_tick(tick as i64).unwrap();
+        actual = incremental.on_tick(tick as i64).unwrap();
+        for name in ["rich_now", "orders_now", "recent"] {
+            assert!(
+                expected[name].equals_missing(&actual[name]),
+                "{name} differs at tick {tick}"
+            );
+        }
+    }
+
+    // Tick 3 rows only, vs ticks 2-3 for the windowed subscription
+    assert_eq!(actual["rich_now"].height(), 2);
+    assert_eq!(actual["recent"].height(), 3);
+    // `orders` received rows only at tick 1; its result is reused since
+    assert_eq!(actual["orders_now"].height(), 1);
+    assert_eq!(
+        incremental.subscription_scope("rich_now"),
+        Some(SubscriptionScope::Now)
+    );
+    assert_eq!(
+        incremental.subscription_scope("recent"),
+        Some(SubscriptionScope::History)
+    );
+}
+
+#[test]
+fn subscription_scope_analysis() {
+    let mut engine = incremental_engine(true);
+    engine.add_base_df("towns", df! { "entity_id" => &[1] }.unwrap().lazy());
+    engine.subscribe("joined", r#"entities.join(orders, on="entity_id")"#);
+    engine.subscribe("with_towns", r#"entities.join(towns, on="entity_id")"#);
+    engine.subscribe("history", r#"entities.all().filter($gold > 100)"#);
+    engine.subscribe("derived_window", r#"entities.filter($gold > 0).at(1)"#);
+    assert_eq!(engine.subscription_scope("joined"), None);
+
+    append_gold(&mut engine, 1, [50, 150]);
+    let orders = df! { "tick" => &[1], "entity_id" => &[1], "qty" => &[3] }
+        .unwrap()
+        .lazy();
+    engine.append_tick("orders", orders).unwrap();
+    engine.on_tick(1).unwrap();
+
+    for (name, scope) in [
+        ("joined", SubscriptionScope::Now),
+        ("derived_window", SubscriptionScope::Now),
+        ("with_towns", SubscriptionScope::History),
+        ("history", SubscriptionScope::History),
+    ] {
+        assert_eq!(engine.subscription_scope(name), Some(scope), "{name}");
+    }
+}
+
+// ============ Retention ============
+
+fn retained_engine(retention: Retention, ticks: std::ops::RangeInclusive<i32>) -> QueryEngine {
+    let mut engine = QueryEngine::new();
+    engine.register_base_with_retention(
+        "entities",
+        TimeSeriesConfig::new("tick", "entity_id"),
+        retention,
+    );
+    for tick in ticks {
+        let rows = df! {
+            "tick" => &[tick, tick],
+            "entity_id" => &[1, 2],
+            "gold" => &[tick * 10, tick * 20],
+        }
+        .unwrap()
+        .lazy();
+        engine.append_tick("entities", rows).unwrap();
+        engine.set_tick(tick as i64);
+    }
+    engine
+}
+
+fn query_ticks(engine: &QueryEngine, query: &str) -> Result<Vec<i32>, PiqlError> {
+    let Value::DataFrame(lf, _) = engine.query(query)? else {
+        panic!("expected a DataFrame from {query}");
+    };
+    let df = lf.collect().unwrap();
+    let mut ticks: Vec<i32> = df
+        .column("tick")
+        .unwrap()
+        .i32()
+        .unwrap()
+        .into_no_null_iter()
+        .collect();
+    ticks.dedup();
+    Ok(ticks)
+}
+
+fn is_evicted(result: Result<Vec<i32>, PiqlError>) -> bool {
+    matches!(
+        result,
+        Err(PiqlError::EvalWithQuery {
+            source: EvalError::Evicted { .. },
+            ..
+        })
+    )
+}
+
+#[test]
+fn retention_keeps_last_ticks_and_flags_evicted_ranges() {
+    let engine = retained_engine(Retention::LastTicks(2), 1..=4);
+
+    assert_eq!(query_ticks(&engine, "entities.all()").unwrap(), [3, 4]);
+    assert_eq!(query_ticks(&engine, "entities").unwrap(), [4]);
+    assert_eq!(
+        query_ticks(&engine, "entities.window(-1, 0)").unwrap(),
+        [3, 4]
+    );
+    assert_eq!(query_ticks(&engine, "entities.since(3)").unwrap(), [3, 4]);
+
+    assert!(is_evicted(query_ticks(&engine, "entities.window(-3, 0)")));
+    assert!(is_evicted(query_ticks(&engine, "entities.at(1)")));
+    assert!(is_evicted(query_ticks(&engine, "entities.since(2)")));
+    assert_eq!(
+        query_ticks(&engine, "entities.window(-3, 0, partial=True)").unwrap(),
+        [3, 4]
+    );
+    // Ticks that never existed were not evicted
+    assert_eq!(
+        query_ticks(&engine, "entities.window(-1, 5)").unwrap(),
+        [3, 4]
+    );
+}
+
+#[test]
+fn retention_downsamples_older_ticks() {
+    let engine = retained_engine(
+        Retention::Downsample {
+            recent: 2,
+            every: 2,
+        },
+        1..=6,
+    );
+
+    assert_eq!(
+        query_ticks(&engine, "entities.all()").unwrap(),
+        [2, 4, 5, 6]
+    );
+    assert_eq!(query_ticks(&engine, "entities.at(4)").unwrap(), [4]);
+    assert_eq!(
+        query_ticks(&engine, "entities.since(4)").unwrap(),
+        [4, 5, 6]
+    );
+    assert!(is_evicted(query_ticks(&engine, "entities.at(3)")));
+    assert!(is_evicted(query_ticks(&engine, "entities.since(3)")));
+    assert_eq!(
+        query_ticks(&engine, "entities.since(3, partial=True)").unwrap(),
+        [4, 5, 6]
+    );
+}
+
+#[test]
+fn compact_applies_retention_to_replaced_history() {
+    let mut engine = retained_engine(Retention::LastTicks(2), 1..=1);
+    let history = df! {
+        "tick" => &[1, 2, 3, 4],
+        "entity_id" => &[1, 1, 1, 1],
+        "gold" => &[10, 20, 30, 40],
+    }
+    .unwrap()
+    .lazy();
+    engine.update_df("entities", history);
+    engine.set_tick(4);
+
+    assert_eq!(engine.compact().unwrap(), 2);
+    assert_eq!(engine.compact().unwrap(), 0);
+    assert_eq!(query_ticks(&engine, "entities.all()").unwrap(), [3, 4]);
+}
+
+// ============ Snapshots ============
+
+#[test]
+fn snapshot_round_trip_restores_engine_state() {
+    let dir = std::env::temp_dir().join(format!("piql-engine-snapshot-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let mut engine = retained_engine(Retention::LastTicks(2), 1..=4);
+    engine.add_base_df("towns", df! { "entity_id" => &[1, 2] }.unwrap().lazy());
+    engine
+        .define_alias("rich", "entities.filter($gold > 50)")
+        .unwrap();
+    engine.materialize("rich_now", "!rich").unwrap();
+    engine.subscribe("rich_towns", r#"rich_now.join(towns, on="entity_id")"#);
+    engine.subscribe("recent", "entities.window(-1, 0)");
+    let expected = engine.on_tick(4).unwrap();
+    engine.save_snapshot(&dir).unwrap();
+
+    // Saving again replaces the previous files, and removes leftovers of an
+    // interrupted save
+    let tables = dir.join("tables");
+    let saved = std::fs::read_dir(&tables).unwrap().count();
+    std::fs::write(tables.join("interrupted-0.parquet"), b"partial").unwrap();
+    engine.save_snapshot(&dir).unwrap();
+    assert_eq!(std::fs::read_dir(&tables).unwrap().count(), saved);
+
+    let mut restored = QueryEngine::new();
+    restored.load_snapshot(&dir).unwrap();
+    assert_eq!(restored.tick(), Some(4));
+    let actual = restored.on_tick(4).unwrap();
+    for name in ["rich_towns", "recent"] {
+        assert!(expected[name].equals_missing(&actual[name]), "{name}");
+    }
+    assert_eq!(query_ticks(&restored, "entities").unwrap(), [4]);
+    assert!(is_evicted(query_ticks(&restored, "entities.at(1)")));
+
+    // Appends continue from the restored history and retention
+    let rows = df! { "tick" => &[5], "entity_id" => &[1], "gold" => &[50] }
+        .unwrap()
+        .lazy();
+    restored.append_tick("entities", rows).unwrap();
+    assert_eq!(query_ticks(&restored, "entities.all()").unwrap(), [4, 5]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+// ============ Subscription Emit Modes ============
+
+fn emit_engine() -> QueryEngine {
+    let mut engine = QueryEngine::new();
+    engine.add_time_series_df(
+        "entities",
+        df! {
+            "entity_id" => &[1, 2, 3],
+            "gold" => &[100, 200, 300],
+        }
+        .unwrap()
+        .lazy(),
+        TimeSeriesConfig::new("tick", "entity_id"),
+    );
+    engine.subscribe_with_mode("rich", "entities.filter($gold > 150)", EmitMode::OnChange);
+    engine.subscribe_with_mode("rich_diff", "entities.filter($gold > 150)", EmitMode::Diff);
+    engine
+}
+
+fn changes(df: &DataFrame) -> Vec<(i32, String)> {
+    let ids = df.column("entity_id").unwrap().i32().unwrap();
+    let kinds = df.column(CHANGE_COLUMN).unwrap().str().unwrap();
+    let mut rows: Vec<(i32, String)> = ids
+        .into_no_null_iter()
+        .zip(kinds.into_no_null_iter())
+        .map(|(id, kind)| (id, kind.to_string()))
+        .collect();
+    rows.sort();
+    rows
+}
+
+#[test]
+fn on_change_subscriptions_skip_unchanged_results() {
+    let mut engine = emit_engine();
+
+    let first = engine.on_tick(1).unwrap();
+    assert_eq!(first["rich"].height(), 2);
+    let unchanged = engine.on_tick(2).unwrap();
+    assert!(!unchanged.contains_key("rich"));
+    assert!(!unchanged.contains_key("rich_diff"));
+
+    let update = df! { "entity_id" => &[1, 2, 3], "gold" => &[100, 250, 300] }
+        .unwrap()
+        .lazy();
+    engine.update_df("entities", update);
+    assert_eq!(engine.on_tick(3).unwrap()["rich"].height(), 2);
+}
+
+#[test]
+fn diff_subscriptions_emit_changed_rows_by_partition_key() {
+    let mut engine = emit_engine();
+
+    let first = engine.on_tick(1).unwrap();
+    assert_eq!(
+        changes(&first["rich_diff"]),
+        [(2, "added".to_string()), (3, "added".to_string())]
+    );
+
+    let update = df! { "entity_id" => &[1, 2, 3], "gold" => &[400, 250, 100] }
+        .unwrap()
+        .lazy();
+    engine.update_df("entities", update);
+    let diff = &engine.on_tick(2).unwrap()["rich_diff"];
+    assert_eq!(
+        changes(diff),
+        [
+            (1, "added".to_string()),
+            (2, "changed".to_string()),
+            (3, "removed".to_string())
+        ]
+    );
+    let gold: Vec<i32> = diff
+        .column("gold")
+        .unwrap()
+        .i32()
+        .unwrap()
+        .into_no_null_iter()
+        .collect();
+    assert!(gold.contains(&250), "changed rows carry the new values");
+}
+
+#[test]
+fn diff_without_key_compares_whole_rows() {
+    let old = df! { "name" => &["a", "b"], "gold" => &[1, 2] }.unwrap();
+    let new = df! { "name" => &["b", "c"], "gold" => &[2, 3] }.unwrap();
+    let diff = piql::diff_results(&old, &new, None).unwrap();
+    let kinds: Vec<&str> = diff
+        .column(CHANGE_COLUMN)
+        .unwrap()
+        .str()
+        .unwrap()
+        .into_no_null_iter()
+        .collect();
+    assert_eq!(kinds, ["added", "removed"]);
+
+    let reshaped = df! { "name" => &["a"] }.unwrap();
+    let diff = piql::diff_results(&old, &reshaped, None).unwrap();
+    assert_eq!(
+        diff.column(CHANGE_COLUMN).unwrap().str().unwrap().get(0),
+        Some("reset")
+    );
+    assert!(piql::diff_results(&old, &old, None).unwrap().is_empty());
+}
+
+// ============ Parallel Subscriptions ============
+
+fn parallel_engine(threads: usize) -> QueryEngine {
+    let mut engine = QueryEngine::new();
+    engine.set_parallelism(threads);
+    engine.set_incremental(true);
+    engine.register_base("entities", TimeSeriesConfig::new("tick", "entity_id"));
+    for i in 0..12 {
+        engine.subscribe(
+            format!("latest_{i}"),
+            format!("entities.filter($gold > {})", i * 20),
+        );
+        engine.subscribe_with_mode(
+            format!("history_{i}"),
+            format!("entities.all().filter($gold > {})", i * 20),
+            EmitMode::OnChange,
+        );
+    }
+    engine
+}
+
+#[test]
+fn parallel_subscriptions_match_serial_evaluation() {
+    let mut serial = parallel_engine(1);
+    let mut parallel = parallel_engine(4);
+    for tick in 1..=4i64 {
+        for engine in [&mut serial, &mut parallel] {
+            let rows = df! {
+                "tick" => &[tick, tick, tick],
+                "entity_id" => &[1, 2, 3],
+                "gold" => &[tick * 10, tick * 40, tick * 70],
+            }
+            .unwrap()
+            .lazy();
+            engine.append_tick("entities", rows).unwrap();
+        }
+
+        let expected = serial.on_tick(tick).unwrap();
+        let results = parallel.on_tick(tick).unwrap();
+        assert_eq!(results.len(), expected.len());
+        for (name, df) in &expected {
+            assert!(
+                results[name].equals_missing(df),
+                "{name} differs at tick {tick}"
+            );
+        }
+    }
+}
+
+#[test]
+fn parallel_subscriptions_report_first_error_by_name() {
+    let mut engine = parallel_engine(4);
+    engine.subscribe("a_broken", "missing_a.head(1)");
+    engine.subscribe("b_broken", "missing_b.head(1)");
+    for _ in 0..3 {
+        let Err(PiqlError::EvalWithQuery {
+            source: EvalError::UnknownIdent(name),
+            ..
+        }) = engine.on_tick(1)
+        else {
+            panic!("expected unknown table");
+        };
+        assert_eq!(name, "missing_a");
+    }
+}
+
+// ============ Shared Subscription Prefixes ============
+
+fn prefix_engine(share: bool) -> QueryEngine {
     let mut engine = QueryEngine::new();
-    engine.register_base(
-        "entities",
-        TimeSeriesConfig {
-            tick_column: "tick".into(),
-            partition_key: "entity_id".into(),
-        },
+    engine.set_prefix_sharing(share);
+    engine.register_base("entities", TimeSeriesConfig::new("tick", "entity_id"));
+    // Two share `entities.all().filter($gold > 20)`, then the scoped pair shares
+    // `.at(2)` on top of it; the last starts from another table read
+    engine.subscribe("rich_at_2", "entities.all().filter($gold > 20).at(2)");
+    engine.subscribe(
+        "rich_at_2_ids",
+        "entities.all().filter($gold > 20).at(2).select($entity_id)",
+    );
+    engine.subscribe(
+        "rich_since_2",
+        "entities.all().filter($gold > 20).since(2).sort('gold')",
     );
+    engine.subscribe("latest", "entities.filter($gold > 20)");
+    engine
+}
 
-    let tick1 = df! {
-        "tick" => &[1],
-        "entity_id" => &[1],
-        "gold" => &[100],
+#[test]
+fn shared_prefixes_are_evaluated_once_with_the_same_results() {
+    let mut shared = prefix_engine(true);
+    let mut separate = prefix_engine(false);
+    for tick in 1..=3i64 {
+        for engine in [&mut shared, &mut separate] {
+            let rows = df! {
+                "tick" => &[tick, tick, tick],
+                "entity_id" => &[1, 2, 3],
+                "gold" => &[tick * 5, tick * 15, tick * 25],
+            }
+            .unwrap()
+            .lazy();
+            engine.append_tick("entities", rows).unwrap();
+        }
+
+        let expected = separate.on_tick(tick).unwrap();
+        let results = shared.on_tick(tick).unwrap();
+        assert_eq!(results.len(), expected.len());
+        for (name, df) in &expected {
+            assert!(
+                results[name].equals_missing(df),
+                "{name} differs at tick {tick}"
+            );
+        }
     }
-    .unwrap()
-    .lazy();
-    engine.append_tick("entities", tick1).unwrap();
+    assert_eq!(shared.shared_prefixes(), 2);
+    assert_eq!(separate.shared_prefixes(), 0);
+    assert_eq!(shared.on_tick(3).unwrap()["rich_at_2_ids"].height(), 2);
+}
 
-    let replaced = df! {
-        "tick" => &[99],
-        "entity_id" => &[1],
-        "gold" => &[999],
+#[test]
+fn shared_prefixes_leave_user_tables_of_any_name_alone() {
+    let engines = [true, false].map(|share| {
+        let mut engine = QueryEngine::new();
+        engine.set_prefix_sharing(share);
+        // `T` sorts first, so its prefix is collected first
+        for (name, gold) in [("T", [1, 2, 3]), ("__prefix_0", [10, 20, 30])] {
+            let df = df! { "gold" => &gold }.unwrap().lazy();
+            engine.add_base_df(name, df);
+        }
+        for table in ["T", "__prefix_0"] {
+            let prefix = format!("{table}.filter($gold > 1)");
+            engine.subscribe(format!("{table}_all"), prefix.clone());
+            engine.subscribe(format!("{table}_top"), format!("{prefix}.head(1)"));
+        }
+        engine
+    });
+    let [mut shared, mut separate] = engines;
+    let expected = separate.on_tick(1).unwrap();
+    let results = shared.on_tick(1).unwrap();
+    assert_eq!(shared.shared_prefixes(), 2);
+    for (name, df) in &expected {
+        assert!(results[name].equals_missing(df), "{name} differs");
     }
-    .unwrap()
-    .lazy();
-    engine.update_df("entities", replaced);
+}
 
-    let result = engine.query("entities").unwrap();
-    if let Value::DataFrame(lf, _) = result {
-        let df = lf.collect().unwrap();
-        let gold = df.column("gold").unwrap().i32().unwrap().get(0).unwrap();
-        assert_eq!(gold, 999);
-    } else {
+// ============ Resource Limits ============
+
+/// Two tables whose join on `k` pairs every row with every other
+fn cross_join_ctx(rows: i64) -> EvalContext {
+    let ids: Vec<i64> = (0..rows).collect();
+    let keys = vec![0i64; ids.len()];
+    EvalContext::new()
+        .with_df("left", df! { "k" => &keys, "a" => &ids }.unwrap().lazy())
+        .with_df("right", df! { "k" => &keys, "b" => &ids }.unwrap().lazy())
+}
+
+fn collect_limited(
+    query: &str,
+    ctx: &EvalContext,
+    limits: ResourceLimits,
+) -> Result<DataFrame, EvalError> {
+    let Value::DataFrame(lf, _) = run(query, ctx).unwrap() else {
         panic!("Expected DataFrame");
-    }
+    };
+    limits.collect(lf)
+}
+
+#[test]
+fn resource_limits_reject_large_results() {
+    let ctx = cross_join_ctx(1000);
+    let query = r#"left.join(right, on="k")"#;
+
+    let rows = ResourceLimits {
+        max_rows: Some(10_000),
+        ..Default::default()
+    };
+    assert!(matches!(
+        collect_limited(query, &ctx, rows),
+        Err(EvalError::ResourceLimit(LimitExceeded::Rows(10_000)))
+    ));
+    // An exact fit is not an error
+    let df = collect_limited(r#"left.join(right, on="k").head(10000)"#, &ctx, rows).unwrap();
+    assert_eq!(df.height(), 10_000);
+    // A ceiling at the top of the range must not overflow
+    let unbounded = ResourceLimits {
+        max_rows: Some(usize::MAX),
+        ..Default::default()
+    };
+    assert_eq!(
+        collect_limited("left", &ctx, unbounded).unwrap().height(),
+        1000
+    );
+
+    let bytes = ResourceLimits {
+        max_bytes: Some(1 << 20),
+        ..Default::default()
+    };
+    assert!(matches!(
+        collect_limited(query, &ctx, bytes),
+        Err(EvalError::ResourceLimit(LimitExceeded::Bytes { limit, .. })) if limit == 1 << 20
+    ));
+    assert_eq!(collect_limited("left", &ctx, bytes).unwrap().height(), 1000);
+}
+
+#[test]
+fn resource_limits_time_out_slow_queries() {
+    let ctx = cross_join_ctx(3000);
+    let limits = ResourceLimits {
+        timeout: Some(std::time::Duration::from_millis(1)),
+        ..Default::default()
+    };
+    let result = collect_limited(
+        r#"left.join(right, on="k").sort("b", descending=True)"#,
+        &ctx,
+        limits,
+    );
+    assert!(matches!(
+        result,
+        Err(EvalError::ResourceLimit(LimitExceeded::Timeout(_)))
+    ));
+    assert_eq!(
+        collect_limited("left", &ctx, ResourceLimits::default())
+            .unwrap()
+            .height(),
+        3000
+    );
+    // Queries finishing in time return their result
+    let generous = ResourceLimits {
+        timeout: Some(std::time::Duration::from_secs(60)),
+        ..Default::default()
+    };
+    assert_eq!(
+        collect_limited("left", &ctx, generous).unwrap().height(),
+        3000
+    );
 }
 
 // ============ describe ============
@@ -1971,17 +4495,458 @@ fn describe_no_numeric_columns() {
     let ctx = EvalContext::new().with_df("test", df);
     let result = run("test.describe()", &ctx);
 
-    match result {
-        Err(e) => {
-            let err = e.to_string();
-            assert!(
-                err.contains("numeric"),
-                "Error should mention numeric: {}",
-                err
-            );
-        }
-        Ok(_) => panic!("Should error with no numeric columns"),
-    }
+    match result {
+        Err(e) => {
+            let err = e.to_string();
+            assert!(
+                err.contains("numeric"),
+                "Error should mention numeric: {}",
+                err
+            );
+        }
+        Ok(_) => panic!("Should error with no numeric columns"),
+    }
+}
+
+// ============ explain ============
+
+#[test]
+fn explain_returns_plan_lines() {
+    let ctx = setup_test_df();
+    let df = run_to_df("entities.filter($gold > 100).select($name).explain()", &ctx);
+
+    assert_eq!(df.get_column_names(), &["plan"]);
+    assert!(df.height() > 1);
+    let plan: Vec<_> = df
+        .column("plan")
+        .unwrap()
+        .str()
+        .unwrap()
+        .into_iter()
+        .flatten()
+        .collect();
+    assert!(
+        plan.iter()
+            .any(|line| line.contains("FILTER") || line.contains("SELECTION"))
+    );
+}
+
+#[test]
+fn explain_unoptimized_plan() {
+    let ctx = setup_test_df();
+    let df = run_to_df("entities.head(1).explain(optimized=False)", &ctx);
+    assert!(df.height() >= 1);
+}
+
+// ============ Plan optimizer ============
+
+/// Core AST of `query` with the optimizer, and of `expected` as written
+fn optimized_cores(query: &str, expected: &str, ctx: &EvalContext) -> (CoreExpr, CoreExpr) {
+    let as_written = ctx.clone().with_optimizer(false);
+    (
+        compile(query, ctx).unwrap().core().clone(),
+        compile(expected, &as_written).unwrap().core().clone(),
+    )
+}
+
+#[test]
+fn optimizer_moves_filters_before_sorts_and_new_columns() {
+    let ctx = EvalContext::new()
+        .with_df(
+            "merchants",
+            df! { "tick" => &[1, 2, 2], "gold" => &[5, 20, 30] }
+                .unwrap()
+                .lazy(),
+        )
+        .with_default_tick_column("tick");
+
+    let (optimized, expected) = optimized_cores(
+        "merchants.sort('gold').with_columns(double=$gold * 2).at(2).filter($gold > 10)",
+        "merchants.at(2).filter($gold > 10).sort('gold').with_columns(double=$gold * 2)",
+        &ctx,
+    );
+    assert_eq!(optimized, expected);
+
+    let (optimized, expected) = optimized_cores(
+        "merchants.sort('gold').filter($gold > 10).filter($tick == 2)",
+        "merchants.filter(($gold > 10) & ($tick == 2)).sort('gold')",
+        &ctx,
+    );
+    assert_eq!(optimized, expected);
+
+    let query = "merchants.sort('gold').with_columns(double=$gold * 2).filter($double > 30)";
+    let (optimized, as_written) = optimized_cores(query, query, &ctx);
+    assert_eq!(optimized, as_written);
+    assert_eq!(run_to_df(query, &ctx).height(), 2);
+}
+
+#[test]
+fn optimizer_keeps_row_dependent_stages_in_place() {
+    let mut engine = QueryEngine::new();
+    engine.register_base("entities", TimeSeriesConfig::new("tick", "id"));
+    for (tick, gold) in [(1, [5, 9]), (2, [8, 3])] {
+        let rows = df! { "tick" => &[tick, tick], "id" => &[1, 2], "gold" => &gold }.unwrap();
+        engine.append_tick("entities", rows.lazy()).unwrap();
+    }
+    engine.on_tick(2).unwrap();
+
+    // Scope methods stay off base table names; aggregates and windows depend on
+    // the rows before them
+    for query in [
+        "entities.sort('gold').at(1)",
+        "entities.all().sort('gold').filter($gold.delta > 0)",
+        "entities.all().sort('gold').filter($gold > $gold.mean())",
+    ] {
+        let optimized = match engine.query(query).unwrap() {
+            Value::DataFrame(lf, _) => lf.collect().unwrap(),
+            _ => panic!("Expected DataFrame"),
+        };
+        engine.set_optimizer(false);
+        let as_written = match engine.query(query).unwrap() {
+            Value::DataFrame(lf, _) => lf.collect().unwrap(),
+            _ => panic!("Expected DataFrame"),
+        };
+        engine.set_optimizer(true);
+        assert!(optimized.equals_missing(&as_written), "{query}");
+    }
+}
+
+#[test]
+fn optimizer_keeps_is_in_against_a_column_in_place() {
+    let ctx = EvalContext::new().with_df(
+        "df",
+        df! { "a" => &[1, 2, 3], "b" => &[3, 4, 1] }.unwrap().lazy(),
+    );
+
+    // Merged into the first filter, `$b` would only hold the rows with `$a > 1`
+    let query = "df.filter($a > 1).filter($a.is_in($b))";
+    let optimized = run_to_df(query, &ctx);
+    let as_written = run_to_df(query, &ctx.clone().with_optimizer(false));
+    assert!(optimized.equals_missing(&as_written));
+    assert_eq!(as_written.height(), 0);
+
+    let (optimized, expected) = optimized_cores(
+        "df.filter($a > 1).filter($a.is_in([1, 3]))",
+        "df.filter(($a > 1) & ($a.is_in([1, 3])))",
+        &ctx,
+    );
+    assert_eq!(optimized, expected);
+}
+
+#[test]
+fn optimizer_reads_column_names_in_horizontal_functions() {
+    let ctx = EvalContext::new().with_df(
+        "t",
+        df! { "gold" => &[5, 20, 30], "food" => &[1, 2, 3] }
+            .unwrap()
+            .lazy(),
+    );
+
+    // The strings name columns, so the filter reads the redefined `gold`
+    for query in [
+        r#"t.with_columns(($gold * 100).alias("gold")).filter(pl.sum_horizontal("gold", "food") > 1000)"#,
+        r#"t.with_columns(($gold * 100).alias("gold")).filter(pl.max_horizontal(["gold", $food]) > 1000)"#,
+        r#"t.with_columns(gold=$gold * 100).filter(pl.sum_horizontal("^g.*$") > 1000)"#,
+    ] {
+        let optimized = run_to_df(query, &ctx);
+        let as_written = run_to_df(query, &ctx.clone().with_optimizer(false));
+        assert!(optimized.equals_missing(&as_written), "{query}");
+        assert_eq!(as_written.height(), 2, "{query}");
+    }
+
+    let (optimized, expected) = optimized_cores(
+        r#"t.with_columns(double=$gold * 2).filter(pl.sum_horizontal("gold", "food") > 10)"#,
+        r#"t.filter(pl.sum_horizontal("gold", "food") > 10).with_columns(double=$gold * 2)"#,
+        &ctx,
+    );
+    assert_eq!(optimized, expected);
+}
+
+#[test]
+fn optimizer_filters_materialized_tables_from_their_stored_rows() {
+    let mut engine = QueryEngine::new();
+    engine.set_default_tick_column("tick");
+    engine.register_base("entities", TimeSeriesConfig::new("tick", "id"));
+    for (tick, gold) in [(99, [5, 40]), (100, [20, 3])] {
+        let rows = df! { "tick" => &[tick, tick], "id" => &[1, 2], "gold" => &gold }.unwrap();
+        engine.append_tick("entities", rows.lazy()).unwrap();
+    }
+    engine.materialize("merchants", "entities.all()").unwrap();
+    engine.on_tick(100).unwrap();
+
+    // Rows appended since the last tick are not in the materialized table yet, so
+    // its definition is not re-derived with the filter applied
+    let rows = df! { "tick" => &[100], "id" => &[3], "gold" => &[50] }.unwrap();
+    engine.append_tick("entities", rows.lazy()).unwrap();
+    let query = "merchants.at(100).filter($gold > 10)";
+    let rich = match engine.query(query).unwrap() {
+        Value::DataFrame(lf, _) => lf.collect().unwrap(),
+        _ => panic!("Expected DataFrame"),
+    };
+    assert_eq!(rich.column("id").unwrap().i32().unwrap().get(0), Some(1));
+    assert_eq!(rich.height(), 1);
+
+    engine.on_tick(100).unwrap();
+    let rich = match engine.query(query).unwrap() {
+        Value::DataFrame(lf, _) => lf.collect().unwrap(),
+        _ => panic!("Expected DataFrame"),
+    };
+    assert_eq!(rich.height(), 2);
+}
+
+#[test]
+fn optimizer_pushes_filters_into_current_materializations() {
+    let mut engine = QueryEngine::new();
+    engine.register_base("entities", TimeSeriesConfig::new("tick", "id"));
+    let rows = df! {
+        "tick" => &[1, 1, 1],
+        "id" => &[1, 2, 3],
+        "role" => &["merchant", "farmer", "merchant"],
+        "gold" => &[5, 40, 20],
+    }
+    .unwrap();
+    engine.append_tick("entities", rows.lazy()).unwrap();
+    engine
+        .materialize("merchants", r#"entities.filter($role == "merchant")"#)
+        .unwrap();
+    engine.on_tick(1).unwrap();
+
+    let query = "merchants.filter($gold > 10)";
+    let collect = |engine: &QueryEngine, query: &str| match engine.query(query).unwrap() {
+        Value::DataFrame(lf, _) => lf.collect().unwrap(),
+        _ => panic!("Expected DataFrame"),
+    };
+    let plan = |engine: &QueryEngine| {
+        let df = collect(engine, &format!("{query}.explain(optimized=False)"));
+        let lines: Vec<&str> = df
+            .column("plan")
+            .unwrap()
+            .str()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        lines.join("\n")
+    };
+
+    // The table is current, so the filter joins the one in its definition
+    assert!(plan(&engine).contains("merchant"));
+    let rich = collect(&engine, query);
+    assert_eq!(rich.column("id").unwrap().i32().unwrap().get(0), Some(3));
+    assert_eq!(rich.height(), 1);
+    engine.set_optimizer(false);
+    assert!(!plan(&engine).contains("merchant"));
+    assert!(collect(&engine, query).equals_missing(&rich));
+    engine.set_optimizer(true);
+
+    // New rows make the stored ones out of date until the next tick
+    let rows = df! {
+        "tick" => &[2],
+        "id" => &[4],
+        "role" => &["merchant"],
+        "gold" => &[90],
+    }
+    .unwrap();
+    engine.append_tick("entities", rows.lazy()).unwrap();
+    assert!(!plan(&engine).contains("merchant"));
+    assert!(collect(&engine, query).equals_missing(&rich));
+    engine.on_tick(2).unwrap();
+    assert!(plan(&engine).contains("merchant"));
+    let rich = collect(&engine, query);
+    assert_eq!(rich.column("id").unwrap().i32().unwrap().get(0), Some(4));
+}
+
+#[test]
+fn optimizer_checks_the_tables_a_materialization_read_are_unchanged() {
+    let mut engine = QueryEngine::new();
+    engine.add_base_df("t", df! { "gold" => &[5, 40, 20] }.unwrap().lazy());
+    engine.materialize("m", "t.filter($gold > 3)").unwrap();
+
+    let collect = |engine: &QueryEngine, query: &str| match engine.query(query).unwrap() {
+        Value::DataFrame(lf, _) => lf.collect().unwrap(),
+        _ => panic!("Expected DataFrame"),
+    };
+    let pushed = |engine: &QueryEngine| {
+        let df = collect(engine, "m.filter($gold > 10).explain(optimized=False)");
+        let plan = df.column("plan").unwrap().str().unwrap();
+        plan.into_no_null_iter().any(|line| line.contains("3"))
+    };
+    assert!(pushed(&engine));
+
+    // Replacing a table the query read leaves `m` holding the old rows
+    engine.update_df("t", df! { "gold" => &[50, 60, 70] }.unwrap().lazy());
+    assert!(!pushed(&engine));
+    assert_eq!(collect(&engine, "m.filter($gold > 10)").height(), 2);
+    engine.on_tick(1).unwrap();
+    assert!(pushed(&engine));
+    assert_eq!(collect(&engine, "m.filter($gold > 10)").height(), 3);
+}
+
+#[test]
+fn optimizer_filters_sampled_materializations_from_their_stored_rows() {
+    let mut engine = QueryEngine::new();
+    let gold: Vec<i32> = (0..200).collect();
+    engine.add_base_df("t", df! { "gold" => &gold }.unwrap().lazy());
+    engine
+        .materialize("m", r#"t.sample(n=5).sort("gold")"#)
+        .unwrap();
+    engine.on_tick(1).unwrap();
+
+    let collect = |query: &str| match engine.query(query).unwrap() {
+        Value::DataFrame(lf, _) => lf.collect().unwrap(),
+        _ => panic!("Expected DataFrame"),
+    };
+    // A fresh sample would hold other rows than the stored one
+    let stored = collect("m");
+    for _ in 0..5 {
+        assert!(collect("m.filter($gold >= 0)").equals_missing(&stored));
+    }
+}
+
+// ============ Horizontal functions ============
+
+fn resources_ctx() -> EvalContext {
+    let df = df! {
+        "name" => &["a", "b"],
+        "res_wood" => &[1i64, 5],
+        "res_stone" => &[Some(2i64), None],
+        "res_iron" => &[3i64, 0],
+    }
+    .unwrap()
+    .lazy();
+    EvalContext::new().with_df("df", df)
+}
+
+#[test]
+fn sum_horizontal_over_columns_and_patterns() {
+    let ctx = resources_ctx();
+    let result = run_to_df(
+        r#"df.select(
+            pl.sum_horizontal([$res_wood, $res_stone, $res_iron]).alias("listed"),
+            pl.sum_horizontal("^res_.*$").alias("pattern")
+        )"#,
+        &ctx,
+    );
+    for name in ["listed", "pattern"] {
+        let totals: Vec<i64> = result
+            .column(name)
+            .unwrap()
+            .i64()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        assert_eq!(totals, vec![6, 5], "{name}");
+    }
+}
+
+#[test]
+fn max_min_horizontal() {
+    let ctx = resources_ctx();
+    let result = run_to_df(
+        r#"df.select(
+            pl.max_horizontal($res_wood, $res_iron).alias("hi"),
+            pl.min_horizontal($res_wood, $res_iron).alias("lo")
+        )"#,
+        &ctx,
+    );
+    assert_eq!(result.column("hi").unwrap().i64().unwrap().get(0), Some(3));
+    assert_eq!(result.column("lo").unwrap().i64().unwrap().get(1), Some(0));
+}
+
+#[test]
+fn concat_str_with_separator() {
+    let ctx = resources_ctx();
+    let result = run_to_df(
+        r#"df.select(pl.concat_str([$name, $res_wood.cast("str")], separator="-").alias("tag"))"#,
+        &ctx,
+    );
+    let tags: Vec<&str> = result
+        .column("tag")
+        .unwrap()
+        .str()
+        .unwrap()
+        .into_no_null_iter()
+        .collect();
+    assert_eq!(tags, vec!["a-1", "b-5"]);
+}
+
+// ============ Selectors ============
+
+fn wide_ctx() -> EvalContext {
+    let df = df! {
+        "name" => &["a", "b"],
+        "tick" => &[1i64, 2],
+        "gold_coins" => &[10i64, 20],
+        "gold_bars" => &[1.5, 2.5],
+        "alive" => &[true, false],
+    }
+    .unwrap()
+    .lazy();
+    EvalContext::new().with_df("df", df)
+}
+
+fn column_names(df: &DataFrame) -> Vec<String> {
+    df.get_column_names()
+        .iter()
+        .map(|name| name.to_string())
+        .collect()
+}
+
+#[test]
+fn pl_all_and_exclude() {
+    let ctx = wide_ctx();
+    let all = run_to_df("df.select(pl.all())", &ctx);
+    assert_eq!(all.width(), 5);
+
+    let excluded = run_to_df(r#"df.select(pl.exclude("name", "alive"))"#, &ctx);
+    assert_eq!(column_names(&excluded), ["tick", "gold_coins", "gold_bars"]);
+
+    let sums = run_to_df(
+        r#"df.select(pl.all().exclude(["name", "alive"]).sum())"#,
+        &ctx,
+    );
+    assert_eq!(sums.height(), 1);
+    assert_eq!(
+        sums.column("gold_coins").unwrap().i64().unwrap().get(0),
+        Some(30)
+    );
+}
+
+#[test]
+fn col_regex_patterns() {
+    let ctx = wide_ctx();
+    let gold = run_to_df(r#"df.select(pl.col("^gold_.*$"))"#, &ctx);
+    assert_eq!(column_names(&gold), ["gold_coins", "gold_bars"]);
+
+    let mixed = run_to_df(r#"df.select(pl.col("name", "^gold_.*$"))"#, &ctx);
+    assert_eq!(mixed.width(), 3);
+}
+
+#[test]
+fn dtype_selectors() {
+    let ctx = wide_ctx();
+    let numeric = run_to_df("df.select(cs.numeric())", &ctx);
+    assert_eq!(column_names(&numeric), ["tick", "gold_coins", "gold_bars"]);
+
+    let strings = run_to_df("df.select(cs.string(), cs.boolean())", &ctx);
+    assert_eq!(column_names(&strings), ["name", "alive"]);
+
+    let floats = run_to_df("df.select(cs.float())", &ctx);
+    assert_eq!(column_names(&floats), ["gold_bars"]);
+
+    let totals = run_to_df(r#"df.select(cs.numeric().exclude("tick").sum())"#, &ctx);
+    assert_eq!(column_names(&totals), ["gold_coins", "gold_bars"]);
+
+    let matched = run_to_df(r#"df.select(cs.matches("coins$"))"#, &ctx);
+    assert_eq!(column_names(&matched), ["gold_coins"]);
+}
+
+#[test]
+fn exclude_requires_selector() {
+    let ctx = wide_ctx();
+    let err = run(r#"df.select($tick.exclude("a"))"#, &ctx)
+        .err()
+        .expect("exclude on a plain column should fail");
+    assert!(err.to_string().contains("selectors"), "{err}");
 }
 
 // ============ pl.len() ============
@@ -2119,3 +5084,605 @@ fn dot_attr_still_works() {
     let df = run_to_df(r#"entities.filter(pl.col("gold") > 100)"#, &ctx);
     assert_eq!(df.height(), 1);
 }
+
+// ============ Query parameters (:name) ============
+
+fn run_params_to_df(query: &str, params: &[(&str, ParamValue)], ctx: &EvalContext) -> DataFrame {
+    let params: Params = params
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.clone()))
+        .collect();
+    match run_with_params(query, &params, ctx).unwrap() {
+        Value::DataFrame(lf, _) => lf.collect().unwrap(),
+        _ => panic!("Expected DataFrame"),
+    }
+}
+
+#[test]
+fn params_bind_scalars() {
+    let ctx = setup_test_df();
+    let df = run_params_to_df(
+        "entities.filter($gold > :threshold)",
+        &[("threshold", 90.into())],
+        &ctx,
+    );
+    assert_eq!(df.height(), 2);
+
+    // Strings with quotes need no escaping
+    let ctx =
+        EvalContext::new().with_df("t", df! { "name" => &["o'brien", "smith"] }.unwrap().lazy());
+    let df = run_params_to_df(
+        "t.filter($name == :name)",
+        &[("name", "o'brien".into())],
+        &ctx,
+    );
+    assert_eq!(df.height(), 1);
+}
+
+#[test]
+fn params_bind_lists_and_kwargs() {
+    let ctx = setup_test_df();
+    let df = run_params_to_df(
+        "entities.sort(:cols, descending=:desc).head(:n)",
+        &[
+            ("cols", vec!["gold"].into()),
+            ("desc", true.into()),
+            ("n", 2.into()),
+        ],
+        &ctx,
+    );
+    let gold: Vec<Option<i32>> = df
+        .column("gold")
+        .unwrap()
+        .i32()
+        .unwrap()
+        .into_iter()
+        .collect();
+    assert_eq!(gold, vec![Some(250), Some(100)]);
+}
+
+#[test]
+fn missing_param_is_an_error() {
+    let ctx = setup_test_df();
+    let result = run("entities.filter($gold > :threshold)", &ctx);
+    assert!(matches!(result, Err(PiqlError::MissingParam(ref name)) if name == "threshold"));
+}
+
+#[test]
+fn placeholder_does_not_clash_with_namespaces() {
+    let df = df! { "v" => &[1, 2, 3] }.unwrap().lazy();
+    let ctx = EvalContext::new().with_df("_all::items", df);
+    let result = run_params_to_df("_all::items.filter($v >= :min)", &[("min", 2.into())], &ctx);
+    assert_eq!(result.height(), 2);
+}
+
+// ============ Prepared queries ============
+
+#[test]
+fn prepared_query_runs_with_different_params() {
+    let ctx = setup_test_df();
+    let prepared = ctx
+        .prepare("entities.filter($gold > :min).sort('gold')")
+        .unwrap();
+    assert!(prepared.has_params());
+    assert_eq!(prepared.referenced_tables(), ["entities"]);
+
+    let heights: Vec<usize> = [40, 90, 200]
+        .into_iter()
+        .map(|min| {
+            let params = Params::from([("min".to_string(), ParamValue::from(min))]);
+            match prepared.run_with_params(&params, &ctx).unwrap() {
+                Value::DataFrame(lf, _) => lf.collect().unwrap().height(),
+                _ => panic!("Expected DataFrame"),
+            }
+        })
+        .collect();
+    assert_eq!(heights, [3, 2, 1]);
+    assert!(matches!(
+        prepared.run(&ctx),
+        Err(PiqlError::MissingParam(ref name)) if name == "min"
+    ));
+}
+
+#[test]
+fn prepared_query_without_params_keeps_its_core_ast() {
+    let mut engine = QueryEngine::new();
+    engine.add_base_df("entities", df! { "gold" => &[1, 5, 9] }.unwrap().lazy());
+    let prepared = engine.prepare("entities.filter($gold > 2)").unwrap();
+    assert!(!prepared.has_params());
+    let compiled = prepared
+        .compile(&Params::new(), &EvalContext::new())
+        .unwrap();
+    assert_eq!(compiled.query(), "entities.filter($gold > 2)");
+
+    // Runs see the engine's current data
+    engine.update_df("entities", df! { "gold" => &[3, 4, 5, 6] }.unwrap().lazy());
+    match engine.run_prepared(&prepared, &Params::new()).unwrap() {
+        Value::DataFrame(lf, _) => assert_eq!(lf.collect().unwrap().height(), 4),
+        _ => panic!("Expected DataFrame"),
+    }
+}
+
+// ============ Aliases (!name) ============
+
+#[test]
+fn aliases_expand_and_nest() {
+    let mut ctx = setup_test_df();
+    ctx.define_alias("merchants", r#"entities.filter($type == "merchant")"#)
+        .unwrap();
+    ctx.define_alias("rich_merchants", "!merchants.filter($gold > :min)")
+        .unwrap();
+
+    let result = run_to_df("!merchants", &ctx);
+    assert_eq!(result.height(), 2);
+
+    // Nested aliases, with parameters bound after expansion
+    let result = run_params_to_df(
+        r#"!rich_merchants.select($name)"#,
+        &[("min", 60.into())],
+        &ctx,
+    );
+    assert_eq!(
+        result.column("name").unwrap().str().unwrap().get(0),
+        Some("alice")
+    );
+}
+
+#[test]
+fn alias_errors() {
+    let mut ctx = setup_test_df();
+    assert!(matches!(
+        ctx.define_alias("broken", "entities.filter("),
+        Err(piql::ParseError { .. })
+    ));
+
+    let result = run("!missing.head(1)", &ctx);
+    assert!(matches!(result, Err(PiqlError::UnknownAlias(ref name)) if name == "missing"));
+
+    ctx.define_alias("a", "!b.head(1)").unwrap();
+    ctx.define_alias("b", "!a.head(2)").unwrap();
+    let Err(PiqlError::AliasCycle(cycle)) = run("!a", &ctx) else {
+        panic!("expected alias cycle");
+    };
+    assert_eq!(cycle, "!a -> !b -> !a");
+}
+
+#[test]
+fn query_engine_alias_redefinition_recompiles_subscriptions() {
+    let mut engine = QueryEngine::new();
+    engine.add_base_df(
+        "entities",
+        setup_test_df().dataframes["entities"].df.clone().lazy(),
+    );
+    engine.define_alias("picked", "entities.head(1)").unwrap();
+    engine.subscribe("sub", "!picked");
+    assert_eq!(engine.on_tick(1).unwrap()["sub"].height(), 1);
+
+    engine.define_alias("picked", "entities.head(2)").unwrap();
+    assert_eq!(engine.on_tick(2).unwrap()["sub"].height(), 2);
+}
+
+// ============ Formatting ============
+
+#[test]
+fn format_canonicalizes_and_breaks_long_chains() {
+    assert_eq!(
+        piql::format("entities .filter( $gold>100 )", 80).unwrap(),
+        "entities.filter($gold > 100)"
+    );
+
+    let formatted = piql::format(
+        r#"entities.filter($gold > 100).select($name, $gold).sort("gold", descending=True).head(5)"#,
+        40,
+    )
+    .unwrap();
+    assert_eq!(formatted.lines().count(), 4, "{formatted}");
+
+    // Comments survive formatting
+    let formatted = piql::format("# richest\nentities.head(5)", 80).unwrap();
+    assert!(formatted.starts_with("# richest\n"), "{formatted}");
+
+    let err = piql::format("entities.filter(", 80).unwrap_err();
+    assert_eq!(err.offset, 16);
+}
+
+// ============ Validation ============
+
+#[test]
+fn validate_accepts_supported_calls() {
+    let queries = [
+        r#"entities.filter($gold > 100).sort("gold", descending=True).head(5)"#,
+        r#"entities.with_columns(pl.col("name").str.contains("a", literal=True).alias("a"))"#,
+        r#"entities.group_by("type").agg(pl.col("gold").sum())"#,
+        r#"entities.select(pl.when($gold > 1).then(1).otherwise(0), cs.numeric())"#,
+        r#"entities.rename(gold="coins").@latest_per($id).filter(@merchant)"#,
+        "entities.with_columns($gold.delta.alias(\"d\")).window(-5, 0)",
+    ];
+    for query in queries {
+        assert_eq!(piql::validate(query).unwrap(), [], "{query}");
+    }
+}
+
+#[test]
+fn validate_reports_unknown_methods_and_bad_arguments() {
+    let messages = |query: &str| -> Vec<String> {
+        piql::validate(query)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.message)
+            .collect()
+    };
+
+    assert_eq!(messages("entities.hed(5)"), ["Unknown method .hed()"]);
+    // `.year()` only exists under `.dt`
+    assert_eq!(
+        messages(r#"entities.select(pl.col("t").str.year())"#),
+        ["Unknown method .year()"]
+    );
+    assert_eq!(
+        messages(r#"entities.filter($gold > 1, $gold < 5).head(1, 2)"#),
+        [
+            ".filter() takes 1 argument, got 2",
+            ".head() takes 0 to 1 arguments, got 2"
+        ]
+    );
+    assert_eq!(
+        messages(r#"entities.sort("gold", reverse=True)"#),
+        [".sort() has no keyword argument `reverse`"]
+    );
+    assert_eq!(
+        messages("entities.select(pl.col())"),
+        [".col() takes at least 1 argument, got 0"]
+    );
+
+    let err = piql::validate("entities.filter(").unwrap_err();
+    assert_eq!(err.offset, 16);
+}
+
+// ============ Completion ============
+
+fn completion_labels(query: &str, ctx: &EvalContext) -> Vec<String> {
+    complete(query, query.len(), ctx)
+        .into_iter()
+        .map(|c| c.label)
+        .collect()
+}
+
+#[test]
+fn complete_table_names_at_start() {
+    let ctx = setup_test_df().with_df("events", df! { "kind" => &["a"] }.unwrap().lazy());
+    assert_eq!(completion_labels("", &ctx), ["entities", "events"]);
+    assert_eq!(completion_labels("ev", &ctx), ["events"]);
+}
+
+#[test]
+fn complete_columns_after_dollar_and_in_pl_col() {
+    let ctx = setup_test_df();
+    let completions = complete("entities.filter($go", 19, &ctx);
+    assert_eq!(completions.len(), 1);
+    assert_eq!(completions[0].label, "gold");
+    assert_eq!(completions[0].kind, CompletionKind::Column);
+    assert_eq!(completions[0].replace_start, 17);
+
+    assert_eq!(
+        completion_labels(r#"entities.select(pl.col("na"#, &ctx),
+        ["name"]
+    );
+    // Other strings are left alone
+    assert!(completion_labels(r#"entities.filter($type == "me"#, &ctx).is_empty());
+}
+
+#[test]
+fn complete_methods_for_receiver_type() {
+    let ctx = setup_test_df();
+    // Prefix matches rank before substring matches
+    let labels = completion_labels("entities.filter($gold > 1).so", &ctx);
+    assert_eq!(labels, ["sort", "join_asof"]);
+
+    let labels = completion_labels("entities.group_by($type).", &ctx);
+    assert_eq!(labels, ["agg", "count", "len", "max", "mean", "min", "sum"]);
+
+    let labels = completion_labels("entities.select($name.str.to_", &ctx);
+    assert_eq!(
+        labels,
+        ["to_date", "to_datetime", "to_lowercase", "to_uppercase"]
+    );
+
+    // Sugar methods only apply to column shorthands
+    let labels = completion_labels("entities.select($gold.de", &ctx);
+    assert_eq!(labels[0], "delta");
+    let labels = completion_labels("entities.select((pl.col(\"gold\") + 1).de", &ctx);
+    assert!(!labels.contains(&"delta".to_string()), "{labels:?}");
+}
+
+#[test]
+fn complete_directives_and_functions() {
+    let mut ctx = setup_test_df();
+    ctx.sugar
+        .register_directive("merchant", |_, _| lit_str("x"));
+    ctx.sugar
+        .register_function("wealth_bucket", 1, |_, _| lit_str("x"));
+    ctx.sugar
+        .register_pipeline_directive("latest_per", |df, _, _| df);
+
+    assert_eq!(completion_labels("entities.filter(@me", &ctx), ["merchant"]);
+    assert_eq!(completion_labels("entities.@la", &ctx), ["latest_per"]);
+    ctx.define_alias("rich", "entities.filter($gold > 100)")
+        .unwrap();
+    assert_eq!(completion_labels("!ri", &ctx), ["rich"]);
+    // The pipeline directive keeps the receiver a dataframe
+    assert!(completion_labels("entities.@latest_per($name).", &ctx).contains(&"filter".into()));
+    assert_eq!(
+        completion_labels("entities.select(wea", &ctx),
+        ["wealth_bucket"]
+    );
+}
+
+// ============ Comments ============
+
+#[test]
+fn queries_with_comments_run() {
+    let ctx = setup_test_df();
+    let result = run_to_df(
+        "# merchants with some gold\n\
+         entities\n\
+         # only merchants\n\
+         .filter($type == \"merchant\") # charlie and alice\n\
+         .sort(\"gold\", descending=True) # richest first\n",
+        &ctx,
+    );
+    assert_eq!(result.height(), 2);
+}
+
+// ============ Scan-backed tables ============
+
+#[test]
+fn scan_backed_tables_reread_until_materialized() {
+    let path = std::env::temp_dir().join(format!("piql-scan-{}.parquet", std::process::id()));
+    let write = |gold: &[i64]| {
+        let mut df = df! { "gold" => gold }.unwrap();
+        ParquetWriter::new(std::fs::File::create(&path).unwrap())
+            .finish(&mut df)
+            .unwrap();
+    };
+    write(&[10, 200]);
+    let scan =
+        LazyFrame::scan_parquet(PlPath::Local(Arc::from(path.as_path())), Default::default())
+            .unwrap();
+    let mut ctx = EvalContext::new().with_scan_df("t", scan);
+    assert!(ctx.dataframes["t"].is_scan());
+    assert_eq!(ctx.dataframes["t"].df.height(), 0);
+    assert_eq!(run_to_df("t.filter($gold > 100)", &ctx).height(), 1);
+
+    // Every query reads the file as it is now
+    write(&[10, 200, 300]);
+    assert_eq!(ctx.dataframes["t"].height().unwrap(), 3);
+    assert_eq!(run_to_df("t.filter($gold > 100)", &ctx).height(), 2);
+
+    // Once materialized, the table keeps the rows it read
+    ctx.materialize_df("t").unwrap();
+    assert!(!ctx.dataframes["t"].is_scan());
+    write(&[1]);
+    assert_eq!(run_to_df("t", &ctx).height(), 3);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+// ============ SQL ============
+
+#[cfg(feature = "sql")]
+fn setup_sql_tables() -> EvalContext {
+    let entities = df! {
+        "name" => &["alice", "bob", "charlie", "dora"],
+        "gold" => &[100, 250, 50, 300],
+        "type" => &["merchant", "producer", "merchant", "producer"],
+        "town_id" => &[1, 2, 1, 3],
+    }
+    .unwrap()
+    .lazy();
+    let towns = df! {
+        "id" => &[1, 2],
+        "town" => &["Ashford", "Brill"],
+    }
+    .unwrap()
+    .lazy();
+    EvalContext::new()
+        .with_df("entities", entities)
+        .with_df("towns", towns)
+}
+
+#[cfg(feature = "sql")]
+fn sql_df(sql: &str, ctx: &EvalContext) -> DataFrame {
+    let query = piql::sql_to_piql(sql).unwrap();
+    match run(&query, ctx).unwrap() {
+        Value::DataFrame(lf, _) => lf.collect().unwrap(),
+        _ => panic!("expected a DataFrame from {query}"),
+    }
+}
+
+#[cfg(feature = "sql")]
+fn sql_strs(df: &DataFrame, column: &str) -> Vec<String> {
+    df.column(column)
+        .unwrap()
+        .str()
+        .unwrap()
+        .into_iter()
+        .map(|s| s.unwrap_or_default().to_string())
+        .collect()
+}
+
+#[cfg(feature = "sql")]
+fn sql_ints(df: &DataFrame, column: &str) -> Vec<i64> {
+    df.column(column)
+        .unwrap()
+        .cast(&DataType::Int64)
+        .unwrap()
+        .i64()
+        .unwrap()
+        .into_iter()
+        .map(|v| v.unwrap_or_default())
+        .collect()
+}
+
+#[cfg(feature = "sql")]
+#[test]
+fn sql_select_where_order_limit() {
+    let ctx = setup_sql_tables();
+    let df = sql_df(
+        "SELECT name, gold * 2 AS double FROM entities WHERE gold >= 100 ORDER BY double DESC LIMIT 2",
+        &ctx,
+    );
+    assert_eq!(df.get_column_names(), ["name", "double"]);
+    assert_eq!(sql_strs(&df, "name"), ["dora", "bob"]);
+    assert_eq!(sql_ints(&df, "double"), [600, 500]);
+}
+
+#[cfg(feature = "sql")]
+#[test]
+fn sql_translates_to_readable_piql() {
+    assert_eq!(
+        piql::sql_to_piql("SELECT name FROM entities WHERE gold > 100").unwrap(),
+        r#"entities.filter(pl.col("gold") > 100).select([pl.col("name")])"#
+    );
+    assert_eq!(
+        piql::sql_to_piql("SELECT * FROM entities ORDER BY type, gold DESC").unwrap(),
+        r#"entities.sort(["type", "gold"], descending=[False, True])"#
+    );
+}
+
+#[cfg(feature = "sql")]
+#[test]
+fn sql_group_by_with_having_on_an_unselected_aggregate() {
+    let ctx = setup_sql_tables();
+    let df = sql_df(
+        "SELECT type, SUM(gold) AS total, COUNT(*) FROM entities \
+         GROUP BY type HAVING MAX(gold) > 200 ORDER BY 2",
+        &ctx,
+    );
+    assert_eq!(df.get_column_names(), ["type", "total", "count"]);
+    assert_eq!(sql_strs(&df, "type"), ["producer"]);
+    assert_eq!(sql_ints(&df, "total"), [550]);
+    assert_eq!(sql_ints(&df, "count"), [2]);
+
+    let df = sql_df(
+        "SELECT COUNT(DISTINCT type) AS kinds, AVG(gold) AS avg_gold FROM entities",
+        &ctx,
+    );
+    assert_eq!(sql_ints(&df, "kinds"), [2]);
+    assert_eq!(sql_ints(&df, "avg_gold"), [175]);
+}
+
+#[cfg(feature = "sql")]
+#[test]
+fn sql_joins_on_qualified_columns() {
+    let ctx = setup_sql_tables();
+    let df = sql_df(
+        "SELECT e.name, t.town FROM towns t JOIN entities e ON e.town_id = t.id ORDER BY name",
+        &ctx,
+    );
+    assert_eq!(sql_strs(&df, "name"), ["alice", "bob", "charlie"]);
+    assert_eq!(sql_strs(&df, "town"), ["Ashford", "Brill", "Ashford"]);
+
+    let df = sql_df(
+        "SELECT name FROM entities LEFT JOIN towns ON town_id = id WHERE town IS NULL",
+        &ctx,
+    );
+    assert_eq!(sql_strs(&df, "name"), ["dora"]);
+}
+
+#[cfg(feature = "sql")]
+#[test]
+fn sql_predicates_and_case() {
+    let ctx = setup_sql_tables();
+    let df = sql_df(
+        "SELECT name FROM entities WHERE name LIKE '%a%' AND gold NOT BETWEEN 60 AND 200 \
+         AND type IN ('merchant', 'producer') ORDER BY name",
+        &ctx,
+    );
+    assert_eq!(sql_strs(&df, "name"), ["charlie", "dora"]);
+
+    let df = sql_df(
+        "SELECT name, CASE WHEN gold > 200 THEN 'rich' ELSE 'poor' END AS class \
+         FROM entities ORDER BY name",
+        &ctx,
+    );
+    assert_eq!(sql_strs(&df, "class"), ["poor", "rich", "poor", "rich"]);
+}
+
+#[cfg(feature = "sql")]
+#[test]
+fn sql_placeholders_bind_as_params() {
+    let ctx = setup_sql_tables();
+    let query =
+        piql::sql_to_piql("SELECT name FROM entities WHERE gold > :min ORDER BY name").unwrap();
+    let params = Params::from([("min".to_string(), ParamValue::from(200))]);
+    let Value::DataFrame(lf, _) = run_with_params(&query, &params, &ctx).unwrap() else {
+        panic!("expected a DataFrame");
+    };
+    assert_eq!(sql_strs(&lf.collect().unwrap(), "name"), ["bob", "dora"]);
+}
+
+#[cfg(feature = "sql")]
+#[test]
+fn sql_parse_sql_produces_core_ast() {
+    let core = piql::parse_sql("SELECT name FROM entities").unwrap();
+    let piql = piql::compile("entities.select([pl.col(\"name\")])", &setup_sql_tables()).unwrap();
+    assert_eq!(&core, piql.core());
+}
+
+#[cfg(feature = "sql")]
+#[test]
+fn sql_unsupported_sql_is_an_error() {
+    for sql in [
+        "DELETE FROM entities",
+        "SELECT name FROM entities UNION SELECT town FROM towns",
+        "SELECT name, gold FROM entities GROUP BY type",
+        "SELECT * FROM entities, towns",
+    ] {
+        assert!(
+            matches!(piql::sql_to_piql(sql), Err(PiqlError::Sql(_))),
+            "{sql} should be rejected"
+        );
+    }
+}
+
+#[cfg(feature = "sql")]
+#[test]
+fn sql_qualified_columns_of_both_join_sides_stay_apart() {
+    let mayors = df! {
+        "town_id" => &[1, 2],
+        "name" => &["mae", "ned"],
+    }
+    .unwrap()
+    .lazy();
+    let ctx = setup_sql_tables().with_df("mayors", mayors);
+
+    let df = sql_df(
+        "SELECT e.name, m.name FROM entities e JOIN mayors m ON e.town_id = m.town_id \
+         ORDER BY e.name",
+        &ctx,
+    );
+    assert_eq!(df.get_column_names(), ["name", "name_right"]);
+    assert_eq!(sql_strs(&df, "name"), ["alice", "bob", "charlie"]);
+    assert_eq!(sql_strs(&df, "name_right"), ["mae", "ned", "mae"]);
+
+    // The joined table's column is the suffixed one whichever side it is selected from
+    let df = sql_df(
+        "SELECT m.name, e.name FROM mayors m JOIN entities e ON m.town_id = e.town_id \
+         WHERE e.gold > 60 ORDER BY e.name",
+        &ctx,
+    );
+    assert_eq!(df.get_column_names(), ["name", "name_right"]);
+    assert_eq!(sql_strs(&df, "name"), ["mae", "ned"]);
+    assert_eq!(sql_strs(&df, "name_right"), ["alice", "bob"]);
+
+    for sql in [
+        "SELECT name, m.name FROM entities e JOIN mayors m USING (town_id)",
+        "SELECT x.name FROM entities e",
+    ] {
+        assert!(
+            matches!(piql::sql_to_piql(sql), Err(PiqlError::Sql(_))),
+            "{sql} should be rejected"
+        );
+    }
+}
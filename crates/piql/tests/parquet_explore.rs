@@ -2,6 +2,8 @@
 //!
 //! Run with: cargo test -p piql --test parquet_explore -- --nocapture
 
+#![cfg(feature = "eval")]
+
 use polars::prelude::*;
 use std::path::Path;
 use std::sync::Arc;
@@ -1,3 +1,5 @@
+#![cfg(feature = "eval")]
+
 use piql::advanced::{parse, pretty};
 use piql::{EvalContext, Value, run};
 use polars::df;